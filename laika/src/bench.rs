@@ -0,0 +1,36 @@
+use clap::Args;
+use laika_combiner::bench::Workload;
+use laika_combiner::config::EventProcessorConfig;
+use laika_combiner::errors::{LaikaError, LaikaResult};
+use laika_combiner::timing::TimingExpiry;
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Correlations in the synthetic stream
+    #[arg(long, default_value_t = 1000)]
+    correlations: usize,
+    /// Events for each correlation, the last of which completes it
+    #[arg(long, default_value_t = 10)]
+    events_per_correlation: usize,
+}
+
+/// Push a synthetic stream of correlated events through the matcher, storage and rules, and
+/// report how quickly they were processed. The config's storage is used, so engines can be
+/// compared, but kept in a scratch directory rather than `dataDir`.
+pub fn bench(args: BenchArgs, config: EventProcessorConfig) -> LaikaResult<()> {
+    let workload = Workload::new(args.correlations, args.events_per_correlation);
+    let state_dir = std::env::temp_dir().join(format!("laika_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&state_dir).map_err(|e| LaikaError::IO(e.to_string()))?;
+    let report = config
+        .storage()
+        .builder_at(&state_dir)
+        .build()
+        .and_then(|storage| {
+            let waker = TimingExpiry::new(state_dir.join(".timing_expiry"))?;
+            let mut runtime = workload.runtime(storage, waker)?;
+            workload.run(&mut runtime)
+        });
+    let _ = std::fs::remove_dir_all(&state_dir);
+    println!("{}", report?);
+    Ok(())
+}
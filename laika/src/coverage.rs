@@ -0,0 +1,63 @@
+use crate::replay::{event_timestamp, open_archive};
+use crate::simulate::parse_timestamp;
+use crate::source_connection;
+use clap::Args;
+use laika_combiner::config::EventProcessorConfig;
+use laika_combiner::coverage::{CorpusEvent, run_coverage};
+use laika_combiner::errors::{LaikaError, LaikaResult};
+use serde_json::Value;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct CoverageArgs {
+    /// Corpus of events, as a JSON Lines file (optionally gzipped)
+    #[arg(long)]
+    input: PathBuf,
+    /// Connection to treat events as received from, when the config has more than one source
+    #[arg(long)]
+    connection: Option<String>,
+    /// Field holding when each event is received, as RFC 3339 or Unix seconds. Events without it
+    /// are received straight after the one before.
+    #[arg(long, default_value = "timestamp")]
+    timestamp_field: String,
+    /// RFC 3339 timestamp to keep the clock running until after the last event, so wakeups due
+    /// by then fire
+    #[arg(long)]
+    until: Option<String>,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// Run a corpus through the rules on a simulated clock, then print which event types it matched
+/// and which rules it fired, so rules that never fire and match keys that never match stand out
+pub fn coverage(args: CoverageArgs, config: EventProcessorConfig) -> LaikaResult<()> {
+    let connection = source_connection(&config, args.connection)?;
+    let until = parse_timestamp(args.until.as_deref(), "until")?;
+    let mut events = Vec::new();
+    for line in open_archive(&args.input)?.lines() {
+        let line = line.map_err(|e| LaikaError::IO(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Value = serde_json::from_str(line.as_str())
+            .map_err(|e| LaikaError::JsonError(e.to_string()))?;
+        events.push(CorpusEvent {
+            source: connection.clone(),
+            received: event_timestamp(&event, args.timestamp_field.as_str()),
+            event,
+        });
+    }
+    let report = run_coverage(config, events, until)?;
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report)
+                .map_err(|e| LaikaError::JsonError(e.to_string()))?
+        );
+    } else {
+        print!("{}", report);
+    }
+    Ok(())
+}
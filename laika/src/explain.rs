@@ -0,0 +1,32 @@
+use crate::{source_connection, start_runtime};
+use clap::Args;
+use laika_combiner::config::EventProcessorConfig;
+use laika_combiner::errors::{LaikaError, LaikaResult};
+
+#[derive(Args)]
+pub struct ExplainArgs {
+    /// Event to explain, as JSON
+    #[arg(long)]
+    event: String,
+    /// Connection the event is received from, when the config has more than one source
+    #[arg(long)]
+    connection: Option<String>,
+}
+
+/// Print a trace of each rule's evaluation against the event and the current state
+pub fn explain(args: ExplainArgs, config: EventProcessorConfig) -> LaikaResult<()> {
+    let event: serde_json::Value = serde_json::from_str(args.event.as_str())
+        .map_err(|e| LaikaError::JsonError(e.to_string()))?;
+    let connection = source_connection(&config, args.connection)?;
+    let mut runtime = start_runtime(config, false);
+    let explanations = runtime.explain(connection.as_str(), event)?;
+    if explanations.is_empty() {
+        eprintln!("Event did not match any event types from {}", connection);
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&explanations)
+            .map_err(|e| LaikaError::JsonError(e.to_string()))?
+    );
+    Ok(())
+}
@@ -1,97 +1,259 @@
-use clap::Parser;
-use laika_combiner::EventProcessor;
+mod bench;
+mod coverage;
+mod explain;
+mod replay;
+mod rule_tests;
+mod simulate;
+mod state;
+#[cfg(feature = "typescript")]
+mod types;
+
+use clap::{Parser, Subcommand};
+use laika_combiner::Runtime;
 use laika_combiner::action::EventAction;
+use laika_combiner::admin;
 use laika_combiner::config::EventProcessorConfig;
 use laika_combiner::config::builder::EventProcessorYamlSpec;
 use laika_combiner::connections::{AckCallback, Connections};
-use laika_combiner::errors::LaikaResult;
-use laika_combiner::event::RawEvent;
-use laika_combiner::event_handler::{handle_raw_event, handle_timing_expiry};
-use laika_combiner::storage::StorageKVBuilder;
+use laika_combiner::coordination::Leadership;
+use laika_combiner::dead_letter::DeadLetters;
+use laika_combiner::dispatcher::Dispatcher;
+use laika_combiner::errors::{LaikaError, LaikaResult};
+use laika_combiner::event::EventMetadata;
+use laika_combiner::logging::{LogFormat, LoggingConfig, PayloadSampler};
+use laika_combiner::shadow::Shadow;
+use laika_combiner::telemetry::{self, TelemetryConfig};
 use laika_combiner::timing::TimingExpiry;
-use std::env::temp_dir;
-use std::fs;
 use std::path::Path;
+use std::sync::Arc;
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::prelude::*;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
     #[arg(short, long)]
     config: String,
+    /// Record every received event, and the actions it led to, in an audit log
+    #[arg(long)]
+    audit: bool,
+    /// Evaluate a second config on the same events, reporting where its actions would differ
+    /// without ever taking them
+    #[arg(long)]
+    shadow: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-async fn process(connections: Connections, mut processors: Vec<EventProcessor>) -> LaikaResult<()> {
-    let mut waker = TimingExpiry::new(".timing_expiry".to_string().parse().unwrap()).unwrap();
-    // TODO: Allow setting a real path for this with a config - otherwise running multiple sessions one after the other
-    //  isn't viable. We also need to forbid trying to open the same StorageKV
-    let tmp_path = temp_dir();
-    let mut storage = StorageKVBuilder::new(tmp_path).build()?;
-    while let Ok(messages) = connections.receive().await {
-        tracing::debug!("Received {} message(s) from connections", messages.len());
-        let mut event_actions: Vec<(Vec<EventAction>, Option<AckCallback>)> = Vec::new();
-        for (message, message_source, callback) in messages {
-            let resultant_actions = handle_raw_event(
-                processors.as_mut_slice(),
-                &mut storage,
-                message_source.as_str(),
-                RawEvent::new(message),
-            )?;
-            event_actions.push((resultant_actions, Some(callback)));
-        }
-        while let Some(expiry) = waker.peek() {
-            let resultant_actions =
-                handle_timing_expiry(processors.as_mut_slice(), &mut storage, expiry)?;
-            event_actions.push((resultant_actions, None));
-            // I wonder if the ACK here needs to be handled in the same way as message acks.
-            waker.ack()?;
-        }
-        tracing::debug!("Processing {} actions", event_actions.iter().filter(|(e, _)| !e.is_empty()).count());
-        for (message_actions, callback) in event_actions {
-            if !message_actions.is_empty() {
-                tracing::debug!("Processing {:?} action", &message_actions);    
-            }
-            for message_action in message_actions {
-                match message_action {
-                    EventAction::Emit(emit_action) => {
-                        connections
-                            .submit_to(emit_action.target.as_str(), emit_action.clone().payload())
-                            .await?;
-                    }
-                    EventAction::ScheduleWakeup(wakeup) => {
-                        waker.add_expiry(wakeup)?;
-                    }
-                }
+#[derive(Subcommand)]
+enum Command {
+    /// Re-ingest archived events through the rules
+    Replay(replay::ReplayArgs),
+    /// Trace how each rule would handle an event, without processing it
+    Explain(explain::ExplainArgs),
+    /// Inspect the state kept for correlations
+    State(state::StateArgs),
+    /// Run events through the rules on a simulated clock, without waiting for timing rules
+    Simulate(simulate::SimulateArgs),
+    /// Measure throughput on a synthetic stream of correlated events
+    Bench(bench::BenchArgs),
+    /// Run the tests written under each rule, without connecting to anything
+    Test(rule_tests::TestArgs),
+    /// Report which event types and rules a corpus of events exercises
+    Coverage(coverage::CoverageArgs),
+    /// Write TypeScript declarations for the config's predicates
+    #[cfg(feature = "typescript")]
+    Types(types::TypesArgs),
+}
+
+/// Connection to treat events as received from, which can be left out when there's only one
+fn source_connection(
+    config: &EventProcessorConfig,
+    connection: Option<String>,
+) -> LaikaResult<String> {
+    if let Some(connection) = connection {
+        return Ok(connection);
+    }
+    let sources: Vec<&str> = config.source_names().collect();
+    match sources.as_slice() {
+        [source] => Ok(source.to_string()),
+        _ => Err(LaikaError::Generic(format!(
+            "Config has sources {:?}, so --connection is needed",
+            sources
+        ))),
+    }
+}
+
+/// Take actions produced by the runtime, one after another
+async fn take_actions(connections: &Connections, actions: Vec<EventAction>) -> LaikaResult<()> {
+    tracing::debug!("Processing {} actions", actions.len());
+    for action in actions {
+        tracing::debug!("Processing {:?} action", &action);
+        match action {
+            EventAction::Emit(emit_action) => {
+                connections.emit(emit_action).await?;
             }
-            if let Some(callback_fn) = callback {
-                callback_fn().await?;
+            EventAction::Http(request) => {
+                connections.request(request).await?;
             }
+            // Wakeups and stored values are handled within the runtime
+            EventAction::ScheduleWakeup(_)
+            | EventAction::CancelWakeup(..)
+            | EventAction::Store(_) => {}
         }
     }
     Ok(())
 }
 
-#[tokio::main]
-async fn main() {
-    let cli = Cli::parse();
+/// Wait until `after` has passed, or forever when there's no wakeup to wait for
+async fn wakeup_due(after: Option<std::time::Duration>) {
+    match after {
+        Some(after) => tokio::time::sleep(after).await,
+        None => std::future::pending().await,
+    }
+}
 
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+/// Push a received event into the runtime, after its lookups, retrying it until it's handled or
+/// given up on. Given up messages are sent to the dead-letter target, and without one the failure
+/// stops processing. Failed messages are logged in full, and handled ones only when sampled.
+async fn push_event(
+    connections: &Connections,
+    runtime: &mut Runtime,
+    dead_letters: &mut Option<DeadLetters>,
+    sampler: &mut PayloadSampler,
+    source: &str,
+    message: serde_json::Value,
+    metadata: EventMetadata,
+) -> LaikaResult<()> {
+    loop {
+        let pushed = match runtime.lookup(source, message.clone(), &metadata).await {
+            Ok(enriched) => runtime.push_event_with_metadata(source, enriched, metadata.clone()),
+            Err(error) => Err(error),
+        };
+        let error = match pushed {
+            Ok(()) => {
+                if let Some(dead_letters) = dead_letters {
+                    dead_letters.handled(source, &message);
+                }
+                if sampler.sampled() {
+                    tracing::info!(payload = %message, "Handled message from {}", source);
+                }
+                return Ok(());
+            }
+            Err(error) => error,
+        };
+        // Another instance is now handling messages, so this one must stop rather than dead-letter
+        // them
+        if matches!(error, LaikaError::StoreTakenOver { .. }) {
+            return Err(error);
+        }
+        let Some(dead_letters) = dead_letters else {
+            tracing::error!(payload = %message, "Failed on message from {} after {}", source, error);
+            return Err(error);
+        };
+        match dead_letters.failed(source, &message, &error) {
+            None => {
+                let delay = dead_letters.retry_delay(source, &message);
+                tracing::warn!(
+                    payload = %message,
+                    "Retrying message from {} in {:?} after {}",
+                    source,
+                    delay,
+                    error
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Some(dead_letter) => {
+                tracing::error!(
+                    payload = %message,
+                    "Giving up on message from {} after {}",
+                    source,
+                    error
+                );
+                return connections
+                    .submit_to(dead_letters.target(), dead_letter, None)
+                    .await;
+            }
+        }
+    }
+}
 
-    let config_path = Path::new(&cli.config);
+async fn process(
+    connections: Arc<Connections>,
+    mut dispatcher: Dispatcher,
+    mut runtime: Runtime,
+    mut dead_letters: Option<DeadLetters>,
+    mut sampler: PayloadSampler,
+    mut shadow: Option<Shadow>,
+) -> LaikaResult<()> {
+    // Kept across wakeups rather than dropped for them, so no message that's part way through
+    // being received is lost
+    let mut receiving = Box::pin(connections.receive());
+    loop {
+        let mut callbacks: Vec<AckCallback> = Vec::new();
+        tokio::select! {
+            received = &mut receiving => {
+                let Ok(messages) = received else {
+                    break;
+                };
+                receiving = Box::pin(connections.receive());
+                tracing::debug!("Received {} message(s) from connections", messages.len());
+                for lag in connections.lag().await {
+                    tracing::trace!(
+                        "Source {} has {} message(s) queued, waited {:?}",
+                        lag.source,
+                        lag.queued,
+                        lag.waited
+                    );
+                }
+                for (message, message_source, metadata, callback) in messages {
+                    if let Some(shadow) = &mut shadow {
+                        shadow
+                            .push_event(message_source.as_str(), message.clone(), metadata.clone())
+                            .await;
+                    }
+                    push_event(
+                        &connections,
+                        &mut runtime,
+                        &mut dead_letters,
+                        &mut sampler,
+                        message_source.as_str(),
+                        message,
+                        metadata,
+                    )
+                    .await?;
+                    callbacks.push(callback);
+                }
+            }
+            // Schedules, rechecks and timeouts are taken when they're due, without waiting for a
+            // message to arrive
+            _ = wakeup_due(runtime.until_next_wakeup()) => {}
+        }
+        let actions = runtime.poll_actions()?;
+        if let Some(shadow) = &mut shadow {
+            shadow.compare(&actions);
+        }
+        // Messages are only acknowledged once the actions they produced have been taken
+        dispatcher.dispatch(actions, callbacks).await?;
+    }
+    if let Some(shadow) = &shadow {
+        tracing::info!(
+            report = %serde_json::json!(shadow.report().report()),
+            "Shadow config divergence"
+        );
+    }
+    // Targets that batch write out what they're holding, so the actions waiting on them finish
+    let (closed, finished) = tokio::join!(connections.close(), dispatcher.finish());
+    finished.and(closed)
+}
+
+fn load_config(config: &str) -> EventProcessorConfig {
+    let config_path = Path::new(config);
     if !config_path.exists() {
-        eprintln!("Error: Config file '{}' does not exist", cli.config);
+        eprintln!("Error: Config file '{}' does not exist", config);
         std::process::exit(1);
     }
-    let yaml_content = match fs::read_to_string(config_path) {
-        Ok(content) => content,
-        Err(e) => {
-            eprintln!("Error reading config file: {}", e);
-            std::process::exit(1);
-        }
-    };
-
-    let processor_spec: EventProcessorYamlSpec = match serde_yaml::from_str(&yaml_content) {
+    let processor_spec = match EventProcessorYamlSpec::from_path(config_path) {
         Ok(config) => config,
         Err(e) => {
             eprintln!("Could not read Config: {}", e);
@@ -99,21 +261,293 @@ async fn main() {
         }
     };
 
-    let processor_config = match EventProcessorConfig::try_from(&processor_spec) {
+    match EventProcessorConfig::try_from(&processor_spec) {
         Ok(processor) => processor,
         Err(e) => {
             eprintln!("Config is not invalid: {}", e);
             std::process::exit(1);
         }
+    }
+}
+
+fn start_runtime(config: EventProcessorConfig, audit: bool) -> Runtime {
+    let storage_config = config.storage().clone();
+    match storage_config
+        .builder()
+        .audit_log(audit)
+        .build()
+        .and_then(|storage| {
+            let waker = TimingExpiry::new(storage_config.timer_path())?
+                .with_max_wakeups(storage_config.max_wakeups());
+            Runtime::new(vec![config.build()?], storage, waker)
+        }) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Could not start runtime: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Log to stderr at the configured levels, or by `RUST_LOG` when it's set, and export spans when
+/// the config has a collector
+fn init_tracing(logging: &LoggingConfig, telemetry_config: &TelemetryConfig) {
+    let filter = match std::env::var_os("RUST_LOG") {
+        Some(_) => Ok(EnvFilter::from_default_env()),
+        None => logging.filter(),
     };
+    let layers = filter.and_then(|filter| {
+        let otlp = telemetry_config
+            .otlp
+            .as_ref()
+            .map(telemetry::otlp_layer)
+            .transpose()?;
+        Ok((filter, otlp))
+    });
+    let (filter, otlp) = match layers {
+        Ok(layers) => layers,
+        Err(e) => {
+            eprintln!("Could not set up logging: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let logs = match logging.format {
+        // Stdout is left to stdout targets, so their output can be piped on
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(std::io::stderr)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(logs.with_filter(filter))
+        .with(otlp)
+        .init();
+}
 
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    let processor_config = load_config(&cli.config);
+    init_tracing(processor_config.logging(), processor_config.telemetry());
     tracing::info!("Initialised with config {:?}", &processor_config);
-    let connections = processor_config.connections().await.unwrap();
+
+    match cli.command {
+        Some(Command::Replay(args)) => {
+            if let Err(e) = replay::replay(args, processor_config, cli.audit).await {
+                eprintln!("Replay failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Explain(args)) => {
+            if let Err(e) = explain::explain(args, processor_config) {
+                eprintln!("Explain failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::State(args)) => {
+            if let Err(e) = state::state(args, processor_config) {
+                eprintln!("Could not read state: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Simulate(args)) => {
+            if let Err(e) = simulate::simulate(args, processor_config).await {
+                eprintln!("Simulation failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Bench(args)) => {
+            if let Err(e) = bench::bench(args, processor_config) {
+                eprintln!("Benchmark failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Test(args)) => {
+            match rule_tests::test(args, &cli.config) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Could not run tests: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Coverage(args)) => {
+            if let Err(e) = coverage::coverage(args, processor_config) {
+                eprintln!("Coverage failed: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        #[cfg(feature = "typescript")]
+        Some(Command::Types(args)) => {
+            if let Err(e) = types::types(args, &processor_config) {
+                eprintln!("Could not write types: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
+
+    // A standby waits here, without connecting or loading timers, until the leader goes away
+    let leadership = match processor_config.coordination().map(Leadership::new) {
+        None => None,
+        Some(Ok(mut leadership)) => {
+            leadership.acquire().await;
+            Some(leadership)
+        }
+        Some(Err(e)) => {
+            eprintln!("Could not set up coordination: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let connections = Arc::new(processor_config.connections().await.unwrap());
     tracing::info!("Initialised with connections {:?}", &connections);
-    let processor: EventProcessor = processor_config.build();
+    let dispatcher = processor_config.dispatcher(connections.clone());
+    let dead_letters = processor_config
+        .dead_letter()
+        .cloned()
+        .map(DeadLetters::new);
+    let sampler = processor_config.logging().payload_sampler();
+    let admin_config = processor_config.admin().cloned();
+    let shadow = cli.shadow.map(|shadow_config| {
+        match Shadow::new(&processor_config, load_config(&shadow_config)) {
+            Ok(shadow) => shadow,
+            Err(e) => {
+                eprintln!("Could not start shadow config: {}", e);
+                std::process::exit(1);
+            }
+        }
+    });
+    let shadow_report = shadow.as_ref().map(Shadow::report);
+    let runtime = start_runtime(processor_config, cli.audit);
+    if let Some(admin_config) = admin_config {
+        let controls = runtime.controls();
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(&admin_config, controls, connections, shadow_report).await
+            {
+                tracing::error!("Admin API stopped: {}", e);
+            }
+        });
+    }
 
-    if let Err(e) = process(connections, vec![processor]).await {
+    let processing = process(
+        connections,
+        dispatcher,
+        runtime,
+        dead_letters,
+        sampler,
+        shadow,
+    );
+    let processed = match leadership {
+        None => processing.await,
+        // Stop as soon as leadership is lost, so the standby can take over without both emitting
+        Some(leadership) => tokio::select! {
+            processed = processing => processed,
+            _ = leadership.hold() => Err(LaikaError::Generic(
+                "Lost leadership, stopping so the standby can take over".to_string(),
+            )),
+        },
+    };
+    telemetry::shutdown();
+    if let Err(e) = processed {
         eprintln!("Processing failed: {}", e);
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use laika_combiner::clock::TestClock;
+    use laika_combiner::storage::StorageKV;
+    use serde_json::json;
+    use std::time::Duration;
+    use tempfile::TempDir;
+    use time::OffsetDateTime;
+
+    const CONFIG: &str = r#"
+connections:
+  orders:
+    type: file
+    path: DIR/orders.jsonl
+    follow: true
+  reports:
+    type: file
+    path: DIR/reports.jsonl
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+correlation:
+  placed:
+    key: "$.orderId"
+storage:
+  engine: memory
+  dataDir: DIR
+triggers:
+  minutely:
+    schedule:
+      cron: "* * * * *"
+    action:
+      target: reports
+      payload:
+        report: "minutely"
+"#;
+
+    #[tokio::test]
+    async fn test_schedule_runs_without_messages() {
+        let dir = TempDir::new().unwrap();
+        let yaml = CONFIG.replace("DIR", dir.path().to_str().unwrap());
+        let config =
+            EventProcessorConfig::try_from(&EventProcessorYamlSpec::from_yaml(&yaml).unwrap())
+                .unwrap();
+        // An earlier run a minute behind leaves a run of the schedule pending that's already due
+        let behind = TestClock::new(OffsetDateTime::now_utc() - time::Duration::minutes(1));
+        Runtime::new_with_clock(
+            vec![config.clone().build().unwrap()],
+            StorageKV::in_memory(),
+            TimingExpiry::new(config.storage().timer_path()).unwrap(),
+            Arc::new(behind),
+        )
+        .unwrap();
+
+        let connections = Arc::new(config.connections().await.unwrap());
+        let dispatcher = config.dispatcher(connections.clone());
+        let sampler = config.logging().payload_sampler();
+        let runtime = start_runtime(config, false);
+        let reports = dir.path().join("reports.jsonl");
+        let reported = async {
+            loop {
+                match std::fs::read_to_string(&reports) {
+                    Ok(lines) if !lines.is_empty() => return lines,
+                    _ => tokio::time::sleep(Duration::from_millis(20)).await,
+                }
+            }
+        };
+        // Nothing is ever written to orders, so only the schedule can lead to a report
+        let lines = tokio::select! {
+            processed = process(connections, dispatcher, runtime, None, sampler, None) => {
+                panic!("Processing stopped with {:?}", processed)
+            }
+            lines = tokio::time::timeout(Duration::from_secs(10), reported) => lines.unwrap(),
+        };
+        let report: serde_json::Value =
+            serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+        assert_eq!(report, json!({"report": "minutely"}));
+    }
+}
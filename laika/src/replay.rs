@@ -0,0 +1,298 @@
+use crate::{source_connection, take_actions};
+use clap::Args;
+use flate2::read::GzDecoder;
+use laika_combiner::Runtime;
+use laika_combiner::clock::TestClock;
+use laika_combiner::config::EventProcessorConfig;
+use laika_combiner::errors::{LaikaError, LaikaResult};
+use laika_combiner::event::EventMetadata;
+use laika_combiner::storage::StorageEngine;
+use laika_combiner::timing::TimingExpiry;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Only replay events at or after this RFC 3339 timestamp
+    #[arg(long)]
+    from: Option<String>,
+    /// Archived events, as a JSON Lines file (optionally gzipped) or a directory of them
+    #[arg(long)]
+    source: PathBuf,
+    /// Connection to replay events as received from, when the config has more than one source
+    #[arg(long)]
+    connection: Option<String>,
+    /// Field holding each event's timestamp, as RFC 3339 or Unix seconds
+    #[arg(long, default_value = "timestamp")]
+    timestamp_field: String,
+    /// Write actions out rather than taking them, so nothing is sent to targets
+    #[arg(long)]
+    dry_run: bool,
+    /// File to write actions to during a dry run, rather than stdout
+    #[arg(long, requires = "dry_run")]
+    output: Option<PathBuf>,
+}
+
+/// Archive files to replay, in name order so time-partitioned archives replay in time order
+fn archive_files(source: &Path) -> LaikaResult<Vec<PathBuf>> {
+    if source.is_file() {
+        return Ok(vec![source.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut directories = vec![source.to_path_buf()];
+    while let Some(directory) = directories.pop() {
+        for entry in std::fs::read_dir(&directory).map_err(|e| LaikaError::IO(e.to_string()))? {
+            let path = entry.map_err(|e| LaikaError::IO(e.to_string()))?.path();
+            if path.is_dir() {
+                directories.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub(crate) fn open_archive(path: &Path) -> LaikaResult<Box<dyn BufRead>> {
+    let file = File::open(path).map_err(|e| LaikaError::IO(e.to_string()))?;
+    let reader: Box<dyn Read> = if path.extension().is_some_and(|extension| extension == "gz") {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+/// When the event happened, read from `field` as RFC 3339 or Unix seconds
+pub(crate) fn event_timestamp(event: &Value, field: &str) -> Option<OffsetDateTime> {
+    let value = field
+        .split('.')
+        .try_fold(event, |value, key| value.get(key))?;
+    match value {
+        Value::String(timestamp) => OffsetDateTime::parse(timestamp, &Rfc3339).ok(),
+        Value::Number(seconds) => OffsetDateTime::from_unix_timestamp(seconds.as_i64()?).ok(),
+        _ => None,
+    }
+}
+
+/// When the first event to replay happened, to start the clock at
+fn first_timestamp(
+    files: &[PathBuf],
+    field: &str,
+    from: Option<OffsetDateTime>,
+) -> LaikaResult<Option<OffsetDateTime>> {
+    for path in files {
+        for line in open_archive(path)?.lines() {
+            let line = line.map_err(|e| LaikaError::IO(e.to_string()))?;
+            let timestamp = serde_json::from_str(line.as_str())
+                .ok()
+                .and_then(|event| event_timestamp(&event, field))
+                .filter(|timestamp| from.is_none_or(|from| *timestamp >= from));
+            if timestamp.is_some() {
+                return Ok(timestamp);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Replay archived events through the rules as if they were received when they happened. State
+/// is kept in a store of its own for the replay, so it starts afresh and leaves the config's
+/// `storage` as it was.
+pub async fn replay(
+    args: ReplayArgs,
+    config: EventProcessorConfig,
+    audit: bool,
+) -> LaikaResult<()> {
+    let state_dir = std::env::temp_dir().join(format!("laika_replay_{}", std::process::id()));
+    let result = replay_in(args, config, audit, &state_dir).await;
+    let _ = std::fs::remove_dir_all(&state_dir);
+    result
+}
+
+async fn replay_in(
+    args: ReplayArgs,
+    config: EventProcessorConfig,
+    audit: bool,
+    state_dir: &Path,
+) -> LaikaResult<()> {
+    let from = args
+        .from
+        .as_deref()
+        .map(|from| OffsetDateTime::parse(from, &Rfc3339))
+        .transpose()
+        .map_err(|e| LaikaError::Generic(format!("Invalid --from timestamp: {}", e)))?;
+    let connection = source_connection(&config, args.connection)?;
+    let files = archive_files(&args.source)?;
+
+    let connections = if args.dry_run {
+        None
+    } else {
+        let connections = config
+            .target_connections()
+            .await
+            .map_err(|e| LaikaError::Generic(e.to_string()))?;
+        // Actions are taken one after another, so a target waiting for a batch to fill would
+        // hold up each of them until its flush interval
+        connections.close().await?;
+        Some(connections)
+    };
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path).map_err(|e| LaikaError::IO(e.to_string()))?),
+        None => Box::new(std::io::stdout()),
+    };
+    // Events are received when they happened, and ones without a timestamp straight after the
+    // one before
+    let start = first_timestamp(&files, args.timestamp_field.as_str(), from)?
+        .unwrap_or_else(OffsetDateTime::now_utc);
+    let clock = TestClock::new(start);
+    let storage_config = config.storage();
+    let storage = storage_config
+        .builder_at(state_dir)
+        .engine(StorageEngine::RocksDb)
+        .audit_log(audit)
+        .build()?;
+    let waker = TimingExpiry::new(state_dir.join(".timing_expiry"))?
+        .with_max_wakeups(storage_config.max_wakeups());
+    let mut runtime = Runtime::new_with_clock(
+        vec![config.build()?],
+        storage,
+        waker,
+        Arc::new(clock.clone()),
+    )?;
+
+    let mut replayed = 0;
+    for path in files {
+        tracing::info!("Replaying events from {}", path.display());
+        for line in open_archive(&path)?.lines() {
+            let line = line.map_err(|e| LaikaError::IO(e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event: Value = serde_json::from_str(line.as_str())
+                .map_err(|e| LaikaError::JsonError(e.to_string()))?;
+            let timestamp = event_timestamp(&event, args.timestamp_field.as_str());
+            // Events without a timestamp can't be placed, so they're always replayed
+            let before_from = from
+                .zip(timestamp)
+                .is_some_and(|(from, timestamp)| timestamp < from);
+            if before_from {
+                continue;
+            }
+            let mut actions = match timestamp {
+                Some(timestamp) => runtime
+                    .advance_to(&clock, timestamp)?
+                    .into_iter()
+                    .flat_map(|(_, actions)| actions)
+                    .collect(),
+                None => Vec::new(),
+            };
+            let event = runtime
+                .lookup(connection.as_str(), event, &EventMetadata::new())
+                .await?;
+            runtime.push_event(connection.as_str(), event)?;
+            replayed += 1;
+
+            actions.extend(runtime.poll_actions()?);
+            match &connections {
+                Some(connections) => take_actions(connections, actions).await?,
+                None => {
+                    for action in &actions {
+                        writeln!(output, "{}", action.describe())
+                            .map_err(|e| LaikaError::IO(e.to_string()))?;
+                    }
+                }
+            }
+        }
+    }
+    tracing::info!("Replayed {} events", replayed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use laika_combiner::config::builder::EventProcessorYamlSpec;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+connections:
+  orders:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+  paid:
+    from: orders
+    matchKey:
+      type: paid
+correlation:
+  placed:
+    key: "$.orderId"
+  paid:
+    key: "$.orderId"
+triggers:
+  unpaid:
+    absent:
+      - paid
+    within: 30m
+    requires:
+      at_least: [placed]
+    filterAndExtract: >
+      (trigger, ctx) => ({ orderId: ctx.events.placed[0].orderId })
+    action:
+      target: alerts
+      payload:
+        orderId: "${{ orderId }}"
+"#;
+
+    #[tokio::test]
+    async fn test_replay_at_recorded_times() {
+        let dir = TempDir::new().unwrap();
+        let source = dir.path().join("orders.jsonl");
+        let events = [
+            json!({"type": "placed", "orderId": "A1", "timestamp": "2024-03-01T12:00:00Z"}),
+            json!({"type": "paid", "orderId": "A1", "timestamp": "2024-03-01T12:10:00Z"}),
+            json!({"type": "placed", "orderId": "A2", "timestamp": "2024-03-01T12:20:00Z"}),
+            json!({"type": "placed", "orderId": "A3", "timestamp": "2024-03-01T13:00:00Z"}),
+        ];
+        let lines: Vec<String> = events.iter().map(Value::to_string).collect();
+        std::fs::write(&source, lines.join("\n")).unwrap();
+        let output = dir.path().join("actions.jsonl");
+        let args = ReplayArgs {
+            from: None,
+            source,
+            connection: None,
+            timestamp_field: "timestamp".to_string(),
+            dry_run: true,
+            output: Some(output.clone()),
+        };
+        let config =
+            EventProcessorConfig::try_from(&EventProcessorYamlSpec::from_yaml(CONFIG).unwrap())
+                .unwrap();
+        replay_in(args, config, false, &dir.path().join("state"))
+            .await
+            .unwrap();
+
+        // Only A2 went unpaid for 30 minutes by the time the last event was received
+        let actions: Vec<Value> = std::fs::read_to_string(&output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["type"], "emit");
+        assert_eq!(actions[0]["payload"], json!({"orderId": "A2"}));
+    }
+}
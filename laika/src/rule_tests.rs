@@ -0,0 +1,24 @@
+use clap::Args;
+use laika_combiner::config::builder::EventProcessorYamlSpec;
+use laika_combiner::errors::LaikaResult;
+use laika_combiner::rule_tests::run_rule_tests;
+
+#[derive(Args)]
+pub struct TestArgs {
+    /// Only run the tests of this rule
+    #[arg(long)]
+    rule: Option<String>,
+}
+
+/// Run the `tests` written under each rule, printing whether each passed. Returns whether they
+/// all did.
+pub fn test(args: TestArgs, config: &str) -> LaikaResult<bool> {
+    let spec = EventProcessorYamlSpec::from_path(config)?;
+    let results = run_rule_tests(&spec, args.rule.as_deref())?;
+    for result in &results {
+        println!("{}", result);
+    }
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    Ok(failed == 0)
+}
@@ -0,0 +1,172 @@
+use crate::replay::{event_timestamp, open_archive};
+use crate::source_connection;
+use clap::Args;
+use laika_combiner::Runtime;
+use laika_combiner::action::EventAction;
+use laika_combiner::clock::{Clock, TestClock};
+use laika_combiner::config::EventProcessorConfig;
+use laika_combiner::errors::{LaikaError, LaikaResult};
+use laika_combiner::event::EventMetadata;
+use laika_combiner::golden::GoldenCapture;
+use laika_combiner::storage::StorageKV;
+use laika_combiner::timing::TimingExpiry;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Args)]
+pub struct SimulateArgs {
+    /// Events to simulate, as a JSON Lines file (optionally gzipped)
+    #[arg(long)]
+    source: PathBuf,
+    /// Connection to treat events as received from, when the config has more than one source
+    #[arg(long)]
+    connection: Option<String>,
+    /// Field holding when each event is received, as RFC 3339 or Unix seconds. Events without it
+    /// are received straight after the one before.
+    #[arg(long, default_value = "timestamp")]
+    timestamp_field: String,
+    /// RFC 3339 timestamp to start the clock at, rather than when the first event is received
+    #[arg(long)]
+    start: Option<String>,
+    /// RFC 3339 timestamp to keep the clock running until after the last event, so wakeups due
+    /// by then fire
+    #[arg(long)]
+    until: Option<String>,
+    /// File to write actions to, rather than stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Golden file of expected emits and requests, as deterministic JSON Lines, to compare the
+    /// simulation's with
+    #[arg(long)]
+    golden: Option<PathBuf>,
+    /// Write the golden file from the simulation, rather than comparing with it
+    #[arg(long, requires = "golden")]
+    update_golden: bool,
+}
+
+pub(crate) fn parse_timestamp(
+    timestamp: Option<&str>,
+    arg: &str,
+) -> LaikaResult<Option<OffsetDateTime>> {
+    timestamp
+        .map(|timestamp| OffsetDateTime::parse(timestamp, &Rfc3339))
+        .transpose()
+        .map_err(|e| LaikaError::Generic(format!("Invalid --{} timestamp: {}", arg, e)))
+}
+
+/// Write out actions along with the simulated time they were taken at
+fn write_actions(
+    output: &mut dyn Write,
+    capture: &mut GoldenCapture,
+    taken_at: OffsetDateTime,
+    actions: Vec<EventAction>,
+) -> LaikaResult<()> {
+    capture.record(&actions);
+    let taken_at = taken_at
+        .format(&Rfc3339)
+        .map_err(|e| LaikaError::Generic(e.to_string()))?;
+    for action in &actions {
+        writeln!(output, "{} {}", taken_at, action.describe())
+            .map_err(|e| LaikaError::IO(e.to_string()))?;
+    }
+    Ok(())
+}
+
+/// Move the clock on to `until`, writing out the actions of each wakeup on the way
+fn advance_to(
+    runtime: &mut Runtime,
+    clock: &TestClock,
+    until: OffsetDateTime,
+    output: &mut dyn Write,
+    capture: &mut GoldenCapture,
+) -> LaikaResult<()> {
+    for (taken_at, actions) in runtime.advance_to(clock, until)? {
+        write_actions(output, capture, taken_at, actions)?;
+    }
+    Ok(())
+}
+
+/// Run events through the rules on a simulated clock, which moves to when each event is received
+/// rather than waiting for it, so timing rules can be tried out in moments. Nothing is sent to
+/// targets, and state is kept in memory rather than the config's `storage`.
+pub async fn simulate(args: SimulateArgs, config: EventProcessorConfig) -> LaikaResult<()> {
+    let connection = source_connection(&config, args.connection)?;
+    let until = parse_timestamp(args.until.as_deref(), "until")?;
+    let mut events = Vec::new();
+    for line in open_archive(&args.source)?.lines() {
+        let line = line.map_err(|e| LaikaError::IO(e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Value = serde_json::from_str(line.as_str())
+            .map_err(|e| LaikaError::JsonError(e.to_string()))?;
+        let received = event_timestamp(&event, args.timestamp_field.as_str());
+        events.push((received, event));
+    }
+    let start = parse_timestamp(args.start.as_deref(), "start")?
+        .or_else(|| events.iter().find_map(|(received, _)| *received))
+        .unwrap_or_else(OffsetDateTime::now_utc);
+
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path).map_err(|e| LaikaError::IO(e.to_string()))?),
+        None => Box::new(std::io::stdout()),
+    };
+    let timer_path = std::env::temp_dir().join(format!("laika_simulate_{}", std::process::id()));
+    let clock = TestClock::new(start);
+    let max_wakeups = config.storage().max_wakeups();
+    let mut runtime = Runtime::new_with_clock(
+        vec![config.build()?],
+        StorageKV::in_memory(),
+        TimingExpiry::new(timer_path.clone())?.with_max_wakeups(max_wakeups),
+        Arc::new(clock.clone()),
+    )?;
+
+    let mut capture = GoldenCapture::new();
+    let result = async {
+        for (received, event) in events {
+            if let Some(received) = received {
+                advance_to(
+                    &mut runtime,
+                    &clock,
+                    received,
+                    output.as_mut(),
+                    &mut capture,
+                )?;
+            }
+            let event = runtime
+                .lookup(connection.as_str(), event, &EventMetadata::new())
+                .await?;
+            runtime.push_event(connection.as_str(), event)?;
+            write_actions(
+                output.as_mut(),
+                &mut capture,
+                clock.now(),
+                runtime.poll_actions()?,
+            )?;
+        }
+        match until {
+            Some(until) => advance_to(&mut runtime, &clock, until, output.as_mut(), &mut capture),
+            None => Ok(()),
+        }
+    }
+    .await;
+    let _ = std::fs::remove_file(&timer_path);
+    result?;
+
+    match &args.golden {
+        Some(golden) if args.update_golden => capture.write_file(golden),
+        Some(golden) => capture.compare_file(golden)?.map_err(|mismatch| {
+            LaikaError::Generic(format!(
+                "Output differs from {}: {}",
+                golden.display(),
+                mismatch
+            ))
+        }),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,56 @@
+use clap::{Args, Subcommand};
+use laika_combiner::config::EventProcessorConfig;
+use laika_combiner::errors::{LaikaError, LaikaResult};
+use laika_combiner::state::inspect;
+use laika_combiner::storage::StorageKV;
+use laika_combiner::timing::TimingExpiry;
+
+#[derive(Args)]
+pub struct StateArgs {
+    #[command(subcommand)]
+    command: StateCommand,
+}
+
+#[derive(Subcommand)]
+enum StateCommand {
+    /// Print the stored events, stored values, rule fires and pending expiries of a correlation
+    Get { correlation_id: String },
+    /// Print the IDs of correlations with stored events, one per line
+    List {
+        /// Only list correlations whose ID starts with this
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Most correlations to list
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+}
+
+/// Open the configured store only to read it, so it can be read while Laika is running, except
+/// for redb stores which are locked while they're open
+fn open_storage(config: &EventProcessorConfig) -> LaikaResult<StorageKV> {
+    config.storage().builder().read_only(true).build()
+}
+
+pub fn state(args: StateArgs, config: EventProcessorConfig) -> LaikaResult<()> {
+    let storage = open_storage(&config)?;
+    match args.command {
+        StateCommand::Get { correlation_id } => {
+            let mut timer = TimingExpiry::new(config.storage().timer_path())?;
+            let state = inspect(&storage, &mut timer, correlation_id.as_str())?;
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&state)
+                    .map_err(|e| LaikaError::JsonError(e.to_string()))?
+            );
+        }
+        StateCommand::List { prefix, limit } => {
+            for correlation_id in
+                storage.correlation_ids_with_prefix(prefix.as_str(), Some(limit))?
+            {
+                println!("{}", correlation_id);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use clap::Args;
+use laika_combiner::config::EventProcessorConfig;
+use laika_combiner::errors::{LaikaError, LaikaResult};
+use laika_combiner::predicate_types::type_definitions;
+use std::path::PathBuf;
+
+#[derive(Args)]
+pub struct TypesArgs {
+    /// File to write the declarations to, rather than stdout
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+/// Write declarations of the trigger, context and `laika` object for the config's predicates, so
+/// `.ts` files in `lib` can be checked with `tsc`
+pub fn types(args: TypesArgs, config: &EventProcessorConfig) -> LaikaResult<()> {
+    let types = type_definitions(config);
+    match args.output {
+        None => print!("{}", types),
+        Some(output) => {
+            std::fs::write(&output, types).map_err(|e| LaikaError::IO(e.to_string()))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use laika_combiner::bench::Workload;
+use laika_combiner::storage::{StorageConfig, StorageKV};
+use laika_combiner::timing::TimingExpiry;
+use tempfile::TempDir;
+
+/// Events through the matcher, storage and rules, from a fresh runtime each iteration so
+/// correlations don't build up between them
+fn pipeline(c: &mut Criterion) {
+    let workload = Workload::new(100, 10);
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements(workload.len() as u64));
+    // Only the predicate engine and matcher, as state is kept in memory
+    group.bench_function("in_memory", |b| {
+        b.iter_batched(
+            || {
+                let state_dir = TempDir::new().unwrap();
+                let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+                let runtime = workload.runtime(StorageKV::in_memory(), waker).unwrap();
+                (state_dir, runtime)
+            },
+            |(state_dir, mut runtime)| {
+                let report = workload.run(&mut runtime).unwrap();
+                // Returned so they're dropped outside of the measurement
+                (state_dir, runtime, report)
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("default_storage", |b| {
+        b.iter_batched(
+            || {
+                let state_dir = TempDir::new().unwrap();
+                let storage = StorageConfig::default()
+                    .builder_at(state_dir.path())
+                    .build()
+                    .unwrap();
+                let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+                let runtime = workload.runtime(storage, waker).unwrap();
+                (state_dir, runtime)
+            },
+            |(state_dir, mut runtime)| {
+                let report = workload.run(&mut runtime).unwrap();
+                (state_dir, runtime, report)
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
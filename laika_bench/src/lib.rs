@@ -0,0 +1,4 @@
+//! Criterion benchmarks for Laika, run with `cargo bench -p laika_bench`.
+//!
+//! The workload is shared with `laika bench` through `laika_combiner::bench`, so the numbers
+//! reported by each are comparable.
@@ -5,4 +5,6 @@ fn main() {
         .file("schemas/event_schema.capnp")
         .run()
         .unwrap();
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("schemas/ingest.proto").unwrap();
 }
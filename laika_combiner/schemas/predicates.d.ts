@@ -0,0 +1,85 @@
+// What Laika gives `filterAndExtract` functions, for type checking predicates kept in `.ts` files.
+// `laika types` adds the event types and rules of a config to these.
+
+declare namespace Laika {
+  /** Metadata of the message an event was received in, such as its headers */
+  type Metadata = Record<string, unknown>;
+
+  interface ReceivedEvent<E extends string = EventType> {
+    type: "received_event";
+    /** Unix seconds the event was received at */
+    timestamp: number;
+    eventType: E;
+    event: any;
+    meta?: Metadata;
+  }
+
+  interface TimerExpired {
+    type: "timer_expired";
+    timestamp: number;
+    rule: RuleName;
+    /** The JSON of the correlation key, or a window's group */
+    correlationId: string;
+    kind: "recheck" | "window_close" | "scheduled" | "timeout";
+    /** RFC 3339 */
+    expiresAt: string;
+  }
+
+  interface Scheduled {
+    type: "scheduled";
+    timestamp: number;
+    rule: RuleName;
+  }
+
+  type Trigger = ReceivedEvent | TimerExpired | Scheduled;
+
+  /** An event stored against the correlation, as kept in `ctx.events` */
+  type StoredEvent<E extends string = EventType> =
+    | { Correlated: { received: unknown; correlation_id: string; event_type: E; data: any } }
+    | { NonCorrelated: { received: unknown; event_type: E; data: any } };
+
+  interface Decision {
+    /** RFC 3339 */
+    firedAt: string;
+    fires: number;
+    result: unknown;
+  }
+
+  interface Stats {
+    count: number;
+    /** RFC 3339 */
+    first: string | null;
+    last: string | null;
+    /** Only for fields given in `context.stats` */
+    min?: Record<string, number | null>;
+    max?: Record<string, number | null>;
+  }
+
+  interface Context {
+    sequence: { type: EventType; data: any }[];
+    events: { [E in EventType]?: StoredEvent<E>[] };
+    stored: Record<string, unknown>;
+    decisions: { [R in RuleName]?: Decision };
+    stats: { [E in EventType]?: Stats };
+  }
+
+  /** What a predicate returns, where `null` means the rule doesn't fire */
+  type Result = Record<string, unknown> | boolean | null;
+
+  type Predicate = (trigger: Trigger, ctx: Context) => Result;
+}
+
+declare const laika: {
+  /** The value at a path such as `$.items[0].price`, or `null` when there's nothing there */
+  extract(path: string, value: unknown): any;
+  /** Milliseconds since the Unix epoch, on the same clock as timing rules */
+  now(): number;
+  log(level: "error" | "warn" | "info" | "debug" | "trace", message: unknown): void;
+  /** SHA-256 of a string, or of the JSON of any other value, as hex */
+  hash(value: unknown): string;
+  global: {
+    get(key: string): any;
+    /** Removes the key when `value` is `null`. `ttl` is a duration such as `10m`. */
+    set(key: string, value: unknown, ttl?: string): void;
+  };
+};
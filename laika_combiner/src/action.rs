@@ -1,4 +1,10 @@
-use crate::broker::EventExpiry;
+use crate::broker::{CorrelationId, EventExpiry};
+use crate::connections::auth::HttpAuth;
+use crate::connections::compression::Compression;
+use crate::connections::tls::TlsConfig;
+use crate::format::EmitFormat;
+use sha2::{Digest, Sha256};
+use std::fmt::{Debug, Formatter};
 use time::OffsetDateTime;
 
 #[derive(Clone)]
@@ -12,6 +18,16 @@ pub struct EmitAction {
     pub target: String,
     /// Rendered payload to be provided to the downstream
     payload: serde_json::Value,
+    /// Rendered routing key or topic, if the downstream supports routing
+    pub routing: Option<String>,
+    /// How the payload is encoded when it's submitted
+    pub format: EmitFormat,
+    /// Correlation the action was produced for, which orders actions to the same target
+    pub correlation_id: Option<CorrelationId>,
+    /// Times the event that led to the action had looped back through internal connections
+    pub depth: u32,
+    /// Span of the message that led to the action, which its emit is traced within
+    pub span: tracing::Span,
 }
 
 impl EmitAction {
@@ -19,16 +35,135 @@ impl EmitAction {
         Self {
             target,
             payload: event,
+            routing: None,
+            format: EmitFormat::Json,
+            correlation_id: None,
+            depth: 0,
+            span: tracing::Span::none(),
         }
     }
 
+    pub fn with_routing(mut self, routing: Option<String>) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    pub fn with_format(mut self, format: EmitFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_correlation(mut self, correlation_id: Option<CorrelationId>) -> Self {
+        self.correlation_id = correlation_id;
+        self
+    }
+
     pub fn payload(self) -> serde_json::Value {
         self.payload
     }
 }
 
+/// Rendered value to be stored against a correlation, rather than emitted
+#[derive(Clone, Debug)]
+pub struct StoreAction {
+    pub correlation_id: CorrelationId,
+    pub key: String,
+    pub value: serde_json::Value,
+}
+
+/// Rendered HTTP request to be sent directly, without a connection target
+#[derive(Clone)]
+pub struct HttpRequest {
+    pub method: reqwest::Method,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<serde_json::Value>,
+    /// TLS settings for the request, rather than the defaults
+    pub tls: Option<TlsConfig>,
+    /// How the body is compressed, given as its `Content-Encoding`
+    pub compression: Compression,
+    /// Credentials the request is sent with
+    pub auth: Option<HttpAuth>,
+    /// Correlation the request was produced for, which orders requests
+    pub correlation_id: Option<CorrelationId>,
+}
+
+impl Debug for HttpRequest {
+    // Headers commonly carry credentials, so only their names are shown
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpRequest")
+            .field("method", &self.method)
+            .field("url", &self.url)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("body", &self.body)
+            .field("tls", &self.tls)
+            .field("compression", &self.compression)
+            .field("auth", &self.auth)
+            .field("correlation_id", &self.correlation_id)
+            .finish()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum EventAction {
     Emit(EmitAction),
+    Http(HttpRequest),
+    Store(StoreAction),
     ScheduleWakeup(EventExpiry),
+    /// Remove pending rechecks of a rule for a correlation, once the rule's been satisfied
+    CancelWakeup(CorrelationId, String),
+}
+
+impl EventAction {
+    /// Summary of the action for logs and dry runs. HTTP headers are left out, as they commonly
+    /// carry credentials.
+    pub fn describe(&self) -> serde_json::Value {
+        match self {
+            EventAction::Emit(emit) => serde_json::json!({
+                "type": "emit",
+                "target": emit.target,
+                "routing": emit.routing,
+                "format": emit.format.name(),
+                "payload": emit.payload,
+            }),
+            EventAction::Http(request) => serde_json::json!({
+                "type": "http",
+                "method": request.method.as_str(),
+                "url": request.url,
+                "body": request.body,
+            }),
+            EventAction::Store(store) => serde_json::json!({
+                "type": "store",
+                "correlationId": store.correlation_id,
+                "key": store.key,
+                "value": store.value,
+            }),
+            EventAction::ScheduleWakeup(expiry) => serde_json::json!({
+                "type": "scheduleWakeup",
+                "rule": expiry.event_rule,
+                "correlationId": expiry.correlation_id,
+                "at": expiry.expires_at.unix_timestamp(),
+            }),
+            EventAction::CancelWakeup(correlation_id, rule) => serde_json::json!({
+                "type": "cancelWakeup",
+                "rule": rule,
+                "correlationId": correlation_id,
+            }),
+        }
+    }
+    /// Digest of what the action does, stable across restarts and releases, for recognising
+    /// the same action taken again
+    pub fn digest(&self) -> String {
+        format!(
+            "{:x}",
+            Sha256::digest(self.describe().to_string().as_bytes())
+        )
+    }
 }
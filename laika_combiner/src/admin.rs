@@ -0,0 +1,157 @@
+use crate::connections::{ConnectionStatus, Connections};
+use crate::controls::RuntimeControls;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::shadow::ShadowReport;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// HTTP API for inspecting and controlling a running processor
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Address to listen on, such as `127.0.0.1:9090`
+    pub listen: SocketAddr,
+}
+
+#[derive(Clone)]
+struct AdminState {
+    controls: RuntimeControls,
+    connections: Arc<Connections>,
+    shadow: Option<ShadowReport>,
+}
+
+/// Routes of the admin API:
+///
+/// - `GET /rules` lists loaded rules, whether they're enabled, and how often they've fired
+/// - `GET /rules/{name}` shows a single rule
+/// - `POST /rules/{name}/disable` and `POST /rules/{name}/enable` switch a rule off and on
+/// - `GET /connections` shows whether sources are still being received from, and targets
+/// - `POST /connections/{name}/pause` and `POST /connections/{name}/resume` stop and restart
+///   reading from a source
+/// - `GET /timers` shows how many wakeups are pending
+/// - `GET /shadow` shows how a shadow config's actions diverge from the active config's, when
+///   one is running
+pub fn router(
+    controls: RuntimeControls,
+    connections: Arc<Connections>,
+    shadow: Option<ShadowReport>,
+) -> Router {
+    Router::new()
+        .route("/rules", get(rules))
+        .route("/rules/:name", get(rule))
+        .route("/rules/:name/disable", post(disable_rule))
+        .route("/rules/:name/enable", post(enable_rule))
+        .route("/connections", get(connections_status))
+        .route("/connections/:name/pause", post(pause_source))
+        .route("/connections/:name/resume", post(resume_source))
+        .route("/timers", get(timers))
+        .route("/shadow", get(shadow_report))
+        .with_state(AdminState {
+            controls,
+            connections,
+            shadow,
+        })
+}
+
+/// Serve the admin API until the listener fails
+pub async fn serve(
+    config: &AdminConfig,
+    controls: RuntimeControls,
+    connections: Arc<Connections>,
+    shadow: Option<ShadowReport>,
+) -> LaikaResult<()> {
+    let listener = tokio::net::TcpListener::bind(config.listen)
+        .await
+        .map_err(|e| LaikaError::config("admin.listen", e.to_string()))?;
+    tracing::info!("Admin API listening on {}", config.listen);
+    axum::serve(listener, router(controls, connections, shadow))
+        .await
+        .map_err(|e| LaikaError::IO(e.to_string()))
+}
+
+fn not_loaded(name: &str) -> Response {
+    (StatusCode::NOT_FOUND, format!("No rule named {}", name)).into_response()
+}
+
+async fn rules(State(state): State<AdminState>) -> Response {
+    Json(state.controls.rules()).into_response()
+}
+
+async fn rule(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    match state.controls.rule(&name) {
+        Some(status) => Json(status).into_response(),
+        None => not_loaded(&name),
+    }
+}
+
+async fn disable_rule(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    match state.controls.disable_rule(&name) {
+        Some(status) => {
+            tracing::warn!("Rule {} disabled through the admin API", name);
+            Json(status).into_response()
+        }
+        None => not_loaded(&name),
+    }
+}
+
+async fn enable_rule(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    match state.controls.enable_rule(&name) {
+        Some(status) => {
+            tracing::warn!("Rule {} enabled through the admin API", name);
+            Json(status).into_response()
+        }
+        None => not_loaded(&name),
+    }
+}
+
+async fn connections_status(State(state): State<AdminState>) -> Response {
+    Json(state.connections.status().await).into_response()
+}
+
+/// Status of a source, once it's been paused or resumed
+async fn source_status(state: &AdminState, name: &str) -> Response {
+    let status = state.connections.status().await.into_iter().find(
+        |status| matches!(status, ConnectionStatus::Source { name: source, .. } if source == name),
+    );
+    match status {
+        Some(status) => Json(status).into_response(),
+        None => no_source(name),
+    }
+}
+
+fn no_source(name: &str) -> Response {
+    (StatusCode::NOT_FOUND, format!("No source named {}", name)).into_response()
+}
+
+async fn pause_source(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    if !state.connections.pause(&name) {
+        return no_source(&name);
+    }
+    tracing::warn!("Source {} paused through the admin API", name);
+    source_status(&state, &name).await
+}
+
+async fn resume_source(State(state): State<AdminState>, Path(name): Path<String>) -> Response {
+    if !state.connections.resume(&name) {
+        return no_source(&name);
+    }
+    tracing::warn!("Source {} resumed through the admin API", name);
+    source_status(&state, &name).await
+}
+
+async fn timers(State(state): State<AdminState>) -> Response {
+    Json(serde_json::json!({ "pending": state.controls.pending_wakeups() })).into_response()
+}
+
+async fn shadow_report(State(state): State<AdminState>) -> Response {
+    match &state.shadow {
+        Some(shadow) => Json(shadow.report()).into_response(),
+        None => (StatusCode::NOT_FOUND, "No shadow config is running").into_response(),
+    }
+}
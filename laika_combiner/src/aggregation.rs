@@ -0,0 +1,223 @@
+use crate::broker::CorrelationId;
+use crate::errors::LaikaResult;
+use crate::event::context::EventContext;
+use crate::event::{Event, EventLike, Trigger};
+use crate::matcher::EventType;
+use crate::storage::StorageKV;
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+
+/// Bounds an aggregated value must fall within for a window to be considered met.
+///
+/// All provided bounds must hold, i.e. `{gt: 2, lte: 5}` is met by 3, 4 and 5.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Threshold {
+    pub(crate) gt: Option<f64>,
+    pub(crate) gte: Option<f64>,
+    pub(crate) lt: Option<f64>,
+    pub(crate) lte: Option<f64>,
+}
+
+impl Threshold {
+    pub fn is_empty(&self) -> bool {
+        self.gt.is_none() && self.gte.is_none() && self.lt.is_none() && self.lte.is_none()
+    }
+
+    pub fn is_met(&self, value: f64) -> bool {
+        self.gt.is_none_or(|bound| value > bound)
+            && self.gte.is_none_or(|bound| value >= bound)
+            && self.lt.is_none_or(|bound| value < bound)
+            && self.lte.is_none_or(|bound| value <= bound)
+    }
+
+    /// Thresholds that only bound from below can be met as soon as events arrive, as further
+    /// events can never un-meet them. Upper bounds can only be judged once the window closes.
+    fn met_early(&self, value: f64) -> bool {
+        self.lt.is_none() && self.lte.is_none() && self.is_met(value)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum AggregateFunction {
+    /// Number of events within the window
+    Count,
+    /// Sum of a numeric field (JSONPath) across events within the window
+    Sum(String),
+}
+
+/// A tumbling window over a single event type within one correlation.
+///
+/// The window opens on the first matching event, and closes `within` later. The threshold is
+/// checked on every matching event, and once more when the window closes.
+#[derive(Clone, Debug)]
+pub struct AggregateConfig {
+    pub(crate) event_type: EventType,
+    pub(crate) within: Duration,
+    pub(crate) function: AggregateFunction,
+    pub(crate) threshold: Threshold,
+}
+
+/// What happened to a rule's window as a result of a single trigger.
+#[derive(Debug, Default)]
+pub(crate) struct WindowObservation {
+    /// A window was opened, and should be closed at the given time.
+    pub(crate) opened_until: Option<OffsetDateTime>,
+    /// The window met its threshold with the given value.
+    pub(crate) met: Option<f64>,
+}
+
+impl AggregateConfig {
+    fn in_window(&self, event: &Event, window_start: OffsetDateTime) -> bool {
+        event.event_type().as_ref() == Some(&self.event_type)
+            && *event.received() >= window_start
+            && *event.received() < window_start + self.within
+    }
+
+    /// Aggregate all events of the configured type within the window starting at `window_start`
+    pub(crate) fn aggregate<'a>(
+        &self,
+        events: impl Iterator<Item = &'a Event>,
+        window_start: OffsetDateTime,
+    ) -> f64 {
+        let windowed = events.filter(|event| self.in_window(event, window_start));
+        match &self.function {
+            AggregateFunction::Count => windowed.count() as f64,
+            AggregateFunction::Sum(path) => windowed
+                .filter_map(|event| {
+                    let value = event.try_extract(path).and_then(|v| v.as_f64());
+                    if value.is_none() {
+                        tracing::debug!("Event has no numeric value at {}, skipping in sum", path);
+                    }
+                    value
+                })
+                .sum(),
+        }
+    }
+
+    /// Update the stored window for `rule` with the trigger, and report whether it was met.
+    pub(crate) fn observe(
+        &self,
+        storage_kv: &StorageKV,
+        rule: &str,
+        correlation_id: &CorrelationId,
+        trigger: &Trigger,
+        context: &EventContext,
+    ) -> LaikaResult<WindowObservation> {
+        let mut observation = WindowObservation::default();
+        match trigger {
+            Trigger::ReceivedEvent(event) => {
+                if event.event_type().as_ref() != Some(&self.event_type) {
+                    return Ok(observation);
+                }
+                let received = *event.received();
+                let window_start = match storage_kv.read_window(rule, correlation_id)? {
+                    Some(start) if received < start + self.within => start,
+                    _ => {
+                        storage_kv.open_window(rule, correlation_id, received)?;
+                        observation.opened_until = Some(received + self.within);
+                        received
+                    }
+                };
+                let value =
+                    self.aggregate(context.events().chain(std::iter::once(event)), window_start);
+                if self.threshold.met_early(value) {
+                    storage_kv.close_window(rule, correlation_id)?;
+                    observation.met = Some(value);
+                }
+            }
+            Trigger::TimerExpired(expiry) => {
+                if expiry.event_rule != rule {
+                    return Ok(observation);
+                }
+                let Some(window_start) = storage_kv.read_window(rule, correlation_id)? else {
+                    return Ok(observation);
+                };
+                if expiry.expires_at < window_start + self.within {
+                    // Wakeup belongs to a window that has since been closed and reopened.
+                    return Ok(observation);
+                }
+                storage_kv.close_window(rule, correlation_id)?;
+                let value = self.aggregate(context.events(), window_start);
+                if self.threshold.is_met(value) {
+                    observation.met = Some(value);
+                }
+            }
+            Trigger::Scheduled(_) => {}
+        }
+        Ok(observation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::RawEvent;
+    use serde_json::json;
+
+    fn count_over(threshold: Threshold) -> AggregateConfig {
+        AggregateConfig {
+            event_type: "PaymentFailed".to_string(),
+            within: Duration::minutes(10),
+            function: AggregateFunction::Count,
+            threshold,
+        }
+    }
+
+    fn event_at(event_type: &str, start: OffsetDateTime, offset: Duration, amount: f64) -> Event {
+        let mut event = RawEvent::new(json!({"amount": amount}))
+            .parse(event_type, Some("correlation".to_string()));
+        event.set_received(start + offset);
+        event
+    }
+
+    #[test]
+    fn test_threshold_bounds() {
+        let threshold = Threshold {
+            gt: Some(2.0),
+            lte: Some(5.0),
+            ..Default::default()
+        };
+        assert!(!threshold.is_met(2.0));
+        assert!(threshold.is_met(3.0));
+        assert!(threshold.is_met(5.0));
+        assert!(!threshold.is_met(6.0));
+        assert!(!threshold.met_early(3.0));
+        assert!(Threshold::default().is_empty());
+    }
+
+    #[test]
+    fn test_aggregate_only_counts_window() {
+        let start = OffsetDateTime::now_utc();
+        let events = vec![
+            event_at("PaymentFailed", start, Duration::minutes(-1), 1.0),
+            event_at("PaymentFailed", start, Duration::minutes(1), 1.0),
+            event_at("PaymentSettled", start, Duration::minutes(2), 1.0),
+            event_at("PaymentFailed", start, Duration::minutes(9), 1.0),
+            event_at("PaymentFailed", start, Duration::minutes(10), 1.0),
+        ];
+        let config = count_over(Threshold {
+            gt: Some(1.0),
+            ..Default::default()
+        });
+        assert_eq!(config.aggregate(events.iter(), start), 2.0);
+    }
+
+    #[test]
+    fn test_sum_skips_missing_fields() {
+        let start = OffsetDateTime::now_utc();
+        let mut events = vec![
+            event_at("PaymentFailed", start, Duration::seconds(1), 12.5),
+            event_at("PaymentFailed", start, Duration::seconds(2), 7.5),
+        ];
+        let mut missing = RawEvent::new(json!({"other": 1}))
+            .parse("PaymentFailed", Some("correlation".to_string()));
+        missing.set_received(start + Duration::seconds(3));
+        events.push(missing);
+        let config = AggregateConfig {
+            function: AggregateFunction::Sum("$.amount".to_string()),
+            ..count_over(Threshold::default())
+        };
+        assert_eq!(config.aggregate(events.iter(), start), 20.0);
+    }
+}
@@ -0,0 +1,49 @@
+use crate::broker::CorrelationId;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// Version of the audit record layout, bumped whenever fields change meaning so older records
+/// can still be told apart
+pub const AUDIT_RECORD_VERSION: u32 = 1;
+
+/// Correlation that records for events without one are kept under
+pub const UNCORRELATED: &str = "*";
+
+/// An event type a received event was matched as
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchedEvent {
+    pub event_type: String,
+    pub correlation_id: Option<CorrelationId>,
+}
+
+/// A received event, and what processing it led to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub version: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub received: OffsetDateTime,
+    /// Connection the event was received from
+    pub source: String,
+    pub event: serde_json::Value,
+    /// Empty when the event didn't match any event type
+    pub matched: Vec<MatchedEvent>,
+    /// Descriptions of the actions produced, see `EventAction::describe`
+    pub actions: Vec<serde_json::Value>,
+}
+
+impl AuditRecord {
+    /// Correlations the record is kept under
+    pub(crate) fn correlation_ids(&self) -> Vec<&str> {
+        let mut correlation_ids: Vec<&str> = self
+            .matched
+            .iter()
+            .map(|matched| matched.correlation_id.as_deref().unwrap_or(UNCORRELATED))
+            .collect();
+        if correlation_ids.is_empty() {
+            correlation_ids.push(UNCORRELATED);
+        }
+        correlation_ids.sort();
+        correlation_ids.dedup();
+        correlation_ids
+    }
+}
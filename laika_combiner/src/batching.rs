@@ -0,0 +1,185 @@
+use crate::action::EmitAction;
+use crate::connections::Connections;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::format::EmitFormat;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{oneshot, Mutex};
+
+/// How emits to a target are grouped into a single submission
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EmitBatching {
+    /// Payloads submitted together at most
+    pub max_batch: usize,
+    /// How long the first payload of a batch waits for others to join it
+    pub max_delay: Duration,
+}
+
+/// Payloads waiting to be submitted together, with whoever is waiting on each
+#[derive(Default)]
+struct PendingBatch {
+    /// Distinguishes this batch from later ones with the same routing, so a batch that's been
+    /// submitted for being full isn't followed by its delayed flush submitting the next early
+    generation: u64,
+    payloads: Vec<serde_json::Value>,
+    waiting: Vec<oneshot::Sender<LaikaResult<()>>>,
+}
+
+/// Groups JSON payloads emitted to a target, by routing key, submitting each group once it's full
+/// or its first payload has waited `max_delay`
+pub(crate) struct EmitBatcher {
+    target: String,
+    batching: EmitBatching,
+    connections: Arc<Connections>,
+    pending: Mutex<HashMap<Option<String>, PendingBatch>>,
+    generations: AtomicU64,
+}
+
+impl EmitBatcher {
+    pub(crate) fn new(target: &str, batching: EmitBatching, connections: Arc<Connections>) -> Self {
+        Self {
+            target: target.to_string(),
+            batching,
+            connections,
+            pending: Mutex::new(HashMap::new()),
+            generations: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether an emit can join a batch, which only JSON payloads can, as batches are submitted
+    /// as JSON
+    pub(crate) fn batches(action: &EmitAction) -> bool {
+        matches!(
+            action.format,
+            EmitFormat::Json | EmitFormat::CloudEvents { .. }
+        )
+    }
+
+    /// Add an emit to its batch, returning once the batch has been submitted
+    pub(crate) async fn emit(self: Arc<Self>, action: EmitAction) -> LaikaResult<()> {
+        let routing = action.routing.clone();
+        let Some(payload) = self
+            .connections
+            .guard_emit(self.target.as_str(), action.payload())
+            .await?
+        else {
+            return Ok(());
+        };
+        let (done, submitted) = oneshot::channel();
+        let (full, started) = {
+            let mut pending = self.pending.lock().await;
+            let batch = pending.entry(routing.clone()).or_default();
+            let started = batch.payloads.is_empty().then(|| {
+                batch.generation = self.generations.fetch_add(1, Ordering::Relaxed);
+                batch.generation
+            });
+            batch.payloads.push(payload);
+            batch.waiting.push(done);
+            let full = (batch.payloads.len() >= self.batching.max_batch)
+                .then(|| pending.remove(&routing))
+                .flatten();
+            (full, started)
+        };
+        match full {
+            Some(batch) => self.submit(routing, batch).await,
+            None => {
+                if let Some(generation) = started {
+                    tokio::spawn(self.clone().flush_after_delay(routing, generation));
+                }
+            }
+        }
+        submitted
+            .await
+            .unwrap_or_else(|e| Err(LaikaError::ChannelError(e.to_string())))
+    }
+
+    async fn flush_after_delay(self: Arc<Self>, routing: Option<String>, generation: u64) {
+        tokio::time::sleep(self.batching.max_delay).await;
+        let batch = {
+            let mut pending = self.pending.lock().await;
+            match pending.get(&routing) {
+                Some(batch) if batch.generation == generation => pending.remove(&routing),
+                _ => None,
+            }
+        };
+        if let Some(batch) = batch {
+            self.submit(routing, batch).await;
+        }
+    }
+
+    async fn submit(&self, routing: Option<String>, batch: PendingBatch) {
+        tracing::debug!(
+            "Submitting {} payloads to {} in one batch",
+            batch.payloads.len(),
+            self.target
+        );
+        let result = self
+            .connections
+            .submit_batch(self.target.as_str(), batch.payloads, routing.as_deref())
+            .await;
+        for waiting in batch.waiting {
+            let _ = waiting.send(result.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::{EventSubmitter, MessagingError};
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+
+    /// Records each submission, so batches can be told apart
+    #[derive(Debug, Default)]
+    struct RecordingSubmitter {
+        submitted: Arc<std::sync::Mutex<Vec<Value>>>,
+    }
+
+    #[async_trait]
+    impl EventSubmitter for RecordingSubmitter {
+        async fn submit(
+            &self,
+            payload: Value,
+            _routing: Option<&str>,
+        ) -> Result<(), MessagingError> {
+            self.submitted.lock().unwrap().push(payload);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_until_full_or_delayed() {
+        let submitter = RecordingSubmitter::default();
+        let submitted = submitter.submitted.clone();
+        let connections = Connections::new(
+            HashMap::new(),
+            HashMap::from([(
+                "alerts".to_string(),
+                Box::new(submitter) as Box<dyn EventSubmitter>,
+            )]),
+        );
+        let batcher = Arc::new(EmitBatcher::new(
+            "alerts",
+            EmitBatching {
+                max_batch: 2,
+                max_delay: Duration::from_millis(20),
+            },
+            Arc::new(connections),
+        ));
+        let emits = (1..=3).map(|n| {
+            batcher
+                .clone()
+                .emit(EmitAction::new("alerts".to_string(), json!({"n": n})))
+        });
+        for emitted in futures::future::join_all(emits).await {
+            emitted.unwrap();
+        }
+        assert_eq!(
+            *submitted.lock().unwrap(),
+            vec![json!([{"n": 1}, {"n": 2}]), json!([{"n": 3}])]
+        );
+    }
+}
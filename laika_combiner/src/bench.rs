@@ -0,0 +1,193 @@
+use crate::action::EventAction;
+use crate::config::builder::EventProcessorYamlSpec;
+use crate::config::EventProcessorConfig;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::storage::StorageKV;
+use crate::timing::TimingExpiry;
+use crate::Runtime;
+use serde_json::{json, Value};
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Connection the synthetic events are received from
+pub const WORKLOAD_SOURCE: &str = "orders";
+
+/// Config the synthetic events are processed by. Orders are correlated by their ID, and a rule
+/// totals an order's items in a predicate once it's paid for.
+pub const WORKLOAD_CONFIG: &str = r#"
+connections:
+  orders:
+    type: file
+    path: "./orders.jsonl"
+  fulfilment:
+    type: file
+    path: "./fulfilment.jsonl"
+
+events:
+  item:
+    from: orders
+    matchKey:
+      type: item
+  paid:
+    from: orders
+    matchKey:
+      type: paid
+
+correlation:
+  item:
+    key: "$.orderId"
+  paid:
+    key: "$.orderId"
+
+triggers:
+  orderPaid:
+    requires:
+      at_least:
+        - item
+        - paid
+    filterAndExtract: >
+      (trigger, ctx) => {
+        if (trigger.event.type !== 'paid') return null;
+        const total = ctx.events.item.reduce((sum, item) => sum + item.amount, 0);
+        return { orderId: trigger.event.orderId, total };
+      }
+    action:
+      target: fulfilment
+      payload:
+        orderId: "${{ orderId }}"
+        total: "${{ total }}"
+"#;
+
+/// Synthetic stream of correlated events, for measuring how quickly they're matched, stored and
+/// evaluated.
+///
+/// Each correlation is an order of items followed by its payment, and correlations are
+/// interleaved as they would be from a busy source.
+#[derive(Clone, Debug)]
+pub struct Workload {
+    correlations: usize,
+    events_per_correlation: usize,
+}
+
+impl Workload {
+    pub fn new(correlations: usize, events_per_correlation: usize) -> Self {
+        Self {
+            correlations,
+            events_per_correlation: events_per_correlation.max(2),
+        }
+    }
+
+    /// Number of events in the stream
+    pub fn len(&self) -> usize {
+        self.correlations * self.events_per_correlation
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = Value> + '_ {
+        (0..self.events_per_correlation).flat_map(move |position| {
+            (0..self.correlations).map(move |order| {
+                let order_id = format!("order-{}", order);
+                if position + 1 == self.events_per_correlation {
+                    json!({"type": "paid", "orderId": order_id})
+                } else {
+                    json!({"type": "item", "orderId": order_id, "amount": position + 1})
+                }
+            })
+        })
+    }
+
+    /// Runtime processing the stream with `storage`, and wakeups kept by `waker`
+    pub fn runtime(&self, storage: StorageKV, waker: TimingExpiry) -> LaikaResult<Runtime> {
+        let spec: EventProcessorYamlSpec = serde_yaml::from_str(WORKLOAD_CONFIG)
+            .map_err(|e| LaikaError::Generic(format!("Could not read config: {}", e)))?;
+        let processor = EventProcessorConfig::try_from(&spec)?.build()?;
+        Runtime::new(vec![processor], storage, waker)
+    }
+
+    /// Push the stream through `runtime`, timing how long each event takes to be processed and
+    /// have its actions polled
+    pub fn run(&self, runtime: &mut Runtime) -> LaikaResult<BenchReport> {
+        let mut latencies = Vec::with_capacity(self.len());
+        let mut actions = 0;
+        let started = Instant::now();
+        for event in self.events() {
+            let pushed = Instant::now();
+            runtime.push_event(WORKLOAD_SOURCE, event)?;
+            actions += runtime
+                .poll_actions()?
+                .iter()
+                .filter(|action| matches!(action, EventAction::Emit(_)))
+                .count();
+            latencies.push(pushed.elapsed());
+        }
+        let elapsed = started.elapsed();
+        latencies.sort();
+        Ok(BenchReport {
+            events: latencies.len(),
+            actions,
+            elapsed,
+            latencies,
+        })
+    }
+}
+
+/// Throughput and latency of a run through a workload
+#[derive(Clone, Debug)]
+pub struct BenchReport {
+    pub events: usize,
+    pub actions: usize,
+    pub elapsed: Duration,
+    /// Time taken for each event, shortest first
+    latencies: Vec<Duration>,
+}
+
+impl BenchReport {
+    pub fn events_per_sec(&self) -> f64 {
+        self.events as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// Latency that `percentile` percent of events were processed within
+    pub fn latency(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} events in {:.2?} ({:.0} events/sec), p50 {:.2?}, p99 {:.2?}, {} actions",
+            self.events,
+            self.elapsed,
+            self.events_per_sec(),
+            self.latency(50.0),
+            self.latency(99.0),
+            self.actions
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_workload_fires_once_per_correlation() {
+        let workload = Workload::new(20, 5);
+        let state_dir = TempDir::new().unwrap();
+        let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+        let mut runtime = workload.runtime(StorageKV::in_memory(), waker).unwrap();
+        let report = workload.run(&mut runtime).unwrap();
+        assert_eq!(report.events, 100);
+        assert_eq!(report.actions, 20);
+        assert!(report.latency(50.0) <= report.latency(99.0));
+    }
+}
@@ -11,13 +11,27 @@ pub type CorrelationId = String;
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct EventId(pub u64);
 
+/// What an expiry wakes up to do
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ExpiryKind {
+    /// Re-evaluate a rule against the stored context of a correlation
+    #[default]
+    Recheck,
+    /// Close a window over uncorrelated events, where the correlation ID is the window's group
+    WindowClose,
+    /// Run a rule on its cron schedule
+    Scheduled,
+    /// Take a rule's `onTimeout` action, its timing having passed without it firing
+    Timeout,
+}
+
 // serde is internal here
 #[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
-// TODO: Add a Rule to this
 pub struct EventExpiry {
     pub expires_at: OffsetDateTime,
     pub correlation_id: CorrelationId,
     pub event_rule: String,
+    pub kind: ExpiryKind,
 }
 
 impl EventExpiry {
@@ -30,8 +44,49 @@ impl EventExpiry {
             expires_at: recheck_at,
             correlation_id,
             event_rule,
+            kind: ExpiryKind::Recheck,
+        }
+    }
+
+    pub fn window_close(closes_at: OffsetDateTime, group: String, event_rule: String) -> Self {
+        Self {
+            expires_at: closes_at,
+            correlation_id: group,
+            event_rule,
+            kind: ExpiryKind::WindowClose,
         }
     }
+
+    pub fn timeout(
+        times_out_at: OffsetDateTime,
+        correlation_id: CorrelationId,
+        event_rule: String,
+    ) -> Self {
+        Self {
+            expires_at: times_out_at,
+            correlation_id,
+            event_rule,
+            kind: ExpiryKind::Timeout,
+        }
+    }
+
+    pub fn scheduled(runs_at: OffsetDateTime, event_rule: String) -> Self {
+        Self {
+            expires_at: runs_at,
+            // Schedules aren't tied to a single correlation
+            correlation_id: "*".to_string(),
+            event_rule,
+            kind: ExpiryKind::Scheduled,
+        }
+    }
+
+    /// Whether this wakes the same rule for the same correlation and purpose as `other`, so
+    /// only one of them needs to be kept
+    pub fn replaces(&self, other: &EventExpiry) -> bool {
+        self.event_rule == other.event_rule
+            && self.correlation_id == other.correlation_id
+            && self.kind == other.kind
+    }
 }
 
 pub struct Broker {
@@ -0,0 +1,245 @@
+use crate::config::builder::invalid_at;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::utils::parse_time::TimeSpan;
+use serde::Deserialize;
+use std::collections::HashSet;
+use time::macros::{format_description, time};
+use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset, Weekday};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CalendarConfig {
+    /// Days business time passes on, such as `[mon, tue, wed, thu, fri]`, which is the default
+    #[serde(default)]
+    work_days: Option<Vec<String>>,
+    /// Hours business hours pass in on a work day, such as `09:00-17:00`, which is the default
+    #[serde(default)]
+    work_hours: Option<String>,
+    /// Dates, such as `2024-12-25`, that aren't work days
+    #[serde(default)]
+    holidays: Vec<String>,
+    /// Fixed offset from UTC the calendar is kept in, such as `+01:00`, defaulting to UTC.
+    /// Daylight saving changes aren't followed.
+    #[serde(default)]
+    timezone: Option<String>,
+}
+
+fn parse_weekday(day: &str) -> LaikaResult<Weekday> {
+    match day.to_lowercase().as_str() {
+        "mon" | "monday" => Ok(Weekday::Monday),
+        "tue" | "tuesday" => Ok(Weekday::Tuesday),
+        "wed" | "wednesday" => Ok(Weekday::Wednesday),
+        "thu" | "thursday" => Ok(Weekday::Thursday),
+        "fri" | "friday" => Ok(Weekday::Friday),
+        "sat" | "saturday" => Ok(Weekday::Saturday),
+        "sun" | "sunday" => Ok(Weekday::Sunday),
+        _ => Err(LaikaError::Generic(format!("unknown day: {}", day))),
+    }
+}
+
+fn parse_offset(timezone: &str) -> LaikaResult<UtcOffset> {
+    match timezone {
+        "UTC" | "Z" => Ok(UtcOffset::UTC),
+        _ => UtcOffset::parse(
+            timezone,
+            format_description!("[offset_hour sign:mandatory]:[offset_minute]"),
+        )
+        .map_err(|e| LaikaError::Generic(e.to_string())),
+    }
+}
+
+impl CalendarConfig {
+    pub(crate) fn parse(&self) -> LaikaResult<BusinessCalendar> {
+        let mut calendar = BusinessCalendar::default();
+        if let Some(work_days) = &self.work_days {
+            calendar.work_days = work_days
+                .iter()
+                .map(|day| parse_weekday(day))
+                .collect::<LaikaResult<_>>()
+                .map_err(invalid_at("workDays"))?;
+            if calendar.work_days.is_empty() {
+                return Err(LaikaError::config(
+                    "workDays",
+                    "workDays must list at least one day",
+                ));
+            }
+        }
+        if let Some(work_hours) = &self.work_hours {
+            let hour = format_description!("[hour]:[minute]");
+            let (opens, closes) = work_hours
+                .split_once('-')
+                .and_then(|(opens, closes)| {
+                    Some((
+                        Time::parse(opens.trim(), hour).ok()?,
+                        Time::parse(closes.trim(), hour).ok()?,
+                    ))
+                })
+                .ok_or_else(|| {
+                    LaikaError::config("workHours", "workHours must be like 09:00-17:00")
+                })?;
+            if opens >= closes {
+                return Err(LaikaError::config(
+                    "workHours",
+                    "workHours must close after they open",
+                ));
+            }
+            calendar.opens = opens;
+            calendar.closes = closes;
+        }
+        calendar.holidays = self
+            .holidays
+            .iter()
+            .map(|holiday| {
+                Date::parse(holiday, format_description!("[year]-[month]-[day]"))
+                    .map_err(|e| LaikaError::Generic(format!("{}: {}", holiday, e)))
+            })
+            .collect::<LaikaResult<_>>()
+            .map_err(invalid_at("holidays"))?;
+        if let Some(timezone) = &self.timezone {
+            calendar.offset = parse_offset(timezone).map_err(invalid_at("timezone"))?;
+        }
+        Ok(calendar)
+    }
+}
+
+/// Work days and hours that business time (`bd` and `bh`) passes in, so that something due in
+/// two business days from a Friday is due on Tuesday rather than Sunday.
+#[derive(Clone, Debug)]
+pub struct BusinessCalendar {
+    work_days: HashSet<Weekday>,
+    opens: Time,
+    closes: Time,
+    holidays: HashSet<Date>,
+    offset: UtcOffset,
+}
+
+impl Default for BusinessCalendar {
+    /// Monday to Friday, 09:00 to 17:00 UTC
+    fn default() -> Self {
+        Self {
+            work_days: HashSet::from([
+                Weekday::Monday,
+                Weekday::Tuesday,
+                Weekday::Wednesday,
+                Weekday::Thursday,
+                Weekday::Friday,
+            ]),
+            opens: time!(09:00),
+            closes: time!(17:00),
+            holidays: HashSet::new(),
+            offset: UtcOffset::UTC,
+        }
+    }
+}
+
+impl BusinessCalendar {
+    fn is_work_day(&self, date: Date) -> bool {
+        self.work_days.contains(&date.weekday()) && !self.holidays.contains(&date)
+    }
+
+    fn next_work_day(&self, after: Date) -> Date {
+        let mut date = after;
+        loop {
+            date = date.next_day().expect("date within range");
+            if self.is_work_day(date) {
+                return date;
+            }
+        }
+    }
+
+    fn at(&self, date: Date, time: Time) -> OffsetDateTime {
+        PrimitiveDateTime::new(date, time).assume_offset(self.offset)
+    }
+
+    /// The first business hour at or after `at`
+    fn next_open(&self, at: OffsetDateTime) -> OffsetDateTime {
+        if self.is_work_day(at.date()) && at.time() < self.closes {
+            return at.max(self.at(at.date(), self.opens));
+        }
+        self.at(self.next_work_day(at.date()), self.opens)
+    }
+
+    /// `start` moved on by `span`. Business days keep the time of day, counting from the next
+    /// work day when `start` isn't on one, while business hours only pass within work hours.
+    pub(crate) fn add(&self, start: OffsetDateTime, span: TimeSpan) -> OffsetDateTime {
+        let local = start.to_offset(self.offset);
+        let end = match span {
+            TimeSpan::Exact(duration) => return start + duration,
+            TimeSpan::BusinessDays(days) => {
+                let mut at = match self.is_work_day(local.date()) {
+                    true => local,
+                    false => self.next_open(local),
+                };
+                for _ in 0..days {
+                    at = self.at(self.next_work_day(at.date()), at.time());
+                }
+                at
+            }
+            TimeSpan::BusinessHours(hours) => {
+                let mut remaining = Duration::hours(hours);
+                let mut at = self.next_open(local);
+                loop {
+                    let left_today = self.at(at.date(), self.closes) - at;
+                    if remaining <= left_today {
+                        break at + remaining;
+                    }
+                    remaining -= left_today;
+                    at = self.at(self.next_work_day(at.date()), self.opens);
+                }
+            }
+        };
+        end.to_offset(start.offset())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_business_days_skip_weekends_and_holidays() {
+        let calendar = BusinessCalendar::default();
+        // A Friday afternoon
+        let friday = datetime!(2024-03-01 15:00 UTC);
+        assert_eq!(
+            calendar.add(friday, TimeSpan::BusinessDays(2)),
+            datetime!(2024-03-05 15:00 UTC)
+        );
+        assert_eq!(
+            calendar.add(datetime!(2024-03-02 11:00 UTC), TimeSpan::BusinessDays(1)),
+            datetime!(2024-03-05 09:00 UTC)
+        );
+
+        let calendar = CalendarConfig {
+            holidays: vec!["2024-03-04".to_string()],
+            ..CalendarConfig::default()
+        }
+        .parse()
+        .unwrap();
+        assert_eq!(
+            calendar.add(friday, TimeSpan::BusinessDays(2)),
+            datetime!(2024-03-06 15:00 UTC)
+        );
+    }
+
+    #[test]
+    fn test_business_hours_only_pass_in_work_hours() {
+        let calendar = CalendarConfig {
+            timezone: Some("+02:00".to_string()),
+            ..CalendarConfig::default()
+        }
+        .parse()
+        .unwrap();
+        // 15:00 on a Friday in the calendar's timezone
+        let friday = datetime!(2024-03-01 13:00 UTC);
+        assert_eq!(
+            calendar.add(friday, TimeSpan::BusinessHours(4)),
+            datetime!(2024-03-04 09:00 UTC)
+        );
+        assert_eq!(
+            calendar.add(friday, TimeSpan::Exact(Duration::hours(4))),
+            datetime!(2024-03-01 17:00 UTC)
+        );
+    }
+}
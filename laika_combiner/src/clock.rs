@@ -0,0 +1,71 @@
+use std::sync::{Arc, Mutex};
+use time::{Duration, OffsetDateTime};
+
+/// Source of the current time, for when events are received and when wakeups are due
+pub trait Clock: Send + Sync {
+    fn now(&self) -> OffsetDateTime;
+}
+
+/// The system's clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// Clock that only moves when it's told to, so timing can be tested deterministically, and
+/// without waiting for it.
+///
+/// Clones share the same time, so a runtime can be given one while its host keeps another to
+/// move it on.
+#[derive(Clone, Debug)]
+pub struct TestClock {
+    now: Arc<Mutex<OffsetDateTime>>,
+}
+
+impl TestClock {
+    pub fn new(start: OffsetDateTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    /// Move the clock on by `by`
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += by;
+    }
+
+    /// Move the clock on to `to`, leaving it where it is if it's already past it
+    pub fn advance_to(&self, to: OffsetDateTime) {
+        let mut now = self.now.lock().unwrap();
+        *now = (*now).max(to);
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> OffsetDateTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_clock_only_moves_forward() {
+        let clock = TestClock::new(datetime!(2024-03-01 12:00:00 UTC));
+        let shared = clock.clone();
+        clock.advance(Duration::minutes(5));
+        assert_eq!(shared.now(), datetime!(2024-03-01 12:05:00 UTC));
+        shared.advance_to(datetime!(2024-03-01 12:01:00 UTC));
+        assert_eq!(clock.now(), datetime!(2024-03-01 12:05:00 UTC));
+        shared.advance_to(datetime!(2024-03-01 13:00:00 UTC));
+        assert_eq!(clock.now(), datetime!(2024-03-01 13:00:00 UTC));
+    }
+}
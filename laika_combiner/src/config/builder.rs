@@ -1,30 +1,274 @@
+use crate::admin::AdminConfig;
+use crate::aggregation::{AggregateConfig, AggregateFunction, Threshold};
+use crate::calendar::{BusinessCalendar, CalendarConfig};
+use crate::clock::Clock;
+use crate::config::include::{self, ConfigSources};
+use crate::config::state_machine::StateMachineConfig;
 use crate::config::{
     EventCorrelation, EventProcessorConfig, EventProcessorConfigBuilder, EventTrigger,
 };
-use crate::connections::ConnectionConfig;
+use crate::connections::auth::{HttpAuth, HttpAuthConfig};
+use crate::connections::compression::Compression;
+use crate::connections::tls::{TlsConfig, TlsSetting};
+use crate::connections::ConnectionSpec;
+use crate::coordination::CoordinationConfig;
+use crate::dead_letter::DeadLetterConfig;
+use crate::dispatcher::DispatchConfig;
 use crate::errors::{LaikaError, LaikaResult};
-use crate::matcher::builder::{EventMatchBuilder, MatchPatternBuilder};
-use crate::matcher::EventType;
-use crate::template::error::TemplateError;
+use crate::event::context::ContextShape;
+use crate::format::{EmitFormat, EmitFormatConfig};
+use crate::late::LatePolicy;
+use crate::logging::LoggingConfig;
+use crate::lookup::LookupConfigYaml;
+use crate::matcher::builder::{EventMatchBuilder, EventSourcesBuilder, MatchPatternBuilder};
+use crate::matcher::{AmbiguousMatch, EventType};
+use crate::output_schema::{FieldSchemaYaml, OutputSchema};
+use crate::partitioning::PartitionConfig;
+use crate::predicate_engine::PredicateConfig;
+use crate::provenance::{content_hash, ProvenanceConfig};
+use crate::redact::RedactionYaml;
+use crate::rollout::Rollout;
+use crate::rule_tests::RuleTestConfig;
+use crate::rules::{Absence, Escalation, Requirement, TriggerScope};
+use crate::schedule::{CronSchedule, ScheduleConfig, ScheduleScope};
+use crate::size_limits::SizeLimitsConfig;
+use crate::storage::StorageConfig;
+use crate::telemetry::TelemetryConfig;
 use crate::template::Template;
-use crate::utils::parse_time::parse_time_str;
+use crate::tenancy::TenancyConfig;
+use crate::utils::extract_json::parse_path;
+use crate::utils::parse_time::{parse_time_span, parse_time_str, TimeSpan};
+use crate::watermark::WatermarkConfig;
+use crate::windowing::{WindowConfig, WindowKind};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
 use time::{Duration, OffsetDateTime};
 
 #[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EventProcessorYamlSpec {
     pub correlation: CorrelationConfig,
-    pub connections: HashMap<String, ConnectionConfig>,
+    pub connections: HashMap<String, ConnectionSpec>,
     pub events: EventMatchBuilder,
+    /// How messages matching several event types are handled
+    #[serde(default, rename = "ambiguousMatch")]
+    pub ambiguous_match: AmbiguousMatch,
     pub triggers: HashMap<String, TriggerConfig>,
+    /// State machines followed by each correlation, compiled into triggers
+    #[serde(default, rename = "stateMachines")]
+    pub state_machines: HashMap<String, StateMachineConfig>,
+    /// Where to send messages that repeatedly fail to be handled
+    #[serde(default, rename = "deadLetter")]
+    pub dead_letter: Option<DeadLetterConfig>,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub predicates: PredicateConfig,
+    /// JS files loaded before any predicate, for helpers shared between them
+    #[serde(default)]
+    pub lib: Vec<String>,
+    /// How events are split between tenants, if they are
+    #[serde(default)]
+    pub tenancy: Option<TenancyConfig>,
+    /// How actions are taken once they've been produced
+    #[serde(default)]
+    pub dispatch: DispatchConfig,
+    /// How wakeups wait for event time, for event types with `eventTime`
+    #[serde(default)]
+    pub watermark: Option<WatermarkConfig>,
+    /// Work days and hours that business time, such as `2bd` or `4bh`, passes in
+    #[serde(default)]
+    pub calendar: Option<CalendarConfig>,
+    /// Telemetry settings, if configured
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// HTTP API for inspecting and controlling the processor while it runs
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// Lock deciding which of a pair of instances is active, when running with a standby
+    #[serde(default)]
+    pub coordination: Option<CoordinationConfig>,
+    /// How correlations are split between instances reading the same sources
+    #[serde(default)]
+    pub partitioning: Option<PartitionConfig>,
+    /// Caps on the size of received messages and emitted payloads
+    #[serde(default, rename = "sizeLimits")]
+    pub size_limits: Option<SizeLimitsConfig>,
+    /// Metadata stamped onto emitted payloads, tying them to the rule that produced them
+    #[serde(default)]
+    pub provenance: Option<ProvenanceConfig>,
+    /// Files the config was read from, to tell when it's changed
+    #[serde(skip)]
+    pub sources: ConfigSources,
+    /// Hash of each trigger and state machine as written, by its path in the config
+    #[serde(skip)]
+    pub(crate) revisions: HashMap<String, String>,
+}
+
+impl EventProcessorYamlSpec {
+    /// Read a config file, along with the files it includes
+    pub fn from_path<P: AsRef<Path>>(path: P) -> LaikaResult<Self> {
+        Self::from_value(include::load(path.as_ref())?)
+    }
+
+    /// Parse a config, along with the files it includes relative to the current directory
+    pub fn from_yaml(yaml: &str) -> LaikaResult<Self> {
+        Self::from_value(include::resolve(yaml, Path::new("."))?)
+    }
+
+    fn from_value((config, sources): (serde_yaml::Value, ConfigSources)) -> LaikaResult<Self> {
+        let mut revisions = HashMap::new();
+        for section in ["triggers", "stateMachines"] {
+            if let Some(serde_yaml::Value::Mapping(entries)) = config.get(section) {
+                for (name, entry) in entries {
+                    if let Some(name) = name.as_str() {
+                        revisions.insert(format!("{}.{}", section, name), content_hash(entry));
+                    }
+                }
+            }
+        }
+        let spec: Self = serde_path_to_error::deserialize(config).map_err(|e| {
+            let path = match e.path().to_string() {
+                root if root == "." => String::new(),
+                path => path,
+            };
+            LaikaError::config(path, e.into_inner().to_string())
+        })?;
+        Ok(Self {
+            sources,
+            revisions,
+            ..spec
+        })
+    }
+
+    /// Check that the event types and connections the config refers to are defined, reporting
+    /// every one that isn't rather than only the first
+    fn validate(&self) -> LaikaResult<()> {
+        let event_types: HashSet<&str> = self.events.event_types().collect();
+        let connections: HashSet<&str> = self.connections.keys().map(String::as_str).collect();
+        let mut errors = Vec::new();
+
+        let mut correlated: Vec<&String> = self.correlation.events.keys().collect();
+        correlated.sort();
+        for event_type in correlated {
+            errors.extend(undefined(
+                format!("correlation.{}", event_type),
+                "events",
+                &event_types,
+                [event_type.as_str()],
+            ));
+        }
+        let mut triggers: Vec<(&String, &TriggerConfig)> = self.triggers.iter().collect();
+        triggers.sort_by_key(|(name, _)| *name);
+        for (name, trigger) in triggers {
+            if let Some(requires) = &trigger.requires {
+                errors.extend(undefined(
+                    format!("triggers.{}.requires", name),
+                    "events",
+                    &event_types,
+                    requires.events().iter().map(String::as_str),
+                ));
+            }
+            if let Some(absent) = &trigger.absent {
+                errors.extend(undefined(
+                    format!("triggers.{}.absent", name),
+                    "events",
+                    &event_types,
+                    absent.iter().map(String::as_str),
+                ));
+            }
+            if let Some(on) = &trigger.on {
+                errors.extend(undefined(
+                    format!("triggers.{}.on", name),
+                    "events",
+                    &event_types,
+                    on.iter()
+                        .map(String::as_str)
+                        .filter(|kind| *kind != TriggerScope::TIMER),
+                ));
+            }
+            if let Some(aggregate) = &trigger.aggregate {
+                errors.extend(undefined(
+                    format!("triggers.{}.aggregate.event", name),
+                    "events",
+                    &event_types,
+                    [aggregate.event.as_str()],
+                ));
+            }
+            if let ActionConfigYaml::Emit { target, .. } = &trigger.action {
+                errors.extend(undefined(
+                    format!("triggers.{}.action.target", name),
+                    "connections",
+                    &connections,
+                    [target.as_str()],
+                ));
+            }
+            if let Some(ActionConfigYaml::Emit { target, .. }) = &trigger.on_timeout {
+                errors.extend(undefined(
+                    format!("triggers.{}.onTimeout.target", name),
+                    "connections",
+                    &connections,
+                    [target.as_str()],
+                ));
+            }
+            for (index, step) in trigger.escalate.iter().enumerate() {
+                if let ActionConfigYaml::Emit { target, .. } = &step.action {
+                    errors.extend(undefined(
+                        format!("triggers.{}.escalate.{}.action.target", name, index),
+                        "connections",
+                        &connections,
+                        [target.as_str()],
+                    ));
+                }
+            }
+            if let Some(target) = trigger.late.as_ref().and_then(LatePolicy::target) {
+                errors.extend(undefined(
+                    format!("triggers.{}.late", name),
+                    "connections",
+                    &connections,
+                    [target],
+                ));
+            }
+        }
+        if let Some(dead_letter) = &self.dead_letter {
+            errors.extend(undefined(
+                "deadLetter.target".to_string(),
+                "connections",
+                &connections,
+                [dead_letter.target.as_str()],
+            ));
+        }
+        LaikaError::check_all(errors)
+    }
+}
+
+/// Error at `path` naming any of `names` that aren't `defined` in `section` of the config
+fn undefined<'a>(
+    path: String,
+    section: &str,
+    defined: &HashSet<&str>,
+    names: impl IntoIterator<Item = &'a str>,
+) -> Option<LaikaError> {
+    let missing: Vec<&str> = names
+        .into_iter()
+        .filter(|name| !defined.contains(name))
+        .collect();
+    (!missing.is_empty()).then(|| {
+        LaikaError::config(
+            path,
+            format!("Not defined in {}: {}", section, missing.join(", ")),
+        )
+    })
 }
 
 #[derive(Clone, Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct MatchOptionsBuilder {
     /// Connector to source this Event from
-    pub from: String,
+    pub from: EventSourcesBuilder,
 
     #[serde(default)]
     pub match_all: Option<serde_yaml::Value>,
@@ -32,12 +276,58 @@ pub struct MatchOptionsBuilder {
     // For matchKey: { type: "..." }
     #[serde(default)]
     pub match_key: Option<HashMap<String, MatchPatternBuilder>>,
+
+    /// Sets of match keys, where events meeting every key of any set match
+    #[serde(default)]
+    pub match_any: Option<Vec<HashMap<String, MatchPatternBuilder>>>,
+
+    /// Fields merged into events of this type, under `_enrich`
+    #[serde(default)]
+    pub enrich: serde_json::Map<String, serde_json::Value>,
+
+    /// Values looked up for events of this type, attached under `_enrich` by name
+    #[serde(default)]
+    pub lookup: HashMap<String, LookupConfigYaml>,
+
+    /// Fields masked or hashed before events of this type are stored or given to rules
+    #[serde(default)]
+    pub redact: Vec<RedactionYaml>,
+
+    /// JSONPath to when events of this type happened, used for timing instead of when they're
+    /// received
+    #[serde(default)]
+    pub event_time: Option<String>,
+
+    /// Preference for this event type over others a message also matches, highest first, under
+    /// the `priority` ambiguous match policy
+    #[serde(default)]
+    pub priority: i32,
+}
+
+/// Report an error from parsing part of the config as a config error at `path`
+pub(crate) fn invalid_at(path: &str) -> impl Fn(LaikaError) -> LaikaError + '_ {
+    move |e| match e {
+        LaikaError::ConfigError { .. } => e.within(path),
+        LaikaError::Generic(message) => LaikaError::config(path, message),
+        other => LaikaError::config(path, other.to_string()),
+    }
 }
 
 impl TryFrom<&EventProcessorYamlSpec> for EventProcessorConfig {
     type Error = LaikaError;
 
     fn try_from(value: &EventProcessorYamlSpec) -> LaikaResult<Self> {
+        value.validate()?;
+        for (event_type, correlation_builder) in &value.correlation.events {
+            parse_path(correlation_builder.key.as_str()).map_err(invalid_at(
+                format!("correlation.{}.key", event_type).as_str(),
+            ))?;
+            for link in &correlation_builder.links {
+                parse_path(link.as_str()).map_err(invalid_at(
+                    format!("correlation.{}.links", event_type).as_str(),
+                ))?;
+            }
+        }
         let event_correlation = EventCorrelation::new(
             value
                 .correlation
@@ -46,15 +336,74 @@ impl TryFrom<&EventProcessorYamlSpec> for EventProcessorConfig {
                 .into_iter()
                 .map(|(event_type, correlation_builder)| (event_type, correlation_builder.key))
                 .collect::<HashMap<EventType, String>>(),
-        );
-        let event_matcher = value.events.clone().build()?;
-        let event_triggers: HashMap<EventType, EventTrigger> = value
-            .triggers
+        )
+        .with_links(
+            value
+                .correlation
+                .events
+                .iter()
+                .filter(|(_, correlation_builder)| !correlation_builder.links.is_empty())
+                .map(|(event_type, correlation_builder)| {
+                    (event_type.clone(), correlation_builder.links.clone())
+                })
+                .collect::<HashMap<EventType, Vec<String>>>(),
+        )
+        .with_tenancy(value.tenancy.clone());
+        let event_matcher = value
+            .events
             .clone()
+            .build()?
+            .with_ambiguous_match(value.ambiguous_match);
+        let correlated = value
+            .correlation
+            .events
+            .keys()
+            .cloned()
+            .collect::<HashSet<String>>();
+        let mut trigger_configs = value.triggers.clone();
+        let mut rule_hashes: HashMap<String, String> = value
+            .triggers
+            .keys()
+            .filter_map(|name| {
+                let hash = value.revisions.get(&format!("triggers.{}", name))?;
+                Some((name.clone(), hash.clone()))
+            })
+            .collect();
+        for (name, machine) in &value.state_machines {
+            let machine_hash = value.revisions.get(&format!("stateMachines.{}", name));
+            for (trigger_name, trigger_config) in machine
+                .compile(name, &correlated)
+                .map_err(|e| e.within(format!("stateMachines.{}", name).as_str()))?
+            {
+                if trigger_configs.contains_key(&trigger_name) {
+                    return Err(LaikaError::config(
+                        format!("stateMachines.{}", name),
+                        format!("{} is already the name of a trigger", trigger_name),
+                    ));
+                }
+                if let Some(hash) = machine_hash {
+                    rule_hashes.insert(trigger_name.clone(), hash.clone());
+                }
+                trigger_configs.insert(trigger_name, trigger_config);
+            }
+        }
+        let provenance = value
+            .provenance
+            .as_ref()
+            .map(|provenance| provenance.build(rule_hashes))
+            .transpose()
+            .map_err(invalid_at("provenance"))?;
+        let event_triggers: HashMap<EventType, EventTrigger> = trigger_configs
             .into_iter()
             .map(|(event_type, trigger_config)| {
-                trigger_config
-                    .try_into()
+                if trigger_config.tenants.is_some() && value.tenancy.is_none() {
+                    return Err(LaikaError::config(
+                        format!("triggers.{}.tenants", event_type),
+                        "tenants needs tenancy to be configured",
+                    ));
+                }
+                EventTrigger::try_from(trigger_config)
+                    .map_err(|e| e.within(format!("triggers.{}", event_type).as_str()))
                     .map(|trigger_config| (event_type, trigger_config))
             })
             .collect::<LaikaResult<HashMap<EventType, EventTrigger>>>()?;
@@ -64,16 +413,32 @@ impl TryFrom<&EventProcessorYamlSpec> for EventProcessorConfig {
             .with_correlation(event_correlation)
             .with_event_matcher(event_matcher)
             .with_triggers(event_triggers)
+            .with_dead_letter(value.dead_letter.clone())
+            .with_storage(value.storage.clone())
+            .with_predicates(value.predicates.clone())
+            .with_lib(value.lib.clone())
+            .with_dispatch(value.dispatch.clone())
+            .with_watermark(value.watermark.clone())
+            .with_calendar(value.calendar.clone())
+            .with_telemetry(value.telemetry.clone())
+            .with_logging(value.logging.clone())
+            .with_admin(value.admin.clone())
+            .with_coordination(value.coordination.clone())
+            .with_partitioning(value.partitioning.clone())
+            .with_size_limits(value.size_limits.clone())
+            .with_provenance(provenance)
             .build()?)
     }
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ContentConfig {
     output: String,
 }
 
 #[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TimingConfigBuilder {
     from: Option<String>,
     check_every: Option<String>,
@@ -86,9 +451,9 @@ impl TimingConfigBuilder {
             from: self
                 .from
                 .as_ref()
-                .map(|s| parse_time_str(s.as_str()))
+                .map(|s| parse_time_span(s.as_str()))
                 .transpose()?
-                .unwrap_or(Duration::seconds(0)),
+                .unwrap_or(TimeSpan::Exact(Duration::seconds(0))),
             check_every: self
                 .check_every
                 .as_ref()
@@ -97,7 +462,7 @@ impl TimingConfigBuilder {
             until: self
                 .until
                 .as_ref()
-                .map(|s| parse_time_str(s.as_str()))
+                .map(|s| parse_time_span(s.as_str()))
                 .transpose()?,
         })
     }
@@ -105,16 +470,32 @@ impl TimingConfigBuilder {
 
 #[derive(Clone, Debug)]
 pub struct TimingConfig {
-    from: Duration,
+    from: TimeSpan,
     check_every: Option<Duration>,
-    until: Option<Duration>,
+    until: Option<TimeSpan>,
 }
 
 impl TimingConfig {
-    pub fn next_check(&self, when_requirements_were_met: OffsetDateTime) -> Option<OffsetDateTime> {
-        let now = OffsetDateTime::now_utc();
-        let start_time = when_requirements_were_met + self.from;
-        let end_time = self.until.map(|d| when_requirements_were_met + d);
+    /// When a rule whose requirements were met at `when_requirements_were_met` stops being
+    /// rechecked, if it ever does
+    pub(crate) fn timeout_at(
+        &self,
+        when_requirements_were_met: OffsetDateTime,
+        calendar: &BusinessCalendar,
+    ) -> Option<OffsetDateTime> {
+        self.until
+            .map(|until| calendar.add(when_requirements_were_met, until))
+    }
+
+    pub fn next_check(
+        &self,
+        when_requirements_were_met: OffsetDateTime,
+        clock: &dyn Clock,
+        calendar: &BusinessCalendar,
+    ) -> Option<OffsetDateTime> {
+        let now = clock.now();
+        let start_time = calendar.add(when_requirements_were_met, self.from);
+        let end_time = self.timeout_at(when_requirements_were_met, calendar);
 
         // Check if we're past the end time
         if let Some(end) = end_time {
@@ -149,45 +530,442 @@ impl TimingConfig {
 }
 
 #[derive(Clone, Deserialize)]
-pub struct CorrelationConfig {
+#[serde(deny_unknown_fields)]
+pub struct SumConfigBuilder {
+    field: String, // JSONPath expression
     #[serde(flatten)]
+    threshold: Threshold,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AggregateConfigBuilder {
+    event: String,
+    within: String,
+    count: Option<Threshold>,
+    sum: Option<SumConfigBuilder>,
+}
+
+impl AggregateConfigBuilder {
+    pub(crate) fn parse(&self) -> LaikaResult<AggregateConfig> {
+        let (function, threshold) = match (&self.count, &self.sum) {
+            (Some(threshold), None) => (AggregateFunction::Count, threshold.clone()),
+            (None, Some(sum)) => (
+                AggregateFunction::Sum(sum.field.clone()),
+                sum.threshold.clone(),
+            ),
+            _ => {
+                return Err(LaikaError::config(
+                    "",
+                    "Aggregate must specify exactly one of count or sum",
+                ))
+            }
+        };
+        if threshold.is_empty() {
+            return Err(LaikaError::config(
+                "",
+                "Aggregate threshold must specify at least one of gt, gte, lt or lte",
+            ));
+        }
+        Ok(AggregateConfig {
+            event_type: self.event.clone(),
+            within: parse_time_str(self.within.as_str()).map_err(invalid_at("within"))?,
+            function,
+            threshold,
+        })
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowKindConfig {
+    Tumbling,
+    Sliding,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct WindowConfigBuilder {
+    #[serde(rename = "type")]
+    kind: WindowKindConfig,
+    size: String,
+    slide: Option<String>,
+    group_by: Option<String>,
+}
+
+impl WindowConfigBuilder {
+    pub(crate) fn parse(&self) -> LaikaResult<WindowConfig> {
+        let kind = match (&self.kind, &self.slide) {
+            (WindowKindConfig::Tumbling, None) => WindowKind::Tumbling,
+            (WindowKindConfig::Sliding, Some(slide)) => WindowKind::Sliding {
+                slide: parse_time_str(slide.as_str()).map_err(invalid_at("slide"))?,
+            },
+            (WindowKindConfig::Tumbling, Some(_)) => {
+                return Err(LaikaError::config(
+                    "slide",
+                    "Tumbling windows do not take a slide",
+                ))
+            }
+            (WindowKindConfig::Sliding, None) => {
+                return Err(LaikaError::config(
+                    "slide",
+                    "Sliding windows require a slide",
+                ))
+            }
+        };
+        WindowConfig::new(
+            kind,
+            parse_time_str(self.size.as_str()).map_err(invalid_at("size"))?,
+            self.group_by.clone(),
+        )
+    }
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfigBuilder {
+    cron: String,
+    #[serde(default)]
+    scope: ScheduleScope,
+}
+
+impl ScheduleConfigBuilder {
+    pub(crate) fn parse(&self) -> LaikaResult<ScheduleConfig> {
+        Ok(ScheduleConfig {
+            cron: CronSchedule::parse(self.cron.as_str()).map_err(invalid_at("cron"))?,
+            scope: self.scope,
+        })
+    }
+}
+
+/// Correlation of each event type, by name. Every key is an event type, and any that isn't
+/// defined in `events` is rejected when the config is checked.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct CorrelationConfig {
     pub(crate) events: HashMap<String, EventCorrelationBuilder>,
 }
 
 #[derive(Deserialize, Clone)]
-#[serde(untagged)]
+#[serde(untagged, deny_unknown_fields)]
 pub enum RequirementConfig {
     Exact { exact: Vec<String> },
     AtLeast { at_least: Vec<String> },
 }
 
+impl RequirementConfig {
+    /// Event types the requirement lists
+    fn events(&self) -> &[String] {
+        match self {
+            RequirementConfig::Exact { exact } => exact,
+            RequirementConfig::AtLeast { at_least } => at_least,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct RoutingConfig {
+    /// Routing key or topic, templated from the condition result
     topic: String,
 }
 
 #[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TriggerConfig {
-    pub(crate) requires: RequirementConfig,
+    /// Optional only for scheduled triggers, which don't need events to be evaluated
+    pub(crate) requires: Option<RequirementConfig>,
     #[serde(rename = "filterAndExtract")]
     pub(crate) filter_and_extract: Option<String>,
+    /// Fields `filterAndExtract` must return, checked before the action is rendered
+    #[serde(rename = "outputSchema", default)]
+    pub(crate) output_schema: Option<BTreeMap<String, FieldSchemaYaml>>,
     pub(crate) timing: Option<TimingConfigBuilder>,
+    pub(crate) aggregate: Option<AggregateConfigBuilder>,
+    pub(crate) window: Option<WindowConfigBuilder>,
+    /// Event types that must not arrive within `within` of the requirements being met
+    pub(crate) absent: Option<Vec<String>>,
+    pub(crate) within: Option<String>,
+    /// Cron schedule to evaluate the trigger on, instead of on received events
+    pub(crate) schedule: Option<ScheduleConfigBuilder>,
+    /// Rules with a higher priority are evaluated first
+    #[serde(default)]
+    pub(crate) priority: i32,
+    /// Skip lower priority rules for a trigger once this rule fires
+    #[serde(rename = "stopOnMatch", default)]
+    pub(crate) stop_on_match: bool,
+    /// Stored events, or fields of them, given to `filterAndExtract` instead of the whole context,
+    /// and the fields summarized in its stats
+    pub(crate) context: Option<ContextConfig>,
+    /// Tenants the trigger is evaluated for, rather than all of them
+    #[serde(default)]
+    pub(crate) tenants: Option<Vec<String>>,
+    /// What to do with events that arrive once the trigger has decided on their correlation
+    #[serde(default)]
+    pub(crate) late: Option<LatePolicy>,
+    /// Event types, or `timer`, that `filterAndExtract` is evaluated on, rather than all triggers
+    #[serde(default)]
+    pub(crate) on: Option<Vec<String>>,
     pub(crate) action: ActionConfigYaml,
+    /// Action taken once if the trigger still hasn't fired when `timing.until` passes
+    #[serde(rename = "onTimeout", default)]
+    pub(crate) on_timeout: Option<ActionConfigYaml>,
+    /// Actions taken as rechecks go on without the trigger firing, each after more of them
+    #[serde(default)]
+    pub(crate) escalate: Vec<EscalationConfig>,
+    /// How long the same action for a correlation is suppressed after it's taken, including
+    /// across restarts
+    pub(crate) dedupe: Option<String>,
+    /// Share of correlations the trigger is evaluated for, such as `10%`, decided by the hash of
+    /// their correlation ID
+    pub(crate) rollout: Option<String>,
+    /// Sample events and what the rule should emit for them, run by `laika test`
+    #[serde(default)]
+    pub(crate) tests: Vec<RuleTestConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EscalationConfig {
+    /// Rechecks that leave the trigger unsatisfied before this step, counted on from the step
+    /// before
+    after: u32,
+    action: ActionConfigYaml,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ContextConfig {
+    /// Event types, or fields of them, kept in the context, or every event without it
+    #[serde(default)]
+    include: Option<Vec<String>>,
+    /// Numeric fields of event types given a minimum and maximum in `ctx.stats`
+    #[serde(default)]
+    stats: Vec<String>,
+}
+
+impl ContextConfig {
+    fn shape(&self) -> LaikaResult<ContextShape> {
+        let shape = match &self.include {
+            Some(include) => ContextShape::parse(include)?,
+            None => ContextShape::everything(),
+        };
+        shape.with_stats(&self.stats)
+    }
+}
+
+impl TriggerConfig {
+    fn escalation(&self) -> LaikaResult<Vec<Escalation>> {
+        if !self.escalate.is_empty() && self.timing.is_none() {
+            return Err(LaikaError::config(
+                "escalate",
+                "escalate requires timing, which schedules the rechecks it counts",
+            ));
+        }
+        let mut rechecks = 0;
+        self.escalate
+            .iter()
+            .enumerate()
+            .map(|(index, step)| {
+                if step.after == 0 {
+                    return Err(LaikaError::config(
+                        format!("escalate.{}.after", index),
+                        "after must be at least 1",
+                    ));
+                }
+                rechecks += step.after;
+                let path = format!("escalate.{}.action", index);
+                Ok(Escalation {
+                    rechecks,
+                    action: step
+                        .action
+                        .clone()
+                        .try_into()
+                        .map_err(invalid_at(path.as_str()))?,
+                })
+            })
+            .collect()
+    }
+
+    fn absence(&self) -> LaikaResult<Option<Absence>> {
+        match (&self.absent, &self.within) {
+            (None, None) => Ok(None),
+            (Some(events), Some(within)) => {
+                if events.is_empty() {
+                    return Err(LaikaError::config(
+                        "absent",
+                        "absent must list at least one event",
+                    ));
+                }
+                Ok(Some(Absence {
+                    events: events.clone(),
+                    within: parse_time_span(within.as_str()).map_err(invalid_at("within"))?,
+                }))
+            }
+            (Some(_), None) => Err(LaikaError::config(
+                "within",
+                "absent requires a within duration",
+            )),
+            (None, Some(_)) => Err(LaikaError::config(
+                "within",
+                "within can only be used alongside absent",
+            )),
+        }
+    }
 }
 
 impl TryFrom<TriggerConfig> for EventTrigger {
     type Error = LaikaError;
     fn try_from(value: TriggerConfig) -> LaikaResult<Self> {
+        if value.aggregate.is_some() && value.window.is_some() {
+            return Err(LaikaError::config(
+                "",
+                "Cannot specify both aggregate and window on a trigger",
+            ));
+        }
+        if value.absent.is_some() && (value.aggregate.is_some() || value.window.is_some()) {
+            return Err(LaikaError::config(
+                "",
+                "Cannot combine absent with aggregate or window on a trigger",
+            ));
+        }
+        if value.schedule.is_some()
+            && (value.aggregate.is_some()
+                || value.window.is_some()
+                || value.absent.is_some()
+                || value.timing.is_some())
+        {
+            return Err(LaikaError::config(
+                "",
+                "Cannot combine schedule with aggregate, window, absent or timing on a trigger",
+            ));
+        }
+        if value.late.is_some() && (value.schedule.is_some() || value.window.is_some()) {
+            return Err(LaikaError::config(
+                "late",
+                "late can't be used with schedule or window, which aren't evaluated on events",
+            ));
+        }
+        if value.on.as_ref().is_some_and(|on| on.is_empty()) {
+            return Err(LaikaError::config(
+                "on",
+                "on must list at least one event type or timer",
+            ));
+        }
+        if value.on.is_some() && (value.schedule.is_some() || value.window.is_some()) {
+            return Err(LaikaError::config(
+                "on",
+                "on can't be used with schedule or window, which aren't evaluated on events",
+            ));
+        }
+        if value.rollout.is_some()
+            && (value.window.is_some()
+                || value
+                    .schedule
+                    .as_ref()
+                    .is_some_and(|schedule| schedule.scope == ScheduleScope::Global))
+        {
+            return Err(LaikaError::config(
+                "rollout",
+                "rollout can't be used with window or global schedules, which have no correlation",
+            ));
+        }
+        let rollout = value.rollout.as_deref().map(Rollout::parse).transpose()?;
+        if value.on_timeout.is_some()
+            && value
+                .timing
+                .as_ref()
+                .is_none_or(|timing| timing.until.is_none())
+        {
+            return Err(LaikaError::config(
+                "onTimeout",
+                "onTimeout requires timing.until, which is when the trigger times out",
+            ));
+        }
+        let absence = value.absence()?;
+        let escalation = value.escalation()?;
+        let schedule = value
+            .schedule
+            .map(|v| v.parse())
+            .transpose()
+            .map_err(invalid_at("schedule"))?;
+        let requirement = match (value.requires, &schedule) {
+            (
+                Some(_),
+                Some(ScheduleConfig {
+                    scope: ScheduleScope::Global,
+                    ..
+                }),
+            ) => {
+                return Err(LaikaError::config(
+                    "requires",
+                    "requires can only be used with correlation scoped schedules",
+                ))
+            }
+            (Some(requires), _) => requires.into(),
+            (None, Some(_)) => Requirement::AtLeast(Vec::new()),
+            (None, None) => {
+                return Err(LaikaError::config(
+                    "requires",
+                    "requires must be provided for triggers without a schedule",
+                ))
+            }
+        };
         Ok(EventTrigger {
-            requirement: value.requires.into(),
+            requirement,
             filter_and_extract: value.filter_and_extract,
-            timing: value.timing.map(|v| v.parse()).transpose()?,
-            action: value.action.try_into().map_err(LaikaError::from)?,
+            output_schema: value
+                .output_schema
+                .as_ref()
+                .map(OutputSchema::parse)
+                .transpose()
+                .map_err(invalid_at("outputSchema"))?,
+            timing: value
+                .timing
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(invalid_at("timing"))?,
+            aggregate: value
+                .aggregate
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(invalid_at("aggregate"))?,
+            window: value
+                .window
+                .map(|v| v.parse())
+                .transpose()
+                .map_err(invalid_at("window"))?,
+            absence,
+            schedule,
+            priority: value.priority,
+            stop_on_match: value.stop_on_match,
+            context: value
+                .context
+                .map(|context| context.shape())
+                .transpose()
+                .map_err(invalid_at("context"))?,
+            tenants: value.tenants.map(|tenants| tenants.into_iter().collect()),
+            late: value.late,
+            on: value.on.map(TriggerScope::new),
+            action: value.action.try_into().map_err(invalid_at("action"))?,
+            on_timeout: value
+                .on_timeout
+                .map(ActionConfig::try_from)
+                .transpose()
+                .map_err(invalid_at("onTimeout"))?,
+            escalation,
+            dedupe: value
+                .dedupe
+                .map(|dedupe| parse_time_str(dedupe.as_str()))
+                .transpose()
+                .map_err(invalid_at("dedupe"))?,
+            rollout,
         })
     }
 }
 
 #[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct EmitConfig {
     target: String,
     routing: RoutingConfig,
@@ -196,28 +974,199 @@ pub struct EmitConfig {
 }
 
 #[derive(Debug, Deserialize, Clone)]
-pub struct ActionConfigYaml {
-    target: String,
-    payload: serde_yaml::Value,
+#[serde(deny_unknown_fields)]
+pub struct HttpActionYaml {
+    #[serde(default = "HttpActionYaml::default_method")]
+    method: String,
+    url: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<serde_yaml::Value>,
+    /// CA and client certificates for the request, where the defaults won't do
+    #[serde(default)]
+    tls: Option<TlsSetting>,
+    /// How the body is compressed, sent as its `Content-Encoding`
+    #[serde(default)]
+    compression: Compression,
+    /// Credentials to send the request with
+    #[serde(default)]
+    auth: Option<HttpAuthConfig>,
+}
+
+impl HttpActionYaml {
+    fn default_method() -> String {
+        "POST".to_string()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StoreActionYaml {
+    key: String,
+    value: serde_yaml::Value,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum ActionConfigYaml {
+    Http {
+        http: HttpActionYaml,
+    },
+    Store {
+        store: StoreActionYaml,
+    },
+    Emit {
+        target: String,
+        payload: serde_yaml::Value,
+        #[serde(default)]
+        routing: Option<RoutingConfig>,
+        /// How the rendered payload is encoded, JSON by default
+        #[serde(default)]
+        format: EmitFormatConfig,
+    },
+}
+
+#[derive(Clone)]
+pub struct HttpActionConfig {
+    pub(crate) method: reqwest::Method,
+    pub(crate) url: Template,
+    pub(crate) headers: Vec<(String, Template)>,
+    pub(crate) body: Option<Template>,
+    pub(crate) tls: Option<TlsConfig>,
+    pub(crate) compression: Compression,
+    pub(crate) auth: Option<HttpAuth>,
+}
+
+impl std::fmt::Debug for HttpActionConfig {
+    // Header templates may have had secrets injected from the environment
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HttpActionConfig")
+            .field("method", &self.method)
+            .field("url", &self.url)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("body", &self.body)
+            .field("tls", &self.tls)
+            .field("compression", &self.compression)
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+impl TryFrom<HttpActionYaml> for HttpActionConfig {
+    type Error = LaikaError;
+    fn try_from(value: HttpActionYaml) -> LaikaResult<Self> {
+        let method =
+            reqwest::Method::from_bytes(value.method.to_uppercase().as_bytes()).map_err(|_| {
+                LaikaError::config(
+                    "http.method",
+                    format!("Invalid HTTP method {}", value.method),
+                )
+            })?;
+        let string_template = |raw: &str| -> LaikaResult<Template> {
+            Ok(Template::from_payload(&serde_yaml::Value::String(
+                raw.to_string(),
+            ))?)
+        };
+        let tls = value.tls.as_ref().and_then(TlsSetting::config);
+        if let Some(tls) = &tls {
+            // Certificates are loaded up front so bad paths fail the config, not each request
+            tls.http_client()
+                .map_err(|e| LaikaError::config("http.tls", e.to_string()))?;
+        }
+        Ok(HttpActionConfig {
+            method,
+            url: string_template(value.url.as_str())?,
+            headers: value
+                .headers
+                .iter()
+                .map(|(name, raw)| string_template(raw).map(|t| (name.clone(), t)))
+                .collect::<LaikaResult<Vec<(String, Template)>>>()?,
+            body: value
+                .body
+                .as_ref()
+                .map(Template::from_payload)
+                .transpose()?,
+            tls,
+            compression: value.compression,
+            auth: value
+                .auth
+                .as_ref()
+                .map(HttpAuthConfig::resolve)
+                .transpose()
+                .map_err(|e| e.within("http.auth"))?,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
-pub struct ActionConfig {
-    pub(crate) target: String,
-    pub emit_template: Template,
+pub enum ActionConfig {
+    /// Render the payload and submit it to a named connection
+    Emit {
+        target: String,
+        emit_template: Template,
+        routing_template: Option<Template>,
+        format: EmitFormat,
+    },
+    /// Render and send an HTTP request directly
+    Http(HttpActionConfig),
+    /// Render a value and store it against the correlation under `key`
+    Store {
+        key: String,
+        value_template: Template,
+    },
+}
+
+impl ActionConfig {
+    /// Connection this action submits to, if any
+    pub(crate) fn target(&self) -> Option<&str> {
+        match self {
+            ActionConfig::Emit { target, .. } => Some(target.as_str()),
+            ActionConfig::Http(_) | ActionConfig::Store { .. } => None,
+        }
+    }
 }
 
 impl TryFrom<ActionConfigYaml> for ActionConfig {
-    type Error = TemplateError;
-    fn try_from(value: ActionConfigYaml) -> Result<Self, Self::Error> {
-        Ok(ActionConfig {
-            target: value.target,
-            emit_template: Template::from_payload(&value.payload)?,
-        })
+    type Error = LaikaError;
+    fn try_from(value: ActionConfigYaml) -> LaikaResult<Self> {
+        match value {
+            ActionConfigYaml::Http { http } => Ok(ActionConfig::Http(http.try_into()?)),
+            ActionConfigYaml::Store { store } => Ok(ActionConfig::Store {
+                key: store.key,
+                value_template: Template::from_payload(&store.value)?,
+            }),
+            ActionConfigYaml::Emit {
+                target,
+                payload,
+                routing,
+                format,
+            } => Ok(ActionConfig::Emit {
+                target,
+                emit_template: Template::from_payload(&payload)?,
+                routing_template: routing
+                    .map(|routing| {
+                        Template::from_payload(&serde_yaml::Value::String(routing.topic))
+                    })
+                    .transpose()?,
+                format: format.try_into().map_err(invalid_at("format"))?,
+            }),
+        }
     }
 }
 
 #[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub(crate) struct EventCorrelationBuilder {
     pub(crate) key: String, // JSONPath expression
+    /// Keys of other correlations the event links into its own, as JSONPath expressions
+    #[serde(default)]
+    pub(crate) links: Vec<String>,
 }
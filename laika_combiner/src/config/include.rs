@@ -0,0 +1,524 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::predicate_engine::transpile_typescript;
+use crate::utils::env::interpolate_config;
+use serde_yaml::{Mapping, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Sections made of named entries, which files can each add entries to
+const NAMED_SECTIONS: [&str; 5] = [
+    "connections",
+    "correlation",
+    "events",
+    "triggers",
+    "stateMachines",
+];
+
+/// Prefix of trigger values that are kept in a file of their own, relative to the config they're
+/// given in
+const FILE_REFERENCE: &str = "file://";
+
+/// Files a config was read from, including those it includes and refers to, with what each
+/// held. A file referred to more than once is only read once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigSources {
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl ConfigSources {
+    /// Read a file, failing at `field` of the config if it can't be, returning what it holds and
+    /// its canonical path
+    fn read(&mut self, path: &Path, field: &str) -> LaikaResult<(String, PathBuf)> {
+        let unreadable = |e: std::io::Error| {
+            LaikaError::config(field, format!("Could not read {}: {}", path.display(), e))
+        };
+        let canonical = path.canonicalize().map_err(unreadable)?;
+        if let Some(text) = self.files.get(&canonical) {
+            return Ok((text.clone(), canonical));
+        }
+        let text = std::fs::read_to_string(&canonical).map_err(unreadable)?;
+        self.files.insert(canonical.clone(), text.clone());
+        Ok((text, canonical))
+    }
+
+    /// Every file read, by canonical path
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.files.keys().map(PathBuf::as_path)
+    }
+}
+
+/// Parse a config, merging in the files it includes. Included paths and patterns, and files
+/// referred to, are relative to `base`, the directory of the config they're listed in.
+pub(crate) fn resolve(yaml: &str, base: &Path) -> LaikaResult<(Value, ConfigSources)> {
+    let root = parse(yaml, Path::new("config"))?;
+    let mut sources = ConfigSources::default();
+    let mut documents = Vec::new();
+    collect(
+        root,
+        base,
+        PathBuf::from("config"),
+        &mut Vec::new(),
+        &mut sources,
+        &mut documents,
+    )?;
+    Ok((merge(documents).map(Value::Mapping)?, sources))
+}
+
+/// Read and parse a config file, merging in the files it includes
+pub(crate) fn load(path: &Path) -> LaikaResult<(Value, ConfigSources)> {
+    let mut sources = ConfigSources::default();
+    let (yaml, canonical) = sources.read(path, "include")?;
+    let root = parse(yaml.as_str(), path)?;
+    let base = canonical.parent().unwrap_or(Path::new("."));
+    let mut documents = Vec::new();
+    collect(
+        root,
+        base,
+        path.to_path_buf(),
+        &mut vec![canonical.clone()],
+        &mut sources,
+        &mut documents,
+    )?;
+    Ok((merge(documents).map(Value::Mapping)?, sources))
+}
+
+/// Replace environment references throughout a file before it's parsed, so they can stand in for
+/// numbers and durations as well as strings. Comment lines are left alone.
+fn interpolate(yaml: &str, path: &Path) -> LaikaResult<String> {
+    yaml.split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| {
+            if line.trim_start().starts_with('#') {
+                return Ok(line.to_string());
+            }
+            interpolate_config(line).map_err(|e| {
+                LaikaError::config("", format!("{} line {}: {}", path.display(), index + 1, e))
+            })
+        })
+        .collect()
+}
+
+fn parse(yaml: &str, path: &Path) -> LaikaResult<Mapping> {
+    match serde_yaml::from_str(interpolate(yaml, path)?.as_str()) {
+        Ok(Value::Mapping(mapping)) => Ok(mapping),
+        Ok(Value::Null) => Ok(Mapping::new()),
+        Ok(_) => Err(LaikaError::config(
+            "",
+            format!("{} isn't a mapping of config sections", path.display()),
+        )),
+        Err(e) => Err(LaikaError::Generic(format!(
+            "Could not read {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+/// Files matching an `include` entry, in name order
+fn matching(pattern: &str, base: &Path) -> LaikaResult<Vec<PathBuf>> {
+    let full = base.join(pattern);
+    let invalid = |message: String| LaikaError::config("include", message);
+    let mut paths = glob::glob(full.to_string_lossy().as_ref())
+        .map_err(|e| invalid(format!("{}: {}", pattern, e)))?
+        .collect::<Result<Vec<PathBuf>, _>>()
+        .map_err(|e| invalid(e.to_string()))?;
+    if paths.is_empty() {
+        return Err(invalid(format!("{} doesn't match any files", pattern)));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Gather a config and everything it includes, included files first so that the config's own
+/// event definitions come after theirs
+fn collect(
+    mut config: Mapping,
+    base: &Path,
+    path: PathBuf,
+    including: &mut Vec<PathBuf>,
+    sources: &mut ConfigSources,
+    documents: &mut Vec<(PathBuf, Mapping)>,
+) -> LaikaResult<()> {
+    let patterns = match config.remove("include") {
+        None => Vec::new(),
+        Some(Value::String(pattern)) => vec![pattern],
+        Some(Value::Sequence(patterns)) => patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                Value::String(pattern) => Ok(pattern),
+                _ => Err(LaikaError::config("include", "Includes must be paths")),
+            })
+            .collect::<LaikaResult<Vec<String>>>()?,
+        Some(_) => {
+            return Err(LaikaError::config(
+                "include",
+                "include must be a path or a list of paths",
+            ))
+        }
+    };
+    for pattern in patterns {
+        for included in matching(pattern.as_str(), base)? {
+            let (yaml, canonical) = sources.read(&included, "include")?;
+            if including.contains(&canonical) {
+                return Err(LaikaError::config(
+                    "include",
+                    format!("{} includes itself", included.display()),
+                ));
+            }
+            let mapping = parse(yaml.as_str(), &included)?;
+            including.push(canonical.clone());
+            collect(
+                mapping,
+                canonical.parent().unwrap_or(base),
+                included,
+                including,
+                sources,
+                documents,
+            )?;
+            including.pop();
+        }
+    }
+    resolve_references(&mut config, base, sources)?;
+    documents.push((path, config));
+    Ok(())
+}
+
+/// What a `file://` reference at `field` refers to, if `value` is one
+fn referenced(
+    value: &Value,
+    field: &str,
+    base: &Path,
+    sources: &mut ConfigSources,
+) -> LaikaResult<Option<(String, PathBuf)>> {
+    let Some(reference) = value.as_str().and_then(|v| v.strip_prefix(FILE_REFERENCE)) else {
+        return Ok(None);
+    };
+    let path = base.join(reference.trim());
+    sources.read(&path, field).map(Some)
+}
+
+/// Replace a `filterAndExtract` that refers to a file with the function in it. TypeScript files
+/// have their types stripped.
+fn resolve_predicate(
+    predicate: &mut Value,
+    field: &str,
+    base: &Path,
+    sources: &mut ConfigSources,
+) -> LaikaResult<()> {
+    let Some((source, path)) = referenced(predicate, field, base, sources)? else {
+        return Ok(());
+    };
+    let source = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("ts") => transpile_typescript(path.to_string_lossy().as_ref(), source)
+            .map_err(|e| LaikaError::config(field, format!("{}: {}", path.display(), e)))?,
+        _ => source,
+    };
+    // The function is evaluated as an expression, so a statement's semicolon is left off
+    *predicate = Value::String(source.trim_end().trim_end_matches(';').to_string());
+    Ok(())
+}
+
+/// Replace a `payload` that refers to a file with the YAML, or JSON, in it
+fn resolve_payload(
+    payload: &mut Value,
+    field: &str,
+    base: &Path,
+    sources: &mut ConfigSources,
+) -> LaikaResult<()> {
+    let Some((yaml, path)) = referenced(payload, field, base, sources)? else {
+        return Ok(());
+    };
+    *payload = serde_yaml::from_str(interpolate(yaml.as_str(), &path)?.as_str())
+        .map_err(|e| LaikaError::config(field, format!("{}: {}", path.display(), e)))?;
+    Ok(())
+}
+
+/// Replace `file://` references in triggers, from `filterAndExtract` and the payloads of their
+/// actions, with what the files hold
+fn resolve_references(
+    config: &mut Mapping,
+    base: &Path,
+    sources: &mut ConfigSources,
+) -> LaikaResult<()> {
+    let Some(Value::Mapping(triggers)) = config.get_mut("triggers") else {
+        return Ok(());
+    };
+    for (name, trigger) in triggers.iter_mut() {
+        let name = format!("triggers.{}", name.as_str().unwrap_or("?"));
+        let Value::Mapping(trigger) = trigger else {
+            continue;
+        };
+        if let Some(predicate) = trigger.get_mut("filterAndExtract") {
+            let field = format!("{}.filterAndExtract", name);
+            resolve_predicate(predicate, field.as_str(), base, sources)?;
+        }
+        let mut actions: Vec<(String, &mut Value)> = Vec::new();
+        for (key, value) in trigger.iter_mut() {
+            match (key.as_str(), value) {
+                (Some(key @ ("action" | "onTimeout")), action) => {
+                    actions.push((format!("{}.{}", name, key), action))
+                }
+                (Some("escalate"), Value::Sequence(steps)) => {
+                    for (index, step) in steps.iter_mut().enumerate() {
+                        if let Some(action) = step.get_mut("action") {
+                            actions.push((format!("{}.escalate.{}.action", name, index), action));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (field, action) in actions {
+            if let Some(payload) = action.get_mut("payload") {
+                let field = format!("{}.payload", field);
+                resolve_payload(payload, field.as_str(), base, sources)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Merge configs into one, failing on anything defined twice. Named entries of the same section
+/// can come from different files, `lib` files are concatenated, and other sections can only be
+/// given once.
+fn merge(documents: Vec<(PathBuf, Mapping)>) -> LaikaResult<Mapping> {
+    let mut merged = Mapping::new();
+    let mut origins: HashMap<String, PathBuf> = HashMap::new();
+    let mut defined = |key: String, path: &Path| match origins.get(&key) {
+        Some(first) => Err(LaikaError::config(
+            key,
+            format!("Defined in both {} and {}", first.display(), path.display()),
+        )),
+        None => {
+            origins.insert(key, path.to_path_buf());
+            Ok(())
+        }
+    };
+    for (path, document) in documents {
+        for (key, value) in document {
+            let Some(section) = key.as_str().map(str::to_string) else {
+                return Err(LaikaError::config(
+                    "",
+                    format!("{} has a section that isn't named", path.display()),
+                ));
+            };
+            match (merged.get_mut(&key), value) {
+                (Some(Value::Mapping(entries)), Value::Mapping(added))
+                    if NAMED_SECTIONS.contains(&section.as_str()) =>
+                {
+                    for (name, entry) in added {
+                        let name_key = format!("{}.{}", section, name.as_str().unwrap_or("?"));
+                        defined(name_key, &path)?;
+                        entries.insert(name, entry);
+                    }
+                }
+                (Some(Value::Sequence(items)), Value::Sequence(added)) if section == "lib" => {
+                    items.extend(added);
+                }
+                (Some(_), _) => defined(section, &path)?,
+                (None, value) => {
+                    if let Value::Mapping(entries) = &value {
+                        if NAMED_SECTIONS.contains(&section.as_str()) {
+                            for name in entries.keys() {
+                                let name_key =
+                                    format!("{}.{}", section, name.as_str().unwrap_or("?"));
+                                defined(name_key, &path)?;
+                            }
+                        }
+                    }
+                    defined(section, &path)?;
+                    merged.insert(key, value);
+                }
+            }
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+    use tempfile::TempDir;
+
+    fn write(dir: &TempDir, name: &str, yaml: &str) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, yaml).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_includes_are_merged() {
+        let dir = TempDir::new().unwrap();
+        write(
+            &dir,
+            "connections.yaml",
+            "connections:\n  orders:\n    type: stdin\n",
+        );
+        write(
+            &dir,
+            "rules/payments.yaml",
+            "events:\n  paid: {}\ntriggers:\n  paymentReceived: {}\nlib: [payments.js]\n",
+        );
+        write(
+            &dir,
+            "rules/refunds.yaml",
+            "events:\n  refunded: {}\ntriggers:\n  refundIssued: {}\nlib: [refunds.js]\n",
+        );
+        let root = write(
+            &dir,
+            "config.yaml",
+            "include: [connections.yaml, rules/*.yaml]\nevents:\n  placed: {}\n",
+        );
+
+        let (merged, _) = load(&root).unwrap();
+        let events: Vec<&str> = merged["events"]
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .filter_map(Value::as_str)
+            .collect();
+        // Included definitions come first, in the order they were included
+        assert_eq!(events, vec!["paid", "refunded", "placed"]);
+        assert_eq!(merged["triggers"].as_mapping().unwrap().len(), 2);
+        assert_eq!(
+            merged["lib"],
+            serde_yaml::from_str::<Value>("[payments.js, refunds.js]").unwrap()
+        );
+        assert!(merged.get("include").is_none());
+    }
+
+    #[test]
+    fn test_duplicates_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "a.yaml", "triggers:\n  orderShipped: {}\n");
+        write(&dir, "b.yaml", "triggers:\n  orderShipped: {}\n");
+        let root = write(&dir, "config.yaml", "include: [a.yaml, b.yaml]\n");
+        assert!(matches!(
+            load(&root),
+            Err(LaikaError::ConfigError { path, .. }) if path == "triggers.orderShipped"
+        ));
+
+        write(&dir, "storage.yaml", "storage:\n  dataDir: /tmp\n");
+        let root = write(
+            &dir,
+            "config.yaml",
+            "include: [storage.yaml]\nstorage:\n  dataDir: /var\n",
+        );
+        assert!(matches!(
+            load(&root),
+            Err(LaikaError::ConfigError { path, .. }) if path == "storage"
+        ));
+    }
+
+    #[test]
+    fn test_environment_is_interpolated() {
+        std::env::set_var("LAIKA_TEST_DATA_DIR", "/var/lib/laika");
+        let (merged, _) = resolve(
+            "# Set ${LAIKA_TEST_UNSET_VARIABLE} in production\nstorage:\n  dataDir: ${LAIKA_TEST_DATA_DIR}\n  maxWakeups: ${LAIKA_TEST_UNSET_VARIABLE:-500}\n",
+            Path::new("."),
+        )
+        .unwrap();
+        assert_eq!(merged["storage"]["dataDir"], "/var/lib/laika");
+        assert_eq!(merged["storage"]["maxWakeups"], 500);
+    }
+
+    #[test]
+    fn test_environment_values_are_not_templates() {
+        std::env::set_var("LAIKA_TEST_AUTHORIZATION", "Bearer a${{ secret }}b");
+        let (merged, _) = resolve(
+            "headers:\n  Authorization: \"${LAIKA_TEST_AUTHORIZATION}\"\n  X-User: \"${{ userId }}\"\n",
+            Path::new("."),
+        )
+        .unwrap();
+        let rendered = Template::from_payload(&merged["headers"])
+            .unwrap()
+            .render(&serde_json::json!({"userId": 1, "secret": "leaked"}))
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(rendered).unwrap(),
+            serde_json::json!({"Authorization": "Bearer a${{ secret }}b", "X-User": "1"})
+        );
+    }
+
+    #[test]
+    fn test_file_references_are_resolved() {
+        std::env::set_var("LAIKA_TEST_ALERT_SEVERITY", "high");
+        let dir = TempDir::new().unwrap();
+        write(
+            &dir,
+            "rules/settlement.js",
+            "(trigger, ctx) => ({ id: trigger.type });\n",
+        );
+        write(
+            &dir,
+            "rules/templates/alert.yaml",
+            "id: \"${{ id }}\"\nseverity: ${LAIKA_TEST_ALERT_SEVERITY}\n",
+        );
+        write(
+            &dir,
+            "rules/settlement.yaml",
+            "triggers:\n  settled:\n    filterAndExtract: file://settlement.js\n    action:\n      payload: file://templates/alert.yaml\n    escalate:\n      - action:\n          payload: file://templates/alert.yaml\n",
+        );
+        let root = write(&dir, "config.yaml", "include: [rules/settlement.yaml]\n");
+
+        let (merged, sources) = load(&root).unwrap();
+        let settled = &merged["triggers"]["settled"];
+        assert_eq!(
+            settled["filterAndExtract"],
+            "(trigger, ctx) => ({ id: trigger.type })"
+        );
+        assert_eq!(settled["action"]["payload"]["severity"], "high");
+        assert_eq!(
+            settled["escalate"][0]["action"]["payload"]["id"],
+            "${{ id }}"
+        );
+        // Both configs and both referenced files, with the template only read once
+        assert_eq!(sources.paths().count(), 4);
+
+        write(
+            &dir,
+            "config.yaml",
+            "triggers:\n  settled:\n    filterAndExtract: file://missing.js\n",
+        );
+        assert!(matches!(
+            load(&root),
+            Err(LaikaError::ConfigError { path, .. }) if path == "triggers.settled.filterAndExtract"
+        ));
+    }
+
+    #[cfg(feature = "typescript")]
+    #[test]
+    fn test_typescript_references_are_transpiled() {
+        let dir = TempDir::new().unwrap();
+        write(
+            &dir,
+            "settlement.ts",
+            "(trigger: Laika.Trigger, ctx: Laika.Context) => ({ id: trigger.type as string });\n",
+        );
+        let root = write(
+            &dir,
+            "config.yaml",
+            "triggers:\n  settled:\n    filterAndExtract: file://settlement.ts\n",
+        );
+
+        let (merged, _) = load(&root).unwrap();
+        let predicate = merged["triggers"]["settled"]["filterAndExtract"]
+            .as_str()
+            .unwrap();
+        assert!(!predicate.contains("Laika.Trigger"));
+        assert!(!predicate.ends_with(';'));
+    }
+
+    #[test]
+    fn test_include_cycles_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "a.yaml", "include: [config.yaml]\n");
+        let root = write(&dir, "config.yaml", "include: [a.yaml]\n");
+        assert!(matches!(
+            load(&root),
+            Err(LaikaError::ConfigError { message, .. }) if message.contains("includes itself")
+        ));
+    }
+}
@@ -1,19 +1,49 @@
 pub mod builder;
+mod include;
+pub use include::ConfigSources;
+pub mod state_machine;
 
+use crate::admin::AdminConfig;
+use crate::aggregation::AggregateConfig;
+use crate::batching::EmitBatching;
 use crate::broker::CorrelationId;
+use crate::calendar::{BusinessCalendar, CalendarConfig};
+use crate::connections::internal::InternalConnection;
 use crate::connections::{
-    create_receiver, create_submitter, ConnectionConfig, Connections, EventReceiver,
-    EventSubmitter, MessagingError,
+    create_receiver, create_submitter, ConnectionConfig, ConnectionRegistry, ConnectionSpec,
+    Connections, EventReceiver, EventSubmitter, MessagingError, ReceiveOptions,
 };
+use crate::coordination::CoordinationConfig;
+use crate::dead_letter::DeadLetterConfig;
+use crate::dispatcher::{DeliveryOrdering, DispatchConfig, Dispatcher};
 use crate::errors::{LaikaError, LaikaResult};
+use crate::event::context::ContextShape;
 use crate::event::{EventLike, RawEvent};
+use crate::late::LatePolicy;
+use crate::logging::LoggingConfig;
 use crate::matcher::{EventType, EventTypeDefinitions};
-use crate::predicate_engine::{JsonPredicate, JsonPredicateEngine};
-use crate::rules::{EventRule, Requirement};
+use crate::output_schema::OutputSchema;
+use crate::partitioning::{PartitionConfig, Partitioning};
+use crate::predicate_engine::{
+    transpile_typescript, JsonPredicate, JsonPredicateEngine, PredicateConfig, PredicateLimits,
+};
+use crate::provenance::Provenance;
+use crate::rollout::Rollout;
+use crate::rules::{Absence, Escalation, EventRule, Requirement, TriggerScope};
+use crate::schedule::ScheduleConfig;
+use crate::size_limits::{SizeLimits, SizeLimitsConfig};
+use crate::storage::{LimitPolicy, StorageConfig};
+use crate::telemetry::TelemetryConfig;
+use crate::tenancy::TenancyConfig;
+use crate::utils::extract_json::extract_json_values;
+use crate::watermark::{Watermark, WatermarkConfig};
+use crate::windowing::WindowConfig;
 use crate::EventProcessor;
-use builder::{ActionConfig, TimingConfig};
+use builder::{invalid_at, ActionConfig, TimingConfig};
 use futures::stream::{self, StreamExt};
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use time::Duration;
 
 const DEFAULT_PREDICATE: &str = r#"(trigger, ctx) => {
   const result = {
@@ -25,9 +55,17 @@ const DEFAULT_PREDICATE: &str = r#"(trigger, ctx) => {
     meta: {}
   };
 
+  // Values stored against the correlation by earlier rules
+  if (ctx.stored && Object.keys(ctx.stored).length > 0) {
+    result.stored = ctx.stored;
+  }
+
   // Add event to trigger if it's a received_event type
   if (trigger.type === "received_event" && trigger.event) {
     result.trigger.event = trigger.event;
+    if (trigger.meta && Object.keys(trigger.meta).length > 0) {
+      result.trigger.meta = trigger.meta;
+    }
   }
 
   // Process events from context
@@ -45,17 +83,41 @@ const DEFAULT_PREDICATE: &str = r#"(trigger, ctx) => {
   // Determine if we should return the result or null based on your business logic
   const hasEvents = Object.keys(result.events).length > 0;
 
-  return hasEvents ? result : null;
+  // Scheduled triggers fire even without events, e.g. for periodic reports
+  return hasEvents || trigger.type === "scheduled" ? result : null;
 }"#;
 
 #[derive(Clone, Debug)]
 pub struct EventCorrelation {
     event_rules: HashMap<EventType, String>,
+    links: HashMap<EventType, Vec<String>>,
+    tenancy: Option<TenancyConfig>,
 }
 
 impl EventCorrelation {
     pub fn new(event_rules: HashMap<EventType, String>) -> Self {
-        Self { event_rules }
+        Self {
+            event_rules,
+            links: HashMap::new(),
+            tenancy: None,
+        }
+    }
+
+    /// Keys of other correlations that events of each type link into their own, merging the
+    /// two into one context
+    pub fn with_links(mut self, links: HashMap<EventType, Vec<String>>) -> Self {
+        self.links = links;
+        self
+    }
+
+    /// Scope correlation IDs to the tenant of each event
+    pub fn with_tenancy(mut self, tenancy: Option<TenancyConfig>) -> Self {
+        self.tenancy = tenancy;
+        self
+    }
+
+    pub(crate) fn tenancy(&self) -> Option<&TenancyConfig> {
+        self.tenancy.as_ref()
     }
 
     pub fn correlation_id(
@@ -63,15 +125,39 @@ impl EventCorrelation {
         event_type: &EventType,
         event: &RawEvent,
     ) -> LaikaResult<Option<CorrelationId>> {
-        if let Some(correlation_path) = self.event_rules.get(event_type) {
-            Ok(Some(
-                event
-                    .try_extract(correlation_path.as_str())
-                    .ok_or(LaikaError::EventMatchError)?
-                    .to_string(),
-            ))
-        } else {
-            Ok(None)
+        self.event_rules
+            .get(event_type)
+            .map(|correlation_path| self.extract_id(correlation_path, event))
+            .transpose()
+    }
+
+    /// Correlation IDs of the other correlations an event links into its own
+    pub fn linked_ids(
+        &self,
+        event_type: &EventType,
+        event: &RawEvent,
+    ) -> LaikaResult<Vec<CorrelationId>> {
+        self.links
+            .get(event_type)
+            .into_iter()
+            .flatten()
+            .map(|link_path| self.extract_id(link_path, event))
+            .collect()
+    }
+
+    /// ID of the correlation `path` leads to. Paths with wildcards or filters have to pick out
+    /// exactly one value, as an event can't belong to several correlations through one key.
+    fn extract_id(&self, path: &str, event: &RawEvent) -> LaikaResult<CorrelationId> {
+        let correlation_id = match extract_json_values(event.get_data(), path)?.as_slice() {
+            [value] => value.to_string(),
+            _ => return Err(LaikaError::EventMatchError),
+        };
+        match &self.tenancy {
+            Some(tenancy) => Ok(TenancyConfig::scope(
+                tenancy.tenant(event.get_data())?.as_str(),
+                correlation_id.as_str(),
+            )),
+            None => Ok(correlation_id),
         }
     }
 }
@@ -80,8 +166,23 @@ impl EventCorrelation {
 pub struct EventTrigger {
     requirement: Requirement,
     filter_and_extract: Option<String>, // JS Compatible Condition
+    output_schema: Option<OutputSchema>,
     timing: Option<TimingConfig>,
+    aggregate: Option<AggregateConfig>,
+    window: Option<WindowConfig>,
+    absence: Option<Absence>,
+    schedule: Option<ScheduleConfig>,
+    priority: i32,
+    stop_on_match: bool,
+    context: Option<ContextShape>,
+    tenants: Option<HashSet<String>>,
+    late: Option<LatePolicy>,
+    on: Option<TriggerScope>,
     action: ActionConfig,
+    on_timeout: Option<ActionConfig>,
+    escalation: Vec<Escalation>,
+    dedupe: Option<Duration>,
+    rollout: Option<Rollout>,
 }
 
 #[derive(Clone)]
@@ -100,34 +201,85 @@ pub enum MatchPattern {
 pub struct EventRuleDefinition {
     pub(crate) name: String,
     pub(crate) filter_and_extract: Option<String>,
+    pub(crate) output_schema: Option<OutputSchema>,
     pub(crate) timing: Option<TimingConfig>,
+    pub(crate) aggregate: Option<AggregateConfig>,
+    pub(crate) window: Option<WindowConfig>,
+    pub(crate) absence: Option<Absence>,
+    pub(crate) schedule: Option<ScheduleConfig>,
     pub(crate) requires: Option<Requirement>,
+    pub(crate) priority: i32,
+    pub(crate) stop_on_match: bool,
+    pub(crate) context: Option<ContextShape>,
+    pub(crate) tenants: Option<HashSet<String>>,
+    pub(crate) late: Option<LatePolicy>,
+    pub(crate) on: Option<TriggerScope>,
     pub(crate) action: ActionConfig,
+    pub(crate) on_timeout: Option<ActionConfig>,
+    pub(crate) escalation: Vec<Escalation>,
+    pub(crate) dedupe: Option<Duration>,
+    pub(crate) rollout: Option<Rollout>,
 }
 
 impl EventRuleDefinition {
-    pub fn register_to_engine(self, engine: &mut JsonPredicateEngine) -> EventRule {
-        let predicate: JsonPredicate = if let Some(ref provided_condition) = self.filter_and_extract
-        {
-            engine.store_predicate(&provided_condition)
-        } else {
-            engine.store_predicate(DEFAULT_PREDICATE)
-        };
-        EventRule {
+    /// Compile the rule's predicate, failing with its location in the config if it's invalid
+    pub fn register_to_engine(self, engine: &mut JsonPredicateEngine) -> LaikaResult<EventRule> {
+        let predicate: JsonPredicate = engine
+            .store_predicate(
+                self.filter_and_extract
+                    .as_deref()
+                    .unwrap_or(DEFAULT_PREDICATE),
+            )
+            .map_err(|e| {
+                LaikaError::config(
+                    format!("triggers.{}.filterAndExtract", self.name),
+                    e.to_string(),
+                )
+            })?;
+        Ok(EventRule {
             name: self.name,
             filter_and_extract: predicate,
+            output_schema: self.output_schema,
             timing: self.timing,
+            aggregate: self.aggregate,
+            window: self.window,
+            absence: self.absence,
+            schedule: self.schedule,
             requires: self.requires,
+            priority: self.priority,
+            stop_on_match: self.stop_on_match,
+            context: self.context,
+            tenants: self.tenants,
+            late: self.late,
+            on: self.on,
             action: self.action,
-        }
+            on_timeout: self.on_timeout,
+            escalation: self.escalation,
+            dedupe: self.dedupe,
+            rollout: self.rollout,
+        })
     }
 }
 
 pub struct EventProcessorConfigBuilder {
-    connections: HashMap<String, ConnectionConfig>,
+    connections: HashMap<String, ConnectionSpec>,
     correlation: Option<EventCorrelation>,
     event_matcher: Option<EventTypeDefinitions>,
     triggers: Option<HashMap<EventType, EventTrigger>>,
+    dead_letter: Option<DeadLetterConfig>,
+    storage: StorageConfig,
+    predicates: PredicateConfig,
+    lib: Vec<String>,
+    dispatch: DispatchConfig,
+    watermark: Option<WatermarkConfig>,
+    calendar: Option<CalendarConfig>,
+    telemetry: TelemetryConfig,
+    logging: LoggingConfig,
+    admin: Option<AdminConfig>,
+    coordination: Option<CoordinationConfig>,
+    partitioning: Option<PartitionConfig>,
+    size_limits: Option<SizeLimitsConfig>,
+    provenance: Option<Provenance>,
 }
 
 impl EventProcessorConfigBuilder {
@@ -137,10 +289,24 @@ impl EventProcessorConfigBuilder {
             correlation: None,
             event_matcher: None,
             triggers: None,
+            dead_letter: None,
+            storage: StorageConfig::default(),
+            predicates: PredicateConfig::default(),
+            lib: Vec::new(),
+            dispatch: DispatchConfig::default(),
+            watermark: None,
+            calendar: None,
+            telemetry: TelemetryConfig::default(),
+            logging: LoggingConfig::default(),
+            admin: None,
+            coordination: None,
+            partitioning: None,
+            size_limits: None,
+            provenance: None,
         }
     }
 
-    pub fn with_connections(mut self, connections: HashMap<String, ConnectionConfig>) -> Self {
+    pub fn with_connections(mut self, connections: HashMap<String, ConnectionSpec>) -> Self {
         self.connections = connections;
         self
     }
@@ -160,6 +326,83 @@ impl EventProcessorConfigBuilder {
         self
     }
 
+    pub fn with_dead_letter(mut self, dead_letter: Option<DeadLetterConfig>) -> Self {
+        self.dead_letter = dead_letter;
+        self
+    }
+
+    pub fn with_storage(mut self, storage: StorageConfig) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn with_predicates(mut self, predicates: PredicateConfig) -> Self {
+        self.predicates = predicates;
+        self
+    }
+
+    /// JS files to load before any predicate, read when the config is built
+    pub fn with_lib(mut self, lib: Vec<String>) -> Self {
+        self.lib = lib;
+        self
+    }
+
+    pub fn with_dispatch(mut self, dispatch: DispatchConfig) -> Self {
+        self.dispatch = dispatch;
+        self
+    }
+
+    /// How far events can arrive out of order, for event types that carry when they happened
+    pub fn with_watermark(mut self, watermark: Option<WatermarkConfig>) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    pub fn with_telemetry(mut self, telemetry: TelemetryConfig) -> Self {
+        self.telemetry = telemetry;
+        self
+    }
+
+    pub fn with_logging(mut self, logging: LoggingConfig) -> Self {
+        self.logging = logging;
+        self
+    }
+
+    pub fn with_admin(mut self, admin: Option<AdminConfig>) -> Self {
+        self.admin = admin;
+        self
+    }
+
+    /// Count business time, such as `2bd` or `4bh`, on `calendar` rather than Monday to Friday,
+    /// 09:00 to 17:00 UTC
+    pub fn with_calendar(mut self, calendar: Option<CalendarConfig>) -> Self {
+        self.calendar = calendar;
+        self
+    }
+
+    pub fn with_coordination(mut self, coordination: Option<CoordinationConfig>) -> Self {
+        self.coordination = coordination;
+        self
+    }
+
+    /// Split correlations between instances reading the same sources
+    pub fn with_partitioning(mut self, partitioning: Option<PartitionConfig>) -> Self {
+        self.partitioning = partitioning;
+        self
+    }
+
+    /// Cap the size of received messages and emitted payloads
+    pub fn with_size_limits(mut self, size_limits: Option<SizeLimitsConfig>) -> Self {
+        self.size_limits = size_limits;
+        self
+    }
+
+    /// Stamp emitted payloads with the rule that produced them
+    pub fn with_provenance(mut self, provenance: Option<Provenance>) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
     pub fn build(self) -> LaikaResult<EventProcessorConfig> {
         // Default event matcher if not provided
         let event_matcher = self.event_matcher.unwrap_or_default();
@@ -168,81 +411,384 @@ impl EventProcessorConfigBuilder {
         let triggers = self.triggers.unwrap_or_default();
 
         let get_connection =
-            |name: &str, connection_type: &str| -> LaikaResult<(String, ConnectionConfig)> {
+            |name: &str, connection_type: &str| -> LaikaResult<(String, ConnectionSpec)> {
                 let name_string = name.to_string();
                 self.connections
                     .get(name)
                     .ok_or_else(|| {
-                        LaikaError::Generic(format!(
-                            "Connection {} listed as a {} but not provided",
-                            name, connection_type
-                        ))
+                        LaikaError::config(
+                            format!("connections.{}", name),
+                            format!("Listed as a {} but not provided", connection_type),
+                        )
                     })
                     .map(|config| (name_string, config.clone()))
             };
 
-        let receiver_configs = event_matcher
-            .receivers()
-            .into_iter()
-            .map(|source_name| get_connection(&source_name, "source"))
-            .collect::<LaikaResult<HashMap<String, ConnectionConfig>>>()?;
-
         let target_names: HashSet<String> = triggers
             .values()
-            .map(|trigger| trigger.action.target.clone())
+            .filter_map(|trigger| trigger.action.target().map(str::to_string))
+            .chain(triggers.values().filter_map(|trigger| {
+                trigger
+                    .late
+                    .as_ref()
+                    .and_then(LatePolicy::target)
+                    .map(str::to_string)
+            }))
+            .chain(
+                self.dead_letter
+                    .as_ref()
+                    .map(|dead_letter| dead_letter.target.clone()),
+            )
             .collect();
 
-        let target_configs = target_names
+        let mut receivers = event_matcher.receivers();
+        if event_matcher.receives_from_any() {
+            // Event types from every source are received from each connection that isn't a target
+            receivers.extend(
+                self.connections
+                    .keys()
+                    .filter(|name| !target_names.contains(*name))
+                    .cloned(),
+            );
+        }
+        let receiver_configs = receivers
+            .into_iter()
+            .map(|source_name| get_connection(&source_name, "source"))
+            .map(|connection| {
+                let (name, spec) = connection?;
+                spec.receive.validate().map_err(|e| {
+                    LaikaError::config(format!("connections.{}", name), e.to_string())
+                })?;
+                Ok((name, spec))
+            })
+            .collect::<LaikaResult<HashMap<String, ConnectionSpec>>>()?;
+        if self.partitioning.is_some() {
+            // Messages for correlations another instance owns are skipped, so they'd be lost
+            // unless that instance receives them too
+            let mut shared: Vec<&String> = receiver_configs
+                .iter()
+                .filter(|(_, spec)| spec.config.shares_messages())
+                .map(|(name, _)| name)
+                .collect();
+            shared.sort();
+            if let Some(name) = shared.first() {
+                return Err(LaikaError::config(
+                    format!("connections.{}", name),
+                    "partitioning needs every instance to receive every message, but this \
+                     source shares its messages out between instances",
+                ));
+            }
+        }
+        let event_matcher = event_matcher.with_source_enrichment(
+            receiver_configs
+                .iter()
+                .filter(|(_, spec)| !spec.enrich.is_empty())
+                .map(|(name, spec)| (name.clone(), spec.enrich.clone()))
+                .collect(),
+        );
+
+        let target_specs = target_names
             .into_iter()
             .map(|target_name| get_connection(&target_name, "target"))
-            .collect::<LaikaResult<HashMap<String, ConnectionConfig>>>()?;
+            .collect::<LaikaResult<HashMap<String, ConnectionSpec>>>()?;
+        if self.dispatch.concurrency == 0 {
+            return Err(LaikaError::config(
+                "dispatch.concurrency",
+                "concurrency must be at least 1",
+            ));
+        }
+        if self.dispatch.max_pending() < self.dispatch.concurrency {
+            return Err(LaikaError::config(
+                "dispatch.maxPending",
+                "maxPending must be at least concurrency",
+            ));
+        }
+        let mut target_concurrency = HashMap::new();
+        let mut unordered_targets = HashSet::new();
+        let mut target_batching = HashMap::new();
+        for (name, spec) in &target_specs {
+            if spec.ordering == DeliveryOrdering::None {
+                unordered_targets.insert(name.clone());
+            }
+            if let Some(batching) = spec
+                .batching()
+                .map_err(|e| e.within(format!("connections.{}", name).as_str()))?
+            {
+                target_batching.insert(name.clone(), batching);
+            }
+            match spec.concurrency {
+                Some(0) => {
+                    return Err(LaikaError::config(
+                        format!("connections.{}.concurrency", name),
+                        "concurrency must be at least 1",
+                    ))
+                }
+                Some(concurrency) => {
+                    target_concurrency.insert(name.clone(), concurrency);
+                }
+                None => {}
+            }
+        }
+        let target_configs = target_specs
+            .into_iter()
+            .map(|(name, spec)| (name, spec.config))
+            .collect();
+
+        if self
+            .storage
+            .context_limits()
+            .is_some_and(|limits| limits.policy() == LimitPolicy::DeadLetter)
+            && self.dead_letter.is_none()
+        {
+            return Err(LaikaError::config(
+                "storage.contextLimits.policy",
+                "deadLetter needs a deadLetter target to be configured",
+            ));
+        }
+
+        // A standby taking over wouldn't see the wakeups the leader had pending
+        if self.coordination.is_some() && self.storage.local_timers() {
+            return Err(LaikaError::config(
+                "storage.timerPath",
+                "Wakeups aren't kept in Redis, so coordination needs a shared timerPath",
+            ));
+        }
+
+        let size_limits = self
+            .size_limits
+            .as_ref()
+            .map(|size_limits| size_limits.build(self.dead_letter.as_ref()))
+            .transpose()
+            .map_err(invalid_at("sizeLimits"))?;
+
+        let predicate_limits = self.predicates.limits().map_err(invalid_at("predicates"))?;
+        let libraries = self
+            .lib
+            .iter()
+            .map(|path| {
+                let source = std::fs::read_to_string(path).map_err(|e| {
+                    LaikaError::config("lib", format!("Could not read {}: {}", path, e))
+                })?;
+                if !path.ends_with(".ts") {
+                    return Ok((path.clone(), source));
+                }
+                transpile_typescript(path, source)
+                    .map(|source| (path.clone(), source))
+                    .map_err(|e| LaikaError::config("lib", format!("{}: {}", path, e)))
+            })
+            .collect::<LaikaResult<Vec<(String, String)>>>()?;
 
         let correlation_rules = self
             .correlation
             .unwrap_or_else(|| EventCorrelation::new(HashMap::new()));
 
+        // Wakeups only wait for event time when some events carry it
+        let watermark = match (&self.watermark, event_matcher.has_event_time()) {
+            (Some(_), false) => {
+                return Err(LaikaError::config(
+                    "watermark",
+                    "watermark needs an event type with eventTime",
+                ))
+            }
+            (watermark, true) => Some(
+                watermark
+                    .clone()
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(invalid_at("watermark"))?,
+            ),
+            (None, false) => None,
+        };
+
         Ok(EventProcessorConfig {
             receiver_configs,
             target_configs,
             correlation_rules,
             event_matcher,
             triggers,
+            dead_letter: self.dead_letter,
+            storage: self.storage,
+            predicate_limits,
+            libraries,
+            dispatch: self.dispatch,
+            target_concurrency,
+            unordered_targets,
+            target_batching,
+            watermark,
+            calendar: self
+                .calendar
+                .as_ref()
+                .map(CalendarConfig::parse)
+                .transpose()
+                .map_err(invalid_at("calendar"))?
+                .unwrap_or_default(),
+            telemetry: self.telemetry,
+            logging: self.logging,
+            admin: self.admin,
+            coordination: self.coordination,
+            partitioning: self
+                .partitioning
+                .as_ref()
+                .map(PartitionConfig::build)
+                .transpose()?,
+            size_limits,
+            provenance: self.provenance,
         })
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct EventProcessorConfig {
-    receiver_configs: HashMap<String, ConnectionConfig>,
+    receiver_configs: HashMap<String, ConnectionSpec>,
     target_configs: HashMap<String, ConnectionConfig>,
     correlation_rules: EventCorrelation,
     event_matcher: EventTypeDefinitions,
     triggers: HashMap<EventType, EventTrigger>,
+    dead_letter: Option<DeadLetterConfig>,
+    storage: StorageConfig,
+    predicate_limits: PredicateLimits,
+    /// Path and source of each JS file in `lib`
+    libraries: Vec<(String, String)>,
+    dispatch: DispatchConfig,
+    /// Limits on the actions in flight for each target that has one
+    target_concurrency: HashMap<String, usize>,
+    /// Targets whose actions are delivered in any order
+    unordered_targets: HashSet<String>,
+    /// Targets whose emits are grouped into batches
+    target_batching: HashMap<String, EmitBatching>,
+    watermark: Option<Watermark>,
+    calendar: BusinessCalendar,
+    telemetry: TelemetryConfig,
+    logging: LoggingConfig,
+    admin: Option<AdminConfig>,
+    coordination: Option<CoordinationConfig>,
+    partitioning: Option<Partitioning>,
+    size_limits: Option<SizeLimits>,
+    provenance: Option<Provenance>,
 }
 
 impl EventProcessorConfig {
+    /// Where to send messages that repeatedly fail to be handled, if anywhere
+    pub fn dead_letter(&self) -> Option<&DeadLetterConfig> {
+        self.dead_letter.as_ref()
+    }
+
+    /// Where and how state is kept
+    pub fn storage(&self) -> &StorageConfig {
+        &self.storage
+    }
+
+    /// Telemetry settings, if configured
+    pub fn telemetry(&self) -> &TelemetryConfig {
+        &self.telemetry
+    }
+
+    /// How logs are written
+    pub fn logging(&self) -> &LoggingConfig {
+        &self.logging
+    }
+
+    /// Metadata stamped onto emitted payloads, if they're stamped
+    pub fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+
+    /// Where the admin API listens, if it's enabled
+    pub fn admin(&self) -> Option<&AdminConfig> {
+        self.admin.as_ref()
+    }
+
+    /// How this instance decides whether it's active or standing by, when it has a standby
+    pub fn coordination(&self) -> Option<&CoordinationConfig> {
+        self.coordination.as_ref()
+    }
+
+    /// Dispatcher taking actions through `connections`, within the configured concurrency and
+    /// ordering
+    pub fn dispatcher(&self, connections: Arc<Connections>) -> Dispatcher {
+        let dispatcher = self.target_concurrency.iter().fold(
+            Dispatcher::new(connections, &self.dispatch),
+            |dispatcher, (target, concurrency)| {
+                dispatcher.with_target_concurrency(target, *concurrency)
+            },
+        );
+        let dispatcher = self
+            .unordered_targets
+            .iter()
+            .fold(dispatcher, |dispatcher, target| {
+                dispatcher.with_target_ordering(target, DeliveryOrdering::None)
+            });
+        self.target_batching
+            .iter()
+            .fold(dispatcher, |dispatcher, (target, batching)| {
+                dispatcher.with_target_batching(target, *batching)
+            })
+    }
+
     fn event_rules(&self) -> Vec<EventRuleDefinition> {
         let mut rules: Vec<EventRuleDefinition> = Vec::with_capacity(self.triggers.len());
         for (rule_name, trigger_config) in self.triggers.clone() {
             rules.push(EventRuleDefinition {
                 name: rule_name,
                 filter_and_extract: trigger_config.filter_and_extract,
+                output_schema: trigger_config.output_schema,
                 timing: trigger_config.timing,
+                aggregate: trigger_config.aggregate,
+                window: trigger_config.window,
+                absence: trigger_config.absence,
+                schedule: trigger_config.schedule,
                 requires: if trigger_config.requirement.is_empty() {
                     None
                 } else {
                     Some(trigger_config.requirement)
                 },
+                priority: trigger_config.priority,
+                stop_on_match: trigger_config.stop_on_match,
+                context: trigger_config.context,
+                tenants: trigger_config.tenants,
+                late: trigger_config.late,
+                on: trigger_config.on,
                 action: trigger_config.action,
+                on_timeout: trigger_config.on_timeout,
+                escalation: trigger_config.escalation,
+                dedupe: trigger_config.dedupe,
+                rollout: trigger_config.rollout,
             })
         }
         rules
     }
 
-    async fn targets(&self) -> Result<Vec<(String, Box<dyn EventSubmitter>)>, MessagingError> {
+    /// Internal connections, shared by their halves as a target and a source
+    fn internal_connections(&self) -> HashMap<String, InternalConnection> {
+        self.target_configs
+            .iter()
+            .chain(
+                self.receiver_configs
+                    .iter()
+                    .map(|(name, spec)| (name, &spec.config)),
+            )
+            .filter_map(|(name, config)| match config {
+                ConnectionConfig::Internal(internal) => Some((
+                    name.clone(),
+                    InternalConnection::new(name, internal.clone()),
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn targets(
+        &self,
+        registry: &ConnectionRegistry,
+        internal: &HashMap<String, InternalConnection>,
+    ) -> Result<Vec<(String, Box<dyn EventSubmitter>)>, MessagingError> {
         stream::iter(self.target_configs.clone())
             .then(|(target_name, target_config)| async move {
-                create_submitter(target_config)
+                if let Some(connection) = internal.get(&target_name) {
+                    let submitter: Box<dyn EventSubmitter> = Box::new(connection.submitter());
+                    return Ok((target_name, submitter));
+                }
+                create_submitter(target_config, registry)
                     .await
                     .and_then(|submitter| Ok((target_name, submitter)))
             })
@@ -252,28 +798,150 @@ impl EventProcessorConfig {
             .collect()
     }
 
-    async fn receivers(&self) -> Result<Vec<(String, Box<dyn EventReceiver>)>, MessagingError> {
+    async fn receivers(
+        &self,
+        registry: &ConnectionRegistry,
+        internal: &HashMap<String, InternalConnection>,
+    ) -> Result<Vec<(String, (Box<dyn EventReceiver>, ReceiveOptions))>, MessagingError> {
         stream::iter(self.receiver_configs.clone())
-            .then(|(receiver_name, receiver_config)| async move {
-                create_receiver(receiver_config)
+            .then(|(receiver_name, receiver_spec)| async move {
+                if let Some(connection) = internal.get(&receiver_name) {
+                    let receiver: Box<dyn EventReceiver> = Box::new(connection.receiver());
+                    return Ok((receiver_name, (receiver, receiver_spec.receive)));
+                }
+                create_receiver(receiver_spec.config, receiver_spec.encoding, registry)
                     .await
-                    .and_then(|submitter| Ok((receiver_name, submitter)))
+                    .and_then(|receiver| Ok((receiver_name, (receiver, receiver_spec.receive))))
             })
-            .collect::<Vec<Result<(String, Box<dyn EventReceiver>), MessagingError>>>()
+            .collect::<Vec<Result<(String, (Box<dyn EventReceiver>, ReceiveOptions)), MessagingError>>>()
             .await
             .into_iter()
             .collect()
     }
 
+    /// Names of the connections events are received from
+    pub fn source_names(&self) -> impl Iterator<Item = &str> {
+        self.receiver_configs.keys().map(String::as_str)
+    }
+
+    /// Names of the event types messages are matched against
+    pub fn event_types(&self) -> impl Iterator<Item = &str> {
+        self.event_matcher.event_types()
+    }
+
+    /// Names of the rules, one for each trigger
+    pub fn rule_names(&self) -> impl Iterator<Item = &str> {
+        self.triggers.keys().map(String::as_str)
+    }
+
+    /// Create connections for targets only, for when events are provided some other way
+    pub async fn target_connections(&self) -> Result<Connections, MessagingError> {
+        Ok(Connections::new(
+            HashMap::new(),
+            self.targets(&ConnectionRegistry::default(), &self.internal_connections())
+                .await?
+                .into_iter()
+                .collect(),
+        )
+        .with_size_limits(self.size_limits.clone()))
+    }
+
     pub async fn connections(&self) -> Result<Connections, MessagingError> {
+        self.connections_with_registry(&ConnectionRegistry::default())
+            .await
+    }
+
+    /// Create connections, using `registry` for any custom connection types
+    pub async fn connections_with_registry(
+        &self,
+        registry: &ConnectionRegistry,
+    ) -> Result<Connections, MessagingError> {
+        let internal = self.internal_connections();
         Ok(Connections::new(
-            self.receivers().await?.into_iter().collect(),
-            self.targets().await?.into_iter().collect(),
+            self.receivers(registry, &internal)
+                .await?
+                .into_iter()
+                .collect(),
+            self.targets(registry, &internal)
+                .await?
+                .into_iter()
+                .collect(),
+        )
+        .with_size_limits(self.size_limits.clone())
+        .with_dead_letter(
+            self.dead_letter
+                .as_ref()
+                .map(|dead_letter| dead_letter.target.clone()),
         ))
     }
 
-    pub fn build(self) -> EventProcessor {
+    /// Create the processor, failing if any `lib` file or trigger's `filterAndExtract` is invalid
+    pub fn build(self) -> LaikaResult<EventProcessor> {
         let rules = self.event_rules();
-        EventProcessor::new(self.event_matcher, self.correlation_rules, rules)
+        let mut engine = JsonPredicateEngine::with_limits(self.predicate_limits);
+        for (path, source) in &self.libraries {
+            engine
+                .load_library(source)
+                .map_err(|e| LaikaError::config("lib", format!("{}: {}", path, e)))?;
+        }
+        Ok(
+            EventProcessor::new(self.event_matcher, self.correlation_rules, rules, engine)?
+                .with_watermark(self.watermark)
+                .with_calendar(self.calendar)
+                .with_partitioning(self.partitioning)
+                .with_provenance(self.provenance),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::builder::EventProcessorYamlSpec;
+
+    #[test]
+    fn test_redis_storage_needs_shared_timers() {
+        let config = |timer_path: &str| {
+            let yaml = format!(
+                r#"
+connections:
+  orders:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+correlation:
+  placed:
+    key: "$.orderId"
+storage:
+  engine: redis
+  url: redis://state.internal
+  {}
+coordination:
+  type: redis
+  url: redis://state.internal
+triggers:
+  orderPlaced:
+    requires:
+      at_least: [placed]
+    action:
+      target: alerts
+      payload:
+        placed: true
+"#,
+                timer_path
+            );
+            EventProcessorConfig::try_from(&EventProcessorYamlSpec::from_yaml(&yaml).unwrap())
+        };
+        assert!(config("timerPath: /mnt/shared/laika-timers").is_ok());
+        // Wakeups left in the local dataDir wouldn't be seen by the standby
+        assert!(matches!(
+            config(""),
+            Err(LaikaError::ConfigError { path, .. }) if path == "storage.timerPath"
+        ));
     }
 }
@@ -0,0 +1,347 @@
+use crate::config::builder::{invalid_at, ActionConfigYaml, RequirementConfig, TriggerConfig};
+use crate::errors::{LaikaError, LaikaResult};
+use crate::utils::parse_time::parse_time_str;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashSet};
+
+/// States a correlation moves through as events arrive, compiled into triggers.
+///
+/// Each correlation starts in `initial`, and moves between states on the event types listed in
+/// `on`. Events that a state has no transition for are ignored.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateMachineConfig {
+    initial: String,
+    states: BTreeMap<String, StateConfig>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateConfig {
+    /// Event types that move a correlation out of this state
+    #[serde(default)]
+    on: BTreeMap<String, TransitionConfig>,
+    /// Action taken if a correlation stays in this state for too long
+    #[serde(default)]
+    timeout: Option<StateTimeoutConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum TransitionConfig {
+    To(String),
+    Transition {
+        to: String,
+        #[serde(default)]
+        action: Option<ActionConfigYaml>,
+    },
+}
+
+impl TransitionConfig {
+    fn to(&self) -> &str {
+        match self {
+            TransitionConfig::To(to) | TransitionConfig::Transition { to, .. } => to,
+        }
+    }
+
+    fn action(&self) -> Option<&ActionConfigYaml> {
+        match self {
+            TransitionConfig::To(_) => None,
+            TransitionConfig::Transition { action, .. } => action.as_ref(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateTimeoutConfig {
+    after: String,
+    action: ActionConfigYaml,
+}
+
+impl StateMachineConfig {
+    /// Triggers for the machine named `name`, one for each transition with an action and one
+    /// for each way into a state with a timeout. `correlated` are the event types that have a
+    /// correlation key, as a machine's state is kept per correlation.
+    pub(crate) fn compile(
+        &self,
+        name: &str,
+        correlated: &HashSet<String>,
+    ) -> LaikaResult<Vec<(String, TriggerConfig)>> {
+        self.validate(correlated)?;
+        let mut triggers = Vec::new();
+        for (state, config) in &self.states {
+            for (event_type, transition) in &config.on {
+                let Some(action) = transition.action() else {
+                    continue;
+                };
+                let predicate = self.predicate(&format!(
+                    r#"if (trigger.type !== "received_event" || trigger.eventType !== {event} || state !== {state}) return null;
+  return {{ machine: {machine}, from: {state}, to: {to}, event: trigger.event, events: ctx.events }};"#,
+                    event = json!(event_type),
+                    state = json!(state),
+                    machine = json!(name),
+                    to = json!(transition.to()),
+                ));
+                triggers.push((
+                    format!("{}.{}.{}", name, state, event_type),
+                    trigger(vec![event_type.clone()], predicate, action.clone()),
+                ));
+            }
+            let Some(timeout) = &config.timeout else {
+                continue;
+            };
+            let leaving = config
+                .on
+                .iter()
+                .filter(|(_, transition)| transition.to() != state)
+                .map(|(event_type, _)| event_type.clone())
+                .collect::<Vec<String>>();
+            // A correlation is timed from when it first enters the state by each event type, as
+            // the absence of the events leaving it
+            for entering in self.entering(state) {
+                let predicate = self.predicate(&format!(
+                    r#"if (trigger.type !== "timer_expired" || state !== {state} || entered !== {event}) return null;
+  return {{ machine: {machine}, state: {state}, events: ctx.events }};"#,
+                    event = json!(entering),
+                    state = json!(state),
+                    machine = json!(name),
+                ));
+                let mut timeout_trigger =
+                    trigger(vec![entering.clone()], predicate, timeout.action.clone());
+                timeout_trigger.absent = Some(leaving.clone());
+                timeout_trigger.within = Some(timeout.after.clone());
+                triggers.push((
+                    format!("{}.{}.timeout.{}", name, state, entering),
+                    timeout_trigger,
+                ));
+            }
+        }
+        Ok(triggers)
+    }
+
+    fn validate(&self, correlated: &HashSet<String>) -> LaikaResult<()> {
+        if !self.states.contains_key(&self.initial) {
+            return Err(LaikaError::config(
+                "initial",
+                format!("{} isn't one of the machine's states", self.initial),
+            ));
+        }
+        for (state, config) in &self.states {
+            for (event_type, transition) in &config.on {
+                let path = format!("states.{}.on.{}", state, event_type);
+                if !correlated.contains(event_type) {
+                    return Err(LaikaError::config(
+                        path,
+                        "transitions must be on correlated event types",
+                    ));
+                }
+                if !self.states.contains_key(transition.to()) {
+                    return Err(LaikaError::config(
+                        path,
+                        format!("{} isn't one of the machine's states", transition.to()),
+                    ));
+                }
+            }
+            if let Some(timeout) = &config.timeout {
+                let path = format!("states.{}.timeout", state);
+                let after = format!("{}.after", path);
+                parse_time_str(&timeout.after).map_err(invalid_at(&after))?;
+                if self.entering(state).is_empty() {
+                    return Err(LaikaError::config(
+                        path,
+                        "timeouts need a transition into the state",
+                    ));
+                }
+                if !config
+                    .on
+                    .values()
+                    .any(|transition| transition.to() != state)
+                {
+                    return Err(LaikaError::config(
+                        path,
+                        "timeouts need a transition out of the state",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Event types that move a correlation into `state` from another state
+    fn entering(&self, state: &str) -> Vec<String> {
+        let mut entering = self
+            .states
+            .iter()
+            .filter(|(from, _)| from.as_str() != state)
+            .flat_map(|(_, config)| config.on.iter())
+            .filter(|(_, transition)| transition.to() == state)
+            .map(|(event_type, _)| event_type.clone())
+            .collect::<Vec<String>>();
+        entering.sort();
+        entering.dedup();
+        entering
+    }
+
+    /// Predicate that replays the correlation's events through the machine, to find its
+    /// `state` and the event type it `entered` that state by, before running `body`
+    fn predicate(&self, body: &str) -> String {
+        let transitions = self
+            .states
+            .iter()
+            .map(|(state, config)| {
+                let on = config
+                    .on
+                    .iter()
+                    .map(|(event_type, transition)| (event_type.clone(), json!(transition.to())))
+                    .collect::<Map<String, Value>>();
+                (state.clone(), Value::Object(on))
+            })
+            .collect::<Map<String, Value>>();
+        format!(
+            r#"(trigger, ctx) => {{
+  const transitions = {transitions};
+  let state = {initial};
+  let entered = null;
+  for (const event of ctx.sequence) {{
+    const next = transitions[state][event.type];
+    if (next === undefined) continue;
+    if (next !== state) entered = event.type;
+    state = next;
+  }}
+  {body}
+}}"#,
+            transitions = Value::Object(transitions),
+            initial = json!(self.initial),
+        )
+    }
+}
+
+fn trigger(requires: Vec<String>, predicate: String, action: ActionConfigYaml) -> TriggerConfig {
+    TriggerConfig {
+        requires: Some(RequirementConfig::AtLeast { at_least: requires }),
+        filter_and_extract: Some(predicate),
+        output_schema: None,
+        timing: None,
+        aggregate: None,
+        window: None,
+        absent: None,
+        within: None,
+        schedule: None,
+        priority: 0,
+        stop_on_match: false,
+        context: None,
+        tenants: None,
+        late: None,
+        on: None,
+        action,
+        on_timeout: None,
+        escalate: Vec::new(),
+        dedupe: None,
+        rollout: None,
+        tests: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDER: &str = r#"
+initial: pending
+states:
+  pending:
+    on:
+      placed: placed
+  placed:
+    on:
+      paid:
+        to: paid
+        action:
+          target: outbox
+          payload:
+            to: "${{ to }}"
+      cancelled: cancelled
+    timeout:
+      after: 1h
+      action:
+        target: outbox
+        payload:
+          state: "${{ state }}"
+  paid:
+    on:
+      shipped: shipped
+    timeout:
+      after: 2d
+      action:
+        target: outbox
+        payload:
+          state: "${{ state }}"
+  shipped: {}
+  cancelled: {}
+"#;
+
+    fn correlated() -> HashSet<String> {
+        ["placed", "paid", "cancelled", "shipped"]
+            .into_iter()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_compile_triggers() {
+        let machine: StateMachineConfig = serde_yaml::from_str(ORDER).unwrap();
+        let triggers = machine.compile("order", &correlated()).unwrap();
+        let mut names = triggers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "order.paid.timeout.paid",
+                "order.placed.paid",
+                "order.placed.timeout.placed"
+            ]
+        );
+        let (_, timeout) = &triggers
+            .iter()
+            .find(|(name, _)| name == "order.placed.timeout.placed")
+            .unwrap();
+        assert_eq!(
+            timeout.absent,
+            Some(vec!["cancelled".to_string(), "paid".to_string()])
+        );
+        assert_eq!(timeout.within.as_deref(), Some("1h"));
+    }
+
+    #[test]
+    fn test_invalid_machine() {
+        let machine: StateMachineConfig =
+            serde_yaml::from_str(&ORDER.replace("cancelled: cancelled", "cancelled: refunded"))
+                .unwrap();
+        let err = machine.compile("order", &correlated()).err().unwrap();
+        assert!(err.to_string().contains("states.placed.on.cancelled"));
+
+        let machine: StateMachineConfig = serde_yaml::from_str(ORDER).unwrap();
+        let mut correlated = correlated();
+        correlated.remove("shipped");
+        let err = machine.compile("order", &correlated).err().unwrap();
+        assert!(err.to_string().contains("states.paid.on.shipped"));
+
+        let machine: StateMachineConfig =
+            serde_yaml::from_str(&ORDER.replace("initial: pending", "initial: placed")).unwrap();
+        let machine = StateMachineConfig {
+            states: machine
+                .states
+                .into_iter()
+                .filter(|(state, _)| state != "pending")
+                .collect(),
+            ..machine
+        };
+        let err = machine.compile("order", &correlated()).err().unwrap();
+        assert!(err.to_string().contains("states.placed.timeout"));
+    }
+}
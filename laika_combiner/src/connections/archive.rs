@@ -0,0 +1,274 @@
+use crate::connections::compression::Compression;
+use crate::connections::{secret, EventSubmitter, MessagingError};
+use crate::utils::parse_time::parse_time_str;
+use async_trait::async_trait;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use time::OffsetDateTime;
+use tokio::sync::{oneshot, Mutex};
+use url::Url;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Bucket and prefix to write under, e.g. `s3://bucket/laika/outputs`
+    url: String,
+    /// Store options such as region or credentials, by their `object_store` config keys
+    #[serde(default)]
+    options: HashMap<String, String>,
+    #[serde(default = "ArchiveConfig::default_compression")]
+    compression: Compression,
+    #[serde(default = "ArchiveConfig::default_batch_size")]
+    batch_size: usize,
+    #[serde(default = "ArchiveConfig::default_flush_interval")]
+    flush_interval: String,
+}
+
+impl ArchiveConfig {
+    fn default_compression() -> Compression {
+        Compression::Gzip
+    }
+
+    fn default_batch_size() -> usize {
+        1000
+    }
+
+    fn default_flush_interval() -> String {
+        "5m".to_string()
+    }
+}
+
+/// Object name for a batch written at `at`, partitioned by hour
+fn object_path(prefix: &Path, at: OffsetDateTime, compression: Compression) -> Path {
+    let extension = match compression.extension() {
+        Some(extension) => format!("jsonl.{}", extension),
+        None => "jsonl".to_string(),
+    };
+    let name = format!(
+        "{:04}/{:02}/{:02}/{:02}/{}.{}",
+        at.year(),
+        u8::from(at.month()),
+        at.day(),
+        at.hour(),
+        uuid::Uuid::new_v4(),
+        extension
+    );
+    Path::from_iter(prefix.parts().chain(Path::from(name).parts()))
+}
+
+/// Payloads waiting to be written, with whoever is waiting on each
+#[derive(Default)]
+struct PendingPayloads {
+    payloads: Vec<Value>,
+    waiting: Vec<oneshot::Sender<Result<(), String>>>,
+}
+
+/// Payloads waiting to be written, along with where to write them
+struct Batch {
+    store: Box<dyn ObjectStore>,
+    prefix: Path,
+    compression: Compression,
+    pending: Mutex<PendingPayloads>,
+    /// Set once nothing more will be waited on to fill a batch, as when shutting down
+    closing: AtomicBool,
+}
+
+impl Batch {
+    /// Write all buffered payloads as a single JSON Lines object, letting whoever is waiting on
+    /// them know how it went. Payloads are dropped from the buffer either way, so the buffer never
+    /// holds more than a batch, and a failed batch is retried by retrying its messages.
+    async fn flush(&self) -> Result<(), MessagingError> {
+        let mut pending = self.pending.lock().await;
+        if pending.payloads.is_empty() {
+            return Ok(());
+        }
+        let PendingPayloads { payloads, waiting } = std::mem::take(&mut *pending);
+        let result = self.write(payloads).await.map_err(|e| e.to_string());
+        for waiting in waiting {
+            let _ = waiting.send(result.clone());
+        }
+        result.map_err(MessagingError::SubmissionError)
+    }
+
+    async fn write(&self, payloads: Vec<Value>) -> Result<(), MessagingError> {
+        let mut lines = Vec::new();
+        for payload in &payloads {
+            serde_json::to_writer(&mut lines, payload)?;
+            lines.push(b'\n');
+        }
+        let body = self.compression.compress(lines)?;
+        let path = object_path(&self.prefix, OffsetDateTime::now_utc(), self.compression);
+        self.store
+            .put(&path, PutPayload::from(body))
+            .await
+            .map_err(|e| MessagingError::SubmissionError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Add payloads to the batch, returning once the batch they're in has been written
+    async fn add(&self, payloads: Vec<Value>, batch_size: usize) -> Result<(), MessagingError> {
+        let (done, written) = oneshot::channel();
+        let full = {
+            let mut pending = self.pending.lock().await;
+            pending.payloads.extend(payloads);
+            pending.waiting.push(done);
+            pending.payloads.len() >= batch_size
+        };
+        if full || self.closing.load(Ordering::SeqCst) {
+            // The outcome comes back through `written`, as it does for the rest of the batch
+            let _ = self.flush().await;
+        }
+        written
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            .map_err(MessagingError::SubmissionError)
+    }
+}
+
+/// Archives payloads to S3, GCS or Azure Blob Storage as JSON Lines objects.
+///
+/// Payloads are written in batches, once `batchSize` are buffered or every `flushInterval`,
+/// whichever is sooner, and submissions only return once their batch has been written. Objects are named `<prefix>/yyyy/mm/dd/hh/<uuid>.jsonl.gz` by the hour
+/// they're written in, with `.zst` rather than `.gz` for zstd and no extension uncompressed.
+pub struct ArchiveSink {
+    url: String,
+    batch_size: usize,
+    batch: Arc<Batch>,
+}
+
+impl Debug for ArchiveSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ArchiveSink")
+            .field("url", &self.url)
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+impl ArchiveSink {
+    pub fn new(config: ArchiveConfig) -> Result<Self, MessagingError> {
+        let flush_interval = parse_time_str(config.flush_interval.as_str())
+            .map_err(|e| MessagingError::ConfigError(e.to_string()))?
+            .unsigned_abs();
+        if config.batch_size == 0 {
+            return Err(MessagingError::ConfigError(
+                "Archive batchSize must be at least 1".to_string(),
+            ));
+        }
+        let url = Url::parse(config.url.as_str())
+            .map_err(|e| MessagingError::ConfigError(format!("Invalid archive URL: {}", e)))?;
+        let options = config
+            .options
+            .iter()
+            .map(|(key, value)| secret(value).map(|value| (key.clone(), value)))
+            .collect::<Result<HashMap<String, String>, MessagingError>>()?;
+        let (store, prefix) = object_store::parse_url_opts(&url, options)
+            .map_err(|e| MessagingError::ConfigError(e.to_string()))?;
+        let batch = Arc::new(Batch {
+            store,
+            prefix,
+            compression: config.compression,
+            pending: Mutex::new(PendingPayloads::default()),
+            closing: AtomicBool::new(false),
+        });
+
+        let flushed_batch: Weak<Batch> = Arc::downgrade(&batch);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+            loop {
+                interval.tick().await;
+                // Stop once the sink has been dropped
+                let Some(batch) = flushed_batch.upgrade() else {
+                    break;
+                };
+                if let Err(e) = batch.flush().await {
+                    tracing::error!("Could not write batch to archive: {}", e);
+                }
+            }
+        });
+
+        Ok(Self {
+            url: config.url,
+            batch_size: config.batch_size,
+            batch,
+        })
+    }
+}
+
+#[async_trait]
+impl EventSubmitter for ArchiveSink {
+    async fn submit(&self, payload: Value, _routing: Option<&str>) -> Result<(), MessagingError> {
+        self.batch.add(vec![payload], self.batch_size).await
+    }
+
+    async fn submit_batch(
+        &self,
+        payloads: Vec<Value>,
+        _routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        self.batch.add(payloads, self.batch_size).await
+    }
+
+    async fn close(&self) -> Result<(), MessagingError> {
+        self.batch.closing.store(true, Ordering::SeqCst);
+        self.batch.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use serde_json::json;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_object_path() {
+        let path = object_path(
+            &Path::from("laika/outputs"),
+            datetime!(2024-03-05 07:30 UTC),
+            Compression::Gzip,
+        );
+        let path = path.to_string();
+        assert!(path.starts_with("laika/outputs/2024/03/05/07/"));
+        assert!(path.ends_with(".jsonl.gz"));
+    }
+
+    #[tokio::test]
+    async fn test_submissions_wait_for_their_batch() {
+        let config: ArchiveConfig = serde_yaml::from_str(
+            "url: \"memory:///outputs\"\ncompression: none\nbatchSize: 2\nflushInterval: 1h\n",
+        )
+        .unwrap();
+        let sink = ArchiveSink::new(config).unwrap();
+        let (first, second) = futures::join!(
+            sink.submit(json!({"n": 1}), None),
+            sink.submit(json!({"n": 2}), None)
+        );
+        first.unwrap();
+        second.unwrap();
+        // Closing writes out the half-full batch rather than leaving it waiting for the interval
+        let (third, closed) = futures::join!(sink.submit(json!({"n": 3}), None), sink.close());
+        third.unwrap();
+        closed.unwrap();
+        sink.submit(json!({"n": 4}), None).await.unwrap();
+
+        let objects: Vec<_> = sink.batch.store.list(None).try_collect().await.unwrap();
+        let mut lines = Vec::new();
+        for object in objects {
+            let body = sink.batch.store.get(&object.location).await.unwrap();
+            lines.push(String::from_utf8(body.bytes().await.unwrap().to_vec()).unwrap());
+        }
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec!["{\"n\":1}\n{\"n\":2}\n", "{\"n\":3}\n", "{\"n\":4}\n"]
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use crate::errors::{ErrorSource, LaikaError, LaikaResult};
+use crate::utils::env::resolve_secret;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Tokens are refreshed this long before they expire, so they don't expire in flight
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+/// How HTTP requests authenticate, as written in the config. Credentials are secrets, so they
+/// can be given as `${NAME}` references or `file:` paths rather than written into the config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HttpAuthConfig {
+    /// `Authorization: Bearer <token>`
+    Bearer { token: String },
+    /// `Authorization: Basic`, from a username and password
+    Basic { username: String, password: String },
+    /// Hex HMAC-SHA256 of the body as it's sent, keyed by `secret`, in `header`
+    Hmac {
+        secret: String,
+        #[serde(default = "HttpAuthConfig::default_signature_header")]
+        header: String,
+    },
+    /// Bearer tokens from an OAuth2 client credentials grant, fetched again before they expire
+    #[serde(rename = "oauth2", rename_all = "camelCase")]
+    OAuth2 {
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        #[serde(default)]
+        scope: Option<String>,
+    },
+}
+
+impl HttpAuthConfig {
+    fn default_signature_header() -> String {
+        "X-Signature".to_string()
+    }
+
+    /// Resolve the credentials, failing if a secret can't be read
+    pub fn resolve(&self) -> LaikaResult<HttpAuth> {
+        let secret = |field: &str, raw: &str| {
+            resolve_secret(raw).map_err(|e| LaikaError::config(field, e.to_string()))
+        };
+        Ok(match self {
+            HttpAuthConfig::Bearer { token } => HttpAuth::Bearer(secret("token", token)?),
+            HttpAuthConfig::Basic { username, password } => HttpAuth::Basic {
+                username: secret("username", username)?,
+                password: secret("password", password)?,
+            },
+            HttpAuthConfig::Hmac {
+                secret: key,
+                header,
+            } => HttpAuth::Hmac {
+                secret: secret("secret", key)?,
+                header: header.clone(),
+            },
+            HttpAuthConfig::OAuth2 {
+                token_url,
+                client_id,
+                client_secret,
+                scope,
+            } => HttpAuth::OAuth2(ClientCredentials {
+                token_url: secret("tokenUrl", token_url)?,
+                client_id: secret("clientId", client_id)?,
+                client_secret: secret("clientSecret", client_secret)?,
+                scope: scope.clone(),
+            }),
+        })
+    }
+}
+
+/// Resolved credentials for an HTTP request
+#[derive(Clone)]
+pub enum HttpAuth {
+    Bearer(String),
+    Basic { username: String, password: String },
+    Hmac { secret: String, header: String },
+    OAuth2(ClientCredentials),
+}
+
+impl Debug for HttpAuth {
+    // Only the kind of credentials is shown, never the secrets
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpAuth::Bearer(_) => f.write_str("Bearer"),
+            HttpAuth::Basic { username, .. } => {
+                f.debug_struct("Basic").field("username", username).finish()
+            }
+            HttpAuth::Hmac { header, .. } => {
+                f.debug_struct("Hmac").field("header", header).finish()
+            }
+            HttpAuth::OAuth2(credentials) => f.debug_tuple("OAuth2").field(credentials).finish(),
+        }
+    }
+}
+
+impl HttpAuth {
+    /// Signature of `body` for the HMAC header
+    pub(crate) fn signature(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(body);
+        format!("{:x}", mac.finalize().into_bytes())
+    }
+}
+
+/// An OAuth2 client, which tokens are fetched for
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct ClientCredentials {
+    token_url: String,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+}
+
+impl Debug for ClientCredentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientCredentials")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+struct CachedToken {
+    access_token: String,
+    /// When the token should be replaced, or `None` if it's kept until it's rejected
+    refresh_at: Option<Instant>,
+}
+
+/// Access tokens for OAuth2 clients, reused until they're close to expiring
+#[derive(Default)]
+pub(crate) struct TokenCache {
+    tokens: Mutex<HashMap<ClientCredentials, CachedToken>>,
+}
+
+impl Debug for TokenCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenCache").finish_non_exhaustive()
+    }
+}
+
+impl TokenCache {
+    /// A current token for `credentials`, fetching one if there isn't one
+    pub(crate) async fn token(
+        &self,
+        client: &reqwest::Client,
+        credentials: &ClientCredentials,
+    ) -> LaikaResult<String> {
+        // Held while fetching, so concurrent requests wait for one token rather than each fetching
+        let mut tokens = self.tokens.lock().await;
+        if let Some(cached) = tokens.get(credentials) {
+            if cached
+                .refresh_at
+                .is_none_or(|refresh_at| Instant::now() < refresh_at)
+            {
+                return Ok(cached.access_token.clone());
+            }
+        }
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+            expires_in: Option<u64>,
+        }
+        let fetch_failed = |retryable: bool, source: ErrorSource| LaikaError::SubmitError {
+            target: credentials.token_url.clone(),
+            retryable,
+            source,
+        };
+        let mut form = vec![("grant_type", "client_credentials")];
+        if let Some(scope) = &credentials.scope {
+            form.push(("scope", scope.as_str()));
+        }
+        let fetched_at = Instant::now();
+        let response: TokenResponse = client
+            .post(credentials.token_url.as_str())
+            .basic_auth(&credentials.client_id, Some(&credentials.client_secret))
+            .form(&form)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                let retryable = e.is_timeout()
+                    || e.is_connect()
+                    || e.status().is_some_and(|status| status.is_server_error());
+                fetch_failed(retryable, ErrorSource::new(e))
+            })?
+            .json()
+            .await
+            .map_err(|e| fetch_failed(false, ErrorSource::new(e)))?;
+        tracing::debug!("Fetched OAuth2 token from {}", credentials.token_url);
+        let refresh_at = response.expires_in.map(|expires_in| {
+            fetched_at + Duration::from_secs(expires_in).saturating_sub(TOKEN_EXPIRY_MARGIN)
+        });
+        tokens.insert(
+            credentials.clone(),
+            CachedToken {
+                access_token: response.access_token.clone(),
+                refresh_at,
+            },
+        );
+        Ok(response.access_token)
+    }
+
+    /// Forget the token for `credentials` once it's been rejected, so the next request fetches
+    /// another
+    pub(crate) async fn invalidate(&self, credentials: &ClientCredentials) {
+        self.tokens.lock().await.remove(credentials);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_signature() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            HttpAuth::signature("Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_credentials_resolved_from_env() {
+        std::env::set_var("LAIKA_TEST_CLIENT_SECRET", "s3cret");
+        let config: HttpAuthConfig = serde_yaml::from_str(
+            "type: oauth2\ntokenUrl: https://auth.example.com/token\nclientId: laika\nclientSecret: ${LAIKA_TEST_CLIENT_SECRET}",
+        )
+        .unwrap();
+        let HttpAuth::OAuth2(credentials) = config.resolve().unwrap() else {
+            panic!("Expected OAuth2 credentials");
+        };
+        assert_eq!(credentials.client_secret, "s3cret");
+        assert!(!format!("{:?}", credentials).contains("s3cret"));
+    }
+}
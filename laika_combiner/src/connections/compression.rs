@@ -0,0 +1,77 @@
+use flate2::write::GzEncoder;
+use serde::Deserialize;
+use std::io::Write;
+
+/// How payloads are compressed before they're submitted
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn compress(self, payload: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(payload),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&payload)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(payload.as_slice(), 0),
+        }
+    }
+
+    /// `Content-Encoding` of compressed payloads, as sent in HTTP headers and message properties
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Extension added to the names of files holding compressed payloads
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_compressed_payloads_round_trip() {
+        let payload = br#"{"userId": "user123", "amount": 100}"#.repeat(10);
+
+        let mut gzipped = String::new();
+        GzDecoder::new(
+            Compression::Gzip
+                .compress(payload.clone())
+                .unwrap()
+                .as_slice(),
+        )
+        .read_to_string(&mut gzipped)
+        .unwrap();
+        assert_eq!(gzipped.as_bytes(), payload.as_slice());
+
+        let zstd = Compression::Zstd.compress(payload.clone()).unwrap();
+        assert!(zstd.len() < payload.len());
+        assert_eq!(zstd::decode_all(zstd.as_slice()).unwrap(), payload);
+
+        assert_eq!(
+            Compression::None.compress(payload.clone()).unwrap(),
+            payload
+        );
+    }
+}
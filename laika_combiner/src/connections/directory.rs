@@ -0,0 +1,307 @@
+use crate::connections::{AckCallback, EventReceiver, MessagingError};
+use crate::errors::LaikaError;
+use async_trait::async_trait;
+use glob::Pattern;
+use notify::event::ModifyKind;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirectoryConfig {
+    path: String,
+    /// Glob that file names must match to be ingested
+    #[serde(default = "DirectoryConfig::default_pattern")]
+    pattern: String,
+    /// Subdirectory files are moved to once all their events have been processed
+    #[serde(default = "DirectoryConfig::default_archive")]
+    archive: String,
+    /// Subdirectory files are moved to when they can't be read
+    #[serde(default = "DirectoryConfig::default_failed")]
+    failed: String,
+}
+
+impl DirectoryConfig {
+    fn default_pattern() -> String {
+        "*.json*".to_string()
+    }
+
+    fn default_archive() -> String {
+        "archive".to_string()
+    }
+
+    fn default_failed() -> String {
+        "failed".to_string()
+    }
+}
+
+/// Files being ingested, so a file seen more than once, such as both on startup and by the
+/// watcher, is only ingested once
+type Claimed = Arc<Mutex<HashSet<PathBuf>>>;
+
+/// A file whose events are being processed
+#[derive(Debug)]
+struct IngestedFile {
+    path: PathBuf,
+    archive: PathBuf,
+    claimed: Claimed,
+    /// Events from the file that haven't been acked yet
+    remaining: AtomicUsize,
+}
+
+/// Move `path` into `directory`, keeping its name
+async fn move_into(path: &Path, directory: &Path) -> std::io::Result<()> {
+    let name = path.file_name().unwrap_or_default();
+    tokio::fs::rename(path, directory.join(name)).await
+}
+
+/// Move a claimed file into `directory`, then release it so a file added later under the same
+/// name is ingested
+async fn release(claimed: &Claimed, path: &Path, directory: &Path) -> std::io::Result<()> {
+    let mut claimed = claimed.lock().await;
+    move_into(path, directory).await?;
+    claimed.remove(path);
+    Ok(())
+}
+
+/// Archive the file once every event in it has been acked
+fn file_ack_callback(file: Arc<IngestedFile>) -> AckCallback {
+    Box::new(move || {
+        Box::pin(async move {
+            if file.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+                release(&file.claimed, &file.path, &file.archive)
+                    .await
+                    .map_err(|e| LaikaError::IO(e.to_string()))?;
+            }
+            Ok(())
+        })
+    })
+}
+
+/// Events within a file. JSON Lines files hold an event per line, and other files hold a single
+/// document, where arrays hold an event per element.
+fn parse_events(path: &Path, content: &str) -> Result<Vec<Value>, serde_json::Error> {
+    let is_lines = matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("jsonl" | "ndjson")
+    );
+    if is_lines {
+        return content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect();
+    }
+    match serde_json::from_str(content)? {
+        Value::Array(events) => Ok(events),
+        event => Ok(vec![event]),
+    }
+}
+
+/// Ingests files as they're added to a directory, including any already there on startup.
+///
+/// Files should be moved into the directory once fully written, as they're read as soon as
+/// they appear.
+pub struct DirectoryReceiver {
+    archive: PathBuf,
+    failed: PathBuf,
+    files: Mutex<mpsc::UnboundedReceiver<PathBuf>>,
+    claimed: Claimed,
+    /// Events from the current file that haven't been received yet
+    pending: Mutex<VecDeque<(Value, Arc<IngestedFile>)>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl std::fmt::Debug for DirectoryReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirectoryReceiver")
+            .field("archive", &self.archive)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
+
+impl DirectoryReceiver {
+    pub fn new(config: DirectoryConfig) -> Result<Self, MessagingError> {
+        let directory = PathBuf::from(&config.path);
+        let pattern = Pattern::new(config.pattern.as_str())
+            .map_err(|e| MessagingError::ConfigError(format!("Invalid file pattern: {}", e)))?;
+        let archive = directory.join(&config.archive);
+        let failed = directory.join(&config.failed);
+        std::fs::create_dir_all(&archive)?;
+        std::fs::create_dir_all(&failed)?;
+
+        let is_ingested = move |path: &Path| {
+            path.is_file()
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| pattern.matches(name))
+        };
+        let (sender, files) = mpsc::unbounded_channel();
+
+        let watched = sender.clone();
+        let watched_is_ingested = is_ingested.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                match event {
+                    Ok(event) => {
+                        // Files moved into the directory are renames rather than creations
+                        if !matches!(
+                            event.kind,
+                            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+                        ) {
+                            return;
+                        }
+                        for path in event.paths {
+                            if watched_is_ingested(&path) {
+                                // Only fails once the receiver has been dropped
+                                let _ = watched.send(path);
+                            }
+                        }
+                    }
+                    Err(e) => tracing::error!("Could not watch directory: {}", e),
+                }
+            })
+            .map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
+        watcher
+            .watch(&directory, RecursiveMode::NonRecursive)
+            .map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
+
+        // Files added while Laika wasn't running
+        let mut existing = std::fs::read_dir(&directory)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        existing.sort();
+        for path in existing.into_iter().filter(|path| is_ingested(path)) {
+            let _ = sender.send(path);
+        }
+
+        Ok(Self {
+            archive,
+            failed,
+            files: Mutex::new(files),
+            claimed: Claimed::default(),
+            pending: Mutex::new(VecDeque::new()),
+            _watcher: watcher,
+        })
+    }
+
+    /// Read the events from the next file, moving it to the failed directory if it can't be parsed
+    async fn ingest_next(&self) -> Result<VecDeque<(Value, Arc<IngestedFile>)>, MessagingError> {
+        let Some(path) = self.files.lock().await.recv().await else {
+            return Err(MessagingError::StreamFinished);
+        };
+        if !self.claimed.lock().await.insert(path.clone()) {
+            return Ok(VecDeque::new());
+        }
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            // Already ingested and moved on
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.claimed.lock().await.remove(&path);
+                return Ok(VecDeque::new());
+            }
+            Err(e) => {
+                self.claimed.lock().await.remove(&path);
+                return Err(e.into());
+            }
+        };
+        let events = match parse_events(&path, content.as_str()) {
+            Ok(events) => events,
+            Err(e) => {
+                tracing::warn!("Could not read events from {}: {}", path.display(), e);
+                release(&self.claimed, &path, &self.failed).await?;
+                return Ok(VecDeque::new());
+            }
+        };
+        if events.is_empty() {
+            release(&self.claimed, &path, &self.archive).await?;
+            return Ok(VecDeque::new());
+        }
+        let file = Arc::new(IngestedFile {
+            path,
+            archive: self.archive.clone(),
+            claimed: self.claimed.clone(),
+            remaining: AtomicUsize::new(events.len()),
+        });
+        Ok(events
+            .into_iter()
+            .map(|event| (event, file.clone()))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl EventReceiver for DirectoryReceiver {
+    async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+        let mut pending = self.pending.lock().await;
+        if pending.is_empty() {
+            *pending = self.ingest_next().await?;
+        }
+        Ok(pending
+            .pop_front()
+            .map(|(event, file)| (event, file_ack_callback(file))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_events() {
+        let lines =
+            parse_events(Path::new("events.jsonl"), "{\"id\": 1}\n\n{\"id\": 2}\n").unwrap();
+        assert_eq!(lines.len(), 2);
+        let array = parse_events(Path::new("events.json"), "[{\"id\": 1}, {\"id\": 2}]").unwrap();
+        assert_eq!(array.len(), 2);
+        let single = parse_events(Path::new("event.json"), "{\"id\": 1}").unwrap();
+        assert_eq!(single, vec![serde_json::json!({"id": 1})]);
+        assert!(parse_events(Path::new("event.json"), "{\"id\": ").is_err());
+    }
+    #[tokio::test]
+    async fn test_file_seen_twice_is_ingested_once() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("a.jsonl");
+        std::fs::write(&path, "{\"file\": \"a\"}\n{\"file\": \"a\"}\n").unwrap();
+        let receiver = DirectoryReceiver::new(
+            serde_yaml::from_str(&format!("path: {}", dir.path().display())).unwrap(),
+        )
+        .unwrap();
+        // Seen on startup, and again by the watcher once moved back in
+        let moved = dir.path().join("archive").join("a.jsonl");
+        std::fs::rename(&path, &moved).unwrap();
+        std::fs::rename(&moved, &path).unwrap();
+        // Queued behind the second sighting of the first file
+        let staging = tempfile::TempDir::new().unwrap();
+        std::fs::write(staging.path().join("b.jsonl"), "{\"file\": \"b\"}\n").unwrap();
+        std::fs::rename(staging.path().join("b.jsonl"), dir.path().join("b.jsonl")).unwrap();
+
+        let mut received = Vec::new();
+        let mut callbacks = Vec::new();
+        while received.last() != Some(&serde_json::json!({"file": "b"})) {
+            let next = tokio::time::timeout(Duration::from_secs(5), receiver.receive_one())
+                .await
+                .expect("Timed out waiting for the second file")
+                .unwrap();
+            if let Some((event, callback)) = next {
+                received.push(event);
+                callbacks.push(callback);
+            }
+        }
+        assert_eq!(received.len(), 3);
+        for callback in callbacks {
+            callback().await.unwrap();
+        }
+        assert!(dir.path().join("archive").join("a.jsonl").exists());
+        assert!(!path.exists());
+    }
+}
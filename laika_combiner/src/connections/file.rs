@@ -1,20 +1,65 @@
 use crate::connections::{
-    noop_ack_callback, AckCallback, EventReceiver, EventSubmitter, MessagingError, RoutingConfig,
+    noop_ack_callback, AckCallback, EventReceiver, EventSubmitter, MessagingError,
 };
+use crate::errors::LaikaError;
 use async_trait::async_trait;
 use serde_json::Value;
+use std::collections::BTreeSet;
 use std::fs::File;
-use std::path::Path;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs::File as TokioFile;
 use tokio::io::{
-    AsyncBufReadExt, AsyncWriteExt, BufReader as TokioBufReader, BufWriter as TokioBufWriter,
+    AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader as TokioBufReader,
+    BufWriter as TokioBufWriter,
 };
 use tokio::sync::Mutex;
 
+/// Read position within the file
+#[derive(Debug)]
+struct FileReader {
+    reader: TokioBufReader<TokioFile>,
+    /// Bytes read so far, including any partial line
+    offset: u64,
+    /// A line that's still being written, when following the file
+    partial: String,
+}
+
+/// Lines handed out but not yet acked, so the cursor only moves past a line once every line
+/// before it has been acked too
+#[derive(Debug, Default)]
+struct CursorAcks {
+    /// Offsets at the end of lines that haven't been acked
+    unacked: BTreeSet<u64>,
+    /// Offsets at the end of acked lines that an unacked line comes before
+    acked: BTreeSet<u64>,
+    /// Offset last saved to the cursor
+    saved: u64,
+}
+
+impl CursorAcks {
+    /// Ack the line ending at `offset`, returning the offset the cursor can move on to, if it
+    /// can move
+    fn ack(&mut self, offset: u64) -> Option<u64> {
+        self.unacked.remove(&offset);
+        self.acked.insert(offset);
+        let first_unacked = self.unacked.first().copied().unwrap_or(u64::MAX);
+        let contiguous = self.acked.range(..first_unacked).next_back().copied()?;
+        self.acked = self.acked.split_off(&(contiguous + 1));
+        (contiguous > self.saved).then_some(contiguous)
+    }
+}
+
 #[derive(Debug)]
 pub struct FileEventQueue {
     file_path: String,
-    reader: Mutex<TokioBufReader<TokioFile>>,
+    /// Wait for more lines at the end of the file, rather than finishing
+    follow: bool,
+    /// Sidecar file the offset of the last acked line is kept in
+    cursor: Option<PathBuf>,
+    acks: Arc<Mutex<CursorAcks>>,
+    reader: Mutex<FileReader>,
     writer: Mutex<TokioBufWriter<TokioFile>>,
 }
 
@@ -37,18 +82,94 @@ impl FileEventQueue {
 
         Ok(Self {
             file_path: file_path.to_string(),
-            reader: Mutex::new(TokioBufReader::new(reader_file)),
+            follow: false,
+            cursor: None,
+            acks: Arc::default(),
+            reader: Mutex::new(FileReader {
+                reader: TokioBufReader::new(reader_file),
+                offset: 0,
+                partial: String::new(),
+            }),
             writer: Mutex::new(TokioBufWriter::new(writer_file)),
         })
     }
+
+    /// Keep waiting for lines to be appended once the end of the file is reached
+    pub fn following(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Keep the read position in `cursor` as lines are acked, resuming from it if it exists
+    pub async fn with_cursor(mut self, cursor: &str) -> Result<Self, MessagingError> {
+        let cursor = PathBuf::from(cursor);
+        if cursor.exists() {
+            let saved = tokio::fs::read_to_string(&cursor).await?;
+            let offset: u64 = saved.trim().parse().map_err(|_| {
+                MessagingError::ConfigError(format!(
+                    "Cursor {} does not hold a file offset",
+                    cursor.display()
+                ))
+            })?;
+            let length = tokio::fs::metadata(&self.file_path).await?.len();
+            let state = self.reader.get_mut();
+            if offset > length {
+                // The file has been truncated or replaced since the cursor was saved
+                tracing::warn!(
+                    "Cursor for {} is past the end of the file, reading from the start",
+                    self.file_path
+                );
+            } else {
+                state.reader.seek(SeekFrom::Start(offset)).await?;
+                state.offset = offset;
+                self.acks.get_mut().saved = offset;
+            }
+        }
+        self.cursor = Some(cursor);
+        Ok(self)
+    }
+}
+
+/// Ack the line ending at `offset`, saving the cursor once every line up to it has been acked
+fn cursor_ack_callback(cursor: PathBuf, acks: Arc<Mutex<CursorAcks>>, offset: u64) -> AckCallback {
+    Box::new(move || {
+        Box::pin(async move {
+            // Held while saving, so an earlier offset can't be saved over a later one
+            let mut acks = acks.lock().await;
+            let Some(offset) = acks.ack(offset) else {
+                return Ok(());
+            };
+            // Write then rename, so a crash can't leave a partially written cursor
+            let staged = cursor.with_extension("tmp");
+            tokio::fs::write(&staged, offset.to_string())
+                .await
+                .map_err(|e| LaikaError::IO(e.to_string()))?;
+            tokio::fs::rename(&staged, &cursor)
+                .await
+                .map_err(|e| LaikaError::IO(e.to_string()))?;
+            acks.saved = offset;
+            Ok(())
+        })
+    })
 }
 
 #[async_trait]
 impl EventSubmitter for FileEventQueue {
-    async fn submit(&self, payload: Value) -> Result<(), MessagingError> {
-        let json_string = serde_json::to_string(&payload).map_err(MessagingError::Json)?;
+    async fn submit(&self, payload: Value, routing: Option<&str>) -> Result<(), MessagingError> {
+        let json_string = serde_json::to_vec(&payload).map_err(MessagingError::Json)?;
+        self.submit_encoded(json_string, "application/json", routing)
+            .await
+    }
+
+    /// Write the payload as a line. Binary payloads containing newlines can't be read back.
+    async fn submit_encoded(
+        &self,
+        payload: Vec<u8>,
+        _content_type: &str,
+        _routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
         let mut writer = self.writer.lock().await;
-        writer.write_all(json_string.as_bytes()).await?;
+        writer.write_all(&payload).await?;
         writer.write_all(b"\n").await?;
         writer.flush().await?;
         Ok(())
@@ -58,13 +179,118 @@ impl EventSubmitter for FileEventQueue {
 #[async_trait]
 impl EventReceiver for FileEventQueue {
     async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
-        let mut reader = self.reader.lock().await;
-        let mut line = String::new();
-        let bytes_read = reader.read_line(&mut line).await?;
-        if bytes_read == 0 {
-            return Err(MessagingError::StreamFinished);
+        let mut state = self.reader.lock().await;
+        let FileReader {
+            reader,
+            offset,
+            partial,
+        } = &mut *state;
+        // Blank lines are skipped straight over, so only the end of the file waits for more
+        let line = loop {
+            let bytes_read = reader.read_line(partial).await?;
+            *offset += bytes_read as u64;
+            if !partial.ends_with('\n') {
+                if self.follow {
+                    // Wait for the rest of the line to be written
+                    return Ok(None);
+                }
+                if partial.is_empty() {
+                    return Err(MessagingError::StreamFinished);
+                }
+            }
+            let line = std::mem::take(partial);
+            if !line.trim().is_empty() {
+                break line;
+            }
+        };
+        let callback = match &self.cursor {
+            Some(cursor) => {
+                self.acks.lock().await.unacked.insert(*offset);
+                cursor_ack_callback(cursor.clone(), self.acks.clone(), *offset)
+            }
+            None => noop_ack_callback(),
+        };
+        match serde_json::from_str(line.trim()) {
+            Ok(value) => Ok(Some((value, callback))),
+            Err(e) => Err(MessagingError::undecodable(
+                line.trim().as_bytes(),
+                e,
+                callback,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resume_from_cursor() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let events = dir.path().join("events.jsonl");
+        let cursor = dir.path().join("events.cursor");
+        std::fs::write(&events, "{\"id\": 1}\n{\"id\": 2}\n").unwrap();
+
+        let queue = FileEventQueue::new(events.to_str().unwrap())
+            .await
+            .unwrap()
+            .with_cursor(cursor.to_str().unwrap())
+            .await
+            .unwrap();
+        let (first, ack) = queue.receive_one().await.unwrap().unwrap();
+        assert_eq!(first["id"], 1);
+        ack().await.unwrap();
+        drop(queue);
+
+        let queue = FileEventQueue::new(events.to_str().unwrap())
+            .await
+            .unwrap()
+            .with_cursor(cursor.to_str().unwrap())
+            .await
+            .unwrap();
+        let (second, _) = queue.receive_one().await.unwrap().unwrap();
+        assert_eq!(second["id"], 2);
+        assert!(matches!(
+            queue.receive_one().await,
+            Err(MessagingError::StreamFinished)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_cursor_waits_for_earlier_acks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let events = dir.path().join("events.jsonl");
+        let cursor = dir.path().join("events.cursor");
+        std::fs::write(&events, "{\"id\": 1}\n\n{\"id\": 2}\n{\"id\": 3}\n").unwrap();
+
+        let queue = FileEventQueue::new(events.to_str().unwrap())
+            .await
+            .unwrap()
+            .with_cursor(cursor.to_str().unwrap())
+            .await
+            .unwrap();
+        let mut acks = Vec::new();
+        for id in 1..=3 {
+            // The blank line is read past rather than returned as nothing to receive
+            let (value, ack) = queue.receive_one().await.unwrap().unwrap();
+            assert_eq!(value["id"], id);
+            acks.push(ack);
         }
-        let value: Value = serde_json::from_str(line.trim())?;
-        Ok(Some((value, noop_ack_callback())))
+        let saved = || std::fs::read_to_string(&cursor).ok();
+        let mut acks = acks.into_iter();
+        let (first, second, third) = (
+            acks.next().unwrap(),
+            acks.next().unwrap(),
+            acks.next().unwrap(),
+        );
+
+        // The second line's ack can't move the cursor past the first, which hasn't been acked
+        second().await.unwrap();
+        assert_eq!(saved(), None);
+        first().await.unwrap();
+        assert_eq!(saved().as_deref(), Some("21"));
+        third().await.unwrap();
+        assert_eq!(saved().as_deref(), Some("31"));
     }
 }
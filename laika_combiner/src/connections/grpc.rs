@@ -0,0 +1,218 @@
+use crate::connections::{AckCallback, EventReceiver, MessagingError};
+use async_trait::async_trait;
+use proto::ingest_server::{Ingest, IngestServer};
+use proto::{PushEventAck, PushEventRequest};
+use serde::Deserialize;
+use serde_json::Value;
+use std::net::SocketAddr;
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+mod proto {
+    tonic::include_proto!("laika.ingest");
+}
+
+/// Events accepted from producers but not yet processed. Producers are held back by HTTP/2 flow
+/// control once this is full.
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// Address to serve on, e.g. `0.0.0.0:50051`
+    address: String,
+}
+
+type AckSender = mpsc::Sender<Result<PushEventAck, Status>>;
+
+/// An event received over a stream, along with where to send its ack
+struct PushedEvent {
+    id: u64,
+    value: Value,
+    acks: AckSender,
+}
+
+impl std::fmt::Debug for PushedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PushedEvent").field("id", &self.id).finish()
+    }
+}
+
+struct IngestService {
+    events: mpsc::Sender<PushedEvent>,
+}
+
+#[tonic::async_trait]
+impl Ingest for IngestService {
+    type PushEventsStream = ReceiverStream<Result<PushEventAck, Status>>;
+
+    async fn push_events(
+        &self,
+        request: Request<Streaming<PushEventRequest>>,
+    ) -> Result<Response<Self::PushEventsStream>, Status> {
+        let mut stream = request.into_inner();
+        let (acks, ack_stream) = mpsc::channel(CHANNEL_CAPACITY);
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            loop {
+                let request = match stream.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = acks.send(Err(status)).await;
+                        break;
+                    }
+                };
+                match serde_json::from_str::<Value>(request.payload.as_str()) {
+                    Ok(value) => {
+                        let event = PushedEvent {
+                            id: request.id,
+                            value,
+                            acks: acks.clone(),
+                        };
+                        if events.send(event).await.is_err() {
+                            let _ = acks
+                                .send(Err(Status::unavailable("Receiver has shut down")))
+                                .await;
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        let rejected = PushEventAck {
+                            id: request.id,
+                            accepted: false,
+                            error: e.to_string(),
+                        };
+                        if acks.send(Ok(rejected)).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(ack_stream)))
+    }
+}
+
+/// Serves the `Ingest` gRPC service, acking each pushed event on its stream once the
+/// `AckCallback` for it resolves.
+#[derive(Debug)]
+pub struct GrpcReceiver {
+    events: Mutex<mpsc::Receiver<PushedEvent>>,
+}
+
+impl GrpcReceiver {
+    pub fn new(config: GrpcConfig) -> Result<Self, MessagingError> {
+        let address: SocketAddr = config
+            .address
+            .parse()
+            .map_err(|e| MessagingError::ConfigError(format!("Invalid gRPC address: {}", e)))?;
+        let (sender, events) = mpsc::channel(CHANNEL_CAPACITY);
+        let service = IngestService { events: sender };
+        tokio::spawn(async move {
+            if let Err(e) = Server::builder()
+                .add_service(IngestServer::new(service))
+                .serve(address)
+                .await
+            {
+                tracing::error!("gRPC server failed: {}", e);
+            }
+        });
+        Ok(Self {
+            events: Mutex::new(events),
+        })
+    }
+}
+
+#[async_trait]
+impl EventReceiver for GrpcReceiver {
+    async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+        let Some(event) = self.events.lock().await.recv().await else {
+            return Err(MessagingError::StreamFinished);
+        };
+        let PushedEvent { id, value, acks } = event;
+        let callback: AckCallback = Box::new(move || {
+            Box::pin(async move {
+                let ack = PushEventAck {
+                    id,
+                    accepted: true,
+                    error: String::new(),
+                };
+                if acks.send(Ok(ack)).await.is_err() {
+                    // The producer has gone, so there's no one to confirm delivery to
+                    tracing::debug!("gRPC stream closed before event {} was acked", id);
+                }
+                Ok(())
+            })
+        });
+        Ok(Some((value, callback)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::ingest_client::IngestClient;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn request(id: u64, payload: &str) -> PushEventRequest {
+        PushEventRequest {
+            id,
+            payload: payload.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_config() {
+        let config: GrpcConfig = serde_yaml::from_str("address: 0.0.0.0:50051").unwrap();
+        assert_eq!(config.address, "0.0.0.0:50051");
+        assert!(serde_yaml::from_str::<GrpcConfig>("port: 50051").is_err());
+        let config: GrpcConfig = serde_yaml::from_str("address: localhost").unwrap();
+        assert!(matches!(
+            GrpcReceiver::new(config),
+            Err(MessagingError::ConfigError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pushed_events_are_acked() {
+        let address = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let receiver = GrpcReceiver::new(GrpcConfig {
+            address: address.to_string(),
+        })
+        .unwrap();
+        let mut client = loop {
+            match IngestClient::connect(format!("http://{}", address)).await {
+                Ok(client) => break client,
+                // The server is started in the background
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let requests = tokio_stream::iter(vec![
+            request(1, r#"{"orderId": 1}"#),
+            request(2, "not json"),
+        ]);
+        let mut acks = client.push_events(requests).await.unwrap().into_inner();
+
+        let (value, callback) = receiver.receive_one().await.unwrap().unwrap();
+        assert_eq!(value, json!({"orderId": 1}));
+        // Events that aren't JSON are rejected straight away, without being received
+        let rejected = acks.message().await.unwrap().unwrap();
+        assert_eq!(rejected.id, 2);
+        assert!(!rejected.accepted);
+        assert!(!rejected.error.is_empty());
+
+        // Accepted events are only acked once they've been processed
+        callback().await.unwrap();
+        let accepted = acks.message().await.unwrap().unwrap();
+        assert_eq!(accepted.id, 1);
+        assert!(accepted.accepted);
+        assert!(accepted.error.is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::connections::{
+    noop_ack_callback, AckCallback, EventReceiver, EventSubmitter, MessagingError,
+};
+use crate::event::EventMetadata;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+/// Metadata key holding how many times an event has looped back through internal connections
+pub const CHAIN_DEPTH_KEY: &str = "chainDepth";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct InternalConfig {
+    /// Times an event can loop back before what it leads to is dropped, so rules that emit to
+    /// each other can't loop forever
+    #[serde(default = "InternalConfig::default_max_depth")]
+    max_depth: u32,
+}
+
+impl InternalConfig {
+    fn default_max_depth() -> u32 {
+        8
+    }
+}
+
+impl Default for InternalConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: Self::default_max_depth(),
+        }
+    }
+}
+
+/// Payload emitted to an internal connection, with the depth of the event it'll become
+type Looped = (Value, u32);
+
+/// Loops payloads emitted to it back in as received events, so rules can be composed in stages.
+///
+/// The submitting and receiving halves are created together, for a connection that's both a
+/// target and a source.
+#[derive(Debug, Clone)]
+pub struct InternalConnection {
+    name: String,
+    max_depth: u32,
+    sender: mpsc::UnboundedSender<Looped>,
+    receiver: Arc<Mutex<mpsc::UnboundedReceiver<Looped>>>,
+}
+
+impl InternalConnection {
+    pub fn new(name: &str, config: InternalConfig) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            name: name.to_string(),
+            max_depth: config.max_depth,
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
+
+    pub fn submitter(&self) -> InternalSubmitter {
+        InternalSubmitter {
+            name: self.name.clone(),
+            max_depth: self.max_depth,
+            sender: self.sender.clone(),
+        }
+    }
+
+    pub fn receiver(&self) -> InternalReceiver {
+        InternalReceiver {
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InternalSubmitter {
+    name: String,
+    max_depth: u32,
+    // Unbounded, as the loop is drained by the same processing that fills it
+    sender: mpsc::UnboundedSender<Looped>,
+}
+
+#[async_trait]
+impl EventSubmitter for InternalSubmitter {
+    async fn submit(&self, payload: Value, routing: Option<&str>) -> Result<(), MessagingError> {
+        self.submit_emitted(payload, routing, 0).await
+    }
+
+    async fn submit_emitted(
+        &self,
+        payload: Value,
+        _routing: Option<&str>,
+        depth: u32,
+    ) -> Result<(), MessagingError> {
+        let depth = depth + 1;
+        if depth > self.max_depth {
+            tracing::error!(
+                "Dropping event looped back through {} {} times, past its maxDepth",
+                self.name,
+                depth
+            );
+            return Ok(());
+        }
+        if self.sender.send((payload, depth)).is_err() {
+            tracing::warn!(
+                "Dropping event emitted to {}, as it isn't read as a source",
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct InternalReceiver {
+    receiver: Arc<Mutex<mpsc::UnboundedReceiver<Looped>>>,
+}
+
+#[async_trait]
+impl EventReceiver for InternalReceiver {
+    async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+        Ok(self
+            .receive_with_metadata()
+            .await?
+            .map(|(payload, _, callback)| (payload, callback)))
+    }
+
+    async fn receive_with_metadata(
+        &self,
+    ) -> Result<Option<(Value, EventMetadata, AckCallback)>, MessagingError> {
+        let Some((payload, depth)) = self.receiver.lock().await.recv().await else {
+            return Err(MessagingError::StreamFinished);
+        };
+        let mut metadata = EventMetadata::new();
+        metadata.insert(CHAIN_DEPTH_KEY.to_string(), Value::from(depth));
+        // Looped events are held in memory, so there's nothing to acknowledge
+        Ok(Some((payload, metadata, noop_ack_callback())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_loops_back_with_depth() {
+        let connection = InternalConnection::new("classified", InternalConfig::default());
+        let (submitter, receiver) = (connection.submitter(), connection.receiver());
+        submitter
+            .submit_emitted(json!({"id": 1}), None, 2)
+            .await
+            .unwrap();
+        let (payload, metadata, _) = receiver.receive_with_metadata().await.unwrap().unwrap();
+        assert_eq!(payload, json!({"id": 1}));
+        assert_eq!(metadata[CHAIN_DEPTH_KEY], json!(3));
+    }
+
+    #[tokio::test]
+    async fn test_drops_past_max_depth() {
+        let connection = InternalConnection::new("classified", InternalConfig { max_depth: 1 });
+        let (submitter, receiver) = (connection.submitter(), connection.receiver());
+        submitter
+            .submit_emitted(json!({"id": 1}), None, 1)
+            .await
+            .unwrap();
+        submitter.submit(json!({"id": 2}), None).await.unwrap();
+        let (payload, metadata, _) = receiver.receive_with_metadata().await.unwrap().unwrap();
+        assert_eq!(payload, json!({"id": 2}));
+        assert_eq!(metadata[CHAIN_DEPTH_KEY], json!(1));
+    }
+}
@@ -1,19 +1,71 @@
+use crate::action::{EmitAction, HttpRequest};
+use crate::batching::EmitBatching;
+use crate::connections::archive::{ArchiveConfig, ArchiveSink};
+use crate::connections::auth::{HttpAuth, TokenCache};
+use crate::connections::compression::Compression;
+use crate::connections::directory::{DirectoryConfig, DirectoryReceiver};
 use crate::connections::file::FileEventQueue;
+#[cfg(feature = "grpc")]
+use crate::connections::grpc::{GrpcConfig, GrpcReceiver};
+use crate::connections::internal::InternalConfig;
+use crate::connections::mqtt::{MqttConfig, MqttConnection};
+use crate::connections::postgres::{PostgresConfig, PostgresSink};
 use crate::connections::rabbitmq::RabbitMqConnection;
+use crate::connections::stdin::StdinReceiver;
 use crate::connections::stdout::StdoutSubmitter;
-use crate::errors::{LaikaError, LaikaResult};
+use crate::connections::tls::{TlsConfig, TlsSetting};
+#[cfg(unix)]
+use crate::connections::unix::{UnixSocketConfig, UnixSocketReceiver, UnixSocketSubmitter};
+use crate::connections::zeromq::{ZmqConfig, ZmqReceiver, ZmqSubmitter};
+use crate::dead_letter::dead_letter;
+use crate::dispatcher::DeliveryOrdering;
+use crate::errors::{ErrorSource, LaikaError, LaikaResult};
+use crate::event::EventMetadata;
+use crate::format::{
+    to_avro, to_confluent_avro, to_text, AvroSchema, EmitFormat, EncodingConfig, PayloadDecoder,
+    SchemaRegistry,
+};
+use crate::size_limits::SizeLimits;
+use crate::utils::env::resolve_secret;
+use crate::utils::parse_time::parse_time_str;
+use apache_avro::Schema;
 use async_trait::async_trait;
-use futures::StreamExt;
-use serde::Deserialize;
+use futures::future::Either;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::future::Future;
 use std::pin::Pin;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tokio::sync::{mpsc, watch, Mutex, Notify};
+use tokio::task::JoinHandle;
 
+mod archive;
+pub mod auth;
+pub mod compression;
+mod directory;
 mod file;
+#[cfg(feature = "grpc")]
+mod grpc;
+pub mod internal;
+mod mqtt;
+mod postgres;
 mod rabbitmq;
+mod stdin;
 mod stdout;
+pub mod tls;
+#[cfg(unix)]
+mod unix;
+mod zeromq;
+
+/// How long to wait before polling a receiver again when it had nothing to return
+const EMPTY_POLL_DELAY: Duration = Duration::from_millis(50);
+
+/// How long an emit waits for others to be batched with it, for targets without a `maxDelay`
+const DEFAULT_MAX_BATCH_DELAY: Duration = Duration::from_millis(50);
 
 #[derive(Error, Debug)]
 pub enum MessagingError {
@@ -31,10 +83,66 @@ pub enum MessagingError {
     Json(#[from] serde_json::Error),
     #[error("Stream Finished")]
     StreamFinished,
+    #[error("Could not decode payload: {0}")]
+    Decode(String),
+    /// A message that was received but couldn't be decoded, handed back with its ack
+    #[error("Could not decode message: {reason}")]
+    Undecodable {
+        reason: String,
+        message: UndecodableMessage,
+    },
+}
+
+/// A received message that couldn't be decoded, kept so it can be dead-lettered or dropped and
+/// then acked, rather than failing the messages received alongside it
+pub struct UndecodableMessage {
+    /// The message as it was received, as text
+    pub raw: String,
+    pub callback: AckCallback,
+}
+
+impl Debug for UndecodableMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UndecodableMessage")
+            .field("raw", &self.raw)
+            .finish()
+    }
+}
+
+impl MessagingError {
+    /// Whether the failure is likely transient, rather than down to config or the payload
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            MessagingError::ConnectionError(_)
+                | MessagingError::ChannelError(_)
+                | MessagingError::SubmissionError(_)
+                | MessagingError::Io(_)
+        )
+    }
+
+    /// A message that couldn't be decoded for `reason`, acked with `callback` once it's been
+    /// dealt with
+    pub fn undecodable(raw: &[u8], reason: impl std::fmt::Display, callback: AckCallback) -> Self {
+        MessagingError::Undecodable {
+            reason: reason.to_string(),
+            message: UndecodableMessage {
+                raw: String::from_utf8_lossy(raw).into_owned(),
+                callback,
+            },
+        }
+    }
+}
+
+/// Resolve a credential given as a `file:` path or with `${NAME}` environment references,
+/// where it's needed rather than when the config is parsed, so it's never in the config's
+/// debug output
+pub(crate) fn secret(value: &str) -> Result<String, MessagingError> {
+    resolve_secret(value).map_err(|e| MessagingError::ConfigError(e.to_string()))
 }
 
 #[derive(Debug, Clone, Deserialize)]
-#[serde(tag = "type")]
+#[serde(tag = "type", deny_unknown_fields)]
 pub enum ConnectionConfig {
     #[serde(rename = "rabbitmq")]
     RabbitMQ {
@@ -43,31 +151,354 @@ pub enum ConnectionConfig {
         username: Option<String>,
         password: Option<String>,
         vhost: Option<String>,
+        /// Exchange to publish to, defaulting to the default exchange
+        exchange: Option<String>,
+        /// Routing key used when an action doesn't provide one
+        #[serde(rename = "routingKey")]
+        routing_key: Option<String>,
+        /// Connect over `amqps`, as `true` or the TLS settings to use
+        #[serde(default)]
+        tls: Option<TlsSetting>,
+        /// How published payloads are compressed, given as their `content_encoding`
+        #[serde(default)]
+        compression: Compression,
     },
     #[serde(rename = "stdout")]
     Stdout {},
+    /// JSON lines piped into the process
+    #[serde(rename = "stdin")]
+    Stdin {},
     #[serde(rename = "file")]
-    File { path: String },
+    File {
+        path: String,
+        /// Wait for new lines at the end of the file, rather than finishing
+        #[serde(default)]
+        follow: bool,
+        /// Sidecar file to keep the read position in, so reading resumes there after a restart
+        cursor: Option<String>,
+    },
+    #[serde(rename = "directory")]
+    Directory(DirectoryConfig),
+    #[serde(rename = "mqtt")]
+    Mqtt(MqttConfig),
+    #[serde(rename = "postgres")]
+    Postgres(PostgresConfig),
+    #[serde(rename = "zmq")]
+    Zmq(ZmqConfig),
+    /// Newline-delimited JSON over a Unix domain socket, for processes on the same host
+    #[cfg(unix)]
+    #[serde(rename = "unix")]
+    Unix(UnixSocketConfig),
+    #[serde(rename = "archive")]
+    Archive(ArchiveConfig),
+    /// Loops emitted payloads back in as events, for rules to be composed in stages
+    #[serde(rename = "internal")]
+    Internal(InternalConfig),
+    #[cfg(feature = "grpc")]
+    #[serde(rename = "grpc")]
+    Grpc(GrpcConfig),
+    /// A connection type registered with a `ConnectionRegistry`, configured by its other fields,
+    /// which are left to the registered type to check. See `ConnectionConfig::from_fields`.
+    #[serde(skip)]
+    Custom {
+        kind: String,
+        options: serde_yaml::Mapping,
+    },
+}
+
+/// Types of the connections Laika has built in, so any other type is taken to be registered with
+/// a `ConnectionRegistry`
+const BUILT_IN_TYPES: [&str; 12] = [
+    "rabbitmq",
+    "stdout",
+    "stdin",
+    "file",
+    "directory",
+    "mqtt",
+    "postgres",
+    "zmq",
+    "unix",
+    "archive",
+    "internal",
+    "grpc",
+];
+
+/// How messages are taken from a connection when it's used as a source
+impl ConnectionConfig {
+    /// A connection from the fields left once `ConnectionSpec` has taken its own. Built-in types
+    /// reject any they don't know, while every field of a custom type is passed on to it.
+    fn from_fields<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = serde_yaml::Mapping::deserialize(deserializer)?;
+        let custom = match fields.get("type") {
+            Some(serde_yaml::Value::String(kind)) if !BUILT_IN_TYPES.contains(&kind.as_str()) => {
+                Some(kind.clone())
+            }
+            _ => None,
+        };
+        let Some(kind) = custom else {
+            return Self::deserialize(serde_yaml::Value::Mapping(fields))
+                .map_err(serde::de::Error::custom);
+        };
+        fields.remove("type");
+        Ok(ConnectionConfig::Custom {
+            kind,
+            options: fields,
+        })
+    }
+
+    /// Whether instances reading from the connection share its messages out between them, as
+    /// competing consumers, rather than each receiving every message. Custom connection types
+    /// are taken not to.
+    pub fn shares_messages(&self) -> bool {
+        match self {
+            ConnectionConfig::File { .. } | ConnectionConfig::Custom { .. } => false,
+            ConnectionConfig::Mqtt(config) => config.shares_messages(),
+            ConnectionConfig::Zmq(config) => config.shares_messages(),
+            // Each instance has a stream, socket or loop of its own, is pushed to separately, or
+            // claims files before another instance can
+            ConnectionConfig::Stdin {}
+            | ConnectionConfig::Directory(_)
+            | ConnectionConfig::Internal(_) => true,
+            #[cfg(unix)]
+            ConnectionConfig::Unix(_) => true,
+            #[cfg(feature = "grpc")]
+            ConnectionConfig::Grpc(_) => true,
+            // Only ever targets
+            ConnectionConfig::RabbitMQ { .. }
+            | ConnectionConfig::Stdout {}
+            | ConnectionConfig::Postgres(_)
+            | ConnectionConfig::Archive(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ReceiveOptions {
+    /// Messages received ahead of processing. The source isn't read from while this many are
+    /// waiting, so slow processing holds back the source rather than buffering without limit.
+    #[serde(default = "ReceiveOptions::default_prefetch")]
+    pub prefetch: usize,
+    /// Most messages taken from the source for a single batch, so that a busy source can't crowd
+    /// out the others
+    #[serde(default = "ReceiveOptions::default_batch_size")]
+    pub batch_size: usize,
+}
+
+impl ReceiveOptions {
+    fn default_prefetch() -> usize {
+        100
+    }
+
+    fn default_batch_size() -> usize {
+        10
+    }
+
+    pub fn validate(&self) -> Result<(), MessagingError> {
+        if self.prefetch == 0 || self.batch_size == 0 {
+            return Err(MessagingError::ConfigError(
+                "prefetch and batchSize must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ReceiveOptions {
+    fn default() -> Self {
+        Self {
+            prefetch: Self::default_prefetch(),
+            batch_size: Self::default_batch_size(),
+        }
+    }
+}
+
+/// A connection as written in the config, along with the options for receiving from it
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConnectionSpec {
+    // Options must come first, so their fields aren't passed on to custom connection types
+    #[serde(flatten)]
+    pub receive: ReceiveOptions,
+    /// How payloads received from the connection are encoded
+    #[serde(default)]
+    pub encoding: EncodingConfig,
+    /// Fields merged into every event received from the connection, under `_enrich`
+    #[serde(default)]
+    pub enrich: serde_json::Map<String, serde_json::Value>,
+    /// Actions submitted to the connection at once, when it's a target, up to the overall
+    /// `dispatch.concurrency`
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// Emitted payloads grouped into a single submission at most, when it's a target
+    #[serde(default, rename = "maxBatch")]
+    pub max_batch: Option<usize>,
+    /// How long an emitted payload waits for others to be batched with it
+    #[serde(default, rename = "maxDelay")]
+    pub max_delay: Option<String>,
+    /// Whether actions for the same correlation are delivered to the connection in order
+    #[serde(default)]
+    pub ordering: DeliveryOrdering,
+    #[serde(flatten, deserialize_with = "ConnectionConfig::from_fields")]
+    pub config: ConnectionConfig,
+}
+
+impl ConnectionSpec {
+    /// How emits to the connection are batched, if they are
+    pub fn batching(&self) -> LaikaResult<Option<EmitBatching>> {
+        let Some(max_batch) = self.max_batch else {
+            if self.max_delay.is_some() {
+                return Err(LaikaError::config("maxDelay", "maxDelay needs maxBatch"));
+            }
+            return Ok(None);
+        };
+        if max_batch == 0 {
+            return Err(LaikaError::config(
+                "maxBatch",
+                "maxBatch must be at least 1",
+            ));
+        }
+        // Batches are submitted as one payload, which would loop back in as a single event
+        if matches!(self.config, ConnectionConfig::Internal(_)) {
+            return Err(LaikaError::config(
+                "maxBatch",
+                "Internal connections can't batch emits",
+            ));
+        }
+        let max_delay = match &self.max_delay {
+            Some(max_delay) => parse_time_str(max_delay)
+                .map_err(|e| LaikaError::config("maxDelay", e.to_string()))?
+                .unsigned_abs(),
+            None => DEFAULT_MAX_BATCH_DELAY,
+        };
+        Ok(Some(EmitBatching {
+            max_batch,
+            max_delay,
+        }))
+    }
+}
+
+/// Creates submitters and receivers for a custom connection type.
+///
+/// Options are the connection's fields in the config other than `type`, and can be deserialized
+/// into the factory's own config with `serde_yaml::from_value`.
+#[async_trait]
+pub trait ConnectionFactory: Send + Sync {
+    async fn create_submitter(
+        &self,
+        _options: serde_yaml::Value,
+    ) -> Result<Box<dyn EventSubmitter>, MessagingError> {
+        Err(MessagingError::ConfigError(
+            "Connection type does not support submitting".to_string(),
+        ))
+    }
+
+    async fn create_receiver(
+        &self,
+        _options: serde_yaml::Value,
+    ) -> Result<Box<dyn EventReceiver>, MessagingError> {
+        Err(MessagingError::ConfigError(
+            "Connection type does not support receiving".to_string(),
+        ))
+    }
+}
+
+/// Custom connection types, by the `type` they're configured with
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    factories: HashMap<String, Box<dyn ConnectionFactory>>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct RoutingConfig {
-    topic: String,
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a factory for connections configured with `type: <kind>`
+    pub fn register<F: ConnectionFactory + 'static>(mut self, kind: &str, factory: F) -> Self {
+        self.factories.insert(kind.to_string(), Box::new(factory));
+        self
+    }
+
+    fn factory(&self, kind: &str) -> Result<&dyn ConnectionFactory, MessagingError> {
+        self.factories
+            .get(kind)
+            .map(|factory| factory.as_ref())
+            .ok_or_else(|| {
+                MessagingError::ConfigError(format!("No connection type {} registered", kind))
+            })
+    }
 }
 
 #[async_trait]
 pub trait EventSubmitter: Send + Sync + Debug {
-    async fn submit(&self, payload: serde_json::Value) -> Result<(), MessagingError>;
+    /// Submit a payload, with a routing key or topic for submitters that support routing
+    async fn submit(
+        &self,
+        payload: serde_json::Value,
+        routing: Option<&str>,
+    ) -> Result<(), MessagingError>;
+
+    /// Submit a payload emitted by a rule, where `depth` is how many times the event that led to
+    /// it had looped back through internal connections
+    async fn submit_emitted(
+        &self,
+        payload: serde_json::Value,
+        routing: Option<&str>,
+        _depth: u32,
+    ) -> Result<(), MessagingError> {
+        self.submit(payload, routing).await
+    }
+
+    /// Submit several payloads at once, as configured by a target's `maxBatch`. Submitters
+    /// without a batch of their own submit them as a single JSON array.
+    async fn submit_batch(
+        &self,
+        payloads: Vec<serde_json::Value>,
+        routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        self.submit(serde_json::Value::Array(payloads), routing)
+            .await
+    }
+
+    /// Submit a payload that's already been encoded, such as plain text or Avro
+    async fn submit_encoded(
+        &self,
+        _payload: Vec<u8>,
+        _content_type: &str,
+        _routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        Err(MessagingError::ConfigError(
+            "Connection type only supports JSON payloads".to_string(),
+        ))
+    }
+
+    /// Write out anything buffered without waiting for the rest of its batch, along with anything
+    /// submitted from then on, as when shutting down
+    async fn close(&self) -> Result<(), MessagingError> {
+        Ok(())
+    }
 }
 
 #[async_trait]
 pub trait EventReceiver: Send + Sync + Debug {
     async fn receive_one(&self)
         -> Result<Option<(serde_json::Value, AckCallback)>, MessagingError>;
+
+    /// Receive a message along with its metadata, such as its headers or the topic it was
+    /// published to. Receivers without metadata give an empty map.
+    async fn receive_with_metadata(
+        &self,
+    ) -> Result<Option<(serde_json::Value, EventMetadata, AckCallback)>, MessagingError> {
+        Ok(self
+            .receive_one()
+            .await?
+            .map(|(payload, callback)| (payload, EventMetadata::new(), callback)))
+    }
 }
 
 pub async fn create_submitter(
     config: ConnectionConfig,
+    registry: &ConnectionRegistry,
 ) -> Result<Box<dyn EventSubmitter>, MessagingError> {
     match config {
         ConnectionConfig::RabbitMQ {
@@ -76,18 +507,67 @@ pub async fn create_submitter(
             username,
             password,
             vhost,
+            exchange,
+            routing_key,
+            tls,
+            compression,
         } => {
-            let submitter = RabbitMqConnection::new(host, port, username, password, vhost).await?;
+            let submitter = RabbitMqConnection::new(
+                host,
+                port,
+                username,
+                password,
+                vhost,
+                tls.as_ref().and_then(TlsSetting::config),
+            )
+            .await?
+            .with_routing(exchange, routing_key)
+            .with_compression(compression);
             Ok(Box::new(submitter))
         }
         ConnectionConfig::Stdout { .. } => Ok(Box::new(StdoutSubmitter::new()?)),
-        ConnectionConfig::File { path } => Ok(Box::new(FileEventQueue::new(&*path).await?)),
+        ConnectionConfig::Stdin { .. } => Err(MessagingError::ConfigError(
+            "Stdin connections can only be used as sources".to_string(),
+        )),
+        ConnectionConfig::File { path, .. } => Ok(Box::new(FileEventQueue::new(&*path).await?)),
+        ConnectionConfig::Directory(_) => Err(MessagingError::ConfigError(
+            "Directory connections can only be used as sources".to_string(),
+        )),
+        ConnectionConfig::Mqtt(config) => Ok(Box::new(MqttConnection::new(config, false).await?)),
+        ConnectionConfig::Postgres(config) => Ok(Box::new(PostgresSink::new(config).await?)),
+        ConnectionConfig::Zmq(config) => Ok(Box::new(ZmqSubmitter::new(config)?)),
+        #[cfg(unix)]
+        ConnectionConfig::Unix(config) => Ok(Box::new(UnixSocketSubmitter::new(config))),
+        ConnectionConfig::Archive(config) => Ok(Box::new(ArchiveSink::new(config)?)),
+        ConnectionConfig::Internal(_) => Err(MessagingError::ConfigError(
+            "Internal connections are only created along with the rest of a config's connections"
+                .to_string(),
+        )),
+        #[cfg(feature = "grpc")]
+        ConnectionConfig::Grpc(_) => Err(MessagingError::ConfigError(
+            "gRPC connections can only be used as sources".to_string(),
+        )),
+        ConnectionConfig::Custom { kind, options } => {
+            registry
+                .factory(kind.as_str())?
+                .create_submitter(serde_yaml::Value::Mapping(options))
+                .await
+        }
     }
 }
 
 pub async fn create_receiver(
     config: ConnectionConfig,
+    encoding: EncodingConfig,
+    registry: &ConnectionRegistry,
 ) -> Result<Box<dyn EventReceiver>, MessagingError> {
+    let decoder = PayloadDecoder::try_from(encoding)?;
+    if !decoder.is_json() && !matches!(config, ConnectionConfig::Mqtt(_) | ConnectionConfig::Zmq(_))
+    {
+        return Err(MessagingError::ConfigError(
+            "Only MQTT and ZeroMQ sources can receive non-JSON payloads".to_string(),
+        ));
+    }
     match config {
         ConnectionConfig::RabbitMQ {
             host,
@@ -95,18 +575,140 @@ pub async fn create_receiver(
             username,
             password,
             vhost,
+            ..
         } => {
             todo!()
         }
         ConnectionConfig::Stdout { .. } => unimplemented!(), // Cannot be implemented
-        ConnectionConfig::File { path } => Ok(Box::new(FileEventQueue::new(&*path).await?)),
+        ConnectionConfig::Stdin { .. } => Ok(Box::new(StdinReceiver::new())),
+        ConnectionConfig::File {
+            path,
+            follow,
+            cursor,
+        } => {
+            let mut receiver = FileEventQueue::new(&*path).await?.following(follow);
+            if let Some(cursor) = cursor {
+                receiver = receiver.with_cursor(&*cursor).await?;
+            }
+            Ok(Box::new(receiver))
+        }
+        ConnectionConfig::Directory(config) => Ok(Box::new(DirectoryReceiver::new(config)?)),
+        ConnectionConfig::Mqtt(config) => Ok(Box::new(
+            MqttConnection::new(config, true)
+                .await?
+                .with_decoder(decoder),
+        )),
+        ConnectionConfig::Postgres(_) => Err(MessagingError::ConfigError(
+            "Postgres connections can only be used as targets".to_string(),
+        )),
+        ConnectionConfig::Zmq(config) => {
+            Ok(Box::new(ZmqReceiver::new(config)?.with_decoder(decoder)))
+        }
+        #[cfg(unix)]
+        ConnectionConfig::Unix(config) => Ok(Box::new(UnixSocketReceiver::new(config)?)),
+        ConnectionConfig::Archive(_) => Err(MessagingError::ConfigError(
+            "Archive connections can only be used as targets".to_string(),
+        )),
+        ConnectionConfig::Internal(_) => Err(MessagingError::ConfigError(
+            "Internal connections are only created along with the rest of a config's connections"
+                .to_string(),
+        )),
+        #[cfg(feature = "grpc")]
+        ConnectionConfig::Grpc(config) => Ok(Box::new(GrpcReceiver::new(config)?)),
+        ConnectionConfig::Custom { kind, options } => {
+            registry
+                .factory(kind.as_str())?
+                .create_receiver(serde_yaml::Value::Mapping(options))
+                .await
+        }
+    }
+}
+
+/// A message, or the error that stopped a source, along with when it was read
+type Received = (
+    Instant,
+    Result<(serde_json::Value, EventMetadata, AckCallback), MessagingError>,
+);
+
+/// A message along with the source it was received from, as returned by `Connections::receive`
+type Message = (serde_json::Value, String, EventMetadata, AckCallback);
+
+/// Messages read ahead from a single source
+#[derive(Debug)]
+struct SourceQueue {
+    name: String,
+    batch_size: usize,
+    messages: mpsc::Receiver<Received>,
+    /// How long the oldest message in the last batch from this source had been queued
+    waited: Duration,
+    paused: watch::Receiver<bool>,
+}
+
+impl SourceQueue {
+    fn paused(&self) -> bool {
+        *self.paused.borrow()
     }
 }
 
+/// How far processing is behind a source
+#[derive(Debug, Clone)]
+pub struct SourceLag {
+    pub source: String,
+    /// Messages read from the source and waiting to be processed
+    pub queued: usize,
+    /// How long the oldest message in the last batch from the source had been waiting
+    pub waited: Duration,
+}
+
+/// Whether a connection is still in use, as reported by the admin API
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "role", rename_all = "camelCase")]
+pub enum ConnectionStatus {
+    Source {
+        name: String,
+        /// Whether the source is still being read from, rather than having stopped or failed
+        receiving: bool,
+        /// Whether reading from the source has been paused
+        paused: bool,
+        /// Messages read from the source and waiting to be processed
+        queued: usize,
+        /// How long the oldest message in the last batch from the source had been waiting, in
+        /// milliseconds
+        #[serde(rename = "waitedMs")]
+        waited_ms: u64,
+    },
+    Target {
+        name: String,
+    },
+}
+
 #[derive(Debug)]
 pub struct Connections {
-    receivers: HashMap<String, Box<dyn EventReceiver>>,
+    sources: Mutex<Vec<SourceQueue>>,
+    // Every source, including those no longer being received from
+    source_names: Vec<String>,
+    // Whether each source is paused, watched by its reader
+    paused: HashMap<String, watch::Sender<bool>>,
+    // Wakes a waiting receive when a source is paused or resumed
+    pause_changed: Notify,
+    // Tasks reading from each receiver into its queue
+    readers: Vec<JoinHandle<()>>,
     submitters: HashMap<String, Box<dyn EventSubmitter>>,
+    // Shared client for HTTP actions, which don't go through a named connection
+    http_client: reqwest::Client,
+    // Avro schemas looked up in schema registries, with their IDs
+    registry_schemas: Mutex<HashMap<SchemaRegistry, (u32, Schema)>>,
+    // Clients for HTTP actions with their own TLS settings
+    tls_http_clients: Mutex<HashMap<TlsConfig, reqwest::Client>>,
+    // OAuth2 access tokens for HTTP actions, shared between requests
+    oauth2_tokens: TokenCache,
+    // Caps on received and emitted payloads, if there are any
+    size_limits: Option<SizeLimits>,
+    // Target that received messages which can't be decoded are sent to
+    dead_letter: Option<String>,
+    // A source that failed while other sources' messages were being received, reported by the
+    // next receive
+    source_failure: Mutex<Option<LaikaError>>,
 }
 
 /// Immediately resolvable AckCallback.
@@ -117,45 +719,929 @@ pub fn noop_ack_callback() -> AckCallback {
 pub type AckCallback =
     Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = LaikaResult<()>> + Send>> + Send + Sync>;
 
+/// Read from a receiver until its queue is dropped or it fails, waiting while the queue is full
+/// or the source is paused. A message that can't be decoded doesn't stop the receiver.
+async fn read_source(
+    receiver: Box<dyn EventReceiver>,
+    queue: mpsc::Sender<Received>,
+    mut paused: watch::Receiver<bool>,
+) {
+    loop {
+        if paused.wait_for(|paused| !*paused).await.is_err() {
+            break;
+        }
+        let received = match receiver.receive_with_metadata().await {
+            Ok(Some(message)) => Ok(message),
+            Ok(None) => {
+                tokio::time::sleep(EMPTY_POLL_DELAY).await;
+                continue;
+            }
+            Err(e) => Err(e),
+        };
+        // Messages that couldn't be decoded are handed on, but the receiver can carry on
+        let failed =
+            matches!(&received, Err(e) if !matches!(e, MessagingError::Undecodable { .. }));
+        if queue.send((Instant::now(), received)).await.is_err() || failed {
+            break;
+        }
+    }
+}
+
 impl Connections {
-    /// Create a Connection object from available connections, as well as named receivers and targets
+    /// Create a Connection object from available connections, as well as named receivers and targets.
+    ///
+    /// Each receiver is read from on its own task, up to its `prefetch` ahead of processing.
     pub fn new(
-        receivers: HashMap<String, Box<dyn EventReceiver>>,
+        receivers: HashMap<String, (Box<dyn EventReceiver>, ReceiveOptions)>,
         submitters: HashMap<String, Box<dyn EventSubmitter>>,
     ) -> Self {
+        let mut sources = Vec::with_capacity(receivers.len());
+        let mut readers = Vec::with_capacity(receivers.len());
+        let mut paused = HashMap::with_capacity(receivers.len());
+        let mut source_names: Vec<String> = receivers.keys().cloned().collect();
+        source_names.sort();
+        for (name, (receiver, options)) in receivers {
+            let (queue, messages) = mpsc::channel(options.prefetch);
+            let (pause, paused_receiver) = watch::channel(false);
+            readers.push(tokio::spawn(read_source(
+                receiver,
+                queue,
+                paused_receiver.clone(),
+            )));
+            paused.insert(name.clone(), pause);
+            sources.push(SourceQueue {
+                name,
+                batch_size: options.batch_size,
+                messages,
+                waited: Duration::ZERO,
+                paused: paused_receiver,
+            });
+        }
         Self {
-            receivers,
+            sources: Mutex::new(sources),
+            source_names,
+            paused,
+            pause_changed: Notify::new(),
+            readers,
             submitters,
+            http_client: reqwest::Client::new(),
+            registry_schemas: Mutex::new(HashMap::new()),
+            tls_http_clients: Mutex::new(HashMap::new()),
+            oauth2_tokens: TokenCache::default(),
+            size_limits: None,
+            dead_letter: None,
+            source_failure: Mutex::new(None),
         }
     }
 
-    /// Submit a single message to a target
-    pub async fn submit_to(&self, target: &str, payload: serde_json::Value) -> LaikaResult<()> {
-        match self.submitters.get(target) {
-            None => Err(LaikaError::Generic(format!(
-                "Submitter not found for {}",
-                target
-            ))),
-            Some(submitter) => submitter.submit(payload).await.map_err(|e| {
-                LaikaError::Generic(format!("Could not submit due to {}", e.to_string()))
-            }),
-        }
+    /// Hold received messages and emitted payloads to `size_limits`
+    pub fn with_size_limits(mut self, size_limits: Option<SizeLimits>) -> Self {
+        self.size_limits = size_limits;
+        self
+    }
+
+    /// Send received messages that can't be decoded to `target`, rather than dropping them
+    pub fn with_dead_letter(mut self, target: Option<String>) -> Self {
+        self.dead_letter = target;
+        self
     }
 
-    /// Receive a batch of messages from available connections
-    /// Returns a Vec of (Payload, Message Source, Callback)
-    pub async fn receive(&self) -> LaikaResult<Vec<(serde_json::Value, String, AckCallback)>> {
-        futures::stream::iter(&self.receivers)
-            .filter_map(|(source, receiver)| async move {
-                match receiver.receive_one().await {
-                    Ok(Some((value, callback))) => Some(Ok((value, source.to_string(), callback))),
-                    Ok(None) => None,
-                    Err(e) => Some(Err(LaikaError::Generic(format!("{}", e.to_string())))),
+    /// Send a single HTTP request, failing on non-success responses
+    pub async fn request(&self, request: HttpRequest) -> LaikaResult<()> {
+        let target = format!("{} {}", request.method, request.url);
+        let client = match &request.tls {
+            None => self.http_client.clone(),
+            Some(tls) => {
+                let mut clients = self.tls_http_clients.lock().await;
+                match clients.get(tls) {
+                    Some(client) => client.clone(),
+                    None => {
+                        let client = tls.http_client().map_err(|e| LaikaError::SubmitError {
+                            target: target.clone(),
+                            retryable: false,
+                            source: ErrorSource::new(e),
+                        })?;
+                        clients.insert(tls.clone(), client.clone());
+                        client
+                    }
                 }
+            }
+        };
+        let mut builder = client.request(request.method.clone(), request.url.as_str());
+        for (name, value) in request.headers {
+            builder = builder.header(name, value);
+        }
+        let invalid = |source: ErrorSource| LaikaError::SubmitError {
+            target: target.clone(),
+            retryable: false,
+            source,
+        };
+        let body = match request.body {
+            Some(body) => {
+                let body = serde_json::to_vec(&body).map_err(|e| invalid(ErrorSource::new(e)))?;
+                builder = builder.header(reqwest::header::CONTENT_TYPE, "application/json");
+                if let Some(encoding) = request.compression.content_encoding() {
+                    builder = builder.header(reqwest::header::CONTENT_ENCODING, encoding);
+                }
+                Some(
+                    request
+                        .compression
+                        .compress(body)
+                        .map_err(|e| invalid(ErrorSource::new(e)))?,
+                )
+            }
+            None => None,
+        };
+        builder = match &request.auth {
+            None => builder,
+            Some(HttpAuth::Bearer(token)) => builder.bearer_auth(token),
+            Some(HttpAuth::Basic { username, password }) => {
+                builder.basic_auth(username, Some(password))
+            }
+            // Signed as sent, after compression
+            Some(HttpAuth::Hmac { secret, header }) => builder.header(
+                header.as_str(),
+                HttpAuth::signature(secret, body.as_deref().unwrap_or_default()),
+            ),
+            Some(HttpAuth::OAuth2(credentials)) => {
+                builder.bearer_auth(self.oauth2_tokens.token(&client, credentials).await?)
+            }
+        };
+        if let Some(body) = body {
+            builder = builder.body(body);
+        }
+        let response = builder.send().await.map_err(|e| LaikaError::SubmitError {
+            target: target.clone(),
+            retryable: e.is_timeout() || e.is_connect(),
+            source: ErrorSource::new(e),
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let mut retryable =
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+            if let (reqwest::StatusCode::UNAUTHORIZED, Some(HttpAuth::OAuth2(credentials))) =
+                (status, &request.auth)
+            {
+                // The token may have been revoked early, so another is fetched next time
+                self.oauth2_tokens.invalidate(credentials).await;
+                retryable = true;
+            }
+            return Err(LaikaError::SubmitError {
+                target,
+                retryable,
+                source: ErrorSource::message(format!("Returned {}", status)),
+            });
+        }
+        Ok(())
+    }
+
+    fn submitter(&self, target: &str) -> LaikaResult<&dyn EventSubmitter> {
+        self.submitters
+            .get(target)
+            .map(|submitter| submitter.as_ref())
+            .ok_or_else(|| LaikaError::SubmitError {
+                target: target.to_string(),
+                retryable: false,
+                source: ErrorSource::message("No connection with this name is configured"),
             })
-            .collect::<Vec<LaikaResult<(serde_json::Value, String, AckCallback)>>>()
+    }
+
+    /// Submit a single message to a target, with an optional routing key or topic
+    pub async fn submit_to(
+        &self,
+        target: &str,
+        payload: serde_json::Value,
+        routing: Option<&str>,
+    ) -> LaikaResult<()> {
+        self.submitter(target)?
+            .submit(payload, routing)
             .await
-            .into_iter()
+            .map_err(|e| LaikaError::SubmitError {
+                target: target.to_string(),
+                retryable: e.is_retryable(),
+                source: ErrorSource::new(e),
+            })
+    }
+
+    /// Submit several payloads to a target in one go, with an optional routing key or topic
+    pub async fn submit_batch(
+        &self,
+        target: &str,
+        payloads: Vec<serde_json::Value>,
+        routing: Option<&str>,
+    ) -> LaikaResult<()> {
+        self.submitter(target)?
+            .submit_batch(payloads, routing)
+            .await
+            .map_err(|e| LaikaError::SubmitError {
+                target: target.to_string(),
+                retryable: e.is_retryable(),
+                source: ErrorSource::new(e),
+            })
+    }
+
+    /// Have every target write out what it's buffered, and stop buffering, so submissions waiting
+    /// on a batch to fill can finish. Every target is closed, failing with the first that failed.
+    pub async fn close(&self) -> LaikaResult<()> {
+        let mut failure = None;
+        for (target, submitter) in &self.submitters {
+            if let Err(e) = submitter.close().await {
+                failure.get_or_insert(LaikaError::SubmitError {
+                    target: target.clone(),
+                    retryable: e.is_retryable(),
+                    source: ErrorSource::new(e),
+                });
+            }
+        }
+        failure.map_or(Ok(()), Err)
+    }
+
+    /// Encode an emitted payload in its format, and submit it to its target
+    #[tracing::instrument(
+        name = "emit",
+        parent = &action.span,
+        skip_all,
+        fields(target = action.target.as_str(), correlation_id = action.correlation_id.as_deref())
+    )]
+    pub async fn emit(&self, action: EmitAction) -> LaikaResult<()> {
+        let target = action.target.clone();
+        let routing = action.routing.clone();
+        let format = action.format.clone();
+        let depth = action.depth;
+        let Some(payload) = self.guard_emit(target.as_str(), action.payload()).await? else {
+            return Ok(());
+        };
+        let encoding_failed = |message: String| LaikaError::SubmitError {
+            target: target.clone(),
+            retryable: false,
+            source: ErrorSource::message(message),
+        };
+        let (encoded, content_type) = match format {
+            EmitFormat::Json | EmitFormat::CloudEvents { .. } => {
+                return self
+                    .submitter(target.as_str())?
+                    .submit_emitted(payload, routing.as_deref(), depth)
+                    .await
+                    .map_err(|e| LaikaError::SubmitError {
+                        target: target.clone(),
+                        retryable: e.is_retryable(),
+                        source: ErrorSource::new(e),
+                    });
+            }
+            EmitFormat::Text => (to_text(payload).into_bytes(), "text/plain"),
+            EmitFormat::Avro(AvroSchema::Inline(schema)) => (
+                to_avro(&schema, payload).map_err(encoding_failed)?,
+                "avro/binary",
+            ),
+            EmitFormat::Avro(AvroSchema::Registry(registry)) => {
+                let (schema_id, schema) = self.registry_schema(&registry).await?;
+                (
+                    to_confluent_avro(schema_id, &schema, payload).map_err(encoding_failed)?,
+                    "application/vnd.confluent.avro",
+                )
+            }
+        };
+        self.submitter(target.as_str())?
+            .submit_encoded(encoded, content_type, routing.as_deref())
+            .await
+            .map_err(|e| LaikaError::SubmitError {
+                target: target.clone(),
+                retryable: e.is_retryable(),
+                source: ErrorSource::new(e),
+            })
+    }
+
+    /// Hold an emitted payload to `maxEmitBytes`, returning it if it can still be submitted.
+    /// Oversized payloads are sent to the dead-letter target under the `deadLetter` policy, and
+    /// otherwise dropped.
+    pub(crate) async fn guard_emit(
+        &self,
+        target: &str,
+        mut payload: serde_json::Value,
+    ) -> LaikaResult<Option<serde_json::Value>> {
+        let Some(limits) = &self.size_limits else {
+            return Ok(Some(payload));
+        };
+        let Err(error) = limits.guard_emit(target, &mut payload) else {
+            return Ok(Some(payload));
+        };
+        match limits.dead_letter() {
+            Some(dead_letters) => {
+                tracing::error!("{}, sending it to {}", error, dead_letters);
+                self.submit_to(
+                    dead_letters,
+                    serde_json::json!({
+                        "target": target,
+                        "payload": payload,
+                        "error": error.to_string(),
+                        "failedAt": OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+                    }),
+                    None,
+                )
+                .await?;
+            }
+            None => tracing::error!("{}, dropping it", error),
+        }
+        Ok(None)
+    }
+
+    /// Hold received messages to `maxInboundBytes`, keeping those that can still be processed.
+    /// Oversized messages are acked rather than redelivered, as they'd be just as big next time,
+    /// once they've been sent to the dead-letter target or dropped.
+    async fn guard_inbound(&self, batch: Vec<Message>) -> LaikaResult<Vec<Message>> {
+        let Some(limits) = &self.size_limits else {
+            return Ok(batch);
+        };
+        let mut guarded = Vec::with_capacity(batch.len());
+        for (mut message, source, metadata, callback) in batch {
+            let Err(error) = limits.guard_inbound(source.as_str(), &mut message) else {
+                guarded.push((message, source, metadata, callback));
+                continue;
+            };
+            match limits.dead_letter() {
+                Some(target) => {
+                    tracing::error!("{}, sending it to {}", error, target);
+                    self.submit_to(target, dead_letter(&source, &message, &error, 1), None)
+                        .await?;
+                }
+                None => tracing::warn!("{}, dropping it", error),
+            }
+            callback().await?;
+        }
+        Ok(guarded)
+    }
+
+    /// The latest schema for a registry subject, and its ID, looked up once and then reused
+    async fn registry_schema(&self, registry: &SchemaRegistry) -> LaikaResult<(u32, Schema)> {
+        let mut schemas = self.registry_schemas.lock().await;
+        if let Some(schema) = schemas.get(registry) {
+            return Ok(schema.clone());
+        }
+        #[derive(Deserialize)]
+        struct LatestVersion {
+            id: u32,
+            schema: String,
+        }
+        let url = format!(
+            "{}/subjects/{}/versions/latest",
+            registry.url.trim_end_matches('/'),
+            registry.subject
+        );
+        let lookup_failed = |retryable: bool, message: String| LaikaError::SubmitError {
+            target: url.clone(),
+            retryable,
+            source: ErrorSource::message(message),
+        };
+        let latest: LatestVersion = self
+            .http_client
+            .get(url.as_str())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| lookup_failed(true, e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| lookup_failed(false, e.to_string()))?;
+        let schema = Schema::parse_str(latest.schema.as_str())
+            .map_err(|e| lookup_failed(false, e.to_string()))?;
+        schemas.insert(registry.clone(), (latest.id, schema.clone()));
+        Ok((latest.id, schema))
+    }
+
+    /// Stop reading from a source until it's resumed, without closing its connection, so its
+    /// messages wait upstream. Messages it has already read ahead are held back as well. Returns
+    /// whether there's a source by that name.
+    pub fn pause(&self, source: &str) -> bool {
+        self.set_paused(source, true)
+    }
+
+    /// Read from a paused source again, returning whether there's a source by that name
+    pub fn resume(&self, source: &str) -> bool {
+        self.set_paused(source, false)
+    }
+
+    pub fn paused(&self, source: &str) -> bool {
+        self.paused
+            .get(source)
+            .is_some_and(|paused| *paused.borrow())
+    }
+
+    fn set_paused(&self, source: &str, paused: bool) -> bool {
+        let Some(pause) = self.paused.get(source) else {
+            return false;
+        };
+        pause.send_replace(paused);
+        self.pause_changed.notify_waiters();
+        true
+    }
+
+    /// Receive a batch of messages from available connections, waiting until there's at least one.
+    /// Each source contributes up to its `batchSize` messages, and sources take turns being first in
+    /// the batch. Paused sources are skipped.
+    /// Returns a Vec of (Payload, Message Source, Metadata, Callback)
+    pub async fn receive(
+        &self,
+    ) -> LaikaResult<Vec<(serde_json::Value, String, EventMetadata, AckCallback)>> {
+        if let Some(failure) = self.source_failure.lock().await.take() {
+            return Err(failure);
+        }
+        let mut sources = self.sources.lock().await;
+        let (first, first_source) = loop {
+            if sources.is_empty() {
+                return Ok(Vec::new());
+            }
+            // Registered before checking which sources are paused, so no change is missed
+            let pause_changed = self.pause_changed.notified();
+            let (indices, waiting): (Vec<usize>, Vec<_>) = sources
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, source)| !source.paused())
+                .map(|(index, source)| (index, Box::pin(source.messages.recv())))
+                .unzip();
+            if waiting.is_empty() {
+                pause_changed.await;
+                continue;
+            }
+            let (received, index) = match futures::future::select(
+                futures::future::select_all(waiting),
+                Box::pin(pause_changed),
+            )
+            .await
+            {
+                Either::Left(((received, index, _), _)) => (received, indices[index]),
+                Either::Right(_) => continue,
+            };
+            match received {
+                Some(received) => break (received, index),
+                // The source's reader has stopped, and everything it read has been processed
+                None => {
+                    sources.remove(index);
+                }
+            }
+        };
+
+        let mut first = Some(first);
+        let mut batch: Vec<(String, Result<_, MessagingError>)> = Vec::new();
+        let now = Instant::now();
+        for (index, source) in sources.iter_mut().enumerate() {
+            let mut taken = 0;
+            source.waited = Duration::ZERO;
+            while taken < source.batch_size {
+                let received = match first.take_if(|_| index == first_source) {
+                    Some(received) => received,
+                    // Paused sources only give the message that was waiting when they paused
+                    None if source.paused() => break,
+                    None => match source.messages.try_recv() {
+                        Ok(received) => received,
+                        Err(_) => break,
+                    },
+                };
+                let (read_at, received) = received;
+                if taken == 0 {
+                    source.waited = now.saturating_duration_since(read_at);
+                }
+                batch.push((source.name.clone(), received));
+                taken += 1;
+            }
+        }
+        // Start the next batch from the following source
+        sources.rotate_left(1);
+        drop(sources);
+
+        // Each message is handled on its own, so one that can't be decoded or a source failing
+        // doesn't lose the rest of the batch
+        let mut messages = Vec::with_capacity(batch.len());
+        for (source, received) in batch {
+            match received {
+                Ok((value, metadata, callback)) => {
+                    messages.push((value, source, metadata, callback))
+                }
+                Err(MessagingError::Undecodable { reason, message }) => {
+                    self.reject_undecodable(&source, reason, message).await?;
+                }
+                Err(e) => {
+                    let failure = LaikaError::Generic(e.to_string());
+                    if messages.is_empty() {
+                        return Err(failure);
+                    }
+                    *self.source_failure.lock().await = Some(failure);
+                }
+            }
+        }
+        self.guard_inbound(messages).await
+    }
+
+    /// Dead-letter or drop a message that couldn't be decoded, then ack it so it isn't received
+    /// again
+    async fn reject_undecodable(
+        &self,
+        source: &str,
+        reason: String,
+        message: UndecodableMessage,
+    ) -> LaikaResult<()> {
+        match &self.dead_letter {
+            Some(target) => {
+                tracing::error!(
+                    "Could not decode message from {}, sending it to {}: {}",
+                    source,
+                    target,
+                    reason
+                );
+                let error = LaikaError::Generic(format!("Could not decode message: {}", reason));
+                let payload = serde_json::Value::String(message.raw);
+                self.submit_to(target, dead_letter(source, &payload, &error, 1), None)
+                    .await?;
+            }
+            None => tracing::warn!(
+                payload = %message.raw,
+                "Could not decode message from {}, dropping it: {}",
+                source,
+                reason
+            ),
+        }
+        (message.callback)().await
+    }
+
+    /// Current lag for each source that's still being received from
+    pub async fn lag(&self) -> Vec<SourceLag> {
+        self.sources
+            .lock()
+            .await
+            .iter()
+            .map(|source| SourceLag {
+                source: source.name.clone(),
+                queued: source.messages.len(),
+                waited: source.waited,
+            })
             .collect()
     }
+
+    /// Status of every source and target, sources first, each by name
+    pub async fn status(&self) -> Vec<ConnectionStatus> {
+        let lag = self.lag().await;
+        let mut targets: Vec<&String> = self.submitters.keys().collect();
+        targets.sort();
+        self.source_names
+            .iter()
+            .map(|name| {
+                let lag = lag.iter().find(|lag| &lag.source == name);
+                ConnectionStatus::Source {
+                    name: name.clone(),
+                    receiving: lag.is_some(),
+                    paused: self.paused(name),
+                    queued: lag.map(|lag| lag.queued).unwrap_or_default(),
+                    waited_ms: lag
+                        .map(|lag| lag.waited.as_millis() as u64)
+                        .unwrap_or_default(),
+                }
+            })
+            .chain(
+                targets
+                    .into_iter()
+                    .map(|name| ConnectionStatus::Target { name: name.clone() }),
+            )
+            .collect()
+    }
+}
+
+impl Drop for Connections {
+    fn drop(&mut self) {
+        for reader in &self.readers {
+            reader.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::internal::InternalConnection;
+    use serde_json::{json, Value};
+    use std::collections::VecDeque;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Receives lines as a line-based source would, counting acks, then finishes
+    #[derive(Debug)]
+    struct LinesReceiver {
+        lines: Mutex<VecDeque<&'static str>>,
+        acked: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl EventReceiver for LinesReceiver {
+        async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+            let Some(line) = self.lines.lock().await.pop_front() else {
+                return Err(MessagingError::StreamFinished);
+            };
+            let acked = self.acked.clone();
+            let callback: AckCallback = Box::new(move || {
+                Box::pin(async move {
+                    acked.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+            });
+            match serde_json::from_str(line) {
+                Ok(value) => Ok(Some((value, callback))),
+                Err(e) => Err(MessagingError::undecodable(line.as_bytes(), e, callback)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_connection_config() {
+        let custom: ConnectionSpec =
+            serde_yaml::from_str("type: kafka\nbrokers: localhost:9092\ntopic: events").unwrap();
+        let ConnectionConfig::Custom { kind, options } = custom.config else {
+            panic!("Expected a custom connection");
+        };
+        assert_eq!(kind, "kafka");
+        assert_eq!(options.len(), 2);
+        assert_eq!(options["topic"], serde_yaml::Value::from("events"));
+
+        let file: ConnectionConfig = serde_yaml::from_str("type: file\npath: ./events").unwrap();
+        assert!(matches!(file, ConnectionConfig::File { .. }));
+    }
+
+    #[test]
+    fn test_unknown_connection_fields_rejected() {
+        for yaml in [
+            "type: file\npath: ./events\nprefech: 500",
+            "type: file\npath: ./events\nfolow: true",
+            "type: stdout\npath: ./events",
+        ] {
+            let error = serde_yaml::from_str::<ConnectionSpec>(yaml).unwrap_err();
+            assert!(error.to_string().contains("unknown field"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_receive_options() {
+        let spec: ConnectionSpec =
+            serde_yaml::from_str("type: kafka\ntopic: events\nprefetch: 500\nbatchSize: 50")
+                .unwrap();
+        assert_eq!(spec.receive.prefetch, 500);
+        assert_eq!(spec.receive.batch_size, 50);
+        let ConnectionConfig::Custom { options, .. } = spec.config else {
+            panic!("Expected a custom connection");
+        };
+        assert_eq!(options.len(), 1);
+
+        let spec: ConnectionSpec = serde_yaml::from_str("type: file\npath: ./events").unwrap();
+        assert_eq!(spec.receive.prefetch, ReceiveOptions::default().prefetch);
+        assert!(ReceiveOptions {
+            prefetch: 0,
+            batch_size: 1
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pause_and_resume_source() {
+        let internal = |name: &str| InternalConnection::new(name, InternalConfig::default());
+        let (a, b) = (internal("a"), internal("b"));
+        let receivers = [("a", &a), ("b", &b)]
+            .into_iter()
+            .map(|(name, connection)| {
+                let receiver = Box::new(connection.receiver()) as Box<dyn EventReceiver>;
+                (name.to_string(), (receiver, ReceiveOptions::default()))
+            })
+            .collect();
+        let connections = std::sync::Arc::new(Connections::new(receivers, HashMap::new()));
+        let sources = |messages: Vec<Message>| -> Vec<String> {
+            messages.into_iter().map(|message| message.1).collect()
+        };
+
+        assert!(connections.pause("a"));
+        assert!(!connections.pause("missing"));
+        for connection in [&a, &b] {
+            let submitter = connection.submitter();
+            submitter.submit(serde_json::json!({}), None).await.unwrap();
+        }
+        assert_eq!(sources(connections.receive().await.unwrap()), vec!["b"]);
+        assert!(matches!(
+            &connections.status().await[0],
+            ConnectionStatus::Source { paused: true, .. }
+        ));
+
+        // Resuming wakes a receive already waiting on the other sources
+        let receiving = tokio::spawn({
+            let connections = connections.clone();
+            async move { sources(connections.receive().await.unwrap()) }
+        });
+        tokio::task::yield_now().await;
+        assert!(connections.resume("a"));
+        assert_eq!(receiving.await.unwrap(), vec!["a"]);
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_message_is_dead_lettered() {
+        let acked = Arc::new(AtomicUsize::new(0));
+        let receiver = LinesReceiver {
+            lines: Mutex::new(VecDeque::from(["{\"id\": 1}", "not json", "{\"id\": 2}"])),
+            acked: acked.clone(),
+        };
+        let dead_letters = InternalConnection::new("deadLetters", InternalConfig::default());
+        let connections = Connections::new(
+            HashMap::from([(
+                "lines".to_string(),
+                (
+                    Box::new(receiver) as Box<dyn EventReceiver>,
+                    ReceiveOptions::default(),
+                ),
+            )]),
+            HashMap::from([(
+                "deadLetters".to_string(),
+                Box::new(dead_letters.submitter()) as Box<dyn EventSubmitter>,
+            )]),
+        )
+        .with_dead_letter(Some("deadLetters".to_string()));
+
+        // The source finishing is only reported once the messages before it have been handed on
+        let mut received = Vec::new();
+        while let Ok(messages) = connections.receive().await {
+            received.extend(messages.into_iter().map(|message| message.0["id"].clone()));
+        }
+        assert_eq!(received, vec![json!(1), json!(2)]);
+        // Only the undecodable message has been acked, as the others haven't been processed
+        assert_eq!(acked.load(Ordering::SeqCst), 1);
+        let (dead_letter, _) = dead_letters
+            .receiver()
+            .receive_one()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(dead_letter["source"], "lines");
+        assert_eq!(dead_letter["event"], "not json");
+    }
+
+    /// Answer a connection at a time with `responses` in order, then return each request's
+    /// method, path and `Authorization` header
+    fn serve_http(
+        responses: Vec<(u16, &'static str)>,
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut received = Vec::new();
+                let mut buf = [0; 4096];
+                // Read the head, then as much of the body as it says there is
+                let head_end = loop {
+                    let read = stream.read(&mut buf).unwrap();
+                    received.extend_from_slice(&buf[..read]);
+                    if let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                };
+                let head = String::from_utf8_lossy(&received[..head_end]).to_string();
+                let header = |name: &str| {
+                    head.lines().find_map(|line| {
+                        let (key, value) = line.split_once(':')?;
+                        key.eq_ignore_ascii_case(name)
+                            .then(|| value.trim().to_string())
+                    })
+                };
+                let length: usize = header("content-length").map_or(0, |l| l.parse().unwrap());
+                while received.len() < head_end + length {
+                    let read = stream.read(&mut buf).unwrap();
+                    received.extend_from_slice(&buf[..read]);
+                }
+                let line: Vec<&str> = head.split_whitespace().take(2).collect();
+                requests.push(format!(
+                    "{} {}",
+                    line.join(" "),
+                    header("authorization").unwrap_or_default()
+                ));
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    /// Request to `{url}/api`, authenticated by an OAuth2 token from `{url}/token`
+    fn oauth2_request(url: &str) -> HttpRequest {
+        let config: crate::connections::auth::HttpAuthConfig = serde_yaml::from_str(&format!(
+            "type: oauth2\ntokenUrl: {}/token\nclientId: laika\nclientSecret: s3cret",
+            url
+        ))
+        .unwrap();
+        HttpRequest {
+            method: reqwest::Method::GET,
+            url: format!("{}/api", url),
+            headers: Vec::new(),
+            body: None,
+            tls: None,
+            compression: Compression::default(),
+            auth: Some(config.resolve().unwrap()),
+            correlation_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_token_is_cached() {
+        let (url, server) = serve_http(vec![
+            (200, r#"{"access_token": "token-1", "expires_in": 3600}"#),
+            (200, "{}"),
+            (200, "{}"),
+        ]);
+        let connections = Connections::new(HashMap::new(), HashMap::new());
+        connections.request(oauth2_request(&url)).await.unwrap();
+        connections.request(oauth2_request(&url)).await.unwrap();
+
+        let requests = server.join().unwrap();
+        // The token is fetched with the client's credentials, then reused
+        assert_eq!(requests[0], "POST /token Basic bGFpa2E6czNjcmV0");
+        assert_eq!(
+            requests[1..],
+            ["GET /api Bearer token-1", "GET /api Bearer token-1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_token_refreshed_before_expiry() {
+        // Expiring within the margin, so it's refreshed before the next request
+        let (url, server) = serve_http(vec![
+            (200, r#"{"access_token": "token-1", "expires_in": 30}"#),
+            (200, "{}"),
+            (200, r#"{"access_token": "token-2", "expires_in": 3600}"#),
+            (200, "{}"),
+        ]);
+        let connections = Connections::new(HashMap::new(), HashMap::new());
+        connections.request(oauth2_request(&url)).await.unwrap();
+        connections.request(oauth2_request(&url)).await.unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[1], "GET /api Bearer token-1");
+        assert!(requests[2].starts_with("POST /token"));
+        assert_eq!(requests[3], "GET /api Bearer token-2");
+    }
+
+    #[tokio::test]
+    async fn test_oauth2_token_invalidated_on_unauthorized() {
+        let (url, server) = serve_http(vec![
+            (200, r#"{"access_token": "token-1", "expires_in": 3600}"#),
+            (401, "{}"),
+            (200, r#"{"access_token": "token-2", "expires_in": 3600}"#),
+            (200, "{}"),
+        ]);
+        let connections = Connections::new(HashMap::new(), HashMap::new());
+        // Rejected tokens may have been revoked early, so the request can be retried
+        let rejected = connections.request(oauth2_request(&url)).await;
+        assert!(matches!(
+            rejected,
+            Err(LaikaError::SubmitError {
+                retryable: true,
+                ..
+            })
+        ));
+        connections.request(oauth2_request(&url)).await.unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[1], "GET /api Bearer token-1");
+        assert!(requests[2].starts_with("POST /token"));
+        assert_eq!(requests[3], "GET /api Bearer token-2");
+    }
+
+    #[tokio::test]
+    async fn test_source_lag_is_recorded() {
+        let internal = InternalConnection::new("events", InternalConfig::default());
+        let receivers = HashMap::from([(
+            "events".to_string(),
+            (
+                Box::new(internal.receiver()) as Box<dyn EventReceiver>,
+                ReceiveOptions::default(),
+            ),
+        )]);
+        let connections = Connections::new(receivers, HashMap::new());
+        let submitter = internal.submitter();
+        for id in 0..3 {
+            submitter.submit(json!({"id": id}), None).await.unwrap();
+        }
+        // Let the reader queue every message, then keep them waiting
+        while connections.lag().await[0].queued < 3 {
+            tokio::task::yield_now().await;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(connections.receive().await.unwrap().len(), 3);
+
+        let lag = connections.lag().await;
+        assert_eq!(lag[0].queued, 0);
+        assert!(lag[0].waited >= Duration::from_millis(20));
+        let status = serde_json::to_value(&connections.status().await[0]).unwrap();
+        assert_eq!(status["queued"], json!(0));
+        assert!(status["waitedMs"].as_u64().unwrap() >= 20);
+    }
 }
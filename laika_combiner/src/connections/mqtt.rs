@@ -0,0 +1,325 @@
+use crate::connections::tls::TlsSetting;
+use crate::connections::{secret, AckCallback, EventReceiver, EventSubmitter, MessagingError};
+use crate::errors::LaikaError;
+use crate::event::EventMetadata;
+use crate::format::PayloadDecoder;
+use async_trait::async_trait;
+use rumqttc::v5::mqttbytes::v5::{Packet, Publish};
+use rumqttc::v5::mqttbytes::QoS;
+use rumqttc::v5::{AsyncClient, Event, MqttOptions};
+use rumqttc::Transport;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+/// Messages buffered between the MQTT event loop and the receiver
+const CHANNEL_CAPACITY: usize = 1024;
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MqttConfig {
+    host: String,
+    #[serde(default = "MqttConfig::default_port")]
+    port: u16,
+    /// Must be stable across restarts, as the broker keeps the session for unacked messages
+    #[serde(default = "MqttConfig::default_client_id")]
+    client_id: String,
+    username: Option<String>,
+    password: Option<String>,
+    /// Topic filters to receive from, which may include `+` and `#` wildcards
+    #[serde(default)]
+    topics: Vec<String>,
+    /// Topic to publish to when an action doesn't provide one
+    topic: Option<String>,
+    #[serde(default = "MqttConfig::default_qos")]
+    qos: u8,
+    /// Connect over TLS, as `true` or the TLS settings to use
+    #[serde(default)]
+    tls: Option<TlsSetting>,
+}
+
+impl MqttConfig {
+    fn default_port() -> u16 {
+        1883
+    }
+
+    fn default_client_id() -> String {
+        "laika".to_string()
+    }
+
+    fn default_qos() -> u8 {
+        1
+    }
+
+    /// Whether any topic is a shared subscription, which the broker shares out between clients
+    pub(crate) fn shares_messages(&self) -> bool {
+        self.topics.iter().any(|topic| topic.starts_with("$share/"))
+    }
+}
+
+#[derive(Debug)]
+pub struct MqttConnection {
+    client: AsyncClient,
+    qos: QoS,
+    topic: Option<String>,
+    messages: Mutex<mpsc::Receiver<Publish>>,
+    decoder: PayloadDecoder,
+}
+
+impl MqttConnection {
+    /// Connect to the broker, subscribing to the configured topics when receiving
+    pub async fn new(config: MqttConfig, receive: bool) -> Result<Self, MessagingError> {
+        let qos = match config.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            other => {
+                return Err(MessagingError::ConfigError(format!(
+                    "Invalid MQTT QoS {}",
+                    other
+                )))
+            }
+        };
+        // Receivers and submitters for the same connection hold separate sessions
+        let client_id = if receive {
+            config.client_id
+        } else {
+            format!("{}-publisher", config.client_id)
+        };
+        let mut options = MqttOptions::new(client_id, config.host, config.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        // Messages are acked once their actions have been taken, through the AckCallback
+        options.set_manual_acks(true);
+        options.set_clean_start(false);
+        if let (Some(username), Some(password)) = (config.username, config.password) {
+            options.set_credentials(secret(&username)?, secret(&password)?);
+        }
+        if let Some(tls) = config.tls.as_ref().and_then(TlsSetting::config) {
+            tls.require_verify("MQTT")?;
+            let transport = match (tls.ca_pem()?, tls.client_pem()?) {
+                (None, None) => Transport::tls_with_default_config(),
+                (Some(ca), client_auth) => Transport::tls(ca, client_auth, None),
+                (None, Some(_)) => {
+                    return Err(MessagingError::ConfigError(
+                        "MQTT client certificates need a ca to be configured".to_string(),
+                    ))
+                }
+            };
+            options.set_transport(transport);
+        }
+
+        let (client, mut event_loop) = AsyncClient::new(options, CHANNEL_CAPACITY);
+        let (sender, messages) = mpsc::channel(CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            loop {
+                match event_loop.poll().await {
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if sender.send(publish).await.is_err() {
+                            // Receiver has been dropped
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!("MQTT connection failed, reconnecting: {}", e);
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                    }
+                }
+            }
+        });
+
+        if receive {
+            for topic in config.topics {
+                client
+                    .subscribe(topic, qos)
+                    .await
+                    .map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
+            }
+        }
+
+        Ok(Self {
+            client,
+            qos,
+            topic: config.topic,
+            messages: Mutex::new(messages),
+            decoder: PayloadDecoder::Json,
+        })
+    }
+
+    /// Decode received payloads with `decoder`, rather than as JSON
+    pub fn with_decoder(mut self, decoder: PayloadDecoder) -> Self {
+        self.decoder = decoder;
+        self
+    }
+}
+
+#[async_trait]
+impl EventSubmitter for MqttConnection {
+    async fn submit(&self, payload: Value, routing: Option<&str>) -> Result<(), MessagingError> {
+        self.submit_encoded(serde_json::to_vec(&payload)?, "application/json", routing)
+            .await
+    }
+
+    async fn submit_encoded(
+        &self,
+        payload: Vec<u8>,
+        _content_type: &str,
+        routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let Some(topic) = routing.or(self.topic.as_deref()) else {
+            return Err(MessagingError::ConfigError(
+                "No topic provided for MQTT submission".to_string(),
+            ));
+        };
+        self.client
+            .publish(topic.to_string(), self.qos, false, payload)
+            .await
+            .map_err(|e| MessagingError::SubmissionError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl EventReceiver for MqttConnection {
+    async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+        Ok(self
+            .receive_with_metadata()
+            .await?
+            .map(|(value, _, callback)| (value, callback)))
+    }
+
+    /// Metadata is the `topic` the message was published to, and its user properties as `header`
+    async fn receive_with_metadata(
+        &self,
+    ) -> Result<Option<(Value, EventMetadata, AckCallback)>, MessagingError> {
+        let Some(publish) = self.messages.lock().await.recv().await else {
+            return Err(MessagingError::StreamFinished);
+        };
+        let payload = publish.payload.clone();
+        let client = self.client.clone();
+        let mut metadata = EventMetadata::new();
+        metadata.insert(
+            "topic".to_string(),
+            Value::String(String::from_utf8_lossy(&publish.topic).into_owned()),
+        );
+        if let Some(properties) = &publish.properties {
+            metadata.insert(
+                "header".to_string(),
+                Value::Object(
+                    properties
+                        .user_properties
+                        .iter()
+                        .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+                        .collect(),
+                ),
+            );
+        }
+        let callback: AckCallback = Box::new(move || {
+            Box::pin(async move {
+                client
+                    .ack(&publish)
+                    .await
+                    .map_err(|e| LaikaError::Generic(format!("Could not ack MQTT message: {}", e)))
+            })
+        });
+        // Messages that can't be decoded are still acked once they've been dealt with, so the
+        // broker doesn't redeliver them
+        let value = match self.decoder.decode(&payload).await {
+            Ok(value) => value,
+            Err(e) => return Err(MessagingError::undecodable(&payload, e, callback)),
+        };
+        Ok(Some((value, metadata, callback)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::v5::mqttbytes::v5::PublishProperties;
+    use rumqttc::v5::EventLoop;
+
+    /// A connection receiving the messages sent on the returned channel. The event loop is never
+    /// polled, so acks are left queued on it.
+    fn connection(capacity: usize) -> (MqttConnection, mpsc::Sender<Publish>, EventLoop) {
+        let (client, event_loop) =
+            AsyncClient::new(MqttOptions::new("laika", "localhost", 1883), capacity);
+        let (sender, messages) = mpsc::channel(CHANNEL_CAPACITY);
+        let connection = MqttConnection {
+            client,
+            qos: QoS::AtLeastOnce,
+            topic: None,
+            messages: Mutex::new(messages),
+            decoder: PayloadDecoder::Json,
+        };
+        (connection, sender, event_loop)
+    }
+
+    fn publish(payload: &'static str, properties: Option<PublishProperties>) -> Publish {
+        let mut publish = Publish::new("sensors/kitchen", QoS::AtLeastOnce, payload, properties);
+        publish.pkid = 1;
+        publish
+    }
+
+    #[tokio::test]
+    async fn test_message_is_received_with_metadata() {
+        let (connection, sender, _event_loop) = connection(10);
+        let properties = PublishProperties {
+            user_properties: vec![("traceparent".to_string(), "00-abc".to_string())],
+            ..Default::default()
+        };
+        sender
+            .send(publish(r#"{"temperature": 21}"#, Some(properties)))
+            .await
+            .unwrap();
+
+        let (value, metadata, callback) =
+            connection.receive_with_metadata().await.unwrap().unwrap();
+        assert_eq!(value, serde_json::json!({"temperature": 21}));
+        assert_eq!(metadata.get("topic").unwrap(), "sensors/kitchen");
+        assert_eq!(
+            metadata.get("header").unwrap(),
+            &serde_json::json!({"traceparent": "00-abc"})
+        );
+        callback().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_message_is_acked() {
+        // Room for a single queued ack
+        let (connection, sender, _event_loop) = connection(1);
+        sender.send(publish("{not json", None)).await.unwrap();
+        sender
+            .send(publish(r#"{"temperature": 21}"#, None))
+            .await
+            .unwrap();
+
+        let Err(MessagingError::Undecodable { message, .. }) =
+            connection.receive_with_metadata().await
+        else {
+            panic!("Expected the message to be undecodable");
+        };
+        assert_eq!(message.raw, "{not json");
+        (message.callback)().await.unwrap();
+        // The ack was queued for the broker, filling the queue
+        assert!(connection.client.try_ack(&publish("", None)).is_err());
+
+        // The source carries on with the next message
+        let (value, _, _) = connection.receive_with_metadata().await.unwrap().unwrap();
+        assert_eq!(value, serde_json::json!({"temperature": 21}));
+    }
+
+    #[tokio::test]
+    async fn test_config() {
+        let config: MqttConfig =
+            serde_yaml::from_str("host: localhost\ntopics: [\"$share/laika/sensors/#\"]\nqos: 3\n")
+                .unwrap();
+        assert_eq!(config.port, 1883);
+        assert_eq!(config.client_id, "laika");
+        assert!(config.shares_messages());
+        assert!(matches!(
+            MqttConnection::new(config, true).await,
+            Err(MessagingError::ConfigError(_))
+        ));
+    }
+}
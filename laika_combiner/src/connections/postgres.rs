@@ -0,0 +1,254 @@
+use crate::connections::{secret, EventSubmitter, MessagingError};
+use crate::utils::extract_json::extract_json_field;
+use crate::utils::parse_time::parse_time_str;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use tokio::sync::{oneshot, Mutex};
+use tokio_postgres::{Client, NoTls};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PostgresConfig {
+    /// libpq style connection string, e.g. `host=localhost user=laika dbname=audit`
+    connection: String,
+    /// Table to insert into, optionally qualified with a schema
+    table: String,
+    /// `jsonb` column the full payload is written to
+    #[serde(default = "PostgresConfig::default_payload_column")]
+    payload_column: String,
+    /// Further columns populated from the payload, by JSONPath
+    #[serde(default)]
+    columns: HashMap<String, String>,
+    #[serde(default = "PostgresConfig::default_batch_size")]
+    batch_size: usize,
+    #[serde(default = "PostgresConfig::default_flush_interval")]
+    flush_interval: String,
+}
+
+impl PostgresConfig {
+    fn default_payload_column() -> String {
+        "payload".to_string()
+    }
+
+    fn default_batch_size() -> usize {
+        100
+    }
+
+    fn default_flush_interval() -> String {
+        "1s".to_string()
+    }
+}
+
+/// Quote an identifier, keeping schema qualification
+fn quote_identifier(identifier: &str) -> String {
+    identifier
+        .split('.')
+        .map(|part| format!("\"{}\"", part.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
+/// Rows waiting to be inserted, with whoever is waiting on each
+#[derive(Default)]
+struct PendingRows {
+    rows: Vec<Value>,
+    waiting: Vec<oneshot::Sender<Result<(), String>>>,
+}
+
+/// Rows waiting to be inserted, along with the statement to insert them
+struct Batch {
+    client: Client,
+    statement: String,
+    pending: Mutex<PendingRows>,
+    /// Set once nothing more will be waited on to fill a batch, as when shutting down
+    closing: AtomicBool,
+}
+
+impl Batch {
+    /// Insert all buffered rows in a single statement, letting whoever is waiting on them know
+    /// how it went. Rows are dropped from the buffer either way, so the buffer never holds more
+    /// than a batch, and the rows of a failed batch are retried by retrying their messages.
+    async fn flush(&self) -> Result<(), MessagingError> {
+        let mut pending = self.pending.lock().await;
+        if pending.rows.is_empty() {
+            return Ok(());
+        }
+        let PendingRows { rows, waiting } = std::mem::take(&mut *pending);
+        let result = self
+            .client
+            .execute(self.statement.as_str(), &[&Value::Array(rows)])
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+        for waiting in waiting {
+            let _ = waiting.send(result.clone());
+        }
+        result.map_err(MessagingError::SubmissionError)
+    }
+
+    /// Add rows to the batch, returning once the batch they're in has been written
+    async fn insert(&self, rows: Vec<Value>, batch_size: usize) -> Result<(), MessagingError> {
+        let (done, written) = oneshot::channel();
+        let full = {
+            let mut pending = self.pending.lock().await;
+            pending.rows.extend(rows);
+            pending.waiting.push(done);
+            pending.rows.len() >= batch_size
+        };
+        if full || self.closing.load(Ordering::SeqCst) {
+            // The outcome comes back through `written`, as it does for the rest of the batch
+            let _ = self.flush().await;
+        }
+        written
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()))
+            .map_err(MessagingError::SubmissionError)
+    }
+}
+
+/// Inserts payloads as rows into a Postgres table.
+///
+/// Rows are written in batches, once `batchSize` rows are buffered or every `flushInterval`,
+/// whichever is sooner. Submissions only return once their rows have been written, so the
+/// messages that led to them aren't acked before then.
+pub struct PostgresSink {
+    table: String,
+    payload_column: String,
+    columns: Vec<(String, String)>,
+    batch_size: usize,
+    batch: Arc<Batch>,
+}
+
+impl Debug for PostgresSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PostgresSink")
+            .field("table", &self.table)
+            .field("payload_column", &self.payload_column)
+            .field("columns", &self.columns)
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+impl PostgresSink {
+    pub async fn new(config: PostgresConfig) -> Result<Self, MessagingError> {
+        let flush_interval = parse_time_str(config.flush_interval.as_str())
+            .map_err(|e| MessagingError::ConfigError(e.to_string()))?
+            .unsigned_abs();
+        if config.batch_size == 0 {
+            return Err(MessagingError::ConfigError(
+                "Postgres batchSize must be at least 1".to_string(),
+            ));
+        }
+        let (client, connection) =
+            tokio_postgres::connect(secret(&config.connection)?.as_str(), NoTls)
+                .await
+                .map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::error!("Postgres connection failed: {}", e);
+            }
+        });
+
+        let table = quote_identifier(config.table.as_str());
+        let columns: Vec<(String, String)> = config.columns.into_iter().collect();
+        let column_names = std::iter::once(&config.payload_column)
+            .chain(columns.iter().map(|(column, _)| column))
+            .map(|column| quote_identifier(column))
+            .collect::<Vec<String>>()
+            .join(", ");
+        // Each row is a JSON object keyed by column, converted to the table's column types by
+        // Postgres, so any number of rows can be inserted with a single parameter.
+        let statement = format!(
+            "INSERT INTO {table} ({column_names}) SELECT {column_names} FROM jsonb_populate_recordset(NULL::{table}, $1::jsonb)"
+        );
+        let batch = Arc::new(Batch {
+            client,
+            statement,
+            pending: Mutex::new(PendingRows::default()),
+            closing: AtomicBool::new(false),
+        });
+
+        let flushed_batch: Weak<Batch> = Arc::downgrade(&batch);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+            loop {
+                interval.tick().await;
+                // Stop once the sink has been dropped
+                let Some(batch) = flushed_batch.upgrade() else {
+                    break;
+                };
+                if let Err(e) = batch.flush().await {
+                    tracing::error!("Could not write batch to Postgres: {}", e);
+                }
+            }
+        });
+
+        Ok(Self {
+            table: config.table,
+            payload_column: config.payload_column,
+            columns,
+            batch_size: config.batch_size,
+            batch,
+        })
+    }
+
+    fn row(&self, payload: Value) -> Value {
+        let mut row = Map::new();
+        for (column, path) in &self.columns {
+            let value = extract_json_field(&payload, path)
+                .map(Cow::into_owned)
+                .unwrap_or(Value::Null);
+            row.insert(column.clone(), value);
+        }
+        row.insert(self.payload_column.clone(), payload);
+        Value::Object(row)
+    }
+}
+
+#[async_trait]
+impl EventSubmitter for PostgresSink {
+    async fn submit(&self, payload: Value, _routing: Option<&str>) -> Result<(), MessagingError> {
+        self.batch
+            .insert(vec![self.row(payload)], self.batch_size)
+            .await
+    }
+
+    async fn submit_batch(
+        &self,
+        payloads: Vec<Value>,
+        _routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let rows = payloads
+            .into_iter()
+            .map(|payload| self.row(payload))
+            .collect();
+        self.batch.insert(rows, self.batch_size).await
+    }
+
+    async fn close(&self) -> Result<(), MessagingError> {
+        self.batch.closing.store(true, Ordering::SeqCst);
+        self.batch.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_identifier() {
+        assert_eq!(quote_identifier("audit"), "\"audit\"");
+        assert_eq!(quote_identifier("public.audit"), "\"public\".\"audit\"");
+        assert_eq!(
+            quote_identifier("audit\"; DROP TABLE x; --"),
+            "\"audit\"\"; DROP TABLE x; --\""
+        );
+    }
+}
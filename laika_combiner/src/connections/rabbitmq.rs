@@ -1,10 +1,19 @@
-use crate::connections::{EventSubmitter, MessagingError};
+use crate::connections::compression::Compression;
+use crate::connections::tls::TlsConfig;
+use crate::connections::{secret, EventSubmitter, MessagingError};
 use async_trait::async_trait;
-use lapin::{Connection, ConnectionProperties};
+use lapin::options::BasicPublishOptions;
+use lapin::tcp::{OwnedIdentity, OwnedTLSConfig};
+use lapin::{BasicProperties, Connection, ConnectionProperties};
 
 #[derive(Debug)]
 pub struct RabbitMqConnection {
     channel: lapin::Channel,
+    /// Exchange to publish to, where "" is the default exchange
+    exchange: String,
+    /// Routing key used when a submission doesn't provide one
+    routing_key: Option<String>,
+    compression: Compression,
 }
 
 impl RabbitMqConnection {
@@ -14,32 +23,179 @@ impl RabbitMqConnection {
         username: Option<String>,
         password: Option<String>,
         vhost: Option<String>,
+        tls: Option<TlsConfig>,
     ) -> Result<Self, MessagingError> {
         let amqp_url = format!(
-            "amqp://{}:{}@{}:{}{}",
-            username.unwrap_or_else(|| "guest".to_string()),
-            password.unwrap_or_else(|| "guest".to_string()),
+            "{}://{}:{}@{}:{}{}",
+            if tls.is_some() { "amqps" } else { "amqp" },
+            username
+                .as_deref()
+                .map(secret)
+                .transpose()?
+                .unwrap_or_else(|| "guest".to_string()),
+            password
+                .as_deref()
+                .map(secret)
+                .transpose()?
+                .unwrap_or_else(|| "guest".to_string()),
             host,
             port,
             vhost.unwrap_or_else(|| "/".to_string()),
         );
 
-        let conn = Connection::connect(&amqp_url, ConnectionProperties::default())
-            .await
-            .map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
+        let conn = match tls {
+            None => Connection::connect(&amqp_url, ConnectionProperties::default()).await,
+            Some(tls) => {
+                tls.require_verify("RabbitMQ")?;
+                let tls_config = OwnedTLSConfig {
+                    identity: tls
+                        .client_pem()?
+                        .map(|(pem, key)| OwnedIdentity::PKCS8 { pem, key }),
+                    cert_chain: tls
+                        .ca_pem()?
+                        .map(|ca| String::from_utf8_lossy(&ca).into_owned()),
+                };
+                Connection::connect_with_config(
+                    &amqp_url,
+                    ConnectionProperties::default(),
+                    tls_config,
+                )
+                .await
+            }
+        }
+        .map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
 
         let channel = conn
             .create_channel()
             .await
             .map_err(|e| MessagingError::ChannelError(e.to_string()))?;
 
-        Ok(Self { channel })
+        Ok(Self {
+            channel,
+            exchange: String::new(),
+            routing_key: None,
+            compression: Compression::None,
+        })
+    }
+
+    pub fn with_routing(mut self, exchange: Option<String>, routing_key: Option<String>) -> Self {
+        self.exchange = exchange.unwrap_or_default();
+        self.routing_key = routing_key;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Properties of a published payload, marking how it's been compressed
+    fn properties(&self, content_type: &str) -> BasicProperties {
+        let properties = BasicProperties::default().with_content_type(content_type.into());
+        match self.compression.content_encoding() {
+            Some(encoding) => properties.with_content_encoding(encoding.into()),
+            None => properties,
+        }
+    }
+
+    /// Routing key for a submission, falling back to the connection's own
+    fn routing_key<'a>(&'a self, routing: Option<&'a str>) -> Result<&'a str, MessagingError> {
+        routing_key(routing, self.routing_key.as_deref())
     }
 }
 
+/// Routing key rendered for a submission, or else `fallback`
+fn routing_key<'a>(
+    routing: Option<&'a str>,
+    fallback: Option<&'a str>,
+) -> Result<&'a str, MessagingError> {
+    routing.or(fallback).ok_or_else(|| {
+        MessagingError::ConfigError("No routing key provided for RabbitMQ submission".to_string())
+    })
+}
+
 #[async_trait]
 impl EventSubmitter for RabbitMqConnection {
-    async fn submit(&self, _payload: serde_json::Value) -> Result<(), MessagingError> {
-        todo!()
+    async fn submit(
+        &self,
+        payload: serde_json::Value,
+        routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        self.submit_encoded(serde_json::to_vec(&payload)?, "application/json", routing)
+            .await
+    }
+
+    /// Publish every payload before waiting on any confirm, rather than one round trip each
+    async fn submit_batch(
+        &self,
+        payloads: Vec<serde_json::Value>,
+        routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let routing_key = self.routing_key(routing)?;
+        let mut confirms = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            confirms.push(
+                self.channel
+                    .basic_publish(
+                        self.exchange.as_str(),
+                        routing_key,
+                        BasicPublishOptions::default(),
+                        self.compression
+                            .compress(serde_json::to_vec(&payload)?)?
+                            .as_slice(),
+                        self.properties("application/json"),
+                    )
+                    .await
+                    .map_err(|e| MessagingError::SubmissionError(e.to_string()))?,
+            );
+        }
+        for confirm in confirms {
+            confirm
+                .await
+                .map_err(|e| MessagingError::SubmissionError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    async fn submit_encoded(
+        &self,
+        payload: Vec<u8>,
+        content_type: &str,
+        routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let routing_key = self.routing_key(routing)?;
+        self.channel
+            .basic_publish(
+                self.exchange.as_str(),
+                routing_key,
+                BasicPublishOptions::default(),
+                self.compression.compress(payload)?.as_slice(),
+                self.properties(content_type),
+            )
+            .await
+            .map_err(|e| MessagingError::SubmissionError(e.to_string()))?
+            .await
+            .map_err(|e| MessagingError::SubmissionError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routing_key() {
+        // Routing rendered for the submission takes precedence over the connection's own
+        assert_eq!(
+            routing_key(Some("orders.eu"), Some("orders")).unwrap(),
+            "orders.eu"
+        );
+        assert_eq!(routing_key(None, Some("orders")).unwrap(), "orders");
+        assert_eq!(routing_key(Some("orders.eu"), None).unwrap(), "orders.eu");
+        assert!(matches!(
+            routing_key(None, None),
+            Err(MessagingError::ConfigError(_))
+        ));
     }
 }
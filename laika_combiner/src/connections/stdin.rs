@@ -0,0 +1,70 @@
+use crate::connections::{noop_ack_callback, AckCallback, EventReceiver, MessagingError};
+use async_trait::async_trait;
+use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+use tokio::sync::Mutex;
+
+/// Reads events as JSON lines from standard input, finishing once it's closed
+pub struct StdinReceiver {
+    reader: Mutex<Box<dyn AsyncBufRead + Send + Unpin>>,
+}
+
+impl std::fmt::Debug for StdinReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StdinReceiver").finish()
+    }
+}
+
+impl StdinReceiver {
+    pub fn new() -> Self {
+        Self::from_reader(BufReader::new(tokio::io::stdin()))
+    }
+
+    /// Read lines from `reader` instead of standard input
+    fn from_reader(reader: impl AsyncBufRead + Send + Unpin + 'static) -> Self {
+        Self {
+            reader: Mutex::new(Box::new(reader)),
+        }
+    }
+}
+
+#[async_trait]
+impl EventReceiver for StdinReceiver {
+    async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+        let mut line = String::new();
+        if self.reader.lock().await.read_line(&mut line).await? == 0 {
+            return Err(MessagingError::StreamFinished);
+        }
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        // Lines can't be read again, so there's nothing to acknowledge
+        match serde_json::from_str(line.trim()) {
+            Ok(value) => Ok(Some((value, noop_ack_callback()))),
+            Err(e) => Err(MessagingError::undecodable(
+                line.trim().as_bytes(),
+                e,
+                noop_ack_callback(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reads_lines_until_closed() {
+        let receiver = StdinReceiver::from_reader(&b"{\"id\": 1}\n\n{\"id\": 2}"[..]);
+        let (first, _) = receiver.receive_one().await.unwrap().unwrap();
+        assert_eq!(first["id"], 1);
+        assert!(receiver.receive_one().await.unwrap().is_none());
+        let (second, _) = receiver.receive_one().await.unwrap().unwrap();
+        assert_eq!(second["id"], 2);
+        assert!(matches!(
+            receiver.receive_one().await,
+            Err(MessagingError::StreamFinished)
+        ));
+    }
+}
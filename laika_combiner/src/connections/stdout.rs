@@ -12,8 +12,22 @@ impl StdoutSubmitter {
 
 #[async_trait]
 impl EventSubmitter for StdoutSubmitter {
-    async fn submit(&self, payload: serde_json::Value) -> Result<(), MessagingError> {
-        println!("{:?}", payload);
+    async fn submit(
+        &self,
+        payload: serde_json::Value,
+        _routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        println!("{}", payload);
+        Ok(())
+    }
+
+    async fn submit_encoded(
+        &self,
+        payload: Vec<u8>,
+        _content_type: &str,
+        _routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        println!("{}", String::from_utf8_lossy(&payload));
         Ok(())
     }
 }
@@ -0,0 +1,174 @@
+use crate::connections::MessagingError;
+use serde::Deserialize;
+
+/// Where a PEM encoded certificate or key is read from
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum PemSource {
+    File(String),
+    /// Environment variable holding the PEM itself
+    Env(String),
+}
+
+impl PemSource {
+    fn load(&self) -> Result<Vec<u8>, MessagingError> {
+        match self {
+            PemSource::File(path) => std::fs::read(path).map_err(|e| {
+                MessagingError::ConfigError(format!("Could not read {}: {}", path, e))
+            }),
+            PemSource::Env(name) => std::env::var(name).map(String::into_bytes).map_err(|_| {
+                MessagingError::ConfigError(format!("Environment variable {} is not set", name))
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsVerify {
+    /// Verify the server's certificate and hostname
+    #[default]
+    Full,
+    /// Accept any certificate, for testing against self-signed servers
+    None,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TlsConfig {
+    /// CA certificates to trust, rather than the system's
+    pub ca: Option<PemSource>,
+    /// Client certificate, for mutual TLS
+    pub cert: Option<PemSource>,
+    /// Private key of the client certificate
+    pub key: Option<PemSource>,
+    #[serde(default)]
+    pub verify: TlsVerify,
+}
+
+/// TLS for a connection, as `tls: true` for the defaults or the settings to use
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum TlsSetting {
+    Enabled(bool),
+    Config(TlsConfig),
+}
+
+impl TlsSetting {
+    /// Settings to connect with, or `None` without TLS
+    pub fn config(&self) -> Option<TlsConfig> {
+        match self {
+            TlsSetting::Enabled(true) => Some(TlsConfig::default()),
+            TlsSetting::Enabled(false) => None,
+            TlsSetting::Config(config) => Some(config.clone()),
+        }
+    }
+}
+
+impl TlsConfig {
+    /// CA certificates to trust, if not the system's
+    pub(crate) fn ca_pem(&self) -> Result<Option<Vec<u8>>, MessagingError> {
+        self.ca.as_ref().map(PemSource::load).transpose()
+    }
+
+    /// Client certificate and key, for mutual TLS
+    pub(crate) fn client_pem(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>, MessagingError> {
+        match (&self.cert, &self.key) {
+            (Some(cert), Some(key)) => Ok(Some((cert.load()?, key.load()?))),
+            (None, None) => Ok(None),
+            _ => Err(MessagingError::ConfigError(
+                "TLS client certificates need both cert and key".to_string(),
+            )),
+        }
+    }
+
+    /// Fail for brokers whose clients can't skip verification
+    pub(crate) fn require_verify(&self, connection_type: &str) -> Result<(), MessagingError> {
+        match self.verify {
+            TlsVerify::Full => Ok(()),
+            TlsVerify::None => Err(MessagingError::ConfigError(format!(
+                "{} connections always verify TLS certificates",
+                connection_type
+            ))),
+        }
+    }
+
+    /// HTTP client connecting with these settings
+    pub(crate) fn http_client(&self) -> Result<reqwest::Client, MessagingError> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(ca) = self.ca_pem()? {
+            for certificate in reqwest::Certificate::from_pem_bundle(&ca)
+                .map_err(|e| MessagingError::ConfigError(e.to_string()))?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+            builder = builder.tls_built_in_root_certs(false);
+        }
+        if let Some((mut cert, key)) = self.client_pem()? {
+            cert.push(b'\n');
+            cert.extend(key);
+            builder = builder.identity(
+                reqwest::Identity::from_pem(&cert)
+                    .map_err(|e| MessagingError::ConfigError(e.to_string()))?,
+            );
+        }
+        if self.verify == TlsVerify::None {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder
+            .build()
+            .map_err(|e| MessagingError::ConfigError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_setting() {
+        let enabled: TlsSetting = serde_yaml::from_str("true").unwrap();
+        assert_eq!(enabled.config(), Some(TlsConfig::default()));
+        let disabled: TlsSetting = serde_yaml::from_str("false").unwrap();
+        assert_eq!(disabled.config(), None);
+
+        let configured: TlsSetting = serde_yaml::from_str(
+            "{ ca: { file: /etc/ssl/ca.pem }, cert: { env: CLIENT_CERT }, key: { env: CLIENT_KEY } }",
+        )
+        .unwrap();
+        let config = configured.config().unwrap();
+        assert_eq!(
+            config.ca,
+            Some(PemSource::File("/etc/ssl/ca.pem".to_string()))
+        );
+        assert_eq!(config.cert, Some(PemSource::Env("CLIENT_CERT".to_string())));
+        assert_eq!(config.verify, TlsVerify::Full);
+    }
+
+    #[test]
+    fn test_client_pem() {
+        std::env::set_var("LAIKA_TEST_TLS_CERT", "cert");
+        std::env::set_var("LAIKA_TEST_TLS_KEY", "key");
+        let config = TlsConfig {
+            cert: Some(PemSource::Env("LAIKA_TEST_TLS_CERT".to_string())),
+            key: Some(PemSource::Env("LAIKA_TEST_TLS_KEY".to_string())),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.client_pem().unwrap(),
+            Some((b"cert".to_vec(), b"key".to_vec()))
+        );
+
+        let missing_key = TlsConfig {
+            key: None,
+            ..config
+        };
+        assert!(missing_key.client_pem().is_err());
+        assert!(TlsConfig {
+            ca: Some(PemSource::Env("LAIKA_TEST_TLS_UNSET".to_string())),
+            ..Default::default()
+        }
+        .ca_pem()
+        .is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::connections::{
+    noop_ack_callback, AckCallback, EventReceiver, EventSubmitter, MessagingError,
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Lines buffered between the connected clients and the receiver
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnixSocketConfig {
+    /// Socket that sources listen on, and targets connect to
+    path: PathBuf,
+}
+
+/// Listens on a Unix domain socket, reading JSON lines from every process that connects
+#[derive(Debug)]
+pub struct UnixSocketReceiver {
+    path: PathBuf,
+    lines: Mutex<mpsc::Receiver<String>>,
+    /// Accepts connections, and reads from each of them until it's aborted
+    listener: JoinHandle<()>,
+}
+
+impl UnixSocketReceiver {
+    pub fn new(config: UnixSocketConfig) -> Result<Self, MessagingError> {
+        let path = config.path;
+        // A socket left behind by an earlier run would stop it being bound again
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            if !metadata.file_type().is_socket() {
+                return Err(MessagingError::ConfigError(format!(
+                    "{} already exists and isn't a socket",
+                    path.display()
+                )));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
+        let (sender, lines) = mpsc::channel(CHANNEL_CAPACITY);
+        let listener = tokio::spawn(async move {
+            // Dropped along with this task, which aborts the readers
+            let mut readers = JoinSet::new();
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        readers.spawn(read_lines(stream, sender.clone()));
+                    }
+                    Err(e) => tracing::error!("Could not accept on Unix socket: {}", e),
+                }
+                // Forget readers whose clients have disconnected
+                while readers.try_join_next().is_some() {}
+            }
+        });
+        Ok(Self {
+            path,
+            lines: Mutex::new(lines),
+            listener,
+        })
+    }
+}
+
+/// Send each line from a connected client on, until it disconnects
+async fn read_lines(stream: UnixStream, sender: mpsc::Sender<String>) {
+    let mut lines = BufReader::new(stream).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => {
+                if sender.send(line).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Unix socket client disconnected: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for UnixSocketReceiver {
+    fn drop(&mut self) {
+        self.listener.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[async_trait]
+impl EventReceiver for UnixSocketReceiver {
+    async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+        let Some(line) = self.lines.lock().await.recv().await else {
+            return Err(MessagingError::StreamFinished);
+        };
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        // Clients get no acknowledgement, as with a pipe
+        match serde_json::from_str(line.trim()) {
+            Ok(value) => Ok(Some((value, noop_ack_callback()))),
+            Err(e) => Err(MessagingError::undecodable(
+                line.trim().as_bytes(),
+                e,
+                noop_ack_callback(),
+            )),
+        }
+    }
+}
+
+/// Writes payloads as lines to a Unix domain socket that another process listens on,
+/// reconnecting on the next submission if the connection is lost
+#[derive(Debug)]
+pub struct UnixSocketSubmitter {
+    path: PathBuf,
+    stream: Mutex<Option<BufWriter<UnixStream>>>,
+}
+
+impl UnixSocketSubmitter {
+    pub fn new(config: UnixSocketConfig) -> Self {
+        Self {
+            path: config.path,
+            stream: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl EventSubmitter for UnixSocketSubmitter {
+    async fn submit(&self, payload: Value, routing: Option<&str>) -> Result<(), MessagingError> {
+        self.submit_encoded(serde_json::to_vec(&payload)?, "application/json", routing)
+            .await
+    }
+
+    /// Write the payload as a line. Binary payloads containing newlines can't be read back.
+    async fn submit_encoded(
+        &self,
+        payload: Vec<u8>,
+        _content_type: &str,
+        _routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let mut stream = self.stream.lock().await;
+        if stream.is_none() {
+            let connected = UnixStream::connect(&self.path).await.map_err(|e| {
+                MessagingError::ConnectionError(format!("{}: {}", self.path.display(), e))
+            })?;
+            *stream = Some(BufWriter::new(connected));
+        }
+        let writer = stream.as_mut().expect("Stream was just connected");
+        let written = async {
+            writer.write_all(&payload).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await
+        }
+        .await;
+        if let Err(e) = written {
+            // Reconnect next time, as the listener may have restarted
+            *stream = None;
+            return Err(MessagingError::ConnectionError(e.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_submitted_lines_are_received() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let config = UnixSocketConfig {
+            path: dir.path().join("laika.sock"),
+        };
+        let receiver = UnixSocketReceiver::new(config.clone()).unwrap();
+        let submitter = UnixSocketSubmitter::new(config);
+        submitter.submit(json!({"id": 1}), None).await.unwrap();
+        submitter.submit(json!({"id": 2}), None).await.unwrap();
+
+        let (first, _) = receiver.receive_one().await.unwrap().unwrap();
+        let (second, _) = receiver.receive_one().await.unwrap().unwrap();
+        assert_eq!(
+            (first["id"].clone(), second["id"].clone()),
+            (json!(1), json!(2))
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use crate::connections::{
+    noop_ack_callback, AckCallback, EventReceiver, EventSubmitter, MessagingError,
+};
+use crate::event::EventMetadata;
+use crate::format::PayloadDecoder;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Messages buffered between the receiving thread and the receiver
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ZmqSocketType {
+    Pull,
+    Sub,
+    Push,
+    Pub,
+}
+
+impl From<ZmqSocketType> for zmq::SocketType {
+    fn from(value: ZmqSocketType) -> Self {
+        match value {
+            ZmqSocketType::Pull => zmq::PULL,
+            ZmqSocketType::Sub => zmq::SUB,
+            ZmqSocketType::Push => zmq::PUSH,
+            ZmqSocketType::Pub => zmq::PUB,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZmqConfig {
+    socket: ZmqSocketType,
+    endpoint: String,
+    /// Bind to the endpoint, rather than connecting to it
+    #[serde(default)]
+    bind: bool,
+    /// Topic prefixes to subscribe to for `sub` sockets, where none subscribes to everything
+    #[serde(default)]
+    topics: Vec<String>,
+    /// Topic to publish under for `pub` sockets when an action doesn't provide one
+    topic: Option<String>,
+}
+
+impl ZmqConfig {
+    /// Whether messages are shared out between readers, as `pull` sockets are round-robined
+    pub(crate) fn shares_messages(&self) -> bool {
+        self.socket == ZmqSocketType::Pull
+    }
+
+    fn open(&self, context: &zmq::Context) -> Result<zmq::Socket, MessagingError> {
+        let socket = context
+            .socket(self.socket.into())
+            .map_err(|e| MessagingError::ChannelError(e.to_string()))?;
+        let attached = if self.bind {
+            socket.bind(self.endpoint.as_str())
+        } else {
+            socket.connect(self.endpoint.as_str())
+        };
+        attached.map_err(|e| MessagingError::ConnectionError(e.to_string()))?;
+        Ok(socket)
+    }
+}
+
+/// Receives events from a `pull` or `sub` socket.
+///
+/// ZeroMQ sockets block, so messages are received on a dedicated thread. The payload is the last
+/// frame of each message, so topic frames from publishers are skipped.
+#[derive(Debug)]
+pub struct ZmqReceiver {
+    /// Payloads, with the topic frame they were published under, if any
+    messages: tokio::sync::Mutex<mpsc::Receiver<(Vec<u8>, Option<Vec<u8>>)>>,
+    decoder: PayloadDecoder,
+}
+
+impl ZmqReceiver {
+    pub fn new(config: ZmqConfig) -> Result<Self, MessagingError> {
+        if !matches!(config.socket, ZmqSocketType::Pull | ZmqSocketType::Sub) {
+            return Err(MessagingError::ConfigError(
+                "ZeroMQ sources must use a pull or sub socket".to_string(),
+            ));
+        }
+        let context = zmq::Context::new();
+        let socket = config.open(&context)?;
+        if config.socket == ZmqSocketType::Sub {
+            let topics = if config.topics.is_empty() {
+                vec![String::new()]
+            } else {
+                config.topics.clone()
+            };
+            for topic in topics {
+                socket
+                    .set_subscribe(topic.as_bytes())
+                    .map_err(|e| MessagingError::ConfigError(e.to_string()))?;
+            }
+        }
+        let (sender, messages) = mpsc::channel(CHANNEL_CAPACITY);
+        std::thread::spawn(move || {
+            // Keep the context alive for as long as the socket
+            let _context = context;
+            loop {
+                match socket.recv_multipart(0) {
+                    Ok(mut frames) => {
+                        let Some(payload) = frames.pop() else {
+                            continue;
+                        };
+                        let topic = (!frames.is_empty()).then(|| frames.remove(0));
+                        if sender.blocking_send((payload, topic)).is_err() {
+                            // Receiver has been dropped
+                            break;
+                        }
+                    }
+                    Err(e) => tracing::error!("Could not receive from ZeroMQ: {}", e),
+                }
+            }
+        });
+        Ok(Self {
+            messages: tokio::sync::Mutex::new(messages),
+            decoder: PayloadDecoder::Json,
+        })
+    }
+
+    /// Decode received payloads with `decoder`, rather than as JSON
+    pub fn with_decoder(mut self, decoder: PayloadDecoder) -> Self {
+        self.decoder = decoder;
+        self
+    }
+}
+
+#[async_trait]
+impl EventReceiver for ZmqReceiver {
+    async fn receive_one(&self) -> Result<Option<(Value, AckCallback)>, MessagingError> {
+        Ok(self
+            .receive_with_metadata()
+            .await?
+            .map(|(value, _, callback)| (value, callback)))
+    }
+
+    /// Metadata is the `topic` frame from publishers
+    async fn receive_with_metadata(
+        &self,
+    ) -> Result<Option<(Value, EventMetadata, AckCallback)>, MessagingError> {
+        let Some((payload, topic)) = self.messages.lock().await.recv().await else {
+            return Err(MessagingError::StreamFinished);
+        };
+        // ZeroMQ has no acknowledgements
+        let value = match self.decoder.decode(payload.as_slice()).await {
+            Ok(value) => value,
+            Err(e) => {
+                return Err(MessagingError::undecodable(
+                    payload.as_slice(),
+                    e,
+                    noop_ack_callback(),
+                ))
+            }
+        };
+        let mut metadata = EventMetadata::new();
+        if let Some(topic) = topic {
+            metadata.insert(
+                "topic".to_string(),
+                Value::String(String::from_utf8_lossy(&topic).into_owned()),
+            );
+        }
+        Ok(Some((value, metadata, noop_ack_callback())))
+    }
+}
+
+/// Submits events to a `push` or `pub` socket.
+///
+/// `pub` sockets send the routing key or configured topic as a leading frame, for subscribers to
+/// filter on.
+pub struct ZmqSubmitter {
+    socket_type: ZmqSocketType,
+    topic: Option<String>,
+    socket: Arc<Mutex<zmq::Socket>>,
+    _context: zmq::Context,
+}
+
+impl std::fmt::Debug for ZmqSubmitter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZmqSubmitter")
+            .field("socket_type", &self.socket_type)
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl ZmqSubmitter {
+    pub fn new(config: ZmqConfig) -> Result<Self, MessagingError> {
+        if !matches!(config.socket, ZmqSocketType::Push | ZmqSocketType::Pub) {
+            return Err(MessagingError::ConfigError(
+                "ZeroMQ targets must use a push or pub socket".to_string(),
+            ));
+        }
+        let context = zmq::Context::new();
+        let socket = config.open(&context)?;
+        Ok(Self {
+            socket_type: config.socket,
+            topic: config.topic,
+            socket: Arc::new(Mutex::new(socket)),
+            _context: context,
+        })
+    }
+}
+
+#[async_trait]
+impl EventSubmitter for ZmqSubmitter {
+    async fn submit(&self, payload: Value, routing: Option<&str>) -> Result<(), MessagingError> {
+        self.submit_encoded(serde_json::to_vec(&payload)?, "application/json", routing)
+            .await
+    }
+
+    async fn submit_encoded(
+        &self,
+        payload: Vec<u8>,
+        _content_type: &str,
+        routing: Option<&str>,
+    ) -> Result<(), MessagingError> {
+        let mut frames: Vec<Vec<u8>> = Vec::with_capacity(2);
+        if self.socket_type == ZmqSocketType::Pub {
+            let topic = routing.or(self.topic.as_deref()).unwrap_or_default();
+            frames.push(topic.as_bytes().to_vec());
+        }
+        frames.push(payload);
+        let socket = self.socket.clone();
+        // Sends block while the socket is at its high water mark
+        tokio::task::spawn_blocking(move || {
+            socket
+                .lock()
+                .map_err(|e| MessagingError::SubmissionError(e.to_string()))?
+                .send_multipart(frames, 0)
+                .map_err(|e| MessagingError::SubmissionError(e.to_string()))
+        })
+        .await
+        .map_err(|e| MessagingError::SubmissionError(e.to_string()))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+
+    /// Endpoint on a port that's free to bind
+    fn endpoint() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("tcp://{}", listener.local_addr().unwrap())
+    }
+
+    fn config(yaml: &str) -> ZmqConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_config() {
+        let sub = config("socket: sub\nendpoint: tcp://localhost:5556\ntopics: [orders]");
+        assert_eq!(sub.socket, ZmqSocketType::Sub);
+        assert!(!sub.bind);
+        assert_eq!(sub.topics, vec!["orders"]);
+        assert!(!sub.shares_messages());
+        assert!(config("socket: pull\nendpoint: tcp://*:5557\nbind: true").shares_messages());
+
+        assert!(
+            serde_yaml::from_str::<ZmqConfig>("socket: router\nendpoint: tcp://*:5557").is_err()
+        );
+        assert!(serde_yaml::from_str::<ZmqConfig>("socket: pull").is_err());
+        // Sockets only go the one way
+        let push = config("socket: push\nendpoint: tcp://localhost:5557");
+        assert!(matches!(
+            ZmqReceiver::new(push),
+            Err(MessagingError::ConfigError(_))
+        ));
+        let sub = config("socket: sub\nendpoint: tcp://localhost:5556");
+        assert!(matches!(
+            ZmqSubmitter::new(sub),
+            Err(MessagingError::ConfigError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_pushed_payloads_are_received() {
+        let endpoint = endpoint();
+        let submitter = ZmqSubmitter::new(config(&format!(
+            "socket: push\nendpoint: {}\nbind: true",
+            endpoint
+        )))
+        .unwrap();
+        let receiver =
+            ZmqReceiver::new(config(&format!("socket: pull\nendpoint: {}", endpoint))).unwrap();
+
+        submitter
+            .submit(json!({"orderId": 1}), Some("ignored"))
+            .await
+            .unwrap();
+        let (value, metadata, _) = receiver.receive_with_metadata().await.unwrap().unwrap();
+        assert_eq!(value, json!({"orderId": 1}));
+        // Only publishers send a topic frame
+        assert!(metadata.get("topic").is_none());
+
+        submitter
+            .submit_encoded(b"not json".to_vec(), "application/json", None)
+            .await
+            .unwrap();
+        let Err(MessagingError::Undecodable { message, .. }) =
+            receiver.receive_with_metadata().await
+        else {
+            panic!("Expected the message to be undecodable");
+        };
+        assert_eq!(message.raw, "not json");
+    }
+
+    #[tokio::test]
+    async fn test_published_topic_is_metadata() {
+        let endpoint = endpoint();
+        let receiver = ZmqReceiver::new(config(&format!(
+            "socket: sub\nendpoint: {}\nbind: true\ntopics: [orders]",
+            endpoint
+        )))
+        .unwrap();
+        let submitter = ZmqSubmitter::new(config(&format!(
+            "socket: pub\nendpoint: {}\ntopic: orders.default",
+            endpoint
+        )))
+        .unwrap();
+
+        // Publishes are dropped until the subscription has reached the publisher
+        let (value, metadata) = loop {
+            submitter
+                .submit(json!({"skipped": true}), Some("payments"))
+                .await
+                .unwrap();
+            submitter.submit(json!({"orderId": 1}), None).await.unwrap();
+            let received =
+                tokio::time::timeout(Duration::from_millis(50), receiver.receive_with_metadata())
+                    .await;
+            if let Ok(received) = received {
+                let (value, metadata, _) = received.unwrap().unwrap();
+                break (value, metadata);
+            }
+        };
+        // Topics the socket isn't subscribed to are filtered out by ZeroMQ
+        assert_eq!(value, json!({"orderId": 1}));
+        assert_eq!(metadata["topic"], json!("orders.default"));
+    }
+}
@@ -0,0 +1,122 @@
+use dashmap::{DashMap, DashSet};
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// State shared between a runtime and whatever is inspecting it while it runs, such as the admin
+/// API. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeControls {
+    inner: Arc<ControlState>,
+}
+
+#[derive(Debug, Default)]
+struct ControlState {
+    /// Rules loaded into the runtime, with their priorities, in evaluation order
+    rules: Vec<(String, i32)>,
+    disabled: DashSet<String>,
+    fires: DashMap<String, u64>,
+    pending_wakeups: AtomicUsize,
+}
+
+/// A loaded rule, and what it has done since the runtime started
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RuleStatus {
+    pub name: String,
+    pub priority: i32,
+    pub enabled: bool,
+    /// Times the rule's condition has been satisfied
+    pub fires: u64,
+}
+
+impl RuntimeControls {
+    pub fn new(rules: Vec<(String, i32)>) -> Self {
+        Self {
+            inner: Arc::new(ControlState {
+                rules,
+                ..ControlState::default()
+            }),
+        }
+    }
+
+    pub fn rules(&self) -> Vec<RuleStatus> {
+        self.inner
+            .rules
+            .iter()
+            .map(|(name, priority)| self.status(name, *priority))
+            .collect()
+    }
+
+    pub fn rule(&self, name: &str) -> Option<RuleStatus> {
+        self.inner
+            .rules
+            .iter()
+            .find(|(rule, _)| rule == name)
+            .map(|(name, priority)| self.status(name, *priority))
+    }
+
+    /// Stop evaluating a rule until it's enabled again, returning its status if it's loaded
+    pub fn disable_rule(&self, name: &str) -> Option<RuleStatus> {
+        self.rule(name)?;
+        self.inner.disabled.insert(name.to_string());
+        self.rule(name)
+    }
+
+    /// Evaluate a disabled rule again, returning its status if it's loaded
+    pub fn enable_rule(&self, name: &str) -> Option<RuleStatus> {
+        self.inner.disabled.remove(name);
+        self.rule(name)
+    }
+
+    pub fn rule_enabled(&self, name: &str) -> bool {
+        !self.inner.disabled.contains(name)
+    }
+
+    /// Wakeups waiting to be processed
+    pub fn pending_wakeups(&self) -> usize {
+        self.inner.pending_wakeups.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn fired(&self, name: &str) {
+        match self.inner.fires.get_mut(name) {
+            Some(mut fires) => *fires += 1,
+            None => {
+                self.inner.fires.insert(name.to_string(), 1);
+            }
+        }
+    }
+
+    pub(crate) fn set_pending_wakeups(&self, pending: usize) {
+        self.inner.pending_wakeups.store(pending, Ordering::Relaxed);
+    }
+
+    fn status(&self, name: &str, priority: i32) -> RuleStatus {
+        RuleStatus {
+            name: name.to_string(),
+            priority,
+            enabled: self.rule_enabled(name),
+            fires: self.inner.fires.get(name).map(|fires| *fires).unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disable_and_enable_rule() {
+        let controls = RuntimeControls::new(vec![("purchase".to_string(), 0)]);
+        let shared = controls.clone();
+        shared.fired("purchase");
+
+        let disabled = controls.disable_rule("purchase").unwrap();
+        assert!(!disabled.enabled);
+        assert_eq!(disabled.fires, 1);
+        assert!(!shared.rule_enabled("purchase"));
+
+        assert!(controls.enable_rule("purchase").unwrap().enabled);
+        assert!(controls.disable_rule("unknown").is_none());
+        assert!(controls.rule_enabled("unknown"));
+    }
+}
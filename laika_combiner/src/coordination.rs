@@ -0,0 +1,357 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::utils::env::resolve_secret;
+use crate::utils::parse_time::parse_time_str;
+use async_trait::async_trait;
+use fs2::FileExt;
+use serde::Deserialize;
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio_postgres::NoTls;
+
+/// How instances agree which of them is active, so a standby can take over processing and
+/// timers when the leader disappears
+#[derive(Clone, Debug, Deserialize)]
+pub struct CoordinationConfig {
+    #[serde(flatten)]
+    lock: LeaderLockConfig,
+    /// How often the leader checks it still holds the lock, and the standby tries to take it
+    #[serde(default = "CoordinationConfig::default_interval")]
+    interval: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum LeaderLockConfig {
+    /// Exclusive lock on a file, on a filesystem shared by both instances
+    File { path: PathBuf },
+    /// Session-level advisory lock, held for as long as the connection is open
+    #[serde(rename_all = "camelCase")]
+    Postgres { connection: String, lock_id: i64 },
+    /// Lease on a key, renewed by the leader and expiring `ttl` after it stops
+    Redis {
+        url: String,
+        #[serde(default = "LeaderLockConfig::default_key")]
+        key: String,
+        #[serde(default = "LeaderLockConfig::default_ttl")]
+        ttl: String,
+    },
+}
+
+impl CoordinationConfig {
+    fn default_interval() -> String {
+        "2s".to_string()
+    }
+}
+
+impl LeaderLockConfig {
+    fn default_key() -> String {
+        "laika:leader".to_string()
+    }
+
+    fn default_ttl() -> String {
+        "10s".to_string()
+    }
+}
+
+/// A lock only one instance can hold at a time
+#[async_trait]
+trait LeaderLock: Send {
+    /// Take the lock if it's free, returning whether this instance now holds it
+    async fn try_acquire(&mut self) -> LaikaResult<bool>;
+
+    /// Keep holding the lock, returning whether it's still held
+    async fn renew(&mut self) -> LaikaResult<bool>;
+}
+
+struct FileLock {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+#[async_trait]
+impl LeaderLock for FileLock {
+    async fn try_acquire(&mut self) -> LaikaResult<bool> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.path)
+            .map_err(|e| LaikaError::IO(e.to_string()))?;
+        if file.try_lock_exclusive().is_err() {
+            return Ok(false);
+        }
+        self.file = Some(file);
+        Ok(true)
+    }
+
+    async fn renew(&mut self) -> LaikaResult<bool> {
+        // Locks are only released when the file is closed, on exit
+        Ok(self.file.is_some())
+    }
+}
+
+struct PostgresLock {
+    connection: String,
+    lock_id: i64,
+    client: Option<tokio_postgres::Client>,
+}
+
+impl PostgresLock {
+    /// Client on an open connection, reconnecting if it's been closed
+    async fn client(&mut self) -> LaikaResult<&tokio_postgres::Client> {
+        if self.client.as_ref().is_none_or(|client| client.is_closed()) {
+            let (client, connection) = tokio_postgres::connect(&self.connection, NoTls)
+                .await
+                .map_err(|e| LaikaError::IO(e.to_string()))?;
+            tokio::spawn(async move {
+                if let Err(e) = connection.await {
+                    tracing::error!("Postgres coordination connection failed: {}", e);
+                }
+            });
+            self.client = Some(client);
+        }
+        Ok(self.client.as_ref().expect("Client was just connected"))
+    }
+}
+
+#[async_trait]
+impl LeaderLock for PostgresLock {
+    async fn try_acquire(&mut self) -> LaikaResult<bool> {
+        let lock_id = self.lock_id;
+        self.client()
+            .await?
+            .query_one("SELECT pg_try_advisory_lock($1)", &[&lock_id])
+            .await
+            .map(|row| row.get(0))
+            .map_err(|e| LaikaError::IO(e.to_string()))
+    }
+
+    async fn renew(&mut self) -> LaikaResult<bool> {
+        // The lock goes with the session, so it's held for as long as the connection is
+        let Some(client) = self.client.as_ref().filter(|client| !client.is_closed()) else {
+            return Ok(false);
+        };
+        Ok(client.query_one("SELECT 1", &[]).await.is_ok())
+    }
+}
+
+#[cfg(feature = "redis")]
+struct RedisLease {
+    client: redis::Client,
+    connection: Option<redis::aio::MultiplexedConnection>,
+    key: String,
+    ttl: Duration,
+    /// Written to the key, so only this instance renews its own lease
+    holder: String,
+}
+
+/// Lease on `key` at the Redis server at `url`
+#[cfg(feature = "redis")]
+fn redis_lease(url: &str, key: &str, ttl: Duration) -> LaikaResult<Box<dyn LeaderLock>> {
+    Ok(Box::new(RedisLease {
+        client: redis::Client::open(resolve_secret(url)?.as_str())
+            .map_err(|e| LaikaError::config("coordination.url", e.to_string()))?,
+        connection: None,
+        key: key.to_string(),
+        ttl,
+        holder: uuid::Uuid::new_v4().to_string(),
+    }))
+}
+
+#[cfg(not(feature = "redis"))]
+fn redis_lease(_url: &str, _key: &str, _ttl: Duration) -> LaikaResult<Box<dyn LeaderLock>> {
+    Err(LaikaError::config(
+        "coordination.type",
+        "Laika was built without the redis feature",
+    ))
+}
+
+#[cfg(feature = "redis")]
+impl RedisLease {
+    async fn connection(&mut self) -> LaikaResult<&mut redis::aio::MultiplexedConnection> {
+        if self.connection.is_none() {
+            self.connection = Some(
+                self.client
+                    .get_multiplexed_async_connection()
+                    .await
+                    .map_err(|e| LaikaError::IO(e.to_string()))?,
+            );
+        }
+        Ok(self
+            .connection
+            .as_mut()
+            .expect("Connection was just opened"))
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl LeaderLock for RedisLease {
+    async fn try_acquire(&mut self) -> LaikaResult<bool> {
+        let key = self.key.clone();
+        let holder = self.holder.clone();
+        let ttl = self.ttl.as_millis() as u64;
+        let set: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(holder)
+            .arg("NX")
+            .arg("PX")
+            .arg(ttl)
+            .query_async(self.connection().await?)
+            .await
+            .map_err(|e| LaikaError::IO(e.to_string()))?;
+        Ok(set.is_some())
+    }
+
+    async fn renew(&mut self) -> LaikaResult<bool> {
+        let script = redis::Script::new(
+            r"if redis.call('GET', KEYS[1]) == ARGV[1] then
+                return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+            end
+            return 0",
+        );
+        let key = self.key.clone();
+        let holder = self.holder.clone();
+        let ttl = self.ttl.as_millis() as u64;
+        let renewed: i64 = script
+            .key(key)
+            .arg(holder)
+            .arg(ttl)
+            .invoke_async(self.connection().await?)
+            .await
+            .map_err(|e| LaikaError::IO(e.to_string()))?;
+        Ok(renewed == 1)
+    }
+}
+
+/// Which of a pair of instances is active.
+///
+/// A standby waits in `acquire` until the leader goes away, and only then connects to its
+/// sources and loads timers. The leader stops as soon as it can't confirm it still leads, so two
+/// instances never process at once.
+pub struct Leadership {
+    lock: Box<dyn LeaderLock>,
+    interval: Duration,
+}
+
+impl Leadership {
+    pub fn new(config: &CoordinationConfig) -> LaikaResult<Self> {
+        let duration = |field: &str, raw: &str| {
+            parse_time_str(raw)
+                .map(|duration| duration.unsigned_abs())
+                .map_err(|e| LaikaError::config(field, e.to_string()))
+        };
+        let interval = duration("coordination.interval", &config.interval)?;
+        let lock: Box<dyn LeaderLock> = match &config.lock {
+            LeaderLockConfig::File { path } => Box::new(FileLock {
+                path: path.clone(),
+                file: None,
+            }),
+            LeaderLockConfig::Postgres {
+                connection,
+                lock_id,
+            } => Box::new(PostgresLock {
+                connection: resolve_secret(connection)?,
+                lock_id: *lock_id,
+                client: None,
+            }),
+            LeaderLockConfig::Redis { url, key, ttl } => {
+                let ttl = duration("coordination.ttl", ttl)?;
+                if ttl <= interval {
+                    return Err(LaikaError::config(
+                        "coordination.ttl",
+                        "ttl must be longer than interval, or the lease expires between renewals",
+                    ));
+                }
+                redis_lease(url, key, ttl)?
+            }
+        };
+        Ok(Self { lock, interval })
+    }
+
+    /// Stand by until this instance becomes the leader. Failures to reach the lock are retried,
+    /// as the leader may still hold it.
+    pub async fn acquire(&mut self) {
+        let mut standing_by = false;
+        loop {
+            match self.lock.try_acquire().await {
+                Ok(true) => {
+                    tracing::info!("Became leader, starting processing");
+                    return;
+                }
+                Ok(false) if !standing_by => {
+                    tracing::info!("Another instance is leader, standing by");
+                    standing_by = true;
+                }
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Could not check for leadership: {}", e),
+            }
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Keep leading, returning once leadership has been lost or can't be confirmed
+    pub async fn hold(mut self) {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            match self.lock.renew().await {
+                Ok(true) => {}
+                Ok(false) => {
+                    tracing::error!("Lost leadership to another instance");
+                    return;
+                }
+                Err(e) => {
+                    tracing::error!("Could not confirm leadership: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[tokio::test]
+    async fn test_file_lock_has_one_leader() {
+        let temp = NamedTempFile::new().unwrap();
+        let config: CoordinationConfig = serde_yaml::from_str(&format!(
+            "type: file\npath: {}\ninterval: 10ms",
+            temp.path().display()
+        ))
+        .unwrap();
+        let mut leader = Leadership::new(&config).unwrap();
+        let mut standby = Leadership::new(&config).unwrap();
+        assert!(leader.lock.try_acquire().await.unwrap());
+        assert!(!standby.lock.try_acquire().await.unwrap());
+        assert!(leader.lock.renew().await.unwrap());
+
+        drop(leader);
+        assert!(standby.lock.try_acquire().await.unwrap());
+    }
+
+    #[test]
+    fn test_redis_ttl_outlasts_interval() {
+        let config: CoordinationConfig =
+            serde_yaml::from_str("type: redis\nurl: redis://localhost\nttl: 1s\ninterval: 2s")
+                .unwrap();
+        assert!(matches!(
+            Leadership::new(&config),
+            Err(LaikaError::ConfigError { path, .. }) if path == "coordination.ttl"
+        ));
+    }
+
+    #[test]
+    fn test_unknown_fields_rejected() {
+        for yaml in [
+            "type: postgres\nconnection: host=localhost\nlockid: 1",
+            "type: file\npath: /var/lib/laika/leader\nintervall: 1s",
+        ] {
+            let error = serde_yaml::from_str::<CoordinationConfig>(yaml).unwrap_err();
+            assert!(error.to_string().contains("unknown field"), "{}", error);
+        }
+    }
+}
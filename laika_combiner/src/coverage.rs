@@ -0,0 +1,267 @@
+use crate::clock::TestClock;
+use crate::config::EventProcessorConfig;
+use crate::errors::LaikaResult;
+use crate::explain::EventExplanation;
+use crate::storage::StorageKV;
+use crate::timing::TimingExpiry;
+use crate::Runtime;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+/// An event from a corpus, with the connection it's received from and when, if known
+pub struct CorpusEvent {
+    pub source: String,
+    /// When the event is received, where it isn't straight after the one before
+    pub received: Option<OffsetDateTime>,
+    pub event: Value,
+}
+
+/// What a corpus of events exercised in a config
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverageReport {
+    pub events: u64,
+    /// Events that matched no event type
+    pub unmatched: u64,
+    /// Events matched by each event type, including those never matched
+    pub event_types: BTreeMap<String, u64>,
+    pub rules: BTreeMap<String, RuleCoverage>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleCoverage {
+    /// Times the rule was checked against an event
+    pub evaluated: u64,
+    /// Times its requirements were satisfied when it was checked
+    pub requirements_met: u64,
+    /// Times its condition was satisfied, including from wakeups
+    pub fires: u64,
+    /// Required event types it was missing that no event in the corpus matched
+    pub never_matched: BTreeSet<String>,
+}
+
+impl CoverageReport {
+    fn new(config: &EventProcessorConfig) -> Self {
+        Self {
+            event_types: config
+                .event_types()
+                .map(|event_type| (event_type.to_string(), 0))
+                .collect(),
+            ..Self::default()
+        }
+    }
+
+    fn record(&mut self, explanations: &[EventExplanation]) {
+        self.events += 1;
+        if explanations.is_empty() {
+            self.unmatched += 1;
+        }
+        for explanation in explanations {
+            *self
+                .event_types
+                .entry(explanation.event_type.clone())
+                .or_default() += 1;
+            for rule in &explanation.rules {
+                let coverage = self.rules.entry(rule.rule.clone()).or_default();
+                if rule.skipped.is_some() {
+                    continue;
+                }
+                coverage.evaluated += 1;
+                match &rule.requirement {
+                    Some(requirement) if requirement.met => coverage.requirements_met += 1,
+                    Some(requirement) => coverage
+                        .never_matched
+                        .extend(requirement.missing.iter().cloned()),
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Rules that never fired
+    pub fn dead_rules(&self) -> impl Iterator<Item = &str> {
+        self.rules
+            .iter()
+            .filter(|(_, coverage)| coverage.fires == 0)
+            .map(|(rule, _)| rule.as_str())
+    }
+
+    /// Event types no event matched
+    pub fn unmatched_event_types(&self) -> impl Iterator<Item = &str> {
+        self.event_types
+            .iter()
+            .filter(|(_, matched)| **matched == 0)
+            .map(|(event_type, _)| event_type.as_str())
+    }
+}
+
+impl Display for CoverageReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} events, {} matched no event type",
+            self.events, self.unmatched
+        )?;
+        writeln!(f, "Event types:")?;
+        for (event_type, matched) in &self.event_types {
+            writeln!(f, "  {:<8} {}", matched, event_type)?;
+        }
+        writeln!(f, "Rules (evaluated, requirements met, fired):")?;
+        for (rule, coverage) in &self.rules {
+            write!(
+                f,
+                "  {:<8} {:<8} {:<8} {}",
+                coverage.evaluated, coverage.requirements_met, coverage.fires, rule
+            )?;
+            if !coverage.never_matched.is_empty() {
+                let missing: Vec<&str> =
+                    coverage.never_matched.iter().map(String::as_str).collect();
+                write!(f, " (needs unmatched {})", missing.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a corpus of events through the rules and report which event types, requirements and rules
+/// it exercised, to find rules that can never fire and match keys that never match.
+///
+/// Events run in memory on a clock that moves to when each is received, and on to `until` after
+/// the last, so timing rules fire as they would have. Lookups aren't made, so events should carry
+/// what they'd have added.
+pub fn run_coverage(
+    config: EventProcessorConfig,
+    events: Vec<CorpusEvent>,
+    until: Option<OffsetDateTime>,
+) -> LaikaResult<CoverageReport> {
+    let mut report = CoverageReport::new(&config);
+    let start = events
+        .iter()
+        .find_map(|event| event.received)
+        .unwrap_or_else(OffsetDateTime::now_utc);
+    let timer_path = std::env::temp_dir().join(format!("laika_coverage_{}", std::process::id()));
+    let clock = TestClock::new(start);
+    let max_wakeups = config.storage().max_wakeups();
+    let mut runtime = Runtime::new_with_clock(
+        vec![config.build()?],
+        StorageKV::in_memory(),
+        TimingExpiry::new(timer_path.clone())?.with_max_wakeups(max_wakeups),
+        Arc::new(clock.clone()),
+    )?;
+
+    let result = (|| {
+        for event in events {
+            if let Some(received) = event.received {
+                runtime.advance_to(&clock, received)?;
+            }
+            report.record(&runtime.explain(event.source.as_str(), event.event.clone())?);
+            runtime.push_event(event.source.as_str(), event.event)?;
+            runtime.poll_actions()?;
+        }
+        match until {
+            Some(until) => runtime.advance_to(&clock, until).map(|_| ()),
+            None => Ok(()),
+        }
+    })();
+    let _ = std::fs::remove_file(&timer_path);
+    result?;
+
+    for status in runtime.controls().rules() {
+        report.rules.entry(status.name).or_default().fires = status.fires;
+    }
+    // Missing types only point at a problem when nothing in the corpus matched them
+    let matched: BTreeSet<String> = report
+        .event_types
+        .iter()
+        .filter(|(_, matched)| **matched > 0)
+        .map(|(event_type, _)| event_type.clone())
+        .collect();
+    for coverage in report.rules.values_mut() {
+        coverage
+            .never_matched
+            .retain(|missing| !matched.contains(missing));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::builder::EventProcessorYamlSpec;
+
+    const CONFIG: &str = r#"
+connections:
+  orders:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+  paid:
+    from: orders
+    matchKey:
+      type: payed
+correlation:
+  placed:
+    key: "$.orderId"
+  paid:
+    key: "$.orderId"
+triggers:
+  orderPlaced:
+    requires:
+      at_least: [placed]
+    filterAndExtract: >
+      (trigger, ctx) => ({ orderId: trigger.event.orderId })
+    action:
+      target: alerts
+      payload:
+        orderId: "${{ orderId }}"
+  orderPaid:
+    requires:
+      at_least: [placed, paid]
+    filterAndExtract: >
+      (trigger, ctx) => ({ orderId: trigger.event.orderId })
+    action:
+      target: alerts
+      payload:
+        orderId: "${{ orderId }}"
+"#;
+
+    #[test]
+    fn test_coverage_finds_dead_rules_and_unmatched_types() {
+        let spec = EventProcessorYamlSpec::from_yaml(CONFIG).unwrap();
+        let config = EventProcessorConfig::try_from(&spec).unwrap();
+        let events = [
+            serde_json::json!({"type": "placed", "orderId": "1"}),
+            serde_json::json!({"type": "paid", "orderId": "1"}),
+        ]
+        .into_iter()
+        .map(|event| CorpusEvent {
+            source: "orders".to_string(),
+            received: None,
+            event,
+        })
+        .collect();
+        let report = run_coverage(config, events, None).unwrap();
+        assert_eq!(report.events, 2);
+        assert_eq!(report.unmatched, 1);
+        assert_eq!(
+            report.unmatched_event_types().collect::<Vec<_>>(),
+            vec!["paid"]
+        );
+        assert_eq!(report.dead_rules().collect::<Vec<_>>(), vec!["orderPaid"]);
+        assert_eq!(
+            report.rules["orderPaid"].never_matched,
+            BTreeSet::from(["paid".to_string()])
+        );
+    }
+}
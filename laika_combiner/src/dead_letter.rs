@@ -0,0 +1,162 @@
+use crate::errors::LaikaError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::time::Duration;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DeadLetterConfig {
+    /// Connection that messages are sent to once they've been given up on
+    pub target: String,
+    /// Attempts at handling a message before it's given up on
+    #[serde(default = "DeadLetterConfig::default_max_attempts")]
+    pub max_attempts: u32,
+}
+
+impl DeadLetterConfig {
+    fn default_max_attempts() -> u32 {
+        3
+    }
+}
+
+/// Wait before the first retry of a message, doubled for each attempt after
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Payload sent to the dead-letter target for a message that's been given up on
+pub fn dead_letter(source: &str, message: &Value, error: &LaikaError, attempts: u32) -> Value {
+    json!({
+        "source": source,
+        "event": message,
+        "error": error.to_string(),
+        "attempts": attempts,
+        "failedAt": OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+    })
+}
+
+/// Tracks failed attempts at handling received messages, deciding when to give up on them.
+///
+/// Messages are told apart by a hash of their source and payload, as not every source gives
+/// messages an ID. Errors that can't succeed on another attempt, such as a rule failing to
+/// evaluate, are given up on straight away.
+///
+/// Attempts are only counted in memory, so a message being retried when Laika restarts starts
+/// again from its first attempt.
+pub struct DeadLetters {
+    config: DeadLetterConfig,
+    failures: HashMap<u64, u32>,
+}
+
+impl DeadLetters {
+    pub fn new(config: DeadLetterConfig) -> Self {
+        Self {
+            config,
+            failures: HashMap::new(),
+        }
+    }
+
+    /// Connection given up messages should be sent to
+    pub fn target(&self) -> &str {
+        self.config.target.as_str()
+    }
+
+    fn message_key(source: &str, message: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        message.to_string().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Record a failed attempt at handling a message. Returns the payload to send to the
+    /// dead-letter target once the message shouldn't be retried, or `None` to try again.
+    pub fn failed(&mut self, source: &str, message: &Value, error: &LaikaError) -> Option<Value> {
+        let key = Self::message_key(source, message);
+        let attempts = self.failures.entry(key).or_default();
+        *attempts += 1;
+        if error.is_retryable() && *attempts < self.config.max_attempts {
+            return None;
+        }
+        let attempts = self.failures.remove(&key).unwrap_or_default();
+        Some(dead_letter(source, message, error, attempts))
+    }
+
+    /// How long to wait before retrying a message, doubling with each failed attempt up to
+    /// `MAX_RETRY_DELAY`. Half of it is random, so retries against a struggling target spread out.
+    pub fn retry_delay(&self, source: &str, message: &Value) -> Duration {
+        let attempts = self
+            .failures
+            .get(&Self::message_key(source, message))
+            .copied()
+            .unwrap_or(1);
+        let delay = RETRY_BASE_DELAY
+            .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+            .min(MAX_RETRY_DELAY);
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay / 2 + (delay / 2).mul_f64(jitter)
+    }
+
+    /// Forget earlier failures of a message that has now been handled
+    pub fn handled(&mut self, source: &str, message: &Value) {
+        if !self.failures.is_empty() {
+            self.failures.remove(&Self::message_key(source, message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorSource;
+
+    #[test]
+    fn test_dead_letter_after_attempts() {
+        let mut dead_letters = DeadLetters::new(DeadLetterConfig {
+            target: "deadLetters".to_string(),
+            max_attempts: 3,
+        });
+        let message = json!({"orderId": 1});
+        let error = LaikaError::StorageError {
+            source: ErrorSource::message("busy"),
+        };
+        assert!(dead_letters.failed("orders", &message, &error).is_none());
+        assert!(dead_letters.failed("orders", &message, &error).is_none());
+        let dead_letter = dead_letters.failed("orders", &message, &error).unwrap();
+        assert_eq!(dead_letter["attempts"], 3);
+        assert_eq!(dead_letter["event"], message);
+        assert_eq!(dead_letter["error"], "Storage error: busy");
+
+        // Errors that won't go away by retrying are given up on straight away
+        let error = LaikaError::PredicateError {
+            rule: "orderShipped".to_string(),
+            source: ErrorSource::message("ReferenceError"),
+        };
+        assert!(dead_letters.failed("orders", &message, &error).is_some());
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off() {
+        let mut dead_letters = DeadLetters::new(DeadLetterConfig {
+            target: "deadLetters".to_string(),
+            max_attempts: 20,
+        });
+        let message = json!({"orderId": 1});
+        let error = LaikaError::StorageError {
+            source: ErrorSource::message("busy"),
+        };
+        for ceiling in [100, 200, 400, 800] {
+            assert!(dead_letters.failed("orders", &message, &error).is_none());
+            let delay = dead_letters.retry_delay("orders", &message);
+            let ceiling = Duration::from_millis(ceiling);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?}", delay);
+        }
+        for _ in 0..10 {
+            dead_letters.failed("orders", &message, &error);
+        }
+        assert!(dead_letters.retry_delay("orders", &message) <= MAX_RETRY_DELAY);
+    }
+}
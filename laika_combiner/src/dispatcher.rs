@@ -0,0 +1,454 @@
+use crate::action::EventAction;
+use crate::batching::{EmitBatcher, EmitBatching};
+use crate::broker::CorrelationId;
+use crate::connections::{AckCallback, Connections};
+use crate::errors::{LaikaError, LaikaResult};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::sync::{oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DispatchConfig {
+    /// Actions being sent at once across every target
+    #[serde(default = "DispatchConfig::default_concurrency")]
+    pub concurrency: usize,
+    /// Actions waiting to be sent or in flight at once, including those held up behind an
+    /// earlier action for their correlation. Received messages aren't taken on while this many
+    /// are pending.
+    #[serde(default)]
+    pub max_pending: Option<usize>,
+}
+
+impl DispatchConfig {
+    fn default_concurrency() -> usize {
+        32
+    }
+
+    /// Pending actions allowed at once, eight times `concurrency` unless set
+    pub fn max_pending(&self) -> usize {
+        self.max_pending.unwrap_or(self.concurrency * 8)
+    }
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: Self::default_concurrency(),
+            max_pending: None,
+        }
+    }
+}
+
+/// Whether actions to a target are delivered in the order they were produced
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryOrdering {
+    /// Actions for the same correlation are delivered one after another, in order
+    #[default]
+    PerCorrelation,
+    /// Actions are delivered as soon as there's capacity, in any order
+    None,
+}
+
+/// Actions taken one after another, being for the same correlation and the same target. HTTP
+/// actions have no target, so share one.
+type Lane = (Option<String>, CorrelationId);
+
+/// Takes actions concurrently on background tasks, so a slow target doesn't hold up the others.
+///
+/// Actions for the same correlation and target are still taken in the order they were produced,
+/// unless the target is unordered, and messages are acked in the order they were dispatched, each
+/// once its batch's actions have all been taken.
+///
+/// An action only counts towards the actions in flight once it's its turn in its lane and its
+/// target has capacity, so a correlation waiting on a slow target doesn't hold up the others.
+pub struct Dispatcher {
+    connections: Arc<Connections>,
+    // Actions dispatched and not yet taken, which dispatching waits on
+    pending: Arc<Semaphore>,
+    // Actions being sent
+    in_flight: Arc<Semaphore>,
+    targets: HashMap<String, Arc<Semaphore>>,
+    // Targets with `DeliveryOrdering::None`, whose actions don't wait for each other
+    unordered: HashSet<String>,
+    // Targets whose emits are grouped into batches
+    batchers: HashMap<String, Arc<EmitBatcher>>,
+    // Completion of the last action in each lane, which the lane's next action waits for
+    lanes: HashMap<Lane, oneshot::Receiver<()>>,
+    // Completion of the last batch's acks, whether or not they succeeded
+    acked: Option<oneshot::Receiver<bool>>,
+    batches: JoinSet<LaikaResult<()>>,
+}
+
+impl Dispatcher {
+    pub fn new(connections: Arc<Connections>, config: &DispatchConfig) -> Self {
+        Self {
+            connections,
+            pending: Arc::new(Semaphore::new(config.max_pending())),
+            in_flight: Arc::new(Semaphore::new(config.concurrency)),
+            targets: HashMap::new(),
+            unordered: HashSet::new(),
+            batchers: HashMap::new(),
+            lanes: HashMap::new(),
+            acked: None,
+            batches: JoinSet::new(),
+        }
+    }
+
+    /// Limit the actions in flight for a single target
+    pub fn with_target_concurrency(mut self, target: &str, concurrency: usize) -> Self {
+        self.targets
+            .insert(target.to_string(), Arc::new(Semaphore::new(concurrency)));
+        self
+    }
+
+    /// Choose whether actions to a target are delivered in order
+    pub fn with_target_ordering(mut self, target: &str, ordering: DeliveryOrdering) -> Self {
+        match ordering {
+            DeliveryOrdering::PerCorrelation => self.unordered.remove(target),
+            DeliveryOrdering::None => self.unordered.insert(target.to_string()),
+        };
+        self
+    }
+
+    /// Group emits to a target into batches, submitted together once full or delayed long enough
+    pub fn with_target_batching(mut self, target: &str, batching: EmitBatching) -> Self {
+        self.batchers.insert(
+            target.to_string(),
+            Arc::new(EmitBatcher::new(target, batching, self.connections.clone())),
+        );
+        self
+    }
+
+    /// Start taking a batch of actions, acking `callbacks` once they've all been taken. Waits
+    /// while the most actions are already pending, and fails if an earlier batch failed.
+    pub async fn dispatch(
+        &mut self,
+        actions: Vec<EventAction>,
+        callbacks: Vec<AckCallback>,
+    ) -> LaikaResult<()> {
+        self.check()?;
+        self.lanes
+            .retain(|_, done| matches!(done.try_recv(), Err(TryRecvError::Empty)));
+
+        let mut taken = Vec::with_capacity(actions.len());
+        for action in actions {
+            let lane = match &action {
+                EventAction::Emit(emit) if self.unordered.contains(&emit.target) => None,
+                EventAction::Emit(emit) => emit
+                    .correlation_id
+                    .clone()
+                    .map(|correlation_id| (Some(emit.target.clone()), correlation_id)),
+                EventAction::Http(request) => request
+                    .correlation_id
+                    .clone()
+                    .map(|correlation_id| (None, correlation_id)),
+                // Wakeups and stored values are handled within the runtime
+                EventAction::ScheduleWakeup(_)
+                | EventAction::CancelWakeup(..)
+                | EventAction::Store(_) => continue,
+            };
+            let pending = self
+                .pending
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|e| LaikaError::Generic(e.to_string()))?;
+            let previous = match lane {
+                Some(lane) => {
+                    let (done, next) = oneshot::channel();
+                    Some((done, self.lanes.insert(lane, next)))
+                }
+                None => None,
+            };
+            taken.push(self.take(action, pending, previous));
+        }
+
+        let previous_batch = self.acked.take();
+        let (acked, next_batch) = oneshot::channel();
+        self.acked = Some(next_batch);
+        self.batches.spawn(async move {
+            let mut failure = None;
+            for action in taken {
+                let result = action
+                    .await
+                    .unwrap_or_else(|e| Err(LaikaError::Generic(e.to_string())));
+                if let Err(e) = result {
+                    failure.get_or_insert(e);
+                }
+            }
+            // An earlier batch that failed has left its messages unacked, so acking these would
+            // move sources with cursors past them
+            let earlier_acked = match previous_batch {
+                Some(previous) => previous.await.unwrap_or(false),
+                None => true,
+            };
+            if let Some(e) = failure {
+                let _ = acked.send(false);
+                return Err(e);
+            }
+            if !earlier_acked {
+                tracing::warn!("Not acking messages after an earlier batch failed");
+                let _ = acked.send(false);
+                return Ok(());
+            }
+            for callback in callbacks {
+                if let Err(e) = callback().await {
+                    let _ = acked.send(false);
+                    return Err(e);
+                }
+            }
+            let _ = acked.send(true);
+            Ok(())
+        });
+        Ok(())
+    }
+
+    fn take(
+        &self,
+        action: EventAction,
+        pending: OwnedSemaphorePermit,
+        previous: Option<(oneshot::Sender<()>, Option<oneshot::Receiver<()>>)>,
+    ) -> JoinHandle<LaikaResult<()>> {
+        let connections = self.connections.clone();
+        let in_flight = self.in_flight.clone();
+        let target = match &action {
+            EventAction::Emit(emit) => self.targets.get(&emit.target).cloned(),
+            _ => None,
+        };
+        let batcher = match &action {
+            EventAction::Emit(emit) if EmitBatcher::batches(emit) => {
+                self.batchers.get(&emit.target).cloned()
+            }
+            _ => None,
+        };
+        tokio::spawn(async move {
+            let _pending = pending;
+            // Held until the action's been taken, when dropping it lets the lane's next go
+            let _done = match previous {
+                Some((done, previous)) => {
+                    if let Some(previous) = previous {
+                        let _ = previous.await;
+                    }
+                    Some(done)
+                }
+                None => None,
+            };
+            // Semaphores are never closed, so acquiring them can't fail
+            let _target_permit = match target {
+                Some(target) => target.acquire_owned().await.ok(),
+                None => None,
+            };
+            let _permit = in_flight.acquire_owned().await.ok();
+            tracing::debug!("Processing {:?} action", &action);
+            match action {
+                EventAction::Emit(emit_action) => match batcher {
+                    Some(batcher) => batcher.emit(emit_action).await,
+                    None => connections.emit(emit_action).await,
+                },
+                EventAction::Http(request) => connections.request(request).await,
+                EventAction::ScheduleWakeup(_)
+                | EventAction::CancelWakeup(..)
+                | EventAction::Store(_) => Ok(()),
+            }
+        })
+    }
+
+    /// Fail with the error of any batch that's failed so far
+    pub fn check(&mut self) -> LaikaResult<()> {
+        while let Some(finished) = self.batches.try_join_next() {
+            finished.map_err(|e| LaikaError::Generic(e.to_string()))??;
+        }
+        Ok(())
+    }
+
+    /// Wait for every batch dispatched so far to be taken and acked
+    pub async fn finish(&mut self) -> LaikaResult<()> {
+        while let Some(finished) = self.batches.join_next().await {
+            finished.map_err(|e| LaikaError::Generic(e.to_string()))??;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::EmitAction;
+    use crate::connections::{EventSubmitter, MessagingError};
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+    use std::sync::Mutex;
+    use tokio::sync::mpsc;
+
+    /// Submits each payload once the test opens its gate, reporting which were submitted
+    #[derive(Debug)]
+    struct GatedSubmitter {
+        gates: Mutex<HashMap<u64, oneshot::Receiver<()>>>,
+        submitted: mpsc::UnboundedSender<u64>,
+    }
+
+    #[async_trait]
+    impl EventSubmitter for GatedSubmitter {
+        async fn submit(
+            &self,
+            payload: Value,
+            _routing: Option<&str>,
+        ) -> Result<(), MessagingError> {
+            let n = payload["n"].as_u64().unwrap();
+            let gate = self.gates.lock().unwrap().remove(&n).unwrap();
+            let _ = gate.await;
+            self.submitted.send(n).unwrap();
+            Ok(())
+        }
+    }
+
+    fn emit(correlation_id: Option<&str>, n: u64) -> EventAction {
+        EventAction::Emit(
+            EmitAction::new("gated".to_string(), json!({"n": n}))
+                .with_correlation(correlation_id.map(str::to_string)),
+        )
+    }
+
+    /// Dispatcher for payloads 1 to `count`, with the gate of each and what's been submitted
+    fn dispatcher(
+        config: &DispatchConfig,
+        count: u64,
+    ) -> (
+        Dispatcher,
+        HashMap<u64, oneshot::Sender<()>>,
+        mpsc::UnboundedReceiver<u64>,
+    ) {
+        let (openers, gates) = (1..=count)
+            .map(|n| {
+                let (open, gate) = oneshot::channel();
+                ((n, open), (n, gate))
+            })
+            .unzip();
+        let (submitted, received) = mpsc::unbounded_channel();
+        let submitter = GatedSubmitter {
+            gates: Mutex::new(gates),
+            submitted,
+        };
+        let connections = Connections::new(
+            HashMap::new(),
+            HashMap::from([(
+                "gated".to_string(),
+                Box::new(submitter) as Box<dyn EventSubmitter>,
+            )]),
+        );
+        (
+            Dispatcher::new(Arc::new(connections), config),
+            openers,
+            received,
+        )
+    }
+
+    fn ack_counter(acks: &Arc<Mutex<Vec<u64>>>, n: u64) -> AckCallback {
+        let acks = acks.clone();
+        Box::new(move || {
+            Box::pin(async move {
+                acks.lock().unwrap().push(n);
+                Ok(())
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn test_ordered_within_correlation() {
+        let (mut dispatcher, mut gates, mut submitted) = dispatcher(&DispatchConfig::default(), 3);
+        dispatcher
+            .dispatch((1..=3).map(|n| emit(Some("a"), n)).collect(), Vec::new())
+            .await
+            .unwrap();
+        // Later actions are ready first, but wait their turn
+        for n in [3, 2, 1] {
+            gates.remove(&n).unwrap().send(()).unwrap();
+        }
+        dispatcher.finish().await.unwrap();
+        let mut order = Vec::new();
+        while let Ok(n) = submitted.try_recv() {
+            order.push(n);
+        }
+        assert_eq!(order, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_without_correlation() {
+        let (mut dispatcher, mut gates, mut submitted) = dispatcher(&DispatchConfig::default(), 3);
+        dispatcher
+            .dispatch((1..=3).map(|n| emit(None, n)).collect(), Vec::new())
+            .await
+            .unwrap();
+        // Each is submitted as soon as it's ready, while the earlier ones are still waiting
+        for n in [3, 2, 1] {
+            gates.remove(&n).unwrap().send(()).unwrap();
+            assert_eq!(submitted.recv().await, Some(n));
+        }
+        dispatcher.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_waiting_lane_leaves_capacity() {
+        let config = DispatchConfig {
+            concurrency: 2,
+            max_pending: None,
+        };
+        let (mut dispatcher, mut gates, mut submitted) = dispatcher(&config, 3);
+        // The second action for a waits on the first, without taking up capacity meanwhile
+        dispatcher
+            .dispatch(
+                vec![emit(Some("a"), 1), emit(Some("a"), 2), emit(Some("b"), 3)],
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+        gates.remove(&3).unwrap().send(()).unwrap();
+        assert_eq!(submitted.recv().await, Some(3));
+        for n in [1, 2] {
+            gates.remove(&n).unwrap().send(()).unwrap();
+            assert_eq!(submitted.recv().await, Some(n));
+        }
+        dispatcher.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unordered_target() {
+        let (dispatcher, mut gates, mut submitted) = dispatcher(&DispatchConfig::default(), 3);
+        let mut dispatcher = dispatcher.with_target_ordering("gated", DeliveryOrdering::None);
+        dispatcher
+            .dispatch((1..=3).map(|n| emit(Some("a"), n)).collect(), Vec::new())
+            .await
+            .unwrap();
+        for n in [3, 2, 1] {
+            gates.remove(&n).unwrap().send(()).unwrap();
+            assert_eq!(submitted.recv().await, Some(n));
+        }
+        dispatcher.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_acked_in_dispatch_order() {
+        let (mut dispatcher, mut gates, mut submitted) = dispatcher(&DispatchConfig::default(), 3);
+        let acks = Arc::new(Mutex::new(Vec::new()));
+        for n in 1..=3 {
+            dispatcher
+                .dispatch(vec![emit(None, n)], vec![ack_counter(&acks, n)])
+                .await
+                .unwrap();
+        }
+        // Later batches are taken first, but wait for the earlier ones to be acked
+        for n in [3, 2] {
+            gates.remove(&n).unwrap().send(()).unwrap();
+            assert_eq!(submitted.recv().await, Some(n));
+        }
+        assert!(acks.lock().unwrap().is_empty());
+        gates.remove(&1).unwrap().send(()).unwrap();
+        dispatcher.finish().await.unwrap();
+        assert_eq!(*acks.lock().unwrap(), vec![1, 2, 3]);
+    }
+}
@@ -1,7 +1,10 @@
 use crate::predicate_engine::JsonPredicateError;
 use crate::template::error::TemplateError;
 use deno_core::error::CoreError;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error as StdError;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
 use thiserror::Error;
 
 pub type LaikaResult<T> = Result<T, LaikaError>;
@@ -23,9 +26,15 @@ pub enum LaikaError {
     #[error("Missing Input: {0}")]
     MissingInput(String),
 
+    #[error("Invalid tenant {0:?}, which must be non-empty and can't contain '/'")]
+    InvalidTenant(String),
+
     #[error("Event did not match ")]
     EventMatchError,
 
+    #[error("Event matched several event types: {}", .0.join(", "))]
+    AmbiguousMatch(Vec<String>),
+
     #[error("Field {0} not found in data at path {1}")]
     FieldNotFound(String, String),
 
@@ -61,8 +70,176 @@ pub enum LaikaError {
 
     #[error("More than 1 NonCorrelatedEvent was submitted in an event batch")]
     InvalidEventGroup,
+
+    #[error("Storage error: {source}")]
+    StorageError {
+        #[source]
+        source: ErrorSource,
+    },
+
+    #[error("Could not submit to {target}: {source}")]
+    SubmitError {
+        target: String,
+        /// Whether the same submission could succeed if tried again
+        retryable: bool,
+        #[source]
+        source: ErrorSource,
+    },
+
+    #[error("Lookup {lookup} failed: {source}")]
+    LookupError {
+        lookup: String,
+        /// Whether the same lookup could succeed if tried again
+        retryable: bool,
+        #[source]
+        source: ErrorSource,
+    },
+
+    #[error("Rule {rule} could not be evaluated: {source}")]
+    PredicateError {
+        rule: String,
+        #[source]
+        source: ErrorSource,
+    },
+
+    #[error("Rule {rule} produced output that doesn't match its outputSchema: {message}")]
+    OutputSchemaError { rule: String, message: String },
+
+    #[error("Invalid config at {path}: {message}")]
+    ConfigError { path: String, message: String },
+
+    #[error(
+        "{} problems with config:{}",
+        .0.len(),
+        .0.iter().map(|e| format!("\n  {}", e)).collect::<String>()
+    )]
+    ConfigErrors(Vec<LaikaError>),
+
+    #[error("Correlation {correlation_id} is over its context limit: {message}")]
+    ContextLimitExceeded {
+        correlation_id: String,
+        message: String,
+    },
+
+    /// Another instance has since claimed a shared store, so this one must stop writing to it
+    #[error("Another instance has taken over writing to {store}")]
+    StoreTakenOver { store: String },
+
+    #[error("{payload} is {size} bytes, over the limit of {limit} bytes")]
+    PayloadTooLarge {
+        payload: String,
+        size: usize,
+        limit: usize,
+    },
+}
+
+impl LaikaError {
+    pub fn storage(source: impl StdError + Send + Sync + 'static) -> Self {
+        LaikaError::StorageError {
+            source: ErrorSource::new(source),
+        }
+    }
+
+    pub fn config(path: impl Into<String>, message: impl Into<String>) -> Self {
+        LaikaError::ConfigError {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Fail with every error found checking a config, or succeed if there weren't any
+    pub fn check_all(mut errors: Vec<LaikaError>) -> LaikaResult<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(LaikaError::ConfigErrors(errors)),
+        }
+    }
+
+    /// Place a config error under `parent`, so paths read from the root of the config.
+    /// Other errors are returned unchanged.
+    pub fn within(self, parent: &str) -> Self {
+        match self {
+            LaikaError::ConfigError { path, message } if path.is_empty() => {
+                LaikaError::config(parent, message)
+            }
+            LaikaError::ConfigError { path, message } => {
+                LaikaError::config(format!("{}.{}", parent, path), message)
+            }
+            other => other,
+        }
+    }
+
+    /// Whether the failed operation could succeed if tried again
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LaikaError::SubmitError { retryable, .. }
+            | LaikaError::LookupError { retryable, .. } => *retryable,
+            LaikaError::StorageError { .. } | LaikaError::IO(_) | LaikaError::ChannelError(_) => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The error that caused a `LaikaError`, kept so the chain of causes can be walked with
+/// `std::error::Error::source`. `LaikaError` is cloned and serialized, so causes are shared and
+/// only their messages are serialized or compared.
+#[derive(Clone)]
+pub struct ErrorSource(Arc<dyn StdError + Send + Sync>);
+
+impl ErrorSource {
+    pub fn new(error: impl StdError + Send + Sync + 'static) -> Self {
+        Self(Arc::new(error))
+    }
+
+    /// A cause known only by its message
+    pub fn message(message: impl Into<String>) -> Self {
+        Self(Arc::new(SourceMessage(message.into())))
+    }
+}
+
+impl Display for ErrorSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl Debug for ErrorSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl StdError for ErrorSource {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.0.source()
+    }
+}
+
+impl PartialEq for ErrorSource {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl Serialize for ErrorSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
 }
 
+impl<'de> Deserialize<'de> for ErrorSource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Self::message(String::deserialize(deserializer)?))
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+struct SourceMessage(String);
+
 #[macro_export]
 macro_rules! laika_bail {
     ($err:ident, $msg:literal $(,)?) => {
@@ -90,15 +267,57 @@ macro_rules! laika_error_from {
     };
 }
 
-laika_error_from!(rocksdb::Error, Generic);
 laika_error_from!(bincode::Error, Generic);
 laika_error_from!(zmq::Error, Generic);
-laika_error_from!(capnp::Error, Generic);
 laika_error_from!(CoreError, Generic);
 laika_error_from!(JsonPredicateError, JsonError);
 
+#[cfg(feature = "rocksdb")]
+impl From<rocksdb::Error> for LaikaError {
+    fn from(value: rocksdb::Error) -> Self {
+        LaikaError::storage(value)
+    }
+}
+
+impl From<capnp::Error> for LaikaError {
+    fn from(value: capnp::Error) -> Self {
+        LaikaError::storage(value)
+    }
+}
+
 impl From<TemplateError> for LaikaError {
     fn from(value: TemplateError) -> Self {
         LaikaError::TemplateError(value.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::MessagingError;
+
+    #[test]
+    fn test_source_chain() {
+        let error = LaikaError::SubmitError {
+            target: "output".to_string(),
+            retryable: true,
+            source: ErrorSource::new(MessagingError::Io(std::io::Error::other("refused"))),
+        };
+        let source = StdError::source(&error).unwrap();
+        assert_eq!(source.to_string(), "IO Error: refused");
+        assert_eq!(source.source().unwrap().to_string(), "refused");
+        assert!(error.is_retryable());
+        assert_eq!(error.clone(), error);
+    }
+
+    #[test]
+    fn test_config_path() {
+        let error = LaikaError::config("http.method", "Invalid HTTP method NOPE")
+            .within("action")
+            .within("triggers.orderShipped");
+        assert_eq!(
+            error.to_string(),
+            "Invalid config at triggers.orderShipped.action.http.method: Invalid HTTP method NOPE"
+        );
+    }
+}
@@ -1,8 +1,187 @@
 use crate::errors::{LaikaError, LaikaResult};
 use crate::event::{Event, EventLike};
+use crate::utils::extract_json::{extract_json_field, parse_path, PathSegment};
 use serde::Serialize;
-use serde_json::json;
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
+use time::format_description::well_known::Rfc3339;
+
+/// The stored events, and fields of them, that a rule's predicate is given instead of the whole
+/// context, and the numeric fields summarized in its stats, from `context` on a trigger
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextShape {
+    /// Paths of the fields kept by event type, or `None` to keep whole events. Without
+    /// `include`, every event is kept whole.
+    include: Option<HashMap<String, Option<Vec<String>>>>,
+    /// Paths of the numeric fields given a minimum and maximum in `stats`, by event type
+    stats: HashMap<String, Vec<String>>,
+}
+
+/// Split an entry of `field` into its event type and the path into its events, if it has one
+fn split_entry<'a>(field: &str, entry: &'a str) -> LaikaResult<(&'a str, Option<String>)> {
+    let (event_type, path) = match entry.split_once(".$") {
+        Some((event_type, path)) if !path.is_empty() => (event_type, Some(format!("${}", path))),
+        Some((event_type, _)) => (event_type, None),
+        None => (entry, None),
+    };
+    if event_type.is_empty() {
+        return Err(LaikaError::config(
+            field,
+            format!("{} doesn't start with an event type", entry),
+        ));
+    }
+    Ok((event_type, path))
+}
+
+impl ContextShape {
+    /// Read entries that are either an event type, keeping whole events, or an event type and
+    /// a path into its events, such as `payment.$.amount`
+    pub fn parse(include: &[String]) -> LaikaResult<Self> {
+        let mut shape: HashMap<String, Option<Vec<String>>> = HashMap::new();
+        for entry in include {
+            let (event_type, path) = split_entry("include", entry)?;
+            let fields = shape
+                .entry(event_type.to_string())
+                .or_insert_with(|| Some(Vec::new()));
+            match (fields, path) {
+                (fields, None) => *fields = None,
+                (Some(paths), Some(path)) => paths.push(path),
+                // The whole event is already kept
+                (None, Some(_)) => {}
+            }
+        }
+        Ok(Self {
+            include: Some(shape),
+            stats: HashMap::new(),
+        })
+    }
+
+    /// Keep every event whole, such as when a trigger only declares `stats`
+    pub fn everything() -> Self {
+        Self {
+            include: None,
+            stats: HashMap::new(),
+        }
+    }
+
+    /// Summarize the fields of `stats` entries, each an event type and a path into its events
+    /// such as `payment.$.amount`, with their minimum and maximum
+    pub fn with_stats(mut self, stats: &[String]) -> LaikaResult<Self> {
+        for entry in stats {
+            let (event_type, path) = split_entry("stats", entry)?;
+            let Some(path) = path else {
+                return Err(LaikaError::config(
+                    "stats",
+                    format!("{} doesn't give a field of the event type", entry),
+                ));
+            };
+            self.stats
+                .entry(event_type.to_string())
+                .or_default()
+                .push(path);
+        }
+        Ok(self)
+    }
+}
+
+/// Copy `value` into `target` at `parts`, creating objects along the way
+fn insert_at(target: &mut Value, parts: &[&str], value: Value) {
+    let Some((first, rest)) = parts.split_first() else {
+        *target = value;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(fields) = target {
+        insert_at(
+            fields.entry(first.to_string()).or_insert(Value::Null),
+            rest,
+            value,
+        );
+    }
+}
+
+/// Only the fields of `data` at `paths`, keeping their place in the document. Fields that
+/// aren't present are left out. Paths with indexes, wildcards or filters don't have a place of
+/// their own, so what they lead to is kept under the path itself.
+fn project(data: &Value, paths: &[String]) -> Value {
+    let mut projected = Value::Object(Map::new());
+    for path in paths {
+        let (Ok(value), Ok(segments)) = (extract_json_field(data, path), parse_path(path)) else {
+            continue;
+        };
+        let parts: Option<Vec<&str>> = segments
+            .iter()
+            .map(|segment| match segment {
+                PathSegment::Field(field) => Some(field.as_str()),
+                _ => None,
+            })
+            .collect();
+        let parts =
+            parts.unwrap_or_else(|| vec![path.trim_start_matches('$').trim_start_matches('.')]);
+        insert_at(&mut projected, &parts, value.into_owned());
+    }
+    projected
+}
+
+/// The number of events of each type, when the first and last were received, and the minimum
+/// and maximum of the numeric values at `fields` for the type. Values that aren't numbers are
+/// left out, and a field with none is `null`.
+fn summarize(
+    events: &HashMap<String, Vec<Event>>,
+    fields: &HashMap<String, Vec<String>>,
+) -> Map<String, Value> {
+    let received = |event: Option<&Event>| {
+        event
+            .and_then(|event| event.received().format(&Rfc3339).ok())
+            .map_or(Value::Null, Value::String)
+    };
+    events
+        .iter()
+        .map(|(event_type, events)| {
+            let mut min = Map::new();
+            let mut max = Map::new();
+            for path in fields.get(event_type).into_iter().flatten() {
+                let mut smallest: Option<(f64, Value)> = None;
+                let mut largest: Option<(f64, Value)> = None;
+                for event in events {
+                    let Ok(value) = extract_json_field(event.get_data(), path) else {
+                        continue;
+                    };
+                    let Some(number) = value.as_f64() else {
+                        continue;
+                    };
+                    if smallest.as_ref().is_none_or(|(least, _)| number < *least) {
+                        smallest = Some((number, value.clone().into_owned()));
+                    }
+                    if largest.as_ref().is_none_or(|(most, _)| number > *most) {
+                        largest = Some((number, value.into_owned()));
+                    }
+                }
+                let name = path.trim_start_matches('$').trim_start_matches('.');
+                min.insert(
+                    name.to_string(),
+                    smallest.map_or(Value::Null, |(_, value)| value),
+                );
+                max.insert(
+                    name.to_string(),
+                    largest.map_or(Value::Null, |(_, value)| value),
+                );
+            }
+            let mut stats = json!({
+                "count": events.len(),
+                "first": received(events.first()),
+                "last": received(events.last()),
+            });
+            if fields.contains_key(event_type) {
+                stats["min"] = Value::Object(min);
+                stats["max"] = Value::Object(max);
+            }
+            (event_type.clone(), stats)
+        })
+        .collect()
+}
 
 #[derive(Clone)]
 /// The content around a given event trigger, *not* including the trigger.  
@@ -11,13 +190,52 @@ use std::collections::HashMap;
 #[derive(Debug)]
 pub struct EventContext {
     sequence: Vec<Event>,
-    events: HashMap<String, Vec<Event>>, // EventType -> Events
+    events: HashMap<String, Vec<Event>>,    // EventType -> Events
+    stored: serde_json::Map<String, Value>, // Values stored against the correlation by rules
+    decisions: serde_json::Map<String, Value>, // Rules that have fired for the correlation
+    stats: serde_json::Map<String, Value>,  // Aggregates of the events by EventType
 }
 
 impl EventContext {
     pub fn events(&self) -> impl Iterator<Item = &Event> {
         self.sequence.iter()
     }
+
+    pub fn with_stored(mut self, stored: serde_json::Map<String, Value>) -> Self {
+        self.stored = stored;
+        self
+    }
+
+    /// Earlier fires of rules for the correlation, by rule
+    pub fn with_decisions(mut self, decisions: serde_json::Map<String, Value>) -> Self {
+        self.decisions = decisions;
+        self
+    }
+
+    /// Only the events and fields included by `shape`, alongside the same stored values and
+    /// decisions. Stats still cover every event, with the fields `shape` summarizes.
+    pub(crate) fn shaped(&self, shape: &ContextShape) -> LaikaResult<Self> {
+        let events = self
+            .sequence
+            .iter()
+            .filter_map(|event| {
+                let Some(include) = &shape.include else {
+                    return Some(event.clone());
+                };
+                let fields = include.get(&event.event_type()?)?;
+                Some(match fields {
+                    None => event.clone(),
+                    Some(paths) => event.with_data(project(event.get_data(), paths)),
+                })
+            })
+            .collect::<Vec<Event>>();
+        Ok(Self {
+            stats: summarize(&self.events, &shape.stats),
+            ..Self::try_from(events)?
+                .with_stored(self.stored.clone())
+                .with_decisions(self.decisions.clone())
+        })
+    }
 }
 
 impl TryFrom<Vec<Event>> for EventContext {
@@ -38,7 +256,13 @@ impl TryFrom<Vec<Event>> for EventContext {
                 }
             }
         }
-        Ok(Self { sequence, events })
+        Ok(Self {
+            stats: summarize(&events, &HashMap::new()),
+            sequence,
+            events,
+            stored: serde_json::Map::new(),
+            decisions: serde_json::Map::new(),
+        })
     }
 }
 
@@ -62,8 +286,73 @@ impl Serialize for EventContext {
         // Events map is already in the right structure
         json!({
             "sequence": sequence,
-            "events": self.events
+            "events": self.events,
+            "stored": self.stored,
+            "decisions": self.decisions,
+            "stats": self.stats
         })
         .serialize(serializer)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::RawEvent;
+
+    #[test]
+    fn test_shaped_context() {
+        let context = EventContext::try_from(vec![
+            RawEvent::new(json!({"amount": 5, "card": {"number": "4111", "expiry": "01/30"}}))
+                .parse("payment", Some("user123".to_string())),
+            RawEvent::new(json!({"page": "/checkout"})).parse("view", Some("user123".to_string())),
+            RawEvent::new(json!({"reason": "declined"}))
+                .parse("refund", Some("user123".to_string())),
+        ])
+        .unwrap();
+        let shape = ContextShape::parse(&[
+            "payment.$.amount".to_string(),
+            "payment.$.card.expiry".to_string(),
+            "refund".to_string(),
+        ])
+        .unwrap();
+        let shaped = serde_json::to_value(context.shaped(&shape).unwrap()).unwrap();
+        assert_eq!(
+            shaped["sequence"],
+            json!([
+                {"type": "payment", "data": {"amount": 5, "card": {"expiry": "01/30"}}},
+                {"type": "refund", "data": {"reason": "declined"}},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_context_stats() {
+        let context = EventContext::try_from(vec![
+            RawEvent::new(json!({"amount": 12})).parse("payment", Some("user123".to_string())),
+            RawEvent::new(json!({"amount": 5})).parse("payment", Some("user123".to_string())),
+            RawEvent::new(json!({"amount": "unknown"}))
+                .parse("payment", Some("user123".to_string())),
+            RawEvent::new(json!({"page": "/checkout"})).parse("view", Some("user123".to_string())),
+        ])
+        .unwrap();
+        let stats = serde_json::to_value(&context).unwrap()["stats"].clone();
+        assert_eq!(stats["payment"]["count"], json!(3));
+        assert_eq!(stats["view"]["count"], json!(1));
+        assert!(stats["payment"].get("min").is_none());
+
+        let shape = ContextShape::parse(&["view".to_string()])
+            .unwrap()
+            .with_stats(&["payment.$.amount".to_string(), "view.$.amount".to_string()])
+            .unwrap();
+        let shaped = serde_json::to_value(context.shaped(&shape).unwrap()).unwrap();
+        assert_eq!(shaped["sequence"].as_array().unwrap().len(), 1);
+        assert_eq!(shaped["stats"]["payment"]["count"], json!(3));
+        assert_eq!(shaped["stats"]["payment"]["min"], json!({"amount": 5}));
+        assert_eq!(shaped["stats"]["payment"]["max"], json!({"amount": 12}));
+        assert_eq!(shaped["stats"]["view"]["min"], json!({"amount": null}));
+        assert!(ContextShape::everything()
+            .with_stats(&["payment".to_string()])
+            .is_err());
+    }
+}
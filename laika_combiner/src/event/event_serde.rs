@@ -5,8 +5,31 @@ use crate::event_schema_capnp::{correlated_event, correlated_event_batch, non_co
 use capnp::message::{Builder, HeapAllocator};
 use time::OffsetDateTime;
 
+/// Stores written before timestamps kept sub-second precision hold whole seconds. Any nanosecond
+/// timestamp after 1970-01-01T00:16:40Z is larger than every seconds timestamp before the year
+/// 33658, so the two can be told apart by size.
+const LEGACY_SECONDS_LIMIT: i64 = 1_000_000_000_000;
+
+fn to_timestamp(received: OffsetDateTime) -> LaikaResult<i64> {
+    i64::try_from(received.unix_timestamp_nanos()).map_err(|_| {
+        LaikaError::IO(format!(
+            "{} is outside the range of storable timestamps",
+            received
+        ))
+    })
+}
+
+fn from_timestamp(received: i64) -> LaikaResult<OffsetDateTime> {
+    if received.abs() < LEGACY_SECONDS_LIMIT {
+        OffsetDateTime::from_unix_timestamp(received)
+    } else {
+        OffsetDateTime::from_unix_timestamp_nanos(i128::from(received))
+    }
+    .map_err(|e| LaikaError::IO(e.to_string()))
+}
+
 pub struct NonCorrelatedEventCapnp {
-    pub received: i64, // Timestamp in ms since epoch
+    pub received: i64, // Timestamp in ns since epoch
     pub event_id: String,
     pub event_type: String,
     pub data: Vec<u8>, // Serialized JSON
@@ -14,7 +37,7 @@ pub struct NonCorrelatedEventCapnp {
 
 #[derive(Clone)]
 pub struct CorrelatedEventCapnp {
-    pub received: i64, // Timestamp in ms since epoch
+    pub received: i64, // Timestamp in ns since epoch
     pub correlation_id: String,
     pub event_type: String,
     pub data: Vec<u8>, // Serialized JSON
@@ -46,7 +69,7 @@ impl TryFrom<NonCorrelatedEvent> for NonCorrelatedEventCapnp {
     type Error = LaikaError;
     fn try_from(value: NonCorrelatedEvent) -> Result<Self, Self::Error> {
         Ok(NonCorrelatedEventCapnp {
-            received: value.received.unix_timestamp(),
+            received: to_timestamp(value.received)?,
             event_id: value.event_id,
             event_type: value.event_type,
             data: serde_json::to_vec(&value.data).map_err(|e| LaikaError::IO(e.to_string()))?,
@@ -82,7 +105,7 @@ impl TryFrom<CorrelatedEvent> for CorrelatedEventCapnp {
     type Error = LaikaError;
     fn try_from(value: CorrelatedEvent) -> Result<Self, Self::Error> {
         Ok(CorrelatedEventCapnp {
-            received: value.received.unix_timestamp(),
+            received: to_timestamp(value.received)?,
             correlation_id: value.correlation_id,
             event_type: value.event_type,
             data: serde_json::to_vec(&value.data).map_err(|e| LaikaError::IO(e.to_string()))?,
@@ -95,10 +118,12 @@ impl TryInto<CorrelatedEvent> for CorrelatedEventCapnp {
 
     fn try_into(self) -> Result<CorrelatedEvent, Self::Error> {
         Ok(CorrelatedEvent {
-            received: OffsetDateTime::from_unix_timestamp(self.received).unwrap(),
+            received: from_timestamp(self.received)?,
             correlation_id: self.correlation_id,
             event_type: self.event_type,
             data: serde_yaml::from_slice(&self.data).map_err(|e| LaikaError::IO(e.to_string()))?,
+            meta: Default::default(),
+            links: Default::default(),
         })
     }
 }
@@ -108,10 +133,11 @@ impl TryFrom<NonCorrelatedEventCapnp> for NonCorrelatedEvent {
 
     fn try_from(value: NonCorrelatedEventCapnp) -> Result<Self, Self::Error> {
         Ok(NonCorrelatedEvent {
-            received: OffsetDateTime::from_unix_timestamp(value.received).unwrap(),
+            received: from_timestamp(value.received)?,
             event_id: value.event_id,
             event_type: value.event_type,
             data: serde_yaml::from_slice(&value.data).map_err(|e| LaikaError::IO(e.to_string()))?,
+            meta: Default::default(),
         })
     }
 }
@@ -141,6 +167,10 @@ impl NonCorrelatedEventCapnp {
 }
 
 impl CorrelatedEventCapnp {
+    fn size(&self) -> usize {
+        self.correlation_id.len() + self.event_type.len() + self.data.len()
+    }
+
     pub fn write_capnp(&self, mut event: correlated_event::Builder) {
         event.set_received(self.received);
         event.set_correlation_id(&self.correlation_id);
@@ -173,6 +203,32 @@ impl CorrelatedEventCapnpBatch {
         Ok(())
     }
 
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Size of the events' payloads, correlation IDs and types, as context limits measure it
+    pub fn size(&self) -> usize {
+        self.events.iter().map(CorrelatedEventCapnp::size).sum()
+    }
+
+    /// When the earliest pushed event was received, if there is one
+    pub fn oldest_received(&self) -> Option<i64> {
+        self.events.first().map(|event| event.received)
+    }
+
+    /// Remove the earliest pushed event, if there is one, returning its size
+    pub fn remove_oldest(&mut self) -> usize {
+        if self.events.is_empty() {
+            return 0;
+        }
+        self.events.remove(0).size()
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> LaikaResult<Self> {
         // Create a message reader from the bytes
         let message_reader =
@@ -216,3 +272,36 @@ impl CorrelatedEventCapnpBatch {
         Ok(Self { events })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_timestamp_keeps_nanoseconds() {
+        let event = CorrelatedEvent {
+            received: datetime!(2024-03-01 12:00:00.123456789 UTC),
+            correlation_id: "user123".to_string(),
+            event_type: "login".to_string(),
+            data: serde_json::json!({"user_id": "user123"}),
+            meta: Default::default(),
+            links: Default::default(),
+        };
+        let batch = CorrelatedEventCapnpBatch::try_from(vec![event.clone()]).unwrap();
+        let read: Vec<CorrelatedEvent> =
+            CorrelatedEventCapnpBatch::from_bytes(batch.to_bytes().unwrap().as_slice())
+                .unwrap()
+                .try_into()
+                .unwrap();
+        assert_eq!(read, vec![event]);
+    }
+
+    #[test]
+    fn test_timestamp_reads_legacy_seconds() {
+        assert_eq!(
+            from_timestamp(1_709_294_400).unwrap(),
+            datetime!(2024-03-01 12:00:00 UTC)
+        );
+    }
+}
@@ -1,19 +1,48 @@
 pub(crate) mod context;
 pub(crate) mod event_serde;
 
-use crate::broker::{CorrelationId, EventExpiry};
+use crate::broker::{CorrelationId, EventExpiry, ExpiryKind};
+use crate::clock::{Clock, SystemClock};
 use crate::matcher::MaybeEventType;
 use crate::utils::extract_json::extract_json_field;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::cmp::Ordering;
+use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
+
+/// Key in event data that `enrich` fields are merged under
+pub const ENRICH_KEY: &str = "_enrich";
+
+/// Merge `fields` into event data under `ENRICH_KEY`, replacing fields of the same name. Data
+/// that isn't an object is left as it is.
+pub(crate) fn enrich(data: &mut Value, fields: &serde_json::Map<String, Value>) {
+    if fields.is_empty() {
+        return;
+    }
+    let Value::Object(data) = data else {
+        tracing::debug!("Event isn't an object, so can't be enriched");
+        return;
+    };
+    let enriched = data
+        .entry(ENRICH_KEY)
+        .or_insert_with(|| Value::Object(Default::default()));
+    match enriched {
+        Value::Object(enriched) => enriched.extend(fields.clone()),
+        other => *other = Value::Object(fields.clone()),
+    }
+}
+
+/// Broker metadata of a received message, such as its `header`s or the `topic` it was published
+/// to
+pub type EventMetadata = serde_json::Map<String, Value>;
+
 pub(crate) trait EventLike {
     fn get_data(&self) -> &Value;
 
     fn try_extract(&self, path: &str) -> Option<Value> {
         match extract_json_field(self.get_data(), path) {
-            Ok(val) => Some(val.clone()),
+            Ok(val) => Some(val.into_owned()),
             Err(_) => None,
         }
     }
@@ -50,16 +79,43 @@ impl EventLike for NonCorrelatedEvent {
 pub struct RawEvent {
     received: OffsetDateTime,
     data: Value,
+    #[serde(default)]
+    meta: EventMetadata,
 }
 
 impl RawEvent {
     pub fn new(data: Value) -> RawEvent {
+        Self::received_by(data, &SystemClock)
+    }
+
+    /// Event received at the time `clock` gives
+    pub fn received_by(data: Value, clock: &dyn Clock) -> RawEvent {
         RawEvent {
-            received: OffsetDateTime::now_utc(),
+            received: clock.now(),
             data,
+            meta: EventMetadata::new(),
         }
     }
 
+    pub fn with_metadata(mut self, meta: EventMetadata) -> RawEvent {
+        self.meta = meta;
+        self
+    }
+
+    pub(crate) fn metadata(&self) -> &EventMetadata {
+        &self.meta
+    }
+
+    /// Merge `fields` into the event's data under `ENRICH_KEY`, replacing fields of the same
+    /// name. Events that aren't objects are left as they are.
+    pub(crate) fn enrich(&mut self, fields: &serde_json::Map<String, Value>) {
+        enrich(&mut self.data, fields)
+    }
+
+    pub(crate) fn data_mut(&mut self) -> &mut Value {
+        &mut self.data
+    }
+
     pub fn parse<S: Into<String>>(
         self,
         event_type: S,
@@ -71,6 +127,8 @@ impl RawEvent {
                 correlation_id,
                 event_type: event_type.into(),
                 data: self.data,
+                meta: self.meta,
+                links: Vec::new(),
             })
         } else {
             Event::NonCorrelated(NonCorrelatedEvent {
@@ -78,6 +136,7 @@ impl RawEvent {
                 event_id: uuid::Uuid::new_v4().to_string(),
                 event_type: event_type.into(),
                 data: self.data,
+                meta: self.meta,
             })
         }
     }
@@ -122,6 +181,16 @@ impl Event {
             Event::NonCorrelated(e) => Some(e.event_type.clone()),
         }
     }
+
+    /// The same event, with its data replaced
+    pub(crate) fn with_data(&self, data: Value) -> Event {
+        let mut event = self.clone();
+        match event {
+            Event::Correlated(ref mut e) => e.data = data,
+            Event::NonCorrelated(ref mut e) => e.data = data,
+        }
+        event
+    }
 }
 
 #[derive(Serialize, Debug, Clone, PartialEq, Eq)]
@@ -130,6 +199,14 @@ pub struct CorrelatedEvent {
     pub(crate) correlation_id: CorrelationId,
     pub(crate) event_type: String,
     pub(crate) data: Value,
+    /// Metadata of the message the event was received in. It isn't stored, so only the event
+    /// that triggered an evaluation has it.
+    #[serde(skip_serializing_if = "EventMetadata::is_empty")]
+    pub(crate) meta: EventMetadata,
+    /// Correlations the event links into its own, read alongside its correlation ID. Like
+    /// metadata, links aren't stored.
+    #[serde(skip)]
+    pub(crate) links: Vec<CorrelationId>,
 }
 
 #[derive(Serialize, Debug, Clone, PartialEq, Eq)]
@@ -139,12 +216,16 @@ pub struct NonCorrelatedEvent {
     pub(crate) event_id: String,
     pub(crate) event_type: String,
     pub(crate) data: Value,
+    #[serde(skip_serializing_if = "EventMetadata::is_empty")]
+    pub(crate) meta: EventMetadata,
 }
 
 #[derive(Debug)]
 pub enum Trigger {
     ReceivedEvent(Event),
     TimerExpired(EventExpiry),
+    /// A rule's cron schedule fired
+    Scheduled(EventExpiry),
 }
 
 impl Serialize for Trigger {
@@ -158,14 +239,18 @@ impl Serialize for Trigger {
                     json!({
                         "type": "received_event",
                         "timestamp": correlated_event.received.unix_timestamp(),
-                        "event": correlated_event.data
+                        "eventType": correlated_event.event_type,
+                        "event": correlated_event.data,
+                        "meta": correlated_event.meta,
                     })
                 }
                 Event::NonCorrelated(uncorrelated_event) => {
                     json!({
                         "type": "received_event",
                         "timestamp": uncorrelated_event.received.unix_timestamp(),
-                        "event": uncorrelated_event.data
+                        "eventType": uncorrelated_event.event_type,
+                        "event": uncorrelated_event.data,
+                        "meta": uncorrelated_event.meta,
                     })
                 }
             },
@@ -173,6 +258,26 @@ impl Serialize for Trigger {
                 json!({
                     "type": "timer_expired",
                     "timestamp": expired_event.expires_at.unix_timestamp(),
+                    "rule": expired_event.event_rule,
+                    // The window's group, for windows over uncorrelated events
+                    "correlationId": expired_event.correlation_id,
+                    "kind": match expired_event.kind {
+                        ExpiryKind::Recheck => "recheck",
+                        ExpiryKind::WindowClose => "window_close",
+                        ExpiryKind::Scheduled => "scheduled",
+                        ExpiryKind::Timeout => "timeout",
+                    },
+                    "expiresAt": expired_event
+                        .expires_at
+                        .format(&Rfc3339)
+                        .map_err(<S::Error as serde::ser::Error>::custom)?,
+                })
+            }
+            Trigger::Scheduled(schedule) => {
+                json!({
+                    "type": "scheduled",
+                    "timestamp": schedule.expires_at.unix_timestamp(),
+                    "rule": schedule.event_rule,
                 })
             }
         }
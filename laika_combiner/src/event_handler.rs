@@ -1,10 +1,13 @@
 use crate::action::EventAction;
-use crate::broker::EventExpiry;
+use crate::audit::{AuditRecord, MatchedEvent, AUDIT_RECORD_VERSION};
+use crate::broker::{EventExpiry, ExpiryKind};
 use crate::errors::LaikaResult;
 use crate::event::context::EventContext;
-use crate::event::{CorrelatedEvent, Event, RawEvent, Trigger};
+use crate::event::{CorrelatedEvent, Event, EventLike, RawEvent, Trigger};
 use crate::event_processor::processor::EventProcessor;
-use crate::storage::StorageKV;
+use crate::explain::EventExplanation;
+use crate::storage::{StorageKV, StorageTransaction};
+use time::OffsetDateTime;
 use tracing::span;
 
 fn handle_correlated_parsed_event(
@@ -12,13 +15,32 @@ fn handle_correlated_parsed_event(
     storage_kv: &mut StorageKV,
     correlated_event: CorrelatedEvent,
 ) -> LaikaResult<Vec<EventAction>> {
-    let correlated_event_span = span!(tracing::Level::INFO, "handle_correlated_parsed_event");
+    let correlated_event_span = span!(
+        tracing::Level::INFO,
+        "handle_correlated_parsed_event",
+        correlation_id = tracing::field::Empty
+    );
     let _enter = correlated_event_span.enter();
     let mut event_actions: Vec<EventAction> = Vec::new();
-    let correlation_id = correlated_event.correlation_id.clone();
     let transaction = storage_kv.start_transaction();
-    let mut context = storage_kv
-        .write_event(&transaction, correlated_event)?
+    let store_span = span!(
+        tracing::Level::INFO,
+        "store",
+        correlation_id = tracing::field::Empty
+    );
+    let stored = store_span.in_scope(|| {
+        let correlated_event = storage_kv.link_event(&transaction, correlated_event)?;
+        let correlation_id = correlated_event.correlation_id.clone();
+        store_span.record("correlation_id", correlation_id.as_str());
+        correlated_event_span.record("correlation_id", correlation_id.as_str());
+        storage_kv
+            .write_event(&transaction, correlated_event)
+            .map(|events| events.map(|events| (correlation_id, events)))
+    })?;
+    let Some((correlation_id, events)) = stored else {
+        return Ok(event_actions);
+    };
+    let mut context = events
         .into_iter()
         .map(Event::Correlated)
         .collect::<Vec<Event>>();
@@ -27,16 +49,42 @@ fn handle_correlated_parsed_event(
             .pop()
             .expect("Events will always contain the most recently triggered event"),
     );
-    let context = EventContext::try_from(context)?;
+    let context = EventContext::try_from(context)?
+        .with_stored(storage_kv.read_stored_values(&transaction, correlation_id.as_str())?)
+        .with_decisions(storage_kv.read_rule_fires(&transaction, correlation_id.as_str())?);
     event_actions.extend(processor.relevant_actions(
+        storage_kv,
+        &transaction,
         &Some(correlation_id),
         &trigger_event,
         &context,
     )?);
+    let event_actions = apply_store_actions(storage_kv, &transaction, event_actions)?;
     transaction.commit()?;
     Ok(event_actions)
 }
 
+/// Store any values rendered by rules within the transaction, returning the remaining actions
+fn apply_store_actions(
+    storage_kv: &StorageKV,
+    transaction: &StorageTransaction,
+    event_actions: Vec<EventAction>,
+) -> LaikaResult<Vec<EventAction>> {
+    let mut remaining_actions = Vec::with_capacity(event_actions.len());
+    for event_action in event_actions {
+        match event_action {
+            EventAction::Store(store) => storage_kv.write_stored_value(
+                transaction,
+                store.correlation_id.as_str(),
+                store.key.as_str(),
+                store.value,
+            )?,
+            other => remaining_actions.push(other),
+        }
+    }
+    Ok(remaining_actions)
+}
+
 /// Produce required CQRS Actions for received actions.
 pub fn handle_raw_event(
     processors: &mut [EventProcessor],
@@ -45,10 +93,33 @@ pub fn handle_raw_event(
     raw_event: RawEvent,
 ) -> LaikaResult<Vec<EventAction>> {
     let mut event_actions: Vec<EventAction> = vec![];
+    let mut matched: Vec<MatchedEvent> = vec![];
+    let mut audited_event = storage_kv
+        .audit_log_enabled()
+        .then(|| (OffsetDateTime::now_utc(), raw_event.get_data().clone()));
     for processor in processors {
         let span = tracing::span!(tracing::Level::TRACE, "Processing event against processor");
         let _enter = span.enter();
-        for parsed_event in processor.parse_event(event_source, raw_event.clone())? {
+        let parsed_events = span!(tracing::Level::INFO, "match", source = event_source)
+            .in_scope(|| processor.parse_event(event_source, raw_event.clone()))?;
+        for parsed_event in parsed_events {
+            processor.observe_event_time(&parsed_event);
+            if let Some((_, event)) = &mut audited_event {
+                // The audit log keeps the received event, so it's redacted for every matched type
+                processor.redact(
+                    parsed_event.event_type().unwrap_or_default().as_str(),
+                    event,
+                );
+                matched.push(MatchedEvent {
+                    event_type: parsed_event.event_type().unwrap_or_default(),
+                    correlation_id: match &parsed_event {
+                        Event::Correlated(correlated_event) => {
+                            Some(correlated_event.correlation_id.clone())
+                        }
+                        Event::NonCorrelated(_) => None,
+                    },
+                });
+            }
             // Start a transaction to write the event to the database for the correlation id.
             // Retrieve events from the database for the correlation id.
             // This will block other writers until this is finished.
@@ -66,40 +137,240 @@ pub fn handle_raw_event(
                     let trigger_event =
                         Trigger::ReceivedEvent(Event::NonCorrelated(non_correlated_event));
                     let context = EventContext::try_from(vec![])?;
+                    let transaction = storage_kv.start_transaction();
                     event_actions.extend(processor.relevant_actions(
+                        storage_kv,
+                        &transaction,
                         &None,
                         &trigger_event,
                         &context,
                     )?);
+                    transaction.commit()?;
                 }
             }
         }
     }
+    if let Some((received, event)) = audited_event {
+        storage_kv.write_audit_record(&AuditRecord {
+            version: AUDIT_RECORD_VERSION,
+            received,
+            source: event_source.to_string(),
+            event,
+            matched,
+            actions: event_actions.iter().map(EventAction::describe).collect(),
+        })?;
+    }
     Ok(event_actions)
 }
 
+/// Explain how a received event would be handled against the stored state, without storing it
+pub fn explain_raw_event(
+    processors: &mut [EventProcessor],
+    storage_kv: &StorageKV,
+    event_source: &str,
+    raw_event: RawEvent,
+) -> LaikaResult<Vec<EventExplanation>> {
+    let mut explanations = Vec::new();
+    for processor in processors {
+        for parsed_event in processor.parse_event(event_source, raw_event.clone())? {
+            let event_type = parsed_event.event_type().unwrap_or_default();
+            let (correlation_id, context) = match &parsed_event {
+                Event::Correlated(correlated_event) => {
+                    // Read only, so the transaction is dropped rather than committed
+                    let transaction = storage_kv.start_transaction();
+                    let correlation_id = storage_kv
+                        .resolve_correlation(&transaction, &correlated_event.correlation_id)?;
+                    let context = EventContext::try_from(
+                        storage_kv
+                            .read_events(&transaction, correlation_id.as_str())?
+                            .into_iter()
+                            .map(Event::Correlated)
+                            .collect::<Vec<Event>>(),
+                    )?
+                    .with_stored(
+                        storage_kv.read_stored_values(&transaction, correlation_id.as_str())?,
+                    )
+                    .with_decisions(
+                        storage_kv.read_rule_fires(&transaction, correlation_id.as_str())?,
+                    );
+                    (Some(correlation_id), context)
+                }
+                Event::NonCorrelated(_) => (None, EventContext::try_from(vec![])?),
+            };
+            let trigger = Trigger::ReceivedEvent(parsed_event);
+            let rules = processor.explain(correlation_id.as_deref(), &trigger, &context)?;
+            explanations.push(EventExplanation {
+                event_type,
+                correlation_id,
+                rules,
+            });
+        }
+    }
+    Ok(explanations)
+}
+
 pub fn handle_timing_expiry(
     rule_groups: &mut [EventProcessor],
     storage_kv: &mut StorageKV,
     event_expiry: EventExpiry,
 ) -> LaikaResult<Vec<EventAction>> {
-    let correlation_id_str = event_expiry.correlation_id.clone();
-    let correlation_id = Some(correlation_id_str.clone());
-    let actions: Vec<EventAction> = vec![];
+    if event_expiry.kind == ExpiryKind::WindowClose {
+        let mut event_actions = Vec::new();
+        for rule_group in rule_groups {
+            event_actions.extend(rule_group.close_window(storage_kv, &event_expiry)?);
+        }
+        return Ok(event_actions);
+    }
+    if event_expiry.kind == ExpiryKind::Scheduled {
+        let transaction = storage_kv.start_transaction();
+        let mut event_actions = Vec::new();
+        for rule_group in rule_groups {
+            event_actions.extend(rule_group.run_schedule(
+                storage_kv,
+                &transaction,
+                &event_expiry,
+            )?);
+        }
+        let event_actions = apply_store_actions(storage_kv, &transaction, event_actions)?;
+        transaction.commit()?;
+        return Ok(event_actions);
+    }
     let transaction = storage_kv.start_transaction();
+    // Timers set before a correlation was merged into another fire for the merged correlation
+    let correlation_id_str =
+        storage_kv.resolve_correlation(&transaction, &event_expiry.correlation_id)?;
+    let correlation_id = Some(correlation_id_str.clone());
     let context = EventContext::try_from(
         storage_kv
             .read_events(&transaction, correlation_id_str.as_str())?
             .into_iter()
             .map(Event::Correlated)
             .collect::<Vec<Event>>(),
-    )?;
+    )?
+    .with_stored(storage_kv.read_stored_values(&transaction, correlation_id_str.as_str())?)
+    .with_decisions(storage_kv.read_rule_fires(&transaction, correlation_id_str.as_str())?);
     let mut event_actions = Vec::new();
+    if event_expiry.kind == ExpiryKind::Timeout {
+        for rule_group in rule_groups {
+            event_actions.extend(rule_group.time_out(
+                &correlation_id_str,
+                &event_expiry,
+                &context,
+            )?);
+        }
+        let event_actions = apply_store_actions(storage_kv, &transaction, event_actions)?;
+        transaction.commit()?;
+        return Ok(event_actions);
+    }
     let trigger = Trigger::TimerExpired(event_expiry);
 
     for rule_group in rule_groups {
-        event_actions.extend(rule_group.relevant_actions(&correlation_id, &trigger, &context)?);
+        event_actions.extend(rule_group.relevant_actions(
+            storage_kv,
+            &transaction,
+            &correlation_id,
+            &trigger,
+            &context,
+        )?);
     }
+    let event_actions = apply_store_actions(storage_kv, &transaction, event_actions)?;
     transaction.commit()?;
-    Ok(actions)
+    Ok(event_actions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::builder::EventProcessorYamlSpec;
+    use crate::config::EventProcessorConfig;
+    use serde_json::{json, Value};
+
+    const CONFIG: &str = r#"
+connections:
+  payments:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  scored:
+    from: payments
+    matchKey:
+      type: scored
+  purchase:
+    from: payments
+    matchKey:
+      type: purchase
+correlation:
+  scored:
+    key: "$.userId"
+  purchase:
+    key: "$.userId"
+triggers:
+  storeRisk:
+    requires:
+      at_least: [scored]
+    filterAndExtract: >
+      (trigger, ctx) => trigger.event.type === "scored" ? { score: trigger.event.score } : null
+    action:
+      store:
+        key: riskScore
+        value: "${{ score }}"
+  riskyPurchase:
+    requires:
+      at_least: [purchase]
+    filterAndExtract: >
+      (trigger, ctx) => trigger.event.type === "purchase" ? { risk: ctx.stored.riskScore } : null
+    action:
+      target: alerts
+      payload:
+        risk: "${{ risk }}"
+"#;
+
+    fn push(
+        processors: &mut [EventProcessor],
+        storage: &mut StorageKV,
+        event: Value,
+    ) -> Vec<Value> {
+        handle_raw_event(processors, storage, "payments", RawEvent::new(event))
+            .unwrap()
+            .into_iter()
+            .map(|action| action.describe())
+            .collect()
+    }
+
+    #[test]
+    fn test_stored_values_enrich_context() {
+        let config =
+            EventProcessorConfig::try_from(&EventProcessorYamlSpec::from_yaml(CONFIG).unwrap())
+                .unwrap();
+        let mut processors = vec![config.build().unwrap()];
+        let mut storage = StorageKV::in_memory();
+
+        // Storing is done within the transaction, rather than handed on as an action
+        let scored = json!({"type": "scored", "userId": "user123", "score": 80});
+        assert!(push(&mut processors, &mut storage, scored).is_empty());
+        let transaction = storage.start_transaction();
+        assert_eq!(
+            storage.read_stored_values(&transaction, "user123").unwrap()["riskScore"],
+            json!("80")
+        );
+        drop(transaction);
+
+        let purchase = json!({"type": "purchase", "userId": "user123"});
+        let actions = push(&mut processors, &mut storage, purchase);
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["payload"], json!({"risk": "80"}));
+
+        // A later value replaces the stored one, and other correlations don't see it
+        let rescored = json!({"type": "scored", "userId": "user123", "score": 20});
+        push(&mut processors, &mut storage, rescored);
+        let purchase = json!({"type": "purchase", "userId": "user123"});
+        let actions = push(&mut processors, &mut storage, purchase);
+        assert_eq!(actions[0]["payload"], json!({"risk": "20"}));
+        let transaction = storage.start_transaction();
+        assert!(storage
+            .read_stored_values(&transaction, "user456")
+            .unwrap()
+            .is_empty());
+    }
 }
@@ -1,19 +1,49 @@
-use crate::action::{EmitAction, EventAction};
-use crate::broker::{CorrelationId, EventExpiry};
+use crate::action::{EmitAction, EventAction, HttpRequest, StoreAction};
+use crate::broker::{CorrelationId, EventExpiry, ExpiryKind};
+use crate::calendar::BusinessCalendar;
+use crate::clock::{Clock, SystemClock};
 use crate::config::builder::ActionConfig;
 use crate::config::{EventCorrelation, EventRuleDefinition};
+use crate::controls::RuntimeControls;
 use crate::errors::{LaikaError, LaikaResult};
 use crate::event::context::EventContext;
 use crate::event::{Event, EventLike, RawEvent, Trigger};
-use crate::matcher::EventTypeDefinitions;
-use crate::predicate_engine::JsonPredicateEngine;
+use crate::explain::{PredicateExplanation, RuleExplanation, TimingExplanation};
+use crate::global_state::GlobalState;
+use crate::late::{self, LatePolicy};
+use crate::matcher::{AmbiguousMatch, EventType, EventTypeDefinitions};
+use crate::partitioning::Partitioning;
+use crate::predicate_engine::{JsonPredicateEngine, PredicateInputs};
+use crate::provenance::Provenance;
 use crate::rules::{EventRule, RuleResult};
+use crate::schedule::ScheduleScope;
+use crate::storage::{StorageKV, StorageTransaction};
+use crate::template::error::TemplateError;
+use crate::tenancy::{TenancyConfig, TenantStats};
+use crate::watermark::{self, Watermark};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 pub struct EventProcessor {
     pub(crate) engine: JsonPredicateEngine,
     event_matcher: EventTypeDefinitions,
     event_correlation: EventCorrelation,
     pub rules: Vec<EventRule>,
+    tenant_stats: HashMap<String, TenantStats>,
+    /// Messages that have matched more than one event type
+    ambiguous_matches: AtomicU64,
+    /// Progress of event time, where event types carry when they happened
+    watermark: Option<Watermark>,
+    clock: Arc<dyn Clock>,
+    /// Rules disabled at runtime, and how often each has fired
+    controls: RuntimeControls,
+    /// Share of correlations this instance handles, when several read the same sources
+    partitioning: Option<Partitioning>,
+    /// Work days and hours business time is counted in
+    calendar: BusinessCalendar,
+    /// Metadata stamped onto emitted payloads, if they're stamped
+    provenance: Option<Provenance>,
 }
 
 impl EventProcessor {
@@ -21,51 +51,416 @@ impl EventProcessor {
         event_matcher: EventTypeDefinitions,
         event_correlation: EventCorrelation,
         rules: Vec<EventRuleDefinition>,
-    ) -> Self {
-        let mut engine = JsonPredicateEngine::new();
-        let rules = rules
+        mut engine: JsonPredicateEngine,
+    ) -> LaikaResult<Self> {
+        let mut rules: Vec<EventRule> = rules
             .into_iter()
             .map(|rule| rule.register_to_engine(&mut engine))
-            .collect();
-        Self {
+            .collect::<LaikaResult<_>>()?;
+        // Highest priority first, falling back to name so evaluation order is deterministic
+        rules.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        Ok(Self {
             engine,
             event_matcher,
             event_correlation,
             rules,
+            tenant_stats: HashMap::new(),
+            ambiguous_matches: AtomicU64::new(0),
+            watermark: None,
+            clock: Arc::new(SystemClock),
+            controls: RuntimeControls::default(),
+            partitioning: None,
+            calendar: BusinessCalendar::default(),
+            provenance: None,
+        })
+    }
+
+    /// Time rechecks, schedules and `laika.now()` by `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.engine.set_clock(clock.clone());
+        self.clock = clock;
+        self
+    }
+
+    /// Skip rules disabled through `controls`, and count rule fires in them
+    pub fn with_controls(mut self, controls: RuntimeControls) -> Self {
+        self.controls = controls;
+        self
+    }
+
+    /// Share `laika.global` in predicates through `state`
+    pub fn with_global_state(mut self, state: GlobalState) -> Self {
+        self.engine.set_global_state(state);
+        self
+    }
+
+    /// Rules by name with their priorities, in evaluation order
+    pub fn rule_priorities(&self) -> Vec<(String, i32)> {
+        self.rules
+            .iter()
+            .map(|rule| (rule.name.clone(), rule.priority))
+            .collect()
+    }
+
+    /// Hold wakeups back until event time passes them
+    pub fn with_watermark(mut self, watermark: Option<Watermark>) -> Self {
+        self.watermark = watermark;
+        self
+    }
+
+    /// Count business time in timing and absence durations on `calendar`
+    pub fn with_calendar(mut self, calendar: BusinessCalendar) -> Self {
+        self.calendar = calendar;
+        self
+    }
+
+    /// Only handle the correlations `partitioning` gives to this instance
+    pub fn with_partitioning(mut self, partitioning: Option<Partitioning>) -> Self {
+        self.partitioning = partitioning;
+        self
+    }
+
+    /// Stamp emitted payloads and request bodies with the rule that produced them
+    pub fn with_provenance(mut self, provenance: Option<Provenance>) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Whether this instance handles events keyed by `key`, which it always does unless
+    /// correlations are partitioned
+    fn owns(&self, key: &str) -> bool {
+        self.partitioning
+            .as_ref()
+            .is_none_or(|partitioning| partitioning.owns(key))
+    }
+
+    /// Move the watermark on with when an event happened, for event types that carry it
+    pub(crate) fn observe_event_time(&mut self, event: &Event) {
+        let Some(watermark) = &mut self.watermark else {
+            return;
+        };
+        if event
+            .event_type()
+            .is_some_and(|event_type| self.event_matcher.event_time(&event_type).is_some())
+        {
+            watermark.observe(*event.received());
+        }
+    }
+
+    /// Whether event time has passed a wakeup, which it always has without event times
+    pub fn watermark_passed(&self, expiry: &EventExpiry) -> bool {
+        self.watermark
+            .as_ref()
+            .is_none_or(|watermark| watermark.passed(expiry))
+    }
+
+    /// Counts of events and actions for each tenant, when events are split between tenants
+    pub fn tenant_stats(&self) -> &HashMap<String, TenantStats> {
+        &self.tenant_stats
+    }
+
+    /// Number of messages that have matched more than one event type, however the ambiguous
+    /// match policy handled them
+    pub fn ambiguous_matches(&self) -> u64 {
+        self.ambiguous_matches.load(Ordering::Relaxed)
+    }
+
+    /// Event types a message is handled as
+    fn match_types(&self, event_source: &str, raw_event: &RawEvent) -> LaikaResult<Vec<EventType>> {
+        let matched = self.event_matcher.match_message(
+            event_source,
+            raw_event.get_data(),
+            raw_event.metadata(),
+        )?;
+        self.event_matcher.resolve_matches(matched)
+    }
+
+    /// Tenant a rule is being evaluated for, where tenancy is configured and the tenant is known
+    fn trigger_tenant(&self, trigger: &Trigger) -> Option<String> {
+        self.event_correlation
+            .tenancy()
+            .and_then(|tenancy| tenancy.trigger_tenant(trigger))
+    }
+
+    /// Look up the values of the event types `raw_event` matches, by lookup name. Lookups are
+    /// made concurrently, and see the event as it's matched, with its `enrich` fields.
+    pub(crate) async fn lookup(
+        &self,
+        event_source: &str,
+        raw_event: &RawEvent,
+    ) -> LaikaResult<serde_json::Map<String, serde_json::Value>> {
+        let mut raw_event = raw_event.clone();
+        if let Some(fields) = self.event_matcher.source_enrichment(event_source) {
+            raw_event.enrich(fields);
+        }
+        let mut pending = Vec::new();
+        for event_type in self.match_types(event_source, &raw_event)? {
+            let lookups = self.event_matcher.lookups(&event_type);
+            if lookups.is_empty() {
+                continue;
+            }
+            let mut raw_event = raw_event.clone();
+            if let Some(fields) = self.event_matcher.type_enrichment(&event_type) {
+                raw_event.enrich(fields);
+            }
+            // Events handled by another instance are looked up there
+            let correlation_id = self
+                .event_correlation
+                .correlation_id(&event_type, &raw_event)?;
+            if !self.owns(correlation_id.as_deref().unwrap_or(event_type.as_str())) {
+                continue;
+            }
+            let data = raw_event.get_data().clone();
+            pending.extend(lookups.iter().map(move |lookup| {
+                let data = data.clone();
+                async move {
+                    lookup
+                        .resolve(&data)
+                        .await
+                        .map(|value| value.map(|value| (lookup.name.clone(), value)))
+                }
+            }));
         }
+        Ok(futures::future::try_join_all(pending)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
     }
 
     /// Parse a Raw Event into all Matching Events
     pub(crate) fn parse_event(
         &self,
         event_source: &str,
-        raw_event: RawEvent,
+        mut raw_event: RawEvent,
     ) -> LaikaResult<Vec<Event>> {
         let mut matched_events: Vec<Event> = Vec::new();
-        for event_type in self
-            .event_matcher
-            .match_message(event_source, raw_event.get_data())?
-        {
-            matched_events.push(
-                raw_event.clone().parse(
-                    event_type.clone(),
-                    self.event_correlation
-                        .correlation_id(&event_type, &raw_event)?,
-                ),
-            );
+        if let Some(fields) = self.event_matcher.source_enrichment(event_source) {
+            raw_event.enrich(fields);
+        }
+        let matched = self.event_matcher.match_message(
+            event_source,
+            raw_event.get_data(),
+            raw_event.metadata(),
+        )?;
+        if matched.len() > 1 {
+            self.ambiguous_matches.fetch_add(1, Ordering::Relaxed);
+            if self.event_matcher.ambiguous_match() == AmbiguousMatch::Warn {
+                tracing::warn!(
+                    "Event from {} matched several event types: {}",
+                    event_source,
+                    matched.join(", ")
+                );
+            }
+        }
+        for event_type in self.event_matcher.resolve_matches(matched)? {
+            let mut raw_event = raw_event.clone();
+            if let Some(fields) = self.event_matcher.type_enrichment(&event_type) {
+                raw_event.enrich(fields);
+            }
+            // Every event needs a tenant, including those that aren't correlated
+            if let Some(tenancy) = self.event_correlation.tenancy() {
+                tenancy.tenant(raw_event.get_data())?;
+            }
+            // Correlation keys are read before redacting, so redacted fields can still correlate
+            let correlation_id = self
+                .event_correlation
+                .correlation_id(&event_type, &raw_event)?;
+            // Uncorrelated events are partitioned by type, so windows over them aren't split
+            if !self.owns(correlation_id.as_deref().unwrap_or(event_type.as_str())) {
+                tracing::trace!("{} is handled by another instance", event_type);
+                continue;
+            }
+            let links = self.event_correlation.linked_ids(&event_type, &raw_event)?;
+            let happened = self
+                .event_matcher
+                .event_time(&event_type)
+                .map(|path| watermark::event_time(raw_event.get_data(), path))
+                .transpose()?;
+            self.redact(&event_type, raw_event.data_mut());
+            let mut event = raw_event.parse(event_type, correlation_id);
+            if let Some(happened) = happened {
+                event.set_received(happened);
+            }
+            if let Event::Correlated(correlated_event) = &mut event {
+                correlated_event.links = links;
+            }
+            matched_events.push(event);
         }
         Ok(matched_events)
     }
 
+    /// Redact the fields of event data that are redacted for `event_type`
+    pub(crate) fn redact(&self, event_type: &str, data: &mut serde_json::Value) {
+        for redaction in self.event_matcher.redactions(event_type) {
+            redaction.apply(data);
+        }
+    }
+
+    /// Stamp a payload `rule` produced with its provenance, if payloads are stamped
+    fn stamp(&self, rule: &EventRule, mut payload: serde_json::Value) -> serde_json::Value {
+        if let Some(provenance) = &self.provenance {
+            provenance.stamp(&rule.name, &mut payload);
+        }
+        payload
+    }
+
+    /// Render the action for a satisfied rule, if it can be taken
     fn emit_action(
+        &self,
+        rule: &EventRule,
         action_config: &ActionConfig,
+        correlation_id: Option<&CorrelationId>,
         output: serde_json::Value,
-    ) -> Result<EventAction, LaikaError> {
-        Ok(EventAction::Emit(EmitAction::new(
-            action_config.target.clone(),
-            serde_json::to_value(action_config.emit_template.clone().render(&output)?)
-                .map_err(|e| LaikaError::TemplateError(e.to_string()))?,
-        )))
+    ) -> Result<Option<EventAction>, LaikaError> {
+        match action_config {
+            ActionConfig::Emit {
+                target,
+                emit_template,
+                routing_template,
+                format,
+            } => Ok(Some(EventAction::Emit(
+                EmitAction::new(
+                    target.clone(),
+                    format.wrap(
+                        self.stamp(
+                            rule,
+                            serde_json::to_value(emit_template.clone().render(&output)?)
+                                .map_err(|e| LaikaError::TemplateError(e.to_string()))?,
+                        ),
+                    ),
+                )
+                .with_format(format.clone())
+                .with_routing(
+                    routing_template
+                        .as_ref()
+                        .map(|template| template.clone().render_string(&output))
+                        .transpose()?,
+                )
+                .with_correlation(correlation_id.cloned()),
+            ))),
+            ActionConfig::Http(http) => Ok(Some(EventAction::Http(HttpRequest {
+                method: http.method.clone(),
+                url: http.url.clone().render_string(&output)?,
+                headers: http
+                    .headers
+                    .iter()
+                    .map(|(name, template)| {
+                        template
+                            .clone()
+                            .render_string(&output)
+                            .map(|value| (name.clone(), value))
+                    })
+                    .collect::<Result<Vec<(String, String)>, _>>()?,
+                body: http
+                    .body
+                    .clone()
+                    .map(|body| {
+                        body.render(&output).and_then(|rendered| {
+                            serde_json::to_value(rendered)
+                                .map_err(|e| TemplateError::RenderError(e.to_string()))
+                        })
+                    })
+                    .transpose()?
+                    .map(|body| self.stamp(rule, body)),
+                tls: http.tls.clone(),
+                compression: http.compression,
+                auth: http.auth.clone(),
+                correlation_id: correlation_id.cloned(),
+            }))),
+            ActionConfig::Store {
+                key,
+                value_template,
+            } => {
+                let Some(correlation_id) = correlation_id else {
+                    tracing::warn!("Cannot store {} for an uncorrelated event, skipping", key);
+                    return Ok(None);
+                };
+                Ok(Some(EventAction::Store(StoreAction {
+                    correlation_id: correlation_id.clone(),
+                    key: key.clone(),
+                    value: serde_json::to_value(value_template.clone().render(&output)?)
+                        .map_err(|e| LaikaError::TemplateError(e.to_string()))?,
+                })))
+            }
+        }
+    }
+
+    /// What a rule's timeout and escalation actions are rendered against, as it hasn't produced
+    /// anything from `filterAndExtract`
+    fn unsatisfied_output(
+        rule: &EventRule,
+        trigger: &Trigger,
+        context: &EventContext,
+    ) -> LaikaResult<serde_json::Value> {
+        Ok(serde_json::json!({
+            "trigger": trigger,
+            "context": rule.predicate_context(context)?,
+        }))
+    }
+
+    /// Drop an action a deduplicated rule has already taken for the correlation within its
+    /// `dedupe` window, such as when a broker redelivers events after a restart
+    fn deduplicate(
+        &self,
+        storage_kv: &StorageKV,
+        rule: &EventRule,
+        correlation_id: Option<&CorrelationId>,
+        action: Option<EventAction>,
+    ) -> LaikaResult<Option<EventAction>> {
+        let (Some(dedupe), Some(correlation_id), Some(taken)) =
+            (rule.dedupe, correlation_id, &action)
+        else {
+            return Ok(action);
+        };
+        let now = self.clock.now();
+        if storage_kv.log_action(
+            &rule.name,
+            correlation_id,
+            &taken.digest(),
+            now,
+            now + dedupe,
+        )? {
+            return Ok(action);
+        }
+        tracing::info!(
+            "Suppressing duplicate action of {} for {}",
+            rule.name,
+            correlation_id
+        );
+        Ok(None)
+    }
+
+    /// Record a rule's decision for a correlation, when the rule has a late event policy that
+    /// needs it, and what it produced if it fired, for later predicates' `ctx.decisions`
+    fn record_decision(
+        &self,
+        storage_kv: &StorageKV,
+        transaction: &StorageTransaction,
+        rule: &EventRule,
+        correlation_id: Option<&CorrelationId>,
+        output: Option<&serde_json::Value>,
+    ) -> LaikaResult<()> {
+        let Some(correlation_id) = correlation_id else {
+            return Ok(());
+        };
+        if rule.late.is_some() {
+            storage_kv.write_decision(&rule.name, correlation_id, &late::decision(output))?;
+        }
+        if let Some(output) = output {
+            storage_kv.record_rule_fire(
+                transaction,
+                &rule.name,
+                correlation_id,
+                self.clock.now(),
+                output,
+            )?;
+        }
+        Ok(())
     }
 
     /// Actions to take given matched conditions, if any
@@ -74,20 +469,251 @@ impl EventProcessor {
     /// Trigger is the item that caused this rule to be evaluated.
     pub fn relevant_actions(
         &mut self,
+        storage_kv: &StorageKV,
+        transaction: &StorageTransaction,
         correlation_id: &Option<CorrelationId>,
         // Either a timing trigger, or a correlated event
         trigger: &Trigger,
         context: &EventContext,
     ) -> LaikaResult<Vec<EventAction>> {
         let mut actions: Vec<EventAction> = Vec::new();
+        let tenant = self.trigger_tenant(trigger);
+        let mut inputs = PredicateInputs::new(trigger, context);
         for rule in self.rules.iter() {
-            match rule.evaluate(&mut self.engine, trigger, context)? {
+            // Scheduled rules are only evaluated when their schedule fires
+            if rule.schedule.is_some() {
+                continue;
+            }
+            if !rule.enabled_for(tenant.as_deref()) || !self.controls.rule_enabled(&rule.name) {
+                continue;
+            }
+            if !rule.rolled_out_to(correlation_id.as_deref()) {
+                continue;
+            }
+            // Windowed rules buffer uncorrelated events, and are only evaluated on window close
+            if let Some(window) = &rule.window {
+                if let Trigger::ReceivedEvent(Event::NonCorrelated(event)) = trigger {
+                    if rule.requires_event_type(&event.event_type) {
+                        if let Some(window_close) =
+                            window.buffer(storage_kv, &rule.name, event, tenant.as_deref())?
+                        {
+                            actions.push(EventAction::ScheduleWakeup(window_close));
+                        }
+                    }
+                }
+                continue;
+            }
+            // Events for a correlation the rule has already decided on are handled by its late
+            // policy, rather than by its usual timing
+            if let (Some(policy), Some(correlation_id), Trigger::ReceivedEvent(event)) =
+                (&rule.late, correlation_id, trigger)
+            {
+                let watched = event
+                    .event_type()
+                    .is_some_and(|event_type| rule.watches_event_type(&event_type));
+                if let Some(original) = watched
+                    .then(|| storage_kv.read_decision(&rule.name, correlation_id))
+                    .transpose()?
+                    .flatten()
+                {
+                    tracing::debug!("{} is late for {}", rule.name, correlation_id);
+                    match policy {
+                        LatePolicy::Ignore => {}
+                        LatePolicy::Route { target } => actions.extend(late::route(
+                            target,
+                            &rule.name,
+                            correlation_id,
+                            trigger,
+                            &original,
+                        )),
+                        LatePolicy::Correct => {
+                            if let RuleResult::ConditionSatisfied {
+                                action_config,
+                                condition_result,
+                                ..
+                            } = rule.evaluate(&mut self.engine, &mut inputs)?
+                            {
+                                self.record_decision(
+                                    storage_kv,
+                                    transaction,
+                                    rule,
+                                    Some(correlation_id),
+                                    Some(&condition_result),
+                                )?;
+                                actions.extend(self.emit_action(
+                                    rule,
+                                    &action_config,
+                                    Some(correlation_id),
+                                    late::correction(condition_result, &original),
+                                )?);
+                            }
+                        }
+                    }
+                    continue;
+                }
+            }
+            // Absence rules wait out their window once requirements are met, and are only
+            // evaluated when it expires without any of the absent events arriving
+            if let Some(absence) = &rule.absence {
+                let Some(correlation_id) = correlation_id else {
+                    continue;
+                };
+                match trigger {
+                    Trigger::ReceivedEvent(_) => {
+                        if let Some(met_at) = rule.completed_requirements(trigger, context) {
+                            actions.push(EventAction::ScheduleWakeup(EventExpiry::new(
+                                self.calendar.add(met_at, absence.within),
+                                correlation_id.clone(),
+                                rule.name.clone(),
+                            )));
+                        }
+                        continue;
+                    }
+                    Trigger::TimerExpired(expiry) => {
+                        if expiry.event_rule != rule.name {
+                            continue;
+                        }
+                        if absence.observed(context, expiry.expires_at) {
+                            self.record_decision(
+                                storage_kv,
+                                transaction,
+                                rule,
+                                Some(correlation_id),
+                                None,
+                            )?;
+                            continue;
+                        }
+                    }
+                    Trigger::Scheduled(_) => continue,
+                }
+            }
+            // Aggregated rules are only evaluated once their window meets its threshold
+            let mut aggregate_value: Option<f64> = None;
+            if let Some(aggregate) = &rule.aggregate {
+                let Some(correlation_id) = correlation_id else {
+                    continue;
+                };
+                let observation =
+                    aggregate.observe(storage_kv, &rule.name, correlation_id, trigger, context)?;
+                if let Some(window_closes) = observation.opened_until {
+                    actions.push(EventAction::ScheduleWakeup(EventExpiry::new(
+                        window_closes,
+                        correlation_id.clone(),
+                        rule.name.clone(),
+                    )));
+                }
+                match observation.met {
+                    Some(value) => aggregate_value = Some(value),
+                    None => continue,
+                }
+            }
+            let evaluated = tracing::info_span!(
+                "rule",
+                rule = rule.name.as_str(),
+                correlation_id = correlation_id.as_deref()
+            )
+            .in_scope(|| rule.evaluate(&mut self.engine, &mut inputs))?;
+            match evaluated {
                 RuleResult::ConditionSatisfied {
                     met_at,
                     action_config,
-                    condition_result,
-                } => actions.push(Self::emit_action(&action_config, condition_result)?),
+                    mut condition_result,
+                } => {
+                    self.controls.fired(&rule.name);
+                    if let (Some(value), Some(result)) =
+                        (aggregate_value, condition_result.as_object_mut())
+                    {
+                        result
+                            .entry("aggregate")
+                            .or_insert(serde_json::json!(value));
+                    }
+                    self.record_decision(
+                        storage_kv,
+                        transaction,
+                        rule,
+                        correlation_id.as_ref(),
+                        Some(&condition_result),
+                    )?;
+                    let action = self.emit_action(
+                        rule,
+                        &action_config,
+                        correlation_id.as_ref(),
+                        condition_result,
+                    )?;
+                    actions.extend(self.deduplicate(
+                        storage_kv,
+                        rule,
+                        correlation_id.as_ref(),
+                        action,
+                    )?);
+                    // Rechecks scheduled while the rule waited on its condition aren't needed
+                    if let (Some(_), Some(correlation_id)) = (&rule.timing, correlation_id) {
+                        actions.push(EventAction::CancelWakeup(
+                            correlation_id.clone(),
+                            rule.name.clone(),
+                        ));
+                        if !rule.escalation.is_empty() {
+                            storage_kv.clear_recheck_count(&rule.name, correlation_id)?;
+                        }
+                    }
+                    if rule.stop_on_match {
+                        tracing::debug!("{} matched, skipping lower priority rules", rule.name);
+                        break;
+                    }
+                }
                 RuleResult::ConditionNotSatisfied { met_at, recheck } => {
+                    // An absence rule's window has passed without it firing
+                    if rule.absence.is_some() {
+                        self.record_decision(
+                            storage_kv,
+                            transaction,
+                            rule,
+                            correlation_id.as_ref(),
+                            None,
+                        )?;
+                    }
+                    // Timeouts are set once, by the event completing the requirements, and
+                    // cancelled along with rechecks if the rule fires
+                    if let (Some(_), Some(timing), Some(correlation_id)) =
+                        (&rule.on_timeout, &recheck, correlation_id)
+                    {
+                        if let Some(times_out_at) = rule
+                            .completed_requirements(trigger, context)
+                            .and_then(|met_at| timing.timeout_at(met_at, &self.calendar))
+                        {
+                            actions.push(EventAction::ScheduleWakeup(EventExpiry::timeout(
+                                times_out_at,
+                                correlation_id.clone(),
+                                rule.name.clone(),
+                            )));
+                        }
+                    }
+                    // Escalating rules count their own rechecks that leave them unsatisfied, taking
+                    // each escalation's action once its count is reached
+                    if let (Trigger::TimerExpired(expiry), Some(correlation_id)) =
+                        (trigger, correlation_id)
+                    {
+                        if !rule.escalation.is_empty()
+                            && expiry.kind == ExpiryKind::Recheck
+                            && expiry.event_rule == rule.name
+                        {
+                            let rechecks = storage_kv.count_recheck(&rule.name, correlation_id)?;
+                            if let Some(escalation) = rule
+                                .escalation
+                                .iter()
+                                .find(|escalation| escalation.rechecks == rechecks)
+                            {
+                                let mut output = Self::unsatisfied_output(rule, trigger, context)?;
+                                output["rechecks"] = serde_json::json!(rechecks);
+                                actions.extend(self.emit_action(
+                                    rule,
+                                    &escalation.action,
+                                    Some(correlation_id),
+                                    output,
+                                )?);
+                            }
+                        }
+                    }
                     // Early return if any condition isn't met
                     let Some(recheck_config) = recheck else {
                         continue;
@@ -96,7 +722,9 @@ impl EventProcessor {
                         continue;
                     };
 
-                    if let Some(next_wakeup) = recheck_config.next_check(met_at) {
+                    if let Some(next_wakeup) =
+                        recheck_config.next_check(met_at, self.clock.as_ref(), &self.calendar)
+                    {
                         actions.push(EventAction::ScheduleWakeup(EventExpiry::new(
                             next_wakeup,
                             correlation_id,
@@ -107,6 +735,480 @@ impl EventProcessor {
                 RuleResult::RequirementNotMet { .. } => {}
             }
         }
+        if let Some(tenant) = tenant {
+            let stats = self.tenant_stats.entry(tenant).or_default();
+            if matches!(trigger, Trigger::ReceivedEvent(_)) {
+                stats.events += 1;
+            }
+            stats.actions += actions
+                .iter()
+                .filter(|action| matches!(action, EventAction::Emit(_) | EventAction::Http(_)))
+                .count() as u64;
+        }
+        Ok(actions)
+    }
+
+    /// Trace how each rule would be evaluated for a trigger, without taking any actions or
+    /// changing any state. Rules with windows, aggregates or schedules depend on state kept
+    /// between triggers, so they're reported as skipped.
+    pub fn explain(
+        &mut self,
+        correlation_id: Option<&str>,
+        trigger: &Trigger,
+        context: &EventContext,
+    ) -> LaikaResult<Vec<RuleExplanation>> {
+        let mut explanations = Vec::with_capacity(self.rules.len());
+        let mut stopped_by: Option<String> = None;
+        let tenant = self.trigger_tenant(trigger);
+        let mut inputs = PredicateInputs::new(trigger, context);
+        for rule in self.rules.iter() {
+            let explanation = RuleExplanation::new(&rule.name, rule.priority);
+            if !self.controls.rule_enabled(&rule.name) {
+                explanations.push(explanation.skipped("Disabled at runtime"));
+                continue;
+            }
+            if !rule.enabled_for(tenant.as_deref()) {
+                explanations.push(explanation.skipped(&format!(
+                    "Not enabled for tenant {}",
+                    tenant.as_deref().unwrap_or_default()
+                )));
+                continue;
+            }
+            if !rule.rolled_out_to(correlation_id) {
+                explanations.push(explanation.skipped("Correlation is outside its rollout"));
+                continue;
+            }
+            if let Some(stopped_by) = &stopped_by {
+                explanations.push(explanation.skipped(&format!(
+                    "{} matched first and stops lower priority rules",
+                    stopped_by
+                )));
+                continue;
+            }
+            let skipped = if rule.schedule.is_some() {
+                Some("Evaluated on its schedule")
+            } else if rule.window.is_some() {
+                Some("Evaluated when its window closes")
+            } else if rule.aggregate.is_some() {
+                Some("Evaluated once its aggregate meets its threshold")
+            } else if rule.absence.is_some() && matches!(trigger, Trigger::ReceivedEvent(_)) {
+                Some("Evaluated once its absence window passes")
+            } else if !rule.reacts_to(trigger) {
+                Some("Not evaluated on this trigger, as it isn't listed under on")
+            } else {
+                None
+            };
+            let mut explanation = match skipped {
+                Some(reason) => explanation.skipped(reason),
+                None => explanation,
+            };
+            explanation.requirement = Some(rule.explain_requirements(trigger, context));
+            let met_at = rule.when_met_requirements(trigger, context);
+            if explanation.skipped.is_some() || met_at.is_none() {
+                explanations.push(explanation);
+                continue;
+            }
+
+            let output = rule.meets_condition(&mut self.engine, &mut inputs)?;
+            explanation.predicate = Some(PredicateExplanation {
+                input: serde_json::json!({
+                    "trigger": trigger,
+                    "context": rule.predicate_context(context)?,
+                }),
+                output: output.clone().unwrap_or(serde_json::Value::Null),
+            });
+            if output.is_some() {
+                explanation.fired = true;
+                if rule.stop_on_match {
+                    stopped_by = Some(rule.name.clone());
+                }
+            } else if let Some(timing) = &rule.timing {
+                let next_check = met_at.and_then(|met_at| {
+                    timing.next_check(met_at, self.clock.as_ref(), &self.calendar)
+                });
+                explanation.timing = Some(TimingExplanation {
+                    next_check: next_check.map(|next_check| next_check.unix_timestamp()),
+                });
+            }
+            explanations.push(explanation);
+        }
+        Ok(explanations)
+    }
+
+    /// Evaluate the windowed rule an expiry was scheduled for against the events in its window
+    pub fn close_window(
+        &mut self,
+        storage_kv: &StorageKV,
+        expiry: &EventExpiry,
+    ) -> LaikaResult<Vec<EventAction>> {
+        let mut actions: Vec<EventAction> = Vec::new();
+        for rule in self.rules.iter() {
+            let Some(window) = &rule.window else {
+                continue;
+            };
+            if rule.name != expiry.event_rule {
+                continue;
+            }
+            // Windows still close while their rule is disabled, so events don't build up
+            let closed = window.close(storage_kv, &rule.name, expiry)?;
+            if let Some(next) = closed.next.clone() {
+                actions.push(EventAction::ScheduleWakeup(next));
+            }
+            if closed.events.is_empty() || !self.controls.rule_enabled(&rule.name) {
+                continue;
+            }
+            let description = closed.describe();
+            let context = EventContext::try_from(
+                closed
+                    .events
+                    .into_iter()
+                    .map(Event::Correlated)
+                    .collect::<Vec<Event>>(),
+            )?;
+            let trigger = Trigger::TimerExpired(expiry.clone());
+            if let Some(mut condition_result) = rule.meets_condition(
+                &mut self.engine,
+                &mut PredicateInputs::new(&trigger, &context),
+            )? {
+                self.controls.fired(&rule.name);
+                if let Some(result) = condition_result.as_object_mut() {
+                    result.entry("window").or_insert(description);
+                }
+                actions.extend(self.emit_action(rule, &rule.action, None, condition_result)?);
+            }
+        }
+        Ok(actions)
+    }
+
+    /// Take the `onTimeout` action of the rule a timeout was scheduled for, which hasn't fired
+    /// for the correlation in time
+    pub fn time_out(
+        &mut self,
+        correlation_id: &CorrelationId,
+        expiry: &EventExpiry,
+        context: &EventContext,
+    ) -> LaikaResult<Vec<EventAction>> {
+        let mut actions: Vec<EventAction> = Vec::new();
+        let trigger = Trigger::TimerExpired(expiry.clone());
+        for rule in self.rules.iter() {
+            let Some(on_timeout) = &rule.on_timeout else {
+                continue;
+            };
+            if rule.name != expiry.event_rule || !self.controls.rule_enabled(&rule.name) {
+                continue;
+            }
+            // Rollouts can change while a timeout is pending
+            if !rule.rolled_out_to(Some(correlation_id.as_str())) {
+                continue;
+            }
+            actions.extend(self.emit_action(
+                rule,
+                on_timeout,
+                Some(correlation_id),
+                Self::unsatisfied_output(rule, &trigger, context)?,
+            )?);
+        }
         Ok(actions)
     }
+
+    /// Wakeups for the next run of every scheduled rule
+    pub fn scheduled_wakeups(&self) -> Vec<EventExpiry> {
+        let now = self.clock.now();
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let schedule = rule.schedule.as_ref()?;
+                let Some(next_run) = schedule.cron.next_after(now) else {
+                    tracing::warn!("Schedule for {} never runs", rule.name);
+                    return None;
+                };
+                Some(EventExpiry::scheduled(next_run, rule.name.clone()))
+            })
+            .collect()
+    }
+
+    /// Evaluate the scheduled rule an expiry was scheduled for, scheduling its next run
+    pub fn run_schedule(
+        &mut self,
+        storage_kv: &StorageKV,
+        transaction: &StorageTransaction,
+        expiry: &EventExpiry,
+    ) -> LaikaResult<Vec<EventAction>> {
+        let mut actions: Vec<EventAction> = Vec::new();
+        let trigger = Trigger::Scheduled(expiry.clone());
+        for rule in self.rules.iter() {
+            let Some(schedule) = &rule.schedule else {
+                continue;
+            };
+            if rule.name != expiry.event_rule {
+                continue;
+            }
+            if let Some(next_run) = schedule.cron.next_after(self.clock.now()) {
+                actions.push(EventAction::ScheduleWakeup(EventExpiry::scheduled(
+                    next_run,
+                    rule.name.clone(),
+                )));
+            }
+            // Disabled schedules keep running, so they pick up again once enabled
+            if !self.controls.rule_enabled(&rule.name) {
+                continue;
+            }
+            match schedule.scope {
+                ScheduleScope::Global => {
+                    // Global schedules run on the instance owning the rule
+                    if !self.owns(&rule.name) {
+                        continue;
+                    }
+                    let context = EventContext::try_from(Vec::new())?;
+                    if let Some(result) = rule.meets_condition(
+                        &mut self.engine,
+                        &mut PredicateInputs::new(&trigger, &context),
+                    )? {
+                        self.controls.fired(&rule.name);
+                        actions.extend(self.emit_action(rule, &rule.action, None, result)?);
+                    }
+                }
+                ScheduleScope::Correlation => {
+                    for correlation_id in storage_kv.correlation_ids()? {
+                        if !self.owns(&correlation_id) {
+                            continue;
+                        }
+                        if !rule.enabled_for(TenancyConfig::tenant_of(correlation_id.as_str()))
+                            || !rule.rolled_out_to(Some(correlation_id.as_str()))
+                        {
+                            continue;
+                        }
+                        let context = EventContext::try_from(
+                            storage_kv
+                                .read_events(transaction, correlation_id.as_str())?
+                                .into_iter()
+                                .map(Event::Correlated)
+                                .collect::<Vec<Event>>(),
+                        )?
+                        .with_stored(
+                            storage_kv.read_stored_values(transaction, correlation_id.as_str())?,
+                        )
+                        .with_decisions(
+                            storage_kv.read_rule_fires(transaction, correlation_id.as_str())?,
+                        );
+                        if rule.requires.is_some()
+                            && rule.when_met_requirements(&trigger, &context).is_none()
+                        {
+                            continue;
+                        }
+                        if let Some(result) = rule.meets_condition(
+                            &mut self.engine,
+                            &mut PredicateInputs::new(&trigger, &context),
+                        )? {
+                            self.controls.fired(&rule.name);
+                            self.record_decision(
+                                storage_kv,
+                                transaction,
+                                rule,
+                                Some(&correlation_id),
+                                Some(&result),
+                            )?;
+                            actions.extend(self.emit_action(
+                                rule,
+                                &rule.action,
+                                Some(&correlation_id),
+                                result,
+                            )?);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(actions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::builder::EventProcessorYamlSpec;
+    use crate::config::EventProcessorConfig;
+    use crate::event::RawEvent;
+    use crate::event_handler::handle_raw_event;
+    use crate::storage::StorageKV;
+    use serde_json::json;
+
+    const CONFIG: &str = r#"
+connections:
+  orders:
+    type: stdin
+  orderEvents:
+    type: stdout
+events:
+  settled:
+    from: orders
+    matchKey:
+      type: settled
+correlation:
+  settled:
+    key: "$.orderId"
+triggers:
+  orderSettled:
+    requires:
+      at_least: [settled]
+    filterAndExtract: >
+      (trigger, ctx) => trigger.event
+    action:
+      target: orderEvents
+      ROUTING
+      payload:
+        order: "${{ orderId }}"
+"#;
+
+    fn config(routing: &str) -> crate::errors::LaikaResult<EventProcessorConfig> {
+        EventProcessorConfig::try_from(&EventProcessorYamlSpec::from_yaml(
+            &CONFIG.replace("ROUTING", routing),
+        )?)
+    }
+
+    #[test]
+    fn test_routing_is_templated() {
+        let config = config("routing:\n        topic: \"orders.${{ region }}.settled\"").unwrap();
+        let mut processors = vec![config.build().unwrap()];
+        let mut storage = StorageKV::in_memory();
+        let settle = |region: &str| json!({"type": "settled", "orderId": region, "region": region});
+        for region in ["eu", "us"] {
+            let actions = handle_raw_event(
+                &mut processors,
+                &mut storage,
+                "orders",
+                RawEvent::new(settle(region)),
+            )
+            .unwrap();
+            let action = actions[0].describe();
+            assert_eq!(
+                action["routing"],
+                json!(format!("orders.{}.settled", region))
+            );
+            assert_eq!(action["payload"], json!({"order": region}));
+        }
+
+        // Routing that can't be rendered fails the action, rather than sending it unrouted
+        let unrouted = json!({"type": "settled", "orderId": "1"});
+        assert!(handle_raw_event(
+            &mut processors,
+            &mut storage,
+            "orders",
+            RawEvent::new(unrouted)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_routing_is_optional() {
+        let config = config("").unwrap();
+        let mut processors = vec![config.build().unwrap()];
+        let mut storage = StorageKV::in_memory();
+        let settled = json!({"type": "settled", "orderId": "1"});
+        let actions = handle_raw_event(
+            &mut processors,
+            &mut storage,
+            "orders",
+            RawEvent::new(settled),
+        )
+        .unwrap();
+        assert_eq!(actions[0].describe()["routing"], json!(null));
+
+        assert!(config("routing: \"orders.settled\"").is_err());
+        assert!(config("routing:\n        key: \"orders.settled\"").is_err());
+    }
+
+    const PRIORITY_CONFIG: &str = r#"
+connections:
+  payments:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  purchase:
+    from: payments
+    matchKey:
+      type: purchase
+correlation:
+  purchase:
+    key: "$.userId"
+triggers:
+  analytics:
+    requires:
+      at_least: [purchase]
+    action:
+      target: alerts
+      payload:
+        rule: analytics
+  audit:
+    requires:
+      at_least: [purchase]
+    action:
+      target: alerts
+      payload:
+        rule: audit
+  largePurchase:
+    priority: 10
+    stopOnMatch: STOP
+    requires:
+      at_least: [purchase]
+    filterAndExtract: >
+      (trigger, ctx) => trigger.event.amount > 10000 ? {} : null
+    action:
+      target: alerts
+      payload:
+        rule: largePurchase
+"#;
+
+    /// Rules that fired for a purchase of `amount`, in the order they fired
+    fn fired(stop_on_match: &str, amount: u32) -> Vec<String> {
+        let config = EventProcessorConfig::try_from(
+            &EventProcessorYamlSpec::from_yaml(&PRIORITY_CONFIG.replace("STOP", stop_on_match))
+                .unwrap(),
+        )
+        .unwrap();
+        let mut processors = vec![config.build().unwrap()];
+        let purchase = json!({"type": "purchase", "userId": "user123", "amount": amount});
+        handle_raw_event(
+            &mut processors,
+            &mut StorageKV::in_memory(),
+            "payments",
+            RawEvent::new(purchase),
+        )
+        .unwrap()
+        .iter()
+        .map(|action| {
+            action.describe()["payload"]["rule"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_rules_evaluated_by_priority() {
+        // Highest priority first, then by name
+        assert_eq!(
+            fired("false", 20000),
+            vec!["largePurchase", "analytics", "audit"]
+        );
+        assert_eq!(fired("true", 20000), vec!["largePurchase"]);
+        // Lower priority rules are only skipped once the rule fires
+        assert_eq!(fired("true", 50), vec!["analytics", "audit"]);
+    }
+
+    #[test]
+    fn test_invalid_priority() {
+        let config = |replace: (&str, &str)| {
+            EventProcessorYamlSpec::from_yaml(
+                &PRIORITY_CONFIG
+                    .replace("STOP", "true")
+                    .replace(replace.0, replace.1),
+            )
+            .and_then(|spec| EventProcessorConfig::try_from(&spec))
+        };
+        assert!(config(("priority: 10", "priority: high")).is_err());
+        assert!(config(("priority: 10", "priority: 1.5")).is_err());
+        assert!(config(("stopOnMatch: true", "stopOnMatch: sometimes")).is_err());
+    }
 }
@@ -0,0 +1,78 @@
+use crate::broker::CorrelationId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// How a received event would be handled, for each event type it matched
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventExplanation {
+    pub event_type: String,
+    pub correlation_id: Option<CorrelationId>,
+    pub rules: Vec<RuleExplanation>,
+}
+
+/// Trace of a single rule's evaluation against a trigger
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleExplanation {
+    pub rule: String,
+    pub priority: i32,
+    /// Why the rule wasn't evaluated for this trigger, if it wasn't
+    pub skipped: Option<String>,
+    pub requirement: Option<RequirementExplanation>,
+    pub predicate: Option<PredicateExplanation>,
+    pub timing: Option<TimingExplanation>,
+    /// Whether the rule's action would be taken
+    pub fired: bool,
+}
+
+impl RuleExplanation {
+    pub(crate) fn new(rule: &str, priority: i32) -> Self {
+        Self {
+            rule: rule.to_string(),
+            priority,
+            skipped: None,
+            requirement: None,
+            predicate: None,
+            timing: None,
+            fired: false,
+        }
+    }
+
+    pub(crate) fn skipped(mut self, reason: &str) -> Self {
+        self.skipped = Some(reason.to_string());
+        self
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequirementExplanation {
+    /// `atLeast`, `exactly`, or `none` for rules without requirements
+    pub kind: &'static str,
+    pub met: bool,
+    /// Unix timestamp of the event that completed the requirements
+    pub met_at: Option<i64>,
+    /// Events that satisfied each required event type
+    pub satisfied_by: BTreeMap<String, Vec<serde_json::Value>>,
+    /// Required event types with no events
+    pub missing: Vec<String>,
+    /// Event types present that an `exactly` requirement doesn't allow
+    pub unexpected: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PredicateExplanation {
+    /// Trigger and context as passed to `filterAndExtract`
+    pub input: serde_json::Value,
+    /// Result of `filterAndExtract`, where null means the condition wasn't satisfied
+    pub output: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimingExplanation {
+    /// Unix timestamp the rule would be rechecked at, if its timing allows another check
+    pub next_check: Option<i64>,
+}
@@ -0,0 +1,391 @@
+use crate::connections::MessagingError;
+use crate::errors::{LaikaError, LaikaResult};
+use apache_avro::Schema;
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Schema registry holding the Avro schema for a subject
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaRegistry {
+    pub url: String,
+    pub subject: String,
+}
+
+/// How an emitted payload is encoded, as written in the config
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EmitFormatConfig {
+    #[default]
+    Json,
+    #[serde(rename = "cloudevents")]
+    CloudEvents {
+        source: String,
+        #[serde(rename = "eventType")]
+        event_type: String,
+    },
+    Text,
+    Avro {
+        /// Schema as JSON, for payloads sent without a schema registry
+        #[serde(default)]
+        schema: Option<String>,
+        #[serde(default)]
+        registry: Option<SchemaRegistry>,
+    },
+}
+
+/// Where the Avro schema for a payload comes from
+#[derive(Debug, Clone)]
+pub enum AvroSchema {
+    /// Payloads are sent as bare Avro datums
+    Inline(Schema),
+    /// The latest schema for the subject is used, and payloads are sent in the Confluent wire
+    /// format, prefixed with the schema's ID
+    Registry(SchemaRegistry),
+}
+
+/// How an emitted payload is encoded, applied once its template is rendered
+#[derive(Debug, Clone, Default)]
+pub enum EmitFormat {
+    #[default]
+    Json,
+    /// A CloudEvents 1.0 envelope in structured mode, with the payload as its `data`
+    CloudEvents {
+        source: String,
+        event_type: String,
+    },
+    /// The payload as plain text, with strings sent without quotes
+    Text,
+    Avro(AvroSchema),
+}
+
+impl TryFrom<EmitFormatConfig> for EmitFormat {
+    type Error = LaikaError;
+    fn try_from(value: EmitFormatConfig) -> LaikaResult<Self> {
+        match value {
+            EmitFormatConfig::Json => Ok(EmitFormat::Json),
+            EmitFormatConfig::CloudEvents { source, event_type } => {
+                Ok(EmitFormat::CloudEvents { source, event_type })
+            }
+            EmitFormatConfig::Text => Ok(EmitFormat::Text),
+            EmitFormatConfig::Avro {
+                schema: Some(schema),
+                registry: None,
+            } => Ok(EmitFormat::Avro(AvroSchema::Inline(
+                Schema::parse_str(schema.as_str())
+                    .map_err(|e| LaikaError::config("schema", e.to_string()))?,
+            ))),
+            EmitFormatConfig::Avro {
+                schema: None,
+                registry: Some(registry),
+            } => Ok(EmitFormat::Avro(AvroSchema::Registry(registry))),
+            EmitFormatConfig::Avro { .. } => Err(LaikaError::config(
+                "",
+                "Avro needs exactly one of schema or registry",
+            )),
+        }
+    }
+}
+
+impl EmitFormat {
+    /// Wrap a rendered payload in the envelope of its format, if the format has one
+    pub(crate) fn wrap(&self, payload: Value) -> Value {
+        match self {
+            EmitFormat::CloudEvents { source, event_type } => serde_json::json!({
+                "specversion": "1.0",
+                "id": uuid::Uuid::new_v4().to_string(),
+                "source": source,
+                "type": event_type,
+                "time": OffsetDateTime::now_utc().format(&Rfc3339).ok(),
+                "datacontenttype": "application/json",
+                "data": payload,
+            }),
+            EmitFormat::Json | EmitFormat::Text | EmitFormat::Avro(_) => payload,
+        }
+    }
+
+    /// Name of the format, for describing actions
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmitFormat::Json => "json",
+            EmitFormat::CloudEvents { .. } => "cloudevents",
+            EmitFormat::Text => "text",
+            EmitFormat::Avro(_) => "avro",
+        }
+    }
+}
+
+/// Render a payload as plain text. Strings are sent as they are, and anything else as JSON.
+pub fn to_text(payload: Value) -> String {
+    match payload {
+        Value::String(text) => text,
+        other => other.to_string(),
+    }
+}
+
+/// Encode a payload as a bare Avro datum
+pub fn to_avro(schema: &Schema, payload: Value) -> Result<Vec<u8>, String> {
+    let value = apache_avro::to_value(payload)
+        .and_then(|value| value.resolve(schema))
+        .map_err(|e| e.to_string())?;
+    apache_avro::to_avro_datum(schema, value).map_err(|e| e.to_string())
+}
+
+/// Encode a payload in the Confluent wire format: a zero byte, the schema's ID as a big-endian
+/// u32, then the Avro datum
+pub fn to_confluent_avro(
+    schema_id: u32,
+    schema: &Schema,
+    payload: Value,
+) -> Result<Vec<u8>, String> {
+    let mut encoded = vec![0];
+    encoded.extend_from_slice(&schema_id.to_be_bytes());
+    encoded.extend(to_avro(schema, payload)?);
+    Ok(encoded)
+}
+
+/// How payloads received from a source are encoded, as written in the config
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EncodingConfig {
+    #[default]
+    Json,
+    Avro {
+        /// Schema as JSON, for bare Avro datums
+        #[serde(default)]
+        schema: Option<String>,
+        /// URL of a schema registry, for payloads in the Confluent wire format
+        #[serde(default)]
+        registry: Option<String>,
+    },
+    Protobuf {
+        /// File holding a serialized `FileDescriptorSet`, as written by `protoc --descriptor_set_out`
+        #[serde(rename = "descriptorSet")]
+        descriptor_set: String,
+        /// Fully qualified name of the message type, e.g. `orders.v1.OrderPlaced`
+        message: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum AvroDecoder {
+    Inline(Schema),
+    /// Schemas are looked up by the ID each payload is prefixed with, then reused
+    Registry {
+        url: String,
+        client: reqwest::Client,
+        schemas: tokio::sync::Mutex<HashMap<u32, Schema>>,
+    },
+}
+
+/// Decodes payloads received from a source into JSON, before they're matched
+#[derive(Debug, Default)]
+pub enum PayloadDecoder {
+    #[default]
+    Json,
+    Avro(AvroDecoder),
+    Protobuf(MessageDescriptor),
+}
+
+impl TryFrom<EncodingConfig> for PayloadDecoder {
+    type Error = MessagingError;
+    fn try_from(value: EncodingConfig) -> Result<Self, MessagingError> {
+        match value {
+            EncodingConfig::Json => Ok(PayloadDecoder::Json),
+            EncodingConfig::Avro {
+                schema: Some(schema),
+                registry: None,
+            } => Ok(PayloadDecoder::Avro(AvroDecoder::Inline(
+                Schema::parse_str(schema.as_str())
+                    .map_err(|e| MessagingError::ConfigError(e.to_string()))?,
+            ))),
+            EncodingConfig::Avro {
+                schema: None,
+                registry: Some(url),
+            } => Ok(PayloadDecoder::Avro(AvroDecoder::Registry {
+                url,
+                client: reqwest::Client::new(),
+                schemas: tokio::sync::Mutex::new(HashMap::new()),
+            })),
+            EncodingConfig::Avro { .. } => Err(MessagingError::ConfigError(
+                "Avro needs exactly one of schema or registry".to_string(),
+            )),
+            EncodingConfig::Protobuf {
+                descriptor_set,
+                message,
+            } => {
+                let pool = DescriptorPool::decode(std::fs::read(&descriptor_set)?.as_slice())
+                    .map_err(|e| {
+                        MessagingError::ConfigError(format!("{}: {}", descriptor_set, e))
+                    })?;
+                let descriptor = pool.get_message_by_name(message.as_str()).ok_or_else(|| {
+                    MessagingError::ConfigError(format!(
+                        "No message {} in {}",
+                        message, descriptor_set
+                    ))
+                })?;
+                Ok(PayloadDecoder::Protobuf(descriptor))
+            }
+        }
+    }
+}
+
+impl PayloadDecoder {
+    pub fn is_json(&self) -> bool {
+        matches!(self, PayloadDecoder::Json)
+    }
+
+    pub async fn decode(&self, payload: &[u8]) -> Result<Value, MessagingError> {
+        match self {
+            PayloadDecoder::Json => Ok(serde_json::from_slice(payload)?),
+            PayloadDecoder::Avro(AvroDecoder::Inline(schema)) => from_avro(schema, payload),
+            PayloadDecoder::Avro(AvroDecoder::Registry {
+                url,
+                client,
+                schemas,
+            }) => {
+                let [0, a, b, c, d, datum @ ..] = payload else {
+                    return Err(MessagingError::Decode(
+                        "Payload isn't in the Confluent wire format".to_string(),
+                    ));
+                };
+                let schema_id = u32::from_be_bytes([*a, *b, *c, *d]);
+                let mut schemas = schemas.lock().await;
+                if !schemas.contains_key(&schema_id) {
+                    let schema = registry_schema(client, url, schema_id).await?;
+                    schemas.insert(schema_id, schema);
+                }
+                from_avro(&schemas[&schema_id], datum)
+            }
+            PayloadDecoder::Protobuf(descriptor) => {
+                let message = DynamicMessage::decode(descriptor.clone(), payload)
+                    .map_err(|e| MessagingError::Decode(e.to_string()))?;
+                Ok(serde_json::to_value(&message)?)
+            }
+        }
+    }
+}
+
+/// Decode a bare Avro datum into JSON
+fn from_avro(schema: &Schema, mut payload: &[u8]) -> Result<Value, MessagingError> {
+    apache_avro::from_avro_datum(schema, &mut payload, None)
+        .and_then(Value::try_from)
+        .map_err(|e| MessagingError::Decode(e.to_string()))
+}
+
+/// Look up a schema by its ID in a schema registry
+async fn registry_schema(
+    client: &reqwest::Client,
+    url: &str,
+    schema_id: u32,
+) -> Result<Schema, MessagingError> {
+    #[derive(Deserialize)]
+    struct RegisteredSchema {
+        schema: String,
+    }
+    let registered: RegisteredSchema = client
+        .get(format!(
+            "{}/schemas/ids/{}",
+            url.trim_end_matches('/'),
+            schema_id
+        ))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| MessagingError::ConnectionError(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| MessagingError::Decode(e.to_string()))?;
+    Schema::parse_str(registered.schema.as_str()).map_err(|e| MessagingError::Decode(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ORDER_SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Order",
+        "fields": [
+            {"name": "id", "type": "string"},
+            {"name": "quantity", "type": "int"}
+        ]
+    }"#;
+
+    #[test]
+    fn test_cloud_event_envelope() {
+        let format = EmitFormat::CloudEvents {
+            source: "/laika/orders".to_string(),
+            event_type: "com.example.order".to_string(),
+        };
+        let event = format.wrap(json!({"id": "a"}));
+        assert_eq!(event["specversion"], json!("1.0"));
+        assert_eq!(event["source"], json!("/laika/orders"));
+        assert_eq!(event["type"], json!("com.example.order"));
+        assert_eq!(event["data"], json!({"id": "a"}));
+        assert!(event["id"].is_string());
+        assert!(event["time"].is_string());
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(to_text(json!("Order a shipped")), "Order a shipped");
+        assert_eq!(to_text(json!({"id": "a"})), r#"{"id":"a"}"#);
+    }
+
+    #[test]
+    fn test_avro() {
+        let schema = Schema::parse_str(ORDER_SCHEMA).unwrap();
+        let payload = json!({"id": "a", "quantity": 2});
+        let datum = to_avro(&schema, payload.clone()).unwrap();
+        // "a" is a length of 1 zigzag encoded, then the byte, then 2 zigzag encoded
+        assert_eq!(datum, vec![2, b'a', 4]);
+        assert_eq!(
+            to_confluent_avro(7, &schema, payload).unwrap(),
+            vec![0, 0, 0, 0, 7, 2, b'a', 4]
+        );
+        assert!(to_avro(&schema, json!({"id": "a"})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_avro() {
+        let decoder = PayloadDecoder::try_from(EncodingConfig::Avro {
+            schema: Some(ORDER_SCHEMA.to_string()),
+            registry: None,
+        })
+        .unwrap();
+        assert_eq!(
+            decoder.decode(&[2, b'a', 4]).await.unwrap(),
+            json!({"id": "a", "quantity": 2})
+        );
+        assert!(decoder.decode(&[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_json() {
+        let decoder = PayloadDecoder::default();
+        assert_eq!(
+            decoder.decode(br#"{"id": "a"}"#).await.unwrap(),
+            json!({"id": "a"})
+        );
+    }
+
+    #[test]
+    fn test_avro_needs_one_schema() {
+        assert!(EmitFormat::try_from(EmitFormatConfig::Avro {
+            schema: None,
+            registry: None,
+        })
+        .is_err());
+        assert!(EmitFormat::try_from(EmitFormatConfig::Avro {
+            schema: Some("not a schema".to_string()),
+            registry: None,
+        })
+        .is_err());
+    }
+}
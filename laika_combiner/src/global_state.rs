@@ -0,0 +1,149 @@
+use crate::clock::{Clock, SystemClock};
+use crate::errors::LaikaResult;
+use crate::storage::StorageKV;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use time::{Duration, OffsetDateTime};
+
+/// A value in the global state, with when it expires if it was set with a TTL
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GlobalValue {
+    pub value: Value,
+    #[serde(with = "time::serde::rfc3339::option", default)]
+    pub expires: Option<OffsetDateTime>,
+}
+
+#[derive(Default)]
+struct Values {
+    /// Values as they're persisted
+    saved: BTreeMap<String, GlobalValue>,
+    /// Values set since the state was last saved, where `None` removes the key
+    unsaved: BTreeMap<String, Option<GlobalValue>>,
+}
+
+/// Values shared by every correlation, such as a maintenance flag or a rolling count, that
+/// predicates read and write through `laika.global`.
+///
+/// Values set while an event is handled are only saved once it's handled, and are discarded if
+/// it fails, so a retried event doesn't set them twice. Clones share the same values.
+#[derive(Clone)]
+pub struct GlobalState {
+    values: Arc<Mutex<Values>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl GlobalState {
+    /// An empty global state, expiring values by `clock`
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            values: Arc::new(Mutex::new(Values::default())),
+            clock,
+        }
+    }
+
+    /// The global state persisted in `storage`, expiring values by `clock`
+    pub fn load(storage: &StorageKV, clock: Arc<dyn Clock>) -> LaikaResult<Self> {
+        let state = Self::new(clock);
+        state.lock().saved = storage.read_global_state()?;
+        Ok(state)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Values> {
+        self.values.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The value of `key`, if it's set and hasn't expired
+    pub fn get(&self, key: &str) -> Option<Value> {
+        let values = self.lock();
+        let value = match values.unsaved.get(key) {
+            Some(unsaved) => unsaved.as_ref(),
+            None => values.saved.get(key),
+        }?;
+        match value.expires {
+            Some(expires) if expires <= self.clock.now() => None,
+            _ => Some(value.value.clone()),
+        }
+    }
+
+    /// Set `key` to `value`, until `ttl` has passed if given. Setting a key to `null` removes it.
+    pub fn set(&self, key: &str, value: Value, ttl: Option<Duration>) {
+        let value = (!value.is_null()).then(|| GlobalValue {
+            value,
+            expires: ttl.map(|ttl| self.clock.now() + ttl),
+        });
+        self.lock().unsaved.insert(key.to_string(), value);
+    }
+
+    /// Persist the values set since the last save, removing those that have expired
+    pub fn save(&self, storage: &StorageKV) -> LaikaResult<()> {
+        let now = self.clock.now();
+        let mut values = self.lock();
+        let expired: Vec<String> = values
+            .saved
+            .iter()
+            .filter(|(_, value)| value.expires.is_some_and(|expires| expires <= now))
+            .map(|(key, _)| key.clone())
+            .collect();
+        if values.unsaved.is_empty() && expired.is_empty() {
+            return Ok(());
+        }
+        let mut changes = std::mem::take(&mut values.unsaved);
+        for key in expired {
+            changes.entry(key).or_insert(None);
+        }
+        storage.write_global_state(&changes)?;
+        for (key, value) in changes {
+            match value {
+                Some(value) => values.saved.insert(key, value),
+                None => values.saved.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    /// Drop the values set since the last save, such as when handling an event fails
+    pub fn discard(&self) {
+        self.lock().unsaved.clear();
+    }
+}
+
+impl Default for GlobalState {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use serde_json::json;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_global_state_expires_and_persists() {
+        let storage = StorageKV::in_memory();
+        let clock = TestClock::new(datetime!(2024-03-01 12:00:00 UTC));
+        let state = GlobalState::load(&storage, Arc::new(clock.clone())).unwrap();
+        state.set("maintenance", json!(true), Some(Duration::minutes(5)));
+        state.set("failures", json!(3), None);
+        assert_eq!(state.get("maintenance"), Some(json!(true)));
+
+        state.save(&storage).unwrap();
+        state.set("failures", json!(4), None);
+        state.discard();
+        let loaded = GlobalState::load(&storage, Arc::new(clock.clone())).unwrap();
+        assert_eq!(loaded.get("failures"), Some(json!(3)));
+        assert_eq!(loaded.get("maintenance"), Some(json!(true)));
+
+        clock.advance(Duration::minutes(5));
+        assert_eq!(loaded.get("maintenance"), None);
+        loaded.save(&storage).unwrap();
+        assert!(!storage
+            .read_global_state()
+            .unwrap()
+            .contains_key("maintenance"));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::action::EventAction;
+use crate::errors::{LaikaError, LaikaResult};
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// What RFC 3339 timestamps in captured output are replaced with, so they compare between runs
+pub const TIMESTAMP_PLACEHOLDER: &str = "<timestamp>";
+
+/// Where two JSON Lines outputs first differ
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonlMismatch {
+    /// Line the outputs differ at, from 1, or 0 when they have different numbers of lines
+    pub line: usize,
+    pub details: String,
+}
+
+impl fmt::Display for JsonlMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "JSON comparison failed at line {}: {}",
+            self.line, self.details
+        )
+    }
+}
+
+impl Error for JsonlMismatch {}
+
+/// Sort object keys and replace RFC 3339 timestamps with `TIMESTAMP_PLACEHOLDER`, so values
+/// compare and serialize the same way each run
+pub fn normalize_json(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut sorted: Vec<_> = map.iter().collect();
+            sorted.sort_by(|a, b| a.0.cmp(b.0));
+            Value::Object(
+                sorted
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), normalize_json(v)))
+                    .collect(),
+            )
+        }
+        Value::Array(arr) => Value::Array(arr.iter().map(normalize_json).collect()),
+        Value::String(s) if OffsetDateTime::parse(s, &Rfc3339).is_ok() => {
+            Value::String(TIMESTAMP_PLACEHOLDER.to_string())
+        }
+        _ => value.clone(),
+    }
+}
+
+/// Compare JSON Lines outputs line by line, after normalizing each line
+pub fn compare_jsonl(actual: &str, expected: &str) -> Result<(), JsonlMismatch> {
+    let actual_lines: Vec<_> = actual
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let expected_lines: Vec<_> = expected
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+
+    if actual_lines.len() != expected_lines.len() {
+        return Err(JsonlMismatch {
+            line: 0,
+            details: format!(
+                "Line count mismatch: actual {} vs expected {}",
+                actual_lines.len(),
+                expected_lines.len()
+            ),
+        });
+    }
+
+    for (index, (actual_line, expected_line)) in
+        actual_lines.into_iter().zip(expected_lines).enumerate()
+    {
+        let parse = |line: &str, which: &str| {
+            serde_json::from_str::<Value>(line)
+                .map(|value| normalize_json(&value))
+                .map_err(|e| JsonlMismatch {
+                    line: index + 1,
+                    details: format!("Failed to parse {} JSON: {}", which, e),
+                })
+        };
+        let actual_json = parse(actual_line, "actual")?;
+        let expected_json = parse(expected_line, "expected")?;
+        if actual_json != expected_json {
+            return Err(JsonlMismatch {
+                line: index + 1,
+                details: format!(
+                    "JSON objects differ.\nActual: {}\nExpected: {}",
+                    serde_json::to_string_pretty(&actual_json).unwrap_or_default(),
+                    serde_json::to_string_pretty(&expected_json).unwrap_or_default()
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Emits and HTTP requests captured as deterministic JSON Lines, for golden-file tests.
+///
+/// Actions are recorded a batch at a time, such as those polled after each event. Batches keep
+/// the order they were recorded in, while actions within a batch are sorted, since rules that
+/// fire together can produce them in any order.
+#[derive(Clone, Debug, Default)]
+pub struct GoldenCapture {
+    lines: Vec<String>,
+}
+
+impl GoldenCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a batch of actions, skipping those handled within the runtime
+    pub fn record(&mut self, actions: &[EventAction]) {
+        let mut batch: Vec<String> = actions
+            .iter()
+            .filter(|action| matches!(action, EventAction::Emit(_) | EventAction::Http(_)))
+            .map(|action| normalize_json(&action.describe()).to_string())
+            .collect();
+        batch.sort();
+        self.lines.extend(batch);
+    }
+
+    pub fn to_jsonl(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+
+    /// Compare the captured output with `expected` JSON Lines
+    pub fn compare(&self, expected: &str) -> Result<(), JsonlMismatch> {
+        compare_jsonl(self.to_jsonl().as_str(), expected)
+    }
+
+    /// Compare the captured output with a golden file
+    pub fn compare_file<P: AsRef<Path>>(&self, path: P) -> LaikaResult<Result<(), JsonlMismatch>> {
+        let expected =
+            std::fs::read_to_string(path.as_ref()).map_err(|e| LaikaError::IO(e.to_string()))?;
+        Ok(self.compare(expected.as_str()))
+    }
+
+    /// Write the captured output, such as to create or update a golden file
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> LaikaResult<()> {
+        std::fs::write(path.as_ref(), self.to_jsonl()).map_err(|e| LaikaError::IO(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::EmitAction;
+    use serde_json::json;
+
+    #[test]
+    fn test_matching_jsonl() {
+        let actual = r#"{"b": 1, "a": 2}
+{"x": [2, 1], "y": "2024-03-01T12:00:00Z"}"#;
+        let expected = r#"{"a": 2, "b": 1}
+{"x": [2, 1], "y": "2025-01-01T00:00:00+01:00"}"#;
+
+        assert!(compare_jsonl(actual, expected).is_ok());
+    }
+
+    #[test]
+    fn test_mismatched_jsonl() {
+        let actual = r#"{"b": 1, "a": 2}
+{"x": [1, 2], "y": 3}"#;
+        let expected = r#"{"a": 2, "b": 1}
+{"x": [2, 1], "y": 3}"#;
+
+        assert_eq!(compare_jsonl(actual, expected).unwrap_err().line, 2);
+    }
+
+    #[test]
+    fn test_capture_sorts_within_batches() {
+        let emit = |target: &str, payload: Value| {
+            EventAction::Emit(EmitAction::new(target.to_string(), payload))
+        };
+        let mut capture = GoldenCapture::new();
+        capture.record(&[emit("b", json!({"n": 1})), emit("a", json!({"n": 1}))]);
+        capture.record(&[emit("a", json!({"n": 1}))]);
+        let targets: Vec<String> = capture
+            .to_jsonl()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["target"].to_string())
+            .collect();
+        assert_eq!(targets, vec!["\"a\"", "\"b\"", "\"a\""]);
+    }
+}
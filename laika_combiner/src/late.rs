@@ -0,0 +1,100 @@
+use crate::action::{EmitAction, EventAction};
+use crate::broker::CorrelationId;
+use crate::event::{EventLike, Trigger};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use time::OffsetDateTime;
+
+/// What a rule does with an event that arrives for a correlation it's already decided on, having
+/// fired for it or had its absence window pass
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LatePolicy {
+    /// Don't evaluate the rule for the event
+    Ignore,
+    /// Evaluate the rule for the event, regardless of its timing, attaching the earlier decision
+    /// to what it produces as `correction.original`
+    Correct,
+    /// Send the event to a target with the earlier decision, instead of evaluating the rule
+    Route { target: String },
+}
+
+impl LatePolicy {
+    pub(crate) fn target(&self) -> Option<&str> {
+        match self {
+            LatePolicy::Route { target } => Some(target),
+            LatePolicy::Ignore | LatePolicy::Correct => None,
+        }
+    }
+}
+
+/// Decision a rule made for a correlation, with what it produced if it fired
+pub(crate) fn decision(output: Option<&Value>) -> Value {
+    json!({
+        "decidedAt": OffsetDateTime::now_utc().unix_timestamp(),
+        "output": output.cloned().unwrap_or(Value::Null),
+    })
+}
+
+/// Attach the earlier decision to the output of a rule evaluated for a late event
+pub(crate) fn correction(mut output: Value, original: &Value) -> Value {
+    if let Some(result) = output.as_object_mut() {
+        result.insert("correction".to_string(), json!({ "original": original }));
+    }
+    output
+}
+
+/// Action routing a late event to `target`, with the rule's earlier decision
+pub(crate) fn route(
+    target: &str,
+    rule: &str,
+    correlation_id: &CorrelationId,
+    trigger: &Trigger,
+    original: &Value,
+) -> Option<EventAction> {
+    let Trigger::ReceivedEvent(event) = trigger else {
+        return None;
+    };
+    Some(EventAction::Emit(
+        EmitAction::new(
+            target.to_string(),
+            json!({
+                "rule": rule,
+                "correlationId": correlation_id,
+                "eventType": event.event_type(),
+                "event": event.get_data(),
+                "original": original,
+            }),
+        )
+        .with_correlation(Some(correlation_id.clone())),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_policy() {
+        let policies: Vec<LatePolicy> =
+            serde_yaml::from_str("[ignore, correct, {route: {target: late_orders}}]").unwrap();
+        assert_eq!(
+            policies,
+            vec![
+                LatePolicy::Ignore,
+                LatePolicy::Correct,
+                LatePolicy::Route {
+                    target: "late_orders".to_string()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_correction() {
+        let original = decision(Some(&json!({"total": 10})));
+        let corrected = correction(json!({"total": 12}), &original);
+        assert_eq!(corrected["total"], 12);
+        assert_eq!(corrected["correction"]["original"]["output"]["total"], 10);
+    }
+}
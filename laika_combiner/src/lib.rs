@@ -2,23 +2,61 @@ use crate::errors::LaikaResult;
 use action::EventAction;
 
 pub mod action;
+pub mod admin;
+mod aggregation;
+pub mod audit;
+pub mod batching;
+pub mod bench;
 mod broker;
+pub mod calendar;
+pub mod clock;
 pub mod config;
 pub mod connections;
+pub mod controls;
+pub mod coverage;
+pub mod coordination;
+pub mod dead_letter;
+pub mod dispatcher;
 pub mod errors;
 pub mod event;
 pub mod event_handler;
 pub mod event_processor;
 mod event_schema_capnp;
+pub mod explain;
+pub mod format;
+pub mod global_state;
+pub mod golden;
+mod late;
+pub mod logging;
+mod lookup;
 mod matcher;
+mod output_schema;
+pub mod partitioning;
 mod predicate_engine;
+#[cfg(feature = "typescript")]
+pub mod predicate_types;
+pub mod provenance;
+mod redact;
+pub mod rollout;
+pub mod rule_tests;
 mod rules;
+mod runtime;
+mod schedule;
+pub mod shadow;
+pub mod size_limits;
+pub mod state;
 pub mod storage;
+pub mod telemetry;
 mod template;
+pub mod tenancy;
 pub mod timing;
 mod utils;
+pub mod watermark;
+mod windowing;
 
 pub use event_processor::processor::EventProcessor;
+pub use runtime::Runtime;
+pub use template::filters::register_filter;
 
 // Building out a CQRS pattern effectively.
 // The full architecture here will be
@@ -0,0 +1,118 @@
+use crate::errors::{LaikaError, LaikaResult};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tracing_subscriber::EnvFilter;
+
+/// How logs are written, when `RUST_LOG` isn't set
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Level logged by modules without one of their own
+    #[serde(default = "LoggingConfig::default_level")]
+    level: String,
+    /// Levels for modules by path, such as `laika_combiner::connections`
+    #[serde(default)]
+    levels: BTreeMap<String, String>,
+    /// Log the full payload of 1 in every N handled messages. Messages that fail are always
+    /// logged in full.
+    sample_payloads: Option<u64>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::default(),
+            level: Self::default_level(),
+            levels: BTreeMap::new(),
+            sample_payloads: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for log pipelines
+    Json,
+}
+
+impl LoggingConfig {
+    fn default_level() -> String {
+        "error".to_string()
+    }
+
+    /// Filter for the configured levels
+    pub fn filter(&self) -> LaikaResult<EnvFilter> {
+        let mut filter = EnvFilter::builder()
+            .parse(self.level.as_str())
+            .map_err(|e| LaikaError::config("logging.level", e.to_string()))?;
+        for (module, level) in &self.levels {
+            let directive = format!("{}={}", module, level).parse().map_err(
+                |e: tracing_subscriber::filter::ParseError| {
+                    LaikaError::config(format!("logging.levels.{}", module), e.to_string())
+                },
+            )?;
+            filter = filter.add_directive(directive);
+        }
+        Ok(filter)
+    }
+
+    pub fn payload_sampler(&self) -> PayloadSampler {
+        PayloadSampler {
+            every: self.sample_payloads.unwrap_or_default(),
+            seen: 0,
+        }
+    }
+}
+
+/// Picks which handled messages have their payloads logged
+pub struct PayloadSampler {
+    /// Never samples when `0`
+    every: u64,
+    seen: u64,
+}
+
+impl PayloadSampler {
+    /// Whether the next handled message should have its payload logged
+    pub fn sampled(&mut self) -> bool {
+        if self.every == 0 {
+            return false;
+        }
+        let sampled = self.seen % self.every == 0;
+        self.seen += 1;
+        sampled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_one_in_every() {
+        let config: LoggingConfig = serde_yaml::from_str("samplePayloads: 3").unwrap();
+        let mut sampler = config.payload_sampler();
+        let sampled: Vec<bool> = (0..6).map(|_| sampler.sampled()).collect();
+        assert_eq!(sampled, vec![true, false, false, true, false, false]);
+
+        let mut sampler = LoggingConfig::default().payload_sampler();
+        assert!(!(0..10).any(|_| sampler.sampled()));
+    }
+
+    #[test]
+    fn test_invalid_level_names_module() {
+        let config: LoggingConfig =
+            serde_yaml::from_str("format: json\nlevels:\n  laika_combiner::connections: loud\n")
+                .unwrap();
+        assert_eq!(config.format, LogFormat::Json);
+        assert!(matches!(
+            config.filter(),
+            Err(LaikaError::ConfigError { path, .. }) if path == "logging.levels.laika_combiner::connections"
+        ));
+    }
+}
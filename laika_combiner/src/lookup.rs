@@ -0,0 +1,366 @@
+use crate::errors::{ErrorSource, LaikaError, LaikaResult};
+use crate::template::Template;
+use crate::utils::env::interpolate_env;
+use crate::utils::extract_json::extract_json_field;
+use crate::utils::parse_time::parse_time_str;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Results kept in each lookup's cache before expired entries are cleared out
+const CACHE_CAPACITY: usize = 10_000;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub enum LookupSourceYaml {
+    /// GET a URL, using the JSON response
+    Http {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// GET a key, using its value as JSON where it parses as JSON, otherwise as a string
+    Redis { url: String, key: String },
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LookupErrorPolicy {
+    /// Process the event without the lookup's result
+    #[default]
+    Skip,
+    /// Fail handling the event, so it's retried or dead-lettered
+    Fail,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct LookupConfigYaml {
+    #[serde(flatten)]
+    pub source: LookupSourceYaml,
+    /// Path within the result to keep, rather than the whole result
+    #[serde(default)]
+    pub select: Option<String>,
+    #[serde(default = "LookupConfigYaml::default_timeout")]
+    pub timeout: String,
+    /// How long results are reused for, where they aren't cached without one
+    #[serde(default)]
+    pub cache: Option<String>,
+    #[serde(default)]
+    pub on_error: LookupErrorPolicy,
+}
+
+impl LookupConfigYaml {
+    fn default_timeout() -> String {
+        "1s".to_string()
+    }
+}
+
+#[derive(Clone)]
+enum LookupSource {
+    Http {
+        url: Template,
+        headers: Vec<(String, Template)>,
+        client: reqwest::Client,
+    },
+    #[cfg(feature = "redis")]
+    Redis {
+        key: Template,
+        client: redis::Client,
+        // Connected on the first lookup, so configs load without Redis being up
+        connection: Arc<tokio::sync::OnceCell<redis::aio::MultiplexedConnection>>,
+    },
+}
+
+impl std::fmt::Debug for LookupSource {
+    // Header templates may have had secrets injected from the environment
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LookupSource::Http { url, headers, .. } => f
+                .debug_struct("Http")
+                .field("url", url)
+                .field(
+                    "headers",
+                    &headers.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+                )
+                .finish(),
+            #[cfg(feature = "redis")]
+            LookupSource::Redis { key, .. } => f.debug_struct("Redis").field("key", key).finish(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LookupCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, Value)>>>,
+}
+
+impl LookupCache {
+    fn get(&self, key: &str) -> Option<Value> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(key)
+            .filter(|(fetched, _)| fetched.elapsed() < self.ttl)
+            .map(|(_, value)| value.clone())
+    }
+
+    fn insert(&self, key: String, value: Value) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= CACHE_CAPACITY {
+            entries.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+            if entries.len() >= CACHE_CAPACITY {
+                entries.clear();
+            }
+        }
+        entries.insert(key, (Instant::now(), value));
+    }
+}
+
+/// A value looked up from an external service for each event of a type, before rules are
+/// evaluated. Results are attached to the event under `_enrich`, by the name of the lookup.
+#[derive(Clone, Debug)]
+pub struct Lookup {
+    pub(crate) name: String,
+    source: LookupSource,
+    select: Option<String>,
+    timeout: Duration,
+    cache: Option<LookupCache>,
+    on_error: LookupErrorPolicy,
+}
+
+impl Lookup {
+    pub(crate) fn try_from_yaml(name: String, value: LookupConfigYaml) -> LaikaResult<Self> {
+        let string_template = |raw: &str| -> LaikaResult<Template> {
+            Ok(Template::from_payload(&serde_yaml::Value::String(
+                interpolate_env(raw)?,
+            ))?)
+        };
+        let source = match value.source {
+            LookupSourceYaml::Http { url, headers } => LookupSource::Http {
+                url: string_template(url.as_str())?,
+                headers: headers
+                    .iter()
+                    .map(|(name, raw)| string_template(raw).map(|t| (name.clone(), t)))
+                    .collect::<LaikaResult<Vec<(String, Template)>>>()?,
+                client: reqwest::Client::new(),
+            },
+            #[cfg(feature = "redis")]
+            LookupSourceYaml::Redis { url, key } => LookupSource::Redis {
+                key: string_template(key.as_str())?,
+                client: redis::Client::open(interpolate_env(url.as_str())?.as_str())
+                    .map_err(|e| LaikaError::config("redis.url", e.to_string()))?,
+                connection: Arc::new(tokio::sync::OnceCell::new()),
+            },
+            #[cfg(not(feature = "redis"))]
+            LookupSourceYaml::Redis { .. } => {
+                return Err(LaikaError::config(
+                    "redis",
+                    "Laika was built without the redis feature",
+                ))
+            }
+        };
+        let duration = |field: &str, raw: &str| {
+            parse_time_str(raw)
+                .map(|duration| duration.unsigned_abs())
+                .map_err(|e| LaikaError::config(field, e.to_string()))
+        };
+        Ok(Self {
+            source,
+            select: value.select,
+            timeout: duration("timeout", value.timeout.as_str())?,
+            cache: value
+                .cache
+                .map(|ttl| {
+                    duration("cache", ttl.as_str()).map(|ttl| LookupCache {
+                        ttl,
+                        entries: Arc::new(Mutex::new(HashMap::new())),
+                    })
+                })
+                .transpose()?,
+            on_error: value.on_error,
+            name,
+        })
+    }
+
+    fn error(&self, retryable: bool, source: ErrorSource) -> LaikaError {
+        LaikaError::LookupError {
+            lookup: self.name.clone(),
+            retryable,
+            source,
+        }
+    }
+
+    /// Look up the value for an event, or `None` when it failed and should be skipped
+    pub(crate) async fn resolve(&self, data: &Value) -> LaikaResult<Option<Value>> {
+        match self.fetch(data).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if self.on_error == LookupErrorPolicy::Skip => {
+                tracing::warn!("Skipping lookup {}: {}", self.name, e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn fetch(&self, data: &Value) -> LaikaResult<Value> {
+        let key = match &self.source {
+            LookupSource::Http { url, .. } => url.clone().render_string(data),
+            #[cfg(feature = "redis")]
+            LookupSource::Redis { key, .. } => key.clone().render_string(data),
+        }
+        .map_err(|e| self.error(false, ErrorSource::new(e)))?;
+        if let Some(value) = self.cache.as_ref().and_then(|cache| cache.get(&key)) {
+            return Ok(value);
+        }
+        let value = tokio::time::timeout(self.timeout, self.fetch_uncached(key.as_str(), data))
+            .await
+            .map_err(|_| {
+                self.error(
+                    true,
+                    ErrorSource::message(format!("Timed out after {:?}", self.timeout)),
+                )
+            })??;
+        let value = match &self.select {
+            Some(path) => extract_json_field(&value, path)
+                .map_err(|e| self.error(false, ErrorSource::new(e)))?
+                .into_owned(),
+            None => value,
+        };
+        if let Some(cache) = &self.cache {
+            cache.insert(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    async fn fetch_uncached(&self, key: &str, data: &Value) -> LaikaResult<Value> {
+        match &self.source {
+            LookupSource::Http {
+                headers, client, ..
+            } => {
+                let mut builder = client.get(key);
+                for (name, template) in headers {
+                    let value = template
+                        .clone()
+                        .render_string(data)
+                        .map_err(|e| self.error(false, ErrorSource::new(e)))?;
+                    builder = builder.header(name, value);
+                }
+                let response = builder.send().await.map_err(|e| {
+                    self.error(e.is_timeout() || e.is_connect(), ErrorSource::new(e))
+                })?;
+                let status = response.status();
+                // Nothing to enrich with isn't a failure, so the event carries on with `null`
+                if status == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(Value::Null);
+                }
+                if !status.is_success() {
+                    return Err(self.error(
+                        status.is_server_error()
+                            || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+                        ErrorSource::message(format!("Returned {}", status)),
+                    ));
+                }
+                response
+                    .json::<Value>()
+                    .await
+                    .map_err(|e| self.error(false, ErrorSource::new(e)))
+            }
+            #[cfg(feature = "redis")]
+            LookupSource::Redis {
+                client, connection, ..
+            } => {
+                let mut connection = connection
+                    .get_or_try_init(|| client.get_multiplexed_async_connection())
+                    .await
+                    .map_err(|e| self.error(true, ErrorSource::new(e)))?
+                    .clone();
+                let value: Option<String> = redis::cmd("GET")
+                    .arg(key)
+                    .query_async(&mut connection)
+                    .await
+                    .map_err(|e| {
+                        self.error(
+                            e.is_io_error() || e.is_timeout() || e.is_connection_dropped(),
+                            ErrorSource::new(e),
+                        )
+                    })?;
+                Ok(value
+                    .map(|raw| serde_json::from_str(&raw).unwrap_or(Value::String(raw)))
+                    .unwrap_or(Value::Null))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "redis"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redis_lookup(yaml: &str) -> Lookup {
+        Lookup::try_from_yaml("customer".to_string(), serde_yaml::from_str(yaml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_lookup_config() {
+        let lookup = redis_lookup(
+            r#"
+redis:
+  url: redis://localhost:6379
+  key: "customer:${{ customer_id }}"
+select: tier
+timeout: 200ms
+cache: 5m
+onError: fail
+"#,
+        );
+        assert_eq!(lookup.timeout, Duration::from_millis(200));
+        assert_eq!(lookup.cache.unwrap().ttl, Duration::from_secs(300));
+        assert_eq!(lookup.on_error, LookupErrorPolicy::Fail);
+        assert_eq!(lookup.select.as_deref(), Some("tier"));
+
+        let defaults = redis_lookup("redis: { url: 'redis://localhost', key: customer }");
+        assert_eq!(defaults.timeout, Duration::from_secs(1));
+        assert!(defaults.cache.is_none());
+        assert_eq!(defaults.on_error, LookupErrorPolicy::Skip);
+    }
+
+    #[tokio::test]
+    async fn test_lookup_cached() {
+        let lookup = redis_lookup(
+            "{ redis: { url: 'redis://localhost', key: 'customer:${{ id }}' }, cache: 1m }",
+        );
+        // Cached results are used without connecting
+        lookup
+            .cache
+            .as_ref()
+            .unwrap()
+            .insert("customer:42".to_string(), json!({"tier": "gold"}));
+        assert_eq!(
+            lookup.resolve(&json!({"id": 42})).await.unwrap(),
+            Some(json!({"tier": "gold"}))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_error_policy() {
+        // Keys that can't be rendered fail without connecting
+        let skipped = redis_lookup("{ redis: { url: 'redis://localhost', key: '${{ id }}' } }");
+        assert_eq!(skipped.resolve(&json!({})).await.unwrap(), None);
+
+        let failed = redis_lookup(
+            "{ redis: { url: 'redis://localhost', key: '${{ id }}' }, onError: fail }",
+        );
+        let error = failed.resolve(&json!({})).await.unwrap_err();
+        assert!(matches!(
+            error,
+            LaikaError::LookupError {
+                retryable: false,
+                ..
+            }
+        ));
+    }
+}
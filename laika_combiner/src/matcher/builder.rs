@@ -1,39 +1,139 @@
 use crate::config::builder::MatchOptionsBuilder;
 use crate::errors::{LaikaError, LaikaResult};
-use crate::matcher::{EventMatchPattern, EventTypeDefinition, EventTypeDefinitions, MatchOn};
+use crate::lookup::Lookup;
+use crate::matcher::{
+    EventMatchPattern, EventSources, EventTypeDefinition, EventTypeDefinitions, MatchOn,
+};
+use crate::redact::Redaction;
 use regex::Regex;
-use serde::Deserialize;
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt::Formatter;
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(untagged)]
 pub enum MatchPatternBuilder {
     Exact(String),
-    Regex { regex: String },
+    Bool(bool),
+    Number(f64),
+    Operators(MatchOperatorsBuilder),
+}
+
+/// Operators a field is matched with. Every operator given has to hold.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct MatchOperatorsBuilder {
+    #[serde(default)]
+    regex: Option<String>,
+    #[serde(default)]
+    gt: Option<f64>,
+    #[serde(default)]
+    gte: Option<f64>,
+    #[serde(default)]
+    lt: Option<f64>,
+    #[serde(default)]
+    lte: Option<f64>,
+    #[serde(default, rename = "in")]
+    one_of: Option<Vec<Value>>,
+    #[serde(default)]
+    exists: Option<bool>,
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    suffix: Option<String>,
+    #[serde(default)]
+    any_of: Option<Vec<MatchPatternBuilder>>,
+    #[serde(default)]
+    all_of: Option<Vec<MatchPatternBuilder>>,
+    /// Matches fields the pattern doesn't, including missing ones
+    #[serde(default)]
+    not: Option<Box<MatchPatternBuilder>>,
+}
+
+/// Connections an event type is received from, as one name, a list of names, or `"*"` for every
+/// source
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EventSourcesBuilder {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl TryFrom<EventSourcesBuilder> for EventSources {
+    type Error = LaikaError;
+    fn try_from(value: EventSourcesBuilder) -> LaikaResult<Self> {
+        let sources = match value {
+            EventSourcesBuilder::One(source) => vec![source],
+            EventSourcesBuilder::Many(sources) => sources,
+        };
+        if sources.is_empty() {
+            return Err(LaikaError::config("", "expected at least one connection"));
+        }
+        if sources.iter().any(|source| source == "*") {
+            return Ok(EventSources::Any);
+        }
+        Ok(EventSources::Named(sources))
+    }
+}
+
+/// Rules matching every field of `match_key` with its pattern
+fn match_rules(
+    match_key: HashMap<String, MatchPatternBuilder>,
+    path: &str,
+) -> LaikaResult<Vec<(String, MatchOn)>> {
+    match_key
+        .into_iter()
+        .map(|(field, pattern)| {
+            MatchOn::try_from(pattern)
+                .map(|p| (field.clone(), p))
+                .map_err(|e| e.within(format!("{}.{}", path, field).as_str()))
+        })
+        .collect()
 }
 
 impl TryFrom<MatchOptionsBuilder> for EventMatchPattern {
     type Error = LaikaError;
     fn try_from(builder: MatchOptionsBuilder) -> LaikaResult<Self> {
+        let specified = [
+            builder.match_all.is_some(),
+            builder.match_key.is_some(),
+            builder.match_any.is_some(),
+        ];
+        if specified.into_iter().filter(|specified| *specified).count() > 1 {
+            return Err(LaikaError::Generic(
+                "Cannot specify more than one of matchAll, matchKey and matchAny".into(),
+            ));
+        }
         if builder.match_all.is_some() {
-            if builder.match_key.is_some() {
-                return Err(LaikaError::Generic(
-                    "Cannot specify both matchAll and matchKey".into(),
-                ));
-            }
             return Ok(EventMatchPattern::All);
         }
         if let Some(match_key) = builder.match_key {
-            return Ok(EventMatchPattern::MatchRules(
-                match_key
+            return Ok(EventMatchPattern::MatchRules(match_rules(
+                match_key, "matchKey",
+            )?));
+        }
+        if let Some(match_any) = builder.match_any {
+            if match_any.is_empty() {
+                return Err(LaikaError::config(
+                    "matchAny",
+                    "expected at least one set of match keys",
+                ));
+            }
+            return Ok(EventMatchPattern::AnyOf(
+                match_any
                     .into_iter()
-                    .map(|(field, pattern)| pattern.try_into().map(|p| (field, p)))
-                    .collect::<LaikaResult<Vec<(String, MatchOn)>>>()?,
+                    .enumerate()
+                    .map(|(index, match_key)| {
+                        match_rules(match_key, format!("matchAny.{}", index).as_str())
+                    })
+                    .collect::<LaikaResult<Vec<Vec<(String, MatchOn)>>>>()?,
             ));
         }
-        // If we reach here, neither matchAll nor matchKey was specified, which is an error
+        // If we reach here, no matcher was specified, which is an error
         Err(LaikaError::Generic(
-            "Must specify either matchAll or matchKey".into(),
+            "Must specify one of matchAll, matchKey or matchAny".into(),
         ))
     }
 }
@@ -43,16 +143,99 @@ impl TryFrom<MatchPatternBuilder> for MatchOn {
     fn try_from(value: MatchPatternBuilder) -> LaikaResult<Self> {
         match value {
             MatchPatternBuilder::Exact(string) => Ok(MatchOn::Exactly(string)),
-            MatchPatternBuilder::Regex { regex } => match Regex::new(&*regex) {
-                Ok(re) => Ok(MatchOn::Regex(re)),
-                Err(e) => Err(LaikaError::RegexError(e.to_string())),
-            },
+            MatchPatternBuilder::Bool(expected) => Ok(MatchOn::Bool(expected)),
+            MatchPatternBuilder::Number(number) => Ok(MatchOn::Number(number)),
+            MatchPatternBuilder::Operators(operators) => operators.try_into(),
         }
     }
 }
 
+fn compose(patterns: Vec<MatchPatternBuilder>, operator: &str) -> LaikaResult<Vec<MatchOn>> {
+    if patterns.is_empty() {
+        return Err(LaikaError::config(
+            operator,
+            "expected at least one pattern",
+        ));
+    }
+    patterns
+        .into_iter()
+        .enumerate()
+        .map(|(index, pattern)| {
+            MatchOn::try_from(pattern)
+                .map_err(|e| e.within(format!("{}.{}", operator, index).as_str()))
+        })
+        .collect()
+}
+
+impl TryFrom<MatchOperatorsBuilder> for MatchOn {
+    type Error = LaikaError;
+    fn try_from(value: MatchOperatorsBuilder) -> LaikaResult<Self> {
+        let mut rules = Vec::new();
+        if let Some(regex) = value.regex {
+            match Regex::new(&regex) {
+                Ok(re) => rules.push(MatchOn::Regex(re)),
+                Err(e) => return Err(LaikaError::RegexError(e.to_string())),
+            }
+        }
+        rules.extend(value.gt.map(MatchOn::Gt));
+        rules.extend(value.gte.map(MatchOn::Gte));
+        rules.extend(value.lt.map(MatchOn::Lt));
+        rules.extend(value.lte.map(MatchOn::Lte));
+        rules.extend(value.one_of.map(MatchOn::In));
+        rules.extend(value.exists.map(MatchOn::Exists));
+        rules.extend(value.prefix.map(MatchOn::Prefix));
+        rules.extend(value.suffix.map(MatchOn::Suffix));
+        if let Some(any_of) = value.any_of {
+            rules.push(MatchOn::AnyOf(compose(any_of, "anyOf")?));
+        }
+        if let Some(all_of) = value.all_of {
+            rules.push(MatchOn::AllOf(compose(all_of, "allOf")?));
+        }
+        if let Some(not) = value.not {
+            let not = MatchOn::try_from(*not).map_err(|e| e.within("not"))?;
+            rules.push(MatchOn::Not(Box::new(not)));
+        }
+        match rules.len() {
+            0 => Err(LaikaError::config(
+                "",
+                "expected a value or at least one operator",
+            )),
+            1 => Ok(rules.remove(0)),
+            _ => Ok(MatchOn::AllOf(rules)),
+        }
+    }
+}
+
+/// Event definitions in the order they're written, which decides between them where a message
+/// matches several
 #[derive(Deserialize, Debug, Clone)]
-pub struct EventMatchBuilder(HashMap<String, MatchOptionsBuilder>);
+pub struct EventMatchBuilder(
+    #[serde(deserialize_with = "definitions_in_order")] Vec<(String, MatchOptionsBuilder)>,
+);
+
+fn definitions_in_order<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(String, MatchOptionsBuilder)>, D::Error> {
+    struct InOrder;
+
+    impl<'de> Visitor<'de> for InOrder {
+        type Value = Vec<(String, MatchOptionsBuilder)>;
+
+        fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+            formatter.write_str("a map of event types to their definitions")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut definitions = Vec::new();
+            while let Some(definition) = map.next_entry()? {
+                definitions.push(definition);
+            }
+            Ok(definitions)
+        }
+    }
+
+    deserializer.deserialize_map(InOrder)
+}
 
 impl EventMatchBuilder {
     pub fn new() -> Self {
@@ -61,18 +244,54 @@ impl EventMatchBuilder {
         }
     }
 
+    /// Names of the defined event types
+    pub(crate) fn event_types(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(event_type, _)| event_type.as_str())
+    }
+
     pub fn build(self) -> LaikaResult<EventTypeDefinitions> {
         let event_match_rules = self
             .0
             .into_iter()
             .map(|(event_type, match_pattern)| {
-                let event_source = match_pattern.from.clone();
+                let sources = EventSources::try_from(match_pattern.from.clone())
+                    .map_err(|e| e.within(format!("events.{}.from", event_type).as_str()))?;
+                let enrich = match_pattern.enrich.clone();
+                let event_time = match_pattern.event_time.clone();
+                let priority = match_pattern.priority;
+                let lookups = match_pattern
+                    .lookup
+                    .clone()
+                    .into_iter()
+                    .map(|(name, lookup)| {
+                        Lookup::try_from_yaml(name.clone(), lookup).map_err(|e| {
+                            e.within(format!("events.{}.lookup.{}", event_type, name).as_str())
+                        })
+                    })
+                    .collect::<LaikaResult<Vec<Lookup>>>()?;
+                let redactions = match_pattern
+                    .redact
+                    .clone()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, redaction)| {
+                        Redaction::try_from(redaction).map_err(|e| {
+                            e.within(format!("events.{}.redact.{}", event_type, index).as_str())
+                        })
+                    })
+                    .collect::<LaikaResult<Vec<Redaction>>>()?;
                 EventMatchPattern::try_from(match_pattern)
-                    .map(|mp| EventTypeDefinition::new(event_source, mp, event_type))
+                    .map_err(|e| e.within(format!("events.{}", event_type).as_str()))
+                    .map(|mp| {
+                        EventTypeDefinition::from_sources(sources, mp, event_type)
+                            .with_enrich(enrich)
+                            .with_lookups(lookups)
+                            .with_redactions(redactions)
+                            .with_event_time(event_time)
+                            .with_priority(priority)
+                    })
             })
             .collect::<LaikaResult<Vec<EventTypeDefinition>>>()?;
-        Ok(EventTypeDefinitions {
-            type_definitions: event_match_rules,
-        })
+        Ok(EventTypeDefinitions::new(event_match_rules))
     }
 }
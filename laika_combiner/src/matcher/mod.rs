@@ -1,48 +1,253 @@
 pub mod builder;
 
 use crate::errors::{LaikaError, LaikaResult};
+use crate::event::EventMetadata;
+use crate::lookup::Lookup;
+use crate::redact::Redaction;
 use crate::utils::extract_json::extract_json_field;
 use regex::Regex;
-use serde_json::Value;
-use std::collections::HashSet;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 
 pub type MaybeEventType = Option<String>;
 
 pub type EventType = String;
 
+/// How a message matching several event types is handled
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AmbiguousMatch {
+    /// Handle it as every type it matches
+    #[default]
+    All,
+    /// Handle it as every type it matches, logging a warning
+    Warn,
+    /// Handle it as the type defined first
+    First,
+    /// Handle it as the type with the highest `priority`, then the one defined first
+    Priority,
+    /// Fail the message
+    Error,
+}
+
 #[derive(Clone, Default, Debug)]
 /// Defining Event Types based on the Patterns they meet.
 pub struct EventTypeDefinitions {
     type_definitions: Vec<EventTypeDefinition>,
+    /// Fields merged into every event from a source, by source
+    source_enrichment: HashMap<String, Map<String, Value>>,
+    ambiguous_match: AmbiguousMatch,
 }
 
 impl EventTypeDefinitions {
-    /// All unique connection sources used in Event Definitions
+    /// Names of the defined event types
+    pub(crate) fn event_types(&self) -> impl Iterator<Item = &str> {
+        self.type_definitions
+            .iter()
+            .map(|definition| definition.event_type.as_str())
+    }
+
+    /// All unique connection sources named in Event Definitions
     pub(crate) fn receivers(&self) -> Vec<String> {
         self.type_definitions
             .iter()
-            .map(|type_definition| type_definition.source.clone())
+            .flat_map(|type_definition| match &type_definition.sources {
+                EventSources::Any => [].as_slice(),
+                EventSources::Named(sources) => sources.as_slice(),
+            })
+            .cloned()
             .collect::<HashSet<String>>()
             .into_iter()
             .collect()
     }
+
+    /// Whether any event type is received from every source
+    pub(crate) fn receives_from_any(&self) -> bool {
+        self.type_definitions
+            .iter()
+            .any(|type_definition| type_definition.sources == EventSources::Any)
+    }
+
+    /// Merge fields into every event from a source, before it's matched
+    pub(crate) fn with_source_enrichment(
+        mut self,
+        source_enrichment: HashMap<String, Map<String, Value>>,
+    ) -> Self {
+        self.source_enrichment = source_enrichment;
+        self
+    }
+
+    pub(crate) fn with_ambiguous_match(mut self, ambiguous_match: AmbiguousMatch) -> Self {
+        self.ambiguous_match = ambiguous_match;
+        self
+    }
+
+    pub(crate) fn ambiguous_match(&self) -> AmbiguousMatch {
+        self.ambiguous_match
+    }
+
+    /// Narrow the event types a message matched, in the order they're defined, down to those
+    /// it's handled as
+    pub(crate) fn resolve_matches(
+        &self,
+        mut matched: Vec<EventType>,
+    ) -> LaikaResult<Vec<EventType>> {
+        if matched.len() < 2 {
+            return Ok(matched);
+        }
+        match self.ambiguous_match {
+            AmbiguousMatch::All | AmbiguousMatch::Warn => Ok(matched),
+            AmbiguousMatch::First => {
+                matched.truncate(1);
+                Ok(matched)
+            }
+            AmbiguousMatch::Priority => {
+                let priority = |event_type: &str| {
+                    self.type_definitions
+                        .iter()
+                        .find(|definition| definition.event_type == event_type)
+                        .map(|definition| definition.priority)
+                        .unwrap_or_default()
+                };
+                let (chosen, _) = matched
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|(index, event_type)| (priority(event_type), Reverse(*index)))
+                    .expect("several event types were matched");
+                Ok(vec![matched.swap_remove(chosen)])
+            }
+            AmbiguousMatch::Error => Err(LaikaError::AmbiguousMatch(matched)),
+        }
+    }
+
+    /// Fields merged into events from `source` before they're matched
+    pub(crate) fn source_enrichment(&self, source: &str) -> Option<&Map<String, Value>> {
+        self.source_enrichment.get(source)
+    }
+
+    /// Fields merged into events once they're matched as `event_type`
+    pub(crate) fn type_enrichment(&self, event_type: &str) -> Option<&Map<String, Value>> {
+        self.type_definitions
+            .iter()
+            .find(|definition| definition.event_type == event_type)
+            .map(|definition| &definition.enrich)
+    }
+
+    /// Values looked up for events once they're matched as `event_type`
+    pub(crate) fn lookups(&self, event_type: &str) -> &[Lookup] {
+        self.type_definitions
+            .iter()
+            .find(|definition| definition.event_type == event_type)
+            .map(|definition| definition.lookups.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Fields redacted from events once they're matched as `event_type`
+    pub(crate) fn redactions(&self, event_type: &str) -> &[Redaction] {
+        self.type_definitions
+            .iter()
+            .find(|definition| definition.event_type == event_type)
+            .map(|definition| definition.redactions.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// JSONPath to when events of `event_type` happened, if it's read from the event
+    pub(crate) fn event_time(&self, event_type: &str) -> Option<&str> {
+        self.type_definitions
+            .iter()
+            .find(|definition| definition.event_type == event_type)
+            .and_then(|definition| definition.event_time.as_deref())
+    }
+
+    /// Whether any event type reads when it happened from the event
+    pub(crate) fn has_event_time(&self) -> bool {
+        self.type_definitions
+            .iter()
+            .any(|definition| definition.event_time.is_some())
+    }
+}
+
+/// Connections an event type is received from
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventSources {
+    /// Every connection that's a source, as `from: "*"`
+    Any,
+    Named(Vec<String>),
+}
+
+impl EventSources {
+    fn includes(&self, source: &str) -> bool {
+        match self {
+            EventSources::Any => true,
+            EventSources::Named(sources) => sources.iter().any(|named| named == source),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct EventTypeDefinition {
-    source: String, // Named Connection Source for this event type
+    sources: EventSources, // Named Connection Sources for this event type
     match_pattern: EventMatchPattern,
     event_type: EventType,
+    /// Fields merged into events of this type
+    enrich: Map<String, Value>,
+    lookups: Vec<Lookup>,
+    redactions: Vec<Redaction>,
+    /// JSONPath to when events of this type happened, where it isn't when they're received
+    event_time: Option<String>,
+    /// Preference for this type over others a message also matches, highest first
+    priority: i32,
 }
 
 impl EventTypeDefinition {
     pub fn new(source: String, match_pattern: EventMatchPattern, event_type: EventType) -> Self {
+        Self::from_sources(EventSources::Named(vec![source]), match_pattern, event_type)
+    }
+
+    pub fn from_sources(
+        sources: EventSources,
+        match_pattern: EventMatchPattern,
+        event_type: EventType,
+    ) -> Self {
         Self {
-            source,
+            sources,
             match_pattern,
             event_type,
+            enrich: Map::new(),
+            lookups: Vec::new(),
+            redactions: Vec::new(),
+            event_time: None,
+            priority: 0,
         }
     }
+
+    pub fn with_enrich(mut self, enrich: Map<String, Value>) -> Self {
+        self.enrich = enrich;
+        self
+    }
+
+    pub(crate) fn with_lookups(mut self, lookups: Vec<Lookup>) -> Self {
+        self.lookups = lookups;
+        self
+    }
+
+    pub(crate) fn with_redactions(mut self, redactions: Vec<Redaction>) -> Self {
+        self.redactions = redactions;
+        self
+    }
+
+    pub(crate) fn with_event_time(mut self, event_time: Option<String>) -> Self {
+        self.event_time = event_time;
+        self
+    }
+
+    pub(crate) fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -53,57 +258,151 @@ pub enum EventMatchPattern {
     ///
     /// (MatchKey, MatchRule)
     MatchRules(Vec<(String, MatchOn)>),
+    /// Events that meet all the rules of any of these sets match this. A set with a field the
+    /// event doesn't have just doesn't match.
+    AnyOf(Vec<Vec<(String, MatchOn)>>),
 }
 
 #[derive(Clone, Debug)]
 pub enum MatchOn {
     Exactly(String),
     Regex(Regex),
+    Bool(bool),
+    Number(f64),
+    Gt(f64),
+    Gte(f64),
+    Lt(f64),
+    Lte(f64),
+    /// Equal to one of the values
+    In(Vec<Value>),
+    /// Whether the field is present, even as `null`
+    Exists(bool),
+    Prefix(String),
+    Suffix(String),
+    AnyOf(Vec<MatchOn>),
+    AllOf(Vec<MatchOn>),
+    Not(Box<MatchOn>),
+}
+
+/// Whether two values are equal, where numbers are compared by value so `1` is `1.0`
+fn same_value(value: &Value, expected: &Value) -> bool {
+    match (value.as_f64(), expected.as_f64()) {
+        (Some(value), Some(expected)) => value == expected,
+        _ => value == expected,
+    }
+}
+
+impl MatchOn {
+    /// Whether the rule holds for a field's value, where `None` is a missing field
+    pub(crate) fn matches(&self, value: Option<&Value>) -> bool {
+        let number = value.and_then(Value::as_f64);
+        let string = value.and_then(Value::as_str);
+        match self {
+            MatchOn::Exactly(expected) => string == Some(expected.as_str()),
+            MatchOn::Regex(regex) => string.is_some_and(|string| regex.is_match(string)),
+            MatchOn::Bool(expected) => value.and_then(Value::as_bool) == Some(*expected),
+            MatchOn::Number(expected) => number == Some(*expected),
+            MatchOn::Gt(bound) => number.is_some_and(|number| number > *bound),
+            MatchOn::Gte(bound) => number.is_some_and(|number| number >= *bound),
+            MatchOn::Lt(bound) => number.is_some_and(|number| number < *bound),
+            MatchOn::Lte(bound) => number.is_some_and(|number| number <= *bound),
+            MatchOn::In(values) => {
+                value.is_some_and(|value| values.iter().any(|expected| same_value(value, expected)))
+            }
+            MatchOn::Exists(expected) => value.is_some() == *expected,
+            MatchOn::Prefix(prefix) => {
+                string.is_some_and(|string| string.starts_with(prefix.as_str()))
+            }
+            MatchOn::Suffix(suffix) => {
+                string.is_some_and(|string| string.ends_with(suffix.as_str()))
+            }
+            MatchOn::AnyOf(rules) => rules.iter().any(|rule| rule.matches(value)),
+            MatchOn::AllOf(rules) => rules.iter().all(|rule| rule.matches(value)),
+            MatchOn::Not(rule) => !rule.matches(value),
+        }
+    }
+
+    /// Whether the rule decides on missing fields, rather than them failing the match
+    fn handles_missing(&self) -> bool {
+        match self {
+            MatchOn::Exists(_) | MatchOn::Not(_) => true,
+            MatchOn::AnyOf(rules) | MatchOn::AllOf(rules) => {
+                rules.iter().any(MatchOn::handles_missing)
+            }
+            _ => false,
+        }
+    }
 }
 
 impl EventTypeDefinitions {
     pub fn new(event_match_rules: Vec<EventTypeDefinition>) -> Self {
         Self {
             type_definitions: event_match_rules,
+            source_enrichment: HashMap::new(),
+            ambiguous_match: AmbiguousMatch::default(),
         }
     }
 
-    /// Attempts to match a JSON message against the configured event types, returning
-    /// all matching types.
-    fn match_rule(value: &str, match_on: &MatchOn) -> bool {
-        match match_on {
-            MatchOn::Exactly(matched_item) => value == matched_item.as_str(),
-            MatchOn::Regex(regex) => regex.is_match(value),
-        }
+    /// Find the field a match key refers to. Keys starting with `@` are read from the message's
+    /// metadata, e.g. `@header.x-event-type`.
+    fn match_field<'a>(
+        message: &'a Value,
+        metadata: &'a EventMetadata,
+        field_path: &str,
+    ) -> LaikaResult<Cow<'a, Value>> {
+        let Some(path) = field_path.strip_prefix('@') else {
+            return extract_json_field(message, field_path);
+        };
+        let (first, rest) = path.split_once('.').unwrap_or((path, ""));
+        let value = metadata
+            .get(first)
+            .ok_or_else(|| LaikaError::FieldNotFound(first.to_string(), field_path.to_string()))?;
+        extract_json_field(value, rest)
     }
 
+    /// Whether a message meets every rule. A field that's missing fails unless its rule decides
+    /// on missing fields.
+    fn match_rules(
+        message: &Value,
+        metadata: &EventMetadata,
+        match_rules: &[(String, MatchOn)],
+    ) -> LaikaResult<bool> {
+        match_rules
+            .iter()
+            .map(|(field_path, match_rule)| {
+                match Self::match_field(message, metadata, field_path) {
+                    Ok(value) => Ok(match_rule.matches(Some(&value))),
+                    Err(_) if match_rule.handles_missing() => Ok(match_rule.matches(None)),
+                    Err(e) => Err(e),
+                }
+            })
+            .try_fold(true, |acc, x| Ok::<bool, LaikaError>(acc && x?))
+    }
+
+    /// Attempts to match a JSON message against the configured event types, returning
+    /// all matching types.
     pub fn match_message(
         &self,
         event_source: &str,
         message: &Value,
+        metadata: &EventMetadata,
     ) -> LaikaResult<Vec<EventType>> {
         let mut matching_event_types: Vec<EventType> = Vec::new();
         for event_type_definition in &self.type_definitions {
-            if event_type_definition.source == event_source {
+            if event_type_definition.sources.includes(event_source) {
                 match &event_type_definition.match_pattern {
                     EventMatchPattern::All => {
                         matching_event_types.push(event_type_definition.event_type.clone());
                     }
                     EventMatchPattern::MatchRules(match_rules) => {
-                        if match_rules
-                            .iter()
-                            .map(|(field_path, match_rule)| {
-                                extract_json_field(message, field_path).map(|value| {
-                                    match value.as_str() {
-                                        Some(value) => {
-                                            EventTypeDefinitions::match_rule(value, match_rule)
-                                        }
-                                        None => false,
-                                    }
-                                })
-                            })
-                            .try_fold(true, |acc, x| Ok::<bool, LaikaError>(acc && x?))?
-                        {
+                        if Self::match_rules(message, metadata, match_rules)? {
+                            matching_event_types.push(event_type_definition.event_type.clone());
+                        }
+                    }
+                    EventMatchPattern::AnyOf(rule_sets) => {
+                        if rule_sets.iter().any(|match_rules| {
+                            Self::match_rules(message, metadata, match_rules).unwrap_or(false)
+                        }) {
                             matching_event_types.push(event_type_definition.event_type.clone());
                         }
                     }
@@ -113,3 +412,200 @@ impl EventTypeDefinitions {
         Ok(matching_event_types)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_match_metadata() -> LaikaResult<()> {
+        let definitions = EventTypeDefinitions::new(vec![EventTypeDefinition::new(
+            "orders".to_string(),
+            EventMatchPattern::MatchRules(vec![(
+                "@header.x-event-type".to_string(),
+                MatchOn::Exactly("OrderPlaced".to_string()),
+            )]),
+            "orderPlaced".to_string(),
+        )]);
+        let metadata: EventMetadata = json!({"header": {"x-event-type": "OrderPlaced"}})
+            .as_object()
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            definitions.match_message("orders", &json!({"id": 1}), &metadata)?,
+            vec!["orderPlaced"]
+        );
+        assert!(definitions
+            .match_message("orders", &json!({"id": 1}), &EventMetadata::new())
+            .is_err());
+        Ok(())
+    }
+
+    fn match_on(yaml: &str) -> MatchOn {
+        serde_yaml::from_str::<builder::MatchPatternBuilder>(yaml)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_match_operators() {
+        let amount = json!(25);
+        let amount = Some(&amount);
+        assert!(match_on("{ gt: 10, lte: 25 }").matches(amount));
+        assert!(!match_on("{ gte: 10, lt: 25 }").matches(amount));
+        assert!(match_on("25").matches(amount));
+        assert!(match_on("{ in: [10, 25.0, 'x'] }").matches(amount));
+        assert!(!match_on("{ prefix: '2' }").matches(amount));
+
+        let region = json!("eu-west-1");
+        let region = Some(&region);
+        assert!(match_on("{ prefix: eu-, suffix: '-1' }").matches(region));
+        assert!(match_on("{ anyOf: [us-east-1, { regex: '^eu-' }] }").matches(region));
+        assert!(!match_on("{ allOf: [{ prefix: eu- }, { in: [eu-west-2] }] }").matches(region));
+
+        assert!(match_on("true").matches(Some(&json!(true))));
+        assert!(!match_on("true").matches(Some(&json!("true"))));
+        assert!(match_on("{ exists: true }").matches(Some(&Value::Null)));
+        assert!(match_on("{ exists: false }").matches(None));
+        assert!(!match_on("{ gt: 0 }").matches(None));
+    }
+
+    #[test]
+    fn test_invalid_match_operators() {
+        let invalid = |yaml: &str| {
+            serde_yaml::from_str::<builder::MatchPatternBuilder>(yaml)
+                .map_err(|e| LaikaError::Generic(e.to_string()))
+                .and_then(MatchOn::try_from)
+                .is_err()
+        };
+        assert!(invalid("{}"));
+        assert!(invalid("{ anyOf: [] }"));
+        assert!(invalid("{ regex: '(' }"));
+        assert!(invalid("{ greaterThan: 1 }"));
+    }
+
+    #[test]
+    fn test_match_not() {
+        let not_sandbox = match_on("{ not: sandbox }");
+        assert!(not_sandbox.matches(Some(&json!("eu-west-1"))));
+        assert!(!not_sandbox.matches(Some(&json!("sandbox"))));
+        assert!(not_sandbox.matches(None));
+        assert!(!match_on("{ not: { in: [a, b] } }").matches(Some(&json!("b"))));
+    }
+
+    #[test]
+    fn test_match_any() -> LaikaResult<()> {
+        let options: crate::config::builder::MatchOptionsBuilder = serde_yaml::from_str(
+            r#"
+            from: payments
+            matchAny:
+              - type: PaymentX
+                region: { not: sandbox }
+              - type: Refund
+            "#,
+        )
+        .unwrap();
+        let definitions = EventTypeDefinitions::new(vec![EventTypeDefinition::new(
+            "payments".to_string(),
+            options.try_into()?,
+            "payment".to_string(),
+        )]);
+        let matches = |message: Value| {
+            definitions
+                .match_message("payments", &message, &EventMetadata::new())
+                .unwrap()
+                .len()
+        };
+        assert_eq!(matches(json!({"type": "PaymentX", "region": "eu"})), 1);
+        assert_eq!(matches(json!({"type": "PaymentX"})), 1);
+        assert_eq!(matches(json!({"type": "PaymentX", "region": "sandbox"})), 0);
+        assert_eq!(matches(json!({"type": "Refund", "region": "sandbox"})), 1);
+        assert_eq!(matches(json!({"id": 1})), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ambiguous_match() {
+        let resolve = |policy: AmbiguousMatch| {
+            EventTypeDefinitions::new(vec![
+                EventTypeDefinition::new("orders".into(), EventMatchPattern::All, "any".into()),
+                EventTypeDefinition::new("orders".into(), EventMatchPattern::All, "order".into())
+                    .with_priority(5),
+            ])
+            .with_ambiguous_match(policy)
+            .resolve_matches(vec!["any".to_string(), "order".to_string()])
+        };
+        assert_eq!(resolve(AmbiguousMatch::All).unwrap(), vec!["any", "order"]);
+        assert_eq!(resolve(AmbiguousMatch::Warn).unwrap(), vec!["any", "order"]);
+        assert_eq!(resolve(AmbiguousMatch::First).unwrap(), vec!["any"]);
+        assert_eq!(resolve(AmbiguousMatch::Priority).unwrap(), vec!["order"]);
+        assert!(matches!(
+            resolve(AmbiguousMatch::Error),
+            Err(LaikaError::AmbiguousMatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_match_sources() -> LaikaResult<()> {
+        let sources = |yaml: &str| {
+            EventSources::try_from(
+                serde_yaml::from_str::<builder::EventSourcesBuilder>(yaml).unwrap(),
+            )
+        };
+        let definitions = EventTypeDefinitions::new(vec![
+            EventTypeDefinition::from_sources(
+                sources("[orders, payments]")?,
+                EventMatchPattern::All,
+                "business".into(),
+            ),
+            EventTypeDefinition::from_sources(
+                sources("'*'")?,
+                EventMatchPattern::All,
+                "audit".into(),
+            ),
+        ]);
+        let matched = |source: &str| {
+            definitions
+                .match_message(source, &json!({}), &EventMetadata::new())
+                .unwrap()
+        };
+        assert_eq!(matched("payments"), vec!["business", "audit"]);
+        assert_eq!(matched("metrics"), vec!["audit"]);
+        assert!(definitions.receives_from_any());
+        let mut receivers = definitions.receivers();
+        receivers.sort();
+        assert_eq!(receivers, vec!["orders", "payments"]);
+        assert!(sources("[]").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_missing_fields() -> LaikaResult<()> {
+        let definitions = EventTypeDefinitions::new(vec![EventTypeDefinition::new(
+            "orders".to_string(),
+            EventMatchPattern::MatchRules(vec![
+                ("$.type".to_string(), match_on("OrderPlaced")),
+                ("$.test".to_string(), match_on("{ exists: false }")),
+            ]),
+            "orderPlaced".to_string(),
+        )]);
+        assert_eq!(
+            definitions.match_message(
+                "orders",
+                &json!({"type": "OrderPlaced"}),
+                &EventMetadata::new()
+            )?,
+            vec!["orderPlaced"]
+        );
+        assert!(definitions
+            .match_message(
+                "orders",
+                &json!({"type": "OrderPlaced", "test": true}),
+                &EventMetadata::new()
+            )?
+            .is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use crate::errors::{LaikaError, LaikaResult};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const TYPES: &str = "string, number, integer, boolean, array, object or any";
+
+/// A field in an `outputSchema`, either a type name such as `string`, ending in `?` when the
+/// field can be missing or null, or the fields of an object
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum FieldSchemaYaml {
+    Type(String),
+    Object(BTreeMap<String, FieldSchemaYaml>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum FieldType {
+    Any,
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    /// An object, with the fields it must have if they're given
+    Object(Option<BTreeMap<String, FieldSchema>>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct FieldSchema {
+    field_type: FieldType,
+    optional: bool,
+}
+
+/// Fields a rule's `filterAndExtract` result must have, checked before its action is rendered so
+/// a mistake in the predicate is reported against the rule rather than as a template error
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutputSchema {
+    fields: BTreeMap<String, FieldSchema>,
+}
+
+fn parse_fields(
+    fields: &BTreeMap<String, FieldSchemaYaml>,
+) -> LaikaResult<BTreeMap<String, FieldSchema>> {
+    fields
+        .iter()
+        .map(|(name, field)| {
+            let schema = match field {
+                FieldSchemaYaml::Object(fields) => FieldSchema {
+                    field_type: FieldType::Object(Some(
+                        parse_fields(fields).map_err(|e| e.within(name))?,
+                    )),
+                    optional: false,
+                },
+                FieldSchemaYaml::Type(name_of_type) => {
+                    let (type_name, optional) = match name_of_type.strip_suffix('?') {
+                        Some(type_name) => (type_name, true),
+                        None => (name_of_type.as_str(), false),
+                    };
+                    let field_type = match type_name {
+                        "any" => FieldType::Any,
+                        "string" => FieldType::String,
+                        "number" => FieldType::Number,
+                        "integer" => FieldType::Integer,
+                        "boolean" => FieldType::Boolean,
+                        "array" => FieldType::Array,
+                        "object" => FieldType::Object(None),
+                        _ => {
+                            return Err(LaikaError::config(
+                                name,
+                                format!("unknown type {}, expected {}", type_name, TYPES),
+                            ))
+                        }
+                    };
+                    FieldSchema {
+                        field_type,
+                        optional,
+                    }
+                }
+            };
+            Ok((name.clone(), schema))
+        })
+        .collect()
+}
+
+fn type_of(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn check_fields(
+    fields: &BTreeMap<String, FieldSchema>,
+    value: &Value,
+    path: &str,
+    problems: &mut Vec<String>,
+) {
+    let Value::Object(object) = value else {
+        problems.push(format!(
+            "{}: expected object, got {}",
+            if path.is_empty() { "output" } else { path },
+            type_of(value)
+        ));
+        return;
+    };
+    for (name, field) in fields {
+        let path = match path {
+            "" => name.clone(),
+            parent => format!("{}.{}", parent, name),
+        };
+        match object.get(name) {
+            None | Some(Value::Null) if field.optional => {}
+            None => problems.push(format!("{}: missing", path)),
+            Some(value) => check_type(&field.field_type, value, &path, problems),
+        }
+    }
+}
+
+fn check_type(field_type: &FieldType, value: &Value, path: &str, problems: &mut Vec<String>) {
+    let matches = match field_type {
+        FieldType::Any => true,
+        FieldType::String => value.is_string(),
+        FieldType::Number => value.is_number(),
+        // Predicates return JavaScript numbers, so whole floats count as integers
+        FieldType::Integer => value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        FieldType::Boolean => value.is_boolean(),
+        FieldType::Array => value.is_array(),
+        FieldType::Object(Some(fields)) => return check_fields(fields, value, path, problems),
+        FieldType::Object(None) => value.is_object(),
+    };
+    if !matches {
+        let expected = match field_type {
+            FieldType::Any => "any",
+            FieldType::String => "string",
+            FieldType::Number => "number",
+            FieldType::Integer => "integer",
+            FieldType::Boolean => "boolean",
+            FieldType::Array => "array",
+            FieldType::Object(_) => "object",
+        };
+        problems.push(format!(
+            "{}: expected {}, got {}",
+            path,
+            expected,
+            type_of(value)
+        ));
+    }
+}
+
+impl OutputSchema {
+    pub(crate) fn parse(fields: &BTreeMap<String, FieldSchemaYaml>) -> LaikaResult<Self> {
+        Ok(Self {
+            fields: parse_fields(fields)?,
+        })
+    }
+
+    /// Check a rule's output, failing with every way it doesn't match
+    pub(crate) fn validate(&self, rule: &str, output: &Value) -> LaikaResult<()> {
+        let mut problems = Vec::new();
+        check_fields(&self.fields, output, "", &mut problems);
+        if problems.is_empty() {
+            return Ok(());
+        }
+        Err(LaikaError::OutputSchemaError {
+            rule: rule.to_string(),
+            message: problems.join("; "),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema(yaml: &str) -> LaikaResult<OutputSchema> {
+        OutputSchema::parse(&serde_yaml::from_str(yaml).unwrap())
+    }
+
+    #[test]
+    fn test_validate_output() {
+        let schema =
+            schema("orderId: string\namount: number\nnote: string?\ncustomer:\n  id: integer\n")
+                .unwrap();
+        assert!(schema
+            .validate(
+                "orderPaid",
+                &json!({"orderId": "1", "amount": 9.5, "customer": {"id": 4.0}})
+            )
+            .is_ok());
+        let error = schema
+            .validate(
+                "orderPaid",
+                &json!({"orderId": 1, "note": null, "customer": {"id": 4.5}}),
+            )
+            .unwrap_err();
+        assert_eq!(
+            error,
+            LaikaError::OutputSchemaError {
+                rule: "orderPaid".to_string(),
+                message: "amount: missing; customer.id: expected integer, got number; \
+                          orderId: expected string, got number"
+                    .to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        assert_eq!(
+            schema("customer:\n  id: int\n").unwrap_err(),
+            LaikaError::config(
+                "customer.id",
+                format!("unknown type int, expected {}", TYPES)
+            )
+        );
+    }
+}
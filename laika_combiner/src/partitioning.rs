@@ -0,0 +1,174 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::utils::env::interpolate_env;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+
+/// Splits correlations between instances reading the same sources, so each correlation is only
+/// handled by one of them and its state isn't split
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PartitionConfig {
+    /// Every instance sharing the sources, by name
+    members: Vec<String>,
+    /// Which of `members` this instance is, usually from the environment, such as `${HOSTNAME}`
+    member: String,
+    /// Points each member has on the hash ring, where more spreads keys more evenly
+    #[serde(default = "PartitionConfig::default_vnodes")]
+    vnodes: usize,
+}
+
+impl PartitionConfig {
+    fn default_vnodes() -> usize {
+        128
+    }
+
+    pub fn build(&self) -> LaikaResult<Partitioning> {
+        let member = interpolate_env(self.member.as_str())
+            .map_err(|e| LaikaError::config("partitioning.member", e.to_string()))?;
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = self.members.iter().find(|member| !seen.insert(*member)) {
+            return Err(LaikaError::config(
+                "partitioning.members",
+                format!("{} is listed more than once", duplicate),
+            ));
+        }
+        let Some(index) = self.members.iter().position(|name| name == &member) else {
+            return Err(LaikaError::config(
+                "partitioning.member",
+                format!("{} isn't one of members {:?}", member, self.members),
+            ));
+        };
+        if self.vnodes == 0 {
+            return Err(LaikaError::config(
+                "partitioning.vnodes",
+                "Each member needs at least one point on the ring",
+            ));
+        }
+        let ring = self
+            .members
+            .iter()
+            .enumerate()
+            .flat_map(|(index, name)| {
+                (0..self.vnodes).map(move |vnode| (hash(&format!("{}#{}", name, vnode)), index))
+            })
+            .collect();
+        Ok(Partitioning {
+            ring,
+            member: index,
+        })
+    }
+}
+
+/// Stable across instances and releases, unlike the standard library's hasher
+fn hash(key: &str) -> u64 {
+    let digest = Sha256::digest(key.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("Digest is 32 bytes"))
+}
+
+/// The share of the hash ring this instance owns
+#[derive(Clone, Debug)]
+pub struct Partitioning {
+    /// Member at each point on the ring
+    ring: BTreeMap<u64, usize>,
+    member: usize,
+}
+
+impl Partitioning {
+    /// Whether this instance handles `key`, owned by the next point on the ring after its hash
+    pub fn owns(&self, key: &str) -> bool {
+        let hashed = hash(key);
+        self.ring
+            .range(hashed..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .is_some_and(|(_, member)| *member == self.member)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str) -> Partitioning {
+        let config: PartitionConfig = serde_yaml::from_str(&format!(
+            "members: [laika-0, laika-1, laika-2]\nmember: {}",
+            name
+        ))
+        .unwrap();
+        config.build().unwrap()
+    }
+
+    #[test]
+    fn test_each_key_has_one_owner() {
+        let members: Vec<Partitioning> = ["laika-0", "laika-1", "laika-2"]
+            .into_iter()
+            .map(member)
+            .collect();
+        let mut owned = [0; 3];
+        for key in (0..3000).map(|id| format!("user{}", id)) {
+            let owners: Vec<usize> = (0..3).filter(|index| members[*index].owns(&key)).collect();
+            assert_eq!(owners.len(), 1, "{} has owners {:?}", key, owners);
+            owned[owners[0]] += 1;
+        }
+        assert!(owned.iter().all(|count| *count > 500), "{:?}", owned);
+    }
+
+    #[test]
+    fn test_member_must_be_listed() {
+        let config: PartitionConfig =
+            serde_yaml::from_str("members: [laika-0, laika-1]\nmember: laika-2").unwrap();
+        assert!(matches!(
+            config.build(),
+            Err(LaikaError::ConfigError { path, .. }) if path == "partitioning.member"
+        ));
+    }
+
+    #[test]
+    fn test_sources_must_reach_every_member() {
+        use crate::config::builder::EventProcessorYamlSpec;
+        use crate::config::EventProcessorConfig;
+
+        let config = |source: &str| {
+            let yaml = format!(
+                r#"
+connections:
+  orders:
+    {}
+  alerts:
+    type: stdout
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+correlation:
+  placed:
+    key: "$.orderId"
+partitioning:
+  members: [laika-0, laika-1]
+  member: laika-0
+triggers:
+  orderPlaced:
+    requires:
+      at_least: [placed]
+    action:
+      target: alerts
+      payload:
+        placed: true
+"#,
+                source
+            );
+            EventProcessorConfig::try_from(&EventProcessorYamlSpec::from_yaml(&yaml).unwrap())
+        };
+        assert!(config("type: file\n    path: ./orders.jsonl").is_ok());
+        assert!(config("type: mqtt\n    host: localhost\n    topics: [orders]").is_ok());
+        // Each message only reaches one member, which would skip those it doesn't own
+        let shared = config("type: mqtt\n    host: localhost\n    topics: [$share/laika/orders]");
+        assert!(matches!(
+            shared,
+            Err(LaikaError::ConfigError { path, .. }) if path == "connections.orders"
+        ));
+        assert!(config("type: zmq\n    socket: pull\n    endpoint: tcp://127.0.0.1:5557").is_err());
+    }
+}
@@ -1,12 +1,28 @@
+use crate::clock::{Clock, SystemClock};
+use crate::config::builder::invalid_at;
 use crate::errors::{LaikaError, LaikaResult};
-use crate::event::context::EventContext;
+use crate::event::context::{ContextShape, EventContext};
 use crate::event::Trigger;
-use deno_core::_ops::RustToV8;
+use crate::global_state::GlobalState;
+use crate::utils::extract_json::{extract_json_field, parse_path};
+use crate::utils::parse_time::parse_time_str;
+#[cfg(feature = "typescript")]
+use deno_ast::{
+    EmitOptions, MediaType, ModuleSpecifier, ParseParams, SourceMapOption, TranspileModuleOptions,
+    TranspileOptions,
+};
 use deno_core::{
     error::{CoreError, JsError},
-    serde_v8, JsRuntime, RuntimeOptions,
+    op2, serde_v8, v8, JsRuntime, OpState, RuntimeOptions,
 };
+use deno_error::JsErrorBox;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,6 +35,14 @@ pub enum JsonPredicateError {
     NonBooleanResult(String),
     #[error("Execution error: {0}")]
     Execution(String),
+    #[error("Timed out after {0:?}")]
+    TimedOut(Duration),
+    #[error("Ran out of memory, using more than {0} bytes of heap")]
+    HeapLimitExceeded(usize),
+    #[error("Expected a function, got {0}")]
+    NotAFunction(String),
+    #[error("Could not transpile TypeScript: {0}")]
+    Transpile(String),
 }
 
 impl From<CoreError> for JsonPredicateError {
@@ -32,68 +56,584 @@ impl From<CoreError> for JsonPredicateError {
     }
 }
 
+/// Strip the types from a TypeScript file, leaving JS that can be loaded into the engine. Types
+/// aren't checked, which is left to `tsc` with the declarations from `laika types`.
+#[cfg(feature = "typescript")]
+pub fn transpile_typescript(path: &str, source: String) -> Result<String, JsonPredicateError> {
+    let specifier = ModuleSpecifier::from_file_path(std::path::absolute(path)?)
+        .map_err(|_| JsonPredicateError::Transpile(format!("{} isn't a file path", path)))?;
+    let parsed = deno_ast::parse_script(ParseParams {
+        specifier,
+        text: source.into(),
+        media_type: MediaType::TypeScript,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })
+    .map_err(|e| JsonPredicateError::Transpile(e.to_string()))?;
+    let transpiled = parsed
+        .transpile(
+            &TranspileOptions::default(),
+            &TranspileModuleOptions::default(),
+            &EmitOptions {
+                source_map: SourceMapOption::None,
+                ..Default::default()
+            },
+        )
+        .map_err(|e| JsonPredicateError::Transpile(e.to_string()))?;
+    Ok(transpiled.into_source().text)
+}
+
+#[cfg(not(feature = "typescript"))]
+pub fn transpile_typescript(path: &str, _source: String) -> Result<String, JsonPredicateError> {
+    Err(JsonPredicateError::Transpile(format!(
+        "Laika was built without the typescript feature, so {} can't be loaded",
+        path
+    )))
+}
+
+/// Clock `laika.now()` reads, shared with the runtime
+struct HostClock(Arc<dyn Clock>);
+
+/// The value at a path such as `$.items[0].price` within a value, or `null` when there's
+/// nothing there
+#[op2]
+#[serde]
+fn op_laika_extract(
+    #[string] path: String,
+    #[serde] value: JsonValue,
+) -> Result<JsonValue, JsErrorBox> {
+    parse_path(path.as_str())
+        .map_err(|e| JsErrorBox::type_error(format!("Invalid path {}: {}", path, e)))?;
+    Ok(extract_json_field(&value, path.as_str())
+        .map(Cow::into_owned)
+        .unwrap_or(JsonValue::Null))
+}
+
+/// Milliseconds since the Unix epoch on the runtime's clock, so predicates keep to simulated
+/// time
+#[op2(fast)]
+fn op_laika_now(state: &mut OpState) -> f64 {
+    (state.borrow::<HostClock>().0.now().unix_timestamp_nanos() / 1_000_000) as f64
+}
+
+/// Log a message from a predicate at a level from `error` to `trace`
+#[op2(fast)]
+fn op_laika_log(#[string] level: String, #[string] message: String) -> Result<(), JsErrorBox> {
+    match level.as_str() {
+        "error" => tracing::error!(target: "laika::predicate", "{}", message),
+        "warn" => tracing::warn!(target: "laika::predicate", "{}", message),
+        "info" => tracing::info!(target: "laika::predicate", "{}", message),
+        "debug" => tracing::debug!(target: "laika::predicate", "{}", message),
+        "trace" => tracing::trace!(target: "laika::predicate", "{}", message),
+        _ => {
+            return Err(JsErrorBox::type_error(format!(
+                "Unknown log level {}, expected one of error, warn, info, debug or trace",
+                level
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 of a value as hex, taken of the text of strings and the JSON of anything else, so
+/// it's the same across restarts and releases
+#[op2]
+#[string]
+fn op_laika_hash(#[serde] value: JsonValue) -> String {
+    let digest = match &value {
+        JsonValue::String(text) => Sha256::digest(text.as_bytes()),
+        value => Sha256::digest(value.to_string().as_bytes()),
+    };
+    format!("{:x}", digest)
+}
+
+/// The value of a key in the global state, or `null` when it isn't set
+#[op2]
+#[serde]
+fn op_laika_global_get(state: &mut OpState, #[string] key: String) -> JsonValue {
+    state
+        .borrow::<GlobalState>()
+        .get(key.as_str())
+        .unwrap_or(JsonValue::Null)
+}
+
+/// Set a key in the global state, until a TTL such as `10m` has passed if given
+#[op2]
+fn op_laika_global_set(
+    state: &mut OpState,
+    #[string] key: String,
+    #[serde] value: JsonValue,
+    #[serde] ttl: Option<String>,
+) -> Result<(), JsErrorBox> {
+    let ttl = ttl
+        .map(|ttl| parse_time_str(ttl.as_str()))
+        .transpose()
+        .map_err(|e| JsErrorBox::type_error(format!("Invalid TTL: {}", e)))?;
+    state.borrow::<GlobalState>().set(key.as_str(), value, ttl);
+    Ok(())
+}
+
+deno_core::extension!(
+    laika_host,
+    ops = [
+        op_laika_extract,
+        op_laika_now,
+        op_laika_log,
+        op_laika_hash,
+        op_laika_global_get,
+        op_laika_global_set,
+    ],
+    state = |state| {
+        state.put(HostClock(Arc::new(SystemClock)));
+        state.put(GlobalState::default());
+    },
+);
+
+/// The `laika` object predicates call host functions through
+const HOST_FUNCTIONS: &str = r#"
+globalThis.laika = {
+  extract: (path, value) => Deno.core.ops.op_laika_extract(String(path), value ?? null),
+  now: () => Deno.core.ops.op_laika_now(),
+  log: (level, message) =>
+    Deno.core.ops.op_laika_log(
+      String(level),
+      typeof message === "string" ? message : String(JSON.stringify(message)),
+    ),
+  hash: (value) => Deno.core.ops.op_laika_hash(value ?? null),
+  global: {
+    get: (key) => Deno.core.ops.op_laika_global_get(String(key)),
+    set: (key, value, ttl) =>
+      Deno.core.ops.op_laika_global_set(String(key), value ?? null, ttl ?? null),
+  },
+};
+"#;
+
+/// Freezes a value and everything within it, with the built-ins it needs taken up front so no
+/// predicate or library can replace them
+const DEEP_FREEZE: &str = r#"
+(() => {
+  const { freeze, values } = Object;
+  const deepFreeze = (value) => {
+    if (typeof value === "object" && value !== null) {
+      freeze(value);
+      values(value).forEach(deepFreeze);
+    }
+    return value;
+  };
+  return deepFreeze;
+})()
+"#;
+
+/// A predicate stored in a `JsonPredicateEngine`
 #[derive(Debug, Clone)]
 pub struct JsonPredicate {
-    id: String,
+    index: usize,
+}
+
+/// A trigger and context shared by every predicate evaluated against them, so they're serialized
+/// and set in the runtime once rather than once per predicate.
+///
+/// The objects are frozen throughout before any predicate is given them, so each predicate sees the
+/// trigger and context as they were received, whatever the predicates before it tried to change.
+pub struct PredicateInputs<'a> {
+    trigger: &'a Trigger,
+    context: &'a EventContext,
+    /// The trigger as a V8 value, once a predicate has asked for it
+    trigger_value: Option<v8::Global<v8::Value>>,
+    /// The context as a V8 value, for each context shape predicates have asked for
+    context_values: Vec<(Option<ContextShape>, v8::Global<v8::Value>)>,
+}
+
+impl<'a> PredicateInputs<'a> {
+    pub fn new(trigger: &'a Trigger, context: &'a EventContext) -> Self {
+        PredicateInputs {
+            trigger,
+            context,
+            trigger_value: None,
+            context_values: Vec::new(),
+        }
+    }
+
+    pub fn trigger(&self) -> &'a Trigger {
+        self.trigger
+    }
+
+    pub fn context(&self) -> &'a EventContext {
+        self.context
+    }
+}
+
+/// Limits on predicates, from `predicates` in the config, so a runaway `filterAndExtract` fails
+/// its rule rather than freezing the processor
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PredicateConfig {
+    /// Longest a single evaluation may run, e.g. `100ms`. Defaults to `1s`.
+    timeout: Option<String>,
+    /// Most heap the predicate runtime may use, in megabytes. Defaults to 256.
+    max_heap_mb: Option<usize>,
+}
+
+impl PredicateConfig {
+    pub fn with_timeout(mut self, timeout: &str) -> Self {
+        self.timeout = Some(timeout.to_string());
+        self
+    }
+
+    pub fn with_max_heap_mb(mut self, max_heap_mb: usize) -> Self {
+        self.max_heap_mb = Some(max_heap_mb);
+        self
+    }
+
+    pub(crate) fn limits(&self) -> LaikaResult<PredicateLimits> {
+        let defaults = PredicateLimits::default();
+        let timeout = match &self.timeout {
+            None => defaults.timeout,
+            Some(timeout) => parse_time_str(timeout.as_str())
+                .and_then(|timeout| {
+                    Duration::try_from(timeout)
+                        .map_err(|_| LaikaError::Generic("must not be negative".to_string()))
+                })
+                .map_err(invalid_at("timeout"))?,
+        };
+        let max_heap_bytes = match self.max_heap_mb {
+            None => defaults.max_heap_bytes,
+            Some(0) => return Err(LaikaError::config("maxHeapMb", "must be more than 0")),
+            Some(max_heap_mb) => max_heap_mb * 1024 * 1024,
+        };
+        Ok(PredicateLimits {
+            timeout,
+            max_heap_bytes,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredicateLimits {
+    pub timeout: Duration,
+    pub max_heap_bytes: usize,
+}
+
+impl Default for PredicateLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            max_heap_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Default)]
+struct WatchState {
+    deadline: Option<Instant>,
+    timed_out: bool,
+    stopped: bool,
+}
+
+/// Terminates scripts that run past their deadline. It watches from a thread of its own, as a
+/// looping script never hands control back to the engine.
+struct Watchdog {
+    state: Arc<(Mutex<WatchState>, Condvar)>,
+}
+
+impl Watchdog {
+    fn spawn(isolate: v8::IsolateHandle) -> Self {
+        let state = Arc::new((Mutex::new(WatchState::default()), Condvar::new()));
+        let watched = state.clone();
+        std::thread::spawn(move || {
+            let (lock, wake) = &*watched;
+            let mut state = lock.lock().unwrap_or_else(PoisonError::into_inner);
+            while !state.stopped {
+                state = match state.deadline {
+                    None => wake.wait(state).unwrap_or_else(PoisonError::into_inner),
+                    Some(deadline) if Instant::now() >= deadline => {
+                        isolate.terminate_execution();
+                        state.deadline = None;
+                        state.timed_out = true;
+                        state
+                    }
+                    Some(deadline) => {
+                        wake.wait_timeout(state, deadline - Instant::now())
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .0
+                    }
+                };
+            }
+        });
+        Watchdog { state }
+    }
+
+    fn start(&self, deadline: Instant) {
+        let (lock, wake) = &*self.state;
+        let mut state = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        state.deadline = Some(deadline);
+        state.timed_out = false;
+        wake.notify_one();
+    }
+
+    /// Stop watching the current script, returning whether it was terminated
+    fn finish(&self) -> bool {
+        let (lock, _) = &*self.state;
+        let mut state = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        state.deadline = None;
+        std::mem::take(&mut state.timed_out)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        let (lock, wake) = &*self.state;
+        lock.lock().unwrap_or_else(PoisonError::into_inner).stopped = true;
+        wake.notify_one();
+    }
 }
 
 pub struct JsonPredicateEngine {
     runtime: JsRuntime,
-    predicate_count: usize,
+    limits: PredicateLimits,
+    watchdog: Watchdog,
+    /// Set when a script fills the heap, as it's then terminated just like one that times out
+    heap_exceeded: Arc<AtomicBool>,
+    /// Stored predicates, indexed by `JsonPredicate`
+    predicates: Vec<v8::Global<v8::Function>>,
+    /// Freezes inputs before they're shared between predicates
+    deep_freeze: v8::Global<v8::Function>,
 }
 
 impl JsonPredicateEngine {
     pub fn new() -> Self {
-        let runtime = JsRuntime::new(RuntimeOptions::default());
+        Self::with_limits(PredicateLimits::default())
+    }
+
+    pub fn with_limits(limits: PredicateLimits) -> Self {
+        let mut runtime = JsRuntime::new(RuntimeOptions {
+            create_params: Some(v8::CreateParams::default().heap_limits(0, limits.max_heap_bytes)),
+            extensions: vec![laika_host::init_ops()],
+            ..Default::default()
+        });
+        runtime
+            .execute_script("[laika]", HOST_FUNCTIONS)
+            .expect("Host functions are defined in a runtime that's just been created");
+        let deep_freeze = runtime
+            .execute_script("[freeze]", DEEP_FREEZE)
+            .expect("Freezing is defined in a runtime that's just been created");
+        let deep_freeze = {
+            let scope = &mut runtime.handle_scope();
+            let deep_freeze = v8::Local::new(scope, deep_freeze);
+            let deep_freeze = v8::Local::<v8::Function>::try_from(deep_freeze)
+                .expect("Freezing is defined as a function");
+            v8::Global::new(scope, deep_freeze)
+        };
+        let watchdog = Watchdog::spawn(runtime.v8_isolate().thread_safe_handle());
+        let heap_exceeded = Arc::new(AtomicBool::new(false));
+        Self::watch_heap(&mut runtime, heap_exceeded.clone());
         JsonPredicateEngine {
             runtime,
-            predicate_count: 0,
+            limits,
+            watchdog,
+            heap_exceeded,
+            predicates: Vec::new(),
+            deep_freeze,
         }
     }
 
-    pub fn store_predicate(&mut self, js_code: &str) -> JsonPredicate {
-        self.predicate_count += 1;
-        let id = format!("pred_{}", self.predicate_count);
+    /// Give `laika.now()` the time on `clock` rather than the system clock
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.runtime.op_state().borrow_mut().put(HostClock(clock));
+    }
+
+    /// Read and write `laika.global` in `state`, rather than a state of the engine's own
+    pub fn set_global_state(&mut self, state: GlobalState) {
+        self.runtime.op_state().borrow_mut().put(state);
+    }
 
-        let setup_code = format!(r#"globalThis['{id}'] = {js_code};"#);
+    /// Terminate scripts as the heap fills up, rather than V8 aborting the process
+    fn watch_heap(runtime: &mut JsRuntime, exceeded: Arc<AtomicBool>) {
+        let isolate = runtime.v8_isolate().thread_safe_handle();
+        runtime.add_near_heap_limit_callback(move |current_limit, _initial_limit| {
+            exceeded.store(true, Ordering::SeqCst);
+            isolate.terminate_execution();
+            // Room for the terminated script to be unwound
+            current_limit * 2
+        });
+    }
+
+    /// Run V8 within the limits, terminating it if it runs too long or fills the heap
+    fn limited<T>(
+        &mut self,
+        run: impl FnOnce(&mut JsRuntime) -> Result<T, JsonPredicateError>,
+    ) -> Result<T, JsonPredicateError> {
+        self.watchdog.start(Instant::now() + self.limits.timeout);
+        let result = run(&mut self.runtime);
+        let timed_out = self.watchdog.finish();
+        let heap_exceeded = self.heap_exceeded.swap(false, Ordering::SeqCst);
+        if timed_out || heap_exceeded {
+            // Let the isolate run scripts again
+            self.runtime.v8_isolate().cancel_terminate_execution();
+        }
+        if heap_exceeded {
+            // The limit was raised to unwind the script, so bring it back down
+            self.runtime
+                .remove_near_heap_limit_callback(self.limits.max_heap_bytes);
+            Self::watch_heap(&mut self.runtime, self.heap_exceeded.clone());
+            return Err(JsonPredicateError::HeapLimitExceeded(
+                self.limits.max_heap_bytes,
+            ));
+        }
+        if timed_out {
+            return Err(JsonPredicateError::TimedOut(self.limits.timeout));
+        }
+        result
+    }
+
+    /// Run a script within the limits
+    fn run(
+        &mut self,
+        name: &'static str,
+        code: String,
+    ) -> Result<v8::Global<v8::Value>, JsonPredicateError> {
+        self.limited(|runtime| Ok(runtime.execute_script(name, code)?))
+    }
+
+    /// Convert a value to V8 within the limits, as a large context can fill the heap
+    fn to_value<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<v8::Global<v8::Value>, JsonPredicateError> {
+        self.limited(|runtime| {
+            let scope = &mut runtime.handle_scope();
+            let local = serde_v8::to_v8(scope, value).map_err(|e| {
+                JsonPredicateError::Execution(format!(
+                    "Failed to convert input to v8::Value: {}",
+                    e
+                ))
+            })?;
+            Ok(v8::Global::new(scope, local))
+        })
+    }
+
+    /// Convert an input to V8, frozen so no predicate can change it for the predicates after it
+    fn to_frozen_value<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<v8::Global<v8::Value>, JsonPredicateError> {
+        let value = self.to_value(value)?;
+        let deep_freeze = self.deep_freeze.clone();
+        self.limited(|runtime| {
+            let scope = &mut runtime.handle_scope();
+            let deep_freeze = v8::Local::new(scope, deep_freeze);
+            let local = v8::Local::new(scope, value.clone());
+            let receiver = v8::undefined(scope).into();
+            match deep_freeze.call(scope, receiver, &[local]) {
+                Some(_) => Ok(value),
+                None => Err(JsonPredicateError::Execution(
+                    "Failed to freeze input".to_string(),
+                )),
+            }
+        })
+    }
+
+    /// Run a JS file before any predicate is stored, so the functions it declares can be called
+    /// from every predicate
+    pub fn load_library(&mut self, source: &str) -> Result<(), JsonPredicateError> {
+        self.run("[lib]", source.to_string())?;
+        Ok(())
+    }
+
+    /// Compile a predicate, failing if it doesn't parse or isn't a function
+    pub fn store_predicate(&mut self, js_code: &str) -> Result<JsonPredicate, JsonPredicateError> {
+        // The predicate starts on the first line, so error locations match its own lines. The
+        // closing bracket is on a line of its own in case the predicate ends with a comment.
+        let setup_code = format!("({js_code}\n)");
         tracing::info!("Storing predicate {}", setup_code);
-        let _ = self.runtime.execute_script("[store]", setup_code);
-        JsonPredicate { id }
+        let value = self.run("[store]", setup_code)?;
+        let scope = &mut self.runtime.handle_scope();
+        let value = v8::Local::new(scope, value);
+        let function = v8::Local::<v8::Function>::try_from(value).map_err(|_| {
+            JsonPredicateError::NotAFunction(value.type_of(scope).to_rust_string_lossy(scope))
+        })?;
+        self.predicates.push(v8::Global::new(scope, function));
+        Ok(JsonPredicate {
+            index: self.predicates.len() - 1,
+        })
+    }
+
+    /// The trigger and context as V8 values, converting them if no predicate has asked for them
+    fn input_values(
+        &mut self,
+        inputs: &mut PredicateInputs,
+        shape: Option<&ContextShape>,
+    ) -> LaikaResult<(v8::Global<v8::Value>, v8::Global<v8::Value>)> {
+        let trigger = match &inputs.trigger_value {
+            Some(trigger) => trigger.clone(),
+            None => {
+                let trigger = self.to_frozen_value(inputs.trigger)?;
+                inputs.trigger_value = Some(trigger.clone());
+                trigger
+            }
+        };
+        if let Some((_, context)) = inputs
+            .context_values
+            .iter()
+            .find(|(set_for, _)| set_for.as_ref() == shape)
+        {
+            return Ok((trigger, context.clone()));
+        }
+
+        let context = match shape {
+            None => Cow::Borrowed(inputs.context),
+            Some(shape) => Cow::Owned(inputs.context.shaped(shape)?),
+        };
+        let context = self.to_frozen_value(context.as_ref())?;
+        inputs
+            .context_values
+            .push((shape.cloned(), context.clone()));
+        Ok((trigger, context))
     }
 
+    /// Evaluate a predicate against the inputs, with the context narrowed to `shape` if given.
+    ///
+    /// The inputs are passed to the predicate as V8 values, never as source, so no payload can
+    /// change the script that's run.
     pub fn evaluate(
         &mut self,
         predicate: &JsonPredicate,
-        trigger: &Trigger,
-        context: &EventContext,
+        inputs: &mut PredicateInputs,
+        shape: Option<&ContextShape>,
     ) -> LaikaResult<Option<JsonValue>> {
-        let trigger_json = serde_json::to_string(trigger).unwrap();
-        let context_json = serde_json::to_string(context).unwrap();
-
-        let eval_code = format!(
-            r#"globalThis['{id}']({trigger_json}, {context_json})"#,
-            id = predicate.id,
-            trigger_json = trigger_json,
-            context_json = context_json
-        );
+        let (trigger, context) = self.input_values(inputs, shape)?;
+        let function = self.predicates[predicate.index].clone();
+        tracing::debug!("Evaluating predicate {}", predicate.index);
 
-        tracing::debug!("Evaluating {}", eval_code);
-
-        let result = self.runtime.execute_script("[evaluate]", eval_code)?;
-        let scope = &mut self.runtime.handle_scope();
-        let local_result = result.to_v8(scope);
-        if local_result.is_null() {
-            Ok(None)
-        } else {
-            Ok(Some(
-                serde_v8::from_v8::<JsonValue>(scope, local_result).map_err(|e| {
-                    LaikaError::Generic(format!(
+        Ok(self.limited(|runtime| {
+            let scope = &mut runtime.handle_scope();
+            let scope = &mut v8::TryCatch::new(scope);
+            let function = v8::Local::new(scope, function);
+            let args = [
+                v8::Local::new(scope, trigger),
+                v8::Local::new(scope, context),
+            ];
+            let receiver = v8::undefined(scope).into();
+            let Some(result) = function.call(scope, receiver, &args) else {
+                let message = match scope.exception() {
+                    Some(exception) => exception.to_rust_string_lossy(scope),
+                    None => "Predicate was terminated".to_string(),
+                };
+                return Err(JsonPredicateError::Execution(message));
+            };
+            if result.is_null() {
+                return Ok(None);
+            }
+            serde_v8::from_v8::<JsonValue>(scope, result)
+                .map(Some)
+                .map_err(|e| {
+                    JsonPredicateError::Execution(format!(
                         "Failed to convert v8::Value to serde_json::Value: {}",
                         e
                     ))
-                })?,
-            ))
-        }
+                })
+        })?)
     }
 }
 
@@ -114,7 +654,7 @@ mod tests {
     fn test_basic_predicate() -> LaikaResult<()> {
         let mut engine = JsonPredicateEngine::new();
         let predicate = engine
-            .store_predicate("(trigger, ctx) => trigger.event.active === true ? trigger : null");
+            .store_predicate("(trigger, ctx) => trigger.event.active === true ? trigger : null")?;
         let events: Vec<Event> = vec![
             RawEvent::new(serde_json::json!({"active": true})).parse("ActiveEvent", None),
             RawEvent::new(serde_json::json!({"active": false})).parse("InactiveEvent", None),
@@ -122,13 +662,17 @@ mod tests {
         let ctx = EventContext::try_from(events.clone()).unwrap();
 
         let trigger = Trigger::ReceivedEvent(events[0].clone());
-        let evaluation_result = engine.evaluate(&predicate, &trigger, &ctx)?;
+        let evaluation_result =
+            engine.evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?;
         assert!(evaluation_result.is_some());
 
         let trigger = Trigger::ReceivedEvent(events[1].clone());
-        let evaluation_result = engine.evaluate(&predicate, &trigger, &ctx)?;
+        let evaluation_result =
+            engine.evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?;
         dbg!(evaluation_result);
-        assert!(engine.evaluate(&predicate, &trigger, &ctx)?.is_none());
+        assert!(engine
+            .evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?
+            .is_none());
         Ok(())
     }
 
@@ -137,7 +681,7 @@ mod tests {
     fn test_string_predicate() -> LaikaResult<()> {
         let mut engine = JsonPredicateEngine::new();
         let predicate = engine
-            .store_predicate("(trigger, ctx) => trigger.event.type === 'test' ? trigger: null");
+            .store_predicate("(trigger, ctx) => trigger.event.type === 'test' ? trigger: null")?;
         let events: Vec<Event> = vec![
             RawEvent::new(serde_json::json!({"type": "test"})).parse("ActiveEvent", None),
             RawEvent::new(serde_json::json!({"type": "not-test"})).parse("InactiveEvent", None),
@@ -145,12 +689,290 @@ mod tests {
         let ctx = EventContext::try_from(events.clone()).unwrap();
 
         let trigger = Trigger::ReceivedEvent(events[0].clone());
-        let evaluation_result = engine.evaluate(&predicate, &trigger, &ctx)?;
+        let evaluation_result =
+            engine.evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?;
         assert!(evaluation_result.is_some());
 
         let trigger = Trigger::ReceivedEvent(events[1].clone());
-        let evaluation_result = engine.evaluate(&predicate, &trigger, &ctx)?;
+        let evaluation_result =
+            engine.evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?;
         assert!(evaluation_result.is_none());
         Ok(())
     }
+
+    #[test]
+    fn test_inputs_shared_between_predicates() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::new();
+        let active =
+            engine.store_predicate("(trigger, ctx) => trigger.event.active ? true : null")?;
+        let counted = engine.store_predicate("(trigger, ctx) => ctx.sequence.length")?;
+        let events: Vec<Event> =
+            vec![RawEvent::new(serde_json::json!({"active": true})).parse("ActiveEvent", None)];
+        let ctx = EventContext::try_from(events.clone()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+
+        let mut inputs = PredicateInputs::new(&trigger, &ctx);
+        assert_eq!(
+            engine.evaluate(&active, &mut inputs, None)?,
+            Some(serde_json::json!(true))
+        );
+        assert_eq!(
+            engine.evaluate(&counted, &mut inputs, None)?,
+            Some(serde_json::json!(1))
+        );
+        assert_eq!(inputs.context_values.len(), 1);
+
+        // Inputs for another trigger don't disturb these
+        let empty = EventContext::try_from(Vec::new()).unwrap();
+        let mut other = PredicateInputs::new(&trigger, &empty);
+        assert_eq!(
+            engine.evaluate(&counted, &mut other, None)?,
+            Some(serde_json::json!(0))
+        );
+        assert_eq!(
+            engine.evaluate(&counted, &mut inputs, None)?,
+            Some(serde_json::json!(1))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_predicates_cannot_change_shared_inputs() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::new();
+        let changing = engine.store_predicate(
+            "(trigger, ctx) => { trigger.event.amount = 0; trigger.event.note = 'seen'; delete ctx.sequence[0]; return null; }",
+        )?;
+        let appending = engine.store_predicate("(trigger, ctx) => { ctx.sequence.push(1); }")?;
+        let reading = engine.store_predicate(
+            "(trigger, ctx) => [trigger.event.amount, trigger.event.note ?? null, ctx.sequence.length]",
+        )?;
+        let events: Vec<Event> =
+            vec![RawEvent::new(serde_json::json!({"amount": 250})).parse("Payment", None)];
+        let ctx = EventContext::try_from(events.clone()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+        let mut inputs = PredicateInputs::new(&trigger, &ctx);
+
+        assert_eq!(engine.evaluate(&changing, &mut inputs, None)?, None);
+        let error = engine.evaluate(&appending, &mut inputs, None).unwrap_err();
+        assert!(error.to_string().contains("not extensible"), "{}", error);
+        assert_eq!(
+            engine.evaluate(&reading, &mut inputs, None)?,
+            Some(serde_json::json!([250, null, 1]))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_predicates_rejected() {
+        let mut engine = JsonPredicateEngine::new();
+        let error = engine
+            .store_predicate("(trigger, ctx) => {\n  return trigger.event.;\n}")
+            .unwrap_err();
+        assert!(matches!(error, JsonPredicateError::Js(_)));
+        assert!(error.to_string().contains("SyntaxError"), "{}", error);
+
+        let error = engine.store_predicate("'not a function'").unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("Expected a function, got string"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_runaway_predicates_terminated() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::with_limits(PredicateLimits {
+            timeout: Duration::from_millis(100),
+            max_heap_bytes: 32 * 1024 * 1024,
+        });
+        let looping = engine.store_predicate("(trigger, ctx) => { while (true) {} }")?;
+        let growing = engine.store_predicate(
+            "(trigger, ctx) => { const kept = []; while (true) { kept.push(new Array(1e5).fill(1)); } }",
+        )?;
+        let valid = engine.store_predicate("(trigger, ctx) => true")?;
+        let events: Vec<Event> =
+            vec![RawEvent::new(serde_json::json!({"active": true})).parse("ActiveEvent", None)];
+        let ctx = EventContext::try_from(events.clone()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+        let mut inputs = PredicateInputs::new(&trigger, &ctx);
+
+        let error = engine.evaluate(&looping, &mut inputs, None).unwrap_err();
+        assert!(error.to_string().contains("Timed out"), "{}", error);
+        let error = engine.evaluate(&growing, &mut inputs, None).unwrap_err();
+        assert!(error.to_string().contains("Ran out of memory"), "{}", error);
+
+        // The engine is still usable once a predicate has been terminated
+        assert_eq!(
+            engine.evaluate(&valid, &mut inputs, None)?,
+            Some(serde_json::json!(true))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_predicate_limits_from_config() {
+        let limits = PredicateConfig::default()
+            .with_timeout("250ms")
+            .with_max_heap_mb(64)
+            .limits()
+            .unwrap();
+        assert_eq!(limits.timeout, Duration::from_millis(250));
+        assert_eq!(limits.max_heap_bytes, 64 * 1024 * 1024);
+        assert_eq!(
+            PredicateConfig::default().limits().unwrap(),
+            PredicateLimits::default()
+        );
+
+        let error = PredicateConfig::default()
+            .with_timeout("soon")
+            .limits()
+            .unwrap_err();
+        assert!(matches!(error, LaikaError::ConfigError { path, .. } if path == "timeout"));
+    }
+
+    #[test]
+    fn test_host_functions() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::new();
+        engine.set_clock(Arc::new(crate::clock::TestClock::new(
+            time::macros::datetime!(2024-03-01 12:00:00 UTC),
+        )));
+        let predicate = engine.store_predicate(
+            r#"(trigger, ctx) => {
+                laika.log("debug", { checking: trigger.event.orderId });
+                return {
+                    price: laika.extract("$.items[0].price", trigger.event),
+                    missing: laika.extract("$.discount", trigger.event),
+                    now: new Date(laika.now()).toISOString(),
+                    hash: laika.hash(trigger.event.orderId),
+                };
+            }"#,
+        )?;
+        let invalid = engine.store_predicate("(trigger, ctx) => laika.log('loud', 'hello')")?;
+        let events: Vec<Event> =
+            vec![
+                RawEvent::new(serde_json::json!({"orderId": "abc", "items": [{"price": 12.5}]}))
+                    .parse("OrderPlaced", None),
+            ];
+        let ctx = EventContext::try_from(Vec::new()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+        let mut inputs = PredicateInputs::new(&trigger, &ctx);
+
+        assert_eq!(
+            engine.evaluate(&predicate, &mut inputs, None)?,
+            Some(serde_json::json!({
+                "price": 12.5,
+                "missing": null,
+                "now": "2024-03-01T12:00:00.000Z",
+                "hash": "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            }))
+        );
+        let error = engine.evaluate(&invalid, &mut inputs, None).unwrap_err();
+        assert!(error.to_string().contains("Unknown log level"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_global_state_shared_between_predicates() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::new();
+        let state = GlobalState::default();
+        engine.set_global_state(state.clone());
+        let counting = engine.store_predicate(
+            "(trigger, ctx) => { laika.global.set('seen', (laika.global.get('seen') ?? 0) + 1, '1h'); return null; }",
+        )?;
+        let reading = engine.store_predicate("(trigger, ctx) => laika.global.get('seen')")?;
+        let invalid = engine
+            .store_predicate("(trigger, ctx) => laika.global.set('seen', 1, 'eventually')")?;
+        let events: Vec<Event> =
+            vec![RawEvent::new(serde_json::json!({"active": true})).parse("ActiveEvent", None)];
+        let ctx = EventContext::try_from(events.clone()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+        let mut inputs = PredicateInputs::new(&trigger, &ctx);
+
+        engine.evaluate(&counting, &mut inputs, None)?;
+        engine.evaluate(&counting, &mut inputs, None)?;
+        assert_eq!(
+            engine.evaluate(&reading, &mut inputs, None)?,
+            Some(serde_json::json!(2))
+        );
+        assert_eq!(state.get("seen"), Some(serde_json::json!(2)));
+        let error = engine.evaluate(&invalid, &mut inputs, None).unwrap_err();
+        assert!(error.to_string().contains("Invalid TTL"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_library_shared_between_predicates() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::new();
+        engine.load_library(
+            "function parseMoney(value) { return Math.round(parseFloat(value) * 100); }",
+        )?;
+        let predicate =
+            engine.store_predicate("(trigger, ctx) => parseMoney(trigger.event.amount)")?;
+        let events: Vec<Event> =
+            vec![RawEvent::new(serde_json::json!({"amount": "12.50"})).parse("Payment", None)];
+        let ctx = EventContext::try_from(events.clone()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+        assert_eq!(
+            engine.evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?,
+            Some(serde_json::json!(1250))
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "typescript")]
+    #[test]
+    fn test_typescript_library() -> LaikaResult<()> {
+        let source = r#"
+interface Payment { amount: string }
+function largePayment(trigger: Laika.Trigger, ctx: Laika.Context): Laika.Result {
+    if (trigger.type !== "received_event") return null;
+    const payment = trigger.event as Payment;
+    return parseFloat(payment.amount) > 100 ? { amount: payment.amount } : null;
+}
+"#;
+        let mut engine = JsonPredicateEngine::new();
+        engine.load_library(transpile_typescript("payments.ts", source.to_string())?.as_str())?;
+        let predicate = engine.store_predicate("largePayment")?;
+        let events: Vec<Event> =
+            vec![RawEvent::new(serde_json::json!({"amount": "250.00"})).parse("Payment", None)];
+        let ctx = EventContext::try_from(Vec::new()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+        assert_eq!(
+            engine.evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?,
+            Some(serde_json::json!({"amount": "250.00"}))
+        );
+
+        let error = transpile_typescript("broken.ts", "function (: {".to_string()).unwrap_err();
+        assert!(
+            matches!(error, JsonPredicateError::Transpile(_)),
+            "{}",
+            error
+        );
+        Ok(())
+    }
+
+    #[cfg(not(feature = "typescript"))]
+    #[test]
+    fn test_typescript_needs_feature() {
+        let error = transpile_typescript("payments.ts", "const a: number = 1;".to_string());
+        assert!(matches!(error, Err(JsonPredicateError::Transpile(_))));
+    }
+
+    #[test]
+    fn test_payloads_passed_as_values() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::new();
+        let predicate = engine.store_predicate("(trigger, ctx) => trigger.event.note")?;
+        let note = "\"}); globalThis.injected = true; ({\"\u{2028}";
+        let events: Vec<Event> =
+            vec![RawEvent::new(serde_json::json!({"note": note})).parse("Note", None)];
+        let ctx = EventContext::try_from(events.clone()).unwrap();
+        let trigger = Trigger::ReceivedEvent(events[0].clone());
+        assert_eq!(
+            engine.evaluate(&predicate, &mut PredicateInputs::new(&trigger, &ctx), None)?,
+            Some(serde_json::json!(note))
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,78 @@
+use crate::config::EventProcessorConfig;
+use std::collections::BTreeSet;
+
+/// Declarations of the trigger, context and `laika` object predicates are given
+const PREDICATE_TYPES: &str = include_str!("../schemas/predicates.d.ts");
+
+/// A union of string literal types, or `never` when there are none
+fn literal_union<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let names: BTreeSet<&str> = names.collect();
+    if names.is_empty() {
+        return "never".to_string();
+    }
+    names
+        .into_iter()
+        .map(|name| serde_json::Value::String(name.to_string()).to_string())
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+/// TypeScript declarations for the predicates of a config, with its event types and rules, so
+/// `.ts` files in `lib` can be type checked against them
+pub fn type_definitions(config: &EventProcessorConfig) -> String {
+    format!(
+        "{}\ndeclare namespace Laika {{\n  type EventType = {};\n  type RuleName = {};\n}}\n",
+        PREDICATE_TYPES,
+        literal_union(config.event_types()),
+        literal_union(config.rule_names()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::builder::EventProcessorYamlSpec;
+
+    #[test]
+    fn test_type_definitions_name_event_types_and_rules() {
+        let spec = EventProcessorYamlSpec::from_yaml(
+            r#"
+connections:
+  orders:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+  paid:
+    from: orders
+    matchKey:
+      type: paid
+correlation:
+  placed:
+    key: "$.orderId"
+  paid:
+    key: "$.orderId"
+triggers:
+  orderPaid:
+    requires:
+      at_least: [placed, paid]
+    filterAndExtract: >
+      (trigger, ctx) => ({ orderId: trigger.event.orderId })
+    action:
+      target: alerts
+      payload:
+        orderId: "${{ orderId }}"
+"#,
+        )
+        .unwrap();
+        let config = EventProcessorConfig::try_from(&spec).unwrap();
+        let types = type_definitions(&config);
+        assert!(types.contains("declare const laika"));
+        assert!(types.contains(r#"type EventType = "paid" | "placed";"#));
+        assert!(types.contains(r#"type RuleName = "orderPaid";"#));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::errors::{LaikaError, LaikaResult};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Version of Laika stamped onto payloads
+pub const LAIKA_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Metadata that can be stamped onto payloads
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ProvenanceField {
+    /// Name of the rule that produced the payload
+    Rule,
+    /// SHA-256 of the rule as it's written in the config
+    RuleHash,
+    ConfigVersion,
+    LaikaVersion,
+}
+
+const ALL_FIELDS: [ProvenanceField; 4] = [
+    ProvenanceField::Rule,
+    ProvenanceField::RuleHash,
+    ProvenanceField::ConfigVersion,
+    ProvenanceField::LaikaVersion,
+];
+
+fn default_key() -> String {
+    "_laika".to_string()
+}
+
+/// Metadata stamped onto emitted payloads, so they can be tied back to the revision of the rule
+/// that produced them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ProvenanceConfig {
+    /// Key of payloads the metadata is put under
+    #[serde(default = "default_key")]
+    key: String,
+    /// Version of the config, such as the commit it was deployed from
+    #[serde(default)]
+    config_version: Option<String>,
+    /// Metadata to stamp, or all of it
+    #[serde(default)]
+    fields: Option<Vec<ProvenanceField>>,
+}
+
+impl ProvenanceConfig {
+    /// Stamp payloads with `rule_hashes`, the hash of each rule by name
+    pub fn build(&self, rule_hashes: HashMap<String, String>) -> LaikaResult<Provenance> {
+        if self.key.is_empty() {
+            return Err(LaikaError::config("key", "key can't be empty"));
+        }
+        let fields = self.fields.clone().unwrap_or_else(|| ALL_FIELDS.to_vec());
+        if fields.is_empty() {
+            return Err(LaikaError::config(
+                "fields",
+                "fields must list at least one field",
+            ));
+        }
+        // Without fields, the config version is only stamped when there is one
+        if self.fields.is_some()
+            && fields.contains(&ProvenanceField::ConfigVersion)
+            && self.config_version.is_none()
+        {
+            return Err(LaikaError::config(
+                "fields",
+                "configVersion can only be stamped when it's set",
+            ));
+        }
+        Ok(Provenance {
+            key: self.key.clone(),
+            config_version: self.config_version.clone(),
+            fields,
+            rule_hashes,
+        })
+    }
+}
+
+/// SHA-256 of part of a config, as hex
+pub fn content_hash(value: &serde_yaml::Value) -> String {
+    let yaml = serde_yaml::to_string(value).unwrap_or_default();
+    format!("{:x}", Sha256::digest(yaml.as_bytes()))
+}
+
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    key: String,
+    config_version: Option<String>,
+    fields: Vec<ProvenanceField>,
+    rule_hashes: HashMap<String, String>,
+}
+
+impl Provenance {
+    /// Key of payloads the metadata is put under
+    pub fn key(&self) -> &str {
+        self.key.as_str()
+    }
+
+    /// Metadata of a payload produced by `rule`. Fields that aren't known, such as the hash of a
+    /// rule that wasn't read from a config, are left out.
+    fn metadata(&self, rule: &str) -> Map<String, Value> {
+        let mut metadata = Map::new();
+        for field in &self.fields {
+            let (name, value) = match field {
+                ProvenanceField::Rule => ("rule", Some(rule.to_string())),
+                ProvenanceField::RuleHash => ("ruleHash", self.rule_hashes.get(rule).cloned()),
+                ProvenanceField::ConfigVersion => ("configVersion", self.config_version.clone()),
+                ProvenanceField::LaikaVersion => ("laikaVersion", Some(LAIKA_VERSION.to_string())),
+            };
+            if let Some(value) = value {
+                metadata.insert(name.to_string(), Value::String(value));
+            }
+        }
+        metadata
+    }
+
+    /// Stamp a payload produced by `rule`, replacing anything already under the key. Payloads
+    /// that aren't objects have nowhere to put it, so are left as they are.
+    pub fn stamp(&self, rule: &str, payload: &mut Value) {
+        if let Value::Object(payload) = payload {
+            payload.insert(self.key.clone(), Value::Object(self.metadata(rule)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_payloads_are_stamped() {
+        let config: ProvenanceConfig =
+            serde_yaml::from_str("key: meta\nconfigVersion: abc123\n").unwrap();
+        let provenance = config
+            .build(HashMap::from([(
+                "orderPaid".to_string(),
+                "f00d".to_string(),
+            )]))
+            .unwrap();
+        let mut payload = json!({"orderId": 1});
+        provenance.stamp("orderPaid", &mut payload);
+        assert_eq!(
+            payload,
+            json!({
+                "orderId": 1,
+                "meta": {
+                    "rule": "orderPaid",
+                    "ruleHash": "f00d",
+                    "configVersion": "abc123",
+                    "laikaVersion": LAIKA_VERSION,
+                }
+            })
+        );
+
+        let config: ProvenanceConfig = serde_yaml::from_str("fields: [rule]\n").unwrap();
+        let provenance = config.build(HashMap::new()).unwrap();
+        let mut payload = json!({"orderId": 1});
+        provenance.stamp("orderPaid", &mut payload);
+        assert_eq!(payload["_laika"], json!({"rule": "orderPaid"}));
+        let mut text = json!("Order paid");
+        provenance.stamp("orderPaid", &mut text);
+        assert_eq!(text, json!("Order paid"));
+    }
+}
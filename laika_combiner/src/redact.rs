@@ -0,0 +1,230 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::utils::env::interpolate_env;
+use crate::utils::extract_json::{parse_path, resolve_index, PathSegment};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// Replaces masked values
+pub const REDACTED: &str = "[REDACTED]";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactMethod {
+    /// Replace the value with `[REDACTED]`
+    #[default]
+    Mask,
+    /// Replace the value with the hex SHA-256 of it, so equal values can still be matched up
+    Hash,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RedactionYaml {
+    Path(String),
+    Rule {
+        path: String,
+        #[serde(default)]
+        with: RedactMethod,
+        /// Prepended to values before they're hashed, so they can't be found by hashing guesses
+        #[serde(default)]
+        salt: Option<String>,
+    },
+}
+
+/// A field of an event that's masked or hashed before the event is stored or given to rules.
+///
+/// Arrays along the path are redacted element by element, so `$.items.card` redacts the card
+/// of every item, as does `$.items[*].card`. Indexes and filters redact only the elements they
+/// pick out.
+#[derive(Clone, Debug)]
+pub struct Redaction {
+    path: Vec<PathSegment>,
+    method: RedactMethod,
+    salt: String,
+}
+
+impl TryFrom<RedactionYaml> for Redaction {
+    type Error = LaikaError;
+    fn try_from(value: RedactionYaml) -> LaikaResult<Self> {
+        let (path, method, salt) = match value {
+            RedactionYaml::Path(path) => (path, RedactMethod::Mask, None),
+            RedactionYaml::Rule { path, with, salt } => (path, with, salt),
+        };
+        let segments =
+            parse_path(path.as_str()).map_err(|e| LaikaError::config("path", e.to_string()))?;
+        if segments.is_empty() {
+            return Err(LaikaError::config(
+                "path",
+                format!("{} doesn't name a field to redact", path),
+            ));
+        }
+        if salt.is_some() && method != RedactMethod::Hash {
+            return Err(LaikaError::config(
+                "salt",
+                "salt can only be used with hash",
+            ));
+        }
+        Ok(Self {
+            path: segments,
+            method,
+            salt: salt
+                .map(|salt| interpolate_env(salt.as_str()))
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl Redaction {
+    /// Redact the field within `data`, if it's there
+    pub(crate) fn apply(&self, data: &mut Value) {
+        self.apply_at(data, self.path.as_slice())
+    }
+
+    fn apply_at(&self, data: &mut Value, path: &[PathSegment]) {
+        let Some((segment, rest)) = path.split_first() else {
+            let redacted = self.redacted(data);
+            *data = redacted;
+            return;
+        };
+        match (segment, data) {
+            (PathSegment::Field(_), Value::Array(items)) => {
+                for item in items {
+                    self.apply_at(item, path);
+                }
+            }
+            (PathSegment::Field(field), data) => {
+                if let Some(value) = data.get_mut(field.as_str()) {
+                    self.apply_at(value, rest);
+                }
+            }
+            (PathSegment::Index(index), Value::Array(items)) => {
+                if let Some(index) = resolve_index(items.len(), *index) {
+                    self.apply_at(&mut items[index], rest);
+                }
+            }
+            (PathSegment::Wildcard, Value::Array(items)) => {
+                for item in items {
+                    self.apply_at(item, rest);
+                }
+            }
+            (PathSegment::Wildcard, Value::Object(fields)) => {
+                for value in fields.values_mut() {
+                    self.apply_at(value, rest);
+                }
+            }
+            (PathSegment::Filter(filter), Value::Array(items)) => {
+                for item in items.iter_mut().filter(|item| filter.holds(item)) {
+                    self.apply_at(item, rest);
+                }
+            }
+            (PathSegment::Filter(filter), Value::Object(fields)) => {
+                for value in fields.values_mut().filter(|value| filter.holds(value)) {
+                    self.apply_at(value, rest);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn redacted(&self, value: &Value) -> Value {
+        match self.method {
+            RedactMethod::Mask => Value::String(REDACTED.to_string()),
+            RedactMethod::Hash => {
+                let raw = match value {
+                    Value::String(raw) => raw.clone(),
+                    other => other.to_string(),
+                };
+                let mut hasher = Sha256::new();
+                hasher.update(self.salt.as_bytes());
+                hasher.update(raw.as_bytes());
+                Value::String(format!("{:x}", hasher.finalize()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn redaction(yaml: &str) -> Redaction {
+        serde_yaml::from_str::<RedactionYaml>(yaml)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut data = json!({"card": {"number": "4111111111111111", "expiry": "12/30"}});
+        redaction("$.card.number").apply(&mut data);
+        assert_eq!(
+            data,
+            json!({"card": {"number": "[REDACTED]", "expiry": "12/30"}})
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        let hash = redaction("{ path: email, with: hash }");
+        let mut first = json!({"email": "a@example.com"});
+        let mut second = json!({"email": "a@example.com"});
+        hash.apply(&mut first);
+        hash.apply(&mut second);
+        assert_eq!(first, second);
+        assert_ne!(first["email"], "a@example.com");
+        assert_eq!(first["email"].as_str().unwrap().len(), 64);
+
+        let mut salted = json!({"email": "a@example.com"});
+        redaction("{ path: email, with: hash, salt: pepper }").apply(&mut salted);
+        assert_ne!(salted, first);
+    }
+
+    #[test]
+    fn test_arrays_and_missing_fields() {
+        let mut data = json!({"items": [{"card": "4111"}, {"sku": "A1"}]});
+        redaction("$.items.card").apply(&mut data);
+        assert_eq!(
+            data,
+            json!({"items": [{"card": "[REDACTED]"}, {"sku": "A1"}]})
+        );
+
+        let mut untouched = json!({"id": 1});
+        redaction("$.card.number").apply(&mut untouched);
+        assert_eq!(untouched, json!({"id": 1}));
+    }
+
+    #[test]
+    fn test_indexes_and_filters() {
+        let items = json!({"items": [
+            {"card": "4111", "type": "card"},
+            {"card": "5500", "type": "gift"}
+        ]});
+        let mut first = items.clone();
+        redaction("$.items[0].card").apply(&mut first);
+        assert_eq!(first["items"][0]["card"], "[REDACTED]");
+        assert_eq!(first["items"][1]["card"], "5500");
+
+        let mut filtered = items.clone();
+        redaction("$.items[?(@.type == 'gift')].card").apply(&mut filtered);
+        assert_eq!(filtered["items"][0]["card"], "4111");
+        assert_eq!(filtered["items"][1]["card"], "[REDACTED]");
+
+        let mut all = items;
+        redaction("$.items[*].card").apply(&mut all);
+        assert_eq!(all["items"][1]["card"], "[REDACTED]");
+    }
+
+    #[test]
+    fn test_invalid_redactions() {
+        let invalid = |yaml: &str| {
+            Redaction::try_from(serde_yaml::from_str::<RedactionYaml>(yaml).unwrap()).is_err()
+        };
+        assert!(invalid("$"));
+        assert!(invalid("$.items[x]"));
+        assert!(invalid("{ path: email, salt: pepper }"));
+    }
+}
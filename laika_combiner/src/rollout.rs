@@ -0,0 +1,96 @@
+use crate::errors::{LaikaError, LaikaResult};
+use sha2::{Digest, Sha256};
+
+/// Buckets correlations are spread over, so rollouts can be given to a hundredth of a percent
+const BUCKETS: u64 = 10_000;
+
+/// Share of correlations a trigger is evaluated for, by where the hash of their correlation ID
+/// falls. Correlations fall in the same place for every trigger, so a trigger rolled out to
+/// `10%` and another to `10%-100%` split correlations between them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rollout {
+    /// First bucket included
+    from: u64,
+    /// Bucket after the last one included
+    to: u64,
+}
+
+/// A percentage such as `12.5%`, in buckets
+fn buckets(raw: &str) -> LaikaResult<u64> {
+    let invalid = || {
+        LaikaError::config(
+            "rollout",
+            format!(
+                "{} isn't a percentage from 0% to 100%, to at most two decimal places",
+                raw
+            ),
+        )
+    };
+    let percent: f64 = raw
+        .trim()
+        .strip_suffix('%')
+        .ok_or_else(invalid)?
+        .trim()
+        .parse()
+        .map_err(|_| invalid())?;
+    let buckets = percent * (BUCKETS / 100) as f64;
+    if !(0.0..=BUCKETS as f64).contains(&buckets) || buckets.fract().abs() > 1e-6 {
+        return Err(invalid());
+    }
+    Ok(buckets.round() as u64)
+}
+
+impl Rollout {
+    /// Parse a rollout such as `10%`, the first tenth of correlations, or `10%-100%`, the rest
+    pub fn parse(raw: &str) -> LaikaResult<Self> {
+        let (from, to) = match raw.split_once('-') {
+            Some((from, to)) => (buckets(from)?, buckets(to)?),
+            None => (0, buckets(raw)?),
+        };
+        if from >= to {
+            return Err(LaikaError::config(
+                "rollout",
+                format!("{} doesn't include any correlations", raw),
+            ));
+        }
+        Ok(Self { from, to })
+    }
+
+    /// Whether the correlation falls within the rollout
+    pub fn includes(&self, correlation_id: &str) -> bool {
+        (self.from..self.to).contains(&bucket(correlation_id))
+    }
+}
+
+/// Stable across instances and releases, and apart from the hash correlations are partitioned
+/// by, so a rollout doesn't follow which instance handles a correlation
+fn bucket(correlation_id: &str) -> u64 {
+    let digest = Sha256::digest(format!("rollout:{}", correlation_id).as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().expect("Digest is 32 bytes")) % BUCKETS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollouts_split_correlations() {
+        let new = Rollout::parse("10%").unwrap();
+        let old = Rollout::parse("10% - 100%").unwrap();
+        let ids: Vec<String> = (0..10_000).map(|n| format!("order-{}", n)).collect();
+        let rolled_out = ids.iter().filter(|id| new.includes(id)).count();
+        assert!((800..1200).contains(&rolled_out), "{}", rolled_out);
+        // Every correlation follows exactly one of the two
+        assert!(ids.iter().all(|id| new.includes(id) != old.includes(id)));
+
+        assert_eq!(
+            Rollout::parse("12.5%").unwrap(),
+            Rollout { from: 0, to: 1250 }
+        );
+        assert!(Rollout::parse("0%").is_err());
+        assert!(Rollout::parse("150%").is_err());
+        assert!(Rollout::parse("10").is_err());
+        assert!(Rollout::parse("0.001%").is_err());
+        assert!(Rollout::parse("50%-10%").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::action::EventAction;
+use crate::clock::{Clock, TestClock};
+use crate::config::builder::EventProcessorYamlSpec;
+use crate::config::EventProcessorConfig;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::storage::StorageKV;
+use crate::timing::TimingExpiry;
+use crate::utils::parse_time::parse_time_str;
+use crate::Runtime;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use time::macros::datetime;
+use time::{Duration, OffsetDateTime};
+
+/// When every test's clock starts, so tests run the same way each time
+const TEST_START: OffsetDateTime = datetime!(2024-01-01 00:00 UTC);
+
+/// Tests run by this process, so each has its own wakeups file
+static TEST_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Events sent through a rule, and what it should produce from them, written under its `tests`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleTestConfig {
+    name: String,
+    events: Vec<TestEventConfig>,
+    /// How long to keep the clock running after the last event, so wakeups due by then fire
+    #[serde(default)]
+    wait: Option<String>,
+    /// Payloads the rule should emit, in order
+    expect: Vec<Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestEventConfig {
+    event: Value,
+    /// Connection the event is received from, which can be left out when there's only one source
+    #[serde(default)]
+    from: Option<String>,
+    /// Time since the previous event, or since the test started
+    #[serde(default)]
+    after: Option<String>,
+}
+
+/// Outcome of one of a rule's tests
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuleTestResult {
+    pub rule: String,
+    pub test: String,
+    /// Why the test failed, if it did
+    pub failure: Option<String>,
+}
+
+impl RuleTestResult {
+    pub fn passed(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+impl Display for RuleTestResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.failure {
+            None => write!(f, "PASS {}: {}", self.rule, self.test),
+            Some(failure) => write!(f, "FAIL {}: {}\n  {}", self.rule, self.test, failure),
+        }
+    }
+}
+
+/// Run the tests of every trigger in the config, or only those of `rule`.
+///
+/// Each test runs in memory on a fresh runtime with only its own rule enabled, through the same
+/// matching, rules and templates as when processing, on a clock that moves to each event rather
+/// than waiting for it. Lookups aren't made, so events should carry what they'd have added.
+pub fn run_rule_tests(
+    spec: &EventProcessorYamlSpec,
+    rule: Option<&str>,
+) -> LaikaResult<Vec<RuleTestResult>> {
+    let mut triggers: Vec<_> = spec
+        .triggers
+        .iter()
+        .filter(|(name, _)| rule.is_none_or(|rule| rule == name.as_str()))
+        .collect();
+    triggers.sort_by_key(|(name, _)| *name);
+    let mut results = Vec::new();
+    for (name, trigger) in triggers {
+        for test in &trigger.tests {
+            let failure = run_test(spec, name, test)
+                .map_err(|e| e.within(format!("triggers.{}.tests.{}", name, test.name).as_str()))?;
+            results.push(RuleTestResult {
+                rule: name.clone(),
+                test: test.name.clone(),
+                failure,
+            });
+        }
+    }
+    Ok(results)
+}
+
+/// Run a test of `rule`, returning why it failed if it did. Problems with the test itself, rather
+/// than the rule, are errors.
+fn run_test(
+    spec: &EventProcessorYamlSpec,
+    rule: &str,
+    test: &RuleTestConfig,
+) -> LaikaResult<Option<String>> {
+    let duration = |raw: &Option<String>, field: &str| {
+        raw.as_ref()
+            .map(|raw| {
+                parse_time_str(raw.as_str())
+                    .map(Duration::abs)
+                    .map_err(|e| LaikaError::config(field, e.to_string()))
+            })
+            .transpose()
+    };
+    let wait = duration(&test.wait, "wait")?;
+    // Payloads are compared as the rule renders them, as its hash changes with every test added
+    let spec = EventProcessorYamlSpec {
+        provenance: None,
+        ..spec.clone()
+    };
+    let config = EventProcessorConfig::try_from(&spec)?;
+    let sources: Vec<String> = config.source_names().map(str::to_string).collect();
+    let mut events = Vec::with_capacity(test.events.len());
+    for (index, event) in test.events.iter().enumerate() {
+        let source = match (&event.from, sources.as_slice()) {
+            (Some(from), _) => from.clone(),
+            (None, [source]) => source.clone(),
+            (None, _) => {
+                return Err(LaikaError::config(
+                    format!("events.{}.from", index),
+                    "from is needed when the config has more than one source",
+                ))
+            }
+        };
+        let after = duration(&event.after, format!("events.{}.after", index).as_str())?;
+        events.push((source, after, event.event.clone()));
+    }
+
+    let timer_path = std::env::temp_dir().join(format!(
+        "laika_rule_test_{}_{}",
+        std::process::id(),
+        TEST_RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let clock = TestClock::new(TEST_START);
+    let mut runtime = Runtime::new_with_clock(
+        vec![config.build()?],
+        StorageKV::in_memory(),
+        TimingExpiry::new(timer_path.clone())?,
+        Arc::new(clock.clone()),
+    )?;
+    let controls = runtime.controls();
+    for other in controls.rules() {
+        if other.name != rule {
+            controls.disable_rule(other.name.as_str());
+        }
+    }
+
+    let produced = (|| {
+        let mut actions = Vec::new();
+        for (source, after, event) in events {
+            if let Some(after) = after {
+                let polls = runtime.advance_to(&clock, clock.now() + after)?;
+                actions.extend(polls.into_iter().flat_map(|(_, actions)| actions));
+            }
+            runtime.push_event(source.as_str(), event)?;
+            actions.extend(runtime.poll_actions()?);
+        }
+        if let Some(wait) = wait {
+            let polls = runtime.advance_to(&clock, clock.now() + wait)?;
+            actions.extend(polls.into_iter().flat_map(|(_, actions)| actions));
+        }
+        Ok::<_, LaikaError>(actions)
+    })();
+    let _ = std::fs::remove_file(&timer_path);
+
+    Ok(match produced {
+        Err(e) => Some(e.to_string()),
+        Ok(actions) => {
+            let payloads: Vec<Value> = actions.into_iter().filter_map(emitted).collect();
+            (payloads != test.expect).then(|| {
+                format!(
+                    "Expected {} but got {}",
+                    Value::Array(test.expect.clone()),
+                    Value::Array(payloads)
+                )
+            })
+        }
+    })
+}
+
+/// Payload a rule emitted, or the body of the request it sent
+fn emitted(action: EventAction) -> Option<Value> {
+    match action {
+        EventAction::Emit(emit) => Some(emit.payload()),
+        EventAction::Http(request) => request.body,
+        EventAction::ScheduleWakeup(_) | EventAction::CancelWakeup(..) | EventAction::Store(_) => {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+connections:
+  orders:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+  paid:
+    from: orders
+    matchKey:
+      type: paid
+correlation:
+  placed:
+    key: "$.orderId"
+  paid:
+    key: "$.orderId"
+triggers:
+  unpaid:
+    requires:
+      at_least: [placed]
+    absent: [paid]
+    within: 1h
+    filterAndExtract: >
+      (trigger, ctx) => ({ orderId: ctx.events.placed[0].orderId })
+    action:
+      target: alerts
+      payload:
+        orderId: "${{ orderId }}"
+    tests:
+      - name: alerts on orders left unpaid
+        events:
+          - event: {type: placed, orderId: "1"}
+        wait: 2h
+        expect:
+          - orderId: "1"
+      - name: paid orders are left alone
+        events:
+          - event: {type: placed, orderId: "1"}
+          - event: {type: paid, orderId: "1"}
+            after: 10m
+        wait: 2h
+        expect: []
+      - name: expects the wrong order
+        events:
+          - event: {type: placed, orderId: "1"}
+        wait: 2h
+        expect:
+          - orderId: "2"
+"#;
+
+    #[test]
+    fn test_rule_tests_run_on_a_simulated_clock() {
+        let spec = EventProcessorYamlSpec::from_yaml(CONFIG).unwrap();
+        let results = run_rule_tests(&spec, None).unwrap();
+        let passed: Vec<bool> = results.iter().map(RuleTestResult::passed).collect();
+        assert_eq!(passed, vec![true, true, false]);
+        assert!(results[2]
+            .failure
+            .as_ref()
+            .unwrap()
+            .contains("Expected [{\"orderId\":\"2\"}]"));
+    }
+}
@@ -1,9 +1,19 @@
+use crate::aggregation::AggregateConfig;
 use crate::config::builder::{ActionConfig, RequirementConfig, TimingConfig};
-use crate::errors::{LaikaError, LaikaResult};
-use crate::event::context::EventContext;
-use crate::event::{Event, Trigger};
-use crate::predicate_engine::{JsonPredicate, JsonPredicateEngine};
-use time::OffsetDateTime;
+use crate::errors::{ErrorSource, LaikaError, LaikaResult};
+use crate::event::context::{ContextShape, EventContext};
+use crate::event::{Event, EventLike, Trigger};
+use crate::explain::RequirementExplanation;
+use crate::late::LatePolicy;
+use crate::output_schema::OutputSchema;
+use crate::predicate_engine::{JsonPredicate, JsonPredicateEngine, PredicateInputs};
+use crate::rollout::Rollout;
+use crate::schedule::ScheduleConfig;
+use crate::utils::parse_time::TimeSpan;
+use crate::windowing::WindowConfig;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use time::{Duration, OffsetDateTime};
 use tracing::error;
 
 #[derive(Debug)]
@@ -52,17 +62,166 @@ impl Requirement {
     }
 }
 
+/// Triggers a rule's condition is evaluated on, given by the event types and trigger kinds under
+/// `on`. Other triggers still count towards its requirements and timing.
+#[derive(Clone, Debug, Default)]
+pub struct TriggerScope {
+    event_types: HashSet<String>,
+    timers: bool,
+}
+
+impl TriggerScope {
+    /// Trigger kind for expired timers, rather than an event type
+    pub const TIMER: &'static str = "timer";
+
+    pub fn new<I: IntoIterator<Item = String>>(on: I) -> Self {
+        let mut scope = TriggerScope::default();
+        for entry in on {
+            if entry == Self::TIMER {
+                scope.timers = true;
+            } else {
+                scope.event_types.insert(entry);
+            }
+        }
+        scope
+    }
+
+    pub(crate) fn includes(&self, trigger: &Trigger) -> bool {
+        match trigger {
+            Trigger::ReceivedEvent(event) => event
+                .event_type()
+                .is_some_and(|event_type| self.event_types.contains(&event_type)),
+            Trigger::TimerExpired(_) => self.timers,
+            // Scheduled rules are only ever evaluated on their schedule
+            Trigger::Scheduled(_) => true,
+        }
+    }
+}
+
+/// Action a rule takes once its rechecks have left it unsatisfied `rechecks` times in a row
+#[derive(Clone, Debug)]
+pub struct Escalation {
+    pub(crate) rechecks: u32,
+    pub(crate) action: ActionConfig,
+}
+
+/// Event types that must *not* arrive for a correlation within a duration of a rule's
+/// requirements being met. The rule is evaluated once that duration has passed.
+#[derive(Clone, Debug)]
+pub struct Absence {
+    pub(crate) events: Vec<String>,
+    pub(crate) within: TimeSpan,
+}
+
+impl Absence {
+    /// Whether any of the absent events arrived by `until`
+    pub(crate) fn observed(&self, context: &EventContext, until: OffsetDateTime) -> bool {
+        context.events().any(|event| {
+            *event.received() <= until
+                && event
+                    .event_type()
+                    .is_some_and(|event_type| self.events.contains(&event_type))
+        })
+    }
+}
+
 #[derive(Clone)]
 pub struct EventRule {
     pub(crate) name: String,
     // EventRules will always have a default JsonPredicate, even if the user hasn't provided one.
     pub(crate) filter_and_extract: JsonPredicate,
+    /// Fields the predicate's result must have, when it returns one
+    pub(crate) output_schema: Option<OutputSchema>,
     pub(crate) timing: Option<TimingConfig>,
+    pub(crate) aggregate: Option<AggregateConfig>,
+    pub(crate) window: Option<WindowConfig>,
+    pub(crate) absence: Option<Absence>,
+    /// Cron schedule the rule is evaluated on, instead of on received events
+    pub(crate) schedule: Option<ScheduleConfig>,
     pub(crate) requires: Option<Requirement>,
+    /// Rules with a higher priority are evaluated first
+    pub(crate) priority: i32,
+    /// Skip lower priority rules for a trigger once this rule fires
+    pub(crate) stop_on_match: bool,
+    /// Stored events, or fields of them, given to the predicate instead of the whole context
+    pub(crate) context: Option<ContextShape>,
+    /// Tenants the rule is evaluated for, where it's evaluated for all of them without any
+    pub(crate) tenants: Option<HashSet<String>>,
+    /// What the rule does with events arriving once it's decided on their correlation
+    pub(crate) late: Option<LatePolicy>,
+    /// Triggers the rule's condition is evaluated on, where it's evaluated on all of them without
+    pub(crate) on: Option<TriggerScope>,
     pub(crate) action: ActionConfig,
+    /// Action taken once if the rule hasn't fired by the time its timing runs out
+    pub(crate) on_timeout: Option<ActionConfig>,
+    /// Actions taken as rechecks go on leaving the rule unsatisfied, fewest rechecks first
+    pub(crate) escalation: Vec<Escalation>,
+    /// How long the rule's action for a correlation is suppressed after it's taken
+    pub(crate) dedupe: Option<Duration>,
+    /// Share of correlations the rule is evaluated for, where it's evaluated for all of them
+    /// without
+    pub(crate) rollout: Option<Rollout>,
 }
 
 impl EventRule {
+    /// Whether the rule's condition is evaluated for `trigger`
+    pub(crate) fn reacts_to(&self, trigger: &Trigger) -> bool {
+        self.on.as_ref().is_none_or(|scope| scope.includes(trigger))
+    }
+
+    /// Whether the rule is evaluated for `tenant`. Rules are always evaluated when the tenant
+    /// isn't known, such as for global schedules.
+    pub(crate) fn enabled_for(&self, tenant: Option<&str>) -> bool {
+        match (&self.tenants, tenant) {
+            (Some(tenants), Some(tenant)) => tenants.contains(tenant),
+            _ => true,
+        }
+    }
+
+    /// Whether the rule is evaluated for a correlation, which it always is without a rollout.
+    /// Events without a correlation are outside every rollout.
+    pub(crate) fn rolled_out_to(&self, correlation_id: Option<&str>) -> bool {
+        match (&self.rollout, correlation_id) {
+            (None, _) => true,
+            (Some(rollout), Some(correlation_id)) => rollout.includes(correlation_id),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether events of this type can contribute to the rule's requirements
+    pub(crate) fn requires_event_type(&self, event_type: &str) -> bool {
+        match &self.requires {
+            None => true,
+            Some(Requirement::AtLeast(types)) | Some(Requirement::Exactly(types)) => {
+                types.iter().any(|t| t == event_type)
+            }
+        }
+    }
+
+    /// Whether events of this type can change the rule's decision, by meeting its requirements
+    /// or being absent
+    pub(crate) fn watches_event_type(&self, event_type: &str) -> bool {
+        self.requires_event_type(event_type)
+            || self
+                .absence
+                .as_ref()
+                .is_some_and(|absence| absence.events.iter().any(|t| t == event_type))
+    }
+
+    /// When the triggering event is the one that completed the rule's requirements, the time it
+    /// completed them.
+    pub(crate) fn completed_requirements(
+        &self,
+        trigger: &Trigger,
+        context: &EventContext,
+    ) -> Option<OffsetDateTime> {
+        let Trigger::ReceivedEvent(event) = trigger else {
+            return None;
+        };
+        self.when_met_requirements(trigger, context)
+            .filter(|met_at| met_at == event.received())
+    }
+
     fn valid_correlation(&self, trigger: &Trigger, context: &EventContext) -> bool {
         let minimum_events: usize = self
             .requires
@@ -86,7 +245,7 @@ impl EventRule {
         true
     }
 
-    fn when_met_requirements(
+    pub(crate) fn when_met_requirements(
         &self,
         trigger: &Trigger,
         context: &EventContext,
@@ -136,32 +295,100 @@ impl EventRule {
         }
     }
 
-    fn meets_condition(
+    /// Which events satisfied which of the rule's requirements, and which are missing
+    pub(crate) fn explain_requirements(
         &self,
-        engine: &mut JsonPredicateEngine,
         trigger: &Trigger,
         context: &EventContext,
+    ) -> RequirementExplanation {
+        let mut events: Vec<&Event> = context.events().collect();
+        if let Trigger::ReceivedEvent(event) = trigger {
+            events.push(event);
+        }
+        let (kind, required): (&'static str, &[String]) = match &self.requires {
+            None => ("none", &[]),
+            Some(Requirement::AtLeast(types)) => ("atLeast", types.as_slice()),
+            Some(Requirement::Exactly(types)) => ("exactly", types.as_slice()),
+        };
+        let mut satisfied_by: std::collections::BTreeMap<String, Vec<serde_json::Value>> =
+            std::collections::BTreeMap::new();
+        let mut unexpected: Vec<String> = Vec::new();
+        for event in events {
+            let event_type = event.event_type().expect("All events have types");
+            if required.contains(&event_type) {
+                satisfied_by
+                    .entry(event_type)
+                    .or_default()
+                    .push(event.get_data().clone());
+            } else if kind == "exactly" && !unexpected.contains(&event_type) {
+                unexpected.push(event_type);
+            }
+        }
+        let met_at = self.when_met_requirements(trigger, context);
+        RequirementExplanation {
+            kind,
+            met: met_at.is_some(),
+            met_at: met_at.map(|met_at| met_at.unix_timestamp()),
+            missing: required
+                .iter()
+                .filter(|event_type| !satisfied_by.contains_key(*event_type))
+                .cloned()
+                .collect(),
+            satisfied_by,
+            unexpected,
+        }
+    }
+
+    /// The context given to the rule's predicate
+    pub(crate) fn predicate_context<'a>(
+        &self,
+        context: &'a EventContext,
+    ) -> LaikaResult<Cow<'a, EventContext>> {
+        match &self.context {
+            None => Ok(Cow::Borrowed(context)),
+            Some(shape) => context.shaped(shape).map(Cow::Owned),
+        }
+    }
+
+    pub(crate) fn meets_condition(
+        &self,
+        engine: &mut JsonPredicateEngine,
+        inputs: &mut PredicateInputs,
     ) -> LaikaResult<Option<serde_json::Value>> {
-        engine
-            .evaluate(&self.filter_and_extract, trigger, context)
+        let output = engine
+            .evaluate(&self.filter_and_extract, inputs, self.context.as_ref())
             .map_err(|e| {
                 error!("{}", e);
-                LaikaError::RuleEvaluationError(e.to_string())
-            })
+                LaikaError::PredicateError {
+                    rule: self.name.clone(),
+                    source: ErrorSource::new(e),
+                }
+            })?;
+        if let (Some(schema), Some(output)) = (&self.output_schema, &output) {
+            schema.validate(&self.name, output)?;
+        }
+        Ok(output)
     }
 
     pub fn evaluate(
         &self,
         engine: &mut JsonPredicateEngine,
-        trigger: &Trigger,
-        context: &EventContext,
+        inputs: &mut PredicateInputs,
     ) -> LaikaResult<RuleResult> {
+        let (trigger, context) = (inputs.trigger(), inputs.context());
         if !self.valid_correlation(trigger, context) {
             return Err(LaikaError::InvalidEventGroup);
         }
         tracing::debug!("Evaluating rule with Trigger {:?} and Context {:?}", trigger, context);
         if let Some(met_at) = self.when_met_requirements(trigger, context) {
-            if let Some(condition_result) = self.meets_condition(engine, trigger, context)? {
+            // Triggers outside `on` skip the condition, but still schedule any recheck
+            if !self.reacts_to(trigger) {
+                return Ok(RuleResult::ConditionNotSatisfied {
+                    met_at,
+                    recheck: self.timing.clone(),
+                });
+            }
+            if let Some(condition_result) = self.meets_condition(engine, inputs)? {
                 Ok(RuleResult::ConditionSatisfied {
                     met_at,
                     action_config: self.action.clone(),
@@ -186,10 +413,12 @@ mod tests {
     use crate::errors::{LaikaError, LaikaResult};
     use crate::event::context::EventContext;
     use crate::event::{Event, RawEvent, Trigger};
+    use crate::format::EmitFormat;
     use crate::matcher::builder::EventMatchBuilder;
-    use crate::predicate_engine::JsonPredicateEngine;
-    use crate::rules::{EventRule, Requirement, RuleResult};
+    use crate::predicate_engine::{JsonPredicateEngine, PredicateInputs};
+    use crate::rules::{Absence, EventRule, Requirement, RuleResult, TriggerScope};
     use crate::template::Template;
+    use crate::utils::parse_time::TimeSpan;
     use serde_json::json;
     use std::collections::HashMap;
 
@@ -265,24 +494,41 @@ mod tests {
         let rule = EventRuleDefinition {
             name: "partialRule".to_string(),
             filter_and_extract: None,
+            output_schema: None,
             timing: None,
+            aggregate: None,
+            window: None,
+            absence: None,
+            schedule: None,
             requires: Some(Requirement::Exactly(vec![
                 "eventA".to_string(),
                 "eventB".to_string(),
                 "eventC".to_string(),
             ])),
-            action: ActionConfig {
+            priority: 0,
+            stop_on_match: false,
+            tenants: None,
+            context: None,
+            late: None,
+            on: None,
+            action: ActionConfig::Emit {
                 target: "".to_string(),
                 emit_template: static_template(),
+                routing_template: None,
+                format: EmitFormat::Json,
             },
+            on_timeout: None,
+            escalation: Vec::new(),
+            dedupe: None,
+            rollout: None,
         }
-        .register_to_engine(&mut engine);
+        .register_to_engine(&mut engine)?;
         let events: Vec<Event> = vec![event_a().parse("eventA", Some("a".to_string()))];
         let context: EventContext = EventContext::try_from(events)?;
         let trigger: Trigger =
             Trigger::ReceivedEvent(event_b().parse("eventB", Some("a".to_string())));
 
-        let result = rule.evaluate(&mut engine, &trigger, &context)?;
+        let result = rule.evaluate(&mut engine, &mut PredicateInputs::new(&trigger, &context))?;
         assert!(matches!(result, RuleResult::RequirementNotMet {}));
         Ok(())
     }
@@ -293,24 +539,41 @@ mod tests {
         let rule = EventRuleDefinition {
             name: "partialRule".to_string(),
             filter_and_extract: None,
+            output_schema: None,
             timing: None,
+            aggregate: None,
+            window: None,
+            absence: None,
+            schedule: None,
             requires: Some(Requirement::Exactly(vec![
                 "eventA".to_string(),
                 "eventB".to_string(),
             ])),
-            action: ActionConfig {
+            priority: 0,
+            stop_on_match: false,
+            tenants: None,
+            context: None,
+            late: None,
+            on: None,
+            action: ActionConfig::Emit {
                 target: "".to_string(),
                 emit_template: static_template(),
+                routing_template: None,
+                format: EmitFormat::Json,
             },
+            on_timeout: None,
+            escalation: Vec::new(),
+            dedupe: None,
+            rollout: None,
         }
-        .register_to_engine(&mut engine);
+        .register_to_engine(&mut engine)?;
 
         // EventA NonCorrelated is the only item
         let events: Vec<Event> = vec![];
         let context: EventContext = EventContext::try_from(events)?;
         let trigger: Trigger = Trigger::ReceivedEvent(event_a().parse("eventA", None));
         assert!(matches!(
-            rule.evaluate(&mut engine, &trigger, &context),
+            rule.evaluate(&mut engine, &mut PredicateInputs::new(&trigger, &context)),
             Err(LaikaError::InvalidEventGroup)
         ));
 
@@ -318,7 +581,7 @@ mod tests {
         let context: EventContext = EventContext::try_from(events)?;
         let trigger: Trigger = Trigger::ReceivedEvent(event_b().parse("eventB", None));
         assert!(matches!(
-            rule.evaluate(&mut engine, &trigger, &context),
+            rule.evaluate(&mut engine, &mut PredicateInputs::new(&trigger, &context)),
             Err(LaikaError::InvalidEventGroup)
         ));
         Ok(())
@@ -332,14 +595,31 @@ mod tests {
         let rule = EventRuleDefinition {
             name: "partialRule".to_string(),
             filter_and_extract: None,
+            output_schema: None,
             timing: None,
+            aggregate: None,
+            window: None,
+            absence: None,
+            schedule: None,
             requires: None,
-            action: ActionConfig {
+            priority: 0,
+            stop_on_match: false,
+            tenants: None,
+            context: None,
+            late: None,
+            on: None,
+            action: ActionConfig::Emit {
                 target: "".to_string(),
                 emit_template: static_template(),
+                routing_template: None,
+                format: EmitFormat::Json,
             },
+            on_timeout: None,
+            escalation: Vec::new(),
+            dedupe: None,
+            rollout: None,
         }
-        .register_to_engine(&mut engine);
+        .register_to_engine(&mut engine)?;
 
         let events: Vec<Event> = vec![
             event_a().parse("eventA", Some("a".to_string())),
@@ -349,11 +629,120 @@ mod tests {
         let trigger: Trigger =
             Trigger::ReceivedEvent(event_c().parse("eventC", Some(("c".to_string()))));
 
-        let result = rule.evaluate(&mut engine, &trigger, &context)?;
+        let result = rule.evaluate(&mut engine, &mut PredicateInputs::new(&trigger, &context))?;
         assert!(
             matches!(result, RuleResult::ConditionSatisfied { .. }),
             "Rule with empty requirements should always be satisfied"
         );
         Ok(())
     }
+
+    #[test]
+    fn test_absence_scheduled_on_completion() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::default();
+        let absence = Absence {
+            events: vec!["eventC".to_string()],
+            within: TimeSpan::Exact(time::Duration::minutes(30)),
+        };
+        let rule = EventRuleDefinition {
+            name: "notSettled".to_string(),
+            filter_and_extract: None,
+            output_schema: None,
+            timing: None,
+            aggregate: None,
+            window: None,
+            absence: Some(absence.clone()),
+            schedule: None,
+            requires: Some(Requirement::AtLeast(vec![
+                "eventA".to_string(),
+                "eventB".to_string(),
+            ])),
+            priority: 0,
+            stop_on_match: false,
+            tenants: None,
+            context: None,
+            late: None,
+            on: None,
+            action: ActionConfig::Emit {
+                target: "".to_string(),
+                emit_template: static_template(),
+                routing_template: None,
+                format: EmitFormat::Json,
+            },
+            on_timeout: None,
+            escalation: Vec::new(),
+            dedupe: None,
+            rollout: None,
+        }
+        .register_to_engine(&mut engine)?;
+
+        let first = event_a().parse("eventA", Some("a".to_string()));
+        let context: EventContext = EventContext::try_from(vec![])?;
+        let trigger = Trigger::ReceivedEvent(first.clone());
+        assert!(rule.completed_requirements(&trigger, &context).is_none());
+
+        let second = event_b().parse("eventB", Some("a".to_string()));
+        let context: EventContext = EventContext::try_from(vec![first.clone()])?;
+        let trigger = Trigger::ReceivedEvent(second.clone());
+        assert_eq!(
+            rule.completed_requirements(&trigger, &context),
+            Some(*second.received())
+        );
+
+        let settled = event_c().parse("eventC", Some("a".to_string()));
+        let context: EventContext = EventContext::try_from(vec![first, second, settled.clone()])?;
+        assert!(absence.observed(&context, *settled.received()));
+        assert!(!absence.observed(&context, *settled.received() - time::Duration::hours(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_condition_only_evaluated_on_scoped_triggers() -> LaikaResult<()> {
+        let mut engine = JsonPredicateEngine::default();
+        let rule = |on: Vec<&str>| EventRuleDefinition {
+            name: "scopedRule".to_string(),
+            filter_and_extract: None,
+            output_schema: None,
+            timing: None,
+            aggregate: None,
+            window: None,
+            absence: None,
+            schedule: None,
+            requires: None,
+            priority: 0,
+            stop_on_match: false,
+            tenants: None,
+            context: None,
+            late: None,
+            on: Some(TriggerScope::new(on.into_iter().map(String::from))),
+            action: ActionConfig::Emit {
+                target: "".to_string(),
+                emit_template: static_template(),
+                routing_template: None,
+                format: EmitFormat::Json,
+            },
+            on_timeout: None,
+            escalation: Vec::new(),
+            dedupe: None,
+            rollout: None,
+        };
+        let scoped_away =
+            rule(vec!["eventA", TriggerScope::TIMER]).register_to_engine(&mut engine)?;
+        let scoped_to = rule(vec!["eventC"]).register_to_engine(&mut engine)?;
+
+        let context: EventContext = EventContext::try_from(vec![])?;
+        let trigger: Trigger =
+            Trigger::ReceivedEvent(event_c().parse("eventC", Some("c".to_string())));
+        let mut inputs = PredicateInputs::new(&trigger, &context);
+
+        assert!(matches!(
+            scoped_away.evaluate(&mut engine, &mut inputs)?,
+            RuleResult::ConditionNotSatisfied { .. }
+        ));
+        assert!(matches!(
+            scoped_to.evaluate(&mut engine, &mut inputs)?,
+            RuleResult::ConditionSatisfied { .. }
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,331 @@
+use crate::action::EventAction;
+use crate::audit::AuditRecord;
+use crate::clock::{Clock, SystemClock, TestClock};
+use crate::config::builder::EventProcessorYamlSpec;
+use crate::config::EventProcessorConfig;
+use crate::connections::internal::CHAIN_DEPTH_KEY;
+use crate::controls::RuntimeControls;
+use crate::errors::LaikaResult;
+use crate::event::{enrich, EventMetadata, RawEvent};
+use crate::event_handler::{explain_raw_event, handle_raw_event, handle_timing_expiry};
+use crate::explain::EventExplanation;
+use crate::global_state::GlobalState;
+use crate::storage::StorageKV;
+use crate::telemetry::follow_upstream;
+use crate::tenancy::TenantStats;
+use crate::timing::TimingExpiry;
+use crate::EventProcessor;
+use std::collections::HashMap;
+use std::env::temp_dir;
+use std::path::Path;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+/// Embeds Laika within another service.
+///
+/// Events are pushed in as they're received, and the actions they produce are polled out for the
+/// host to carry out. Wakeups and stored values are handled by the runtime itself, so polled
+/// actions are only ever `EventAction::Emit` or `EventAction::Http`.
+pub struct Runtime {
+    processors: Vec<EventProcessor>,
+    storage: StorageKV,
+    waker: TimingExpiry,
+    pending: Vec<EventAction>,
+    clock: Arc<dyn Clock>,
+    controls: RuntimeControls,
+    /// Values shared by every correlation, saved once each event or wakeup is handled
+    global_state: GlobalState,
+    /// When wakeups were last polled for
+    polled_at: Option<OffsetDateTime>,
+}
+
+impl Runtime {
+    /// Create a runtime from existing processors and state
+    pub fn new(
+        processors: Vec<EventProcessor>,
+        storage: StorageKV,
+        waker: TimingExpiry,
+    ) -> LaikaResult<Self> {
+        Self::new_with_clock(processors, storage, waker, Arc::new(SystemClock))
+    }
+
+    /// Create a runtime from existing processors and state, timing events and wakeups by `clock`
+    /// rather than the system clock
+    pub fn new_with_clock(
+        processors: Vec<EventProcessor>,
+        storage: StorageKV,
+        waker: TimingExpiry,
+        clock: Arc<dyn Clock>,
+    ) -> LaikaResult<Self> {
+        let controls = RuntimeControls::new(
+            processors
+                .iter()
+                .flat_map(EventProcessor::rule_priorities)
+                .collect(),
+        );
+        let global_state = GlobalState::load(&storage, clock.clone())?;
+        let processors: Vec<EventProcessor> = processors
+            .into_iter()
+            .map(|processor| {
+                processor
+                    .with_clock(clock.clone())
+                    .with_controls(controls.clone())
+                    .with_global_state(global_state.clone())
+            })
+            .collect();
+        let mut waker = waker.with_clock(clock.clone());
+        // Schedules already pending from an earlier run are kept, so a missed run still fires
+        waker.add_missing_expiries(
+            processors
+                .iter()
+                .flat_map(|processor| processor.scheduled_wakeups())
+                .collect(),
+        )?;
+        controls.set_pending_wakeups(waker.pending_count());
+        Ok(Self {
+            processors,
+            storage,
+            waker,
+            pending: Vec::new(),
+            clock,
+            controls,
+            global_state,
+            polled_at: None,
+        })
+    }
+
+    /// Create a runtime from a YAML config, keeping state in the system temporary directory.
+    ///
+    /// Only one runtime can use a state directory at a time; use `Runtime::from_yaml_with_state`
+    /// to run more than one.
+    pub fn from_yaml(yaml: &str) -> LaikaResult<Self> {
+        Self::from_yaml_with_state(yaml, temp_dir().join("laika_runtime"))
+    }
+
+    /// Create a runtime from a YAML config, keeping state within `state_dir` rather than the
+    /// config's `storage.dataDir`
+    pub fn from_yaml_with_state<P: AsRef<Path>>(yaml: &str, state_dir: P) -> LaikaResult<Self> {
+        let spec = EventProcessorYamlSpec::from_yaml(yaml)?;
+        let processor = EventProcessorConfig::try_from(&spec)?.build()?;
+        let storage = spec.storage.builder_at(state_dir.as_ref()).build()?;
+        let waker = TimingExpiry::new(state_dir.as_ref().join(".timing_expiry"))?
+            .with_max_wakeups(spec.storage.max_wakeups());
+        Self::new(vec![processor], storage, waker)
+    }
+
+    /// Process an event received from the connection named `source`
+    pub fn push_event(&mut self, source: &str, value: serde_json::Value) -> LaikaResult<()> {
+        self.push_event_with_metadata(source, value, EventMetadata::new())
+    }
+
+    /// Process an event received from the connection named `source`, along with the metadata of
+    /// the message it was received in, such as its headers
+    pub fn push_event_with_metadata(
+        &mut self,
+        source: &str,
+        value: serde_json::Value,
+        metadata: EventMetadata,
+    ) -> LaikaResult<()> {
+        let depth = metadata
+            .get(CHAIN_DEPTH_KEY)
+            .and_then(serde_json::Value::as_u64)
+            .and_then(|depth| u32::try_from(depth).ok())
+            .unwrap_or_default();
+        let span = tracing::info_span!("message", source);
+        follow_upstream(&span, &metadata);
+        let _entered = span.enter();
+        let handled = handle_raw_event(
+            self.processors.as_mut_slice(),
+            &mut self.storage,
+            source,
+            RawEvent::received_by(value, self.clock.as_ref()).with_metadata(metadata),
+        );
+        let mut actions = self.save_global_state(handled)?;
+        // Emits carry the depth on, so internal connections can stop events looping forever
+        for action in actions.iter_mut() {
+            if let EventAction::Emit(emit) = action {
+                emit.depth = depth;
+                emit.span = span.clone();
+            }
+        }
+        self.take_actions(actions)
+    }
+
+    /// Resolve the lookups of the event types an event from `source` matches, returning the event
+    /// with their results under `_enrich`, ready to be pushed. Events without lookups are
+    /// returned unchanged.
+    pub async fn lookup(
+        &self,
+        source: &str,
+        mut value: serde_json::Value,
+        metadata: &EventMetadata,
+    ) -> LaikaResult<serde_json::Value> {
+        let raw_event = RawEvent::received_by(value.clone(), self.clock.as_ref())
+            .with_metadata(metadata.clone());
+        for processor in &self.processors {
+            enrich(&mut value, &processor.lookup(source, &raw_event).await?);
+        }
+        Ok(value)
+    }
+
+    /// Process any wakeups that are due, and return all actions produced since the last poll
+    pub fn poll_actions(&mut self) -> LaikaResult<Vec<EventAction>> {
+        self.polled_at = Some(self.clock.now());
+        while let Some(expiry) = self.waker.next_expired() {
+            // Wakeups timed from when events happened also wait for event time to pass them
+            if !self
+                .processors
+                .iter()
+                .all(|processor| processor.watermark_passed(&expiry))
+            {
+                break;
+            }
+            let span = tracing::info_span!(
+                "wakeup",
+                rule = expiry.event_rule.as_str(),
+                correlation_id = expiry.correlation_id.as_str()
+            );
+            let handled = span.in_scope(|| {
+                handle_timing_expiry(self.processors.as_mut_slice(), &mut self.storage, expiry)
+            });
+            let mut actions = self.save_global_state(handled)?;
+            for action in actions.iter_mut() {
+                if let EventAction::Emit(emit) = action {
+                    emit.span = span.clone();
+                }
+            }
+            self.waker.ack()?;
+            self.take_actions(actions)?;
+        }
+        Ok(std::mem::take(&mut self.pending))
+    }
+
+    /// When the next wakeup is due, if any, so hosts know when to poll without new events
+    pub fn next_wakeup(&self) -> Option<OffsetDateTime> {
+        self.waker.peek().map(|expiry| expiry.expires_at)
+    }
+
+    /// How long until `poll_actions` has a wakeup to process, so hosts can poll while no events
+    /// arrive. `None` when nothing is pending, or when the next wakeup waits on event time, which
+    /// only moves on with new events.
+    pub fn until_next_wakeup(&self) -> Option<std::time::Duration> {
+        let next_wakeup = self.next_wakeup()?;
+        // Still pending after a poll it was due for, so held back by the watermark
+        if self
+            .polled_at
+            .is_some_and(|polled_at| next_wakeup <= polled_at)
+        {
+            return None;
+        }
+        Some(
+            (next_wakeup - self.clock.now())
+                .try_into()
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Move `clock`, the one the runtime was made with, on to `until`, stopping at each wakeup
+    /// due before then so it fires on time. Returns the actions of each poll along with the time
+    /// it was made at.
+    pub fn advance_to(
+        &mut self,
+        clock: &TestClock,
+        until: OffsetDateTime,
+    ) -> LaikaResult<Vec<(OffsetDateTime, Vec<EventAction>)>> {
+        let mut polls = Vec::new();
+        // Wakeups already due that didn't fire are held back by a watermark, so aren't waited on
+        while let Some(next) = self
+            .next_wakeup()
+            .filter(|next| *next > clock.now() && *next <= until)
+        {
+            clock.advance_to(next);
+            polls.push((clock.now(), self.poll_actions()?));
+        }
+        clock.advance_to(until);
+        polls.push((clock.now(), self.poll_actions()?));
+        Ok(polls)
+    }
+
+    /// Explain how an event from `source` would be handled, without processing it
+    pub fn explain(
+        &mut self,
+        source: &str,
+        value: serde_json::Value,
+    ) -> LaikaResult<Vec<EventExplanation>> {
+        let explanations = explain_raw_event(
+            self.processors.as_mut_slice(),
+            &self.storage,
+            source,
+            RawEvent::received_by(value, self.clock.as_ref()),
+        );
+        // Explaining an event doesn't process it, so nothing predicates set is kept
+        self.global_state.discard();
+        explanations
+    }
+
+    /// Counts of events and actions for each tenant since the runtime started, when events are
+    /// split between tenants
+    pub fn tenant_stats(&self) -> HashMap<String, TenantStats> {
+        let mut stats: HashMap<String, TenantStats> = HashMap::new();
+        for processor in &self.processors {
+            for (tenant, processor_stats) in processor.tenant_stats() {
+                stats
+                    .entry(tenant.clone())
+                    .or_default()
+                    .merge(processor_stats);
+            }
+        }
+        stats
+    }
+
+    /// Number of messages since the runtime started that have matched more than one event type
+    pub fn ambiguous_matches(&self) -> u64 {
+        self.processors
+            .iter()
+            .map(EventProcessor::ambiguous_matches)
+            .sum()
+    }
+
+    /// Controls shared with the runtime while it runs, to list its rules and disable or enable
+    /// them
+    pub fn controls(&self) -> RuntimeControls {
+        self.controls.clone()
+    }
+
+    /// Audit records for a correlation, when the storage keeps an audit log
+    pub fn audit_log(&self, correlation_id: &str) -> LaikaResult<Vec<AuditRecord>> {
+        self.storage.read_audit_log(correlation_id)
+    }
+
+    /// Save what predicates set in the global state while handling an event or wakeup, or drop
+    /// it if handling failed, so it's set again when it's retried
+    fn save_global_state<T>(&self, handled: LaikaResult<T>) -> LaikaResult<T> {
+        match handled {
+            Ok(handled) => {
+                self.global_state.save(&self.storage)?;
+                Ok(handled)
+            }
+            Err(e) => {
+                self.global_state.discard();
+                Err(e)
+            }
+        }
+    }
+
+    fn take_actions(&mut self, actions: Vec<EventAction>) -> LaikaResult<()> {
+        for action in actions {
+            match action {
+                EventAction::ScheduleWakeup(wakeup) => self.waker.add_expiry(wakeup)?,
+                EventAction::CancelWakeup(correlation_id, rule) => {
+                    self.waker.nack(&correlation_id, &rule)?
+                }
+                // Stored values are written alongside the events that produced them
+                EventAction::Store(_) => {}
+                other => self.pending.push(other),
+            }
+        }
+        self.controls
+            .set_pending_wakeups(self.waker.pending_count());
+        Ok(())
+    }
+}
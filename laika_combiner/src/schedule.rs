@@ -0,0 +1,202 @@
+use crate::errors::{LaikaError, LaikaResult};
+use serde::Deserialize;
+use time::{Date, Duration, Month, OffsetDateTime, UtcOffset};
+
+/// How far ahead to search for a matching time before treating a schedule as unsatisfiable,
+/// e.g. `0 0 30 2 *`
+const SEARCH_LIMIT_DAYS: i64 = 366 * 5;
+
+/// What a scheduled rule is evaluated against when its schedule fires
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ScheduleScope {
+    /// Evaluated once, without any stored events
+    #[default]
+    Global,
+    /// Evaluated once for every correlation with stored events
+    Correlation,
+}
+
+/// Values allowed in a single field of a cron expression
+#[derive(Clone, Debug)]
+struct CronField {
+    allowed: u64,
+    /// Whether the field was given as `*`, which matters when combining day fields
+    wildcard: bool,
+}
+
+impl CronField {
+    fn parse(field: &str, min: u32, max: u32) -> LaikaResult<Self> {
+        let invalid = || LaikaError::Generic(format!("Invalid cron field {}", field));
+        let value = |raw: &str| raw.parse::<u32>().map_err(|_| invalid());
+        let mut allowed = 0u64;
+        for part in field.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, value(step)?),
+                None => (part, 1),
+            };
+            let (start, end) = match range {
+                "*" => (min, max),
+                _ => match range.split_once('-') {
+                    Some((start, end)) => (value(start)?, value(end)?),
+                    // `5/15` runs from 5 to the end of the range
+                    None if step > 1 => (value(range)?, max),
+                    None => (value(range)?, value(range)?),
+                },
+            };
+            if step == 0 || start < min || end > max || start > end {
+                return Err(invalid());
+            }
+            for allowed_value in (start..=end).step_by(step as usize) {
+                allowed |= 1 << allowed_value;
+            }
+        }
+        Ok(Self {
+            allowed,
+            wildcard: field.starts_with('*'),
+        })
+    }
+
+    fn contains(&self, value: u32) -> bool {
+        self.allowed & (1 << value) != 0
+    }
+}
+
+/// A standard five field cron expression (minute, hour, day of month, month, day of week),
+/// evaluated in UTC.
+#[derive(Clone, Debug)]
+pub struct CronSchedule {
+    minutes: CronField,
+    hours: CronField,
+    days_of_month: CronField,
+    months: CronField,
+    days_of_week: CronField,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> LaikaResult<Self> {
+        let expression = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields.as_slice() else {
+            return Err(LaikaError::Generic(format!(
+                "Cron expression {} must have five fields",
+                expression
+            )));
+        };
+        let mut days_of_week = CronField::parse(days_of_week, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if days_of_week.contains(7) {
+            days_of_week.allowed |= 1;
+        }
+        Ok(Self {
+            minutes: CronField::parse(minutes, 0, 59)?,
+            hours: CronField::parse(hours, 0, 23)?,
+            days_of_month: CronField::parse(days_of_month, 1, 31)?,
+            months: CronField::parse(months, 1, 12)?,
+            days_of_week,
+        })
+    }
+
+    fn matches_day(&self, date: Date) -> bool {
+        let day_of_month = self.days_of_month.contains(date.day() as u32);
+        let day_of_week = self
+            .days_of_week
+            .contains(date.weekday().number_days_from_sunday() as u32);
+        // As in cron, when both day fields are restricted either may match
+        match (self.days_of_month.wildcard, self.days_of_week.wildcard) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        }
+    }
+
+    /// The first time strictly after `after` that the schedule fires
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let after = after.to_offset(UtcOffset::UTC);
+        let limit = after + Duration::days(SEARCH_LIMIT_DAYS);
+        let mut candidate =
+            after.replace_second(0).ok()?.replace_nanosecond(0).ok()? + Duration::minutes(1);
+        while candidate <= limit {
+            if !self.months.contains(u8::from(candidate.month()) as u32) {
+                let (year, month) = match candidate.month() {
+                    Month::December => (candidate.year() + 1, Month::January),
+                    month => (candidate.year(), month.next()),
+                };
+                candidate = Date::from_calendar_date(year, month, 1)
+                    .ok()?
+                    .midnight()
+                    .assume_utc();
+            } else if !self.matches_day(candidate.date()) {
+                candidate = candidate.date().next_day()?.midnight().assume_utc();
+            } else if !self.hours.contains(candidate.hour() as u32) {
+                candidate = candidate.replace_minute(0).ok()? + Duration::hours(1);
+            } else if !self.minutes.contains(candidate.minute() as u32) {
+                candidate += Duration::minutes(1);
+            } else {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+}
+
+/// A cron schedule a rule is evaluated on, rather than on received events.
+#[derive(Clone, Debug)]
+pub struct ScheduleConfig {
+    pub(crate) cron: CronSchedule,
+    pub(crate) scope: ScheduleScope,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_next_after_steps() -> LaikaResult<()> {
+        let schedule = CronSchedule::parse("*/15 9-17 * * *")?;
+        assert_eq!(
+            schedule.next_after(datetime!(2024-03-04 09:07:30 UTC)),
+            Some(datetime!(2024-03-04 09:15:00 UTC))
+        );
+        assert_eq!(
+            schedule.next_after(datetime!(2024-03-04 17:45:00 UTC)),
+            Some(datetime!(2024-03-05 09:00:00 UTC))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_after_day_fields() -> LaikaResult<()> {
+        // 2024-03-04 is a Monday
+        let weekly = CronSchedule::parse("0 9 * * 1")?;
+        assert_eq!(
+            weekly.next_after(datetime!(2024-03-04 09:00:00 UTC)),
+            Some(datetime!(2024-03-11 09:00:00 UTC))
+        );
+        // Either the 1st of the month or a Sunday
+        let either = CronSchedule::parse("0 0 1 * 7")?;
+        assert_eq!(
+            either.next_after(datetime!(2024-03-04 00:00:00 UTC)),
+            Some(datetime!(2024-03-10 00:00:00 UTC))
+        );
+        assert_eq!(
+            CronSchedule::parse("0 0 30 2 *")?.next_after(datetime!(2024-03-04 00:00:00 UTC)),
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(CronSchedule::parse("0 9 * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("0 17-9 * * *").is_err());
+    }
+}
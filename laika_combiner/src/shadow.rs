@@ -0,0 +1,294 @@
+use crate::action::EventAction;
+use crate::config::EventProcessorConfig;
+use crate::errors::LaikaResult;
+use crate::event::EventMetadata;
+use crate::storage::StorageKV;
+use crate::timing::TimingExpiry;
+use crate::Runtime;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Divergent actions kept in the report, most recent last
+const RECENT_DIVERGENCES: usize = 50;
+
+static SHADOW_RUNS: AtomicU64 = AtomicU64::new(0);
+
+/// Which config took an action the other didn't
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Divergence {
+    /// Taken by the active config, which the shadow config wouldn't have taken
+    ActiveOnly,
+    /// Would have been taken by the shadow config, which the active config didn't take
+    ShadowOnly,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DivergentAction {
+    pub divergence: Divergence,
+    /// Summary of the action, see `EventAction::describe`
+    pub action: Value,
+}
+
+/// How the actions a shadow config would have taken compare with the active config's, since it
+/// was loaded
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DivergenceReport {
+    /// Messages evaluated by the shadow config
+    pub messages: u64,
+    /// Messages or wakeups the shadow config failed on
+    pub errors: u64,
+    /// Actions both configs took
+    pub matched: u64,
+    pub active_only: u64,
+    pub shadow_only: u64,
+    /// The most recent actions only one of the configs took
+    pub recent: VecDeque<DivergentAction>,
+}
+
+impl DivergenceReport {
+    fn diverged(&mut self, divergence: Divergence, action: Value) {
+        match divergence {
+            Divergence::ActiveOnly => self.active_only += 1,
+            Divergence::ShadowOnly => self.shadow_only += 1,
+        }
+        if self.recent.len() == RECENT_DIVERGENCES {
+            self.recent.pop_front();
+        }
+        self.recent
+            .push_back(DivergentAction { divergence, action });
+    }
+}
+
+/// Divergence report of a running shadow config, read while it runs such as by the admin API.
+/// Clones share the same report.
+#[derive(Clone, Debug, Default)]
+pub struct ShadowReport {
+    inner: Arc<Mutex<DivergenceReport>>,
+}
+
+impl ShadowReport {
+    pub fn report(&self) -> DivergenceReport {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn update(&self, update: impl FnOnce(&mut DivergenceReport)) {
+        update(&mut self.inner.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+/// A second config evaluated on the same messages as the active one, so changes to rules can be
+/// tried on live traffic. Its actions are only compared with the active config's, never taken.
+///
+/// The shadow config keeps its state in memory, apart from the active config's, so it starts
+/// afresh each run. Failures in it are logged and counted rather than stopping processing.
+pub struct Shadow {
+    runtime: Runtime,
+    timer_path: PathBuf,
+    /// Keys stamped onto payloads by either config's `provenance`, left out of comparisons as
+    /// they differ between configs
+    ignored_keys: Vec<String>,
+    report: ShadowReport,
+}
+
+impl Shadow {
+    /// Evaluate `shadow` alongside `active`
+    pub fn new(active: &EventProcessorConfig, shadow: EventProcessorConfig) -> LaikaResult<Self> {
+        let ignored_keys = [active.provenance(), shadow.provenance()]
+            .into_iter()
+            .flatten()
+            .map(|provenance| provenance.key().to_string())
+            .collect();
+        let timer_path = std::env::temp_dir().join(format!(
+            "laika_shadow_{}_{}",
+            std::process::id(),
+            SHADOW_RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let waker =
+            TimingExpiry::new(timer_path.clone())?.with_max_wakeups(shadow.storage().max_wakeups());
+        let runtime = Runtime::new(vec![shadow.build()?], StorageKV::in_memory(), waker)?;
+        Ok(Self {
+            runtime,
+            timer_path,
+            ignored_keys,
+            report: ShadowReport::default(),
+        })
+    }
+
+    /// Report shared with whatever reads it while the shadow config runs
+    pub fn report(&self) -> ShadowReport {
+        self.report.clone()
+    }
+
+    /// Evaluate a message the active config has been given, after the shadow config's lookups
+    pub async fn push_event(&mut self, source: &str, value: Value, metadata: EventMetadata) {
+        let pushed = match self.runtime.lookup(source, value, &metadata).await {
+            Ok(enriched) => self
+                .runtime
+                .push_event_with_metadata(source, enriched, metadata),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &pushed {
+            tracing::warn!(
+                target: "laika::shadow",
+                "Shadow config failed on message from {}: {}",
+                source,
+                e
+            );
+        }
+        self.report.update(|report| {
+            report.messages += 1;
+            report.errors += u64::from(pushed.is_err());
+        });
+    }
+
+    /// Compare the actions the active config took for the messages since the last comparison,
+    /// including those from wakeups, with those the shadow config would have taken
+    pub fn compare(&mut self, active: &[EventAction]) {
+        let shadow = match self.runtime.poll_actions() {
+            Ok(actions) => actions,
+            Err(e) => {
+                tracing::warn!(target: "laika::shadow", "Shadow config failed on a wakeup: {}", e);
+                self.report.update(|report| report.errors += 1);
+                Vec::new()
+            }
+        };
+        let mut shadow: Vec<Value> = shadow.iter().filter_map(|a| self.comparable(a)).collect();
+        let mut active_only = Vec::new();
+        let mut matched = 0;
+        for action in active.iter().filter_map(|a| self.comparable(a)) {
+            match shadow.iter().position(|taken| *taken == action) {
+                Some(index) => {
+                    shadow.swap_remove(index);
+                    matched += 1;
+                }
+                None => active_only.push(action),
+            }
+        }
+        self.report.update(|report| {
+            report.matched += matched;
+            for action in active_only {
+                tracing::info!(
+                    target: "laika::shadow",
+                    action = %action,
+                    "Shadow config wouldn't have taken an action"
+                );
+                report.diverged(Divergence::ActiveOnly, action);
+            }
+            for action in shadow {
+                tracing::info!(
+                    target: "laika::shadow",
+                    action = %action,
+                    "Shadow config would have taken an action"
+                );
+                report.diverged(Divergence::ShadowOnly, action);
+            }
+        });
+    }
+
+    /// Summary of an action sent out, without what `provenance` stamps onto it
+    fn comparable(&self, action: &EventAction) -> Option<Value> {
+        let field = match action {
+            EventAction::Emit(_) => "payload",
+            EventAction::Http(_) => "body",
+            EventAction::Store(_)
+            | EventAction::ScheduleWakeup(_)
+            | EventAction::CancelWakeup(..) => return None,
+        };
+        let mut described = action.describe();
+        if let Some(Value::Object(payload)) = described.get_mut(field) {
+            for key in &self.ignored_keys {
+                payload.remove(key);
+            }
+        }
+        Some(described)
+    }
+}
+
+impl Drop for Shadow {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.timer_path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::builder::EventProcessorYamlSpec;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+connections:
+  orders:
+    type: stdin
+  alerts:
+    type: stdout
+events:
+  placed:
+    from: orders
+    matchKey:
+      type: placed
+correlation:
+  placed:
+    key: "$.orderId"
+provenance:
+  configVersion: "VERSION"
+triggers:
+  largeOrder:
+    requires:
+      at_least: [placed]
+    filterAndExtract: >
+      (trigger, ctx) => trigger.event.total > LIMIT ? { orderId: trigger.event.orderId } : null
+    action:
+      target: alerts
+      payload:
+        orderId: "${{ orderId }}"
+"#;
+
+    fn config(version: &str, limit: u32) -> EventProcessorConfig {
+        let yaml = CONFIG
+            .replace("VERSION", version)
+            .replace("LIMIT", &limit.to_string());
+        EventProcessorConfig::try_from(&EventProcessorYamlSpec::from_yaml(&yaml).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_shadow_divergence_is_reported() {
+        let dir = TempDir::new().unwrap();
+        let active_config = config("1", 100);
+        let mut shadow = Shadow::new(&active_config, config("2", 50)).unwrap();
+        let mut active = Runtime::new(
+            vec![active_config.build().unwrap()],
+            StorageKV::in_memory(),
+            TimingExpiry::new(dir.path().join(".timing_expiry")).unwrap(),
+        )
+        .unwrap();
+        for (order, total) in [("A1", 150), ("A2", 75), ("A3", 10)] {
+            let event = json!({"type": "placed", "orderId": order, "total": total});
+            active.push_event("orders", event.clone()).unwrap();
+            shadow
+                .push_event("orders", event, EventMetadata::new())
+                .await;
+        }
+        shadow.compare(&active.poll_actions().unwrap());
+
+        let report = shadow.report().report();
+        assert_eq!(report.messages, 3);
+        assert_eq!(report.errors, 0);
+        // Only the config versions differ for A1, which provenance stamps aren't compared on
+        assert_eq!(report.matched, 1);
+        assert_eq!(report.active_only, 0);
+        assert_eq!(report.shadow_only, 1);
+        assert_eq!(report.recent[0].divergence, Divergence::ShadowOnly);
+        assert_eq!(report.recent[0].action["payload"], json!({"orderId": "A2"}));
+    }
+}
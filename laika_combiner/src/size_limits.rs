@@ -0,0 +1,277 @@
+use crate::dead_letter::DeadLetterConfig;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::utils::extract_json::{parse_path, resolve_index, PathSegment};
+use serde::Deserialize;
+use serde_json::Value;
+use std::io::Write;
+
+/// Ends values shortened to bring a payload within its limit, and replaces those that couldn't be
+pub const TRUNCATED: &str = "[TRUNCATED]";
+
+/// What happens to a payload over its size limit
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SizePolicy {
+    /// Drop the payload, logging why, so it never reaches rules or targets
+    #[default]
+    Reject,
+    /// Send the payload to the dead-letter target instead
+    DeadLetter,
+    /// Shorten the `truncate` fields until the payload fits, rejecting it if it still doesn't
+    Truncate,
+}
+
+/// Caps on the size of received messages and emitted payloads, measured as encoded JSON, so a
+/// single huge message can't reach predicates or storage
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SizeLimitsConfig {
+    max_inbound_bytes: Option<usize>,
+    max_emit_bytes: Option<usize>,
+    #[serde(default)]
+    policy: SizePolicy,
+    /// Paths to fields shortened in turn, under the `truncate` policy
+    #[serde(default)]
+    truncate: Vec<String>,
+}
+
+impl SizeLimitsConfig {
+    pub fn build(&self, dead_letter: Option<&DeadLetterConfig>) -> LaikaResult<SizeLimits> {
+        if self.max_inbound_bytes == Some(0) || self.max_emit_bytes == Some(0) {
+            return Err(LaikaError::config(
+                "",
+                "maxInboundBytes and maxEmitBytes must be at least 1",
+            ));
+        }
+        let dead_letter = match (self.policy, dead_letter) {
+            (SizePolicy::DeadLetter, None) => {
+                return Err(LaikaError::config(
+                    "policy",
+                    "deadLetter needs a deadLetter target to be configured",
+                ))
+            }
+            (SizePolicy::DeadLetter, Some(dead_letter)) => Some(dead_letter.target.clone()),
+            _ => None,
+        };
+        match (self.policy, self.truncate.is_empty()) {
+            (SizePolicy::Truncate, true) => {
+                return Err(LaikaError::config(
+                    "truncate",
+                    "The truncate policy needs fields to truncate",
+                ))
+            }
+            (SizePolicy::Reject | SizePolicy::DeadLetter, false) => {
+                return Err(LaikaError::config(
+                    "truncate",
+                    "truncate can only be used with the truncate policy",
+                ))
+            }
+            _ => {}
+        }
+        let truncate = self
+            .truncate
+            .iter()
+            .map(|path| {
+                let segments = parse_path(path.as_str())
+                    .map_err(|e| LaikaError::config("truncate", e.to_string()))?;
+                let definite = segments.iter().all(|segment| {
+                    matches!(segment, PathSegment::Field(_) | PathSegment::Index(_))
+                });
+                if segments.is_empty() || !definite {
+                    return Err(LaikaError::config(
+                        "truncate",
+                        format!("{} doesn't name a single field", path),
+                    ));
+                }
+                Ok(segments)
+            })
+            .collect::<LaikaResult<Vec<Vec<PathSegment>>>>()?;
+        Ok(SizeLimits {
+            max_inbound_bytes: self.max_inbound_bytes,
+            max_emit_bytes: self.max_emit_bytes,
+            policy: self.policy,
+            truncate,
+            dead_letter,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SizeLimits {
+    max_inbound_bytes: Option<usize>,
+    max_emit_bytes: Option<usize>,
+    policy: SizePolicy,
+    truncate: Vec<Vec<PathSegment>>,
+    dead_letter: Option<String>,
+}
+
+/// Length of a value as compact JSON, without encoding it
+fn encoded_len(value: &Value) -> usize {
+    struct Counter(usize);
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut counter = Counter(0);
+    // Counting can't fail, and JSON values always serialize
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+fn field_mut<'a>(value: &'a mut Value, path: &[PathSegment]) -> Option<&'a mut Value> {
+    path.iter()
+        .try_fold(value, |value, segment| match (segment, value) {
+            (PathSegment::Field(name), value) => value.get_mut(name.as_str()),
+            (PathSegment::Index(index), Value::Array(items)) => {
+                let index = resolve_index(items.len(), *index)?;
+                items.get_mut(index)
+            }
+            _ => None,
+        })
+}
+
+impl SizeLimits {
+    /// Connection oversized payloads are sent to, under the `deadLetter` policy
+    pub fn dead_letter(&self) -> Option<&str> {
+        self.dead_letter.as_deref()
+    }
+
+    /// Check a message received from `source`, truncating it if that's the policy. Fails with
+    /// `PayloadTooLarge` if it's still over `maxInboundBytes`.
+    pub fn guard_inbound(&self, source: &str, message: &mut Value) -> LaikaResult<()> {
+        self.guard(message, self.max_inbound_bytes, || {
+            format!("Message from {}", source)
+        })
+    }
+
+    /// Check a payload emitted to `target`, as for `guard_inbound` but against `maxEmitBytes`
+    pub fn guard_emit(&self, target: &str, payload: &mut Value) -> LaikaResult<()> {
+        self.guard(payload, self.max_emit_bytes, || {
+            format!("Emit to {}", target)
+        })
+    }
+
+    fn guard(
+        &self,
+        payload: &mut Value,
+        limit: Option<usize>,
+        describe: impl Fn() -> String,
+    ) -> LaikaResult<()> {
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+        let size = encoded_len(payload);
+        if size <= limit {
+            return Ok(());
+        }
+        if self.policy == SizePolicy::Truncate {
+            let truncated = self.truncate(payload, size, limit);
+            if truncated <= limit {
+                tracing::warn!(
+                    "{} truncated from {} to {} bytes",
+                    describe(),
+                    size,
+                    truncated
+                );
+                return Ok(());
+            }
+        }
+        Err(LaikaError::PayloadTooLarge {
+            payload: describe(),
+            size,
+            limit,
+        })
+    }
+
+    /// Shorten each `truncate` field in turn until the payload fits, returning its new size
+    fn truncate(&self, payload: &mut Value, mut size: usize, limit: usize) -> usize {
+        for path in &self.truncate {
+            if size <= limit {
+                break;
+            }
+            let Some(field) = field_mut(payload, path) else {
+                continue;
+            };
+            let excess = size - limit + TRUNCATED.len();
+            *field = match &*field {
+                Value::String(text) if text.len() > excess => {
+                    let mut end = text.len() - excess;
+                    while !text.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    Value::String(format!("{}{}", &text[..end], TRUNCATED))
+                }
+                _ => Value::String(TRUNCATED.to_string()),
+            };
+            size = encoded_len(payload);
+        }
+        size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn limits(yaml: &str) -> SizeLimits {
+        serde_yaml::from_str::<SizeLimitsConfig>(yaml)
+            .unwrap()
+            .build(None)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_truncates_listed_fields_until_within_limit() {
+        let limits =
+            limits("maxInboundBytes: 100\npolicy: truncate\ntruncate: [$.attachments, $.body]");
+        let mut message = json!({
+            "orderId": 1,
+            "attachments": ["a".repeat(40)],
+            "body": "b".repeat(200),
+        });
+        limits.guard_inbound("orders", &mut message).unwrap();
+        assert!(encoded_len(&message) <= 100);
+        assert_eq!(message["orderId"], 1);
+        assert_eq!(message["attachments"], TRUNCATED);
+        let body = message["body"].as_str().unwrap();
+        assert!(body.starts_with("bbb") && body.ends_with(TRUNCATED));
+
+        // Payloads that fit are left alone
+        let mut small = json!({"orderId": 2, "body": "short"});
+        limits.guard_inbound("orders", &mut small).unwrap();
+        assert_eq!(small["body"], "short");
+    }
+
+    #[test]
+    fn test_rejects_oversized_payloads() {
+        let limits = limits("maxEmitBytes: 20");
+        let mut payload = json!({"body": "b".repeat(50)});
+        assert!(matches!(
+            limits.guard_emit("alerts", &mut payload),
+            Err(LaikaError::PayloadTooLarge {
+                size: 61,
+                limit: 20,
+                ..
+            })
+        ));
+        // Inbound messages aren't limited unless maxInboundBytes is set
+        assert!(limits.guard_inbound("orders", &mut payload).is_ok());
+    }
+
+    #[test]
+    fn test_dead_letter_policy_needs_target() {
+        let config: SizeLimitsConfig =
+            serde_yaml::from_str("maxInboundBytes: 100\npolicy: deadLetter").unwrap();
+        assert!(matches!(
+            config.build(None),
+            Err(LaikaError::ConfigError { path, .. }) if path == "policy"
+        ));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::broker::{CorrelationId, ExpiryKind};
+use crate::errors::LaikaResult;
+use crate::storage::StorageKV;
+use crate::timing::TimingExpiry;
+use serde::Serialize;
+use time::OffsetDateTime;
+
+/// An event stored against a correlation
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredEvent {
+    pub event_type: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub received: OffsetDateTime,
+    pub data: serde_json::Value,
+}
+
+/// A wakeup still to happen for a correlation
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingExpiry {
+    pub rule: String,
+    pub kind: ExpiryKind,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+}
+
+/// Everything kept for a correlation, for debugging correlations that never complete
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CorrelationState {
+    pub correlation_id: CorrelationId,
+    pub events: Vec<StoredEvent>,
+    /// Values stored against the correlation by rules
+    pub stored: serde_json::Map<String, serde_json::Value>,
+    /// Rules that have fired for the correlation, with when they last fired and what they produced
+    pub decisions: serde_json::Map<String, serde_json::Value>,
+    pub expiries: Vec<PendingExpiry>,
+}
+
+/// Read the events, stored values, rule fires and pending expiries of a correlation, without
+/// changing them
+pub fn inspect(
+    storage: &StorageKV,
+    timer: &mut TimingExpiry,
+    correlation_id: &str,
+) -> LaikaResult<CorrelationState> {
+    let txn = storage.start_transaction();
+    let events = storage
+        .read_events(&txn, correlation_id)?
+        .into_iter()
+        .map(|event| StoredEvent {
+            event_type: event.event_type,
+            received: event.received,
+            data: event.data,
+        })
+        .collect();
+    let stored = storage.read_stored_values(&txn, correlation_id)?;
+    let decisions = storage.read_rule_fires(&txn, correlation_id)?;
+    let expiries = timer
+        .pending()?
+        .into_iter()
+        .filter(|expiry| expiry.correlation_id == correlation_id)
+        .map(|expiry| PendingExpiry {
+            rule: expiry.event_rule,
+            kind: expiry.kind,
+            expires_at: expiry.expires_at,
+        })
+        .collect();
+    Ok(CorrelationState {
+        correlation_id: correlation_id.to_string(),
+        events,
+        stored,
+        decisions,
+        expiries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::EventExpiry;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_inspect_correlation() {
+        let storage = StorageKV::in_memory();
+        let txn = storage.start_transaction();
+        storage
+            .write_stored_value(&txn, "user123", "score", serde_json::json!(5))
+            .unwrap();
+        for plan in ["free", "pro"] {
+            storage
+                .record_rule_fire(
+                    &txn,
+                    "upgraded",
+                    "user123",
+                    OffsetDateTime::UNIX_EPOCH,
+                    &serde_json::json!({ "plan": plan }),
+                )
+                .unwrap();
+        }
+        txn.commit().unwrap();
+
+        let timer_file = NamedTempFile::new().unwrap();
+        let mut timer = TimingExpiry::new(timer_file.path().to_path_buf()).unwrap();
+        let expires_at = OffsetDateTime::now_utc() + time::Duration::minutes(5);
+        timer
+            .add_expiries(vec![
+                EventExpiry::new(expires_at, "user123".to_string(), "signup".to_string()),
+                EventExpiry::new(expires_at, "user456".to_string(), "signup".to_string()),
+            ])
+            .unwrap();
+
+        let state = inspect(&storage, &mut timer, "user123").unwrap();
+        assert!(state.events.is_empty());
+        assert_eq!(state.stored["score"], serde_json::json!(5));
+        assert_eq!(
+            state.decisions["upgraded"],
+            serde_json::json!({
+                "firedAt": "1970-01-01T00:00:00Z",
+                "fires": 2,
+                "result": { "plan": "pro" }
+            })
+        );
+        assert_eq!(
+            state.expiries,
+            vec![PendingExpiry {
+                rule: "signup".to_string(),
+                kind: ExpiryKind::Recheck,
+                expires_at,
+            }]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use crate::errors::LaikaResult;
+use crate::storage::{KeyWrite, StorageBackend};
+use dashmap::DashMap;
+use std::collections::BTreeMap;
+
+/// Keeps state in memory, so it's lost when the process stops. Suited to tests, simulations,
+/// and deployments where state can be rebuilt.
+///
+/// Tables are locked independently, so writes across tables aren't applied atomically. Each
+/// store has a single writer, so a batch's writes are still never seen partially applied.
+#[derive(Default)]
+pub struct MemoryBackend {
+    tables: DashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn get(&self, table: &str, key: &[u8]) -> LaikaResult<Option<Vec<u8>>> {
+        Ok(self
+            .tables
+            .get(table)
+            .and_then(|entries| entries.get(key).cloned()))
+    }
+
+    fn write(&self, writes: Vec<KeyWrite>) -> LaikaResult<()> {
+        for write in writes {
+            let mut entries = self.tables.entry(write.table).or_default();
+            match write.value {
+                Some(value) => {
+                    entries.insert(write.key, value);
+                }
+                None => {
+                    entries.remove(&write.key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn scan(&self, table: &str, prefix: &[u8]) -> LaikaResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .tables
+            .get(table)
+            .map(|entries| {
+                entries
+                    .range(prefix.to_vec()..)
+                    .take_while(|(key, _)| key.starts_with(prefix))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn scan_keys(
+        &self,
+        table: &str,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> LaikaResult<Vec<Vec<u8>>> {
+        Ok(self
+            .tables
+            .get(table)
+            .map(|entries| {
+                entries
+                    .range(prefix.to_vec()..)
+                    .take_while(|(key, _)| key.starts_with(prefix))
+                    .take(limit.unwrap_or(usize::MAX))
+                    .map(|(key, _)| key.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,1623 @@
+use crate::audit::{AuditRecord, AUDIT_RECORD_VERSION};
+use crate::broker::CorrelationId;
+use crate::errors::{ErrorSource, LaikaError, LaikaResult};
+use crate::event::event_serde::CorrelatedEventCapnpBatch;
+use crate::event::CorrelatedEvent;
+use crate::global_state::GlobalValue;
+use crate::timing::DEFAULT_MAX_WAKEUPS;
+use fs2::FileExt;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+pub use memory::MemoryBackend;
+
+mod memory;
+#[cfg(feature = "redb")]
+mod redb_store;
+#[cfg(feature = "redis")]
+mod redis_store;
+#[cfg(feature = "rocksdb")]
+mod rocks;
+
+/// Table holding events received for a correlation, keyed by correlation
+const EVENTS: &str = "events";
+/// Table holding the start of open aggregation windows, keyed by rule and correlation
+const AGGREGATION_WINDOWS: &str = "aggregation_windows";
+/// Table holding events buffered into windows, keyed by rule and group
+const WINDOW_EVENTS: &str = "window_events";
+/// Table holding values stored by rules against a correlation, as a JSON object
+const STORED_VALUES: &str = "stored_values";
+/// Table holding audit records of received events, keyed by correlation then time
+const AUDIT_LOG: &str = "audit_log";
+/// Table holding the correlation each linked correlation was merged into, keyed by the linked one
+const CORRELATION_LINKS: &str = "correlation_links";
+/// Table holding the decisions of rules with late event policies, keyed by rule and correlation
+const RULE_DECISIONS: &str = "rule_decisions";
+/// Table holding how many rechecks in a row of an escalating rule haven't satisfied it, keyed by
+/// rule and correlation
+const RECHECK_COUNTS: &str = "recheck_counts";
+/// Table holding digests of actions recently taken by deduplicated rules, with when each stops
+/// being a duplicate, keyed by rule and correlation
+const ACTION_LOG: &str = "action_log";
+/// Table holding when each rule last fired for a correlation and what it produced, as a JSON
+/// object keyed by rule, keyed by correlation
+const RULE_FIRES: &str = "rule_fires";
+/// Table holding values shared by every correlation, with when each expires, keyed by name
+const GLOBAL_STATE: &str = "global_state";
+const TABLES: [&str; 11] = [
+    EVENTS,
+    AGGREGATION_WINDOWS,
+    WINDOW_EVENTS,
+    STORED_VALUES,
+    AUDIT_LOG,
+    CORRELATION_LINKS,
+    RULE_DECISIONS,
+    RECHECK_COUNTS,
+    ACTION_LOG,
+    RULE_FIRES,
+    GLOBAL_STATE,
+];
+/// Held exclusively by the process using a state directory
+const LOCK_FILE: &str = "laika.lock";
+
+/// Table holding events of the types in an `eventTables` entry
+fn event_table_name(name: &str) -> String {
+    format!("events.{}", name)
+}
+
+/// Smallest key greater than every key starting with `prefix`, if there is one
+pub(crate) fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < u8::MAX {
+            end.push(last + 1);
+            return Some(end);
+        }
+    }
+    None
+}
+
+/// Take an exclusive lock on a state directory, so two processes can't share a store
+fn lock_state_dir(base_path: &Path) -> LaikaResult<File> {
+    std::fs::create_dir_all(base_path).map_err(|e| LaikaError::IO(e.to_string()))?;
+    let lock =
+        File::create(base_path.join(LOCK_FILE)).map_err(|e| LaikaError::IO(e.to_string()))?;
+    lock.try_lock_exclusive()
+        .map_err(|_| LaikaError::StorageError {
+            source: ErrorSource::message(format!(
+                "{} is already in use by another process",
+                base_path.display()
+            )),
+        })?;
+    Ok(lock)
+}
+
+/// A change to a single key, where a value of `None` deletes it
+pub struct KeyWrite {
+    pub table: String,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// Ordered key-value tables that `StorageKV` keeps state in.
+///
+/// Tables are a set of names fixed when the store is opened, and keys within a table sort
+/// bytewise.
+pub trait StorageBackend: Send + Sync {
+    fn get(&self, table: &str, key: &[u8]) -> LaikaResult<Option<Vec<u8>>>;
+
+    /// Apply writes in order, as a single atomic write where the backend supports it
+    fn write(&self, writes: Vec<KeyWrite>) -> LaikaResult<()>;
+
+    /// Entries in a table with keys starting with `prefix`, ordered by key
+    fn scan(&self, table: &str, prefix: &[u8]) -> LaikaResult<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Keys in a table starting with `prefix`, ordered, up to `limit` of them
+    fn scan_keys(
+        &self,
+        table: &str,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> LaikaResult<Vec<Vec<u8>>> {
+        Ok(self
+            .scan(table, prefix)?
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    /// Reclaim space after a large number of deletes
+    fn compact(&self) -> LaikaResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngine {
+    /// Requires the `rocksdb` feature, which is enabled by default
+    #[default]
+    RocksDb,
+    /// Pure Rust, so it builds anywhere Rust does, such as musl targets. Requires the `redb`
+    /// feature.
+    Redb,
+    /// Nothing is persisted, so state is lost when the process stops
+    Memory,
+    /// Kept in Redis at `url`, so it can be shared between instances. Requires the `redis`
+    /// feature.
+    Redis,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactionStyle {
+    #[default]
+    Level,
+    Universal,
+    Fifo,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CompactionConfig {
+    #[serde(default)]
+    style: CompactionStyle,
+    /// How often files are compacted regardless of size, e.g. `7d`
+    periodic: Option<String>,
+}
+
+/// Events of some types, kept in a table of their own so high-volume types can be compacted and
+/// expired without slowing reads of everything else
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct EventTableConfig {
+    event_types: Vec<String>,
+    #[serde(default)]
+    compaction: CompactionConfig,
+    /// Age after which events may be dropped during compaction, e.g. `7d`. Only RocksDB expires
+    /// events.
+    ttl: Option<String>,
+}
+
+impl EventTableConfig {
+    pub fn new(event_types: Vec<String>) -> Self {
+        Self {
+            event_types,
+            compaction: CompactionConfig::default(),
+            ttl: None,
+        }
+    }
+
+    pub fn with_compaction(mut self, style: CompactionStyle) -> Self {
+        self.compaction.style = style;
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: &str) -> Self {
+        self.ttl = Some(ttl.to_string());
+        self
+    }
+}
+
+/// What happens to an event that would take a correlation over its context limits
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LimitPolicy {
+    /// Drop the correlation's oldest events until it's back within its limits, keeping the new
+    /// event even when it's over them on its own
+    #[default]
+    DropOldest,
+    /// Ignore the new event, leaving the correlation as it was
+    Reject,
+    /// Fail the new event so it's sent to the dead-letter target
+    DeadLetter,
+}
+
+/// Caps on the events kept for each correlation across every event table, so a runaway
+/// correlation ID can't slow every evaluation of it
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ContextLimits {
+    max_events: Option<usize>,
+    /// Size of a correlation's stored events, counting their payloads as JSON, correlation IDs
+    /// and types
+    max_bytes: Option<usize>,
+    #[serde(default)]
+    policy: LimitPolicy,
+}
+
+impl ContextLimits {
+    pub fn new(policy: LimitPolicy) -> Self {
+        Self {
+            max_events: None,
+            max_bytes: None,
+            policy,
+        }
+    }
+
+    pub fn with_max_events(mut self, max_events: usize) -> Self {
+        self.max_events = Some(max_events);
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn policy(&self) -> LimitPolicy {
+        self.policy
+    }
+
+    /// Description of the limit exceeded by a correlation's events, if any
+    fn exceeded(&self, events: usize, bytes: usize) -> Option<String> {
+        match (self.max_events, self.max_bytes) {
+            (Some(max_events), _) if events > max_events => Some(format!(
+                "{} events is over the limit of {}",
+                events, max_events
+            )),
+            (_, Some(max_bytes)) if bytes > max_bytes => Some(format!(
+                "{} bytes is over the limit of {}",
+                bytes, max_bytes
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Where and how state is kept, from the `storage` section of the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct StorageConfig {
+    #[serde(default)]
+    engine: StorageEngine,
+    #[serde(default = "StorageConfig::default_data_dir")]
+    data_dir: PathBuf,
+    /// Server to keep state on, for remote engines
+    url: Option<String>,
+    /// Prefix for keys on the server, so deployments can share one
+    #[serde(default = "StorageConfig::default_key_prefix")]
+    key_prefix: String,
+    /// Wakeups are kept in `dataDir` unless given a path of their own
+    timer_path: Option<PathBuf>,
+    /// Pending wakeups kept before new ones are ignored, 100,000 by default
+    max_wakeups: Option<usize>,
+    /// Bytes of write-ahead log kept before flushing, 10GiB by default
+    max_total_wal_size: Option<u64>,
+    parallelism: Option<usize>,
+    max_background_jobs: Option<usize>,
+    #[serde(default)]
+    compaction: CompactionConfig,
+    /// Tables of their own for events of the given types, by name
+    #[serde(default)]
+    event_tables: BTreeMap<String, EventTableConfig>,
+    context_limits: Option<ContextLimits>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            engine: StorageEngine::default(),
+            data_dir: Self::default_data_dir(),
+            url: None,
+            key_prefix: Self::default_key_prefix(),
+            timer_path: None,
+            max_wakeups: None,
+            max_total_wal_size: None,
+            parallelism: None,
+            max_background_jobs: None,
+            compaction: CompactionConfig::default(),
+            event_tables: BTreeMap::new(),
+            context_limits: None,
+        }
+    }
+}
+
+impl StorageConfig {
+    fn default_data_dir() -> PathBuf {
+        std::env::temp_dir()
+    }
+
+    fn default_key_prefix() -> String {
+        "laika".to_string()
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        self.data_dir.as_path()
+    }
+
+    /// Caps on the events kept for each correlation, if any
+    pub fn context_limits(&self) -> Option<&ContextLimits> {
+        self.context_limits.as_ref()
+    }
+
+    pub fn timer_path(&self) -> PathBuf {
+        self.timer_path
+            .clone()
+            .unwrap_or_else(|| self.data_dir.join(".timing_expiry"))
+    }
+
+    /// Whether pending wakeups are only kept where this instance can see them, being left in
+    /// `dataDir` while state is kept on a server
+    pub fn local_timers(&self) -> bool {
+        self.engine == StorageEngine::Redis && self.timer_path.is_none()
+    }
+
+    pub fn max_wakeups(&self) -> usize {
+        self.max_wakeups.unwrap_or(DEFAULT_MAX_WAKEUPS)
+    }
+
+    /// Builder for a store in `dataDir` with these options
+    pub fn builder(&self) -> StorageKVBuilder {
+        self.builder_at(self.data_dir())
+    }
+
+    /// Builder for a store with these options, kept in `base_path` rather than `dataDir`
+    pub fn builder_at<P: AsRef<Path>>(&self, base_path: P) -> StorageKVBuilder {
+        StorageKVBuilder {
+            engine: self.engine,
+            url: self.url.clone(),
+            key_prefix: self.key_prefix.clone(),
+            max_total_wal_size: self.max_total_wal_size,
+            parallelism: self.parallelism,
+            max_background_jobs: self.max_background_jobs,
+            compaction: self.compaction.clone(),
+            event_tables: self.event_tables.clone(),
+            context_limits: self.context_limits.clone(),
+            ..StorageKVBuilder::new(base_path)
+        }
+    }
+}
+
+/// Writes made within a transaction, applied together on commit. Reads within the transaction
+/// see its own writes.
+///
+/// A store only has one writer, so transactions don't check for conflicting writes.
+pub struct StorageTransaction<'a> {
+    backend: &'a dyn StorageBackend,
+    writes: RefCell<BTreeMap<(String, Vec<u8>), Option<Vec<u8>>>>,
+}
+
+impl StorageTransaction<'_> {
+    fn get(&self, table: &str, key: &[u8]) -> LaikaResult<Option<Vec<u8>>> {
+        if let Some(written) = self.writes.borrow().get(&(table.to_string(), key.to_vec())) {
+            return Ok(written.clone());
+        }
+        self.backend.get(table, key)
+    }
+
+    fn put(&self, table: &str, key: &[u8], value: Vec<u8>) {
+        self.writes
+            .borrow_mut()
+            .insert((table.to_string(), key.to_vec()), Some(value));
+    }
+
+    fn delete(&self, table: &str, key: &[u8]) {
+        self.writes
+            .borrow_mut()
+            .insert((table.to_string(), key.to_vec()), None);
+    }
+
+    pub fn commit(self) -> LaikaResult<()> {
+        let writes = self
+            .writes
+            .into_inner()
+            .into_iter()
+            .map(|((table, key), value)| KeyWrite { table, key, value })
+            .collect::<Vec<KeyWrite>>();
+        if writes.is_empty() {
+            return Ok(());
+        }
+        self.backend.write(writes)
+    }
+}
+
+pub struct StorageKV {
+    backend: Box<dyn StorageBackend>,
+    /// Whether received events are recorded in the audit log
+    audit_log: bool,
+    /// Tables that events of a type are kept in, for types not kept in the shared table
+    event_tables: HashMap<String, String>,
+    context_limits: Option<ContextLimits>,
+}
+
+pub struct StorageKVBuilder {
+    engine: StorageEngine,
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    url: Option<String>,
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    key_prefix: String,
+    audit_log: bool,
+    read_only: bool,
+    max_total_wal_size: Option<u64>,
+    parallelism: Option<usize>,
+    max_background_jobs: Option<usize>,
+    compaction: CompactionConfig,
+    event_tables: BTreeMap<String, EventTableConfig>,
+    context_limits: Option<ContextLimits>,
+    base_path: PathBuf,
+}
+
+impl StorageKVBuilder {
+    pub fn new<P: AsRef<Path>>(base_path: P) -> StorageKVBuilder {
+        StorageKVBuilder {
+            engine: StorageEngine::default(),
+            url: None,
+            key_prefix: StorageConfig::default_key_prefix(),
+            audit_log: false,
+            read_only: false,
+            max_total_wal_size: None,
+            parallelism: None,
+            max_background_jobs: None,
+            compaction: CompactionConfig::default(),
+            event_tables: BTreeMap::new(),
+            context_limits: None,
+            base_path: PathBuf::from(base_path.as_ref()),
+        }
+    }
+
+    pub fn engine(mut self, engine: StorageEngine) -> StorageKVBuilder {
+        self.engine = engine;
+        self
+    }
+
+    /// Server to keep state on, for remote engines
+    pub fn url(mut self, url: &str) -> StorageKVBuilder {
+        self.url = Some(url.to_string());
+        self
+    }
+
+    /// Record every received event, and the actions it led to, in an audit log
+    pub fn audit_log(mut self, enabled: bool) -> StorageKVBuilder {
+        self.audit_log = enabled;
+        self
+    }
+
+    /// Open an existing store only to read it, without taking the state directory lock. RocksDB
+    /// stores can be read while another process is running on them, and Redis stores aren't
+    /// claimed from their writer. redb locks its file while it's open, so redb stores can only be
+    /// read once nothing else has them open.
+    pub fn read_only(mut self, read_only: bool) -> StorageKVBuilder {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn max_total_wal_size(mut self, size: u64) -> StorageKVBuilder {
+        self.max_total_wal_size = Some(size);
+        self
+    }
+
+    pub fn parallelism(mut self, parallelism: usize) -> StorageKVBuilder {
+        self.parallelism = Some(parallelism);
+        self
+    }
+    pub fn max_background_jobs(mut self, jobs: usize) -> StorageKVBuilder {
+        self.max_background_jobs = Some(jobs);
+        self
+    }
+
+    /// Keep events of some types in a table of their own, called `name`
+    pub fn event_table(mut self, name: &str, table: EventTableConfig) -> StorageKVBuilder {
+        self.event_tables.insert(name.to_string(), table);
+        self
+    }
+
+    /// Cap the events kept for each correlation
+    pub fn context_limits(mut self, limits: ContextLimits) -> StorageKVBuilder {
+        self.context_limits = Some(limits);
+        self
+    }
+
+    /// Every table the store is opened with
+    fn tables(&self) -> Vec<String> {
+        TABLES
+            .iter()
+            .map(|table| table.to_string())
+            .chain(self.event_tables.keys().map(|name| event_table_name(name)))
+            .collect()
+    }
+
+    /// Table each event type with a table of its own is kept in
+    fn event_type_tables(&self) -> LaikaResult<HashMap<String, String>> {
+        let mut event_type_tables = HashMap::new();
+        for (name, table) in &self.event_tables {
+            for event_type in &table.event_types {
+                if let Some(existing) =
+                    event_type_tables.insert(event_type.clone(), event_table_name(name))
+                {
+                    return Err(LaikaError::config(
+                        format!("storage.eventTables.{}.eventTypes", name),
+                        format!("{} is already kept in {}", event_type, existing),
+                    ));
+                }
+            }
+        }
+        Ok(event_type_tables)
+    }
+
+    pub fn build(self) -> LaikaResult<StorageKV> {
+        let event_tables = self.event_type_tables()?;
+        let backend: Box<dyn StorageBackend> = match self.engine {
+            #[cfg(feature = "rocksdb")]
+            StorageEngine::RocksDb => Box::new(rocks::RocksDbBackend::open(&self)?),
+            #[cfg(not(feature = "rocksdb"))]
+            StorageEngine::RocksDb => {
+                return Err(LaikaError::config(
+                    "storage.engine",
+                    "Laika was built without the rocksdb feature",
+                ))
+            }
+            #[cfg(feature = "redb")]
+            StorageEngine::Redb => Box::new(redb_store::RedbBackend::open(
+                &self.base_path,
+                self.tables(),
+                self.read_only,
+            )?),
+            #[cfg(not(feature = "redb"))]
+            StorageEngine::Redb => {
+                return Err(LaikaError::config(
+                    "storage.engine",
+                    "Laika was built without the redb feature",
+                ))
+            }
+            #[cfg(feature = "redis")]
+            StorageEngine::Redis => {
+                let url = self.url.as_deref().ok_or_else(|| {
+                    LaikaError::config("storage.url", "The redis engine requires a url")
+                })?;
+                Box::new(redis_store::RedisBackend::open(
+                    url,
+                    self.key_prefix.as_str(),
+                    self.read_only,
+                )?)
+            }
+            #[cfg(not(feature = "redis"))]
+            StorageEngine::Redis => {
+                return Err(LaikaError::config(
+                    "storage.engine",
+                    "Laika was built without the redis feature",
+                ))
+            }
+            StorageEngine::Memory => Box::new(MemoryBackend::new()),
+        };
+        let mut storage = StorageKV::new(backend);
+        storage.audit_log = self.audit_log;
+        storage.event_tables = event_tables;
+        storage.context_limits = self.context_limits;
+        Ok(storage)
+    }
+}
+
+impl StorageKV {
+    pub fn new(backend: Box<dyn StorageBackend>) -> Self {
+        Self {
+            backend,
+            audit_log: false,
+            event_tables: HashMap::new(),
+            context_limits: None,
+        }
+    }
+
+    /// A store kept only in memory
+    pub fn in_memory() -> Self {
+        Self::new(Box::new(MemoryBackend::new()))
+    }
+
+    fn put(&self, table: &str, key: &[u8], value: Vec<u8>) -> LaikaResult<()> {
+        self.backend.write(vec![KeyWrite {
+            table: table.to_string(),
+            key: key.to_vec(),
+            value: Some(value),
+        }])
+    }
+
+    fn delete(&self, table: &str, key: &[u8]) -> LaikaResult<()> {
+        self.backend.write(vec![KeyWrite {
+            table: table.to_string(),
+            key: key.to_vec(),
+            value: None,
+        }])
+    }
+
+    /// Table events of a type are kept in
+    fn event_table(&self, event_type: &str) -> &str {
+        self.event_tables
+            .get(event_type)
+            .map(String::as_str)
+            .unwrap_or(EVENTS)
+    }
+
+    /// Every table events are kept in
+    fn all_event_tables(&self) -> BTreeSet<&str> {
+        std::iter::once(EVENTS)
+            .chain(self.event_tables.values().map(String::as_str))
+            .collect()
+    }
+
+    /// Remove all entries
+    pub fn delete_all_keys(&self) -> LaikaResult<()> {
+        let tables: BTreeSet<&str> = TABLES.into_iter().chain(self.all_event_tables()).collect();
+        for table in tables {
+            let deletes = self
+                .backend
+                .scan(table, &[])?
+                .into_iter()
+                .map(|(key, _)| KeyWrite {
+                    table: table.to_string(),
+                    key,
+                    value: None,
+                })
+                .collect::<Vec<KeyWrite>>();
+            if !deletes.is_empty() {
+                self.backend.write(deletes)?;
+            }
+        }
+        self.backend.compact()?;
+        tracing::debug!("All keys have been removed from KV");
+        Ok(())
+    }
+
+    /// Correlations with stored events
+    pub fn correlation_ids(&self) -> LaikaResult<Vec<CorrelationId>> {
+        self.correlation_ids_with_prefix("", None)
+    }
+
+    /// Correlations with stored events whose ID starts with `prefix`, in order, up to `limit` of
+    /// them. Only as many keys as the limit are read from each event table.
+    pub fn correlation_ids_with_prefix(
+        &self,
+        prefix: &str,
+        limit: Option<usize>,
+    ) -> LaikaResult<Vec<CorrelationId>> {
+        let mut correlation_ids = BTreeSet::new();
+        for table in self.all_event_tables() {
+            for key in self.backend.scan_keys(table, prefix.as_bytes(), limit)? {
+                correlation_ids.insert(String::from_utf8_lossy(&key).to_string());
+            }
+        }
+        Ok(correlation_ids
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    pub fn start_transaction(&self) -> StorageTransaction<'_> {
+        StorageTransaction {
+            backend: self.backend.as_ref(),
+            writes: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Events received for a correlation, oldest first
+    pub fn read_events(
+        &self,
+        txn: &StorageTransaction,
+        correlation_id: &str,
+    ) -> LaikaResult<Vec<CorrelatedEvent>> {
+        let mut events = Vec::new();
+        for table in self.all_event_tables() {
+            if let Some(stored) = txn.get(table, correlation_id.as_bytes())? {
+                let stored: Vec<CorrelatedEvent> =
+                    CorrelatedEventCapnpBatch::from_bytes(stored.as_slice())?.try_into()?;
+                events.extend(stored);
+            }
+        }
+        // Each table is in arrival order, but events split across tables need merging
+        if !self.event_tables.is_empty() {
+            events.sort_by_key(|event| event.received);
+        }
+        Ok(events)
+    }
+
+    /// Add an event to its correlation, returning the correlation's events with the new event
+    /// last. Returns `None` when the event was rejected for taking the correlation over its
+    /// context limits.
+    pub fn write_event(
+        &self,
+        txn: &StorageTransaction,
+        event: CorrelatedEvent,
+    ) -> LaikaResult<Option<Vec<CorrelatedEvent>>> {
+        tracing::debug!("Writing Correlated Event to KV");
+        let correlation_id = event.correlation_id.clone();
+        let table = self.event_table(event.event_type.as_str());
+        let existing_events = txn.get(table, correlation_id.as_bytes())?;
+        let updated_events = match existing_events {
+            Some(existing) => {
+                let mut existing_event_batch: CorrelatedEventCapnpBatch =
+                    CorrelatedEventCapnpBatch::from_bytes(existing.as_slice())?;
+                existing_event_batch.push_event(event)?;
+                existing_event_batch
+            }
+            None => CorrelatedEventCapnpBatch::try_from(vec![event])?,
+        };
+        // Limits apply to the correlation as a whole, so its events in other tables count too
+        let mut batches = vec![(table, updated_events)];
+        if self.context_limits.is_some() {
+            for other in self.all_event_tables() {
+                if other == table {
+                    continue;
+                }
+                if let Some(stored) = txn.get(other, correlation_id.as_bytes())? {
+                    batches.push((
+                        other,
+                        CorrelatedEventCapnpBatch::from_bytes(stored.as_slice())?,
+                    ));
+                }
+            }
+        }
+        let mut trimmed = BTreeSet::new();
+        if let Some(limits) = &self.context_limits {
+            let mut events: usize = batches.iter().map(|(_, batch)| batch.len()).sum();
+            let mut bytes: usize = batches.iter().map(|(_, batch)| batch.size()).sum();
+            while let Some(exceeded) = limits.exceeded(events, bytes) {
+                match limits.policy {
+                    LimitPolicy::DropOldest => {
+                        // The new event is last in the first batch, and always kept so it can
+                        // still trigger rules
+                        let oldest = batches
+                            .iter()
+                            .enumerate()
+                            .filter(|(index, (_, batch))| batch.len() > usize::from(*index == 0))
+                            .min_by_key(|(_, (_, batch))| batch.oldest_received())
+                            .map(|(index, _)| index);
+                        let Some(oldest) = oldest else {
+                            tracing::warn!(
+                                "Keeping event for {} over the context limits on its own: {}",
+                                correlation_id,
+                                exceeded
+                            );
+                            break;
+                        };
+                        bytes -= batches[oldest].1.remove_oldest();
+                        events -= 1;
+                        trimmed.insert(oldest);
+                    }
+                    LimitPolicy::Reject => {
+                        tracing::warn!("Rejected event for {}: {}", correlation_id, exceeded);
+                        return Ok(None);
+                    }
+                    LimitPolicy::DeadLetter => {
+                        return Err(LaikaError::ContextLimitExceeded {
+                            correlation_id,
+                            message: exceeded,
+                        })
+                    }
+                }
+            }
+        }
+        for (index, (table, batch)) in batches.iter().enumerate() {
+            if index > 0 && !trimmed.contains(&index) {
+                continue;
+            }
+            if batch.is_empty() {
+                txn.delete(table, correlation_id.as_bytes());
+            } else {
+                txn.put(table, correlation_id.as_bytes(), batch.to_bytes()?);
+            }
+        }
+        tracing::debug!("Wrote new event to KV");
+        if self.event_tables.is_empty() {
+            let (_, updated_events) = batches.swap_remove(0);
+            Ok(Some(Vec::try_from(updated_events)?))
+        } else {
+            self.read_events(txn, correlation_id.as_str()).map(Some)
+        }
+    }
+
+    /// Correlation that `correlation_id` has been merged into, or itself if it hasn't been
+    pub fn resolve_correlation(
+        &self,
+        txn: &StorageTransaction,
+        correlation_id: &str,
+    ) -> LaikaResult<CorrelationId> {
+        let mut resolved = correlation_id.to_string();
+        // Correlations are only ever merged into ones that haven't been, so links can't loop
+        while let Some(merged_into) = txn.get(CORRELATION_LINKS, resolved.as_bytes())? {
+            resolved = String::from_utf8_lossy(&merged_into).to_string();
+        }
+        Ok(resolved)
+    }
+
+    /// Move an event onto the correlation its ID has been merged into, first merging the
+    /// correlations it links into its own
+    pub fn link_event(
+        &self,
+        txn: &StorageTransaction,
+        mut event: CorrelatedEvent,
+    ) -> LaikaResult<CorrelatedEvent> {
+        event.correlation_id = self.resolve_correlation(txn, event.correlation_id.as_str())?;
+        for linked in std::mem::take(&mut event.links) {
+            let linked = self.resolve_correlation(txn, linked.as_str())?;
+            if linked != event.correlation_id {
+                self.merge_correlation(txn, linked.as_str(), event.correlation_id.as_str())?;
+            }
+        }
+        Ok(event)
+    }
+
+    /// Merge the events and stored values of one correlation into another, so later events for
+    /// either are handled as one. Values already stored against `into` are kept over those of
+    /// `from`, and merged events aren't checked against context limits.
+    fn merge_correlation(
+        &self,
+        txn: &StorageTransaction,
+        from: &str,
+        into: &str,
+    ) -> LaikaResult<()> {
+        tracing::debug!("Merging correlation {} into {}", from, into);
+        for table in self.all_event_tables() {
+            let Some(merged) = txn.get(table, from.as_bytes())? else {
+                continue;
+            };
+            let mut events: Vec<CorrelatedEvent> =
+                CorrelatedEventCapnpBatch::from_bytes(merged.as_slice())?.try_into()?;
+            for event in events.iter_mut() {
+                event.correlation_id = into.to_string();
+            }
+            if let Some(existing) = txn.get(table, into.as_bytes())? {
+                let existing: Vec<CorrelatedEvent> =
+                    CorrelatedEventCapnpBatch::from_bytes(existing.as_slice())?.try_into()?;
+                events.extend(existing);
+            }
+            events.sort_by_key(|event| event.received);
+            txn.put(
+                table,
+                into.as_bytes(),
+                CorrelatedEventCapnpBatch::try_from(events)?.to_bytes()?,
+            );
+            txn.delete(table, from.as_bytes());
+        }
+        let mut values = self.read_stored_values(txn, from)?;
+        if !values.is_empty() {
+            values.extend(self.read_stored_values(txn, into)?);
+            txn.put(
+                STORED_VALUES,
+                into.as_bytes(),
+                serde_json::to_vec(&values).map_err(|e| LaikaError::JsonError(e.to_string()))?,
+            );
+            txn.delete(STORED_VALUES, from.as_bytes());
+        }
+        txn.put(CORRELATION_LINKS, from.as_bytes(), into.as_bytes().to_vec());
+        Ok(())
+    }
+
+    /// Values previously stored against a correlation by rules
+    pub fn read_stored_values(
+        &self,
+        txn: &StorageTransaction,
+        correlation_id: &str,
+    ) -> LaikaResult<serde_json::Map<String, serde_json::Value>> {
+        match txn.get(STORED_VALUES, correlation_id.as_bytes())? {
+            None => Ok(serde_json::Map::new()),
+            Some(values) => serde_json::from_slice(values.as_slice())
+                .map_err(|e| LaikaError::JsonError(e.to_string())),
+        }
+    }
+
+    /// Store a value against a correlation under `key`, replacing any previous value
+    pub fn write_stored_value(
+        &self,
+        txn: &StorageTransaction,
+        correlation_id: &str,
+        key: &str,
+        value: serde_json::Value,
+    ) -> LaikaResult<()> {
+        tracing::debug!("Storing {} for {}", key, correlation_id);
+        let mut values = self.read_stored_values(txn, correlation_id)?;
+        values.insert(key.to_string(), value);
+        txn.put(
+            STORED_VALUES,
+            correlation_id.as_bytes(),
+            serde_json::to_vec(&values).map_err(|e| LaikaError::JsonError(e.to_string()))?,
+        );
+        Ok(())
+    }
+
+    /// Rules that have fired for a correlation, each with when it last fired, what it produced
+    /// and how many times it's fired
+    pub fn read_rule_fires(
+        &self,
+        txn: &StorageTransaction,
+        correlation_id: &str,
+    ) -> LaikaResult<serde_json::Map<String, serde_json::Value>> {
+        match txn.get(RULE_FIRES, correlation_id.as_bytes())? {
+            None => Ok(serde_json::Map::new()),
+            Some(fires) => serde_json::from_slice(fires.as_slice())
+                .map_err(|e| LaikaError::JsonError(e.to_string())),
+        }
+    }
+
+    /// Record a rule firing for a correlation at `fired_at`, producing `result`
+    pub fn record_rule_fire(
+        &self,
+        txn: &StorageTransaction,
+        rule: &str,
+        correlation_id: &str,
+        fired_at: OffsetDateTime,
+        result: &serde_json::Value,
+    ) -> LaikaResult<()> {
+        let mut fires = self.read_rule_fires(txn, correlation_id)?;
+        let count = fires
+            .get(rule)
+            .and_then(|fire| fire["fires"].as_u64())
+            .unwrap_or_default();
+        fires.insert(
+            rule.to_string(),
+            serde_json::json!({
+                "firedAt": fired_at
+                    .format(&Rfc3339)
+                    .map_err(|e| LaikaError::Generic(e.to_string()))?,
+                "fires": count + 1,
+                "result": result,
+            }),
+        );
+        txn.put(
+            RULE_FIRES,
+            correlation_id.as_bytes(),
+            serde_json::to_vec(&fires).map_err(|e| LaikaError::JsonError(e.to_string()))?,
+        );
+        Ok(())
+    }
+
+    pub fn audit_log_enabled(&self) -> bool {
+        self.audit_log
+    }
+
+    // Correlation IDs are length prefixed, so the keys of one never start with another's prefix
+    fn audit_prefix(correlation_id: &str) -> Vec<u8> {
+        let mut prefix = (correlation_id.len() as u64).to_be_bytes().to_vec();
+        prefix.extend_from_slice(correlation_id.as_bytes());
+        prefix
+    }
+
+    /// Record a received event under each correlation it matched
+    pub fn write_audit_record(&self, record: &AuditRecord) -> LaikaResult<()> {
+        let value = serde_json::to_vec(record).map_err(|e| LaikaError::JsonError(e.to_string()))?;
+        // Big-endian with the sign bit flipped, so keys sort by time within a correlation even
+        // before 1970
+        let mut suffix = ((record.received.unix_timestamp_nanos() as u128) ^ (1 << 127))
+            .to_be_bytes()
+            .to_vec();
+        suffix.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+        self.backend.write(
+            record
+                .correlation_ids()
+                .into_iter()
+                .map(|correlation_id| KeyWrite {
+                    table: AUDIT_LOG.to_string(),
+                    key: [Self::audit_prefix(correlation_id), suffix.clone()].concat(),
+                    value: Some(value.clone()),
+                })
+                .collect(),
+        )
+    }
+
+    /// Audit records for a correlation, oldest first. Records for events without a correlation
+    /// are under `audit::UNCORRELATED`.
+    pub fn read_audit_log(&self, correlation_id: &str) -> LaikaResult<Vec<AuditRecord>> {
+        let prefix = Self::audit_prefix(correlation_id);
+        let mut records = Vec::new();
+        for (_, value) in self.backend.scan(AUDIT_LOG, prefix.as_slice())? {
+            let record: AuditRecord =
+                serde_json::from_slice(&value).map_err(|e| LaikaError::JsonError(e.to_string()))?;
+            if record.version > AUDIT_RECORD_VERSION {
+                return Err(LaikaError::StorageError {
+                    source: ErrorSource::message(format!(
+                        "Audit record version {} is newer than this version of Laika",
+                        record.version
+                    )),
+                });
+            }
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    fn window_key(rule: &str, correlation_id: &str) -> String {
+        format!("{}/{}", rule, correlation_id)
+    }
+
+    /// Start time of the open aggregation window for a rule and correlation, if any
+    pub fn read_window(
+        &self,
+        rule: &str,
+        correlation_id: &str,
+    ) -> LaikaResult<Option<OffsetDateTime>> {
+        match self.backend.get(
+            AGGREGATION_WINDOWS,
+            Self::window_key(rule, correlation_id).as_bytes(),
+        )? {
+            None => Ok(None),
+            Some(bytes) => {
+                let nanos: [u8; 16] =
+                    bytes
+                        .as_slice()
+                        .try_into()
+                        .map_err(|_| LaikaError::StorageError {
+                            source: ErrorSource::message(format!(
+                                "Corrupt aggregation window for {}",
+                                Self::window_key(rule, correlation_id)
+                            )),
+                        })?;
+                Ok(Some(
+                    OffsetDateTime::from_unix_timestamp_nanos(i128::from_be_bytes(nanos))
+                        .map_err(LaikaError::storage)?,
+                ))
+            }
+        }
+    }
+
+    pub fn open_window(
+        &self,
+        rule: &str,
+        correlation_id: &str,
+        start: OffsetDateTime,
+    ) -> LaikaResult<()> {
+        tracing::debug!(
+            "Opening aggregation window for {} on {}",
+            rule,
+            correlation_id
+        );
+        self.put(
+            AGGREGATION_WINDOWS,
+            Self::window_key(rule, correlation_id).as_bytes(),
+            start.unix_timestamp_nanos().to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn close_window(&self, rule: &str, correlation_id: &str) -> LaikaResult<()> {
+        tracing::debug!(
+            "Closing aggregation window for {} on {}",
+            rule,
+            correlation_id
+        );
+        self.delete(
+            AGGREGATION_WINDOWS,
+            Self::window_key(rule, correlation_id).as_bytes(),
+        )
+    }
+
+    /// Decision a rule has made for a correlation, if it's made one
+    pub fn read_decision(
+        &self,
+        rule: &str,
+        correlation_id: &str,
+    ) -> LaikaResult<Option<serde_json::Value>> {
+        match self.backend.get(
+            RULE_DECISIONS,
+            Self::window_key(rule, correlation_id).as_bytes(),
+        )? {
+            None => Ok(None),
+            Some(decision) => serde_json::from_slice(decision.as_slice())
+                .map(Some)
+                .map_err(|e| LaikaError::JsonError(e.to_string())),
+        }
+    }
+
+    /// Record a rule's decision for a correlation, replacing any earlier one
+    pub fn write_decision(
+        &self,
+        rule: &str,
+        correlation_id: &str,
+        decision: &serde_json::Value,
+    ) -> LaikaResult<()> {
+        tracing::debug!("Recording decision of {} for {}", rule, correlation_id);
+        self.put(
+            RULE_DECISIONS,
+            Self::window_key(rule, correlation_id).as_bytes(),
+            serde_json::to_vec(decision).map_err(|e| LaikaError::JsonError(e.to_string()))?,
+        )
+    }
+
+    /// Count another recheck of a rule that didn't satisfy it for a correlation, returning how
+    /// many there have been since it was last satisfied
+    pub fn count_recheck(&self, rule: &str, correlation_id: &str) -> LaikaResult<u32> {
+        let key = Self::window_key(rule, correlation_id);
+        let count = match self.backend.get(RECHECK_COUNTS, key.as_bytes())? {
+            None => 0,
+            Some(bytes) => u32::from_be_bytes(bytes.as_slice().try_into().map_err(|_| {
+                LaikaError::StorageError {
+                    source: ErrorSource::message(format!("Corrupt recheck count for {}", key)),
+                }
+            })?),
+        } + 1;
+        self.put(RECHECK_COUNTS, key.as_bytes(), count.to_be_bytes().to_vec())?;
+        Ok(count)
+    }
+
+    /// Start counting a rule's rechecks for a correlation afresh, once it's satisfied
+    pub fn clear_recheck_count(&self, rule: &str, correlation_id: &str) -> LaikaResult<()> {
+        self.delete(
+            RECHECK_COUNTS,
+            Self::window_key(rule, correlation_id).as_bytes(),
+        )
+    }
+
+    /// Log an action a rule has taken for a correlation, identified by `digest`, until `expires`.
+    /// Returns false without logging it when the same action is logged and hasn't expired by
+    /// `now`, as it's a duplicate. Expired actions are dropped from the log as it's written.
+    pub fn log_action(
+        &self,
+        rule: &str,
+        correlation_id: &str,
+        digest: &str,
+        now: OffsetDateTime,
+        expires: OffsetDateTime,
+    ) -> LaikaResult<bool> {
+        let key = Self::window_key(rule, correlation_id);
+        let mut logged: BTreeMap<String, i64> =
+            match self.backend.get(ACTION_LOG, key.as_bytes())? {
+                None => BTreeMap::new(),
+                Some(bytes) => serde_json::from_slice(bytes.as_slice())
+                    .map_err(|e| LaikaError::JsonError(e.to_string()))?,
+            };
+        logged.retain(|_, expires| *expires > now.unix_timestamp());
+        if logged.contains_key(digest) {
+            return Ok(false);
+        }
+        logged.insert(digest.to_string(), expires.unix_timestamp());
+        self.put(
+            ACTION_LOG,
+            key.as_bytes(),
+            serde_json::to_vec(&logged).map_err(|e| LaikaError::JsonError(e.to_string()))?,
+        )?;
+        Ok(true)
+    }
+
+    /// Every value in the global state, including those that have expired
+    pub fn read_global_state(&self) -> LaikaResult<BTreeMap<String, GlobalValue>> {
+        self.backend
+            .scan(GLOBAL_STATE, &[])?
+            .into_iter()
+            .map(|(key, value)| {
+                Ok((
+                    String::from_utf8_lossy(key.as_slice()).into_owned(),
+                    serde_json::from_slice(value.as_slice())
+                        .map_err(|e| LaikaError::JsonError(e.to_string()))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Write changes to the global state at once, removing keys changed to `None`
+    pub fn write_global_state(
+        &self,
+        changes: &BTreeMap<String, Option<GlobalValue>>,
+    ) -> LaikaResult<()> {
+        let writes = changes
+            .iter()
+            .map(|(key, value)| {
+                Ok(KeyWrite {
+                    table: GLOBAL_STATE.to_string(),
+                    key: key.as_bytes().to_vec(),
+                    value: value
+                        .as_ref()
+                        .map(serde_json::to_vec)
+                        .transpose()
+                        .map_err(|e| LaikaError::JsonError(e.to_string()))?,
+                })
+            })
+            .collect::<LaikaResult<Vec<KeyWrite>>>()?;
+        self.backend.write(writes)
+    }
+
+    /// Buffer an event into a window, returning whether this opened the window
+    pub fn append_window_event(
+        &self,
+        rule: &str,
+        group: &str,
+        event: CorrelatedEvent,
+    ) -> LaikaResult<bool> {
+        let key = Self::window_key(rule, group);
+        let (events, opened) = match self.backend.get(WINDOW_EVENTS, key.as_bytes())? {
+            Some(existing) => {
+                let mut batch = CorrelatedEventCapnpBatch::from_bytes(existing.as_slice())?;
+                batch.push_event(event)?;
+                (batch, false)
+            }
+            None => (CorrelatedEventCapnpBatch::try_from(vec![event])?, true),
+        };
+        self.put(WINDOW_EVENTS, key.as_bytes(), events.to_bytes()?)?;
+        Ok(opened)
+    }
+
+    pub fn read_window_events(&self, rule: &str, group: &str) -> LaikaResult<Vec<CorrelatedEvent>> {
+        match self
+            .backend
+            .get(WINDOW_EVENTS, Self::window_key(rule, group).as_bytes())?
+        {
+            None => Ok(Vec::new()),
+            Some(events) => CorrelatedEventCapnpBatch::from_bytes(events.as_slice())?.try_into(),
+        }
+    }
+
+    /// Replace the events buffered in a window
+    pub fn write_window_events(
+        &self,
+        rule: &str,
+        group: &str,
+        events: Vec<CorrelatedEvent>,
+    ) -> LaikaResult<()> {
+        self.put(
+            WINDOW_EVENTS,
+            Self::window_key(rule, group).as_bytes(),
+            CorrelatedEventCapnpBatch::try_from(events)?.to_bytes()?,
+        )
+    }
+
+    pub fn clear_window_events(&self, rule: &str, group: &str) -> LaikaResult<()> {
+        self.delete(WINDOW_EVENTS, Self::window_key(rule, group).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "rocksdb")]
+    #[test]
+    fn test_state_directory_lock() {
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let storage = StorageKVBuilder::new(state_dir.path()).build().unwrap();
+        assert!(matches!(
+            StorageKVBuilder::new(state_dir.path()).build(),
+            Err(LaikaError::StorageError { .. })
+        ));
+        drop(storage);
+        assert!(StorageKVBuilder::new(state_dir.path()).build().is_ok());
+    }
+
+    #[test]
+    fn test_event_tables() {
+        let storage = StorageKVBuilder::new(std::env::temp_dir())
+            .engine(StorageEngine::Memory)
+            .event_table("clicks", EventTableConfig::new(vec!["click".to_string()]))
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        for (seconds, event_type) in ["login", "click", "logout"].into_iter().enumerate() {
+            storage
+                .write_event(
+                    &transaction,
+                    CorrelatedEvent {
+                        received: OffsetDateTime::UNIX_EPOCH
+                            + time::Duration::seconds(seconds as i64),
+                        correlation_id: "user123".to_string(),
+                        event_type: event_type.to_string(),
+                        data: serde_json::json!({}),
+                        meta: Default::default(),
+                        links: Default::default(),
+                    },
+                )
+                .unwrap();
+        }
+        transaction.commit().unwrap();
+
+        let transaction = storage.start_transaction();
+        let event_types: Vec<String> = storage
+            .read_events(&transaction, "user123")
+            .unwrap()
+            .into_iter()
+            .map(|event| event.event_type)
+            .collect();
+        assert_eq!(event_types, vec!["login", "click", "logout"]);
+        assert!(storage
+            .backend
+            .get("events.clicks", b"user123")
+            .unwrap()
+            .is_some());
+        assert_eq!(storage.correlation_ids().unwrap(), vec!["user123"]);
+    }
+
+    #[test]
+    fn test_correlation_ids_are_limited() {
+        let storage = StorageKVBuilder::new(std::env::temp_dir())
+            .engine(StorageEngine::Memory)
+            .event_table("clicks", EventTableConfig::new(vec!["click".to_string()]))
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        for (correlation_id, event_type) in [
+            ("user1", "login"),
+            ("user2", "click"),
+            ("user3", "login"),
+            ("user4", "click"),
+            ("admin1", "login"),
+        ] {
+            let event = CorrelatedEvent {
+                correlation_id: correlation_id.to_string(),
+                event_type: event_type.to_string(),
+                ..login(0)
+            };
+            storage.write_event(&transaction, event).unwrap();
+        }
+        transaction.commit().unwrap();
+
+        // Correlations are listed in order across event tables
+        assert_eq!(
+            storage
+                .correlation_ids_with_prefix("user", Some(3))
+                .unwrap(),
+            vec!["user1", "user2", "user3"]
+        );
+        assert_eq!(
+            storage.correlation_ids_with_prefix("user", None).unwrap(),
+            vec!["user1", "user2", "user3", "user4"]
+        );
+        assert_eq!(
+            storage
+                .backend
+                .scan_keys("events.clicks", b"", Some(1))
+                .unwrap(),
+            vec![b"user2".to_vec()]
+        );
+    }
+
+    fn login(received: i64) -> CorrelatedEvent {
+        CorrelatedEvent {
+            received: OffsetDateTime::from_unix_timestamp(received).unwrap(),
+            correlation_id: "null".to_string(),
+            event_type: "login".to_string(),
+            data: serde_json::json!({}),
+            meta: Default::default(),
+            links: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_context_limits() {
+        let storage = StorageKVBuilder::new(std::env::temp_dir())
+            .engine(StorageEngine::Memory)
+            .context_limits(ContextLimits::new(LimitPolicy::DropOldest).with_max_events(2))
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        for received in 0..3 {
+            storage.write_event(&transaction, login(received)).unwrap();
+        }
+        let received: Vec<i64> = storage
+            .read_events(&transaction, "null")
+            .unwrap()
+            .into_iter()
+            .map(|event| event.received.unix_timestamp())
+            .collect();
+        assert_eq!(received, vec![1, 2]);
+
+        let storage = StorageKVBuilder::new(std::env::temp_dir())
+            .engine(StorageEngine::Memory)
+            .context_limits(ContextLimits::new(LimitPolicy::Reject).with_max_events(2))
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        for received in 0..2 {
+            assert!(storage
+                .write_event(&transaction, login(received))
+                .unwrap()
+                .is_some());
+        }
+        assert_eq!(storage.write_event(&transaction, login(2)).unwrap(), None);
+        assert_eq!(storage.read_events(&transaction, "null").unwrap().len(), 2);
+
+        // An event over the limit on its own is still kept
+        let storage = StorageKVBuilder::new(std::env::temp_dir())
+            .engine(StorageEngine::Memory)
+            .context_limits(ContextLimits::new(LimitPolicy::DropOldest).with_max_bytes(10))
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        for received in 0..2 {
+            storage.write_event(&transaction, login(received)).unwrap();
+        }
+        let events = storage.read_events(&transaction, "null").unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].received.unix_timestamp(), 1);
+    }
+
+    #[test]
+    fn test_context_limits_across_event_tables() {
+        let storage = StorageKVBuilder::new(std::env::temp_dir())
+            .engine(StorageEngine::Memory)
+            .event_table("clicks", EventTableConfig::new(vec!["click".to_string()]))
+            .context_limits(ContextLimits::new(LimitPolicy::DropOldest).with_max_events(2))
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        let click = |received: i64| CorrelatedEvent {
+            event_type: "click".to_string(),
+            ..login(received)
+        };
+        storage.write_event(&transaction, login(0)).unwrap();
+        storage.write_event(&transaction, click(1)).unwrap();
+        // The oldest event is dropped from the other table
+        let events = storage
+            .write_event(&transaction, click(2))
+            .unwrap()
+            .unwrap();
+        let received: Vec<i64> = events
+            .iter()
+            .map(|event| event.received.unix_timestamp())
+            .collect();
+        assert_eq!(received, vec![1, 2]);
+        assert_eq!(storage.read_events(&transaction, "null").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_link_correlations() {
+        let storage = StorageKV::in_memory();
+        let transaction = storage.start_transaction();
+        let event = |received: i64, correlation_id: &str, links: &[&str]| CorrelatedEvent {
+            correlation_id: correlation_id.to_string(),
+            links: links.iter().map(|link| link.to_string()).collect(),
+            ..login(received)
+        };
+        storage
+            .write_event(&transaction, event(0, "payment1", &[]))
+            .unwrap();
+        storage
+            .write_stored_value(
+                &transaction,
+                "payment1",
+                "status",
+                serde_json::json!("settled"),
+            )
+            .unwrap();
+        let linking = storage
+            .link_event(&transaction, event(1, "order1", &["payment1"]))
+            .unwrap();
+        assert_eq!(linking.correlation_id, "order1");
+        storage.write_event(&transaction, linking).unwrap();
+
+        // Later events for the linked correlation join the one it was merged into
+        let linked = storage
+            .link_event(&transaction, event(2, "payment1", &[]))
+            .unwrap();
+        assert_eq!(linked.correlation_id, "order1");
+        storage.write_event(&transaction, linked).unwrap();
+        transaction.commit().unwrap();
+
+        let transaction = storage.start_transaction();
+        let received: Vec<(i64, String)> = storage
+            .read_events(&transaction, "order1")
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.received.unix_timestamp(), event.correlation_id))
+            .collect();
+        assert_eq!(
+            received,
+            vec![
+                (0, "order1".to_string()),
+                (1, "order1".to_string()),
+                (2, "order1".to_string())
+            ]
+        );
+        assert!(storage
+            .read_events(&transaction, "payment1")
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            storage.read_stored_values(&transaction, "order1").unwrap()["status"],
+            "settled"
+        );
+    }
+
+    #[test]
+    fn test_event_type_in_one_table() {
+        let result = StorageKVBuilder::new(std::env::temp_dir())
+            .engine(StorageEngine::Memory)
+            .event_table("a", EventTableConfig::new(vec!["click".to_string()]))
+            .event_table("b", EventTableConfig::new(vec!["click".to_string()]))
+            .build();
+        assert!(matches!(result, Err(LaikaError::ConfigError { .. })));
+    }
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"user1\0"), Some(b"user1\x01".to_vec()));
+        assert_eq!(prefix_end(b"a\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_end(b"\xff"), None);
+        assert_eq!(prefix_end(b""), None);
+    }
+
+    #[test]
+    fn test_transaction_reads_own_writes() {
+        let storage = StorageKV::in_memory();
+        let transaction = storage.start_transaction();
+        storage
+            .write_stored_value(&transaction, "user123", "score", serde_json::json!(5))
+            .unwrap();
+        assert_eq!(
+            storage
+                .read_stored_values(&transaction, "user123")
+                .unwrap()
+                .get("score"),
+            Some(&serde_json::json!(5))
+        );
+        transaction.commit().unwrap();
+
+        let transaction = storage.start_transaction();
+        assert_eq!(
+            storage.read_stored_values(&transaction, "user123").unwrap()["score"],
+            serde_json::json!(5)
+        );
+    }
+
+    #[test]
+    fn test_rule_fires_are_transactional() {
+        let storage = StorageKV::in_memory();
+        let transaction = storage.start_transaction();
+        let result = serde_json::json!({ "plan": "pro" });
+        storage
+            .record_rule_fire(
+                &transaction,
+                "upgraded",
+                "user123",
+                OffsetDateTime::UNIX_EPOCH,
+                &result,
+            )
+            .unwrap();
+        storage
+            .record_rule_fire(
+                &transaction,
+                "upgraded",
+                "user123",
+                OffsetDateTime::UNIX_EPOCH,
+                &result,
+            )
+            .unwrap();
+        assert_eq!(
+            storage.read_rule_fires(&transaction, "user123").unwrap()["upgraded"]["fires"],
+            serde_json::json!(2)
+        );
+        // Dropped rather than committed, as when handling the event fails
+        drop(transaction);
+
+        let transaction = storage.start_transaction();
+        assert!(storage
+            .read_rule_fires(&transaction, "user123")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_audit_log_order() {
+        let storage = StorageKV::in_memory();
+        let record = |received: OffsetDateTime, correlation_id: &str| AuditRecord {
+            version: AUDIT_RECORD_VERSION,
+            received,
+            source: "orders".to_string(),
+            event: serde_json::json!({}),
+            matched: vec![crate::audit::MatchedEvent {
+                event_type: "order".to_string(),
+                correlation_id: Some(correlation_id.to_string()),
+            }],
+            actions: Vec::new(),
+        };
+        let before_epoch = OffsetDateTime::UNIX_EPOCH - time::Duration::seconds(1);
+        let after_epoch = OffsetDateTime::UNIX_EPOCH + time::Duration::seconds(1);
+        for received in [after_epoch, OffsetDateTime::UNIX_EPOCH, before_epoch] {
+            storage.write_audit_record(&record(received, "a")).unwrap();
+        }
+        // Starts with the prefix of "a" if IDs are only NUL terminated
+        storage
+            .write_audit_record(&record(after_epoch, "a\0b"))
+            .unwrap();
+
+        let received: Vec<OffsetDateTime> = storage
+            .read_audit_log("a")
+            .unwrap()
+            .into_iter()
+            .map(|record| record.received)
+            .collect();
+        assert_eq!(
+            received,
+            vec![before_epoch, OffsetDateTime::UNIX_EPOCH, after_epoch]
+        );
+        assert_eq!(storage.read_audit_log("a\0b").unwrap().len(), 1);
+    }
+}
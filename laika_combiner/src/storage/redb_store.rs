@@ -0,0 +1,216 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::storage::{lock_state_dir, KeyWrite, StorageBackend};
+use redb::{Database, DatabaseError, ReadOnlyTable, ReadableTable, TableDefinition, TableError};
+use std::fs::File;
+use std::path::Path;
+
+type Definition<'a> = TableDefinition<'a, &'static [u8], &'static [u8]>;
+
+fn table(name: &str) -> Definition<'_> {
+    TableDefinition::new(name)
+}
+
+/// Keeps state in a single redb file, with a redb table per table
+pub struct RedbBackend {
+    db: Database,
+    read_only: bool,
+    /// Lock on the state directory, released when the store is dropped
+    _lock: Option<File>,
+}
+
+impl RedbBackend {
+    pub(crate) fn open(
+        base_path: &Path,
+        tables: Vec<String>,
+        read_only: bool,
+    ) -> LaikaResult<Self> {
+        let path = base_path.join("laika.redb");
+        if read_only {
+            // redb holds an exclusive lock on its file, so there's no reading it alongside a
+            // running instance
+            let db = Database::open(&path).map_err(|e| match e {
+                DatabaseError::DatabaseAlreadyOpen => LaikaError::Generic(format!(
+                    "{} is open in another process, and redb stores can't be read while they're \
+                     in use. Stop Laika to read its state.",
+                    path.display()
+                )),
+                e => LaikaError::storage(e),
+            })?;
+            return Ok(Self {
+                db,
+                read_only,
+                _lock: None,
+            });
+        }
+        let lock = lock_state_dir(base_path)?;
+        let db = Database::create(path).map_err(LaikaError::storage)?;
+        // Tables are created up front, as reading from a missing table fails
+        let txn = db.begin_write().map_err(LaikaError::storage)?;
+        for name in &tables {
+            txn.open_table(table(name)).map_err(LaikaError::storage)?;
+        }
+        txn.commit().map_err(LaikaError::storage)?;
+        Ok(Self {
+            db,
+            read_only,
+            _lock: Some(lock),
+        })
+    }
+
+    /// A table to read, or `None` if it's never been written to, as when a store opened to read
+    /// was last written before the table was configured
+    fn read_table(
+        &self,
+        name: &str,
+    ) -> LaikaResult<Option<ReadOnlyTable<&'static [u8], &'static [u8]>>> {
+        let txn = self.db.begin_read().map_err(LaikaError::storage)?;
+        match txn.open_table(table(name)) {
+            Ok(entries) => Ok(Some(entries)),
+            Err(TableError::TableDoesNotExist(_)) => Ok(None),
+            Err(e) => Err(LaikaError::storage(e)),
+        }
+    }
+}
+
+impl StorageBackend for RedbBackend {
+    fn get(&self, table_name: &str, key: &[u8]) -> LaikaResult<Option<Vec<u8>>> {
+        let Some(entries) = self.read_table(table_name)? else {
+            return Ok(None);
+        };
+        Ok(entries
+            .get(key)
+            .map_err(LaikaError::storage)?
+            .map(|value| value.value().to_vec()))
+    }
+
+    fn write(&self, writes: Vec<KeyWrite>) -> LaikaResult<()> {
+        if self.read_only {
+            return Err(LaikaError::Generic(
+                "Can't write to a store opened read-only".to_string(),
+            ));
+        }
+        let txn = self.db.begin_write().map_err(LaikaError::storage)?;
+        for write in writes {
+            let mut entries = txn
+                .open_table(table(&write.table))
+                .map_err(LaikaError::storage)?;
+            match write.value {
+                Some(value) => {
+                    entries
+                        .insert(write.key.as_slice(), value.as_slice())
+                        .map_err(LaikaError::storage)?;
+                }
+                None => {
+                    entries
+                        .remove(write.key.as_slice())
+                        .map_err(LaikaError::storage)?;
+                }
+            }
+        }
+        txn.commit().map_err(LaikaError::storage)
+    }
+
+    fn scan(&self, table_name: &str, prefix: &[u8]) -> LaikaResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let Some(entries) = self.read_table(table_name)? else {
+            return Ok(Vec::new());
+        };
+        let mut scanned = Vec::new();
+        for entry in entries.range(prefix..).map_err(LaikaError::storage)? {
+            let (key, value) = entry.map_err(LaikaError::storage)?;
+            if !key.value().starts_with(prefix) {
+                break;
+            }
+            scanned.push((key.value().to_vec(), value.value().to_vec()));
+        }
+        Ok(scanned)
+    }
+
+    fn scan_keys(
+        &self,
+        table_name: &str,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> LaikaResult<Vec<Vec<u8>>> {
+        let Some(entries) = self.read_table(table_name)? else {
+            return Ok(Vec::new());
+        };
+        let mut keys = Vec::new();
+        for entry in entries
+            .range(prefix..)
+            .map_err(LaikaError::storage)?
+            .take(limit.unwrap_or(usize::MAX))
+        {
+            let (key, _) = entry.map_err(LaikaError::storage)?;
+            if !key.value().starts_with(prefix) {
+                break;
+            }
+            keys.push(key.value().to_vec());
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::LaikaError;
+    use crate::storage::{StorageEngine, StorageKVBuilder};
+
+    #[test]
+    fn test_redb_reopen() {
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let storage = StorageKVBuilder::new(state_dir.path())
+            .engine(StorageEngine::Redb)
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        storage
+            .write_stored_value(&transaction, "user123", "score", serde_json::json!(5))
+            .unwrap();
+        transaction.commit().unwrap();
+        drop(storage);
+
+        let storage = StorageKVBuilder::new(state_dir.path())
+            .engine(StorageEngine::Redb)
+            .build()
+            .unwrap();
+        let transaction = storage.start_transaction();
+        assert_eq!(
+            storage.read_stored_values(&transaction, "user123").unwrap()["score"],
+            serde_json::json!(5)
+        );
+    }
+
+    #[test]
+    fn test_redb_read_only() {
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let open = |read_only| {
+            StorageKVBuilder::new(state_dir.path())
+                .engine(StorageEngine::Redb)
+                .read_only(read_only)
+                .build()
+        };
+        let storage = open(false).unwrap();
+        let transaction = storage.start_transaction();
+        storage
+            .write_stored_value(&transaction, "user123", "score", serde_json::json!(5))
+            .unwrap();
+        transaction.commit().unwrap();
+        // redb keeps its file locked while it's open, which is explained rather than passed on
+        assert!(matches!(
+            open(true),
+            Err(LaikaError::Generic(message)) if message.contains("Stop Laika")
+        ));
+        drop(storage);
+
+        let storage = open(true).unwrap();
+        let transaction = storage.start_transaction();
+        assert_eq!(
+            storage.read_stored_values(&transaction, "user123").unwrap()["score"],
+            serde_json::json!(5)
+        );
+        storage
+            .write_stored_value(&transaction, "user123", "score", serde_json::json!(6))
+            .unwrap();
+        assert!(transaction.commit().is_err());
+    }
+}
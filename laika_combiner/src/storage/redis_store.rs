@@ -0,0 +1,427 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::storage::{prefix_end, KeyWrite, StorageBackend};
+use redis::{ConnectionLike, RedisResult};
+use std::sync::{Mutex, PoisonError};
+
+type Connection = Box<dyn ConnectionLike + Send>;
+
+/// Opens a connection to the server, and again whenever the last one has dropped
+type Connect = Box<dyn Fn() -> RedisResult<Connection> + Send + Sync>;
+
+/// Keeps state in Redis, so several instances can share it. Each table is a hash of keys to
+/// values, alongside a sorted set of its keys so they can be scanned in order.
+///
+/// Opening the store to write claims it, and each write checks the claim is still held, so an
+/// instance that's been taken over from fails instead of overwriting the new writer's state.
+/// Dropped connections are reopened on the next command.
+pub struct RedisBackend {
+    connect: Connect,
+    connection: Mutex<Option<Connection>>,
+    key_prefix: String,
+    /// Token the store is claimed with, or `None` when it's only read
+    writer: Option<String>,
+}
+
+impl RedisBackend {
+    pub(crate) fn open(url: &str, key_prefix: &str, read_only: bool) -> LaikaResult<Self> {
+        let client = redis::Client::open(url).map_err(LaikaError::storage)?;
+        Self::with_connect(
+            Box::new(move || {
+                client
+                    .get_connection()
+                    .map(|connection| Box::new(connection) as Connection)
+            }),
+            key_prefix,
+            (!read_only).then(|| uuid::Uuid::new_v4().to_string()),
+        )
+    }
+
+    /// Claim the store with `writer`, unless it's only read
+    fn with_connect(
+        connect: Connect,
+        key_prefix: &str,
+        writer: Option<String>,
+    ) -> LaikaResult<Self> {
+        let backend = Self {
+            connect,
+            connection: Mutex::new(None),
+            key_prefix: key_prefix.to_string(),
+            writer,
+        };
+        if let Some(writer) = &backend.writer {
+            let writer_key = backend.writer_key();
+            backend.query(|connection| {
+                redis::cmd("SET")
+                    .arg(&writer_key)
+                    .arg(writer)
+                    .query::<()>(connection)
+            })?;
+        }
+        Ok(backend)
+    }
+
+    fn values_key(&self, table: &str) -> String {
+        format!("{}:{}", self.key_prefix, table)
+    }
+
+    fn keys_key(&self, table: &str) -> String {
+        format!("{}:{}:keys", self.key_prefix, table)
+    }
+
+    fn writer_key(&self) -> String {
+        format!("{}:writer", self.key_prefix)
+    }
+
+    /// Run `command`, reconnecting and running it again if the connection has dropped. Writes
+    /// only ever put whole values, so running one again after it was applied changes nothing.
+    fn query<T>(
+        &self,
+        command: impl Fn(&mut dyn ConnectionLike) -> RedisResult<T>,
+    ) -> LaikaResult<T> {
+        // A panic mid-command leaves nothing half-written, as writes are sent as one transaction
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        loop {
+            let fresh = connection.is_none();
+            if fresh {
+                *connection = Some((self.connect)().map_err(LaikaError::storage)?);
+            }
+            let open = connection.as_mut().expect("Connection was opened above");
+            match command(open.as_mut()) {
+                Err(e) if e.is_unrecoverable_error() => {
+                    *connection = None;
+                    if fresh {
+                        return Err(LaikaError::storage(e));
+                    }
+                    tracing::warn!("Reconnecting to Redis after {}", e);
+                }
+                result => return result.map_err(LaikaError::storage),
+            }
+        }
+    }
+
+    fn pipeline(&self, writes: &[KeyWrite]) -> redis::Pipeline {
+        let mut pipeline = redis::pipe();
+        pipeline.atomic();
+        for write in writes {
+            let values_key = self.values_key(&write.table);
+            let keys_key = self.keys_key(&write.table);
+            match &write.value {
+                Some(value) => {
+                    pipeline
+                        .cmd("HSET")
+                        .arg(&values_key)
+                        .arg(write.key.as_slice())
+                        .arg(value.as_slice())
+                        .ignore();
+                    pipeline
+                        .cmd("ZADD")
+                        .arg(&keys_key)
+                        .arg(0)
+                        .arg(write.key.as_slice())
+                        .ignore();
+                }
+                None => {
+                    pipeline
+                        .cmd("HDEL")
+                        .arg(&values_key)
+                        .arg(write.key.as_slice())
+                        .ignore();
+                    pipeline
+                        .cmd("ZREM")
+                        .arg(&keys_key)
+                        .arg(write.key.as_slice())
+                        .ignore();
+                }
+            }
+        }
+        pipeline
+    }
+
+    fn taken_over(&self) -> LaikaError {
+        LaikaError::StoreTakenOver {
+            store: format!("Redis keys under {}", self.key_prefix),
+        }
+    }
+}
+
+impl StorageBackend for RedisBackend {
+    fn get(&self, table: &str, key: &[u8]) -> LaikaResult<Option<Vec<u8>>> {
+        let values_key = self.values_key(table);
+        self.query(|connection| {
+            redis::cmd("HGET")
+                .arg(&values_key)
+                .arg(key)
+                .query(connection)
+        })
+    }
+
+    fn write(&self, writes: Vec<KeyWrite>) -> LaikaResult<()> {
+        let Some(writer) = &self.writer else {
+            return Err(LaikaError::Generic(
+                "Can't write to a store opened read-only".to_string(),
+            ));
+        };
+        let writer_key = self.writer_key();
+        let pipeline = self.pipeline(&writes);
+        // Watching the claim aborts the transaction if another instance claims the store between
+        // checking it and writing
+        let written = self.query(|connection| {
+            redis::cmd("WATCH")
+                .arg(&writer_key)
+                .query::<()>(connection)?;
+            let claimed_by: Option<String> =
+                redis::cmd("GET").arg(&writer_key).query(connection)?;
+            if claimed_by.as_ref() != Some(writer) {
+                redis::cmd("UNWATCH").query::<()>(connection)?;
+                return Ok(None);
+            }
+            pipeline.query::<Option<()>>(connection)
+        })?;
+        written.ok_or_else(|| self.taken_over())
+    }
+
+    fn scan(&self, table: &str, prefix: &[u8]) -> LaikaResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let keys_key = self.keys_key(table);
+        let values_key = self.values_key(table);
+        let (min, max) = lex_range(prefix);
+        self.query(|connection| {
+            let keys: Vec<Vec<u8>> = redis::cmd("ZRANGEBYLEX")
+                .arg(&keys_key)
+                .arg(&min)
+                .arg(&max)
+                .query(connection)?;
+            if keys.is_empty() {
+                return Ok(Vec::new());
+            }
+            let values: Vec<Option<Vec<u8>>> = redis::cmd("HMGET")
+                .arg(&values_key)
+                .arg(&keys)
+                .query(connection)?;
+            Ok(keys
+                .into_iter()
+                .zip(values)
+                .filter_map(|(key, value)| value.map(|value| (key, value)))
+                .collect())
+        })
+    }
+
+    fn scan_keys(
+        &self,
+        table: &str,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> LaikaResult<Vec<Vec<u8>>> {
+        let keys_key = self.keys_key(table);
+        let (min, max) = lex_range(prefix);
+        self.query(|connection| {
+            let mut command = redis::cmd("ZRANGEBYLEX");
+            command.arg(&keys_key).arg(&min).arg(&max);
+            if let Some(limit) = limit {
+                command.arg("LIMIT").arg(0).arg(limit);
+            }
+            command.query(connection)
+        })
+    }
+}
+
+/// Bounds of `ZRANGEBYLEX` covering every key starting with `prefix`
+fn lex_range(prefix: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let min = [b"[".as_slice(), prefix].concat();
+    let max = match prefix_end(prefix) {
+        Some(end) => [b"(".as_slice(), end.as_slice()].concat(),
+        None => b"+".to_vec(),
+    };
+    (min, max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::{RedisError, Value};
+    use redis_test::{MockCmd, MockRedisConnection};
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+
+    /// Opens `connections` in turn, then fails as though the server can't be reached
+    fn connect(connections: Vec<MockRedisConnection>) -> Connect {
+        let connections = Arc::new(Mutex::new(VecDeque::from(connections)));
+        Box::new(move || {
+            let connection = connections.lock().unwrap().pop_front().ok_or_else(|| {
+                RedisError::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            })?;
+            Ok(Box::new(connection) as Connection)
+        })
+    }
+
+    fn dropped() -> Result<Value, RedisError> {
+        Err(RedisError::from(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset,
+        )))
+    }
+
+    fn hget(key: &str) -> redis::Cmd {
+        redis::cmd("HGET").arg("laika:events").arg(key).clone()
+    }
+
+    fn claim(writer: &str) -> Vec<MockCmd> {
+        vec![
+            MockCmd::new(redis::cmd("WATCH").arg("laika:writer"), Ok(Value::Okay)),
+            MockCmd::new(redis::cmd("GET").arg("laika:writer"), Ok(writer)),
+        ]
+    }
+
+    fn writes() -> Vec<KeyWrite> {
+        vec![KeyWrite {
+            table: "events".to_string(),
+            key: b"user1".to_vec(),
+            value: Some(b"events".to_vec()),
+        }]
+    }
+
+    #[test]
+    fn test_reconnects_after_dropped_connection() {
+        let backend = RedisBackend::with_connect(
+            connect(vec![
+                MockRedisConnection::new(vec![
+                    MockCmd::new(hget("user1"), Ok("first")),
+                    MockCmd::new(hget("user2"), dropped()),
+                ]),
+                MockRedisConnection::new(vec![
+                    MockCmd::new(hget("user2"), Ok("second")),
+                    MockCmd::new(hget("user3"), dropped()),
+                ]),
+            ]),
+            "laika",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            backend.get("events", b"user1").unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert_eq!(
+            backend.get("events", b"user2").unwrap(),
+            Some(b"second".to_vec())
+        );
+        // Reconnecting is only tried once
+        assert!(matches!(
+            backend.get("events", b"user3"),
+            Err(LaikaError::StorageError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_writes_check_claim() {
+        let mut commands = vec![MockCmd::new(
+            redis::cmd("SET").arg("laika:writer").arg("writer1"),
+            Ok(Value::Okay),
+        )];
+        let pipeline = RedisBackend::with_connect(connect(Vec::new()), "laika", None)
+            .unwrap()
+            .pipeline(&writes());
+        commands.extend(claim("writer1"));
+        commands.push(MockCmd::with_values(
+            &pipeline,
+            Ok(vec![Value::Array(vec![Value::Int(1), Value::Int(1)])]),
+        ));
+        // Another instance claims the store between checking the claim and writing
+        commands.extend(claim("writer1"));
+        commands.push(MockCmd::with_values(&pipeline, Ok(vec![Value::Nil])));
+        // Another instance has claimed the store
+        commands.extend(claim("writer2"));
+        commands.push(MockCmd::new(redis::cmd("UNWATCH"), Ok(Value::Okay)));
+
+        let backend = RedisBackend::with_connect(
+            connect(vec![MockRedisConnection::new(commands)]),
+            "laika",
+            Some("writer1".to_string()),
+        )
+        .unwrap();
+        backend.write(writes()).unwrap();
+        for _ in 0..2 {
+            assert!(matches!(
+                backend.write(writes()),
+                Err(LaikaError::StoreTakenOver { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_read_only_store_isnt_claimed() {
+        // Opening doesn't connect, as there's no claim to make
+        let backend = RedisBackend::with_connect(connect(Vec::new()), "laika", None).unwrap();
+        assert!(matches!(
+            backend.write(writes()),
+            Err(LaikaError::Generic(_))
+        ));
+    }
+
+    #[test]
+    fn test_scan() {
+        assert_eq!(
+            lex_range(b"user1\0"),
+            (b"[user1\0".to_vec(), b"(user1\x01".to_vec())
+        );
+        assert_eq!(lex_range(b""), (b"[".to_vec(), b"+".to_vec()));
+
+        let (min, max) = lex_range(b"user");
+        let backend = RedisBackend::with_connect(
+            connect(vec![MockRedisConnection::new(vec![
+                MockCmd::new(
+                    redis::cmd("ZRANGEBYLEX")
+                        .arg("laika:events:keys")
+                        .arg(&min)
+                        .arg(&max),
+                    Ok(Value::Array(vec![
+                        Value::BulkString(b"user1".to_vec()),
+                        Value::BulkString(b"user2".to_vec()),
+                    ])),
+                ),
+                MockCmd::new(
+                    redis::cmd("HMGET")
+                        .arg("laika:events")
+                        .arg(vec![b"user1".to_vec(), b"user2".to_vec()]),
+                    Ok(Value::Array(vec![
+                        Value::BulkString(b"a".to_vec()),
+                        // Deleted since its key was read
+                        Value::Nil,
+                    ])),
+                ),
+            ])]),
+            "laika",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            backend.scan("events", b"user").unwrap(),
+            vec![(b"user1".to_vec(), b"a".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_scan_keys_is_limited() {
+        let (min, max) = lex_range(b"user");
+        let backend = RedisBackend::with_connect(
+            connect(vec![MockRedisConnection::new(vec![MockCmd::new(
+                redis::cmd("ZRANGEBYLEX")
+                    .arg("laika:events:keys")
+                    .arg(&min)
+                    .arg(&max)
+                    .arg("LIMIT")
+                    .arg(0)
+                    .arg(1),
+                Ok(Value::Array(vec![Value::BulkString(b"user1".to_vec())])),
+            )])]),
+            "laika",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            backend.scan_keys("events", b"user", Some(1)).unwrap(),
+            vec![b"user1".to_vec()]
+        );
+    }
+}
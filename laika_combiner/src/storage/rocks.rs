@@ -0,0 +1,192 @@
+use crate::config::builder::invalid_at;
+use crate::errors::LaikaResult;
+use crate::storage::{
+    event_table_name, lock_state_dir, prefix_end, CompactionConfig, CompactionStyle, KeyWrite,
+    StorageBackend, StorageKVBuilder, TABLES,
+};
+use crate::utils::parse_time::parse_time_str;
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, DBCompactionStyle, DBIteratorWithThreadMode, Direction,
+    IteratorMode, Options, ReadOptions, WriteBatch, DB, DEFAULT_COLUMN_FAMILY_NAME,
+};
+use std::fs::File;
+
+/// Keeps state in RocksDB, with a column family per table, each with its own compaction
+pub struct RocksDbBackend {
+    db: DB,
+    tables: Vec<String>,
+    /// Lock on the state directory, released when the store is dropped
+    _lock: Option<File>,
+}
+
+impl RocksDbBackend {
+    pub(crate) fn open(builder: &StorageKVBuilder) -> LaikaResult<Self> {
+        // Read-only handles don't write, so can share the directory with the process that does
+        let lock = if builder.read_only {
+            None
+        } else {
+            Some(lock_state_dir(&builder.base_path)?)
+        };
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        opts.set_max_total_wal_size(
+            builder
+                .max_total_wal_size
+                .unwrap_or(10 * 1024 * 1024 * 1024),
+        );
+        opts.set_max_background_jobs(builder.max_background_jobs.unwrap_or(4) as i32);
+        opts.increase_parallelism(builder.parallelism.unwrap_or(4) as i32);
+        Self::set_compaction(&mut opts, &builder.compaction, "storage.compaction")?;
+
+        let mut column_families: Vec<(String, Options)> = TABLES
+            .iter()
+            .map(|table| (Self::column_family_name(table).to_string(), opts.clone()))
+            .collect();
+        for (name, table) in &builder.event_tables {
+            let path = format!("storage.eventTables.{}", name);
+            let mut table_opts = opts.clone();
+            Self::set_compaction(
+                &mut table_opts,
+                &table.compaction,
+                format!("{}.compaction", path).as_str(),
+            )?;
+            if let Some(ttl) = &table.ttl {
+                let ttl = parse_time_str(ttl.as_str())
+                    .map_err(invalid_at(format!("{}.ttl", path).as_str()))?;
+                table_opts.set_ttl(ttl.whole_seconds().unsigned_abs());
+            }
+            column_families.push((event_table_name(name), table_opts));
+        }
+
+        let path = builder.base_path.join("events_by_correlation_id");
+        // Every existing column family has to be opened, including those of event tables that
+        // are no longer configured
+        for name in DB::list_cf(&opts, &path).unwrap_or_default() {
+            if !column_families
+                .iter()
+                .any(|(configured, _)| configured == &name)
+            {
+                column_families.push((name, opts.clone()));
+            }
+        }
+        let column_families = column_families
+            .into_iter()
+            .map(|(name, cf_opts)| ColumnFamilyDescriptor::new(name, cf_opts));
+        let db = if builder.read_only {
+            DB::open_cf_descriptors_read_only(&opts, path, column_families, false)?
+        } else {
+            DB::open_cf_descriptors(&opts, path, column_families)?
+        };
+        Ok(Self {
+            db,
+            tables: builder.tables(),
+            _lock: lock,
+        })
+    }
+
+    fn set_compaction(
+        opts: &mut Options,
+        compaction: &CompactionConfig,
+        path: &str,
+    ) -> LaikaResult<()> {
+        opts.set_compaction_style(match compaction.style {
+            CompactionStyle::Level => DBCompactionStyle::Level,
+            CompactionStyle::Universal => DBCompactionStyle::Universal,
+            CompactionStyle::Fifo => DBCompactionStyle::Fifo,
+        });
+        if let Some(periodic) = &compaction.periodic {
+            let periodic = parse_time_str(periodic.as_str())
+                .map_err(invalid_at(format!("{}.periodic", path).as_str()))?;
+            opts.set_periodic_compaction_seconds(periodic.whole_seconds().unsigned_abs());
+        }
+        Ok(())
+    }
+
+    /// Events have always been kept in the default column family, so stores written before
+    /// tables existed can still be read
+    fn column_family_name(table: &str) -> &str {
+        match table {
+            crate::storage::EVENTS => DEFAULT_COLUMN_FAMILY_NAME,
+            table => table,
+        }
+    }
+
+    fn column_family(&self, table: &str) -> &ColumnFamily {
+        self.db
+            .cf_handle(Self::column_family_name(table))
+            .expect("All column families are created on open")
+    }
+
+    /// Entries of a table from `prefix` onwards, stopping at the end of the prefix where it has one
+    fn prefix_iterator(&self, table: &str, prefix: &[u8]) -> DBIteratorWithThreadMode<'_, DB> {
+        // Bounding the iterator lets RocksDB skip files and tombstones past the prefix
+        let mut read_opts = ReadOptions::default();
+        if let Some(end) = prefix_end(prefix) {
+            read_opts.set_iterate_upper_bound(end);
+        }
+        self.db.iterator_cf_opt(
+            self.column_family(table),
+            read_opts,
+            IteratorMode::From(prefix, Direction::Forward),
+        )
+    }
+}
+
+impl StorageBackend for RocksDbBackend {
+    fn get(&self, table: &str, key: &[u8]) -> LaikaResult<Option<Vec<u8>>> {
+        Ok(self.db.get_cf(self.column_family(table), key)?)
+    }
+
+    fn write(&self, writes: Vec<KeyWrite>) -> LaikaResult<()> {
+        let mut batch = WriteBatch::default();
+        for write in writes {
+            let cf = self.column_family(&write.table);
+            match write.value {
+                Some(value) => batch.put_cf(cf, write.key, value),
+                None => batch.delete_cf(cf, write.key),
+            }
+        }
+        Ok(self.db.write(batch)?)
+    }
+
+    fn scan(&self, table: &str, prefix: &[u8]) -> LaikaResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut entries = Vec::new();
+        for item in self.prefix_iterator(table, prefix) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    fn scan_keys(
+        &self,
+        table: &str,
+        prefix: &[u8],
+        limit: Option<usize>,
+    ) -> LaikaResult<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        for item in self
+            .prefix_iterator(table, prefix)
+            .take(limit.unwrap_or(usize::MAX))
+        {
+            let (key, _) = item?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            keys.push(key.to_vec());
+        }
+        Ok(keys)
+    }
+
+    fn compact(&self) -> LaikaResult<()> {
+        for table in &self.tables {
+            self.db
+                .compact_range_cf(self.column_family(table), None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(self.db.flush()?)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::event::EventMetadata;
+use serde::Deserialize;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Where spans are exported, beyond local logs
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub otlp: Option<OtlpConfig>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// Collector to export spans to over OTLP/HTTP, such as `http://localhost:4318/v1/traces`
+    endpoint: String,
+    /// Reported as the `service.name` of every span
+    #[serde(default = "OtlpConfig::default_service_name")]
+    service_name: String,
+}
+
+impl OtlpConfig {
+    fn default_service_name() -> String {
+        "laika".to_string()
+    }
+}
+
+/// Layer exporting spans at `INFO` and above to the configured collector, regardless of the
+/// level local logs are filtered to. Upstream trace context is read from the `traceparent` header
+/// of received messages.
+#[cfg(feature = "otlp")]
+pub fn otlp_layer<S>(config: &OtlpConfig) -> LaikaResult<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::{runtime, trace::TracerProvider, Resource};
+    use tracing_subscriber::filter::LevelFilter;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(config.endpoint.as_str())
+        .build()
+        .map_err(|e| LaikaError::config("telemetry.otlp", e.to_string()))?;
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]))
+        .build();
+    let tracer = provider.tracer("laika");
+    opentelemetry::global::set_tracer_provider(provider);
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(tracing_opentelemetry::layer()
+        .with_tracer(tracer)
+        .with_filter(LevelFilter::INFO)
+        .boxed())
+}
+
+#[cfg(not(feature = "otlp"))]
+pub fn otlp_layer<S>(_config: &OtlpConfig) -> LaikaResult<Box<dyn Layer<S> + Send + Sync>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    Err(LaikaError::config(
+        "telemetry.otlp",
+        "Laika was built without the otlp feature",
+    ))
+}
+
+/// Export any spans still buffered, before exiting
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Continue the trace a received message was sent in, when its headers carry one
+pub(crate) fn follow_upstream(span: &tracing::Span, metadata: &EventMetadata) {
+    #[cfg(feature = "otlp")]
+    {
+        use opentelemetry::propagation::Extractor;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        struct Headers<'a>(&'a serde_json::Map<String, serde_json::Value>);
+
+        impl Extractor for Headers<'_> {
+            // Brokers differ in the case they keep header names in
+            fn get(&self, key: &str) -> Option<&str> {
+                self.0
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                    .and_then(|(_, value)| value.as_str())
+            }
+
+            fn keys(&self) -> Vec<&str> {
+                self.0.keys().map(String::as_str).collect()
+            }
+        }
+
+        let Some(serde_json::Value::Object(headers)) = metadata.get("header") else {
+            return;
+        };
+        let upstream = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&Headers(headers))
+        });
+        span.set_parent(upstream);
+    }
+    #[cfg(not(feature = "otlp"))]
+    let _ = (span, metadata);
+}
+
+#[cfg(all(test, feature = "otlp"))]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_follows_upstream_traceparent() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = opentelemetry_sdk::trace::TracerProvider::default().tracer("test");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        let metadata: EventMetadata = serde_json::json!({
+            "header": {
+                "TraceParent": "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+            }
+        })
+        .as_object()
+        .cloned()
+        .unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("message");
+            follow_upstream(&span, &metadata);
+            assert_eq!(
+                span.context().span().span_context().trace_id().to_string(),
+                "4bf92f3577b34da6a3ce929d0e0e4736"
+            );
+        });
+    }
+}
@@ -18,6 +18,10 @@ pub enum TemplateError {
     UnexpectedToken(usize),
     #[error("Template not closed properly - expected a }} at position {0}")]
     UnclosedTemplate(usize),
+    #[error("Block opened at position {0} is never closed")]
+    UnclosedBlock(usize),
+    #[error("No template filter named {0}")]
+    UnknownFilter(String),
     #[error("Could not render template from JSON due to {0}")]
     RenderError(String),
 }
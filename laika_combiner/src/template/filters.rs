@@ -0,0 +1,245 @@
+use crate::template::error::TemplateError;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// A filter given the value being rendered and the filter's arguments
+type Filter = dyn Fn(&Value, &[Value]) -> Result<Value, String> + Send + Sync;
+
+const BUILT_IN: [&str; 8] = [
+    "upper", "lower", "trim", "round", "date", "default", "length", "join",
+];
+
+static CUSTOM_FILTERS: OnceLock<RwLock<HashMap<String, Arc<Filter>>>> = OnceLock::new();
+
+fn custom_filters() -> &'static RwLock<HashMap<String, Arc<Filter>>> {
+    CUSTOM_FILTERS.get_or_init(Default::default)
+}
+
+/// Register a filter for every template to use as `${{ field | name(args) }}`, replacing any
+/// built-in filter of the same name.
+///
+/// Templates are checked for unknown filters as the config is read, so filters need to be
+/// registered before then.
+pub fn register_filter<F>(name: &str, filter: F)
+where
+    F: Fn(&Value, &[Value]) -> Result<Value, String> + Send + Sync + 'static,
+{
+    custom_filters()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(name.to_string(), Arc::new(filter));
+}
+
+/// A filter applied to a template's value, e.g. `round(2)` in `${{ amount | round(2) }}`
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub(crate) struct FilterCall {
+    name: String,
+    args: Vec<Value>,
+}
+
+impl FilterCall {
+    pub(crate) fn new(name: &str, args: Vec<Value>) -> Result<Self, TemplateError> {
+        let registered = custom_filters()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .contains_key(name);
+        if !registered && !BUILT_IN.contains(&name) {
+            return Err(TemplateError::UnknownFilter(name.to_string()));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            args,
+        })
+    }
+
+    /// Whether the filter gives a value for missing fields, rather than them failing to render
+    pub(crate) fn handles_missing(&self) -> bool {
+        self.name == "default"
+    }
+
+    pub(crate) fn apply(&self, value: &Value) -> Result<Value, TemplateError> {
+        let custom = custom_filters()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&self.name)
+            .cloned();
+        match custom {
+            Some(filter) => filter(value, &self.args),
+            None => built_in(self.name.as_str(), value, &self.args),
+        }
+        .map_err(|e| TemplateError::RenderError(format!("{} filter failed: {}", self.name, e)))
+    }
+}
+
+fn string_arg<'a>(args: &'a [Value], position: usize) -> Result<Option<&'a str>, String> {
+    match args.get(position) {
+        None => Ok(None),
+        Some(Value::String(arg)) => Ok(Some(arg.as_str())),
+        Some(other) => Err(format!("expected a string argument, got {}", other)),
+    }
+}
+
+fn as_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn built_in(name: &str, value: &Value, args: &[Value]) -> Result<Value, String> {
+    match name {
+        "upper" => Ok(Value::String(as_text(value).to_uppercase())),
+        "lower" => Ok(Value::String(as_text(value).to_lowercase())),
+        "trim" => Ok(Value::String(as_text(value).trim().to_string())),
+        "round" => {
+            let places = match args.first() {
+                None => 0,
+                Some(places) => places
+                    .as_u64()
+                    .ok_or_else(|| format!("expected a number of places, got {}", places))?
+                    as usize,
+            };
+            let number = match value {
+                Value::Number(n) => n.as_f64(),
+                Value::String(s) => s.parse::<f64>().ok(),
+                _ => None,
+            }
+            .ok_or_else(|| format!("expected a number, got {}", value))?;
+            Ok(Value::String(format!("{:.*}", places, number)))
+        }
+        "date" => {
+            let timestamp = match value {
+                Value::String(s) => OffsetDateTime::parse(s, &Rfc3339).map_err(|e| e.to_string()),
+                Value::Number(n) => n
+                    .as_i64()
+                    .ok_or_else(|| format!("expected whole seconds, got {}", n))
+                    .and_then(|seconds| {
+                        OffsetDateTime::from_unix_timestamp(seconds).map_err(|e| e.to_string())
+                    }),
+                other => Err(format!("expected a timestamp, got {}", other)),
+            }?;
+            let format = strftime_description(string_arg(args, 0)?.unwrap_or("%Y-%m-%d"))?;
+            let format =
+                time::format_description::parse(format.as_str()).map_err(|e| e.to_string())?;
+            timestamp
+                .format(&format)
+                .map(Value::String)
+                .map_err(|e| e.to_string())
+        }
+        "default" => match value {
+            Value::Null => Ok(args.first().cloned().unwrap_or(Value::Null)),
+            other => Ok(other.clone()),
+        },
+        "length" => match value {
+            Value::String(s) => Ok(Value::from(s.chars().count())),
+            Value::Array(items) => Ok(Value::from(items.len())),
+            Value::Object(entries) => Ok(Value::from(entries.len())),
+            other => Err(format!("expected a string, array or object, got {}", other)),
+        },
+        "join" => match value {
+            Value::Array(items) => Ok(Value::String(
+                items
+                    .iter()
+                    .map(as_text)
+                    .collect::<Vec<String>>()
+                    .join(string_arg(args, 0)?.unwrap_or(", ")),
+            )),
+            other => Err(format!("expected an array, got {}", other)),
+        },
+        _ => Err("no such filter".to_string()),
+    }
+}
+
+/// Translate a `strftime`-style format, e.g. `%Y-%m-%d`, into a `time` format description
+fn strftime_description(format: &str) -> Result<String, String> {
+    let mut description = String::new();
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '%' => description.push_str(match chars.next() {
+                Some('Y') => "[year]",
+                Some('y') => "[year repr:last_two]",
+                Some('m') => "[month]",
+                Some('d') => "[day]",
+                Some('j') => "[ordinal]",
+                Some('H') => "[hour]",
+                Some('M') => "[minute]",
+                Some('S') => "[second]",
+                Some('z') => "[offset_hour sign:mandatory][offset_minute]",
+                Some('%') => "%",
+                Some(other) => return Err(format!("unsupported date format %{}", other)),
+                None => return Err("date format ends with %".to_string()),
+            }),
+            '[' => description.push_str("[["),
+            other => description.push(other),
+        }
+    }
+    Ok(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(name: &str, value: Value, args: Vec<Value>) -> Result<Value, TemplateError> {
+        FilterCall::new(name, args)?.apply(&value)
+    }
+
+    #[test]
+    fn test_built_in_filters() -> Result<(), TemplateError> {
+        assert_eq!(apply("upper", json!("ada"), vec![])?, json!("ADA"));
+        assert_eq!(apply("trim", json!("  ada "), vec![])?, json!("ada"));
+        assert_eq!(
+            apply("round", json!(12.3456), vec![json!(2)])?,
+            json!("12.35")
+        );
+        assert_eq!(apply("round", json!("7.6"), vec![])?, json!("8"));
+        assert_eq!(
+            apply(
+                "date",
+                json!("2024-03-05T10:15:00Z"),
+                vec![json!("%Y-%m-%d %H:%M")]
+            )?,
+            json!("2024-03-05 10:15")
+        );
+        assert_eq!(apply("date", json!(0), vec![])?, json!("1970-01-01"));
+        assert_eq!(
+            apply("default", Value::Null, vec![json!("n/a")])?,
+            json!("n/a")
+        );
+        assert_eq!(apply("length", json!([1, 2, 3]), vec![])?, json!(3));
+        assert_eq!(
+            apply("join", json!(["a", 1]), vec![json!("-")])?,
+            json!("a-1")
+        );
+        assert!(apply("round", json!("twelve"), vec![]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_filters() -> Result<(), TemplateError> {
+        assert!(matches!(
+            FilterCall::new("mask", vec![]),
+            Err(TemplateError::UnknownFilter(_))
+        ));
+        register_filter("mask", |value, _| {
+            let text = as_text(value);
+            let kept = text.chars().count().saturating_sub(4);
+            Ok(Value::String(
+                text.chars()
+                    .enumerate()
+                    .map(|(i, ch)| if i < kept { '*' } else { ch })
+                    .collect(),
+            ))
+        });
+        assert_eq!(
+            apply("mask", json!("4111111111111111"), vec![])?,
+            json!("************1111")
+        );
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
 use crate::template::error::TemplateError;
-use crate::template::values::TemplateValue;
-use serde::ser::SerializeMap;
+use crate::template::values::{is_truthy, lookup, render_values, KeyBlock, TemplateValue};
+use serde::ser::{SerializeMap, SerializeSeq};
 use serde::Serialize;
 
 pub(crate) mod error;
+pub(crate) mod filters;
 mod values;
 
 pub(crate) type TemplateValues = Vec<TemplateValue>;
@@ -13,6 +14,12 @@ pub(crate) enum TemplateNode {
     Leaf(TemplateValues),
     // A branch is a KV, like a dict or a hashmap
     Branch(TemplateBranch),
+    /// Entries merged into the enclosing branch when the field is truthy, from a
+    /// `${{#if field}}` key
+    When(Vec<String>, TemplateBranch),
+    /// An array of the node rendered against each element of the field, from a
+    /// `${{#each field}}` key
+    Each(Vec<String>, Box<TemplateNode>),
 }
 
 impl RenderedTemplate {
@@ -20,13 +27,32 @@ impl RenderedTemplate {
         leaf_node: TemplateValues,
         associated_value: &serde_json::Value,
     ) -> Result<Self, TemplateError> {
-        Ok(RenderedTemplate::Leaf(
-            leaf_node
-                .into_iter()
-                .map(|n| n.render(associated_value))
-                .collect::<Result<Vec<String>, TemplateError>>()?
-                .join(""),
-        ))
+        Ok(RenderedTemplate::Leaf(render_values(
+            leaf_node,
+            associated_value,
+        )?))
+    }
+
+    /// Render the entries of a branch, flattening in those of `#if` keys that hold
+    fn try_parse_entries(
+        branch_node: TemplateBranch,
+        associated_value: &serde_json::Value,
+        entries: &mut Vec<(RenderedTemplate, RenderedTemplate)>,
+    ) -> Result<(), TemplateError> {
+        for (leaf_node, template_node) in branch_node {
+            match template_node {
+                TemplateNode::When(fields, branch_node) => {
+                    if is_truthy(lookup(associated_value, &fields).ok().as_deref()) {
+                        Self::try_parse_entries(branch_node, associated_value, entries)?;
+                    }
+                }
+                template_node => entries.push((
+                    Self::try_parse_leaf(leaf_node, associated_value)?,
+                    Self::try_parse(template_node, associated_value)?,
+                )),
+            }
+        }
+        Ok(())
     }
 
     fn try_parse(
@@ -35,17 +61,35 @@ impl RenderedTemplate {
     ) -> Result<Self, TemplateError> {
         match value {
             TemplateNode::Leaf(leaf_node) => Ok(Self::try_parse_leaf(leaf_node, associated_value)?),
-            TemplateNode::Branch(branch_node) => Ok(RenderedTemplate::Branch(
-                branch_node
-                    .into_iter()
-                    .map(|(leaf_node, template_node)| {
-                        Self::try_parse_leaf(leaf_node, associated_value).and_then(|x| {
-                            Self::try_parse(template_node, associated_value).map(|y| (x, y))
-                        })
-                    })
-                    .collect::<Result<Vec<(RenderedTemplate, RenderedTemplate)>, TemplateError>>(
-                    )?,
-            )),
+            TemplateNode::Branch(branch_node) => {
+                let mut entries = Vec::with_capacity(branch_node.len());
+                Self::try_parse_entries(branch_node, associated_value, &mut entries)?;
+                Ok(RenderedTemplate::Branch(entries))
+            }
+            TemplateNode::When(fields, branch_node) => {
+                let mut entries = Vec::new();
+                Self::try_parse_entries(
+                    vec![(Vec::new(), TemplateNode::When(fields, branch_node))],
+                    associated_value,
+                    &mut entries,
+                )?;
+                Ok(RenderedTemplate::Branch(entries))
+            }
+            TemplateNode::Each(fields, item_node) => {
+                match lookup(associated_value, &fields).ok().as_deref() {
+                    None | Some(serde_json::Value::Null) => Ok(RenderedTemplate::Array(Vec::new())),
+                    Some(serde_json::Value::Array(items)) => Ok(RenderedTemplate::Array(
+                        items
+                            .iter()
+                            .map(|item| Self::try_parse(item_node.as_ref().clone(), item))
+                            .collect::<Result<Vec<RenderedTemplate>, TemplateError>>()?,
+                    )),
+                    Some(_) => Err(TemplateError::RenderError(format!(
+                        "Expected {} to be an array",
+                        fields.join(".")
+                    ))),
+                }
+            }
         }
     }
 }
@@ -53,6 +97,7 @@ impl RenderedTemplate {
 pub(crate) enum RenderedTemplate {
     Leaf(String),
     Branch(Vec<(RenderedTemplate, RenderedTemplate)>),
+    Array(Vec<RenderedTemplate>),
 }
 
 impl Serialize for RenderedTemplate {
@@ -69,6 +114,13 @@ impl Serialize for RenderedTemplate {
                 }
                 map.end()
             }
+            RenderedTemplate::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
         }
     }
 }
@@ -79,6 +131,14 @@ impl TemplateNode {
         value: &serde_yaml::Value,
     ) -> Result<(TemplateValues, TemplateNode), TemplateError> {
         if let Some(key) = key.as_str() {
+            if let Some(KeyBlock::If(fields)) = KeyBlock::try_parse(key) {
+                return match TemplateNode::from_value(value)? {
+                    TemplateNode::Branch(branch) => {
+                        Ok((Vec::new(), TemplateNode::When(fields, branch)))
+                    }
+                    _ => Err(TemplateError::NoMappingFound),
+                };
+            }
             let key = TemplateValue::try_parse(key)?;
             let value = TemplateNode::from_value(value)?;
             Ok((key, value))
@@ -88,6 +148,16 @@ impl TemplateNode {
     }
 
     fn from_value(value: &serde_yaml::Value) -> Result<Self, TemplateError> {
+        // A mapping of just an `#each` key is the array it renders to
+        if let Some(mapping) = value.as_mapping().filter(|mapping| mapping.len() == 1) {
+            let (key, item) = mapping.iter().next().expect("mapping has one entry");
+            if let Some(KeyBlock::Each(fields)) = key.as_str().and_then(KeyBlock::try_parse) {
+                return Ok(TemplateNode::Each(
+                    fields,
+                    Box::new(TemplateNode::from_value(item)?),
+                ));
+            }
+        }
         match value.as_mapping() {
             Some(mapping) => Ok(TemplateNode::Branch(
                 mapping
@@ -135,6 +205,22 @@ impl Template {
         })
     }
 
+    /// Render a `crate::template::Template` built from a single string back into a string
+    pub(crate) fn render_string(
+        self,
+        associated_value: &serde_json::Value,
+    ) -> Result<String, TemplateError> {
+        match self.render(associated_value)? {
+            RenderedTemplate::Leaf(value) => Ok(value),
+            RenderedTemplate::Branch(_) => Err(TemplateError::RenderError(
+                "Expected a string, found a mapping".to_string(),
+            )),
+            RenderedTemplate::Array(_) => Err(TemplateError::RenderError(
+                "Expected a string, found an array".to_string(),
+            )),
+        }
+    }
+
     /// Render a `crate::template::Template` into a format serializable with JSON
     pub(crate) fn render(
         self,
@@ -143,3 +229,41 @@ impl Template {
         Ok(RenderedTemplate::try_parse(self.root, associated_value)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_block_keys() -> Result<(), TemplateError> {
+        let template = Template::from_payload(
+            &serde_yaml::from_str(
+                r#"
+userId: "${{ userId }}"
+"${{#if refund}}":
+  refundAmount: "${{ refund.amount }}"
+"${{#if chargeback}}":
+  disputed: "true"
+items:
+  "${{#each items}}":
+    sku: "${{ sku }}"
+"#,
+            )
+            .unwrap(),
+        )?;
+        let rendered = template.render(&serde_json::json!({
+            "userId": 1,
+            "refund": {"amount": 5},
+            "items": [{"sku": "a"}, {"sku": "b"}],
+        }))?;
+        assert_eq!(
+            serde_json::to_value(rendered).unwrap(),
+            serde_json::json!({
+                "userId": "1",
+                "refundAmount": "5",
+                "items": [{"sku": "a"}, {"sku": "b"}],
+            })
+        );
+        Ok(())
+    }
+}
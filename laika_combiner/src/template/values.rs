@@ -1,11 +1,17 @@
 use crate::template::error::TemplateError;
+use crate::template::filters::FilterCall;
 use crate::utils::extract_json::extract_json_field;
 use serde_json::Value;
+use std::borrow::Cow;
+use std::iter::Peekable;
+use std::str::Chars;
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub(crate) struct TemplatedValue {
     prefix: Option<String>,
     template_fields: Vec<String>,
+    /// Filters piped the value through, in order, e.g. `${{ name | trim | upper }}`
+    filters: Vec<FilterCall>,
     postfix: Option<String>,
 }
 
@@ -37,10 +43,23 @@ impl TemplatedValue {
     }
 
     pub fn render(self, json: &Value) -> Result<String, TemplateError> {
-        let extracted_element = Self::format_json_value(
-            extract_json_field(json, &self.template_fields.join("."))
-                .map_err(|e| TemplateError::RenderError(e.to_string()))?,
-        );
+        let value = match lookup(json, &self.template_fields) {
+            Ok(value) => value.into_owned(),
+            Err(_)
+                if self
+                    .filters
+                    .first()
+                    .is_some_and(FilterCall::handles_missing) =>
+            {
+                Value::Null
+            }
+            Err(e) => return Err(e),
+        };
+        let value = self
+            .filters
+            .iter()
+            .try_fold(value, |value, filter| filter.apply(&value))?;
+        let extracted_element = Self::format_json_value(&value);
         tracing::debug!("Extracted element {}", extracted_element);
         Ok(format!(
             "{}{}{}",
@@ -51,10 +70,58 @@ impl TemplatedValue {
     }
 }
 
+/// Find a field of `json`. Within an `#each` block, `this` is the current element.
+pub(crate) fn lookup<'a>(
+    json: &'a Value,
+    fields: &[String],
+) -> Result<Cow<'a, Value>, TemplateError> {
+    let fields = match fields.split_first() {
+        Some((first, rest)) if first == "this" => rest,
+        _ => fields,
+    };
+    extract_json_field(json, &fields.join("."))
+        .map_err(|e| TemplateError::RenderError(e.to_string()))
+}
+
+/// Whether an `#if` block is rendered for a value. Missing fields, `null`, `false`, `0`, empty
+/// strings and empty arrays aren't.
+pub(crate) fn is_truthy(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) | Some(Value::Bool(false)) => false,
+        Some(Value::Number(n)) => n.as_f64() != Some(0.0),
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(Value::Array(arr)) => !arr.is_empty(),
+        Some(_) => true,
+    }
+}
+
+/// Render each value against `json`, joined into one string
+pub(crate) fn render_values(
+    values: Vec<TemplateValue>,
+    json: &Value,
+) -> Result<String, TemplateError> {
+    Ok(values
+        .into_iter()
+        .map(|value| value.render(json))
+        .collect::<Result<Vec<String>, TemplateError>>()?
+        .join(""))
+}
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub(crate) enum TemplateValue {
     Raw(String),
     Template(TemplatedValue),
+    /// `${{#if field}}then${{else}}otherwise${{/if}}`
+    If {
+        fields: Vec<String>,
+        then: Vec<TemplateValue>,
+        otherwise: Vec<TemplateValue>,
+    },
+    /// `${{#each field}}body${{/each}}`, rendering the body against each element of the array
+    Each {
+        fields: Vec<String>,
+        body: Vec<TemplateValue>,
+    },
 }
 
 impl TemplateValue {
@@ -62,12 +129,66 @@ impl TemplateValue {
         match self {
             TemplateValue::Raw(raw_string) => Ok(raw_string),
             TemplateValue::Template(templated_value) => templated_value.render(json),
+            TemplateValue::If {
+                fields,
+                then,
+                otherwise,
+            } => {
+                if is_truthy(lookup(json, &fields).ok().as_deref()) {
+                    render_values(then, json)
+                } else {
+                    render_values(otherwise, json)
+                }
+            }
+            TemplateValue::Each { fields, body } => match lookup(json, &fields).ok().as_deref() {
+                None | Some(Value::Null) => Ok(String::new()),
+                Some(Value::Array(items)) => Ok(items
+                    .iter()
+                    .map(|item| render_values(body.clone(), item))
+                    .collect::<Result<Vec<String>, TemplateError>>()?
+                    .join("")),
+                Some(_) => Err(TemplateError::RenderError(format!(
+                    "Expected {} to be an array",
+                    fields.join(".")
+                ))),
+            },
         }
     }
 
     /// Attempt to parse `value` into one or multiple `TemplateValue`s.
     pub(crate) fn try_parse<T: Into<String>>(value: T) -> Result<Vec<Self>, TemplateError> {
-        parse(lex(value.into().as_str()))
+        parse(lex(value.into().as_str())?)
+    }
+}
+
+/// A block opened by a whole mapping key, such as `${{#if refund}}`. The key's value is the
+/// block's body, so there's no closing tag.
+#[derive(PartialEq, Eq, Debug)]
+pub(crate) enum KeyBlock {
+    If(Vec<String>),
+    Each(Vec<String>),
+}
+
+impl KeyBlock {
+    pub(crate) fn try_parse(key: &str) -> Option<Self> {
+        let tokens = lex(key).ok()?;
+        let [Token::TemplateStart, opening, rest @ .., Token::TemplateEnd] = tokens.as_slice()
+        else {
+            return None;
+        };
+        let mut fields = Vec::new();
+        for token in rest {
+            match token {
+                Token::TemplateIdentifier(id) => fields.push(id.clone()),
+                Token::TemplateDot => {}
+                _ => return None,
+            }
+        }
+        match opening {
+            Token::TemplateIf => Some(KeyBlock::If(fields)),
+            Token::TemplateEach => Some(KeyBlock::Each(fields)),
+            _ => None,
+        }
     }
 }
 
@@ -78,14 +199,53 @@ enum Token {
     TemplateIdentifier(String),
     TemplateDot,
     TemplateEnd,
+    /// `#if`, opening a conditional block
+    TemplateIf,
+    /// `#each`, opening a loop
+    TemplateEach,
+    TemplateElse,
+    /// `/if`, closing a conditional block
+    TemplateEndIf,
+    /// `/each`, closing a loop
+    TemplateEndEach,
+    /// `|`, passing the value to a filter
+    TemplatePipe,
+    /// A filter's arguments, e.g. `(2)` in `round(2)`
+    TemplateFilterArgs(Vec<Value>),
 }
 
-fn lex(input: &str) -> Vec<Token> {
+/// The block keyword at the start of a template, if there is one, and its length
+fn block_keyword(rest: &str) -> Option<(Token, usize)> {
+    [
+        ("#if", Token::TemplateIf),
+        ("#each", Token::TemplateEach),
+        ("/if", Token::TemplateEndIf),
+        ("/each", Token::TemplateEndEach),
+        ("else", Token::TemplateElse),
+    ]
+    .into_iter()
+    .find(|(keyword, _)| {
+        rest.strip_prefix(keyword)
+            .and_then(|after| after.chars().next())
+            .is_some_and(|next| next.is_whitespace() || next == '}')
+    })
+    .map(|(keyword, token)| (token, keyword.chars().count()))
+}
+
+/// Split a string into text and the tokens of its templates. Templates that aren't closed, or
+/// have braces or quotes outside of a filter's arguments, are errors rather than text.
+fn lex(input: &str) -> Result<Vec<Token>, TemplateError> {
     let mut tokens = Vec::new();
     let mut chars = input.chars().peekable();
     let mut current_text = String::new();
 
     while let Some(ch) = chars.next() {
+        // `$${{` is a literal `${{`, such as from an environment variable
+        if ch == '$' && chars.clone().take(3).eq("${{".chars()) {
+            current_text.push_str("${{");
+            chars.nth(2);
+            continue;
+        }
         if ch == '$' && chars.peek() == Some(&'{') && chars.clone().nth(1) == Some('{') {
             // We found a template start marker
             if !current_text.is_empty() {
@@ -96,6 +256,7 @@ fn lex(input: &str) -> Vec<Token> {
             // Consume the '{{' after the '$'
             chars.next();
             chars.next();
+            let template_start = tokens.len();
             tokens.push(Token::TemplateStart);
 
             // Collect whitespace before the identifier
@@ -107,23 +268,45 @@ fn lex(input: &str) -> Vec<Token> {
                 }
             }
 
-            // Collect identifiers and dots
+            // Keywords are at most 5 characters, followed by whitespace or the closing brackets
+            let rest: String = chars.clone().take(6).collect();
+            if let Some((token, length)) = block_keyword(rest.as_str()) {
+                for _ in 0..length {
+                    chars.next();
+                }
+                tokens.push(token);
+                while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+                    chars.next();
+                }
+            }
+
+            // Collect identifiers, dots and filters
             let mut identifier = String::new();
             while let Some(&ch) = chars.peek() {
-                if ch.is_alphanumeric() || ch == '_' {
-                    identifier.push(chars.next().unwrap());
-                } else if ch == '.' {
+                if ch == '.' || ch == '|' || ch == '(' || ch.is_whitespace() {
                     if !identifier.is_empty() {
                         tokens.push(Token::TemplateIdentifier(identifier));
                         identifier = String::new();
                     }
                     chars.next();
-                    tokens.push(Token::TemplateDot);
-                } else if ch.is_whitespace() || ch == '}' {
+                    match ch {
+                        '.' => tokens.push(Token::TemplateDot),
+                        '|' => tokens.push(Token::TemplatePipe),
+                        '(' => tokens.push(Token::TemplateFilterArgs(lex_filter_args(
+                            &mut chars,
+                            template_start,
+                        )?)),
+                        _ => {}
+                    }
+                } else if ch == '}' {
                     break;
+                } else if matches!(ch, '{' | ')' | '$' | '"' | '\'') {
+                    // Nested templates, quotes and unopened arguments can't be part of a field
+                    return Err(TemplateError::UnexpectedToken(tokens.len()));
                 } else {
-                    // Unexpected character in identifier
-                    identifier.push(chars.next().unwrap());
+                    // Fields can have any other characters, such as `-` or `@`
+                    identifier.push(ch);
+                    chars.next();
                 }
             }
 
@@ -141,12 +324,9 @@ fn lex(input: &str) -> Vec<Token> {
             }
 
             // Check for closing '}}' sequence
-            if chars.next() == Some('}') && chars.next() == Some('}') {
-                tokens.push(Token::TemplateEnd);
-            } else {
-                // Malformed template
-                current_text.push_str("${{");
-                // Continue normal text processing
+            match (chars.next(), chars.next()) {
+                (Some('}'), Some('}')) => tokens.push(Token::TemplateEnd),
+                _ => return Err(TemplateError::UnclosedTemplate(template_start)),
             }
         } else {
             current_text.push(ch);
@@ -157,97 +337,295 @@ fn lex(input: &str) -> Vec<Token> {
         tokens.push(Token::Text(current_text));
     }
 
-    tokens
+    Ok(tokens)
 }
 
-fn parse(mut tokens: Vec<Token>) -> Result<Vec<TemplateValue>, TemplateError> {
-    let mut buffer = Vec::new();
+/// Read a filter's arguments, up to and including the closing `)`. Quoted arguments are
+/// strings, and anything else is read as JSON, falling back to a string. Commas and brackets
+/// within JSON arrays, objects and strings don't end an argument.
+fn lex_filter_args(
+    chars: &mut Peekable<Chars>,
+    template_start: usize,
+) -> Result<Vec<Value>, TemplateError> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    // Quote of the string being read, either a quoted argument or a string within JSON
+    let mut quote = None;
+    // How deeply nested the JSON being read is within arrays and objects
+    let mut depth = 0usize;
+    while let Some(ch) = chars.next() {
+        match (ch, quote) {
+            ('\\', Some(_)) => {
+                // JSON keeps its escapes, to be read by its parser
+                if depth > 0 {
+                    current.push(ch);
+                }
+                current.extend(chars.next());
+            }
+            (_, Some(open)) if ch == open => {
+                quote = None;
+                if depth > 0 {
+                    current.push(ch);
+                }
+            }
+            (_, Some(_)) => current.push(ch),
+            (',' | ')', None) if depth == 0 => {
+                let arg = std::mem::take(&mut current);
+                if quoted {
+                    args.push(Value::String(arg));
+                } else if !arg.trim().is_empty() {
+                    let arg = arg.trim();
+                    args.push(
+                        serde_json::from_str(arg)
+                            .unwrap_or_else(|_| Value::String(arg.to_string())),
+                    );
+                }
+                quoted = false;
+                if ch == ')' {
+                    return Ok(args);
+                }
+            }
+            (_, None) if quoted && ch.is_whitespace() => {}
+            // Nothing but a comma or `)` can follow a quoted argument
+            (_, None) if quoted => return Err(TemplateError::UnexpectedToken(template_start)),
+            ('"' | '\'', None) if depth == 0 => {
+                if !current.trim().is_empty() {
+                    return Err(TemplateError::UnexpectedToken(template_start));
+                }
+                current.clear();
+                quote = Some(ch);
+                quoted = true;
+            }
+            ('"' | '\'', None) => {
+                current.push(ch);
+                quote = Some(ch);
+            }
+            ('[' | '{', None) => {
+                depth += 1;
+                current.push(ch);
+            }
+            (']' | '}', None) if depth > 0 => {
+                depth -= 1;
+                current.push(ch);
+            }
+            (_, None) => current.push(ch),
+        }
+    }
+    Err(TemplateError::UnclosedTemplate(template_start))
+}
+
+/// Read the dotted field of a template from `start`, and any filters it's piped through, up to
+/// and including its closing `}}`. Returns the field, its filters and the position after it.
+fn parse_fields(
+    tokens: &[Token],
+    template_start: usize,
+    start: usize,
+) -> Result<(Vec<String>, Vec<FilterCall>, usize), TemplateError> {
+    let mut j = start;
+    let mut template_fields = Vec::new();
+    let mut filters = Vec::new();
+    // Fields are identifiers separated by dots, so one is expected at the start and after a dot
+    let mut expect_identifier = true;
+    while j < tokens.len() {
+        match &tokens[j] {
+            Token::TemplateIdentifier(id) if filters.is_empty() && expect_identifier => {
+                template_fields.push(id.clone());
+                expect_identifier = false;
+                j += 1;
+            }
+            Token::TemplateDot if filters.is_empty() && !expect_identifier => {
+                expect_identifier = true;
+                j += 1;
+            }
+            Token::TemplatePipe if !expect_identifier => {
+                let Some(Token::TemplateIdentifier(name)) = tokens.get(j + 1) else {
+                    return Err(TemplateError::UnexpectedToken(j + 1));
+                };
+                j += 2;
+                let args = match tokens.get(j) {
+                    Some(Token::TemplateFilterArgs(args)) => {
+                        j += 1;
+                        args.clone()
+                    }
+                    _ => Vec::new(),
+                };
+                filters.push(FilterCall::new(name, args)?);
+            }
+            // Block tags like `${{/if}}` have no field
+            Token::TemplateEnd if !expect_identifier || template_fields.is_empty() => {
+                return Ok((template_fields, filters, j + 1));
+            }
+            _ => {
+                // Unexpected token
+                return Err(TemplateError::UnexpectedToken(j));
+            }
+        }
+    }
+    Err(TemplateError::UnclosedTemplate(template_start))
+}
+
+fn parse(tokens: Vec<Token>) -> Result<Vec<TemplateValue>, TemplateError> {
     let mut i = 0;
+    let (values, closed_by) = parse_block(&tokens, &mut i)?;
+    match closed_by {
+        None => Ok(values),
+        Some(closing) => Err(TemplateError::UnexpectedToken(closing)),
+    }
+}
+
+/// Parse values from `i` until the tokens run out or a block is closed. Returns the values and
+/// the position of the `${{` of the `else`, `/if` or `/each` that closed the block, leaving `i`
+/// after it.
+fn parse_block(
+    tokens: &[Token],
+    i: &mut usize,
+) -> Result<(Vec<TemplateValue>, Option<usize>), TemplateError> {
+    let mut buffer = Vec::new();
 
-    while i < tokens.len() {
-        match &tokens[i] {
+    while *i < tokens.len() {
+        match &tokens[*i] {
             Token::Text(text) => {
                 if !buffer.is_empty() {
                     if let Some(TemplateValue::Template(templated_value)) = buffer.last_mut() {
                         if templated_value.postfix.is_none() {
                             // This text is a postfix for the previous template
                             templated_value.postfix = Some(text.clone());
-                            i += 1;
+                            *i += 1;
                             continue;
                         }
                     }
                 }
                 // Otherwise it's a typical text value
                 buffer.push(TemplateValue::Raw(text.clone()));
-                i += 1;
+                *i += 1;
             }
             Token::TemplateStart => {
-                let mut j = i + 1;
-                let mut template_fields = Vec::new();
-                let mut current_field = String::new();
-
-                // Extract template fields
-                while j < tokens.len() {
-                    match &tokens[j] {
-                        Token::TemplateIdentifier(id) => {
-                            current_field = id.clone();
-                            template_fields.push(current_field.clone());
-                            j += 1;
-                        }
-                        Token::TemplateDot => {
-                            j += 1;
-                        }
-                        Token::TemplateEnd => {
-                            break;
-                        }
-                        _ => {
-                            // Unexpected token
-                            return Err(TemplateError::UnexpectedToken(j));
-                        }
-                    }
-                }
-
-                if j >= tokens.len() || !matches!(tokens[j], Token::TemplateEnd) {
-                    return Err(TemplateError::UnclosedTemplate(i));
-                }
-
-                // Gosh this is a rubbish peek.
-                let prefix = buffer
-                    .pop()
-                    .map(|possible_prefix| {
-                        match possible_prefix {
-                            TemplateValue::Raw(prefix) => Some(prefix),
-                            TemplateValue::Template(t) => {
-                                // We don't want this, put it back
-                                buffer.push(TemplateValue::Template(t));
-                                None
+                let template_start = *i;
+                match tokens.get(*i + 1) {
+                    Some(Token::TemplateIf) => {
+                        let (fields, after) = parse_block_fields(tokens, template_start)?;
+                        *i = after;
+                        let (then, closed_by) = parse_block(tokens, i)?;
+                        let otherwise = match closed_by.map(|closing| &tokens[closing + 1]) {
+                            Some(Token::TemplateElse) => {
+                                let (otherwise, closed_by) = parse_block(tokens, i)?;
+                                expect_closing(
+                                    tokens,
+                                    template_start,
+                                    closed_by,
+                                    Token::TemplateEndIf,
+                                )?;
+                                otherwise
+                            }
+                            _ => {
+                                expect_closing(
+                                    tokens,
+                                    template_start,
+                                    closed_by,
+                                    Token::TemplateEndIf,
+                                )?;
+                                Vec::new()
                             }
+                        };
+                        buffer.push(TemplateValue::If {
+                            fields,
+                            then,
+                            otherwise,
+                        });
+                    }
+                    Some(Token::TemplateEach) => {
+                        let (fields, after) = parse_block_fields(tokens, template_start)?;
+                        *i = after;
+                        let (body, closed_by) = parse_block(tokens, i)?;
+                        expect_closing(tokens, template_start, closed_by, Token::TemplateEndEach)?;
+                        buffer.push(TemplateValue::Each { fields, body });
+                    }
+                    Some(Token::TemplateElse | Token::TemplateEndIf | Token::TemplateEndEach) => {
+                        let (_, after) = parse_block_fields(tokens, template_start)?;
+                        *i = after;
+                        return Ok((buffer, Some(template_start)));
+                    }
+                    _ => {
+                        let (template_fields, filters, after) =
+                            parse_fields(tokens, template_start, *i + 1)?;
+                        if template_fields.is_empty() {
+                            return Err(TemplateError::UnexpectedToken(template_start + 1));
                         }
-                    })
-                    .flatten();
 
-                buffer.push(TemplateValue::Template(TemplatedValue {
-                    prefix,
-                    template_fields,
-                    postfix: None,
-                }));
+                        // Gosh this is a rubbish peek.
+                        let prefix = buffer
+                            .pop()
+                            .map(|possible_prefix| {
+                                match possible_prefix {
+                                    TemplateValue::Raw(prefix) => Some(prefix),
+                                    other => {
+                                        // We don't want this, put it back
+                                        buffer.push(other);
+                                        None
+                                    }
+                                }
+                            })
+                            .flatten();
 
-                // Skip to after the template end
-                i = j + 1;
+                        buffer.push(TemplateValue::Template(TemplatedValue {
+                            prefix,
+                            template_fields,
+                            filters,
+                            postfix: None,
+                        }));
+
+                        // Skip to after the template end
+                        *i = after;
+                    }
+                }
             }
             _ => {
                 // Unexpected token at this position
-                i += 1;
+                *i += 1;
             }
         }
     }
-    Ok(buffer)
+    Ok((buffer, None))
+}
+
+/// Read the field of a block's tag, which can't be filtered. Only the tags opening blocks have
+/// a field.
+fn parse_block_fields(
+    tokens: &[Token],
+    template_start: usize,
+) -> Result<(Vec<String>, usize), TemplateError> {
+    let opening = matches!(
+        tokens.get(template_start + 1),
+        Some(Token::TemplateIf | Token::TemplateEach)
+    );
+    match parse_fields(tokens, template_start, template_start + 2)? {
+        (fields, filters, after) if filters.is_empty() && fields.is_empty() != opening => {
+            Ok((fields, after))
+        }
+        _ => Err(TemplateError::UnexpectedToken(template_start)),
+    }
+}
+
+/// Check a block was closed by `closing`, rather than by another block's tag or not at all
+fn expect_closing(
+    tokens: &[Token],
+    block_start: usize,
+    closed_by: Option<usize>,
+    closing: Token,
+) -> Result<(), TemplateError> {
+    match closed_by {
+        Some(closed_by) if tokens[closed_by + 1] == closing => Ok(()),
+        Some(closed_by) => Err(TemplateError::UnexpectedToken(closed_by)),
+        None => Err(TemplateError::UnclosedBlock(block_start)),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::errors::LaikaResult;
+    use proptest::prelude::*;
 
     #[test]
     pub fn test_lex() {
@@ -302,7 +680,7 @@ mod test {
             ),
         ];
         for (output, expected_output) in input_targets {
-            assert_eq!(output, expected_output)
+            assert_eq!(output.unwrap(), expected_output)
         }
     }
 
@@ -310,50 +688,55 @@ mod test {
     pub fn test_parse() -> Result<(), TemplateError> {
         let input_targets = vec![
             (
-                parse(lex("raw_string")),
+                TemplateValue::try_parse("raw_string"),
                 vec![TemplateValue::Raw("raw_string".to_string())],
             ),
             (
-                parse(lex("${{ raw_string }}")),
+                TemplateValue::try_parse("${{ raw_string }}"),
                 vec![TemplateValue::Template(TemplatedValue {
                     prefix: None,
                     template_fields: vec!["raw_string".to_string()],
+                    filters: vec![],
                     postfix: None,
                 })],
             ),
             (
-                parse(lex("${{ raw_string.sub_key }}")),
+                TemplateValue::try_parse("${{ raw_string.sub_key }}"),
                 vec![TemplateValue::Template(TemplatedValue {
                     prefix: None,
                     template_fields: vec!["raw_string".to_string(), "sub_key".to_string()],
+                    filters: vec![],
                     postfix: None,
                 })],
             ),
             (
-                parse(lex("MyPrefix${{ raw_string.sub_key }}MyPostfix")),
+                TemplateValue::try_parse("MyPrefix${{ raw_string.sub_key }}MyPostfix"),
                 vec![TemplateValue::Template(TemplatedValue {
                     prefix: Some("MyPrefix".to_string()),
                     template_fields: vec!["raw_string".to_string(), "sub_key".to_string()],
+                    filters: vec![],
                     postfix: Some("MyPostfix".to_string()),
                 })],
             ),
             (
-                parse(lex("MyPrefix${{ raw_string.sub_key }}${{ second_string }}")),
+                TemplateValue::try_parse("MyPrefix${{ raw_string.sub_key }}${{ second_string }}"),
                 vec![
                     TemplateValue::Template(TemplatedValue {
                         prefix: Some("MyPrefix".to_string()),
                         template_fields: vec!["raw_string".to_string(), "sub_key".to_string()],
+                        filters: vec![],
                         postfix: None,
                     }),
                     TemplateValue::Template(TemplatedValue {
                         prefix: None,
                         template_fields: vec!["second_string".to_string()],
+                        filters: vec![],
                         postfix: None,
                     }),
                 ],
             ),
             (
-                parse(lex("raw_string.sub_key")),
+                TemplateValue::try_parse("raw_string.sub_key"),
                 vec![TemplateValue::Raw("raw_string.sub_key".to_string())],
             ),
         ];
@@ -362,4 +745,217 @@ mod test {
         }
         Ok(())
     }
+
+    #[test]
+    pub fn test_lex_blocks() {
+        assert_eq!(
+            lex("${{#if refund}}yes${{ else }}no${{/if}}").unwrap(),
+            vec![
+                Token::TemplateStart,
+                Token::TemplateIf,
+                Token::TemplateIdentifier("refund".to_string()),
+                Token::TemplateEnd,
+                Token::Text("yes".to_string()),
+                Token::TemplateStart,
+                Token::TemplateElse,
+                Token::TemplateEnd,
+                Token::Text("no".to_string()),
+                Token::TemplateStart,
+                Token::TemplateEndIf,
+                Token::TemplateEnd,
+            ]
+        );
+        assert_eq!(
+            lex("${{ #each items }}${{/each}}").unwrap(),
+            vec![
+                Token::TemplateStart,
+                Token::TemplateEach,
+                Token::TemplateIdentifier("items".to_string()),
+                Token::TemplateEnd,
+                Token::TemplateStart,
+                Token::TemplateEndEach,
+                Token::TemplateEnd,
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_render_blocks() -> Result<(), TemplateError> {
+        let json = serde_json::json!({
+            "user": {"name": "Ada"},
+            "refund": {"amount": 5},
+            "items": [{"sku": "a"}, {"sku": "b"}],
+            "tags": ["x", "y"],
+        });
+        let render = |template: &str| render_values(TemplateValue::try_parse(template)?, &json);
+
+        assert_eq!(
+            render("${{#if refund}}Refunded ${{ refund.amount }}${{/if}}")?,
+            "Refunded 5"
+        );
+        assert_eq!(render("${{#if missing}}yes${{else}}no${{/if}}")?, "no");
+        assert_eq!(render("${{#each items}}${{ sku }};${{/each}}")?, "a;b;");
+        assert_eq!(
+            render("${{ user.name }}: ${{#each tags}}[${{ this }}]${{/each}}")?,
+            "Ada: [x][y]"
+        );
+        assert_eq!(
+            render("${{#each items}}${{#if sku}}${{ this.sku }}${{/if}}${{/each}}")?,
+            "ab"
+        );
+        assert_eq!(render("${{ items[-1].sku }}")?, "b");
+        assert_eq!(
+            render("${{#each items[*].sku}}${{ this }};${{/each}}")?,
+            "a;b;"
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_lex_filters() {
+        assert_eq!(
+            lex("${{ amount | round(2) }} ${{ ts|date(\"%Y-%m-%d\", 'a, b') }}").unwrap(),
+            vec![
+                Token::TemplateStart,
+                Token::TemplateIdentifier("amount".to_string()),
+                Token::TemplatePipe,
+                Token::TemplateIdentifier("round".to_string()),
+                Token::TemplateFilterArgs(vec![serde_json::json!(2)]),
+                Token::TemplateEnd,
+                Token::Text(" ".to_string()),
+                Token::TemplateStart,
+                Token::TemplateIdentifier("ts".to_string()),
+                Token::TemplatePipe,
+                Token::TemplateIdentifier("date".to_string()),
+                Token::TemplateFilterArgs(vec![
+                    serde_json::json!("%Y-%m-%d"),
+                    serde_json::json!("a, b"),
+                ]),
+                Token::TemplateEnd,
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_render_filters() -> Result<(), TemplateError> {
+        let json = serde_json::json!({
+            "user": {"name": " ada "},
+            "amount": 12.3456,
+            "ts": "2024-03-05T10:15:00Z",
+        });
+        let render = |template: &str| render_values(TemplateValue::try_parse(template)?, &json);
+
+        assert_eq!(render("Hi ${{ user.name | trim | upper }}!")?, "Hi ADA!");
+        assert_eq!(render("${{ amount | round(2) }}")?, "12.35");
+        assert_eq!(render("${{ ts | date(\"%d/%m/%Y\") }}")?, "05/03/2024");
+        assert_eq!(render("${{ missing | default(\"none\") }}")?, "none");
+        assert!(render("${{ missing | upper }}").is_err());
+        assert!(matches!(
+            TemplateValue::try_parse("${{ amount | nonsense }}"),
+            Err(TemplateError::UnknownFilter(_))
+        ));
+        assert!(matches!(
+            TemplateValue::try_parse("${{#if amount | round }}yes${{/if}}"),
+            Err(TemplateError::UnexpectedToken(0))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_unbalanced_blocks() {
+        assert!(matches!(
+            TemplateValue::try_parse("${{#if refund}}yes"),
+            Err(TemplateError::UnclosedBlock(0))
+        ));
+        assert!(matches!(
+            TemplateValue::try_parse("${{#if refund}}yes${{/each}}"),
+            Err(TemplateError::UnexpectedToken(_))
+        ));
+        assert!(matches!(
+            TemplateValue::try_parse("yes${{/if}}"),
+            Err(TemplateError::UnexpectedToken(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_malformed_templates() {
+        for template in [
+            "${{ name",
+            "${{ name }",
+            "${{ name } }}",
+            "${{ ${{ name }} }}",
+            "${{ name | default(\"x\" }}",
+            "${{ name | default(\"x\"y) }}",
+            "${{ name | default([1, 2) }}",
+            "${{ first second }}",
+            "${{ .name }}",
+            "${{ name. }}",
+            "${{ user..name }}",
+            "${{ }}",
+            "${{ | upper }}",
+            "${{#if}}yes${{/if}}",
+            "${{#if refund}}yes${{/if refund}}",
+        ] {
+            assert!(
+                TemplateValue::try_parse(template).is_err(),
+                "{} should not parse",
+                template
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_lex_json_filter_args() {
+        assert_eq!(
+            lex("${{ name | default({\"a\": \"}}\", \"b\": [1, 2]}) }}").unwrap(),
+            vec![
+                Token::TemplateStart,
+                Token::TemplateIdentifier("name".to_string()),
+                Token::TemplatePipe,
+                Token::TemplateIdentifier("default".to_string()),
+                Token::TemplateFilterArgs(vec![serde_json::json!({"a": "}}", "b": [1, 2]})]),
+                Token::TemplateEnd,
+            ]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_never_panics(template in "\\PC{0,64}") {
+            let _ = TemplateValue::try_parse(template);
+        }
+
+        // Mostly template syntax, to reach its edge cases far more often than arbitrary text
+        #[test]
+        fn test_syntax_never_panics(
+            template in r#"(\$\{\{|\}\}|#if |#each |/if|/each|else|upper|default|[ .|(),"'\\\[\]{}a-zé0-9]){0,32}"#
+        ) {
+            if let Ok(values) = TemplateValue::try_parse(template) {
+                let _ = render_values(values, &serde_json::json!({"a": [1, {"b": "é"}], "é": 2}));
+            }
+        }
+
+        #[test]
+        fn test_text_is_unchanged(text in "[^$]{0,64}") {
+            let values = TemplateValue::try_parse(text.as_str()).unwrap();
+            prop_assert_eq!(render_values(values, &Value::Null).unwrap(), text);
+        }
+
+        #[test]
+        fn test_fields_render(
+            prefix in "[^$]{0,16}",
+            field in "[a-zA-Zé_][a-zA-Z0-9é_@-]{0,8}",
+            value in "\\PC{0,16}",
+            postfix in "[^$]{0,16}",
+        ) {
+            prop_assume!(field != "else" && field != "this");
+            let template = format!("{}${{{{ {} }}}}{}", prefix, field, postfix);
+            let json = Value::Object([(field, Value::String(value.clone()))].into_iter().collect());
+            let values = TemplateValue::try_parse(template).unwrap();
+            prop_assert_eq!(
+                render_values(values, &json).unwrap(),
+                format!("{}{}{}", prefix, value, postfix)
+            );
+        }
+    }
 }
@@ -0,0 +1,125 @@
+use crate::errors::{LaikaError, LaikaResult};
+use crate::event::{EventLike, Trigger};
+use crate::utils::extract_json::extract_json_field;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Separates the tenant from the rest of a tenant scoped correlation ID or window group
+pub const TENANT_SEPARATOR: char = '/';
+
+/// Splits events between tenants, so one instance can serve many without their correlations
+/// mixing. Correlation IDs are prefixed with the tenant, which namespaces everything stored
+/// against them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TenancyConfig {
+    /// JSONPath to the tenant within each event
+    pub key: String,
+    /// Tenant for events without one, where such events fail without it
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+impl TenancyConfig {
+    /// Tenant an event belongs to
+    pub(crate) fn tenant(&self, data: &Value) -> LaikaResult<String> {
+        let tenant = match extract_json_field(data, self.key.as_str()).as_deref() {
+            Ok(Value::String(tenant)) => tenant.clone(),
+            Ok(Value::Null) | Err(_) => self
+                .default
+                .clone()
+                .ok_or_else(|| LaikaError::MissingInput(format!("No tenant at {}", self.key)))?,
+            Ok(other) => other.to_string(),
+        };
+        if tenant.is_empty() || tenant.contains(TENANT_SEPARATOR) {
+            return Err(LaikaError::InvalidTenant(tenant));
+        }
+        Ok(tenant)
+    }
+
+    /// Prefix `id` with the tenant it belongs to
+    pub(crate) fn scope(tenant: &str, id: &str) -> String {
+        format!("{}{}{}", tenant, TENANT_SEPARATOR, id)
+    }
+
+    /// Tenant a scoped correlation ID belongs to
+    pub(crate) fn tenant_of(scoped_id: &str) -> Option<&str> {
+        scoped_id
+            .split_once(TENANT_SEPARATOR)
+            .map(|(tenant, _)| tenant)
+    }
+
+    /// Tenant a rule is being evaluated for, where it's known. Global schedules aren't for any
+    /// one tenant.
+    pub(crate) fn trigger_tenant(&self, trigger: &Trigger) -> Option<String> {
+        match trigger {
+            Trigger::ReceivedEvent(event) => self.tenant(event.get_data()).ok(),
+            Trigger::TimerExpired(expiry) | Trigger::Scheduled(expiry) => {
+                Self::tenant_of(expiry.correlation_id.as_str()).map(str::to_string)
+            }
+        }
+    }
+}
+
+/// Counts of what a tenant's events have led to since the runtime started
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TenantStats {
+    /// Events received for the tenant
+    pub events: u64,
+    /// Emits and HTTP requests produced for the tenant
+    pub actions: u64,
+}
+
+impl TenantStats {
+    pub(crate) fn merge(&mut self, other: &TenantStats) {
+        self.events += other.events;
+        self.actions += other.actions;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tenancy(default: Option<&str>) -> TenancyConfig {
+        TenancyConfig {
+            key: "$.account.tenant".to_string(),
+            default: default.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_tenant() {
+        let tenancy = tenancy(None);
+        assert_eq!(
+            tenancy.tenant(&json!({"account": {"tenant": "acme"}})),
+            Ok("acme".to_string())
+        );
+        assert_eq!(
+            tenancy.tenant(&json!({"account": {"tenant": 42}})),
+            Ok("42".to_string())
+        );
+        assert!(tenancy.tenant(&json!({"account": {}})).is_err());
+        assert_eq!(
+            tenancy.tenant(&json!({"account": {"tenant": "a/b"}})),
+            Err(LaikaError::InvalidTenant("a/b".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_default_tenant() {
+        assert_eq!(
+            tenancy(Some("shared")).tenant(&json!({"id": 1})),
+            Ok("shared".to_string())
+        );
+    }
+
+    #[test]
+    fn test_scope() {
+        let scoped = TenancyConfig::scope("acme", "order/1");
+        assert_eq!(scoped, "acme/order/1");
+        assert_eq!(TenancyConfig::tenant_of(scoped.as_str()), Some("acme"));
+        assert_eq!(TenancyConfig::tenant_of("order"), None);
+    }
+}
@@ -1,17 +1,51 @@
-use crate::broker::{CorrelationId, EventExpiry};
+use crate::broker::{CorrelationId, EventExpiry, ExpiryKind};
+use crate::clock::{Clock, SystemClock};
 use crate::errors::{LaikaError, LaikaResult};
 use fs2::FileExt;
+use serde::Deserialize;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufWriter, Read, Write};
 use std::path::PathBuf;
+use std::sync::Arc;
 use time::OffsetDateTime;
 
+/// Wakeups kept when no other bound is given
+pub const DEFAULT_MAX_WAKEUPS: usize = 100_000;
+
+/// Starts timer files written with a version, ahead of the version and then the expiries. Files
+/// written before expiries had a kind start with the number of expiries instead, which can't
+/// begin with these bytes short of over a billion expiries.
+const FILE_MAGIC: &[u8; 4] = b"LKAT";
+
+/// Layout of the expiries written after `FILE_MAGIC`
+const FILE_VERSION: u8 = 1;
+
+/// An expiry as written before expiries had a kind, when each was a recheck
+#[derive(Deserialize)]
+struct UnversionedExpiry {
+    expires_at: OffsetDateTime,
+    correlation_id: CorrelationId,
+    event_rule: String,
+}
+
+impl From<UnversionedExpiry> for EventExpiry {
+    fn from(expiry: UnversionedExpiry) -> Self {
+        EventExpiry::new(expiry.expires_at, expiry.correlation_id, expiry.event_rule)
+    }
+}
+
 /// TimingExpiry tracks time windows for correlated events, enabling config like
 /// "if A and B don't occur within 30 minutes, do X". Events are linked by a
 /// correlation ID and persist across service restarts.
+///
+/// Only one expiry is kept for each rule, correlation and kind, with later ones replacing it.
 pub struct TimingExpiry {
     expiry: Option<EventExpiry>,
     source: PathBuf,
+    clock: Arc<dyn Clock>,
+    max_wakeups: usize,
+    /// Expiries still to be processed, kept so it can be read without the file
+    pending_count: usize,
 }
 
 impl TimingExpiry {
@@ -20,37 +54,87 @@ impl TimingExpiry {
         let mut timing_expiry = Self {
             expiry: None,
             source,
+            clock: Arc::new(SystemClock),
+            max_wakeups: DEFAULT_MAX_WAKEUPS,
+            pending_count: 0,
         };
-        timing_expiry.expiry = timing_expiry.read_expiries()?.first().cloned();
+        let expiries = timing_expiry.read_expiries()?;
+        timing_expiry.expiry = expiries.first().cloned();
+        timing_expiry.pending_count = expiries.len();
         Ok(timing_expiry)
     }
 
+    /// Time expiries by `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Keep at most `max_wakeups` expiries, ignoring new ones beyond it
+    pub fn with_max_wakeups(mut self, max_wakeups: usize) -> Self {
+        self.max_wakeups = max_wakeups;
+        self
+    }
+
     /// Returns the next expiry to be processed without acknowledging it
     pub fn peek(&self) -> Option<EventExpiry> {
         self.expiry.clone()
     }
 
+    /// Returns the next expiry to be processed, only if its time has passed
+    pub fn next_expired(&self) -> Option<EventExpiry> {
+        self.expiry
+            .clone()
+            .filter(|expiry| expiry.expires_at <= self.clock.now())
+    }
+
     /// Adds a time window to check for correlated events. When the time expires,
     /// the system can check if all expected events occurred for this correlation ID.
     pub fn add_expiry(&mut self, expiry: EventExpiry) -> LaikaResult<()> {
-        let mut current = self.read_expiries()?;
-        current.push(expiry);
-        self.update_expiry(current)
+        self.add_expiries(vec![expiry])
     }
 
+    /// Adds several expiries, each replacing any pending expiry of the same rule, correlation
+    /// and kind. New expiries beyond the maximum are ignored, with a warning.
     pub fn add_expiries(&mut self, expiries: Vec<EventExpiry>) -> LaikaResult<()> {
         let mut current = self.read_expiries()?;
-        current.extend(expiries);
+        for expiry in expiries {
+            match current.iter_mut().find(|pending| pending.replaces(&expiry)) {
+                Some(pending) => *pending = expiry,
+                None if current.len() >= self.max_wakeups => tracing::warn!(
+                    "Ignoring wakeup of {} for {}, as {} wakeups are already pending",
+                    expiry.event_rule,
+                    expiry.correlation_id,
+                    current.len()
+                ),
+                None => current.push(expiry),
+            }
+        }
         self.update_expiry(current)
     }
 
+    /// Adds those of `expiries` that no pending expiry is for the same rule, correlation and kind
+    /// as, leaving pending ones as they are. A run of a schedule that was due while Laika was
+    /// stopped is then still woken on startup, rather than being pushed back to the next run.
+    pub fn add_missing_expiries(&mut self, expiries: Vec<EventExpiry>) -> LaikaResult<()> {
+        let current = self.read_expiries()?;
+        let missing: Vec<EventExpiry> = expiries
+            .into_iter()
+            .filter(|expiry| !current.iter().any(|pending| pending.replaces(expiry)))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        self.add_expiries(missing)
+    }
+
     /// Acknowledges and removes the current expiry if its time has passed.
     /// Returns error if no expiry exists or if the expiry time hasn't been reached.
     pub fn ack(&mut self) -> LaikaResult<()> {
         match self.expiry.take() {
             None => Err(LaikaError::Generic("No expiry to acknowledge".to_string())),
             Some(expiry) => {
-                if expiry.expires_at > OffsetDateTime::now_utc() {
+                if expiry.expires_at > self.clock.now() {
                     self.expiry = Some(expiry); // Put it back
                     Err(LaikaError::Generic("Expiry not yet met".to_string()))
                 } else {
@@ -61,25 +145,35 @@ impl TimingExpiry {
         }
     }
 
-    /// Negatively acknowledge and remove an expiry with the given correlation ID,
-    /// indicating all expected correlated events have been received and the expiry
-    /// can be safely removed.
-    pub fn nack(&mut self, correlation_id: CorrelationId) -> LaikaResult<()> {
+    /// Negatively acknowledge and remove any rechecks and timeouts of `rule` for the given
+    /// correlation ID, indicating the rule has been satisfied and no longer needs waking. Window
+    /// closes and schedules are left in place, and having nothing to remove isn't an error.
+    pub fn nack(&mut self, correlation_id: &str, rule: &str) -> LaikaResult<()> {
         let mut expiries = self.read_expiries()?;
         let original_len = expiries.len();
 
-        expiries.retain(|exp| exp.correlation_id != correlation_id);
+        expiries.retain(|exp| {
+            !matches!(exp.kind, ExpiryKind::Recheck | ExpiryKind::Timeout)
+                || exp.correlation_id != correlation_id
+                || exp.event_rule != rule
+        });
 
         if expiries.len() == original_len {
-            return Err(LaikaError::Generic(format!(
-                "No expiry found for correlation ID: {}",
-                correlation_id
-            )));
+            return Ok(());
         }
-
         self.update_expiry(expiries)
     }
 
+    /// All expiries still to be processed, soonest first
+    pub fn pending(&mut self) -> LaikaResult<Vec<EventExpiry>> {
+        self.read_expiries()
+    }
+
+    /// Number of expiries still to be processed
+    pub fn pending_count(&self) -> usize {
+        self.pending_count
+    }
+
     fn update_expiry(&mut self, mut expiries: Vec<EventExpiry>) -> LaikaResult<()> {
         expiries.sort();
         let file = File::create(&self.source).map_err(|e| LaikaError::IO(e.to_string()))?;
@@ -87,6 +181,11 @@ impl TimingExpiry {
             .map_err(|e| LaikaError::IO(e.to_string()))?;
         let mut writer = BufWriter::new(&file);
         self.expiry = expiries.first().cloned();
+        self.pending_count = expiries.len();
+        writer
+            .write_all(FILE_MAGIC)
+            .and_then(|_| writer.write_all(&[FILE_VERSION]))
+            .map_err(|e| LaikaError::IO(e.to_string()))?;
         bincode::serialize_into(&mut writer, &expiries)
             .map_err(|e| LaikaError::IO(format!("Failed to write expiries due to {}", e)))?;
         writer.flush().map_err(|e| LaikaError::IO(e.to_string()))?;
@@ -101,20 +200,31 @@ impl TimingExpiry {
     }
 
     fn read_expiries(&mut self) -> LaikaResult<Vec<EventExpiry>> {
-        let file = match File::open(&self.source) {
+        let mut file = match File::open(&self.source) {
             Ok(f) => f,
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
             Err(e) => return Err(LaikaError::IO(e.to_string())),
         };
-        let metadata = file
-            .metadata()
-            .map_err(|e| LaikaError::IO(format!("Failed to read file metadata: {}", e)))?;
-        if metadata.len() == 0 {
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .map_err(|e| LaikaError::IO(format!("Failed to read expiries due to {}", e)))?;
+        if contents.is_empty() {
             return Ok(Vec::new());
         }
-        let reader = BufReader::new(file);
-        bincode::deserialize_from(reader)
-            .map_err(|e| LaikaError::IO(format!("Failed to read expiries due to {}", e)))
+        let unreadable =
+            |e: bincode::Error| LaikaError::IO(format!("Failed to read expiries due to {}", e));
+        match contents.strip_prefix(FILE_MAGIC) {
+            Some([FILE_VERSION, expiries @ ..]) => {
+                bincode::deserialize(expiries).map_err(unreadable)
+            }
+            Some(_) => Err(LaikaError::IO(format!(
+                "Timer file {} was written by a newer version of Laika",
+                self.source.display()
+            ))),
+            None => bincode::deserialize::<Vec<UnversionedExpiry>>(&contents)
+                .map(|expiries| expiries.into_iter().map(EventExpiry::from).collect())
+                .map_err(unreadable),
+        }
     }
 }
 
@@ -122,6 +232,7 @@ impl TimingExpiry {
 mod tests {
     use super::*;
     use crate::broker::CorrelationId;
+    use crate::clock::TestClock;
     use tempfile::NamedTempFile;
     use time::OffsetDateTime;
 
@@ -152,6 +263,7 @@ mod tests {
         expiry.add_expiry(event.clone())?;
 
         assert_eq!(expiry.peek(), Some(event));
+        assert_eq!(expiry.pending_count(), 1);
         Ok(())
     }
 
@@ -159,7 +271,11 @@ mod tests {
     fn test_multiple_expiries_ordered() -> LaikaResult<()> {
         let (mut expiry, _temp) = create_test_expiry();
 
-        let later = create_test_event(10);
+        let later = EventExpiry::new(
+            OffsetDateTime::now_utc() + time::Duration::minutes(10),
+            "other-id".to_string(),
+            "ExampleEventRule".to_string(),
+        );
         let sooner = create_test_event(5);
 
         expiry.add_expiry(later.clone())?;
@@ -170,6 +286,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_next_expired_skips_future_expiry() -> LaikaResult<()> {
+        let (mut expiry, _temp) = create_test_expiry();
+        expiry.add_expiry(create_test_event(5))?;
+        assert!(expiry.next_expired().is_none());
+
+        let past_event = create_test_event(-5);
+        expiry.add_expiry(past_event.clone())?;
+        assert_eq!(expiry.next_expired(), Some(past_event));
+        Ok(())
+    }
+
     #[test]
     fn test_ack_future_expiry_fails() -> LaikaResult<()> {
         let (mut expiry, _temp) = create_test_expiry();
@@ -181,6 +309,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_expiries_follow_clock() -> LaikaResult<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let clock = TestClock::new(OffsetDateTime::now_utc());
+        let mut expiry =
+            TimingExpiry::new(temp.path().to_path_buf())?.with_clock(Arc::new(clock.clone()));
+        let event = create_test_event(5);
+        expiry.add_expiry(event.clone())?;
+        assert!(expiry.next_expired().is_none());
+
+        clock.advance(time::Duration::minutes(10));
+        assert_eq!(expiry.next_expired(), Some(event));
+        expiry.ack()?;
+        assert!(expiry.peek().is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_ack_past_expiry_succeeds() -> LaikaResult<()> {
         let (mut expiry, _temp) = create_test_expiry();
@@ -229,4 +374,113 @@ mod tests {
         assert_eq!(expiry.peek(), Some(event2));
         Ok(())
     }
+
+    #[test]
+    fn test_nack_removes_rechecks_for_rule() -> LaikaResult<()> {
+        let (mut expiry, _temp) = create_test_expiry();
+
+        let recheck = create_test_event(5);
+        let other_rule = EventExpiry::new(
+            recheck.expires_at,
+            recheck.correlation_id.clone(),
+            "OtherRule".to_string(),
+        );
+        let window_close = EventExpiry::window_close(
+            recheck.expires_at + time::Duration::minutes(1),
+            recheck.correlation_id.clone(),
+            recheck.event_rule.clone(),
+        );
+        expiry.add_expiries(vec![
+            recheck.clone(),
+            create_test_event(10),
+            other_rule.clone(),
+            window_close.clone(),
+        ])?;
+
+        expiry.nack("test-id", "ExampleEventRule")?;
+
+        assert_eq!(expiry.pending()?, vec![other_rule, window_close]);
+        // Nothing left to remove
+        expiry.nack("test-id", "ExampleEventRule")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_expiry_replaces_pending_for_rule_and_correlation() -> LaikaResult<()> {
+        let (mut expiry, _temp) = create_test_expiry();
+
+        for minutes in 1..=50 {
+            expiry.add_expiry(create_test_event(minutes))?;
+        }
+        let latest = create_test_event(60);
+        expiry.add_expiry(latest.clone())?;
+
+        assert_eq!(expiry.pending()?, vec![latest]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_expiries_leave_pending_in_place() -> LaikaResult<()> {
+        let (mut expiry, _temp) = create_test_expiry();
+        let now = OffsetDateTime::now_utc();
+        let missed = EventExpiry::scheduled(now - time::Duration::hours(1), "hourly".to_string());
+        expiry.add_expiry(missed.clone())?;
+
+        let next = EventExpiry::scheduled(now + time::Duration::hours(1), "hourly".to_string());
+        let daily = EventExpiry::scheduled(now + time::Duration::days(1), "daily".to_string());
+        expiry.add_missing_expiries(vec![next, daily.clone()])?;
+        assert_eq!(expiry.pending()?, vec![missed, daily]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_timer_file_without_version() -> LaikaResult<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let expires_at = OffsetDateTime::now_utc() + time::Duration::minutes(5);
+        // Expiries were written without a header or kind, as structs are written as tuples
+        let written: Vec<(OffsetDateTime, String, String)> = vec![(
+            expires_at,
+            "test-id".to_string(),
+            "ExampleEventRule".to_string(),
+        )];
+        std::fs::write(temp.path(), bincode::serialize(&written).unwrap()).unwrap();
+
+        let mut expiry = TimingExpiry::new(temp.path().to_path_buf())?;
+        let recheck = EventExpiry::new(
+            expires_at,
+            "test-id".to_string(),
+            "ExampleEventRule".to_string(),
+        );
+        assert_eq!(expiry.peek(), Some(recheck.clone()));
+
+        // Rewritten with a version once anything changes, and still readable
+        let timeout = EventExpiry::timeout(
+            expires_at + time::Duration::minutes(5),
+            "other-id".to_string(),
+            "Rule".to_string(),
+        );
+        expiry.add_expiry(timeout.clone())?;
+        assert!(std::fs::read(temp.path()).unwrap().starts_with(FILE_MAGIC));
+        let mut reloaded = TimingExpiry::new(temp.path().to_path_buf())?;
+        assert_eq!(reloaded.pending()?, vec![recheck, timeout]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_wakeups_ignores_new_expiries() -> LaikaResult<()> {
+        let temp = NamedTempFile::new().unwrap();
+        let mut expiry = TimingExpiry::new(temp.path().to_path_buf())?.with_max_wakeups(2);
+
+        let first = create_test_event(5);
+        let second = EventExpiry::new(first.expires_at, "second".to_string(), "Rule".to_string());
+        let third = EventExpiry::new(first.expires_at, "third".to_string(), "Rule".to_string());
+        expiry.add_expiries(vec![first.clone(), second.clone(), third])?;
+        assert_eq!(expiry.pending()?.len(), 2);
+
+        // Replacing a pending expiry is still allowed at the limit
+        let rescheduled = create_test_event(10);
+        expiry.add_expiry(rescheduled.clone())?;
+        assert_eq!(expiry.pending()?, vec![second, rescheduled]);
+        Ok(())
+    }
 }
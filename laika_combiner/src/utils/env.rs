@@ -0,0 +1,126 @@
+use crate::errors::{LaikaError, LaikaResult};
+use regex::Regex;
+use std::sync::LazyLock;
+
+static ENV_REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$(\$)?\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").unwrap());
+
+/// Replace `${NAME}` references with the value of the environment variable `NAME`, and
+/// `${NAME:-default}` references with `default` when `NAME` is unset or empty. `$${NAME}` is
+/// left as a literal `${NAME}`.
+///
+/// Template expressions (`${{ ... }}`) are left untouched.
+pub fn interpolate_env(value: &str) -> LaikaResult<String> {
+    replace_references(value, |resolved| resolved)
+}
+
+/// Replace references as [`interpolate_env`] does, throughout config that's yet to be parsed.
+/// `${{` in the values is escaped as `$${{`, so templates read it as text rather than as a
+/// template of their own.
+pub fn interpolate_config(value: &str) -> LaikaResult<String> {
+    replace_references(value, |resolved| resolved.replace("${{", "$${{"))
+}
+
+fn replace_references(value: &str, escape: impl Fn(String) -> String) -> LaikaResult<String> {
+    let mut output = String::with_capacity(value.len());
+    let mut last_match = 0;
+    for captures in ENV_REFERENCE.captures_iter(value) {
+        let reference = captures.get(0).expect("Capture group 0 is always present");
+        output.push_str(&value[last_match..reference.start()]);
+        last_match = reference.end();
+        if captures.get(1).is_some() {
+            output.push_str(&reference.as_str()[1..]);
+            continue;
+        }
+        let name = &captures[2];
+        let resolved = match (std::env::var(name), captures.get(3)) {
+            (Ok(resolved), Some(default)) if resolved.is_empty() => default.as_str().to_string(),
+            (Ok(resolved), _) => resolved,
+            (Err(_), Some(default)) => default.as_str().to_string(),
+            (Err(_), None) => {
+                return Err(LaikaError::Generic(format!(
+                    "Environment variable {} is not set",
+                    name
+                )))
+            }
+        };
+        output.push_str(&escape(resolved));
+    }
+    output.push_str(&value[last_match..]);
+    Ok(output)
+}
+
+/// Prefix of secrets read from a file, such as `file:/run/secrets/rabbitmq_password`
+pub const SECRET_FILE_PREFIX: &str = "file:";
+
+/// Resolve a credential from the config, so it needn't be written into the config itself.
+///
+/// `file:<path>` is replaced with the contents of the file, without surrounding whitespace, and
+/// anything else has its `${NAME}` references replaced as by [`interpolate_env`].
+pub fn resolve_secret(value: &str) -> LaikaResult<String> {
+    match value.strip_prefix(SECRET_FILE_PREFIX) {
+        Some(path) => std::fs::read_to_string(path)
+            .map(|secret| secret.trim().to_string())
+            .map_err(|e| LaikaError::Generic(format!("Could not read secret {}: {}", path, e))),
+        None => interpolate_env(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate_env() {
+        std::env::set_var("LAIKA_TEST_TOKEN", "secret");
+        assert_eq!(
+            interpolate_env("Bearer ${LAIKA_TEST_TOKEN}").unwrap(),
+            "Bearer secret"
+        );
+        assert_eq!(
+            interpolate_env("${{ userId }}/${LAIKA_TEST_TOKEN}").unwrap(),
+            "${{ userId }}/secret"
+        );
+        assert!(interpolate_env("${LAIKA_TEST_UNSET_VARIABLE}").is_err());
+        assert_eq!(
+            interpolate_env("amqp://${LAIKA_TEST_UNSET_VARIABLE:-localhost}:5672").unwrap(),
+            "amqp://localhost:5672"
+        );
+        assert_eq!(
+            interpolate_env("${LAIKA_TEST_TOKEN:-default}/$${LAIKA_TEST_TOKEN}").unwrap(),
+            "secret/${LAIKA_TEST_TOKEN}"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_config() {
+        std::env::set_var("LAIKA_TEST_TEMPLATED", "a${{ secret }}b");
+        assert_eq!(
+            interpolate_config("token: ${LAIKA_TEST_TEMPLATED}").unwrap(),
+            "token: a$${{ secret }}b"
+        );
+        assert_eq!(
+            interpolate_env("${LAIKA_TEST_TEMPLATED}").unwrap(),
+            "a${{ secret }}b"
+        );
+    }
+
+    #[test]
+    fn test_resolve_secret() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("password");
+        std::fs::write(&path, "hunter2\n").unwrap();
+        assert_eq!(
+            resolve_secret(format!("file:{}", path.display()).as_str()).unwrap(),
+            "hunter2"
+        );
+        assert!(
+            resolve_secret(format!("file:{}", dir.path().join("missing").display()).as_str())
+                .is_err()
+        );
+
+        std::env::set_var("LAIKA_TEST_SECRET", "s3cret");
+        assert_eq!(resolve_secret("${LAIKA_TEST_SECRET}").unwrap(), "s3cret");
+        assert_eq!(resolve_secret("guest").unwrap(), "guest");
+    }
+}
@@ -1,17 +1,363 @@
 use crate::errors::{LaikaError, LaikaResult};
 use serde_json::Value;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
 
-pub fn extract_json_field<'a>(value: &'a Value, field_path: &str) -> LaikaResult<&'a Value> {
-    let path = field_path.strip_prefix('$').unwrap_or(field_path);
-    let mut current = value;
+/// A step along a JSONPath
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum PathSegment {
+    /// `.name` or `['name']`
+    Field(String),
+    /// `[0]`, or `[-1]` counting back from the end of the array
+    Index(i64),
+    /// `[*]` or `.*`, every element of an array or value of an object
+    Wildcard,
+    /// `[?(@.status == 'open')]`, the elements of an array the filter holds for
+    Filter(Filter),
+}
+
+impl PathSegment {
+    /// Whether the segment leads to at most one value
+    fn is_definite(&self) -> bool {
+        matches!(self, PathSegment::Field(_) | PathSegment::Index(_))
+    }
+}
 
-    for part in path.split('.').filter(|p| !p.is_empty()) {
-        current = current
-            .get(part)
-            .ok_or_else(|| LaikaError::FieldNotFound(part.to_string(), field_path.to_string()))?;
+impl Display for PathSegment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathSegment::Field(name) => write!(f, "{}", name),
+            PathSegment::Index(index) => write!(f, "[{}]", index),
+            PathSegment::Wildcard => write!(f, "[*]"),
+            PathSegment::Filter(_) => write!(f, "[?]"),
+        }
     }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn holds(self, value: &Value, expected: &Value) -> bool {
+        let ordering = match (value, expected) {
+            (Value::Number(value), Value::Number(expected)) => value
+                .as_f64()
+                .zip(expected.as_f64())
+                .and_then(|(value, expected)| value.partial_cmp(&expected)),
+            (Value::String(value), Value::String(expected)) => Some(value.cmp(expected)),
+            _ => (value == expected).then_some(Ordering::Equal),
+        };
+        match self {
+            Comparison::Eq => ordering == Some(Ordering::Equal),
+            Comparison::Ne => ordering != Some(Ordering::Equal),
+            Comparison::Lt => ordering == Some(Ordering::Less),
+            Comparison::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Comparison::Gt => ordering == Some(Ordering::Greater),
+            Comparison::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+/// Predicate on the elements of an array. Without a comparison it checks the path relative to the
+/// element (`@`) leads somewhere.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Filter {
+    path: Vec<PathSegment>,
+    comparison: Option<(Comparison, Value)>,
+}
+
+impl Filter {
+    pub(crate) fn holds(&self, item: &Value) -> bool {
+        let mut found = Vec::new();
+        select(item, &self.path, &mut found);
+        found.into_iter().any(|value| match &self.comparison {
+            None => true,
+            Some((comparison, expected)) => comparison.holds(value, expected),
+        })
+    }
+}
 
-    Ok(current)
+struct PathParser<'a> {
+    path: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl PathParser<'_> {
+    fn error(&self, message: &str) -> LaikaError {
+        LaikaError::JsonPathError(format!(
+            "Invalid path {}: {} at position {}",
+            self.path, message, self.pos
+        ))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        let eaten = self.peek() == Some(ch);
+        if eaten {
+            self.pos += 1;
+        }
+        eaten
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Segments up to the end of the path, or the first character `stop` holds for
+    fn segments(&mut self, stop: fn(char) -> bool) -> LaikaResult<Vec<PathSegment>> {
+        let mut segments = Vec::new();
+        while let Some(ch) = self.peek() {
+            if stop(ch) {
+                break;
+            }
+            match ch {
+                '.' => self.pos += 1,
+                '[' => {
+                    self.pos += 1;
+                    segments.push(self.bracket()?);
+                }
+                _ => segments.push(self.name(stop)),
+            }
+        }
+        Ok(segments)
+    }
+
+    fn name(&mut self, stop: fn(char) -> bool) -> PathSegment {
+        let start = self.pos;
+        while let Some(ch) = self.peek() {
+            if ch == '.' || ch == '[' || stop(ch) {
+                break;
+            }
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        if name == "*" {
+            PathSegment::Wildcard
+        } else {
+            PathSegment::Field(name)
+        }
+    }
+
+    fn bracket(&mut self) -> LaikaResult<PathSegment> {
+        self.skip_whitespace();
+        let segment = match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                PathSegment::Wildcard
+            }
+            Some(quote @ ('\'' | '"')) => {
+                self.pos += 1;
+                PathSegment::Field(self.quoted(quote)?)
+            }
+            Some('?') => {
+                self.pos += 1;
+                PathSegment::Filter(self.filter()?)
+            }
+            _ => {
+                let start = self.pos;
+                self.eat('-');
+                while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+                let index: String = self.chars[start..self.pos].iter().collect();
+                PathSegment::Index(
+                    index
+                        .parse()
+                        .map_err(|_| self.error("expected an index, *, a name or a filter"))?,
+                )
+            }
+        };
+        self.skip_whitespace();
+        if !self.eat(']') {
+            return Err(self.error("expected ]"));
+        }
+        Ok(segment)
+    }
+
+    fn quoted(&mut self, quote: char) -> LaikaResult<String> {
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unclosed string")),
+                Some('\\') => {
+                    self.pos += 1;
+                    if let Some(ch) = self.peek() {
+                        text.push(ch);
+                        self.pos += 1;
+                    }
+                }
+                Some(ch) if ch == quote => {
+                    self.pos += 1;
+                    return Ok(text);
+                }
+                Some(ch) => {
+                    text.push(ch);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    fn filter(&mut self) -> LaikaResult<Filter> {
+        self.skip_whitespace();
+        let parenthesised = self.eat('(');
+        self.skip_whitespace();
+        if !self.eat('@') {
+            return Err(self.error("expected a filter on @"));
+        }
+        let path = self
+            .segments(|ch| ch.is_whitespace() || matches!(ch, '=' | '!' | '<' | '>' | ')' | ']'))?;
+        self.skip_whitespace();
+        let comparison = match self.comparison() {
+            Some(comparison) => {
+                self.skip_whitespace();
+                Some((comparison, self.literal()?))
+            }
+            None => None,
+        };
+        self.skip_whitespace();
+        if parenthesised && !self.eat(')') {
+            return Err(self.error("expected )"));
+        }
+        Ok(Filter { path, comparison })
+    }
+
+    fn comparison(&mut self) -> Option<Comparison> {
+        let next: String = self.chars[self.pos..].iter().take(2).collect();
+        let (comparison, length) = match next.as_str() {
+            "==" => (Comparison::Eq, 2),
+            "!=" => (Comparison::Ne, 2),
+            "<=" => (Comparison::Le, 2),
+            ">=" => (Comparison::Ge, 2),
+            _ if next.starts_with('<') => (Comparison::Lt, 1),
+            _ if next.starts_with('>') => (Comparison::Gt, 1),
+            _ => return None,
+        };
+        self.pos += length;
+        Some(comparison)
+    }
+
+    /// A string in single or double quotes, or any other JSON value
+    fn literal(&mut self) -> LaikaResult<Value> {
+        if let Some(quote @ ('\'' | '"')) = self.peek() {
+            self.pos += 1;
+            return self.quoted(quote).map(Value::String);
+        }
+        let start = self.pos;
+        while let Some(ch) = self.peek() {
+            if ch.is_whitespace() || ch == ')' || ch == ']' {
+                break;
+            }
+            self.pos += 1;
+        }
+        let literal: String = self.chars[start..self.pos].iter().collect();
+        serde_json::from_str(literal.as_str())
+            .map_err(|_| self.error("expected a value to compare with"))
+    }
+}
+
+/// Parse a JSONPath such as `$.items[0].id`, `$.items[*].id` or
+/// `$.items[?(@.status == 'open')].id`. The leading `$` is optional, and an empty path is the
+/// root.
+pub(crate) fn parse_path(path: &str) -> LaikaResult<Vec<PathSegment>> {
+    let mut parser = PathParser {
+        path,
+        chars: path.strip_prefix('$').unwrap_or(path).chars().collect(),
+        pos: 0,
+    };
+    parser.segments(|_| false)
+}
+
+/// Position of `index` within an array of `len` elements, counting back from the end if it's
+/// negative
+pub(crate) fn resolve_index(len: usize, index: i64) -> Option<usize> {
+    let position = usize::try_from(index.unsigned_abs()).ok()?;
+    if index < 0 {
+        len.checked_sub(position)
+    } else {
+        (position < len).then_some(position)
+    }
+}
+
+/// Every value one step along from `value`
+fn step<'a>(value: &'a Value, segment: &PathSegment, found: &mut Vec<&'a Value>) {
+    match (segment, value) {
+        (PathSegment::Field(name), value) => found.extend(value.get(name.as_str())),
+        (PathSegment::Index(index), Value::Array(items)) => {
+            found.extend(resolve_index(items.len(), *index).map(|index| &items[index]))
+        }
+        (PathSegment::Wildcard, Value::Array(items)) => found.extend(items),
+        (PathSegment::Wildcard, Value::Object(fields)) => found.extend(fields.values()),
+        (PathSegment::Filter(filter), Value::Array(items)) => {
+            found.extend(items.iter().filter(|item| filter.holds(item)))
+        }
+        (PathSegment::Filter(filter), Value::Object(fields)) => {
+            found.extend(fields.values().filter(|item| filter.holds(item)))
+        }
+        _ => {}
+    }
+}
+
+fn select<'a>(value: &'a Value, segments: &[PathSegment], found: &mut Vec<&'a Value>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        found.push(value);
+        return;
+    };
+    let mut next = Vec::new();
+    step(value, segment, &mut next);
+    for value in next {
+        select(value, rest, found);
+    }
+}
+
+/// Every value `field_path` leads to within `value`
+pub fn extract_json_values<'a>(value: &'a Value, field_path: &str) -> LaikaResult<Vec<&'a Value>> {
+    let mut found = Vec::new();
+    select(value, &parse_path(field_path)?, &mut found);
+    Ok(found)
+}
+
+/// Value `field_path` leads to within `value`. Paths with wildcards or filters can lead to many
+/// values, so give an array of everything they match.
+pub fn extract_json_field<'a>(value: &'a Value, field_path: &str) -> LaikaResult<Cow<'a, Value>> {
+    let segments = parse_path(field_path)?;
+    if segments.iter().all(PathSegment::is_definite) {
+        let mut current = value;
+        for segment in &segments {
+            let mut found = Vec::new();
+            step(current, segment, &mut found);
+            current = found.pop().ok_or_else(|| {
+                LaikaError::FieldNotFound(segment.to_string(), field_path.to_string())
+            })?;
+        }
+        return Ok(Cow::Borrowed(current));
+    }
+
+    let mut found = Vec::new();
+    select(value, &segments, &mut found);
+    if found.is_empty() {
+        return Err(LaikaError::FieldNotFound(
+            field_path.to_string(),
+            field_path.to_string(),
+        ));
+    }
+    Ok(Cow::Owned(Value::Array(
+        found.into_iter().cloned().collect(),
+    )))
 }
 
 #[cfg(test)]
@@ -31,11 +377,14 @@ mod tests {
             }
         });
 
-        assert_eq!(extract_json_field(&json, "$.user.id"), Ok(&json! {123}));
+        assert_eq!(
+            extract_json_field(&json, "$.user.id"),
+            Ok(Cow::Borrowed(&json! {123}))
+        );
 
         assert_eq!(
             extract_json_field(&json, "user.details.email"),
-            Ok(&json! {"test@example.com"})
+            Ok(Cow::Borrowed(&json! {"test@example.com"}))
         );
 
         assert!(matches!(
@@ -48,4 +397,121 @@ mod tests {
             Err(LaikaError::FieldNotFound(..))
         ));
     }
+
+    fn order() -> Value {
+        json!({
+            "id": "order-1",
+            "items": [
+                {"sku": "a", "qty": 1, "status": "open"},
+                {"sku": "b", "qty": 3, "status": "shipped"},
+                {"sku": "c", "qty": 5, "status": "open", "gift": true}
+            ],
+            "tags": {"priority": "high"}
+        })
+    }
+
+    #[test]
+    fn test_indexes() {
+        let order = order();
+        assert_eq!(
+            extract_json_field(&order, "$.items[0].sku")
+                .unwrap()
+                .as_ref(),
+            &json!("a")
+        );
+        assert_eq!(
+            extract_json_field(&order, "$.items[-1].sku")
+                .unwrap()
+                .as_ref(),
+            &json!("c")
+        );
+        assert_eq!(
+            extract_json_field(&order, "$['tags']['priority']")
+                .unwrap()
+                .as_ref(),
+            &json!("high")
+        );
+        assert!(matches!(
+            extract_json_field(&order, "$.items[3].sku"),
+            Err(LaikaError::FieldNotFound(..))
+        ));
+        assert!(matches!(
+            extract_json_field(&order, "$.items[-4]"),
+            Err(LaikaError::FieldNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn test_wildcards() {
+        let order = order();
+        assert_eq!(
+            extract_json_field(&order, "$.items[*].sku")
+                .unwrap()
+                .as_ref(),
+            &json!(["a", "b", "c"])
+        );
+        assert_eq!(
+            extract_json_field(&order, "$.tags.*").unwrap().as_ref(),
+            &json!(["high"])
+        );
+        assert!(matches!(
+            extract_json_field(&order, "$.items[*].missing"),
+            Err(LaikaError::FieldNotFound(..))
+        ));
+    }
+
+    #[test]
+    fn test_filters() {
+        let order = order();
+        assert_eq!(
+            extract_json_field(&order, "$.items[?(@.status == 'open')].sku")
+                .unwrap()
+                .as_ref(),
+            &json!(["a", "c"])
+        );
+        assert_eq!(
+            extract_json_field(&order, "$.items[?@.qty>1].sku")
+                .unwrap()
+                .as_ref(),
+            &json!(["b", "c"])
+        );
+        assert_eq!(
+            extract_json_field(&order, "$.items[?(@.gift)].sku")
+                .unwrap()
+                .as_ref(),
+            &json!(["c"])
+        );
+        assert_eq!(
+            extract_json_values(&order, "$.items[?(@.qty <= 1)]")
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            extract_json_field(&order, "$.items[?(@.status != \"open\")].sku")
+                .unwrap()
+                .as_ref(),
+            &json!(["b"])
+        );
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        for path in [
+            "$.items[",
+            "$.items[x]",
+            "$.items['a]",
+            "$.items[?(@.qty > )]",
+            "$.items[?(@.qty > 1]",
+            "$.items[?(qty > 1)]",
+        ] {
+            assert!(
+                matches!(parse_path(path), Err(LaikaError::JsonPathError(_))),
+                "{} should be invalid",
+                path
+            );
+        }
+        assert_eq!(parse_path("$").unwrap(), vec![]);
+        assert_eq!(parse_path("").unwrap(), vec![]);
+    }
 }
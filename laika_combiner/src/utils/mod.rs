@@ -1,2 +1,3 @@
+pub(crate) mod env;
 pub(crate) mod extract_json;
 pub(crate) mod parse_time;
@@ -1,6 +1,22 @@
 use crate::errors::{LaikaError, LaikaResult};
 use time::Duration;
 
+/// A length of time that's either exact, or counted in business time on a calendar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeSpan {
+    Exact(Duration),
+    /// Working days, as `2bd`
+    BusinessDays(i64),
+    /// Hours within working hours, as `4bh`
+    BusinessHours(i64),
+}
+
+fn parse_amount(value: &str) -> LaikaResult<i64> {
+    value
+        .parse()
+        .map_err(|_| LaikaError::Generic("invalid number".to_string()))
+}
+
 pub fn parse_time_str(time_str: &str) -> LaikaResult<Duration> {
     let (value, unit) = time_str.split_at(
         time_str
@@ -9,9 +25,7 @@ pub fn parse_time_str(time_str: &str) -> LaikaResult<Duration> {
             .ok_or(LaikaError::Generic("missing unit".to_string()))?,
     );
 
-    let amount: i64 = value
-        .parse()
-        .map_err(|_| LaikaError::Generic("invalid number".to_string()))?;
+    let amount = parse_amount(value)?;
 
     match unit {
         "ms" => Ok(Duration::milliseconds(amount)),
@@ -23,6 +37,26 @@ pub fn parse_time_str(time_str: &str) -> LaikaResult<Duration> {
     }
 }
 
+/// Parse a duration as `parse_time_str` does, or in business days (`bd`) or hours (`bh`)
+pub fn parse_time_span(time_str: &str) -> LaikaResult<TimeSpan> {
+    let business = |value: &str| {
+        let amount = parse_amount(value)?;
+        if amount < 0 {
+            return Err(LaikaError::Generic(
+                "business time can't be negative".to_string(),
+            ));
+        }
+        Ok(amount)
+    };
+    if let Some(days) = time_str.strip_suffix("bd") {
+        return business(days).map(TimeSpan::BusinessDays);
+    }
+    if let Some(hours) = time_str.strip_suffix("bh") {
+        return business(hours).map(TimeSpan::BusinessHours);
+    }
+    parse_time_str(time_str).map(TimeSpan::Exact)
+}
+
 #[test]
 fn test_parse_time_str() {
     assert_eq!(parse_time_str("30m").unwrap(), Duration::seconds(1800));
@@ -30,3 +64,15 @@ fn test_parse_time_str() {
     assert_eq!(parse_time_str("24h").unwrap(), Duration::seconds(86400));
     assert!(parse_time_str("invalid").is_err());
 }
+
+#[test]
+fn test_parse_time_span() {
+    assert_eq!(
+        parse_time_span("30m").unwrap(),
+        TimeSpan::Exact(Duration::minutes(30))
+    );
+    assert_eq!(parse_time_span("2bd").unwrap(), TimeSpan::BusinessDays(2));
+    assert_eq!(parse_time_span("4bh").unwrap(), TimeSpan::BusinessHours(4));
+    assert!(parse_time_span("-1bd").is_err());
+    assert!(parse_time_str("2bd").is_err());
+}
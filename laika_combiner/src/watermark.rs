@@ -0,0 +1,131 @@
+use crate::broker::{EventExpiry, ExpiryKind};
+use crate::config::builder::invalid_at;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::utils::extract_json::extract_json_field;
+use crate::utils::parse_time::parse_time_str;
+use serde::Deserialize;
+use serde_json::Value;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct WatermarkConfig {
+    /// How far behind the latest event time an event can be, and still be in time for wakeups
+    #[serde(default)]
+    max_out_of_orderness: Option<String>,
+}
+
+impl WatermarkConfig {
+    pub(crate) fn parse(&self) -> LaikaResult<Watermark> {
+        Ok(Watermark {
+            out_of_orderness: self
+                .max_out_of_orderness
+                .as_deref()
+                .map(parse_time_str)
+                .transpose()
+                .map_err(invalid_at("maxOutOfOrderness"))?
+                .unwrap_or(Duration::ZERO),
+            latest: None,
+        })
+    }
+}
+
+/// How far event time has progressed, for processors whose events carry the time they happened.
+///
+/// Wakeups are timed from event times, which can be long past when events are replayed or a
+/// broker delivers them late, so they wait for the watermark to pass them as well as the clock.
+#[derive(Clone, Debug)]
+pub struct Watermark {
+    out_of_orderness: Duration,
+    latest: Option<OffsetDateTime>,
+}
+
+impl Watermark {
+    pub(crate) fn observe(&mut self, event_time: OffsetDateTime) {
+        self.latest = Some(
+            self.latest
+                .map_or(event_time, |latest| latest.max(event_time)),
+        );
+    }
+
+    /// Time by which every earlier event is expected to have arrived, once any event has
+    pub fn current(&self) -> Option<OffsetDateTime> {
+        self.latest.map(|latest| latest - self.out_of_orderness)
+    }
+
+    /// Whether the watermark has passed an expiry. Schedules run on the clock, so are always
+    /// past it.
+    pub(crate) fn passed(&self, expiry: &EventExpiry) -> bool {
+        expiry.kind == ExpiryKind::Scheduled
+            || self
+                .current()
+                .is_some_and(|current| current >= expiry.expires_at)
+    }
+}
+
+/// When an event happened, read from an RFC 3339 timestamp or Unix seconds at `path`
+pub(crate) fn event_time(data: &Value, path: &str) -> LaikaResult<OffsetDateTime> {
+    match extract_json_field(data, path).as_deref() {
+        Ok(Value::String(timestamp)) => OffsetDateTime::parse(timestamp, &Rfc3339).map_err(|e| {
+            LaikaError::MissingInput(format!("Invalid event time at {}: {}", path, e))
+        }),
+        Ok(Value::Number(seconds)) => seconds
+            .as_f64()
+            .and_then(|seconds| {
+                let nanos = (seconds.fract() * 1e9).round() as i64;
+                OffsetDateTime::from_unix_timestamp(seconds.trunc() as i64)
+                    .ok()
+                    .map(|time| time + Duration::nanoseconds(nanos))
+            })
+            .ok_or_else(|| {
+                LaikaError::MissingInput(format!("Invalid event time at {}: {}", path, seconds))
+            }),
+        _ => Err(LaikaError::MissingInput(format!(
+            "No event time at {}",
+            path
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_event_time() {
+        let data = json!({"at": "2024-03-01T12:00:00Z", "ts": 1709294400.5});
+        assert_eq!(
+            event_time(&data, "$.at").unwrap(),
+            datetime!(2024-03-01 12:00:00 UTC)
+        );
+        assert_eq!(
+            event_time(&data, "$.ts").unwrap(),
+            datetime!(2024-03-01 12:00:00.5 UTC)
+        );
+        assert!(event_time(&data, "$.missing").is_err());
+    }
+
+    #[test]
+    fn test_watermark_passed() {
+        let mut watermark = WatermarkConfig {
+            max_out_of_orderness: Some("1m".to_string()),
+        }
+        .parse()
+        .unwrap();
+        let expiry = EventExpiry::new(
+            datetime!(2024-03-01 12:05:00 UTC),
+            "order1".to_string(),
+            "unpaid".to_string(),
+        );
+        assert!(!watermark.passed(&expiry));
+        watermark.observe(datetime!(2024-03-01 12:05:30 UTC));
+        assert!(!watermark.passed(&expiry));
+        // Events arriving out of order don't move the watermark back
+        watermark.observe(datetime!(2024-03-01 12:01:00 UTC));
+        watermark.observe(datetime!(2024-03-01 12:06:00 UTC));
+        assert!(watermark.passed(&expiry));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::broker::EventExpiry;
+use crate::errors::{LaikaError, LaikaResult};
+use crate::event::{CorrelatedEvent, EventLike, NonCorrelatedEvent};
+use crate::storage::StorageKV;
+use crate::tenancy::TenancyConfig;
+use serde_json::{json, Value};
+use time::{Duration, OffsetDateTime};
+
+/// Group used when a window isn't grouped by a field
+const GLOBAL_GROUP: &str = "*";
+
+#[derive(Clone, Debug)]
+pub enum WindowKind {
+    /// Fixed, non-overlapping windows of `size`, aligned to the epoch
+    Tumbling,
+    /// Windows of `size`, closing every `slide`
+    Sliding { slide: Duration },
+}
+
+/// A window over uncorrelated events, optionally grouped by a field in the event.
+///
+/// Events are buffered per rule and group until the window closes, at which point the rule is
+/// evaluated once against every event within the window.
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub(crate) kind: WindowKind,
+    pub(crate) size: Duration,
+    pub(crate) group_by: Option<String>, // JSONPath expression
+}
+
+/// The events within a window once it has closed.
+pub(crate) struct ClosedWindow {
+    pub(crate) events: Vec<CorrelatedEvent>,
+    pub(crate) group: String,
+    pub(crate) start: OffsetDateTime,
+    pub(crate) end: OffsetDateTime,
+    /// Wakeup to close the following window, if events remain buffered for it
+    pub(crate) next: Option<EventExpiry>,
+}
+
+impl ClosedWindow {
+    pub(crate) fn describe(&self) -> Value {
+        json!({
+            "group": self.group,
+            "start": self.start.unix_timestamp(),
+            "end": self.end.unix_timestamp(),
+        })
+    }
+}
+
+impl WindowConfig {
+    pub(crate) fn new(
+        kind: WindowKind,
+        size: Duration,
+        group_by: Option<String>,
+    ) -> LaikaResult<Self> {
+        if size <= Duration::ZERO {
+            return Err(LaikaError::Generic(
+                "Window size must be positive".to_string(),
+            ));
+        }
+        if let WindowKind::Sliding { slide } = kind {
+            if slide <= Duration::ZERO || slide > size {
+                return Err(LaikaError::Generic(
+                    "Window slide must be positive and no larger than the window size".to_string(),
+                ));
+            }
+        }
+        Ok(Self {
+            kind,
+            size,
+            group_by,
+        })
+    }
+
+    fn group(&self, event: &NonCorrelatedEvent) -> Option<String> {
+        match &self.group_by {
+            None => Some(GLOBAL_GROUP.to_string()),
+            Some(path) => event.try_extract(path).map(|value| match value {
+                Value::String(s) => s,
+                other => other.to_string(),
+            }),
+        }
+    }
+
+    /// Floor `at` to a multiple of `step` since the epoch
+    fn align(at: OffsetDateTime, step: Duration) -> OffsetDateTime {
+        let step_nanos = step.whole_nanoseconds();
+        let at_nanos = at.unix_timestamp_nanos();
+        OffsetDateTime::from_unix_timestamp_nanos(at_nanos - at_nanos.rem_euclid(step_nanos))
+            .expect("Aligned timestamp is always earlier than a valid timestamp")
+    }
+
+    fn closes_at(&self, received: OffsetDateTime) -> OffsetDateTime {
+        match self.kind {
+            WindowKind::Tumbling => Self::align(received, self.size) + self.size,
+            WindowKind::Sliding { slide } => Self::align(received, slide) + slide,
+        }
+    }
+
+    /// Buffer an event into its window, returning the wakeup to close the window if the event
+    /// opened it.
+    pub(crate) fn buffer(
+        &self,
+        storage_kv: &StorageKV,
+        rule: &str,
+        event: &NonCorrelatedEvent,
+        tenant: Option<&str>,
+    ) -> LaikaResult<Option<EventExpiry>> {
+        let Some(group) = self.group(event) else {
+            tracing::debug!("Event has no group for window on {}, skipping", rule);
+            return Ok(None);
+        };
+        // Tenants have their own windows, as they have their own correlations
+        let group = match tenant {
+            Some(tenant) => TenancyConfig::scope(tenant, group.as_str()),
+            None => group,
+        };
+        let windowed_event = CorrelatedEvent {
+            received: event.received,
+            correlation_id: group.clone(),
+            event_type: event.event_type.clone(),
+            data: event.data.clone(),
+            meta: Default::default(),
+            links: Default::default(),
+        };
+        let opened = storage_kv.append_window_event(rule, &group, windowed_event)?;
+        Ok(opened.then(|| {
+            EventExpiry::window_close(self.closes_at(event.received), group, rule.to_string())
+        }))
+    }
+
+    /// Close the window that `expiry` was scheduled for, removing events no later window needs.
+    pub(crate) fn close(
+        &self,
+        storage_kv: &StorageKV,
+        rule: &str,
+        expiry: &EventExpiry,
+    ) -> LaikaResult<ClosedWindow> {
+        let group = expiry.correlation_id.clone();
+        let end = expiry.expires_at;
+        let start = end - self.size;
+        let buffered = storage_kv.read_window_events(rule, &group)?;
+        let (events, next) = match self.kind {
+            WindowKind::Tumbling => {
+                storage_kv.clear_window_events(rule, &group)?;
+                (buffered, None)
+            }
+            WindowKind::Sliding { slide } => {
+                let next_start = end + slide - self.size;
+                let events = buffered
+                    .iter()
+                    .filter(|event| event.received >= start && event.received < end)
+                    .cloned()
+                    .collect();
+                let remaining: Vec<CorrelatedEvent> = buffered
+                    .into_iter()
+                    .filter(|event| event.received >= next_start)
+                    .collect();
+                if remaining.is_empty() {
+                    storage_kv.clear_window_events(rule, &group)?;
+                    (events, None)
+                } else {
+                    storage_kv.write_window_events(rule, &group, remaining)?;
+                    (
+                        events,
+                        Some(EventExpiry::window_close(
+                            end + slide,
+                            group.clone(),
+                            rule.to_string(),
+                        )),
+                    )
+                }
+            }
+        };
+        Ok(ClosedWindow {
+            events,
+            group,
+            start,
+            end,
+            next,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tumbling_windows_align_to_size() {
+        let window = WindowConfig::new(WindowKind::Tumbling, Duration::minutes(5), None).unwrap();
+        let received = OffsetDateTime::from_unix_timestamp(7 * 60 + 12).unwrap();
+        assert_eq!(
+            window.closes_at(received),
+            OffsetDateTime::from_unix_timestamp(10 * 60).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_sliding_windows_close_every_slide() {
+        let window = WindowConfig::new(
+            WindowKind::Sliding {
+                slide: Duration::minutes(1),
+            },
+            Duration::minutes(5),
+            None,
+        )
+        .unwrap();
+        let received = OffsetDateTime::from_unix_timestamp(7 * 60 + 12).unwrap();
+        assert_eq!(
+            window.closes_at(received),
+            OffsetDateTime::from_unix_timestamp(8 * 60).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_invalid_slide_rejected() {
+        assert!(WindowConfig::new(
+            WindowKind::Sliding {
+                slide: Duration::minutes(10),
+            },
+            Duration::minutes(5),
+            None,
+        )
+        .is_err());
+    }
+}
@@ -1,10 +1,18 @@
 use crate::common::process_file;
 use crate::common::test_utils::TestCase;
+use laika_combiner::action::EventAction;
+use laika_combiner::clock::TestClock;
 use laika_combiner::config::builder::EventProcessorYamlSpec;
 use laika_combiner::config::EventProcessorConfig;
-use laika_combiner::EventProcessor;
+use laika_combiner::errors::LaikaError;
+use laika_combiner::storage::StorageKVBuilder;
+use laika_combiner::timing::TimingExpiry;
+use laika_combiner::{EventProcessor, Runtime};
+use std::fs;
 use std::fs::File;
 use std::io::Write;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
 
 #[test]
 pub fn test_single_event_processing() {
@@ -22,7 +30,8 @@ pub fn test_single_event_processing() {
     let processor_spec: EventProcessorYamlSpec = serde_yaml::from_str(&test_case.config()).unwrap();
     let processor: EventProcessor = EventProcessorConfig::try_from(&processor_spec)
         .unwrap()
-        .build();
+        .build()
+        .unwrap();
     let result = process_file(processor, test_case.input.clone());
     let mut result_file = File::create(test_case.output_path()).unwrap();
     for value in result {
@@ -34,3 +43,1255 @@ pub fn test_single_event_processing() {
         assert!(false);
     }
 }
+
+#[test]
+pub fn test_runtime_single_event_processing() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&test_case.config(), state_dir.path()).unwrap();
+    for line in fs::read_to_string(&test_case.input).unwrap().lines() {
+        // local_messages is from the basic config
+        if let Err(e) = runtime.push_event("local_messages", serde_json::from_str(line).unwrap()) {
+            tracing::error!("{:?}", e);
+        }
+    }
+    let mut result_file = File::create(test_case.output_path()).unwrap();
+    for action in runtime.poll_actions().unwrap() {
+        if let EventAction::Emit(emit_action) = action {
+            let line = serde_json::to_string(&emit_action.payload()).unwrap();
+            writeln!(result_file, "{}", line).unwrap();
+        }
+    }
+    if let Err(e) = test_case.compare_output() {
+        tracing::error!("{}", e);
+        assert!(false);
+    }
+}
+
+#[test]
+pub fn test_runtime_audit_log() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let processor_spec: EventProcessorYamlSpec = serde_yaml::from_str(&test_case.config()).unwrap();
+    let processor: EventProcessor = EventProcessorConfig::try_from(&processor_spec)
+        .unwrap()
+        .build()
+        .unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let storage = StorageKVBuilder::new(state_dir.path())
+        .audit_log(true)
+        .build()
+        .unwrap();
+    let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+    let mut runtime = Runtime::new(vec![processor], storage, waker).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test", "data": "example"}),
+        )
+        .unwrap();
+
+    let records = runtime.audit_log("1").unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].source, "local_messages");
+    assert_eq!(records[0].matched[0].event_type, "message");
+    assert_eq!(records[0].actions.len(), 1);
+    assert_eq!(records[0].actions[0]["target"], "local_outbox");
+    assert!(runtime.audit_log("2").unwrap().is_empty());
+}
+
+#[test]
+pub fn test_runtime_explain() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&test_case.config(), state_dir.path()).unwrap();
+
+    let explanations = runtime
+        .explain(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test", "data": "example"}),
+        )
+        .unwrap();
+    assert_eq!(explanations.len(), 1);
+    assert_eq!(explanations[0].correlation_id.as_deref(), Some("1"));
+    let rule = &explanations[0].rules[0];
+    assert_eq!(rule.rule, "exampleTrigger");
+    assert!(rule.requirement.as_ref().unwrap().met);
+    assert!(rule.fired);
+
+    let explanations = runtime
+        .explain(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "other"}),
+        )
+        .unwrap();
+    let rule = &explanations[0].rules[0];
+    assert!(!rule.fired);
+    assert!(rule.predicate.as_ref().unwrap().output.is_null());
+    // Explaining doesn't process the event
+    assert!(runtime.poll_actions().unwrap().is_empty());
+}
+
+#[test]
+pub fn test_runtime_cloud_events_format() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case.config().replace(
+        "      target: local_outbox\n",
+        "      target: local_outbox\n      format:\n        type: cloudevents\n        source: \"/laika/tests\"\n        eventType: \"com.example.processed\"\n",
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test", "data": "example"}),
+        )
+        .unwrap();
+
+    let actions = runtime.poll_actions().unwrap();
+    let [EventAction::Emit(emit)] = actions.as_slice() else {
+        panic!("Expected a single emit, got {:?}", actions);
+    };
+    assert_eq!(emit.format.name(), "cloudevents");
+    let event = emit.clone().payload();
+    assert_eq!(event["specversion"], "1.0");
+    assert_eq!(event["source"], "/laika/tests");
+    assert_eq!(event["type"], "com.example.processed");
+    assert_eq!(event["data"]["id"], "1");
+    assert_eq!(event["data"]["output_data"], "example");
+}
+
+#[test]
+pub fn test_runtime_trigger_metadata() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace("data: trigger.event.data", "data: trigger.meta.topic");
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    let metadata = serde_json::json!({"topic": "orders/eu"})
+        .as_object()
+        .cloned()
+        .unwrap();
+    runtime
+        .push_event_with_metadata(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test"}),
+            metadata,
+        )
+        .unwrap();
+
+    let actions = runtime.poll_actions().unwrap();
+    let [EventAction::Emit(emit)] = actions.as_slice() else {
+        panic!("Expected a single emit, got {:?}", actions);
+    };
+    assert_eq!(emit.clone().payload()["output_data"], "orders/eu");
+}
+
+#[test]
+pub fn test_runtime_static_enrichment() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "path: \"./single_event.jsonl\"",
+            "path: \"./single_event.jsonl\"\n    enrich:\n      env: prod\n      region: eu-west-1",
+        )
+        .replace(
+            "matchAll: {}",
+            "matchAll: {}\n    enrich:\n      region: us-east-1",
+        )
+        .replace(
+            "data: trigger.event.data",
+            "data: `${trigger.event._enrich.env}/${trigger.event._enrich.region}`",
+        );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test"}),
+        )
+        .unwrap();
+
+    let actions = runtime.poll_actions().unwrap();
+    let [EventAction::Emit(emit)] = actions.as_slice() else {
+        panic!("Expected a single emit, got {:?}", actions);
+    };
+    assert_eq!(emit.clone().payload()["output_data"], "prod/us-east-1");
+}
+
+#[test]
+pub fn test_runtime_chain_depth() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&test_case.config(), state_dir.path()).unwrap();
+    // Events looped back through an internal connection carry their depth on to what they emit
+    let metadata = serde_json::json!({ "chainDepth": 2 })
+        .as_object()
+        .unwrap()
+        .clone();
+    runtime
+        .push_event_with_metadata(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test"}),
+            metadata,
+        )
+        .unwrap();
+
+    let actions = runtime.poll_actions().unwrap();
+    let [EventAction::Emit(emit)] = actions.as_slice() else {
+        panic!("Expected a single emit, got {:?}", actions);
+    };
+    assert_eq!(emit.depth, 2);
+}
+
+#[test]
+pub fn test_runtime_state_machine() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let transition = |to: &str| {
+        format!(
+            "          message:\n            to: {}\n            action:\n              target: local_outbox\n              payload:\n                state: \"${{{{ to }}}}\"\n",
+            to
+        )
+    };
+    let config = format!(
+        "{}\nstateMachines:\n  conversation:\n    initial: new\n    states:\n      new:\n        on:\n{}      open:\n        on:\n{}      closed: {{}}\n",
+        test_case.config(),
+        transition("open"),
+        transition("closed")
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    // Messages that aren't of type test are only handled by the state machine
+    for _ in 0..3 {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"id": "1", "type": "chat"}),
+            )
+            .unwrap();
+    }
+
+    let states: Vec<serde_json::Value> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .map(|action| match action {
+            EventAction::Emit(emit) => emit.payload()["state"].clone(),
+            other => panic!("Expected an emit, got {:?}", other),
+        })
+        .collect();
+    // The third message arrives once the conversation is closed, which has no transitions
+    assert_eq!(states, vec!["open", "closed"]);
+}
+
+#[test]
+pub fn test_runtime_linked_correlations() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "    matchAll: {}\n",
+            "    matchKey:\n      type: test\n  payment:\n    from: local_messages\n    matchKey:\n      type: payment\n  assigned:\n    from: local_messages\n    matchKey:\n      type: assigned\n",
+        )
+        .replace(
+            "    key: \"$.id\"\n",
+            "    key: \"$.id\"\n  payment:\n    key: \"$.ref\"\n  assigned:\n    key: \"$.id\"\n    links:\n      - \"$.ref\"\n",
+        )
+        .replace(
+            "data: trigger.event.data",
+            "data: (ctx.events.payment || []).length",
+        );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    for event in [
+        serde_json::json!({"type": "payment", "ref": "p1"}),
+        // Links payment p1 to order 1, bringing its payment along
+        serde_json::json!({"type": "assigned", "id": "1", "ref": "p1"}),
+        serde_json::json!({"type": "test", "id": "1"}),
+        serde_json::json!({"type": "payment", "ref": "p1"}),
+        serde_json::json!({"type": "test", "id": "1"}),
+    ] {
+        runtime.push_event("local_messages", event).unwrap();
+    }
+
+    let payments: Vec<serde_json::Value> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .map(|action| match action {
+            EventAction::Emit(emit) => emit.payload()["output_data"].clone(),
+            other => panic!("Expected an emit, got {:?}", other),
+        })
+        .collect();
+    assert_eq!(payments, vec!["1", "2"]);
+}
+
+#[test]
+pub fn test_runtime_late_events() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let payloads = |late: &str| {
+        let config = test_case.config().replace(
+            "    requires:\n",
+            &format!("    late: {}\n    requires:\n", late),
+        );
+        let state_dir = tempfile::TempDir::new().unwrap();
+        let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+        for data in ["first", "second"] {
+            runtime
+                .push_event(
+                    "local_messages",
+                    serde_json::json!({"id": "1", "type": "test", "data": data}),
+                )
+                .unwrap();
+        }
+        runtime
+            .poll_actions()
+            .unwrap()
+            .into_iter()
+            .map(|action| match action {
+                EventAction::Emit(emit) => emit.payload(),
+                other => panic!("Expected an emit, got {:?}", other),
+            })
+            .collect::<Vec<serde_json::Value>>()
+    };
+
+    // The rule has fired for correlation 1, so the second message is late
+    let ignored = payloads("ignore");
+    assert_eq!(ignored.len(), 1);
+    assert_eq!(ignored[0]["output_data"], "first");
+
+    let routed = payloads("{route: {target: local_outbox}}");
+    assert_eq!(routed.len(), 2);
+    assert_eq!(routed[1]["rule"], "exampleTrigger");
+    assert_eq!(routed[1]["event"]["data"], "second");
+    assert_eq!(routed[1]["original"]["output"]["data"], "first");
+}
+
+#[test]
+pub fn test_runtime_event_time() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "    matchAll: {}\n",
+            "    matchKey:\n      type: test\n    eventTime: \"$.at\"\n  paid:\n    from: local_messages\n    matchKey:\n      type: paid\n    eventTime: \"$.at\"\n",
+        )
+        .replace(
+            "    key: \"$.id\"\n",
+            "    key: \"$.id\"\n  paid:\n    key: \"$.id\"\n",
+        )
+        .replace(
+            "    requires:\n",
+            "    absent:\n      - paid\n    within: 30m\n    requires:\n",
+        )
+        .replace("        if (trigger.event.type !== 'test') return null;\n", "")
+        .replace("id: trigger.event.id", "id: ctx.events.message[0].id");
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    let mut unpaid = |events: Vec<serde_json::Value>| {
+        for event in events {
+            runtime.push_event("local_messages", event).unwrap();
+        }
+        runtime
+            .poll_actions()
+            .unwrap()
+            .into_iter()
+            .map(|action| match action {
+                EventAction::Emit(emit) => emit.payload()["id"].clone(),
+                other => panic!("Expected an emit, got {:?}", other),
+            })
+            .collect::<Vec<serde_json::Value>>()
+    };
+
+    // Windows closed long ago by the clock, but event time hasn't passed them yet
+    assert!(unpaid(vec![
+        serde_json::json!({"type": "test", "id": "1", "at": "2024-03-01T12:00:00Z"}),
+        serde_json::json!({"type": "paid", "id": "1", "at": "2024-03-01T12:20:00Z"}),
+        serde_json::json!({"type": "test", "id": "2", "at": "2024-03-01T12:05:00Z"}),
+    ])
+    .is_empty());
+    assert_eq!(
+        unpaid(vec![
+            serde_json::json!({"type": "test", "id": "3", "at": "2024-03-01T13:00:00Z"})
+        ]),
+        vec!["2"]
+    );
+}
+
+#[test]
+pub fn test_runtime_test_clock() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "    matchAll: {}\n",
+            "    matchKey:\n      type: test\n  paid:\n    from: local_messages\n    matchKey:\n      type: paid\n",
+        )
+        .replace(
+            "    key: \"$.id\"\n",
+            "    key: \"$.id\"\n  paid:\n    key: \"$.id\"\n",
+        )
+        .replace(
+            "    requires:\n",
+            "    absent:\n      - paid\n    within: 30m\n    requires:\n",
+        )
+        .replace("        if (trigger.event.type !== 'test') return null;\n", "")
+        .replace("id: trigger.event.id", "id: ctx.events.message[0].id");
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    let processor = EventProcessorConfig::try_from(&spec)
+        .unwrap()
+        .build()
+        .unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let storage = spec.storage.builder_at(state_dir.path()).build().unwrap();
+    let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+    let clock = TestClock::new(OffsetDateTime::now_utc());
+    let mut runtime =
+        Runtime::new_with_clock(vec![processor], storage, waker, Arc::new(clock.clone())).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"type": "test", "id": "1"}),
+        )
+        .unwrap();
+    let mut unpaid = || {
+        runtime
+            .poll_actions()
+            .unwrap()
+            .into_iter()
+            .map(|action| match action {
+                EventAction::Emit(emit) => emit.payload()["id"].clone(),
+                other => panic!("Expected an emit, got {:?}", other),
+            })
+            .collect::<Vec<serde_json::Value>>()
+    };
+
+    assert!(unpaid().is_empty());
+    // The window closes without waiting for it
+    clock.advance(Duration::minutes(29));
+    assert!(unpaid().is_empty());
+    clock.advance(Duration::minutes(2));
+    assert_eq!(unpaid(), vec!["1"]);
+}
+
+#[test]
+pub fn test_runtime_timer_trigger() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "    requires:\n",
+            "    timing:\n      from: 10m\n    requires:\n",
+        )
+        .replace(
+            "if (trigger.event.type !== 'test') return null;",
+            "if (trigger.type !== 'timer_expired') return null;",
+        )
+        .replace("id: trigger.event.id", "id: trigger.correlationId")
+        .replace(
+            "data: trigger.event.data",
+            "data: [trigger.rule, trigger.kind, typeof trigger.expiresAt].join('/')",
+        );
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    let processor = EventProcessorConfig::try_from(&spec)
+        .unwrap()
+        .build()
+        .unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let storage = spec.storage.builder_at(state_dir.path()).build().unwrap();
+    let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+    let clock = TestClock::new(OffsetDateTime::now_utc());
+    let mut runtime =
+        Runtime::new_with_clock(vec![processor], storage, waker, Arc::new(clock.clone())).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"type": "test", "id": "1"}),
+        )
+        .unwrap();
+    assert!(runtime.poll_actions().unwrap().is_empty());
+
+    clock.advance(Duration::minutes(11));
+    let payloads: Vec<serde_json::Value> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .map(|action| match action {
+            EventAction::Emit(emit) => emit.payload(),
+            other => panic!("Expected an emit, got {:?}", other),
+        })
+        .collect();
+    assert_eq!(payloads.len(), 1);
+    // Correlation IDs are the JSON of the key they're read from
+    assert_eq!(payloads[0]["id"], "\"1\"");
+    assert_eq!(payloads[0]["output_data"], "exampleTrigger/recheck/string");
+}
+
+#[test]
+pub fn test_runtime_timeout_action() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "    requires:\n",
+            "    timing:\n      from: 10m\n      until: 30m\n    requires:\n",
+        )
+        .replace(
+            "if (trigger.event.type !== 'test') return null;",
+            "if (trigger.type !== 'received_event' || !ctx.events.message) return null;",
+        )
+        .replace(
+            "        output_data: \"${{ data }}\"\n",
+            "        output_data: \"${{ data }}\"\n    onTimeout:\n      target: local_outbox\n      payload:\n        id: \"${{ trigger.correlationId }}\"\n        type: \"${{ trigger.kind }}\"\n",
+        );
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    let processor = EventProcessorConfig::try_from(&spec)
+        .unwrap()
+        .build()
+        .unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let storage = spec.storage.builder_at(state_dir.path()).build().unwrap();
+    let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+    let clock = TestClock::new(OffsetDateTime::now_utc());
+    let mut runtime =
+        Runtime::new_with_clock(vec![processor], storage, waker, Arc::new(clock.clone())).unwrap();
+    // The second correlation fires before it times out, so its timeout is cancelled
+    for id in ["1", "2", "2"] {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"type": "test", "id": id, "data": "x"}),
+            )
+            .unwrap();
+    }
+    let mut payloads = || {
+        runtime
+            .poll_actions()
+            .unwrap()
+            .into_iter()
+            .map(|action| match action {
+                EventAction::Emit(emit) => emit.payload(),
+                other => panic!("Expected an emit, got {:?}", other),
+            })
+            .collect::<Vec<serde_json::Value>>()
+    };
+    assert_eq!(payloads().len(), 1);
+
+    // Rechecks within the timing don't take the timeout action
+    clock.advance(Duration::minutes(20));
+    assert!(payloads().is_empty());
+    clock.advance(Duration::minutes(11));
+    let timed_out = payloads();
+    assert_eq!(timed_out.len(), 1);
+    // Correlation IDs are the JSON of the key they're read from
+    assert_eq!(timed_out[0]["id"], "\"1\"");
+    assert_eq!(timed_out[0]["type"], "timeout");
+    clock.advance(Duration::hours(1));
+    assert!(payloads().is_empty());
+}
+
+#[test]
+pub fn test_runtime_escalation() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let escalate = r#"    escalate:
+      - after: 2
+        action:
+          target: local_outbox
+          payload:
+            level: slack
+            rechecks: "${{ rechecks }}"
+      - after: 2
+        action:
+          target: local_outbox
+          payload:
+            level: pager
+            rechecks: "${{ rechecks }}"
+"#;
+    let config = test_case
+        .config()
+        .replace(
+            "    requires:\n",
+            "    timing:\n      from: 10m\n      check_every: 10m\n      until: 2h\n    requires:\n",
+        )
+        .replace("if (trigger.event.type !== 'test') return null;", "return null;")
+        .replace("    action:\n", &format!("{}    action:\n", escalate));
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    let processor = EventProcessorConfig::try_from(&spec)
+        .unwrap()
+        .build()
+        .unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let storage = spec.storage.builder_at(state_dir.path()).build().unwrap();
+    let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+    let clock = TestClock::new(OffsetDateTime::now_utc());
+    let mut runtime =
+        Runtime::new_with_clock(vec![processor], storage, waker, Arc::new(clock.clone())).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"type": "test", "id": "1"}),
+        )
+        .unwrap();
+    assert!(runtime.poll_actions().unwrap().is_empty());
+
+    // Each recheck is a little after the one before, and escalations follow the second and fourth
+    let mut escalations = Vec::new();
+    for _ in 0..5 {
+        clock.advance(Duration::minutes(10) + Duration::seconds(1));
+        escalations.push(
+            runtime
+                .poll_actions()
+                .unwrap()
+                .into_iter()
+                .map(|action| match action {
+                    EventAction::Emit(emit) => emit.payload()["level"].clone(),
+                    other => panic!("Expected an emit, got {:?}", other),
+                })
+                .collect::<Vec<serde_json::Value>>(),
+        );
+    }
+    assert_eq!(
+        escalations,
+        vec![
+            vec![],
+            vec![serde_json::json!("slack")],
+            vec![],
+            vec![serde_json::json!("pager")],
+            vec![],
+        ]
+    );
+}
+
+#[test]
+pub fn test_runtime_dedupe_across_restart() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace("    action:\n", "    dedupe: 10m\n    action:\n");
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let clock = TestClock::new(OffsetDateTime::now_utc());
+    let start = || {
+        let processor = EventProcessorConfig::try_from(&spec)
+            .unwrap()
+            .build()
+            .unwrap();
+        let storage = spec.storage.builder_at(state_dir.path()).build().unwrap();
+        let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+        Runtime::new_with_clock(vec![processor], storage, waker, Arc::new(clock.clone())).unwrap()
+    };
+    let emits = |runtime: &mut Runtime| {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"type": "test", "id": "1", "data": "example"}),
+            )
+            .unwrap();
+        runtime.poll_actions().unwrap().len()
+    };
+
+    let mut runtime = start();
+    assert_eq!(emits(&mut runtime), 1);
+    drop(runtime);
+
+    // The broker redelivering the event after a restart doesn't emit it again
+    let mut runtime = start();
+    assert_eq!(emits(&mut runtime), 0);
+
+    clock.advance(Duration::minutes(11));
+    assert_eq!(emits(&mut runtime), 1);
+}
+
+#[tokio::test]
+pub async fn test_runtime_rule_scoped_to_timer() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "    requires:\n",
+            "    on: [timer]\n    timing:\n      from: 10m\n    requires:\n",
+        )
+        .replace(
+            "if (trigger.event.type !== 'test') return null;",
+            "if (trigger.type !== 'timer_expired') throw new Error('Evaluated on an event');",
+        )
+        .replace("id: trigger.event.id", "id: trigger.correlationId")
+        .replace("data: trigger.event.data", "data: trigger.kind");
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    let processor = EventProcessorConfig::try_from(&spec)
+        .unwrap()
+        .build()
+        .unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let storage = spec.storage.builder_at(state_dir.path()).build().unwrap();
+    let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+    let clock = TestClock::new(OffsetDateTime::now_utc());
+    let mut runtime =
+        Runtime::new_with_clock(vec![processor], storage, waker, Arc::new(clock.clone())).unwrap();
+    // The condition isn't evaluated on the event, but its recheck is still scheduled
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"type": "test", "id": "1"}),
+        )
+        .unwrap();
+    assert!(runtime.poll_actions().unwrap().is_empty());
+    assert!(runtime.next_wakeup().is_some());
+
+    clock.advance(Duration::minutes(11));
+    let payloads: Vec<serde_json::Value> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .map(|action| match action {
+            EventAction::Emit(emit) => emit.payload(),
+            other => panic!("Expected an emit, got {:?}", other),
+        })
+        .collect();
+    assert_eq!(payloads.len(), 1);
+    assert_eq!(payloads[0]["output_data"], "recheck");
+}
+
+#[tokio::test]
+pub async fn test_runtime_satisfied_rule_cancels_recheck() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "    requires:\n",
+            "    timing:\n      from: 10m\n    requires:\n",
+        )
+        .replace(
+            "if (trigger.event.type !== 'test') return null;",
+            "if (trigger.event?.type !== 'test') return null;",
+        );
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    let processor = EventProcessorConfig::try_from(&spec)
+        .unwrap()
+        .build()
+        .unwrap();
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let storage = spec.storage.builder_at(state_dir.path()).build().unwrap();
+    let waker = TimingExpiry::new(state_dir.path().join(".timing_expiry")).unwrap();
+    let clock = TestClock::new(OffsetDateTime::now_utc());
+    let mut runtime =
+        Runtime::new_with_clock(vec![processor], storage, waker, Arc::new(clock.clone())).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"type": "pending", "id": "1"}),
+        )
+        .unwrap();
+    assert!(runtime.poll_actions().unwrap().is_empty());
+    assert!(runtime.next_wakeup().is_some());
+
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"type": "test", "id": "1", "data": "early"}),
+        )
+        .unwrap();
+    assert_eq!(runtime.poll_actions().unwrap().len(), 1);
+    assert_eq!(runtime.next_wakeup(), None);
+
+    clock.advance(Duration::minutes(11));
+    assert!(runtime.poll_actions().unwrap().is_empty());
+}
+
+#[tokio::test]
+pub async fn test_runtime_jsonpath_correlation() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace("key: \"$.id\"", "key: \"$.refs[?(@.kind == 'order')].id\"")
+        .replace(
+            "data: trigger.event.data",
+            "data: (ctx.events.message || []).length + 1",
+        );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    for refs in [
+        serde_json::json!([{"kind": "order", "id": "o-1"}, {"kind": "user", "id": "u-1"}]),
+        serde_json::json!([{"kind": "user", "id": "u-2"}, {"kind": "order", "id": "o-1"}]),
+    ] {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"type": "test", "refs": refs}),
+            )
+            .unwrap();
+    }
+
+    let outputs: Vec<serde_json::Value> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .map(|action| match action {
+            EventAction::Emit(emit) => emit.payload()["output_data"].clone(),
+            other => panic!("Expected an emit, got {:?}", other),
+        })
+        .collect();
+    // Both events are correlated by their order, wherever it is among their references
+    assert_eq!(outputs, vec!["1", "2"]);
+
+    let invalid = test_case
+        .config()
+        .replace("key: \"$.id\"", "key: \"$.refs[\"");
+    assert!(Runtime::from_yaml_with_state(&invalid, state_dir.path()).is_err());
+}
+
+#[tokio::test]
+pub async fn test_runtime_ambiguous_match() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = |policy: &str| {
+        format!(
+            "ambiguousMatch: {}\n\n{}",
+            policy,
+            test_case.config().replace(
+                "    matchAll: {}\n",
+                "    matchAll: {}\n  copy:\n    from: local_messages\n    matchAll: {}\n    priority: 1\n",
+            )
+        )
+    };
+    let event = serde_json::json!({"id": "1", "type": "test", "data": "example"});
+    let state_dir = tempfile::TempDir::new().unwrap();
+
+    // `message` is defined first, so the trigger still sees it
+    let mut first = Runtime::from_yaml_with_state(&config("first"), state_dir.path()).unwrap();
+    first.push_event("local_messages", event.clone()).unwrap();
+    assert_eq!(first.poll_actions().unwrap().len(), 1);
+    assert_eq!(first.ambiguous_matches(), 1);
+
+    // `copy` has the higher priority, so the event is never a `message`
+    let priority_dir = tempfile::TempDir::new().unwrap();
+    let mut priority =
+        Runtime::from_yaml_with_state(&config("priority"), priority_dir.path()).unwrap();
+    priority
+        .push_event("local_messages", event.clone())
+        .unwrap();
+    assert!(priority.poll_actions().unwrap().is_empty());
+
+    let error_dir = tempfile::TempDir::new().unwrap();
+    let mut error = Runtime::from_yaml_with_state(&config("error"), error_dir.path()).unwrap();
+    assert!(error.push_event("local_messages", event).is_err());
+    assert_eq!(error.ambiguous_matches(), 1);
+}
+
+#[tokio::test]
+pub async fn test_runtime_wildcard_source() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace("    from: local_messages\n", "    from: \"*\"\n");
+    let spec: EventProcessorYamlSpec = serde_yaml::from_str(&config).unwrap();
+    // The outbox is a target, so isn't received from
+    assert_eq!(
+        EventProcessorConfig::try_from(&spec)
+            .unwrap()
+            .source_names()
+            .collect::<Vec<&str>>(),
+        vec!["local_messages"]
+    );
+
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test", "data": "example"}),
+        )
+        .unwrap();
+    assert_eq!(runtime.poll_actions().unwrap().len(), 1);
+}
+
+#[tokio::test]
+pub async fn test_runtime_http_lookup() {
+    // Answers a single lookup request with a customer record
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0u8; 1024];
+        let read = std::io::Read::read(&mut stream, &mut request).unwrap();
+        let body = r#"{"id": "1", "tier": "gold"}"#;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        String::from_utf8_lossy(&request[..read]).into_owned()
+    });
+
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "matchAll: {}",
+            &format!(
+                "matchAll: {{}}\n    lookup:\n      customer:\n        http:\n          url: \"http://127.0.0.1:{}/customers/${{{{ id }}}}\"\n        select: tier",
+                port
+            ),
+        )
+        .replace("data: trigger.event.data", "data: trigger.event._enrich.customer");
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    let event = runtime
+        .lookup(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test"}),
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+    runtime.push_event("local_messages", event).unwrap();
+
+    assert!(server.join().unwrap().starts_with("GET /customers/1 "));
+    let actions = runtime.poll_actions().unwrap();
+    let [EventAction::Emit(emit)] = actions.as_slice() else {
+        panic!("Expected a single emit, got {:?}", actions);
+    };
+    assert_eq!(emit.clone().payload()["output_data"], "gold");
+}
+
+#[test]
+pub fn test_runtime_tenancy() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = format!(
+        "tenancy:\n  key: \"$.tenant\"\n\n{}",
+        test_case
+            .config()
+            .replace(
+                "    requires:\n",
+                "    tenants: [\"acme\"]\n    requires:\n",
+            )
+            .replace(
+                "data: trigger.event.data",
+                "data: (ctx.events.message || []).length + 1"
+            )
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    for tenant in ["acme", "globex", "acme"] {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"id": "1", "type": "test", "tenant": tenant}),
+            )
+            .unwrap();
+    }
+    assert!(runtime
+        .push_event(
+            "local_messages",
+            serde_json::json!({"id": "1", "type": "test"}),
+        )
+        .is_err());
+
+    let outputs: Vec<serde_json::Value> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .map(|action| match action {
+            EventAction::Emit(emit) => emit.payload()["output_data"].clone(),
+            other => panic!("Expected an emit, got {:?}", other),
+        })
+        .collect();
+    // Correlation 1 of globex is kept apart, and its rule isn't enabled
+    assert_eq!(outputs, vec!["1", "2"]);
+
+    let stats = runtime.tenant_stats();
+    assert_eq!(stats["acme"].events, 2);
+    assert_eq!(stats["acme"].actions, 2);
+    assert_eq!(stats["globex"].events, 1);
+    assert_eq!(stats["globex"].actions, 0);
+}
+
+#[test]
+pub fn test_runtime_redaction() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case
+        .config()
+        .replace(
+            "matchAll: {}",
+            "matchAll: {}\n    redact:\n      - \"$.card.number\"\n      - path: \"$.id\"\n        with: hash",
+        )
+        .replace(
+            "data: trigger.event.data",
+            "data: `${trigger.event.card.number} ${(ctx.events.message || []).length}`",
+        );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    for _ in 0..2 {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"id": "1", "type": "test", "card": {"number": "4111111111111111"}}),
+            )
+            .unwrap();
+    }
+
+    let actions = runtime.poll_actions().unwrap();
+    let [EventAction::Emit(first), EventAction::Emit(second)] = actions.as_slice() else {
+        panic!("Expected two emits, got {:?}", actions);
+    };
+    assert_eq!(first.clone().payload()["output_data"], "[REDACTED] 0");
+    // Events still correlate on the hashed ID
+    assert_eq!(second.clone().payload()["output_data"], "[REDACTED] 1");
+    assert_ne!(second.clone().payload()["id"], "1");
+}
+
+#[test]
+pub fn test_runtime_rejects_invalid_config() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let misspelt = test_case
+        .config()
+        .replace("filterAndExtract:", "filterAndExtact:");
+    assert!(matches!(
+        Runtime::from_yaml_with_state(&misspelt, state_dir.path()),
+        Err(LaikaError::ConfigError { path, message })
+            if path == "triggers.exampleTrigger" && message.contains("filterAndExtact")
+    ));
+
+    // Every undefined reference is reported at once
+    let undefined = test_case
+        .config()
+        .replace("        - message\n", "        - mesage\n")
+        .replace("target: local_outbox", "target: local_outbx");
+    let Err(LaikaError::ConfigErrors(errors)) =
+        Runtime::from_yaml_with_state(&undefined, state_dir.path())
+    else {
+        panic!("Expected every undefined reference to be reported");
+    };
+    let paths: Vec<String> = errors
+        .into_iter()
+        .filter_map(|error| match error {
+            LaikaError::ConfigError { path, .. } => Some(path),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        paths,
+        vec![
+            "triggers.exampleTrigger.requires",
+            "triggers.exampleTrigger.action.target"
+        ]
+    );
+}
+
+#[test]
+pub fn test_runtime_rollout() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    // The existing trigger keeps the correlations the new one isn't rolled out to
+    let config = format!(
+        "{}\n  newTrigger:\n    requires:\n      at_least: [message]\n    rollout: 30%\n    filterAndExtract: >\n      (trigger, ctx) => ({{ id: trigger.event.id }})\n    action:\n      target: local_outbox\n      payload:\n        id: \"${{{{ id }}}}\"\n        type: \"test_rolled_out\"\n",
+        test_case.config().replace(
+            "    action:\n      target: local_outbox",
+            "    rollout: 30%-100%\n    action:\n      target: local_outbox"
+        )
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    for id in 0..100 {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"id": id.to_string(), "type": "test", "data": "example"}),
+            )
+            .unwrap();
+    }
+
+    let mut handled: Vec<(String, String)> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .filter_map(|action| match action {
+            EventAction::Emit(emit) => {
+                let payload = emit.payload();
+                Some((
+                    payload["id"].as_str()?.to_string(),
+                    payload["type"].as_str()?.to_string(),
+                ))
+            }
+            _ => None,
+        })
+        .collect();
+    handled.sort();
+    // Every correlation is handled by exactly one of the triggers
+    assert_eq!(handled.len(), 100);
+    handled.dedup_by(|a, b| a.0 == b.0);
+    assert_eq!(handled.len(), 100);
+    let rolled_out = handled
+        .iter()
+        .filter(|(_, handled_by)| handled_by == "test_rolled_out")
+        .count();
+    assert!((15..45).contains(&rolled_out), "{}", rolled_out);
+}
+
+#[test]
+pub fn test_runtime_decisions() {
+    let test_case = TestCase::new(
+        "single_event",
+        "basic.yaml",
+        "single_event.jsonl",
+        "single_event_output.jsonl",
+    );
+    let config = test_case.config().replace(
+        "data: trigger.event.data",
+        "data: String(ctx.decisions.exampleTrigger?.fires ?? 0)",
+    );
+    let state_dir = tempfile::TempDir::new().unwrap();
+    let mut runtime = Runtime::from_yaml_with_state(&config, state_dir.path()).unwrap();
+    for _ in 0..3 {
+        runtime
+            .push_event(
+                "local_messages",
+                serde_json::json!({"id": "1", "type": "test"}),
+            )
+            .unwrap();
+    }
+
+    // Each event sees the fires recorded for the ones before it
+    let fires: Vec<serde_json::Value> = runtime
+        .poll_actions()
+        .unwrap()
+        .into_iter()
+        .filter_map(|action| match action {
+            EventAction::Emit(emit) => Some(emit.payload()["output_data"].clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(fires, vec!["0", "1", "2"]);
+}